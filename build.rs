@@ -0,0 +1,30 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The gRPC service is generated from its checked-in definition, with a
+    // bundled protoc so building it needs nothing installed.
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no bundled protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::compile_protos("proto/to_not_do.proto")
+            .expect("failed to compile proto/to_not_do.proto");
+    }
+
+    // The header for the C ABI, generated into the build directory; the copy
+    // in include/ is checked against it by the `ffi` tests.
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not set");
+        cbindgen::Builder::new()
+            .with_src("src/ffi.rs")
+            .with_language(cbindgen::Language::C)
+            .with_include_guard("TO_NOT_DO_H")
+            .with_header("/* Generated from src/ffi.rs by cbindgen; do not edit. */")
+            .with_cpp_compat(true)
+            .generate()
+            .expect("failed to generate the C header")
+            .write_to_file(std::path::Path::new(&out_dir).join("to_not_do.h"));
+    }
+}
@@ -0,0 +1,91 @@
+/* Generated from src/ffi.rs by cbindgen; do not edit. */
+
+#ifndef TO_NOT_DO_H
+#define TO_NOT_DO_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An open database, as returned by [`tnd_open`].
+ */
+typedef struct TndDatabase TndDatabase;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the database file at `path`, creating it when there is none yet.
+ * Returns `NULL` on failure. Close it with [`tnd_close`].
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string.
+ */
+struct TndDatabase *tnd_open(const char *path);
+
+/**
+ * Closes a database opened by [`tnd_open`]. Does nothing for `NULL`.
+ *
+ * # Safety
+ *
+ * `db` must come from [`tnd_open`] and not be used afterwards.
+ */
+void tnd_close(struct TndDatabase *db);
+
+/**
+ * Adds a task described by `description`, returning its ID, or `NULL` on
+ * failure.
+ *
+ * # Safety
+ *
+ * `db` must come from [`tnd_open`] and `description` must be a
+ * NUL-terminated string.
+ */
+char *tnd_add(struct TndDatabase *db, const char *description);
+
+/**
+ * The tasks matching `filter`, a JSON object like the query parameters of
+ * `GET /api/tasks` (`state`, `project` and `archived`), as a JSON array.
+ * `NULL` lists the active tasks. Returns `NULL` on failure.
+ *
+ * # Safety
+ *
+ * `db` must come from [`tnd_open`] and `filter` must be `NULL` or a
+ * NUL-terminated string.
+ */
+char *tnd_list(struct TndDatabase *db, const char *filter);
+
+/**
+ * Marks the task with ID `id` done. Returns 0, or -1 on failure.
+ *
+ * # Safety
+ *
+ * `db` must come from [`tnd_open`] and `id` must be a NUL-terminated
+ * string.
+ */
+int tnd_complete(struct TndDatabase *db, const char *id);
+
+/**
+ * The message of the last failed call on this thread, or `NULL` if none
+ * failed. It stays valid until the next failing call on the thread.
+ */
+const char *tnd_last_error(void);
+
+/**
+ * Frees a string returned by the library. Does nothing for `NULL`.
+ *
+ * # Safety
+ *
+ * `text` must come from the library and not be used afterwards.
+ */
+void tnd_string_free(char *text);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TO_NOT_DO_H */
@@ -0,0 +1,85 @@
+use crate::{
+    compression::Compression,
+    dump::Dump,
+    encryption::{self, Cipher},
+    error::{DatabaseError, ToNotDoError},
+};
+
+/// Marks an archive file: `MAGIC`, then the compressed dump, encrypted when
+/// the archive was written with a passphrase.
+pub const MAGIC: &[u8; 4] = b"TNDA";
+
+fn invalid(reason: impl ToString) -> ToNotDoError {
+    ToNotDoError::DatabaseError(DatabaseError::InvalidDump(reason.to_string()))
+}
+
+/// Packs `dump` into a single archive file, encrypted with `passphrase` when
+/// one is given.
+pub fn write_archive(dump: &Dump, passphrase: Option<&str>) -> Result<Vec<u8>, ToNotDoError> {
+    let json = dump.to_json()?;
+    let mut payload = Compression::Zstd
+        .compress(json.as_bytes())
+        .map_err(invalid)?;
+
+    if let Some(passphrase) = passphrase {
+        payload = Cipher::generate(passphrase)?.encrypt(&payload)?;
+    }
+
+    let mut archive = MAGIC.to_vec();
+    archive.extend_from_slice(&payload);
+    Ok(archive)
+}
+
+/// Unpacks an archive written by [`write_archive`]. `passphrase` is only
+/// asked for when the archive is encrypted.
+pub fn read_archive(
+    data: &[u8],
+    passphrase: impl FnOnce() -> Result<String, ToNotDoError>,
+) -> Result<Dump, ToNotDoError> {
+    let payload = data
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or_else(|| invalid("not a to-not-do archive"))?;
+
+    let payload = if encryption::is_encrypted(payload) {
+        Cipher::for_file(payload, &passphrase()?)?.decrypt(payload)?
+    } else {
+        payload.to_vec()
+    };
+
+    let json = Compression::decompress(&payload).map_err(invalid)?;
+    Dump::from_json(&String::from_utf8(json).map_err(invalid)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::{Database, Task};
+
+    #[test]
+    fn test_archive_round_trip() {
+        let mut db = Database::default();
+        db.insert_task(Task::new("Moved to the new laptop"))
+            .unwrap();
+        let dump = Dump::new(db, Some("[list]\n".to_string()));
+
+        let archive = write_archive(&dump, Some("correct horse")).unwrap();
+        assert!(encryption::is_encrypted(&archive[MAGIC.len()..]));
+        assert!(!archive
+            .windows(b"new laptop".len())
+            .any(|w| w == b"new laptop"));
+
+        let wrong = read_archive(&archive, || Ok("battery staple".to_string()));
+        assert!(wrong.is_err());
+
+        let read = read_archive(&archive, || Ok("correct horse".to_string())).unwrap();
+        assert_eq!(read.to_json().unwrap(), dump.to_json().unwrap());
+        assert_eq!(read.config.as_deref(), Some("[list]\n"));
+
+        let plain = write_archive(&dump, None).unwrap();
+        assert!(!encryption::is_encrypted(&plain[MAGIC.len()..]));
+        let read = read_archive(&plain, || panic!("no passphrase needed")).unwrap();
+        assert_eq!(read.database.tasks().len(), 1);
+
+        assert!(read_archive(b"{}", || Ok(String::new())).is_err());
+    }
+}
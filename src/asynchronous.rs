@@ -0,0 +1,145 @@
+//! Async access to the task store, for servers and GUI frontends that must not
+//! block their executor on file I/O. Enabled by the `async` feature.
+//!
+//! Every call runs the blocking [`DatabaseManager`] on tokio's blocking
+//! thread pool, one call at a time, so all the merging and retention logic
+//! is shared with the command line tool, which keeps using the manager
+//! directly.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    config::StorageConfig,
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task},
+    filter::TaskFilter,
+    journal::Change,
+    repository::TaskRepository,
+    storage::{open_storage, Storage},
+};
+
+/// A [`DatabaseManager`] that can be shared between tasks and awaited on.
+/// Clones refer to the same manager.
+#[derive(Clone)]
+pub struct AsyncDatabaseManager {
+    inner: Arc<Mutex<DatabaseManager>>,
+}
+
+impl AsyncDatabaseManager {
+    /// Opens the database file at `db_file` with the storage the command line
+    /// tool would use for `config`.
+    pub async fn open(db_file: &Path, config: &StorageConfig) -> Result<Self, ToNotDoError> {
+        let db_file: PathBuf = db_file.to_path_buf();
+        let config = config.clone();
+        Self::with_storage_blocking(move || open_storage(&db_file, &config)).await
+    }
+
+    pub async fn with_storage(storage: Box<dyn Storage>) -> Result<Self, ToNotDoError> {
+        Self::with_storage_blocking(move || storage).await
+    }
+
+    async fn with_storage_blocking(
+        storage: impl FnOnce() -> Box<dyn Storage> + Send + 'static,
+    ) -> Result<Self, ToNotDoError> {
+        let manager = blocking(move || DatabaseManager::with_storage(storage())).await?;
+        Ok(Self::new(manager))
+    }
+
+    pub fn new(manager: DatabaseManager) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(manager)),
+        }
+    }
+
+    /// Runs `f` on the manager without blocking the caller, for the
+    /// operations that have no async method of their own.
+    pub async fn with_manager<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut DatabaseManager) -> Result<T, ToNotDoError> + Send + 'static,
+    ) -> Result<T, ToNotDoError> {
+        let inner = Arc::clone(&self.inner);
+        blocking(move || f(&mut inner.lock().unwrap_or_else(|e| e.into_inner()))).await
+    }
+
+    pub async fn add(&self, task: Task) -> Result<(), ToNotDoError> {
+        self.with_manager(move |manager| manager.add(&task)).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<Task>, ToNotDoError> {
+        self.with_manager(move |manager| manager.get(id)).await
+    }
+
+    pub async fn query(&self, filter: TaskFilter) -> Result<Vec<Task>, ToNotDoError> {
+        self.with_manager(move |manager| manager.query(&filter))
+            .await
+    }
+
+    pub async fn update(&self, task: Task) -> Result<(), ToNotDoError> {
+        self.with_manager(move |manager| manager.update(&task))
+            .await
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<(), ToNotDoError> {
+        self.with_manager(move |manager| manager.delete(id)).await
+    }
+
+    /// See [`TaskRepository::watch`].
+    pub async fn watch(&self) -> Result<Vec<Change>, ToNotDoError> {
+        self.with_manager(TaskRepository::watch).await
+    }
+}
+
+/// Runs `f` on the blocking thread pool, passing its panics on to the caller.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::TaskState;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_async_manager_shares_the_file_with_the_blocking_one() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let tasks = AsyncDatabaseManager::open(&db_file, &StorageConfig::default())
+            .await
+            .unwrap();
+
+        let task = Task::new("Answer the mail");
+        let (first, second, third) = tokio::join!(
+            tasks.add(Task::new("First")),
+            tasks.add(Task::new("Second")),
+            tasks.add(Task::new("Third")),
+        );
+        first.and(second).and(third).unwrap();
+        tasks.add(task.clone()).await.unwrap();
+        tasks
+            .with_manager({
+                let id = task.id();
+                move |manager| manager.set_task_state(id, TaskState::Done)
+            })
+            .await
+            .unwrap();
+
+        let mut cli = DatabaseManager::open(&db_file).unwrap();
+        assert_eq!(cli.get_tasks().unwrap().len(), 4);
+        assert_eq!(
+            cli.get(task.id()).unwrap().unwrap().state(),
+            TaskState::Done
+        );
+
+        cli.delete_task(task.id()).unwrap();
+        assert_eq!(tasks.get(task.id()).await.unwrap(), None);
+        assert_eq!(tasks.query(TaskFilter::default()).await.unwrap().len(), 3);
+    }
+}
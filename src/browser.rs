@@ -0,0 +1,139 @@
+//! A [`Storage`] in the `localStorage` of a web page, for a client-side web
+//! version built on the same task model, queries and serialization as the
+//! command line tool. Enabled by the `browser` feature.
+//!
+//! The library builds for the browser without its servers and network
+//! services, and without the default features:
+//!
+//! ```text
+//! cargo build --lib --target wasm32-unknown-unknown --no-default-features --features browser
+//! ```
+//!
+//! zstd compression is built from C, so this needs a clang that targets
+//! wasm32.
+//!
+//! The database is kept as the JSON a database file would hold, under a key
+//! of its own, and goes through the same schema migrations when loaded.
+//! `localStorage` is used rather than IndexedDB because [`Storage`] is
+//! synchronous, and IndexedDB can only be reached asynchronously.
+
+use serde_json::Value;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::Database,
+    migration,
+    storage::Storage,
+};
+
+/// The key used when none is given.
+pub const DEFAULT_KEY: &str = "to-not-do";
+
+fn browser_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::BrowserError(reason.to_string())
+}
+
+/// Keeps the database under `key` in the `localStorage` of the page.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    key: String,
+}
+
+impl LocalStorage {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+        }
+    }
+
+    /// The storage of the page, looked up on every call as it cannot be sent
+    /// between threads.
+    fn storage() -> Result<web_sys::Storage, ToNotDoError> {
+        web_sys::window()
+            .ok_or_else(|| browser_error("No window"))?
+            .local_storage()
+            .map_err(|e| browser_error(format!("{:?}", e)))?
+            .ok_or_else(|| browser_error("localStorage is disabled"))
+    }
+}
+
+impl Default for LocalStorage {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEY)
+    }
+}
+
+/// Reads a database as stored, returning whether it had to be migrated.
+fn decode(text: &str) -> Result<(Database, bool), ToNotDoError> {
+    let invalid = |e: serde_json::Error| {
+        ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            e,
+        )))
+    };
+    let mut db: Value = serde_json::from_str(text).map_err(invalid)?;
+    let migrated = migration::migrate(&mut db)?;
+    Ok((serde_json::from_value(db).map_err(invalid)?, migrated))
+}
+
+fn encode(db: &Database) -> Result<String, ToNotDoError> {
+    serde_json::to_string(db).map_err(|e| {
+        ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            e,
+        )))
+    })
+}
+
+impl Storage for LocalStorage {
+    fn exists(&self) -> bool {
+        Self::storage()
+            .ok()
+            .and_then(|storage| storage.get_item(&self.key).ok().flatten())
+            .is_some()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        let text = Self::storage()?
+            .get_item(&self.key)
+            .map_err(|e| browser_error(format!("{:?}", e)))?
+            .ok_or_else(|| browser_error(format!("Nothing stored under {}", self.key)))?;
+        let (db, migrated) = decode(&text)?;
+
+        if migrated {
+            self.save(&db)?;
+        }
+
+        Ok(db)
+    }
+
+    /// Fails when the page is out of storage quota.
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        Self::storage()?
+            .set_item(&self.key, &encode(db)?)
+            .map_err(|e| browser_error(format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::Task;
+
+    #[test]
+    fn test_encoding() {
+        let mut db = Database::default();
+        db.put_task(Task::new("Water the plants"));
+        let (decoded, migrated) = decode(&encode(&db).unwrap()).unwrap();
+        assert_eq!(decoded.tasks(), db.tasks());
+        assert!(!migrated);
+
+        // Databases from before schema versions are upgraded.
+        let (old, migrated) =
+            decode(r#"{"name": "to-not-do", "version": "0.0.1", "tasks": []}"#).unwrap();
+        assert!(old.tasks().is_empty());
+        assert!(migrated);
+
+        assert!(decode("not json").is_err());
+    }
+}
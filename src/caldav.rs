@@ -0,0 +1,1376 @@
+//! Two-way sync with a CalDAV task list, such as one on Nextcloud or
+//! Fastmail, run by `caldav sync`.
+//!
+//! Tasks are stored as VTODOs. The description, notes, state, due date,
+//! priority and tags are synced as `SUMMARY`, `DESCRIPTION`, `STATUS`,
+//! `DUE`, `PRIORITY` and `CATEGORIES`; whatever else other apps put in a
+//! VTODO is left alone. States map to `NEEDS-ACTION`, `IN-PROCESS` and
+//! `COMPLETED`, and a cancelled VTODO counts as done. Priorities 1 to 4 are
+//! high, 5 medium and 6 to 9 low. Due times are dropped, keeping the date.
+//!
+//! Each sync compares both sides with how they were left by the previous
+//! one, which is kept in the state directory, and takes every field from
+//! the side that changed it. When both changed the same field, the change
+//! made here wins.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{merge_field, DatabaseManager, Priority, Task, TaskState, APP_NAME},
+};
+
+/// Read for the CalDAV password when the `[caldav]` section gives none.
+pub const CALDAV_PASSWORD_ENV: &str = "TO_NOT_DO_CALDAV_PASSWORD";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Properties of a VTODO that are written from the task.
+const SYNCED_PROPERTIES: [&str; 10] = [
+    "SUMMARY",
+    "DESCRIPTION",
+    "STATUS",
+    "COMPLETED",
+    "PERCENT-COMPLETE",
+    "DUE",
+    "PRIORITY",
+    "CATEGORIES",
+    "LAST-MODIFIED",
+    "DTSTAMP",
+];
+
+/// Asks for every VTODO of a collection with its ETag and data.
+const CALENDAR_QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop><d:getetag/><c:calendar-data/></d:prop>
+  <c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VTODO"/></c:comp-filter></c:filter>
+</c:calendar-query>
+"#;
+
+/// Settings from the `[caldav]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalDavConfig {
+    /// The task list, e.g.
+    /// `https://cloud.example.com/remote.php/dav/calendars/me/tasks/`.
+    pub url: Option<String>,
+    pub username: Option<String>,
+    /// Falls back to the `TO_NOT_DO_CALDAV_PASSWORD` environment variable.
+    pub password: Option<String>,
+}
+
+/// The properties of a VTODO that map onto a task.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoFields {
+    pub summary: String,
+    pub notes: Option<String>,
+    pub state: TaskState,
+    pub due: Option<NaiveDate>,
+    pub priority: Option<Priority>,
+    pub categories: Vec<String>,
+}
+
+impl TodoFields {
+    pub fn of(task: &Task) -> Self {
+        Self {
+            summary: task.description().to_string(),
+            notes: task.notes().map(str::to_string),
+            state: task.state(),
+            due: task.due(),
+            priority: task.priority(),
+            categories: task.tags().to_vec(),
+        }
+    }
+
+    /// Each field from `local` where it changed since `base`, and from
+    /// `remote` everywhere else; local edits win conflicts.
+    fn merge(base: &Self, local: &Self, remote: &Self) -> Self {
+        Self {
+            summary: merge_field(&base.summary, &local.summary, &remote.summary, true),
+            notes: merge_field(&base.notes, &local.notes, &remote.notes, true),
+            state: merge_field(&base.state, &local.state, &remote.state, true),
+            due: merge_field(&base.due, &local.due, &remote.due, true),
+            priority: merge_field(&base.priority, &local.priority, &remote.priority, true),
+            categories: merge_field(
+                &base.categories,
+                &local.categories,
+                &remote.categories,
+                true,
+            ),
+        }
+    }
+}
+
+/// Reads the first VTODO of an iCalendar object: its UID and the synced
+/// fields. `None` when there is no VTODO with a UID.
+pub fn parse(data: &str) -> Option<(String, TodoFields)> {
+    let mut uid = None;
+    let mut fields = TodoFields {
+        summary: String::new(),
+        notes: None,
+        state: TaskState::Todo,
+        due: None,
+        priority: None,
+        categories: Vec::new(),
+    };
+
+    let mut cursor = TodoCursor::default();
+    for line in content_lines(data) {
+        let Some((name, value)) = property(&line) else {
+            continue;
+        };
+        match cursor.step(&name, value) {
+            Place::End => return uid.map(|uid| (uid, fields)),
+            Place::Outside => continue,
+            Place::Property => {}
+        }
+
+        match name.as_str() {
+            "UID" => uid = Some(unescape(value)),
+            "SUMMARY" => fields.summary = unescape(value),
+            "DESCRIPTION" => fields.notes = Some(unescape(value)).filter(|notes| !notes.is_empty()),
+            "STATUS" => {
+                fields.state = match value.trim().to_ascii_uppercase().as_str() {
+                    "IN-PROCESS" => TaskState::InProgress,
+                    "COMPLETED" | "CANCELLED" => TaskState::Done,
+                    _ => TaskState::Todo,
+                }
+            }
+            "DUE" => {
+                fields.due = value
+                    .get(..8)
+                    .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+            }
+            "PRIORITY" => {
+                fields.priority = match value.trim().parse::<u8>() {
+                    Ok(1..=4) => Some(Priority::High),
+                    Ok(5) => Some(Priority::Medium),
+                    Ok(6..=9) => Some(Priority::Low),
+                    _ => None,
+                }
+            }
+            "CATEGORIES" => fields.categories.extend(split_list(value)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `fields` as an iCalendar object holding the VTODO `uid`. Given the object
+/// the server has, the properties that are not synced are kept from it.
+pub fn render(
+    uid: &str,
+    fields: &TodoFields,
+    existing: Option<&str>,
+    now: DateTime<Utc>,
+) -> String {
+    let stamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut properties = vec![format!("SUMMARY:{}", escape(&fields.summary))];
+    if let Some(notes) = &fields.notes {
+        properties.push(format!("DESCRIPTION:{}", escape(notes)));
+    }
+    properties.push(format!(
+        "STATUS:{}",
+        match fields.state {
+            TaskState::Todo => "NEEDS-ACTION",
+            TaskState::InProgress => "IN-PROCESS",
+            TaskState::Done => "COMPLETED",
+        }
+    ));
+    if fields.state == TaskState::Done {
+        properties.push(format!("COMPLETED:{}", stamp));
+        properties.push("PERCENT-COMPLETE:100".to_string());
+    }
+    if let Some(due) = fields.due {
+        properties.push(format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+    }
+    if let Some(priority) = fields.priority {
+        let level = match priority {
+            Priority::High => 1,
+            Priority::Medium => 5,
+            Priority::Low => 9,
+        };
+        properties.push(format!("PRIORITY:{}", level));
+    }
+    if !fields.categories.is_empty() {
+        let categories: Vec<String> = fields.categories.iter().map(|c| escape(c)).collect();
+        properties.push(format!("CATEGORIES:{}", categories.join(",")));
+    }
+    properties.push(format!("LAST-MODIFIED:{}", stamp));
+    properties.push(format!("DTSTAMP:{}", stamp));
+
+    let existing = existing
+        .map(content_lines)
+        .filter(|lines| parse(&lines.join("\r\n")).is_some());
+    let lines = match existing {
+        Some(existing) => {
+            let mut lines = Vec::new();
+            let mut cursor = TodoCursor::default();
+            for line in existing {
+                let Some((name, value)) = property(&line) else {
+                    lines.push(line);
+                    continue;
+                };
+                match cursor.step(&name, value) {
+                    Place::Property if SYNCED_PROPERTIES.contains(&name.as_str()) => continue,
+                    Place::End => lines.append(&mut properties),
+                    _ => {}
+                }
+                lines.push(line);
+            }
+            lines
+        }
+        None => [
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            format!("PRODID:-//{}//EN", APP_NAME),
+            "BEGIN:VTODO".to_string(),
+            format!("UID:{}", escape(uid)),
+        ]
+        .into_iter()
+        .chain(properties)
+        .chain(["END:VTODO".to_string(), "END:VCALENDAR".to_string()])
+        .collect(),
+    };
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// Where a content line is relative to the first VTODO.
+#[derive(Debug, PartialEq, Eq)]
+enum Place {
+    /// Before or after the VTODO, or inside a component within it.
+    Outside,
+    /// A property of the VTODO itself.
+    Property,
+    /// The `END:VTODO` line.
+    End,
+}
+
+/// Follows the content lines of an object to tell which belong to its
+/// first VTODO.
+#[derive(Debug, Default)]
+struct TodoCursor {
+    inside: bool,
+    done: bool,
+    /// Components, such as alarms, open within the VTODO.
+    depth: usize,
+}
+
+impl TodoCursor {
+    fn step(&mut self, name: &str, value: &str) -> Place {
+        if self.done {
+            return Place::Outside;
+        }
+        let is_todo = value.trim().eq_ignore_ascii_case("VTODO");
+        match (name, self.inside) {
+            ("BEGIN", false) if is_todo => self.inside = true,
+            ("BEGIN", true) => self.depth += 1,
+            ("END", true) if self.depth > 0 => self.depth -= 1,
+            ("END", true) if is_todo => {
+                self.done = true;
+                return Place::End;
+            }
+            (_, true) if self.depth == 0 => return Place::Property,
+            _ => {}
+        }
+        Place::Outside
+    }
+}
+
+/// The content lines of an iCalendar object, with folded lines joined.
+fn content_lines(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Splits a content line into its upper-cased name and its value, leaving
+/// out the parameters.
+fn property(line: &str) -> Option<(String, &str)> {
+    let mut quoted = false;
+    let (colon, _) = line.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ':' && !quoted
+    })?;
+    let head = &line[..colon];
+    let name = head.split(';').next().unwrap_or(head);
+    Some((name.to_ascii_uppercase(), &line[colon + 1..]))
+}
+
+/// Splits a line into lines of at most 75 bytes, as iCalendar requires.
+pub(crate) fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// The items of a comma separated list such as `CATEGORIES`.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                items.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+
+    items
+        .into_iter()
+        .map(|item| unescape(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// A VTODO in a collection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// Full URL of the item.
+    pub href: String,
+    pub etag: Option<String>,
+    /// The iCalendar object.
+    pub data: String,
+}
+
+/// A CalDAV collection of VTODOs.
+pub trait Collection {
+    /// URL of the collection, ending in `/`.
+    fn url(&self) -> &str;
+
+    /// Every VTODO in the collection.
+    fn list(&self) -> Result<Vec<Item>, ToNotDoError>;
+
+    /// Stores `data` at `href` as long as the item there still has the ETag
+    /// `etag`, or as a new item when `etag` is `None`. Fails with
+    /// [`ToNotDoError::RemoteConflict`] when someone else got there first.
+    fn put(&self, href: &str, data: &str, etag: Option<&str>) -> Result<(), ToNotDoError>;
+
+    /// Deletes the item at `href` as long as it still has the ETag `etag`.
+    fn delete(&self, href: &str, etag: Option<&str>) -> Result<(), ToNotDoError>;
+}
+
+fn caldav_error(url: &str, reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::CalDavError(format!("{}: {}", url, reason))
+}
+
+/// A collection on a CalDAV server, using basic authentication.
+pub struct CalDavCollection {
+    agent: ureq::Agent,
+    url: String,
+    authorization: Option<String>,
+}
+
+impl CalDavCollection {
+    pub fn new(config: &CalDavConfig) -> Result<Self, ToNotDoError> {
+        let url = config.url.as_deref().ok_or_else(|| {
+            ToNotDoError::CalDavError(
+                "No task list; set url in the [caldav] config section".to_string(),
+            )
+        })?;
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(caldav_error(url, "not an http(s) URL"));
+        }
+
+        let authorization = config.username.as_ref().map(|username| {
+            let password = config
+                .password
+                .clone()
+                .or_else(|| std::env::var(CALDAV_PASSWORD_ENV).ok())
+                .unwrap_or_default();
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", username, password))
+            )
+        });
+
+        Ok(Self {
+            // Statuses like 404 and 412 are answers here, not failures.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            url: format!("{}/", url.trim_end_matches('/')),
+            authorization,
+        })
+    }
+
+    fn authorize<B>(&self, request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+        match &self.authorization {
+            Some(authorization) => request.header("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    /// `href` from a response as a full URL.
+    fn resolve(&self, href: &str) -> String {
+        if href.starts_with("http://") || href.starts_with("https://") {
+            return href.to_string();
+        }
+        match href.strip_prefix('/') {
+            Some(path) => {
+                let (scheme, rest) = self.url.split_once("://").unwrap_or(("https", &self.url));
+                let host = rest.split('/').next().unwrap_or(rest);
+                format!("{}://{}/{}", scheme, host, path)
+            }
+            None => format!("{}{}", self.url, href),
+        }
+    }
+}
+
+impl Collection for CalDavCollection {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn list(&self) -> Result<Vec<Item>, ToNotDoError> {
+        let (status, body) = report(&self.url, self.authorization.as_deref(), CALENDAR_QUERY)?;
+        match status {
+            207 => Ok(multistatus_items(&body)
+                .into_iter()
+                .map(|item| Item {
+                    href: self.resolve(&item.href),
+                    ..item
+                })
+                .collect()),
+            401 | 403 => Err(caldav_error(
+                &self.url,
+                "access denied; check username and password in the [caldav] config section",
+            )),
+            status => Err(caldav_error(
+                &self.url,
+                format!("unexpected status {}", status),
+            )),
+        }
+    }
+
+    fn put(&self, href: &str, data: &str, etag: Option<&str>) -> Result<(), ToNotDoError> {
+        let request = self
+            .authorize(self.agent.put(href))
+            .header("Content-Type", "text/calendar; charset=utf-8");
+        let request = match etag {
+            Some(etag) => request.header("If-Match", etag),
+            None => request.header("If-None-Match", "*"),
+        };
+
+        let response = request.send(data).map_err(|e| caldav_error(href, e))?;
+        match response.status().as_u16() {
+            200..=299 => Ok(()),
+            412 => Err(ToNotDoError::RemoteConflict(href.to_string())),
+            status => Err(caldav_error(href, format!("unexpected status {}", status))),
+        }
+    }
+
+    fn delete(&self, href: &str, etag: Option<&str>) -> Result<(), ToNotDoError> {
+        let mut request = self.authorize(self.agent.delete(href));
+        if let Some(etag) = etag {
+            request = request.header("If-Match", etag);
+        }
+
+        let response = request.call().map_err(|e| caldav_error(href, e))?;
+        match response.status().as_u16() {
+            200..=299 | 404 => Ok(()),
+            412 => Err(ToNotDoError::RemoteConflict(href.to_string())),
+            status => Err(caldav_error(href, format!("unexpected status {}", status))),
+        }
+    }
+}
+
+/// Sends a `REPORT` request with `body` to `url`, returning the status and
+/// the body of the response. ureq only sends the methods of HTTP/1.1 itself,
+/// so this one goes over a connection of our own.
+fn report(
+    url: &str,
+    authorization: Option<&str>,
+    body: &str,
+) -> Result<(u16, String), ToNotDoError> {
+    let error = |reason: &dyn std::fmt::Display| caldav_error(url, reason);
+    let uri: ureq::http::Uri = url.parse().map_err(|e| error(&e))?;
+    let https = uri.scheme_str() == Some("https");
+    let host = uri.host().ok_or_else(|| error(&"no host"))?;
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let authority = uri.authority().map_or(host, |authority| authority.as_str());
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    let mut request = format!(
+        "REPORT {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Depth: 1\r\n\
+         Content-Type: application/xml; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n",
+        path,
+        authority,
+        body.len()
+    );
+    if let Some(authorization) = authorization {
+        request.push_str(&format!("Authorization: {}\r\n", authorization));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let stream = TcpStream::connect((host, port)).map_err(|e| error(&e))?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| error(&e))?;
+
+    let response = if https {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| error(&e))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name =
+            rustls::pki_types::ServerName::try_from(host.to_string()).map_err(|e| error(&e))?;
+        let connection =
+            rustls::ClientConnection::new(Arc::new(config), name).map_err(|e| error(&e))?;
+        exchange(rustls::StreamOwned::new(connection, stream), &request)
+    } else {
+        exchange(stream, &request)
+    }
+    .map_err(|e| error(&e))?;
+
+    parse_response(&response).ok_or_else(|| error(&"invalid HTTP response"))
+}
+
+/// Writes `request` and reads the response until the server hangs up.
+fn exchange(mut stream: impl Read + Write, request: &str) -> std::io::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        Ok(_) => Ok(response),
+        // Plenty of servers close TLS connections without saying so.
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => {
+            Ok(response)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The status and body of a raw HTTP/1.1 response.
+fn parse_response(response: &[u8]) -> Option<(u16, String)> {
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..split]).ok()?;
+    let body = &response[split + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+    Some((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Joins the chunks of a chunked body.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut joined = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(joined);
+        }
+        joined.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+/// The items in a multistatus response to a calendar query, with their
+/// hrefs as the server gave them.
+fn multistatus_items(xml: &str) -> Vec<Item> {
+    elements(xml, "response")
+        .into_iter()
+        .filter_map(|response| {
+            let text = |name| elements(response, name).first().map(|text| xml_text(text));
+            Some(Item {
+                href: text("href")?,
+                etag: text("getetag").filter(|etag| !etag.is_empty()),
+                data: text("calendar-data").filter(|data| !data.is_empty())?,
+            })
+        })
+        .collect()
+}
+
+/// The contents of every element called `name` in `xml`, whatever its
+/// namespace prefix. Enough for the responses of CalDAV servers, which do
+/// not nest elements of the same name.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let tag_name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let local_name = tag_name.rsplit(':').next().unwrap_or(tag_name);
+        if tag.starts_with(['/', '?', '!']) || local_name != name {
+            continue;
+        }
+        if tag.ends_with('/') {
+            found.push("");
+            continue;
+        }
+
+        let close = format!("</{}>", tag_name);
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        found.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    found
+}
+
+/// The text of an element, with entities and CDATA sections read.
+fn xml_text(text: &str) -> String {
+    let text = text.trim();
+    if let Some(cdata) = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|text| text.strip_suffix("]]>"))
+    {
+        return cdata.to_string();
+    }
+
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest.find(';').and_then(|semi| {
+            let c = match &rest[1..semi] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                code => match code.strip_prefix("#x").or_else(|| code.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => code.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                }
+                .and_then(char::from_u32),
+            };
+            c.map(|c| (c, semi))
+        });
+        match entity {
+            Some((c, semi)) => {
+                unescaped.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// What a [`sync`] changed on either side.
+#[derive(Debug, Default)]
+pub struct CalDavReport {
+    /// Tasks added, changed or deleted here.
+    pub pulled: usize,
+    /// VTODOs added, changed or deleted on the server.
+    pub pushed: usize,
+    /// Tasks that failed to sync; they are tried again next time.
+    pub failures: Vec<ToNotDoError>,
+}
+
+/// A task paired with a VTODO, with the fields both had after the last sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Synced {
+    href: String,
+    uid: String,
+    fields: TodoFields,
+}
+
+/// The last agreed state of each synced task, stored under the state
+/// directory so the next sync can tell what changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CalDavState {
+    /// The collection the tasks were synced with.
+    url: String,
+    tasks: BTreeMap<Uuid, Synced>,
+}
+
+impl CalDavState {
+    fn read(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+/// A VTODO from the server with what was read from it.
+struct Remote {
+    item: Item,
+    uid: String,
+    fields: TodoFields,
+}
+
+/// What syncing one task did.
+#[derive(Default)]
+struct Outcome {
+    synced: Option<(Uuid, Synced)>,
+    pulled: bool,
+    pushed: bool,
+}
+
+/// Syncs the tasks of `db_manager` with the VTODOs of `collection` both
+/// ways, remembering in `state_path` how both sides were left. Archived
+/// tasks are only uploaded if they were synced before being archived.
+pub fn sync(
+    db_manager: &mut DatabaseManager,
+    collection: &dyn Collection,
+    state_path: &Path,
+) -> Result<CalDavReport, ToNotDoError> {
+    let mut state = CalDavState::read(state_path);
+    if state.url != collection.url() {
+        state = CalDavState {
+            url: collection.url().to_string(),
+            tasks: BTreeMap::new(),
+        };
+    }
+
+    let mut remote: BTreeMap<String, Remote> = collection
+        .list()?
+        .into_iter()
+        .filter_map(|item| {
+            let (uid, fields) = parse(&item.data)?;
+            Some((item.href.clone(), Remote { item, uid, fields }))
+        })
+        .collect();
+    let snapshot = db_manager.database()?.clone();
+    let local = |id: Uuid| snapshot.task(id);
+    let now = Utc::now();
+
+    let mut report = CalDavReport::default();
+    let mut synced = BTreeMap::new();
+    let mut record =
+        |result: Result<Outcome, ToNotDoError>, previous: Option<(Uuid, Synced)>| match result {
+            Ok(outcome) => {
+                report.pulled += usize::from(outcome.pulled);
+                report.pushed += usize::from(outcome.pushed);
+                synced.extend(outcome.synced);
+            }
+            Err(e) => {
+                report.failures.push(e);
+                synced.extend(previous);
+            }
+        };
+
+    db_manager.begin();
+    let known: BTreeSet<Uuid> = state.tasks.keys().copied().collect();
+    for (id, entry) in std::mem::take(&mut state.tasks) {
+        let result = sync_known(
+            db_manager,
+            collection,
+            id,
+            &entry,
+            local(id),
+            remote.remove(&entry.href),
+            now,
+        );
+        record(result, Some((id, entry)));
+    }
+
+    let mut linked = BTreeSet::new();
+    for (href, remote) in remote {
+        let id = Uuid::parse_str(&remote.uid)
+            .ok()
+            .filter(|id| !known.contains(id) && !linked.contains(id))
+            .unwrap_or_else(Uuid::new_v4);
+        linked.insert(id);
+        let result = sync_new_remote(db_manager, collection, id, href, remote, local(id), now);
+        record(result, None);
+    }
+
+    for task in snapshot.tasks() {
+        let id = task.id();
+        if known.contains(&id) || linked.contains(&id) || task.is_archived() {
+            continue;
+        }
+        let uid = id.to_string();
+        let entry = Synced {
+            href: format!("{}{}.ics", collection.url(), uid),
+            fields: TodoFields::of(task),
+            uid,
+        };
+        let result = collection
+            .put(
+                &entry.href,
+                &render(&entry.uid, &entry.fields, None, now),
+                None,
+            )
+            .map(|()| Outcome {
+                synced: Some((id, entry)),
+                pushed: true,
+                ..Outcome::default()
+            });
+        record(result, None);
+    }
+    db_manager.commit()?;
+
+    state.tasks = synced;
+    state.write(state_path)?;
+    Ok(report)
+}
+
+/// Syncs a task that was paired with a VTODO by an earlier sync. Either may
+/// have been deleted since; the other is then deleted too, unless it was
+/// changed, in which case it is copied back.
+fn sync_known(
+    db_manager: &mut DatabaseManager,
+    collection: &dyn Collection,
+    id: Uuid,
+    entry: &Synced,
+    task: Option<&Task>,
+    remote: Option<Remote>,
+    now: DateTime<Utc>,
+) -> Result<Outcome, ToNotDoError> {
+    let mut outcome = Outcome::default();
+    match (task, remote) {
+        (None, None) => {}
+        (None, Some(remote)) if remote.fields == entry.fields => {
+            collection.delete(&remote.item.href, remote.item.etag.as_deref())?;
+            outcome.pushed = true;
+        }
+        (None, Some(remote)) => {
+            // Deleted here but changed there; the old ID may not be reused.
+            let id = Uuid::new_v4();
+            store(db_manager, id, None, &remote.fields)?;
+            outcome.pulled = true;
+            outcome.synced = Some((
+                id,
+                Synced {
+                    fields: remote.fields,
+                    ..entry.clone()
+                },
+            ));
+        }
+        (Some(task), None) if TodoFields::of(task) == entry.fields => {
+            db_manager.delete_task(id)?;
+            outcome.pulled = true;
+        }
+        (Some(task), None) => {
+            let fields = TodoFields::of(task);
+            collection.put(&entry.href, &render(&entry.uid, &fields, None, now), None)?;
+            outcome.pushed = true;
+            outcome.synced = Some((
+                id,
+                Synced {
+                    fields,
+                    ..entry.clone()
+                },
+            ));
+        }
+        (Some(task), Some(remote)) => {
+            let fields = TodoFields::merge(&entry.fields, &TodoFields::of(task), &remote.fields);
+            outcome = update_both(db_manager, collection, id, task, &remote, fields, now)?;
+        }
+    }
+    Ok(outcome)
+}
+
+/// Pairs a VTODO not seen before with a task: the one with its UID as ID if
+/// there is one, or a new one.
+fn sync_new_remote(
+    db_manager: &mut DatabaseManager,
+    collection: &dyn Collection,
+    id: Uuid,
+    href: String,
+    remote: Remote,
+    task: Option<&Task>,
+    now: DateTime<Utc>,
+) -> Result<Outcome, ToNotDoError> {
+    match task {
+        // Paired by a sync that failed to save its state; what is here wins.
+        Some(task) => {
+            let fields = TodoFields::of(task);
+            update_both(db_manager, collection, id, task, &remote, fields, now)
+        }
+        None => {
+            store(db_manager, id, None, &remote.fields)?;
+            Ok(Outcome {
+                synced: Some((
+                    id,
+                    Synced {
+                        href,
+                        uid: remote.uid,
+                        fields: remote.fields,
+                    },
+                )),
+                pulled: true,
+                pushed: false,
+            })
+        }
+    }
+}
+
+/// Gives both the task and the VTODO `fields`, where they differ.
+fn update_both(
+    db_manager: &mut DatabaseManager,
+    collection: &dyn Collection,
+    id: Uuid,
+    task: &Task,
+    remote: &Remote,
+    fields: TodoFields,
+    now: DateTime<Utc>,
+) -> Result<Outcome, ToNotDoError> {
+    let pulled = fields != TodoFields::of(task);
+    if pulled {
+        store(db_manager, id, Some(task), &fields)?;
+    }
+    let pushed = fields != remote.fields;
+    if pushed {
+        let data = render(&remote.uid, &fields, Some(&remote.item.data), now);
+        collection.put(&remote.item.href, &data, remote.item.etag.as_deref())?;
+    }
+
+    Ok(Outcome {
+        synced: Some((
+            id,
+            Synced {
+                href: remote.item.href.clone(),
+                uid: remote.uid.clone(),
+                fields,
+            },
+        )),
+        pulled,
+        pushed,
+    })
+}
+
+/// Gives the task `id` the synced `fields`, adding it when there is no
+/// `task` yet.
+fn store(
+    db_manager: &mut DatabaseManager,
+    id: Uuid,
+    task: Option<&Task>,
+    fields: &TodoFields,
+) -> Result<(), ToNotDoError> {
+    let task = match task {
+        Some(task) => task.clone(),
+        None => {
+            let task = Task::new(&fields.summary).with_id(id);
+            db_manager.add_task(&task)?;
+            task
+        }
+    };
+
+    if task.description() != fields.summary {
+        db_manager.update_description(id, &fields.summary)?;
+    }
+    if task.notes() != fields.notes.as_deref() {
+        db_manager.set_notes(id, fields.notes.as_deref())?;
+    }
+    if task.state() != fields.state {
+        db_manager.set_task_state(id, fields.state)?;
+    }
+    if task.due() != fields.due {
+        db_manager.set_due(id, fields.due)?;
+    }
+    if task.priority() != fields.priority {
+        db_manager.set_priority(id, fields.priority)?;
+    }
+    if task.tags() != fields.categories {
+        db_manager.set_tags(id, &fields.categories)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+    use tempfile::tempdir;
+
+    const PHONE_TODO: &str = "BEGIN:VCALENDAR\r\n\
+        VERSION:2.0\r\n\
+        PRODID:+//IDN tasks.org//android\r\n\
+        BEGIN:VTODO\r\n\
+        UID:3916441817683594283\r\n\
+        SUMMARY:Buy milk\\, eggs\r\n\
+        DESCRIPTION:From the corner shop\\nnot the big one\r\n\
+        STATUS:IN-PROCESS\r\n\
+        DUE;TZID=Europe/Berlin:20250310T180000\r\n\
+        PRIORITY:2\r\n\
+        CATEGORIES:errands,food\\, mostly\r\n\
+        X-APPLE-SORT-ORDER:42\r\n\
+        BEGIN:VALARM\r\n\
+        ACTION:DISPLAY\r\n\
+        DESCRIPTION:Reminder\r\n\
+        END:VALARM\r\n\
+        END:VTODO\r\n\
+        END:VCALENDAR\r\n";
+
+    /// A collection kept in memory, with ETags counting the writes.
+    #[derive(Default)]
+    struct MemoryCollection {
+        items: RefCell<BTreeMap<String, (String, String)>>,
+        writes: RefCell<usize>,
+    }
+
+    impl MemoryCollection {
+        fn data(&self, href: &str) -> Option<String> {
+            self.items.borrow().get(href).map(|(_, data)| data.clone())
+        }
+
+        fn set(&self, href: &str, data: &str) {
+            *self.writes.borrow_mut() += 1;
+            let etag = format!("\"{}\"", self.writes.borrow());
+            self.items
+                .borrow_mut()
+                .insert(href.to_string(), (etag, data.to_string()));
+        }
+    }
+
+    impl Collection for MemoryCollection {
+        fn url(&self) -> &str {
+            "https://dav.example.com/tasks/"
+        }
+
+        fn list(&self) -> Result<Vec<Item>, ToNotDoError> {
+            Ok(self
+                .items
+                .borrow()
+                .iter()
+                .map(|(href, (etag, data))| Item {
+                    href: href.clone(),
+                    etag: Some(etag.clone()),
+                    data: data.clone(),
+                })
+                .collect())
+        }
+
+        fn put(&self, href: &str, data: &str, etag: Option<&str>) -> Result<(), ToNotDoError> {
+            let current = self.items.borrow().get(href).map(|(etag, _)| etag.clone());
+            if current.as_deref() != etag {
+                return Err(ToNotDoError::RemoteConflict(href.to_string()));
+            }
+            self.set(href, data);
+            Ok(())
+        }
+
+        fn delete(&self, href: &str, _etag: Option<&str>) -> Result<(), ToNotDoError> {
+            self.items.borrow_mut().remove(href);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let (uid, fields) = parse(PHONE_TODO).unwrap();
+        assert_eq!(uid, "3916441817683594283");
+        assert_eq!(
+            fields,
+            TodoFields {
+                summary: "Buy milk, eggs".to_string(),
+                notes: Some("From the corner shop\nnot the big one".to_string()),
+                state: TaskState::InProgress,
+                due: NaiveDate::from_ymd_opt(2025, 3, 10),
+                priority: Some(Priority::High),
+                categories: vec!["errands".to_string(), "food, mostly".to_string()],
+            }
+        );
+
+        let folded = PHONE_TODO.replace("SUMMARY:Buy milk", "SUMMARY:Buy\r\n  milk");
+        assert_eq!(parse(&folded).unwrap().1.summary, "Buy milk, eggs");
+        assert!(parse("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:1\r\nEND:VEVENT\r\n").is_none());
+    }
+
+    #[test]
+    fn test_render() {
+        let now = Utc::now();
+        let (uid, mut fields) = parse(PHONE_TODO).unwrap();
+        fields.summary = "Buy oat milk".to_string();
+        fields.state = TaskState::Done;
+        fields.priority = None;
+
+        let data = render(&uid, &fields, Some(PHONE_TODO), now);
+        assert_eq!(parse(&data), Some((uid.clone(), fields.clone())));
+        assert!(data.contains("X-APPLE-SORT-ORDER:42\r\n"));
+        assert!(data
+            .contains("BEGIN:VALARM\r\nACTION:DISPLAY\r\nDESCRIPTION:Reminder\r\nEND:VALARM\r\n"));
+        assert!(data.contains("PERCENT-COMPLETE:100\r\n"));
+        assert!(!data.contains("PRIORITY"));
+
+        fields.notes = Some("x".repeat(200));
+        let data = render("new", &fields, None, now);
+        assert!(data.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(data.lines().all(|line| line.len() <= 75));
+        assert_eq!(parse(&data), Some(("new".to_string(), fields)));
+    }
+
+    #[test]
+    fn test_multistatus_items() {
+        let xml = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+              <d:response>
+                <d:href>/dav/tasks/a%40b.ics</d:href>
+                <d:propstat><d:prop>
+                  <d:getetag>&quot;1&quot;</d:getetag>
+                  <cal:calendar-data>BEGIN:VCALENDAR&#13;
+UID:a &amp; b&#13;
+END:VCALENDAR</cal:calendar-data>
+                </d:prop></d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/dav/tasks/</d:href>
+                <d:propstat><d:prop><d:getetag/><cal:calendar-data/></d:prop></d:propstat>
+              </d:response>
+              <response xmlns="DAV:"><href>c.ics</href>
+                <propstat><prop><C:calendar-data xmlns:C="urn:ietf:params:xml:ns:caldav"><![CDATA[BEGIN:VTODO]]></C:calendar-data></prop></propstat>
+              </response>
+            </d:multistatus>"#;
+
+        let items = multistatus_items(xml);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].href, "/dav/tasks/a%40b.ics");
+        assert_eq!(items[0].etag.as_deref(), Some("\"1\""));
+        assert_eq!(
+            items[0].data,
+            "BEGIN:VCALENDAR\r\nUID:a & b\r\nEND:VCALENDAR"
+        );
+        assert_eq!(items[1].href, "c.ics");
+        assert_eq!(items[1].etag, None);
+        assert_eq!(items[1].data, "BEGIN:VTODO");
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = b"HTTP/1.1 207 Multi-Status\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nHello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_response(response),
+            Some((207, "Hello, world".to_string()))
+        );
+        assert_eq!(
+            parse_response(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n"),
+            Some((401, String::new()))
+        );
+        assert_eq!(parse_response(b"garbage"), None);
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempdir().unwrap();
+        let state = dir.path().join("tasks.caldav");
+        let collection = MemoryCollection::default();
+        let phone_href = "https://dav.example.com/tasks/phone.ics";
+        collection.set(phone_href, PHONE_TODO);
+
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let report_task = Task::new("Write report").with_priority(Priority::Low);
+        db_manager.add_task(&report_task).unwrap();
+
+        let report = sync(&mut db_manager, &collection, &state).unwrap();
+        assert_eq!((report.pulled, report.pushed), (1, 1));
+        assert!(report.failures.is_empty());
+
+        let report_href = format!("{}{}.ics", collection.url(), report_task.id());
+        let (uid, fields) = parse(&collection.data(&report_href).unwrap()).unwrap();
+        assert_eq!(uid, report_task.id().to_string());
+        assert_eq!(fields, TodoFields::of(&report_task));
+
+        let milk = db_manager
+            .get_tasks()
+            .unwrap()
+            .iter()
+            .find(|task| task.description() == "Buy milk, eggs")
+            .unwrap()
+            .clone();
+        assert_eq!(milk.state(), TaskState::InProgress);
+        assert_eq!(milk.tags(), ["errands", "food, mostly"]);
+
+        let report = sync(&mut db_manager, &collection, &state).unwrap();
+        assert_eq!((report.pulled, report.pushed), (0, 0), "nothing changed");
+
+        // Both sides change the milk task, and the phone finishes the report.
+        db_manager
+            .set_due(milk.id(), NaiveDate::from_ymd_opt(2025, 3, 12))
+            .unwrap();
+        db_manager
+            .update_description(milk.id(), "Buy milk")
+            .unwrap();
+        let (_, mut phone_fields) = parse(PHONE_TODO).unwrap();
+        phone_fields.summary = "Buy milk and eggs".to_string();
+        phone_fields.state = TaskState::Done;
+        collection.set(
+            phone_href,
+            &render(
+                "3916441817683594283",
+                &phone_fields,
+                Some(PHONE_TODO),
+                Utc::now(),
+            ),
+        );
+        let mut done = fields.clone();
+        done.state = TaskState::Done;
+        collection.set(&report_href, &render(&uid, &done, None, Utc::now()));
+
+        let report = sync(&mut db_manager, &collection, &state).unwrap();
+        assert_eq!((report.pulled, report.pushed), (2, 1));
+        let milk = db_manager.get_task(milk.id()).unwrap().clone();
+        assert_eq!(milk.description(), "Buy milk", "the change made here wins");
+        assert_eq!(milk.state(), TaskState::Done);
+        assert_eq!(milk.due(), NaiveDate::from_ymd_opt(2025, 3, 12));
+        let (_, phone_fields) = parse(&collection.data(phone_href).unwrap()).unwrap();
+        assert_eq!(phone_fields, TodoFields::of(&milk));
+        assert!(collection
+            .data(phone_href)
+            .unwrap()
+            .contains("BEGIN:VALARM"));
+        assert_eq!(
+            db_manager.get_task(report_task.id()).unwrap().state(),
+            TaskState::Done
+        );
+
+        // Deleting on either side deletes on the other.
+        db_manager.delete_task(milk.id()).unwrap();
+        collection.delete(&report_href, None).unwrap();
+        let report = sync(&mut db_manager, &collection, &state).unwrap();
+        assert_eq!((report.pulled, report.pushed), (1, 1));
+        assert!(db_manager.get_tasks().unwrap().is_empty());
+        assert!(collection.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_report_request() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://me:secret@{}/tasks/", listener.local_addr().unwrap());
+        listener.set_nonblocking(true).unwrap();
+
+        let app = axum::Router::new().route(
+            "/tasks/",
+            axum::routing::any(
+                |method: axum::http::Method, headers: axum::http::HeaderMap, body: String| async move {
+                    assert_eq!(method.as_str(), "REPORT");
+                    assert_eq!(headers["depth"], "1");
+                    assert_eq!(headers["authorization"], "Basic bWU6c2VjcmV0");
+                    assert!(body.contains("comp-filter name=\"VTODO\""));
+                    (
+                        axum::http::StatusCode::MULTI_STATUS,
+                        "<multistatus><response><href>a.ics</href><getetag>1</getetag>\
+                         <calendar-data>BEGIN:VCALENDAR</calendar-data></response></multistatus>",
+                    )
+                },
+            ),
+        );
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await
+            })
+        });
+
+        let (status, body) = report(&url, Some("Basic bWU6c2VjcmV0"), CALENDAR_QUERY).unwrap();
+        assert_eq!(status, 207);
+        assert_eq!(multistatus_items(&body)[0].href, "a.ics");
+    }
+}
@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::Database,
+    storage::{write_atomically, Revision, Storage},
+};
+
+/// Checksum file kept next to the database file at `db_file`.
+pub fn checksum_path(db_file: &Path) -> PathBuf {
+    db_file.with_extension("sum")
+}
+
+/// What the database file looked like when it was last saved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    /// Number of times the file has been saved since checksums were kept.
+    pub generation: u64,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Checksum {
+    fn of(data: &[u8], generation: u64) -> Self {
+        Self {
+            generation,
+            size: data.len() as u64,
+            sha256: Sha256::digest(data)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+
+    pub fn read(path: &Path) -> Option<Self> {
+        let json = std::fs::read(path).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+/// How the database file differs from its last save by this tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Missing,
+    Truncated { expected: u64, actual: u64 },
+    Modified,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mismatch::Missing => write!(f, "is missing"),
+            Mismatch::Truncated { expected, actual } => {
+                write!(f, "is truncated ({} of {} bytes)", actual, expected)
+            }
+            Mismatch::Modified => write!(f, "was modified outside to-not-do"),
+        }
+    }
+}
+
+/// Compares the database file at `db_file` with the checksum recorded when
+/// it was last saved. Files saved before checksums were kept always match.
+pub fn check(db_file: &Path) -> Option<Mismatch> {
+    let checksum = Checksum::read(&checksum_path(db_file))?;
+    let Ok(data) = std::fs::read(db_file) else {
+        return Some(Mismatch::Missing);
+    };
+
+    if (data.len() as u64) < checksum.size {
+        return Some(Mismatch::Truncated {
+            expected: checksum.size,
+            actual: data.len() as u64,
+        });
+    }
+
+    (Checksum::of(&data, checksum.generation) != checksum).then_some(Mismatch::Modified)
+}
+
+/// Records a checksum of the database file at `db_file` as it is now, for
+/// [`check`] to compare against. Everything that writes the file must call
+/// this afterwards, which [`ChecksumStorage`] does.
+pub fn record(db_file: &Path) -> Result<(), ToNotDoError> {
+    let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+    let path = checksum_path(db_file);
+    let generation = Checksum::read(&path).map_or(0, |checksum| checksum.generation) + 1;
+    let data = std::fs::read(db_file).map_err(write_error)?;
+    let json = serde_json::to_vec_pretty(&Checksum::of(&data, generation))
+        .map_err(|e| write_error(e.into()))?;
+
+    write_atomically(&path, &json).map_err(write_error)
+}
+
+/// Records a checksum of the database file every time another storage saves
+/// it, for [`check`] to compare against on the next start. Per-project files
+/// are not covered.
+pub struct ChecksumStorage {
+    inner: Box<dyn Storage>,
+    db_file: PathBuf,
+}
+
+impl ChecksumStorage {
+    pub fn new(inner: Box<dyn Storage>, db_file: &Path) -> Self {
+        Self {
+            inner,
+            db_file: db_file.to_path_buf(),
+        }
+    }
+}
+
+impl Storage for ChecksumStorage {
+    fn exists(&self) -> bool {
+        self.inner.exists()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        // Loading saves the file again when it migrates it.
+        let before = Revision::of([&self.db_file]);
+        let db = self.inner.load()?;
+        if Revision::of([&self.db_file]) != before {
+            record(&self.db_file)?;
+        }
+        Ok(db)
+    }
+
+    fn revision(&self) -> Option<Revision> {
+        self.inner.revision()
+    }
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.inner.save(db)?;
+        record(&self.db_file)
+    }
+
+    fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+        let revision = self.inner.save_revised(db)?;
+        record(&self.db_file)?;
+        Ok(revision)
+    }
+
+    fn stage(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.inner.stage(db)
+    }
+
+    fn set_read_only(&mut self) {
+        self.inner.set_read_only();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_management::{DatabaseManager, Task},
+        storage::FileStorage,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checksum_detects_outside_changes() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let storage = || {
+            Box::new(ChecksumStorage::new(
+                Box::new(FileStorage::new(&db_file)),
+                &db_file,
+            ))
+        };
+
+        assert_eq!(check(&db_file), None);
+
+        let mut db_manager = DatabaseManager::with_storage(storage()).unwrap();
+        db_manager.add_task(&Task::new("Checked")).unwrap();
+        assert_eq!(check(&db_file), None);
+        let generation = Checksum::read(&checksum_path(&db_file)).unwrap().generation;
+        assert_eq!(generation, 2);
+
+        let json = std::fs::read_to_string(&db_file).unwrap();
+        std::fs::write(&db_file, json.replace("Checked", "Chacked")).unwrap();
+        assert_eq!(check(&db_file), Some(Mismatch::Modified));
+
+        std::fs::write(&db_file, &json[..json.len() / 2]).unwrap();
+        assert!(matches!(check(&db_file), Some(Mismatch::Truncated { .. })));
+
+        std::fs::remove_file(&db_file).unwrap();
+        assert_eq!(check(&db_file), Some(Mismatch::Missing));
+
+        // Saving again accepts the file as it now is.
+        DatabaseManager::with_storage(storage()).unwrap();
+        assert_eq!(check(&db_file), None);
+    }
+}
@@ -1,11 +1,177 @@
+use std::{
+    collections::HashSet,
+    io::{IsTerminal, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use chrono::{Duration, NaiveDate};
 use clap::{Parser, Subcommand, ValueEnum};
-use serde::{Deserialize, Serialize};
 use uuid::{self, Uuid};
 
-use crate::file_management::{self, Task};
+#[derive(Debug, Subcommand, Clone)]
+pub enum SyncService {
+    #[clap(
+        name = "todoist",
+        about = "Exchange changes with Todoist, both ways [env: TO_NOT_DO_TODOIST_TOKEN]"
+    )]
+    Todoist {
+        #[arg(
+            long,
+            value_enum,
+            default_value = "ours",
+            help = "Side whose change wins when a field changed on both"
+        )]
+        prefer: MergePreference,
+    },
+    #[clap(
+        name = "github",
+        about = "Mirror the open issues of a repository assigned to you as tasks [env: TO_NOT_DO_GITHUB_TOKEN]"
+    )]
+    Github {
+        #[arg(long, value_name = "OWNER/NAME")]
+        repo: String,
+        #[arg(
+            long,
+            help = "Close the issues whose task is done, as close_issues in the [github] config section does"
+        )]
+        close: bool,
+        #[arg(
+            long,
+            help = "Ask for a personal access token and store it in the OS keychain first"
+        )]
+        save_token: bool,
+    },
+    #[clap(
+        name = "markdown",
+        about = "Mirror tasks to and from `- [ ]` checkboxes in a folder of Markdown notes"
+    )]
+    Markdown {
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Folder of notes, instead of vault in the [markdown] config section"
+        )]
+        vault: Option<PathBuf>,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "ours",
+            help = "Side whose change wins when a task changed on both"
+        )]
+        prefer: MergePreference,
+    },
+    #[clap(
+        name = "gcal",
+        about = "Show tasks with a due date as all-day events in a Google Calendar, both ways"
+    )]
+    Gcal {
+        #[arg(
+            long,
+            value_enum,
+            default_value = "ours",
+            help = "Side whose change wins when a field changed on both"
+        )]
+        prefer: MergePreference,
+        #[arg(
+            long,
+            help = "Sign in to Google first and store the tokens in the OS keychain"
+        )]
+        login: bool,
+    },
+    #[clap(
+        name = "status",
+        about = "Show when the last sync was, the changes waiting to be pushed and the unresolved conflicts"
+    )]
+    Status,
+    #[clap(
+        name = "resolve",
+        about = "Go through the tasks edited on two devices at once, field by field"
+    )]
+    Resolve {
+        #[arg(
+            long,
+            value_enum,
+            help = "Side to keep in every conflict instead of asking; newest keeps what the sync chose"
+        )]
+        prefer: Option<MergePreference>,
+    },
+}
+
+#[cfg(feature = "dbus")]
+use to_not_do::dbus;
+#[cfg(feature = "plugins")]
+use to_not_do::plugin::{PluginHost, PLUGINS_DIR};
+#[cfg(feature = "notifications")]
+use to_not_do::reminder::{self, ReminderAction, ReminderState};
+#[cfg(feature = "scripting")]
+use to_not_do::script;
+use to_not_do::{
+    archive, caldav, checksum, compact,
+    config::{Column, CompactConfig, Config},
+    conflict,
+    digest::{self, Digest, DigestPeriod},
+    dump::Dump,
+    duration::parse_duration,
+    encryption::{self, Cipher, KeyringKeySource},
+    error::{DatabaseError, ToNotDoError},
+    events, external, feed,
+    file_management::{
+        self, AppPaths, MergePreference, Priority, Reload, Task, TaskState, APP_NAME, DB_FILE_NAME,
+        LOCAL_DIR_NAME,
+    },
+    filter::TaskFilter,
+    foreign,
+    format::Format,
+    gcal, git_hook, github, hooks, jira, markdown, matrix, mcp, microsoft_todo, migration, org,
+    profile, publish, remote, repair,
+    reporting::{self, NO_PROJECT},
+    rpc, schedule, serve, snapshot, stats,
+    statusbar::{self, Bar},
+    storage::{self, FileStorage, Storage},
+    sync, systemd,
+    telegram::{self, TelegramApi},
+    todoist, uri, verify,
+    webhook::{self, WebhookConfig, WebhookEvent},
+};
 
 #[derive(Parser)]
+#[command(after_help = "\
+Files:
+  The config file lives in the config directory (e.g. ~/.config/to-not-do),
+  the default database and backups in the data directory (e.g.
+  ~/.local/share/to-not-do) and sync state in the state directory (e.g.
+  ~/.local/state/to-not-do). $TO_NOT_DO_DATA_DIR puts all of them in one
+  directory. Profiles keep theirs in profiles/<name> inside each directory,
+  and a .tonotdo directory created by `init --local` keeps everything for a
+  project.
+
+  The database is chosen in this order: --db, --profile, the nearest
+  .tonotdo directory above the current one, the default_profile setting,
+  and finally the data directory. --db only moves the database; config and
+  backups still come from the profile, project or data directory.")]
 pub struct Args {
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Use this database file instead of the one in the data directory, a WebDAV \
+                (https://...) or S3 (s3://bucket/key) URL, or :memory: to save nothing"
+    )]
+    pub db: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Use this profile instead of the default one [env: TO_NOT_DO_PROFILE]"
+    )]
+    pub profile: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Open the database read-only, refusing any change (implied when it is not writable)"
+    )]
+    pub read_only: bool,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -14,7 +180,26 @@ pub struct Args {
 #[command(rename_all = "kebab-case")]
 pub enum Commands {
     #[clap(name = "add", about = "Add a new task")]
-    Add { task_description: String },
+    Add {
+        #[arg(required_unless_present = "from_clipboard")]
+        task_description: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "task_description",
+            help = "Create the task from the clipboard contents"
+        )]
+        from_clipboard: bool,
+        #[arg(long, help = "Due date (YYYY-MM-DD)")]
+        due: Option<NaiveDate>,
+        #[arg(long)]
+        priority: Option<Priority>,
+        #[arg(long = "tag", short = 't', help = "Tag the task (repeatable)")]
+        tags: Vec<String>,
+        #[arg(long, help = "Add the task as a subtask of another task")]
+        parent: Option<Uuid>,
+        #[arg(long, short = 'p', help = "Add the task to a project")]
+        project: Option<String>,
+    },
     #[clap(name = "update", about = "Update an existing task")]
     Update {
         task_id: Uuid,
@@ -23,24 +208,841 @@ pub enum Commands {
     #[clap(name = "delete", about = "Delete a task")]
     Delete { task_id: Uuid },
     #[clap(name = "list", about = "List tasks")]
-    List { filter: Option<TaskState> },
+    List {
+        filter: Option<TaskState>,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Show a table with these columns, e.g. id,desc,due,tags"
+        )]
+        columns: Option<Vec<Column>>,
+        #[arg(
+            long,
+            conflicts_with = "columns",
+            help = "Show subtasks nested under their parents"
+        )]
+        tree: bool,
+        #[arg(
+            long,
+            requires = "tree",
+            help = "Expand completed subtrees in the tree view"
+        )]
+        all: bool,
+        #[arg(
+            long,
+            conflicts_with = "tree",
+            help = "Group tasks by project with a progress footer"
+        )]
+        by_project: bool,
+        #[arg(long, conflicts_with = "tree", help = "Show archived tasks instead")]
+        archived: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["columns", "tree", "by_project"],
+            help = "Print one compact line per task, for grep/awk/fzf"
+        )]
+        oneline: bool,
+        #[arg(
+            long,
+            value_name = "USER",
+            help = "Only show tasks USER created, completed or changed"
+        )]
+        by: Option<String>,
+        #[cfg(feature = "plugins")]
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Only show tasks the plugin filter NAME accepts"
+        )]
+        plugin_filter: Option<String>,
+    },
+    #[clap(name = "project", about = "Inspect projects")]
+    Project {
+        #[command(subcommand)]
+        command: ProjectCommands,
+    },
+    #[clap(name = "init", about = "Create a database")]
+    Init {
+        #[arg(
+            long,
+            help = "Create a project-local database in .tonotdo in the current directory"
+        )]
+        local: bool,
+    },
+    #[clap(name = "profile", about = "Manage separate task lists")]
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    #[clap(name = "context", about = "Scope commands to a project")]
+    Context {
+        #[command(subcommand)]
+        command: Option<ContextCommands>,
+    },
+    #[clap(name = "show", about = "Show a task with its history")]
+    Show { task_id: Uuid },
+    #[clap(name = "link-uri", about = "Print a deep-link URI for a task")]
+    LinkUri { task_id: Uuid },
+    #[clap(name = "handle-uri", about = "Open a to-not-do:// deep-link URI")]
+    HandleUri { uri: String },
+    #[clap(name = "search", about = "Search task descriptions, notes and tags")]
+    Search {
+        query: String,
+        #[arg(long, help = "Only print the number of matching tasks")]
+        count: bool,
+    },
     #[clap(name = "mark-done", about = "Mark a task as done")]
     MarkDone { task_id: Uuid },
+    #[clap(
+        name = "done",
+        about = "Mark the open task matching a search text as done"
+    )]
+    Done { text: String },
     #[clap(name = "mark-in-progress", about = "Mark a task as in progress")]
     MarkInProgress { task_id: Uuid },
+    #[clap(name = "postpone", about = "Push a task's due date forward")]
+    Postpone {
+        #[arg(required_unless_present = "all_overdue", requires = "duration")]
+        task_id: Option<Uuid>,
+        #[arg(
+            value_parser = parse_duration,
+            required_unless_present = "all_overdue",
+            help = "How far to postpone, e.g. 2d or 1w"
+        )]
+        duration: Option<Duration>,
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            value_name = "DURATION",
+            conflicts_with_all = ["task_id", "duration"],
+            help = "Postpone every overdue task"
+        )]
+        all_overdue: Option<Duration>,
+    },
+    #[clap(
+        name = "prune",
+        about = "Archive or delete tasks that have not been touched in a while"
+    )]
+    Prune {
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            value_name = "DURATION",
+            help = "Prune tasks not updated within this long, e.g. 90d"
+        )]
+        older_than: Duration,
+        #[arg(long, help = "Only prune tasks in this state")]
+        state: Option<TaskState>,
+        #[arg(long, help = "Delete the tasks instead of archiving them")]
+        delete: bool,
+        #[arg(long, short = 'y', help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    #[clap(name = "focus", about = "Focus on a task, or show the focused task")]
+    Focus {
+        task_id: Option<Uuid>,
+        #[arg(long, conflicts_with = "task_id", help = "Clear the current focus")]
+        clear: bool,
+    },
+    #[clap(name = "status", about = "Print a one-line summary for shell prompts")]
+    Status {
+        #[arg(
+            long,
+            value_enum,
+            help = "Print the summary for a status bar instead, cached until the tasks change"
+        )]
+        format: Option<Bar>,
+        #[arg(long, conflicts_with = "format", help = "Same as --format tmux")]
+        tmux: bool,
+    },
+    #[clap(
+        name = "batch",
+        about = "Run newline-separated commands read from stdin, saving once at the end"
+    )]
+    Batch,
+    #[clap(name = "triage", about = "Walk through todo tasks one at a time")]
+    Triage,
+    #[clap(
+        name = "select",
+        about = "Pick several tasks and mark them done, retag or delete them at once"
+    )]
+    Select,
+    #[clap(
+        name = "backup",
+        about = "Copy the database and configuration into a timestamped backup file"
+    )]
+    Backup {
+        #[arg(
+            long,
+            short = 'o',
+            help = "File or directory to write the backup to (defaults to the backups folder)"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[clap(
+        name = "export",
+        about = "Write the whole database out for another machine"
+    )]
+    Export {
+        #[arg(long, value_enum, default_value = "dump")]
+        format: ExportFormat,
+        #[arg(long, short = 'o', help = "File to write to (defaults to stdout)")]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["output", "format"],
+            help = "Write a single compressed archive file, for moving to another machine"
+        )]
+        archive: Option<PathBuf>,
+        #[arg(
+            long,
+            requires = "archive",
+            help = "Protect the archive with a passphrase"
+        )]
+        encrypt: bool,
+    },
+    #[clap(
+        name = "import",
+        about = "Replace the database and configuration with an exported copy, or add tasks from another tool"
+    )]
+    Import {
+        #[arg(required_unless_present_any = ["archive", "from"])]
+        file: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["file", "format"],
+            help = "Read an archive written by `export --archive`"
+        )]
+        archive: Option<PathBuf>,
+        #[arg(
+            long,
+            value_enum,
+            conflicts_with_all = ["file", "archive", "format"],
+            help = "Add the open tasks of a service instead, keeping the current ones"
+        )]
+        from: Option<ImportSource>,
+        #[arg(
+            long,
+            value_name = "QUERY",
+            requires = "from",
+            help = "Issues to import with --from jira, instead of jql from the [jira] config section"
+        )]
+        jql: Option<String>,
+        #[arg(long, value_enum, default_value = "dump")]
+        format: ExportFormat,
+        #[arg(long, short = 'y', help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    #[clap(
+        name = "unlock",
+        about = "Store the encryption key in the OS keychain so commands stop asking for the passphrase"
+    )]
+    Unlock,
+    #[clap(
+        name = "lock",
+        about = "Remove the encryption key from the OS keychain"
+    )]
+    Lock,
+    #[clap(
+        name = "convert",
+        about = "Copy a database file into another storage format, e.g. JSON to MessagePack"
+    )]
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(
+            long,
+            value_enum,
+            help = "Format to write (defaults to the output file extension)"
+        )]
+        format: Option<Format>,
+    },
+    #[clap(
+        name = "repair",
+        about = "Recover an unreadable database from its remains and the latest backup"
+    )]
+    Repair,
+    #[clap(
+        name = "compact",
+        about = "Shrink the database by dropping old deletions and history and folding in the journal"
+    )]
+    Compact,
+    #[clap(
+        name = "purge",
+        about = "Apply the retention policies from the [compact] config section"
+    )]
+    Purge {
+        #[arg(
+            long,
+            required = true,
+            help = "Delete archived tasks, deletions and history kept longer than configured"
+        )]
+        expired: bool,
+        #[arg(long, short = 'y', help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    #[clap(
+        name = "webhook",
+        about = "Check the webhooks from the [[webhooks]] config sections"
+    )]
+    Webhook {
+        #[command(subcommand)]
+        command: WebhookCommands,
+    },
+    #[cfg(feature = "plugins")]
+    #[clap(
+        name = "plugin",
+        about = "Inspect the WASM plugins in the data directory"
+    )]
+    Plugin {
+        #[command(subcommand)]
+        command: PluginCommands,
+    },
+    #[cfg(feature = "scripting")]
+    #[clap(name = "script", about = "Run Lua scripts that query and change tasks")]
+    Script {
+        #[command(subcommand)]
+        command: ScriptCommands,
+    },
+    #[clap(
+        name = "tick",
+        about = "Apply the retention policies and report overdue tasks to the webhooks, e.g. from cron"
+    )]
+    Tick,
+    #[clap(
+        name = "digest",
+        about = "Summarize the tasks due, overdue and completed, or send the summary by email"
+    )]
+    Digest {
+        #[arg(long, value_enum, default_value_t = DigestPeriod::Daily)]
+        period: DigestPeriod,
+        #[arg(
+            long,
+            help = "Send it to the recipients from the [email] config section"
+        )]
+        email: bool,
+        #[arg(
+            long,
+            conflicts_with = "email",
+            help = "Print it as an email message, for piping to sendmail -t"
+        )]
+        stdout: bool,
+    },
+    #[clap(name = "generate", about = "Generate files for setting up to-not-do")]
+    Generate {
+        #[command(subcommand)]
+        command: GenerateCommands,
+    },
+    #[clap(
+        name = "git-hook",
+        about = "Mark tasks done from git commits whose message says `closes task:<id>`"
+    )]
+    GitHook {
+        #[command(subcommand)]
+        command: GitHookCommands,
+    },
+    #[clap(name = "daemon", about = "Keep running and tick on an interval")]
+    Daemon {
+        #[arg(
+            long,
+            value_name = "MINUTES",
+            default_value_t = 60,
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Minutes between ticks"
+        )]
+        every: u64,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Control socket answering JSON-RPC requests, instead of one in the state directory"
+        )]
+        socket: Option<PathBuf>,
+        #[arg(long, conflicts_with = "socket", help = "Listen on no control socket")]
+        no_socket: bool,
+    },
+    #[clap(
+        name = "bot",
+        about = "Keep running as a chat bot for adding and completing tasks"
+    )]
+    Bot {
+        #[command(subcommand)]
+        command: BotCommands,
+    },
+    #[cfg(feature = "notifications")]
+    #[clap(
+        name = "notify-daemon",
+        about = "Keep running and show a desktop notification when a task comes due"
+    )]
+    NotifyDaemon,
+    #[cfg(feature = "dbus")]
+    #[clap(
+        name = "dbus",
+        about = "Keep running as the org.tonotdo.Tasks service on the D-Bus session bus"
+    )]
+    Dbus,
+    /// A command provided by a plugin, or by a `to-not-do-<name>` program on
+    /// the `PATH`.
+    #[command(external_subcommand)]
+    External(Vec<String>),
+    #[clap(name = "db", about = "Inspect the database itself")]
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    #[clap(
+        name = "verify",
+        about = "Check the database for broken invariants such as duplicate IDs or missing parents"
+    )]
+    Verify {
+        #[arg(long, help = "Repair the problems that can be repaired")]
+        fix: bool,
+    },
+    #[clap(
+        name = "sync",
+        about = "Exchange changes with a sync server shared by your devices"
+    )]
+    #[command(args_conflicts_with_subcommands = true)]
+    Sync {
+        #[arg(
+            long,
+            value_name = "URL",
+            help = "Server to sync with; defaults to the one used last time"
+        )]
+        remote: Option<String>,
+        #[arg(long, help = "Token to authenticate with [env: TO_NOT_DO_SYNC_TOKEN]")]
+        token: Option<String>,
+        #[command(subcommand)]
+        service: Option<SyncService>,
+    },
+    #[clap(
+        name = "caldav",
+        about = "Sync with the CalDAV task list from the [caldav] config section"
+    )]
+    Caldav {
+        #[command(subcommand)]
+        command: CaldavCommands,
+    },
+    #[clap(
+        name = "serve",
+        about = "Serve the database over HTTP: a web page, a REST API under /api/tasks, sync and Prometheus metrics at /metrics"
+    )]
+    Serve {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        #[arg(long, default_value = "127.0.0.1", help = "Address to listen on")]
+        bind: std::net::IpAddr,
+        #[arg(long, help = "Token clients must present [env: TO_NOT_DO_SYNC_TOKEN]")]
+        token: Option<String>,
+        #[arg(long, help = "Replace the secret in the calendar feed URL")]
+        new_feed_token: bool,
+    },
+    #[clap(
+        name = "rpc",
+        about = "Answer JSON-RPC requests on stdin, one per line, for editors and other programs"
+    )]
+    Rpc,
+    #[clap(
+        name = "mcp",
+        about = "Run a Model Context Protocol server on stdin, for AI assistants"
+    )]
+    Mcp,
+    #[clap(
+        name = "events",
+        about = "Print the tasks as NDJSON events, or with --follow each change as it happens"
+    )]
+    Events {
+        #[arg(
+            long,
+            short = 'f',
+            help = "Keep running and print created, updated, completed and deleted events"
+        )]
+        follow: bool,
+    },
+    #[clap(
+        name = "publish",
+        about = "Write the tasks as a read-only static HTML page"
+    )]
+    Publish {
+        #[arg(long, short = 'o', help = "Directory to write index.html to")]
+        output: PathBuf,
+        #[arg(long, help = "Leave out tasks tagged private")]
+        exclude_private: bool,
+        #[arg(long, help = "Page title, the current context by default")]
+        title: Option<String>,
+    },
+    #[clap(
+        name = "merge",
+        about = "Import the tasks of another database file, matching them by ID"
+    )]
+    Merge {
+        other_db: PathBuf,
+        #[arg(
+            long,
+            value_enum,
+            help = "Resolve tasks that differ instead of only reporting them"
+        )]
+        prefer: Option<MergePreference>,
+    },
+    #[clap(
+        name = "snapshot",
+        about = "Keep named copies of the database to roll back to"
+    )]
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommands,
+    },
+    #[clap(
+        name = "restore",
+        about = "Restore the database, and the configuration backed up with it, from a backup file"
+    )]
+    Restore {
+        backup_file: PathBuf,
+        #[arg(
+            long,
+            help = "Add the backup's tasks to the current database instead of replacing it"
+        )]
+        merge: bool,
+        #[arg(long, short = 'y', help = "Do not ask for confirmation")]
+        yes: bool,
+    },
 }
 
-#[derive(Debug, ValueEnum, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum TaskState {
-    Todo,
-    InProgress,
-    Done,
+#[derive(Debug, Subcommand, Clone)]
+pub enum ProfileCommands {
+    #[clap(name = "create", about = "Create a profile with its own database")]
+    Create { name: String },
+    #[clap(name = "list", about = "List profiles, marking the active one")]
+    List,
+    #[clap(name = "switch", about = "Make a profile the default")]
+    Switch { name: String },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum SnapshotCommands {
+    #[clap(
+        name = "create",
+        about = "Save a copy of the database, named after the current time by default"
+    )]
+    Create { name: Option<String> },
+    #[clap(name = "list", about = "List snapshots, oldest first")]
+    List,
+    #[clap(name = "restore", about = "Replace the database with a snapshot")]
+    Restore {
+        name: String,
+        #[arg(long, short = 'y', help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum DbCommands {
+    #[clap(
+        name = "stats",
+        about = "Show the database size, task counts, journal, last backup and storage"
+    )]
+    Stats,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum WebhookCommands {
+    #[clap(
+        name = "test",
+        about = "Send a sample event to every configured webhook, or to --url"
+    )]
+    Test {
+        #[arg(long, value_enum, default_value_t = WebhookEvent::Created)]
+        event: WebhookEvent,
+        #[arg(long, help = "Send to this URL instead of the configured webhooks")]
+        url: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum CaldavCommands {
+    #[clap(
+        name = "sync",
+        about = "Exchange changes with the task list, both ways [env: TO_NOT_DO_CALDAV_PASSWORD]"
+    )]
+    Sync,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum GitHookCommands {
+    #[clap(
+        name = "install",
+        about = "Install a post-commit hook in a git repository for this profile"
+    )]
+    Install {
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        repo: PathBuf,
+        #[arg(long, help = "Replace a post-commit hook not installed by to-not-do")]
+        force: bool,
+    },
+    /// Run by the hook after each commit.
+    #[clap(name = "post-commit", hide = true)]
+    PostCommit,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum BotCommands {
+    #[clap(
+        name = "telegram",
+        about = "Answer the chats from the [telegram] config section: add tasks from messages, list today's with /today [env: TO_NOT_DO_TELEGRAM_TOKEN]"
+    )]
+    Telegram,
+    #[clap(
+        name = "matrix",
+        about = "Answer !todo commands in the room from the [matrix] config section and post the daily digest there [env: TO_NOT_DO_MATRIX_TOKEN]"
+    )]
+    Matrix,
+}
+
+#[cfg(feature = "plugins")]
+#[derive(Debug, Subcommand, Clone)]
+pub enum PluginCommands {
+    #[clap(
+        name = "list",
+        about = "List the plugins with the commands, filters and events they provide"
+    )]
+    List,
+}
+
+#[cfg(feature = "scripting")]
+#[derive(Debug, Subcommand, Clone)]
+pub enum ScriptCommands {
+    #[clap(name = "run", about = "Run a Lua script file")]
+    Run {
+        file: PathBuf,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    #[clap(
+        name = "list",
+        about = "List the scripts from the [scripts] config sections"
+    )]
+    List,
+    /// A script from the `[scripts]` config sections, by name.
+    #[command(external_subcommand)]
+    Named(Vec<String>),
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum GenerateCommands {
+    #[clap(
+        name = "systemd",
+        about = "Print systemd user units running notify-daemon and tick for this profile"
+    )]
+    Systemd {
+        #[arg(
+            long,
+            value_name = "CALENDAR",
+            default_value = "hourly",
+            help = "When tick runs, as a systemd calendar event"
+        )]
+        tick: String,
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Write the units here instead, e.g. ~/.config/systemd/user"
+        )]
+        output_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum ProjectCommands {
+    #[clap(name = "list", about = "List projects with their completion progress")]
+    List,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum ContextCommands {
+    #[clap(
+        name = "use",
+        about = "Make a project the active context for add, list and status"
+    )]
+    Use { project: String },
+    #[clap(name = "clear", about = "Clear the active context")]
+    Clear,
+}
+
+/// Layouts `export` and `import` understand.
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Everything, versioned, guaranteed to import back unchanged.
+    Dump,
+    /// The JSON of `task export` and `task import`. Importing it adds to and
+    /// updates the tasks here instead of replacing them.
+    Taskwarrior,
+    /// An Emacs org-mode outline with subtasks under their parent. Importing
+    /// it adds to and updates the tasks here too.
+    Org,
+    /// An Atom feed of the tasks completed last, for feed readers. It can
+    /// only be exported.
+    Atom,
+}
+
+/// Services `import --from` adds tasks from.
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ImportSource {
+    /// Todoist, with the token from the `[todoist]` config section.
+    Todoist,
+    /// The issues of a JQL query, from the site in the `[jira]` config
+    /// section.
+    Jira,
+    /// Every Microsoft To Do list, through the Graph API, with the token
+    /// from the `[microsoft_todo]` config section.
+    MicrosoftTodo,
+}
+
+/// Runs the commands that work on files rather than the open database. They
+/// are handled before the database is opened so unlocking does not ask for
+/// the passphrase twice.
+pub fn handle_file_commands(args: &Args, config: &Config, paths: &AppPaths) -> Option<ExitCode> {
+    if paths.db_file.as_os_str() == storage::MEMORY_DB && needs_database_file(&args.command) {
+        eprintln!("An in-memory database has no file for this command");
+        return Some(ExitCode::FAILURE);
+    }
+    if remote::is_remote(&paths.db_file) && needs_database_file(&args.command) {
+        eprintln!("A remote database has no local file for this command");
+        return Some(ExitCode::FAILURE);
+    }
+
+    if args.read_only && writes_database_file(&args.command) {
+        eprintln!(
+            "{}",
+            DatabaseError::ReadOnly(remote::display_db(&paths.db_file))
+        );
+        return Some(ExitCode::FAILURE);
+    }
+
+    match &args.command {
+        Commands::Init { local } => Some(handle_init(*local, config, paths)),
+        Commands::Profile { command } => Some(handle_profile(command, paths)),
+        Commands::Repair => Some(handle_repair(config, paths)),
+        Commands::Verify { fix } => Some(handle_verify(*fix, config, paths)),
+        Commands::Compact => Some(handle_compact(config, paths)),
+        Commands::Db { command } => Some(handle_db(command, config, paths)),
+        Commands::Sync {
+            remote,
+            token,
+            service: None,
+        } => Some(handle_sync(remote.as_deref(), token.clone(), config, paths)),
+        Commands::Sync {
+            service: Some(SyncService::Status),
+            ..
+        } => Some(handle_sync_status(config, paths)),
+        Commands::Sync { .. } => None,
+        Commands::External(args) => handle_external(args, paths),
+        Commands::Status { format, tmux } => format
+            .or(tmux.then_some(Bar::Tmux))
+            .and_then(|bar| print_cached_status(bar, paths)),
+        Commands::Serve {
+            port,
+            bind,
+            token,
+            new_feed_token,
+        } => Some(handle_serve(
+            *bind,
+            *port,
+            token.clone(),
+            *new_feed_token,
+            config,
+            paths,
+        )),
+        Commands::Unlock => Some(handle_unlock(&paths.db_file)),
+        Commands::Lock => Some(handle_lock(&paths.db_file)),
+        Commands::Convert {
+            input,
+            output,
+            format,
+        } => Some(handle_convert(input, output, *format)),
+        Commands::Webhook { command } => Some(handle_webhook(command, config)),
+        Commands::Generate { command } => Some(handle_generate(
+            command,
+            args.profile.as_deref(),
+            args.db.is_some(),
+            paths,
+        )),
+        Commands::GitHook {
+            command: GitHookCommands::Install { repo, force },
+        } => Some(handle_git_hook_install(
+            repo,
+            *force,
+            args.profile.as_deref(),
+            args.db.is_some(),
+            paths,
+        )),
+        #[cfg(feature = "plugins")]
+        Commands::Plugin { command } => Some(handle_plugin(command, paths)),
+        _ => None,
+    }
+}
+
+/// Whether `command` rewrites the database file without going through the
+/// storage, and so has to be refused up front in read-only mode.
+fn writes_database_file(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Repair
+            | Commands::Verify { fix: true }
+            | Commands::Compact
+            | Commands::Sync { service: None, .. }
+            | Commands::Serve { .. }
+            | Commands::Unlock
+            | Commands::Lock
+    )
+}
+
+/// Whether `command` works on the database file itself rather than through
+/// the storage.
+fn needs_database_file(command: &Commands) -> bool {
+    writes_database_file(command)
+        || matches!(command, Commands::Verify { .. } | Commands::Db { .. })
 }
 
-pub fn handle_commands(args: Args, db_manager: &mut file_management::DatabaseManager) {
+/// Runs a command, returning a failure exit code when a search finds nothing.
+pub fn handle_commands(
+    args: Args,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
     match args.command {
-        Commands::Add { task_description } => {
-            handle_add_task(task_description, db_manager);
+        Commands::Add {
+            task_description,
+            from_clipboard,
+            due,
+            priority,
+            tags,
+            parent,
+            project,
+        } => {
+            let content = if from_clipboard {
+                match arboard::Clipboard::new().and_then(|mut c| c.get_text()) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        println!("Failed to read clipboard: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            } else {
+                task_description.unwrap_or_default()
+            };
+
+            let project = project.or_else(|| db_manager.context().map(str::to_string));
+
+            handle_add_task(
+                &content,
+                due,
+                priority,
+                &tags,
+                parent,
+                project.as_deref(),
+                db_manager,
+            );
         }
         Commands::Update {
             task_id,
@@ -51,26 +1053,341 @@ pub fn handle_commands(args: Args, db_manager: &mut file_management::DatabaseMan
         Commands::Delete { task_id } => {
             handle_delete_task(task_id, db_manager);
         }
-        Commands::List { filter } => {
-            handle_list_tasks(db_manager, filter);
+        Commands::List {
+            filter,
+            columns,
+            tree,
+            all,
+            by_project,
+            archived,
+            oneline,
+            by,
+            #[cfg(feature = "plugins")]
+            plugin_filter,
+        } => {
+            if tree {
+                handle_list_tree(db_manager, all);
+                return ExitCode::SUCCESS;
+            }
+
+            let columns = columns.or_else(|| config.list.columns.clone());
+            let format = match columns.as_deref() {
+                _ if oneline => ListFormat::OneLine,
+                Some(columns) => ListFormat::Table(columns),
+                None => ListFormat::Detailed,
+            };
+
+            let keep: TaskPredicate = Box::new(|_| Ok(true));
+            #[cfg(feature = "plugins")]
+            let keep: TaskPredicate = match plugin_filter {
+                Some(name) => {
+                    let mut host = load_plugins(paths);
+                    Box::new(move |task| host.filter(&name, task))
+                }
+                None => keep,
+            };
+            let keep: TaskPredicate = match by {
+                Some(user) => {
+                    let mut keep = keep;
+                    Box::new(move |task| Ok(task.touched_by(&user) && keep(task)?))
+                }
+                None => keep,
+            };
+
+            handle_list_tasks(db_manager, filter, keep, format, by_project, archived);
+        }
+        Commands::Project { command } => match command {
+            ProjectCommands::List => handle_project_list(db_manager),
+        },
+        Commands::Context { command } => {
+            handle_context(command, db_manager);
+        }
+        Commands::Show { task_id } => {
+            handle_show(task_id, db_manager);
+        }
+        Commands::LinkUri { task_id } => {
+            handle_link_uri(task_id, db_manager);
+        }
+        Commands::HandleUri { uri } => match uri::parse_task_uri(&uri) {
+            Ok(task_id) => handle_show(task_id, db_manager),
+            Err(e) => {
+                println!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        Commands::Search { query, count } => {
+            return handle_search(&query, count, db_manager);
         }
         Commands::MarkDone { task_id } => {
             handle_mark_done(task_id, db_manager);
         }
+        Commands::Done { text } => {
+            handle_done(&text, db_manager);
+        }
         Commands::MarkInProgress { task_id } => {
             handle_mark_in_progress(task_id, db_manager);
         }
+        Commands::Postpone {
+            task_id,
+            duration,
+            all_overdue,
+        } => {
+            handle_postpone(task_id, duration, all_overdue, db_manager);
+        }
+        Commands::Prune {
+            older_than,
+            state,
+            delete,
+            yes,
+        } => {
+            handle_prune(older_than, state, delete, yes, db_manager);
+        }
+        Commands::Focus { task_id, clear } => {
+            handle_focus(task_id, clear, db_manager);
+        }
+        Commands::Status { format, tmux } => {
+            handle_status(format.or(tmux.then_some(Bar::Tmux)), paths, db_manager);
+        }
+        Commands::Batch => return handle_batch(config, paths, db_manager),
+        Commands::Triage => {
+            handle_triage(db_manager);
+        }
+        Commands::Select => {
+            handle_select(db_manager);
+        }
+        Commands::Backup { output } => {
+            let output = output.unwrap_or_else(|| paths.backup_dir());
+            handle_backup(paths, &output, db_manager);
+        }
+        Commands::Export {
+            format,
+            output,
+            archive,
+            encrypt,
+        } => {
+            let output = match &archive {
+                Some(archive) => ExportOutput::Archive {
+                    path: archive,
+                    encrypt,
+                },
+                None => ExportOutput::Dump(output.as_deref()),
+            };
+            return handle_export(format, output, &paths.config_file, db_manager);
+        }
+        Commands::Import {
+            file,
+            archive,
+            from,
+            jql,
+            format,
+            yes,
+        } => {
+            let input = match (&archive, &file, from) {
+                (_, _, Some(ImportSource::Todoist)) => {
+                    return handle_todoist(None, config, paths, db_manager)
+                }
+                (_, _, Some(ImportSource::Jira)) => {
+                    return handle_jira_import(jql.as_deref(), config, db_manager)
+                }
+                (_, _, Some(ImportSource::MicrosoftTodo)) => {
+                    return handle_microsoft_todo_import(config, db_manager)
+                }
+                (Some(archive), _, None) => ImportInput::Archive(archive),
+                (None, Some(file), None) if format != ExportFormat::Dump => {
+                    return handle_foreign_import(file, format, db_manager)
+                }
+                (None, Some(file), None) => ImportInput::Dump(file),
+                (None, None, None) => unreachable!("clap requires a file, an archive or a source"),
+            };
+            return handle_import(input, yes, &paths.config_file, db_manager);
+        }
+        Commands::Restore {
+            backup_file,
+            merge,
+            yes,
+        } => {
+            handle_restore(&backup_file, merge, yes, paths, db_manager);
+        }
+        Commands::Merge { other_db, prefer } => {
+            handle_merge(&other_db, prefer, config, db_manager);
+        }
+        Commands::Snapshot { command } => {
+            return handle_snapshot(command, paths, db_manager);
+        }
+        Commands::Unlock => return handle_unlock(&paths.db_file),
+        Commands::Lock => return handle_lock(&paths.db_file),
+        Commands::Repair => return handle_repair(config, paths),
+        Commands::Verify { fix } => return handle_verify(fix, config, paths),
+        Commands::Compact => return handle_compact(config, paths),
+        Commands::Purge { expired: _, yes } => {
+            return handle_purge(yes, &config.compact, db_manager);
+        }
+        Commands::Tick => return handle_tick(config, paths, db_manager),
+        Commands::Digest {
+            period,
+            email,
+            stdout,
+        } => return handle_digest(period, email, stdout, config, db_manager),
+        Commands::Generate { command } => {
+            return handle_generate(&command, args.profile.as_deref(), args.db.is_some(), paths)
+        }
+        Commands::GitHook {
+            command: GitHookCommands::PostCommit,
+        } => return handle_post_commit(db_manager),
+        Commands::GitHook {
+            command: GitHookCommands::Install { repo, force },
+        } => {
+            return handle_git_hook_install(
+                &repo,
+                force,
+                args.profile.as_deref(),
+                args.db.is_some(),
+                paths,
+            )
+        }
+        Commands::Daemon {
+            every,
+            socket,
+            no_socket,
+        } => return handle_daemon(every, socket, no_socket, config, paths, db_manager),
+        Commands::Bot { command } => return handle_bot(command, config, paths, db_manager),
+        Commands::Rpc => return handle_rpc(db_manager),
+        Commands::Mcp => return handle_mcp(db_manager),
+        Commands::Events { follow } => return handle_events(follow, db_manager),
+        Commands::Publish {
+            output,
+            exclude_private,
+            title,
+        } => return handle_publish(&output, exclude_private, title, db_manager),
+        Commands::Db { command } => return handle_db(&command, config, paths),
+        Commands::Sync {
+            remote,
+            token,
+            service: None,
+        } => return handle_sync(remote.as_deref(), token, config, paths),
+        Commands::Sync {
+            service: Some(SyncService::Todoist { prefer }),
+            ..
+        } => return handle_todoist(Some(prefer), config, paths, db_manager),
+        Commands::Sync {
+            service:
+                Some(SyncService::Github {
+                    repo,
+                    close,
+                    save_token,
+                }),
+            ..
+        } => return handle_github(&repo, close, save_token, config, db_manager),
+        Commands::Sync {
+            service: Some(SyncService::Markdown { vault, prefer }),
+            ..
+        } => return handle_markdown(vault.as_deref(), prefer, config, paths, db_manager),
+        Commands::Sync {
+            service: Some(SyncService::Gcal { prefer, login }),
+            ..
+        } => return handle_gcal(prefer, login, config, paths, db_manager),
+        Commands::Sync {
+            service: Some(SyncService::Status),
+            ..
+        } => return handle_sync_status(config, paths),
+        Commands::Sync {
+            service: Some(SyncService::Resolve { prefer }),
+            ..
+        } => return handle_sync_resolve(prefer, paths, db_manager),
+        Commands::Caldav { command } => return handle_caldav(command, config, paths, db_manager),
+        Commands::Serve {
+            port,
+            bind,
+            token,
+            new_feed_token,
+        } => return handle_serve(bind, port, token, new_feed_token, config, paths),
+        Commands::Profile { command } => return handle_profile(&command, paths),
+        Commands::Init { local } => return handle_init(local, config, paths),
+        Commands::Convert {
+            input,
+            output,
+            format,
+        } => return handle_convert(&input, &output, format),
+        Commands::Webhook { command } => return handle_webhook(&command, config),
+        #[cfg(feature = "plugins")]
+        Commands::Plugin { command } => return handle_plugin(&command, paths),
+        #[cfg(feature = "scripting")]
+        Commands::Script { command } => return handle_script(command, config, paths, db_manager),
+        #[cfg(feature = "notifications")]
+        Commands::NotifyDaemon => return handle_notify_daemon(config, paths, db_manager),
+        #[cfg(feature = "dbus")]
+        Commands::Dbus => return handle_dbus(db_manager),
+        #[cfg(feature = "plugins")]
+        Commands::External(args) => return handle_plugin_command(&args, paths, db_manager),
+        #[cfg(not(feature = "plugins"))]
+        Commands::External(args) => return unknown_command(&args[0]),
     }
+
+    ExitCode::SUCCESS
 }
 
-fn handle_add_task(task_description: String, db_manager: &mut file_management::DatabaseManager) {
-    println!("Adding task: {}", task_description);
+fn handle_add_task(
+    content: &str,
+    due: Option<NaiveDate>,
+    priority: Option<Priority>,
+    tags: &[String],
+    parent: Option<Uuid>,
+    project: Option<&str>,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    if let Some(parent) = parent {
+        if !db_manager.contains_task(parent) {
+            println!("Parent task not found");
+            return;
+        }
+    }
+
+    let Some((description, notes)) = split_description_and_notes(content) else {
+        println!("Task description cannot be empty");
+        return;
+    };
+
+    println!("Adding task: {}", description);
 
-    let task = Task::new(&task_description);
+    let mut task = Task::new(&description);
+    if let Some(notes) = notes {
+        task = task.with_notes(&notes);
+    }
+    if let Some(due) = due {
+        task = task.with_due(due);
+    }
+    if let Some(priority) = priority {
+        task = task.with_priority(priority);
+    }
+    if !tags.is_empty() {
+        task = task.with_tags(tags);
+    }
+    if let Some(parent) = parent {
+        task = task.with_parent(parent);
+    }
+    if let Some(project) = project {
+        task = task.with_project(project);
+    }
 
     match db_manager.add_task(&task) {
         Ok(_) => println!("Task added successfully"),
-        Err(_) => println!("Failed to add task"),
+        Err(e) => println!("Failed to add task: {}", e),
+    }
+}
+
+/// Splits multi-line text into a description (the first non-blank line) and
+/// optional notes (everything after it).
+fn split_description_and_notes(content: &str) -> Option<(String, Option<String>)> {
+    let mut lines = content.lines().skip_while(|line| line.trim().is_empty());
+    let description = lines.next()?.trim().to_string();
+
+    let notes = lines.collect::<Vec<_>>().join("\n");
+    let notes = notes.trim();
+
+    if notes.is_empty() {
+        Some((description, None))
+    } else {
+        Some((description, Some(notes.to_string())))
     }
 }
 
@@ -83,129 +1400,4444 @@ fn handle_update_task(
 
     match db_manager.update_description(task_id, &task_description) {
         Ok(_) => println!("Task updated successfully"),
-        Err(_) => println!("Task not found"),
+        Err(e) => println!("{}", e),
     };
 }
 
 fn handle_delete_task(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
     match db_manager.delete_task(task_id) {
         Ok(_) => println!("Task deleted successfully"),
-        Err(_) => println!("Task not found"),
+        Err(e) => println!("{}", e),
     };
 }
 
-fn handle_list_tasks(db_manager: &mut file_management::DatabaseManager, filter: Option<TaskState>) {
-    if let Some(filter) = filter {
+/// Decides whether a listed task is shown.
+type TaskPredicate = Box<dyn FnMut(&Task) -> Result<bool, ToNotDoError>>;
+
+enum ListFormat<'a> {
+    Detailed,
+    Table(&'a [Column]),
+    OneLine,
+}
+
+fn handle_list_tasks(
+    db_manager: &mut file_management::DatabaseManager,
+    filter: Option<TaskState>,
+    mut keep: TaskPredicate,
+    format: ListFormat,
+    by_project: bool,
+    archived: bool,
+) {
+    let quiet = matches!(format, ListFormat::OneLine);
+    let context = db_manager.context().map(str::to_string);
+
+    if let (Some(context), false) = (&context, quiet) {
+        println!("Context: {}", context);
+    }
+
+    if let (Some(filter), false, false) = (filter, archived, quiet) {
         println!("Listing tasks with filter: {:?}", filter);
-        let filtered_tasks = db_manager.filter_tasks(filter);
+    }
 
-        if filtered_tasks.is_empty() {
-            println!("No tasks found with the specified filter");
-        } else {
-            for task in filtered_tasks {
-                println!("------------------");
-                println!("{}", task);
-            }
-            println!("------------------");
+    let task_filter = TaskFilter {
+        state: filter,
+        project: context,
+        archived,
+    };
+    let tasks = match db_manager.get_tasks() {
+        Ok(tasks) => tasks,
+        Err(_) => {
+            println!("Failed to retrieve tasks");
+            return;
         }
-    } else {
-        let tasks = match db_manager.get_tasks() {
+    };
+
+    let mut failure = None;
+    let matching = tasks
+        .iter()
+        .filter(|task| task_filter.matches(task))
+        .filter(|task| {
+            failure.is_none()
+                && keep(task).unwrap_or_else(|e| {
+                    failure = Some(e);
+                    false
+                })
+        });
+
+    // Matching tasks are written out one by one rather than collected
+    // first, and a closed pipe ends the listing quietly. The database was
+    // already loaded whole when it was opened, so this saves the copy and
+    // the formatting of what `list | head` never shows, not the load.
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let written = if by_project {
+        let matching: Vec<Task> = matching.cloned().collect();
+        let all_tasks = match db_manager.get_active_tasks() {
             Ok(tasks) => tasks,
             Err(_) => {
                 println!("Failed to retrieve tasks");
                 return;
             }
         };
+        write_by_project(&mut out, &matching, &all_tasks, &format)
+    } else {
+        write_tasks(&mut out, matching, &format)
+    };
+    let written = written.and_then(|count| out.flush().map(|()| count));
+    drop(out);
 
-        if tasks.is_empty() {
-            println!("No tasks found");
+    let count = match written {
+        Ok(count) => count,
+        // The reader went away.
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return,
+        Err(e) => {
+            eprintln!("Failed to print tasks: {}", e);
+            return;
+        }
+    };
+    if let Some(e) = failure {
+        println!("{}", e);
+        return;
+    }
+
+    if count == 0 && !quiet {
+        if filter.is_some() {
+            println!("No tasks found with the specified filter");
         } else {
+            println!("No tasks found");
+        }
+    }
+}
+
+/// Writes `tasks` under the progress of their projects, returning how many
+/// were written.
+fn write_by_project(
+    out: &mut impl Write,
+    tasks: &[Task],
+    all_tasks: &[Task],
+    format: &ListFormat,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    for progress in reporting::project_progress(all_tasks, true) {
+        let group = tasks
+            .iter()
+            .filter(|t| t.project().unwrap_or(NO_PROJECT) == progress.project);
+        if group.clone().next().is_none() {
+            continue;
+        }
+
+        writeln!(out, "== {} ==", progress.project)?;
+        count += write_tasks(out, group, format)?;
+        writeln!(
+            out,
+            "{} {} {}% ({}/{} done)",
+            progress.project,
+            progress.bar(PROGRESS_BAR_WIDTH),
+            progress.percent(),
+            progress.done,
+            progress.total
+        )?;
+        writeln!(out)?;
+    }
+
+    Ok(count)
+}
+
+/// Whether a task belongs to the active context, if there is one.
+fn in_context(task: &Task, context: Option<&str>) -> bool {
+    context.is_none_or(|context| task.project() == Some(context))
+}
+
+/// Writes `tasks` as they come, except for a table, which needs them all to
+/// size its columns. Returns how many were written.
+fn write_tasks<'a>(
+    out: &mut impl Write,
+    tasks: impl IntoIterator<Item = &'a Task>,
+    format: &ListFormat,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    match format {
+        ListFormat::Detailed => {
+            for task in tasks {
+                writeln!(out, "------------------")?;
+                writeln!(out, "{}", task)?;
+                count += 1;
+            }
+            if count > 0 {
+                writeln!(out, "------------------")?;
+            }
+        }
+        ListFormat::Table(columns) => {
+            let tasks: Vec<&Task> = tasks.into_iter().collect();
+            count = tasks.len();
+            if count > 0 {
+                for line in render_table(&tasks, columns) {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+        }
+        ListFormat::OneLine => {
             for task in tasks {
-                println!("------------------");
-                println!("{}", task);
+                writeln!(out, "{}", task.oneline())?;
+                count += 1;
             }
-            println!("------------------");
         }
     }
+
+    Ok(count)
 }
 
-fn handle_mark_done(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
-    match db_manager.set_task_state(task_id, TaskState::Done) {
-        Ok(_) => println!("Task marked as done"),
-        Err(_) => println!("Task not found"),
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+fn handle_project_list(db_manager: &mut file_management::DatabaseManager) {
+    let tasks = match db_manager.get_active_tasks() {
+        Ok(tasks) => tasks,
+        Err(_) => {
+            println!("Failed to retrieve tasks");
+            return;
+        }
     };
+
+    let projects = reporting::project_progress(&tasks, false);
+
+    if projects.is_empty() {
+        println!("No projects found");
+        return;
+    }
+
+    let width = projects
+        .iter()
+        .map(|p| p.project.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    for progress in projects {
+        println!(
+            "{:<width$}  {} {:>3}% ({}/{} done)",
+            progress.project,
+            progress.bar(PROGRESS_BAR_WIDTH),
+            progress.percent(),
+            progress.done,
+            progress.total,
+            width = width
+        );
+    }
 }
 
-fn handle_mark_in_progress(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
-    match db_manager.set_task_state(task_id, TaskState::InProgress) {
-        Ok(_) => println!("Task marked as in progress"),
-        Err(_) => println!("Task not found"),
+/// Lays tasks out as a table with one header row, truncating cells that are
+/// wider than their column allows.
+fn render_table(tasks: &[&Task], columns: &[Column]) -> Vec<String> {
+    let rows: Vec<Vec<String>> = tasks
+        .iter()
+        .map(|task| {
+            columns
+                .iter()
+                .map(|&column| truncate(&task.column_value(column), column.max_width()))
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(column.header().len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let format_row = |cells: Vec<String>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
     };
+
+    let header = columns.iter().map(|c| c.header().to_string()).collect();
+
+    std::iter::once(format_row(header))
+        .chain(rows.into_iter().map(format_row))
+        .collect()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use clap::Parser;
-    use uuid::Uuid;
+fn truncate(value: &str, max_width: usize) -> String {
+    if value.chars().count() <= max_width {
+        return value.to_string();
+    }
 
-    #[test]
-    fn test_add_command() {
-        let args = Args::parse_from(["to-not-do", "add", "Test task"]);
-        if let Commands::Add { task_description } = args.command {
-            assert_eq!(task_description, "Test task");
-        } else {
-            panic!("Expected Add command");
-        }
+    let mut truncated: String = value.chars().take(max_width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn handle_list_tree(db_manager: &mut file_management::DatabaseManager, show_all: bool) {
+    let context = db_manager.context().map(str::to_string);
+    if let Some(context) = &context {
+        println!("Context: {}", context);
     }
 
-    #[test]
-    fn test_update_command() {
-        let task_id = Uuid::new_v4();
-        let args = Args::parse_from(["to-not-do", "update", &task_id.to_string(), "Updated task"]);
-        if let Commands::Update {
-            task_id: id,
-            task_description,
-        } = args.command
-        {
-            assert_eq!(id, task_id);
-            assert_eq!(task_description, "Updated task");
-        } else {
-            panic!("Expected Update command");
+    let tasks: Vec<Task> = match db_manager.get_active_tasks() {
+        Ok(tasks) => tasks
+            .into_iter()
+            .filter(|t| in_context(t, context.as_deref()))
+            .collect(),
+        Err(_) => {
+            println!("Failed to retrieve tasks");
+            return;
         }
+    };
+
+    if tasks.is_empty() {
+        println!("No tasks found");
+        return;
     }
 
-    #[test]
-    fn test_delete_command() {
-        let task_id = Uuid::new_v4();
-        let args = Args::parse_from(["to-not-do", "delete", &task_id.to_string()]);
-        if let Commands::Delete { task_id: id } = args.command {
-            assert_eq!(id, task_id);
+    for line in render_tree(&tasks, show_all) {
+        println!("{}", line);
+    }
+}
+
+/// Renders tasks as a tree of parents and subtasks. Subtrees that are entirely
+/// done are collapsed to their root unless `show_all` is set.
+fn render_tree(tasks: &[Task], show_all: bool) -> Vec<String> {
+    let is_root = |task: &Task| {
+        task.parent()
+            .is_none_or(|parent| !tasks.iter().any(|t| t.id() == parent))
+    };
+
+    // Tasks whose parents loop back to them are under no root; each loop is
+    // shown from the first of its tasks once the roots are done.
+    let mut lines = Vec::new();
+    let mut visited = HashSet::new();
+    for root in tasks.iter().filter(|t| is_root(t)).chain(tasks) {
+        render_subtree(tasks, root, "", None, show_all, &mut lines, &mut visited);
+    }
+
+    lines
+}
+
+fn render_subtree(
+    tasks: &[Task],
+    task: &Task,
+    prefix: &str,
+    is_last: Option<bool>,
+    show_all: bool,
+    lines: &mut Vec<String>,
+    visited: &mut HashSet<Uuid>,
+) {
+    if !visited.insert(task.id()) {
+        return;
+    }
+
+    let children: Vec<&Task> = tasks
+        .iter()
+        .filter(|t| t.parent() == Some(task.id()) && !visited.contains(&t.id()))
+        .collect();
+
+    let mut subtree = HashSet::new();
+    let collapsed = !show_all && !children.is_empty() && is_subtree_done(tasks, task, &mut subtree);
+
+    let (branch, child_prefix) = match is_last {
+        None => ("", prefix.to_string()),
+        Some(false) => ("├── ", format!("{}│   ", prefix)),
+        Some(true) => ("└── ", format!("{}    ", prefix)),
+    };
+
+    let checkbox = match task.state() {
+        TaskState::Todo => "[ ]",
+        TaskState::InProgress => "[~]",
+        TaskState::Done => "[x]",
+    };
+
+    let mut line = format!(
+        "{}{}{} {} ({})",
+        prefix,
+        branch,
+        checkbox,
+        task.description(),
+        task.short_id()
+    );
+    if collapsed {
+        line.push_str(&format!(" [+{} done]", children.len()));
+    }
+    lines.push(line);
+
+    if collapsed {
+        visited.extend(subtree);
+        return;
+    }
+
+    for (index, child) in children.iter().enumerate() {
+        let is_last = index + 1 == children.len();
+        render_subtree(
+            tasks,
+            child,
+            &child_prefix,
+            Some(is_last),
+            show_all,
+            lines,
+            visited,
+        );
+    }
+}
+
+/// Whether `task` and everything under it is done, adding the tasks it
+/// looked at to `seen`.
+fn is_subtree_done(tasks: &[Task], task: &Task, seen: &mut HashSet<Uuid>) -> bool {
+    if !seen.insert(task.id()) {
+        return true;
+    }
+
+    task.state() == TaskState::Done
+        && tasks
+            .iter()
+            .filter(|t| t.parent() == Some(task.id()))
+            .all(|child| is_subtree_done(tasks, child, seen))
+}
+
+fn handle_mark_done(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
+    match db_manager.set_task_state(task_id, TaskState::Done) {
+        Ok(_) => {
+            println!("Task marked as done");
+            print_completion_summary(db_manager);
+        }
+        Err(e) => println!("{}", e),
+    };
+}
+
+fn print_completion_summary(db_manager: &mut file_management::DatabaseManager) {
+    let Ok(tasks) = db_manager.get_tasks() else {
+        return;
+    };
+
+    let summary = reporting::completion_summary(tasks, chrono::Utc::now().date_naive());
+
+    println!(
+        "Nice work! {} done today, {} day streak, {} remaining",
+        summary.completed_today, summary.streak, summary.remaining
+    );
+}
+
+fn handle_done(text: &str, db_manager: &mut file_management::DatabaseManager) {
+    let matches = db_manager.find_open_tasks(text);
+
+    let task = match matches.as_slice() {
+        [] => {
+            println!("No open task matches \"{}\"", text);
+            return;
+        }
+        [task] => task,
+        _ => {
+            println!("Multiple open tasks match \"{}\":", text);
+            for (index, task) in matches.iter().enumerate() {
+                println!("{}) {}", index + 1, task.description());
+            }
+            println!("Which one is done? (empty to cancel)");
+
+            let choice = read_line().and_then(|line| line.trim().parse::<usize>().ok());
+            match choice
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| matches.get(i))
+            {
+                Some(task) => task,
+                None => {
+                    println!("Cancelled");
+                    return;
+                }
+            }
+        }
+    };
+
+    match db_manager.set_task_state(task.id(), TaskState::Done) {
+        Ok(_) => {
+            println!("Task marked as done: {}", task.description());
+            print_completion_summary(db_manager);
+        }
+        Err(e) => println!("{}", e),
+    };
+}
+
+fn handle_mark_in_progress(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
+    match db_manager.set_task_state(task_id, TaskState::InProgress) {
+        Ok(_) => println!("Task marked as in progress"),
+        Err(e) => println!("{}", e),
+    };
+}
+
+fn handle_postpone(
+    task_id: Option<Uuid>,
+    duration: Option<Duration>,
+    all_overdue: Option<Duration>,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    if let Some(duration) = all_overdue {
+        match db_manager.postpone_overdue(duration) {
+            Ok(0) => println!("No overdue tasks"),
+            Ok(count) => println!("Postponed {} overdue tasks", count),
+            Err(e) => println!("Failed to postpone tasks: {}", e),
+        }
+        return;
+    }
+
+    if let (Some(task_id), Some(duration)) = (task_id, duration) {
+        match db_manager.postpone_task(task_id, duration) {
+            Ok(due) => println!("Task postponed to {}", due),
+            Err(e) => println!("{}", e),
+        };
+    }
+}
+
+fn handle_prune(
+    older_than: Duration,
+    state: Option<TaskState>,
+    delete: bool,
+    yes: bool,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    let Some(cutoff) = chrono::Utc::now()
+        .date_naive()
+        .checked_sub_signed(older_than)
+    else {
+        println!(
+            "Cannot prune tasks older than {} days; that is before the earliest date there is",
+            older_than.num_days()
+        );
+        return;
+    };
+    let stale = db_manager.stale_tasks(cutoff, state);
+
+    if stale.is_empty() {
+        println!("No tasks to prune");
+        return;
+    }
+
+    let action = if delete { "Delete" } else { "Archive" };
+
+    println!("Tasks not updated since {}:", cutoff);
+    for task in &stale {
+        println!("  {} {}", task.short_id(), task.description());
+    }
+
+    if !yes && !confirm(&format!("{} {} tasks?", action, stale.len())) {
+        println!("Cancelled");
+        return;
+    }
+
+    db_manager.begin();
+
+    let mut pruned = 0;
+    for task in &stale {
+        let result = if delete {
+            db_manager.delete_task(task.id())
+        } else {
+            db_manager.archive_task(task.id())
+        };
+
+        if result.is_ok() {
+            pruned += 1;
+        }
+    }
+
+    if let Err(e) = db_manager.commit() {
+        println!("Failed to save changes: {}", e);
+        return;
+    }
+
+    let verb = if delete { "Deleted" } else { "Archived" };
+    println!("{} {} tasks", verb, pruned);
+}
+
+fn handle_show(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
+    let Some(task) = db_manager.get_task(task_id) else {
+        println!("Task not found");
+        return;
+    };
+
+    println!("{}", task);
+
+    if !task.history().is_empty() {
+        println!("History:");
+        for entry in task.history() {
+            println!("  {} {}", entry.at.format("%Y-%m-%d %H:%M"), entry.event);
+        }
+    }
+}
+
+fn handle_link_uri(task_id: Uuid, db_manager: &mut file_management::DatabaseManager) {
+    if db_manager.contains_task(task_id) {
+        println!("{}", uri::task_uri(task_id));
+    } else {
+        println!("Task not found");
+    }
+}
+
+fn handle_backup(
+    paths: &AppPaths,
+    output: &Path,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    // Backups of an encrypted database are encrypted with its key.
+    let written = encryption::copy_cipher(&paths.db_file)
+        .and_then(|cipher| db_manager.backup(&paths.config_file, output, cipher.as_ref()));
+    match written {
+        Ok(path) => println!("Backup written to {}", path.display()),
+        Err(_) => println!("Failed to write backup"),
+    }
+}
+
+enum ExportOutput<'a> {
+    /// A dump written to a file, or to stdout.
+    Dump(Option<&'a Path>),
+    Archive {
+        path: &'a Path,
+        encrypt: bool,
+    },
+}
+
+enum ImportInput<'a> {
+    Dump(&'a Path),
+    Archive(&'a Path),
+}
+
+fn handle_export(
+    format: ExportFormat,
+    output: ExportOutput,
+    config_file: &Path,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let config = std::fs::read_to_string(config_file).ok();
+    let result = db_manager.database().and_then(|db| match output {
+        ExportOutput::Dump(_) => match format {
+            ExportFormat::Dump => Dump::new(db.clone(), config)
+                .to_json()
+                .map(String::into_bytes),
+            ExportFormat::Taskwarrior => Ok(foreign::taskwarrior_export(db.tasks()).into_bytes()),
+            ExportFormat::Org => Ok(org::export(db.tasks()).into_bytes()),
+            ExportFormat::Atom => Ok(feed::render_atom(db.tasks(), APP_NAME).into_bytes()),
+        },
+        ExportOutput::Archive { encrypt, .. } => {
+            let passphrase = if encrypt {
+                Some(encryption::prompt_secret("Archive passphrase", true)?)
+            } else {
+                None
+            };
+            archive::write_archive(&Dump::new(db.clone(), config), passphrase.as_deref())
+        }
+    });
+
+    let data = match result {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to export database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let output = match output {
+        ExportOutput::Dump(Some(path)) | ExportOutput::Archive { path, .. } => path,
+        ExportOutput::Dump(None) => {
+            println!("{}", String::from_utf8_lossy(&data));
+            return ExitCode::SUCCESS;
+        }
+    };
+
+    match storage::write_atomically(output, &data) {
+        Ok(()) => {
+            println!("Exported database to {}", output.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to write {}: {}", output.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_import(
+    input: ImportInput,
+    yes: bool,
+    config_file: &Path,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let (ImportInput::Dump(file) | ImportInput::Archive(file)) = input;
+    let result = std::fs::read(file)
+        .map_err(|e| e.to_string())
+        .and_then(|data| {
+            match input {
+                ImportInput::Dump(_) => Dump::from_json(&String::from_utf8_lossy(&data)),
+                ImportInput::Archive(_) => archive::read_archive(&data, || {
+                    encryption::prompt_secret("Archive passphrase", false)
+                }),
+            }
+            .map_err(|e| e.to_string())
+        });
+    let dump = match result {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("Failed to read {}: {}", file.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let count = dump.database.tasks().len();
+    let question = format!(
+        "Replace the current database and configuration with {} tasks from the dump taken {}?",
+        count,
+        dump.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    if !yes && !confirm(&question) {
+        println!("Aborted");
+        return ExitCode::FAILURE;
+    }
+
+    if let Some(config) = &dump.config {
+        let written = match config_file.parent() {
+            Some(dir) => std::fs::create_dir_all(dir)
+                .and_then(|()| storage::write_atomically(config_file, config.as_bytes())),
+            None => storage::write_atomically(config_file, config.as_bytes()),
+        };
+        if let Err(e) = written {
+            println!("Failed to write {}: {}", config_file.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Err(e) = db_manager.restore(dump.database) {
+        println!("Failed to import dump: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    println!("Imported {} tasks", count);
+    ExitCode::SUCCESS
+}
+
+/// Adds and updates tasks from a Taskwarrior export or an org outline.
+fn handle_foreign_import(
+    file: &Path,
+    format: ExportFormat,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let data = match std::fs::read(file) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to read {}: {}", file.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match format {
+        ExportFormat::Taskwarrior => foreign::import_taskwarrior(db_manager, &data).map(|import| {
+            format!(
+                "Added {} tasks, updated {} and deleted {}",
+                import.added, import.updated, import.deleted
+            )
+        }),
+        ExportFormat::Org => {
+            org::import(db_manager, &String::from_utf8_lossy(&data)).map(|import| {
+                format!(
+                    "Added {} tasks and updated {}",
+                    import.added, import.updated
+                )
+            })
+        }
+        ExportFormat::Atom => {
+            println!("Atom feeds can only be exported");
+            return ExitCode::FAILURE;
+        }
+        ExportFormat::Dump => unreachable!("dumps replace the database"),
+    };
+    match result {
+        Ok(summary) => {
+            println!("{}", summary);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to import {}: {}", file.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_merge(
+    other_db: &Path,
+    prefer: Option<MergePreference>,
+    config: &Config,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    // Read like this database, so its journal, log or partitions count too.
+    let mut other = storage::open_storage(other_db, &config.storage);
+    if !other.exists() {
+        println!("No database file at {}", other_db.display());
+        return;
+    }
+
+    let other = other.load();
+
+    match other.and_then(|other| db_manager.merge_database(other, prefer)) {
+        Ok(report) => print_merge_report(&report, prefer),
+        Err(e) => println!("Failed to merge database: {}", e),
+    }
+}
+
+fn print_merge_report(report: &file_management::MergeReport, prefer: Option<MergePreference>) {
+    for conflict in &report.conflicts {
+        let kept = if conflict.took_theirs {
+            "theirs"
+        } else {
+            "ours"
+        };
+        println!(
+            "Conflict {}: ours \"{}\" ({}), theirs \"{}\" ({}) -> kept {}",
+            conflict.ours.short_id(),
+            conflict.ours.description(),
+            conflict
+                .ours
+                .state()
+                .to_possible_value()
+                .unwrap()
+                .get_name(),
+            conflict.theirs.description(),
+            conflict
+                .theirs
+                .state()
+                .to_possible_value()
+                .unwrap()
+                .get_name(),
+            kept
+        );
+    }
+
+    println!(
+        "Added {} tasks, {} already identical, {} conflicts",
+        report.added,
+        report.identical,
+        report.conflicts.len()
+    );
+
+    if prefer.is_none() && !report.conflicts.is_empty() {
+        println!("Kept our version of conflicting tasks; rerun with --prefer newest|ours|theirs to choose");
+    }
+}
+
+/// Looks for copies of the database that a file-sync service created after
+/// conflicting edits and, if the user agrees, merges them into the database
+/// field by field and deletes them.
+pub fn offer_conflict_merge(paths: &AppPaths, db_manager: &mut file_management::DatabaseManager) {
+    // This runs before every command, so stay out of scripted and piped
+    // output, and keep the prompt off stdout.
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return;
+    }
+
+    let copies = conflict::find_conflict_copies(&paths.db_file);
+    if copies.is_empty() {
+        return;
+    }
+
+    eprintln!("Found {} conflicting copies of the database:", copies.len());
+    for copy in &copies {
+        eprintln!("  {}", copy.display());
+    }
+    eprintln!("Merge them into the database and delete them? [y/N]");
+    if !read_yes() {
+        return;
+    }
+
+    for copy in copies {
+        // Copies share the database's key, so look it up under its path.
+        let other = FileStorage::new(&copy)
+            .with_encryption(false, Box::new(KeyringKeySource::new(&paths.db_file)))
+            .load();
+
+        match other.and_then(|other| db_manager.sync(&other)) {
+            Ok(changes) => {
+                println!("Merged {} ({} changes)", copy.display(), changes);
+                if let Err(e) = std::fs::remove_file(&copy) {
+                    println!("Failed to delete {}: {}", copy.display(), e);
+                }
+            }
+            Err(e) => println!("Failed to merge {}: {}", copy.display(), e),
+        }
+    }
+}
+
+/// Offers to convert a database file that another tool wrote, such as a
+/// todo.txt, into a database in place, keeping the original next to it.
+/// Returns whether it was converted.
+pub fn offer_foreign_migration(paths: &AppPaths, config: &Config) -> bool {
+    let Ok(data) = std::fs::read(&paths.db_file) else {
+        return false;
+    };
+    let Some(format) = foreign::detect(&paths.db_file, &data) else {
+        return false;
+    };
+
+    let db = match foreign::convert(format, &data) {
+        Ok(db) => db,
+        Err(e) => {
+            println!("{} looks like a {}: {}", paths.db_file.display(), format, e);
+            return false;
+        }
+    };
+
+    println!(
+        "{} looks like a {} with {} tasks rather than a {} database",
+        paths.db_file.display(),
+        format,
+        db.tasks().len(),
+        APP_NAME
+    );
+
+    if !std::io::stdin().is_terminal() || !confirm("Convert it in place?") {
+        return false;
+    }
+
+    let mut name = paths.db_file.file_name().unwrap_or_default().to_os_string();
+    name.push(".orig");
+    let original = paths.db_file.with_file_name(name);
+    if let Err(e) = std::fs::copy(&paths.db_file, &original) {
+        println!("Failed to keep a copy of the original: {}", e);
+        return false;
+    }
+
+    match storage::open_app_storage(paths, &config.storage).save(&db) {
+        Ok(()) => {
+            println!(
+                "Converted {} tasks; the original is kept at {}",
+                db.tasks().len(),
+                original.display()
+            );
+            true
+        }
+        Err(e) => {
+            println!("Failed to convert database: {}", e);
+            false
+        }
+    }
+}
+
+fn handle_restore(
+    backup_file: &Path,
+    merge: bool,
+    yes: bool,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    let mut keys = KeyringKeySource::new(&paths.db_file);
+    let backup = match file_management::Backup::read(backup_file, Some(&mut keys)) {
+        Ok(backup) => backup,
+        Err(e) => {
+            println!("Failed to read backup: {}", e);
+            return;
+        }
+    };
+
+    let count = backup.database.tasks().len();
+
+    if merge {
+        match db_manager.merge_tasks(backup.database) {
+            Ok((added, skipped)) => println!(
+                "Merged {} tasks from backup ({} already present)",
+                added, skipped
+            ),
+            Err(e) => println!("Failed to merge backup: {}", e),
+        }
+        return;
+    }
+
+    // The configuration is only replaced when the backup's differs.
+    let config = backup
+        .config
+        .filter(|config| std::fs::read_to_string(&paths.config_file).ok().as_ref() != Some(config));
+    let question = format!(
+        "Replace the current database{} with {} tasks from the backup taken {}?",
+        if config.is_some() {
+            " and configuration"
+        } else {
+            ""
+        },
+        count,
+        backup.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    if !yes && !confirm(&question) {
+        println!("Aborted");
+        return;
+    }
+
+    if let Some(config) = config {
+        let written = match paths.config_file.parent() {
+            Some(dir) => std::fs::create_dir_all(dir)
+                .and_then(|()| storage::write_atomically(&paths.config_file, config.as_bytes())),
+            None => storage::write_atomically(&paths.config_file, config.as_bytes()),
+        };
+        if let Err(e) = written {
+            println!("Failed to write {}: {}", paths.config_file.display(), e);
+            return;
+        }
+    }
+
+    match db_manager.restore(backup.database) {
+        Ok(()) => println!("Restored {} tasks from backup", count),
+        Err(e) => println!("Failed to restore backup: {}", e),
+    }
+}
+
+fn handle_snapshot(
+    command: SnapshotCommands,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let dir = &paths.snapshot_dir();
+    let mut keys = encryption::CachedKeySource::new(KeyringKeySource::new(&paths.db_file));
+    let result = match command {
+        SnapshotCommands::Create { name } => db_manager.database().and_then(|db| {
+            // Snapshots of an encrypted database are encrypted with its key.
+            let cipher = encryption::copy_cipher(&paths.db_file)?;
+            let name = snapshot::create_snapshot(dir, name.as_deref(), db, cipher.as_ref())?;
+            println!("Saved snapshot '{}' with {} tasks", name, db.tasks().len());
+            Ok(())
+        }),
+        SnapshotCommands::List => {
+            let snapshots = snapshot::list_snapshots(dir, Some(&mut keys));
+            if snapshots.is_empty() {
+                println!("No snapshots");
+            }
+            for info in snapshots {
+                println!(
+                    "{}  {}  {} tasks",
+                    info.created_at.format("%Y-%m-%d %H:%M:%S"),
+                    info.name,
+                    info.tasks
+                );
+            }
+            Ok(())
+        }
+        SnapshotCommands::Restore { name, yes } => {
+            let snapshot = match snapshot::read_snapshot(dir, &name, Some(&mut keys)) {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    println!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let count = snapshot.database.tasks().len();
+            let question = format!(
+                "Replace the current database with {} tasks from snapshot '{}'?",
+                count, name
+            );
+            if !yes && !confirm(&question) {
+                println!("Aborted");
+                return ExitCode::FAILURE;
+            }
+
+            db_manager
+                .restore(snapshot.database)
+                .map(|()| println!("Restored {} tasks from snapshot '{}'", count, name))
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_unlock(db_file: &Path) -> ExitCode {
+    let data = std::fs::read(db_file).unwrap_or_default();
+
+    let result = encryption::prompt_passphrase(!encryption::is_encrypted(&data))
+        .and_then(|passphrase| Cipher::for_file(&data, &passphrase))
+        .and_then(|cipher| KeyringKeySource::new(db_file).store(&cipher));
+
+    match result {
+        Ok(()) => {
+            println!("Database unlocked");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to unlock database: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_lock(db_file: &Path) -> ExitCode {
+    match KeyringKeySource::new(db_file).forget() {
+        Ok(true) => println!("Database locked"),
+        Ok(false) => println!("Database was not unlocked"),
+        Err(e) => {
+            println!("Failed to lock database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn handle_init(local: bool, config: &Config, paths: &AppPaths) -> ExitCode {
+    let db_file = if local {
+        let local_dir = match std::env::current_dir() {
+            Ok(dir) => dir.join(LOCAL_DIR_NAME),
+            Err(e) => {
+                println!("Failed to get the current directory: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if local_dir.exists() {
+            println!("{} already exists", local_dir.display());
+            return ExitCode::FAILURE;
+        }
+        if let Err(e) = std::fs::create_dir(&local_dir) {
+            println!("Failed to create {}: {}", local_dir.display(), e);
+            return ExitCode::FAILURE;
+        }
+
+        local_dir.join(DB_FILE_NAME)
+    } else {
+        paths.db_file.clone()
+    };
+
+    if db_file.exists() {
+        println!("Database already exists at {}", db_file.display());
+        return ExitCode::SUCCESS;
+    }
+
+    match storage::open_storage(&db_file, &config.storage).open() {
+        Ok(_) => {
+            println!("Created database at {}", db_file.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to create database: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_profile(command: &ProfileCommands, paths: &AppPaths) -> ExitCode {
+    let result = match command {
+        ProfileCommands::Create { name } => {
+            profile::create_profile(&paths.root_dir, name).map(|dir| {
+                println!("Created profile '{}' in {}", name, dir.display());
+            })
+        }
+        ProfileCommands::List => {
+            let active = paths.profile.as_deref().unwrap_or(profile::DEFAULT_PROFILE);
+            for name in profile::list_profiles(&paths.root_dir) {
+                let marker = if name == active { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+            Ok(())
+        }
+        ProfileCommands::Switch { name } => {
+            if !profile::profile_exists(&paths.root_dir, name) {
+                println!("Profile '{}' does not exist", name);
+                return ExitCode::FAILURE;
+            }
+
+            profile::set_default_profile(&paths.base_config_file(), name)
+                .map(|()| println!("Switched to profile '{}'", name))
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_repair(config: &Config, paths: &AppPaths) -> ExitCode {
+    let mut storage = storage::open_app_storage(paths, &config.storage);
+
+    let report = match repair::repair(paths, storage.as_mut()) {
+        Ok(Some(report)) => report,
+        Ok(None) => {
+            println!("Database is readable, nothing to repair");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            println!("Failed to repair database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for path in &report.quarantined {
+        println!("Moved damaged file to {}", path.display());
+    }
+    match &report.backup {
+        Some((path, count)) => println!("Restored {} tasks from {}", count, path.display()),
+        None => println!("No backup found to restore from"),
+    }
+    println!("Salvaged {} tasks from the damaged files", report.salvaged);
+    println!("Database now holds {} tasks", report.total);
+
+    ExitCode::SUCCESS
+}
+
+fn handle_sync(
+    remote: Option<&str>,
+    token: Option<String>,
+    config: &Config,
+    paths: &AppPaths,
+) -> ExitCode {
+    // The sync state keeps a copy of every task, and the server a log of
+    // every change, both in plain text.
+    if config.storage.encrypt || encryption::salt_of_file(&paths.db_file).is_some() {
+        println!("Encrypted databases cannot be synced");
+        return ExitCode::FAILURE;
+    }
+
+    let token = token.or_else(|| std::env::var(sync::SYNC_TOKEN_ENV).ok());
+    let mut storage = storage::open_app_storage(paths, &config.storage);
+
+    let state_path = paths.state_file("sync");
+    match sync::sync(storage.as_mut(), &state_path, remote, token.as_deref()) {
+        Ok(report) => {
+            println!(
+                "Pushed {} changes, pulled {} changes",
+                report.pushed, report.pulled
+            );
+            if report.conflicts > 0 {
+                println!(
+                    "{} tasks were edited on another device too; see sync resolve",
+                    report.conflicts
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}", e);
+            if remote.is_none() {
+                println!("Pass --remote <URL> to choose a sync server");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Shows how this device stands with its sync server.
+fn handle_sync_status(config: &Config, paths: &AppPaths) -> ExitCode {
+    let mut storage = storage::open_app_storage(paths, &config.storage);
+    let status = match sync::status(storage.as_mut(), &paths.state_file("sync")) {
+        Ok(status) => status,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match &status.remote {
+        Some(remote) => println!("Remote: {}", remote),
+        None => println!("Remote: none; pass --remote <URL> to sync to choose one"),
+    }
+    match status.synced_at {
+        Some(at) => println!(
+            "Last synced: {}",
+            at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+        ),
+        None => println!("Last synced: never"),
+    }
+    println!("Changes to push: {}", status.pending);
+    println!("Unresolved conflicts: {}", status.conflicts.len());
+    for conflict in &status.conflicts {
+        let fields: Vec<String> = conflict.fields.iter().map(|f| f.to_string()).collect();
+        println!(
+            "  {} {} ({})",
+            conflict.ours.short_id(),
+            conflict.ours.description(),
+            fields.join(", ")
+        );
+    }
+
+    let copies = conflict::find_conflict_copies(&paths.db_file);
+    if !copies.is_empty() {
+        println!("Conflicting copies from file sync: {}", copies.len());
+        for copy in &copies {
+            println!("  {}", copy.display());
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Walks through the conflicts the last syncs left, asking for each field
+/// which side to keep unless `prefer` says. The side kept becomes a new
+/// edit, so the other devices take it on their next sync.
+fn handle_sync_resolve(
+    prefer: Option<MergePreference>,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let state_path = paths.state_file("sync");
+    let conflicts = match sync::conflicts(&state_path) {
+        Ok(conflicts) => conflicts,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if conflicts.is_empty() {
+        println!("No conflicts to resolve");
+        return ExitCode::SUCCESS;
+    }
+    if prefer.is_none() && !std::io::stdin().is_terminal() {
+        println!(
+            "{} conflicts; pass --prefer to resolve them without asking",
+            conflicts.len()
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let mut resolved = 0;
+    'conflicts: for conflict in &conflicts {
+        println!(
+            "{} {}",
+            conflict.ours.short_id(),
+            conflict.ours.description()
+        );
+        let mut choices = Vec::new();
+        for &field in &conflict.fields {
+            let side = match prefer {
+                Some(MergePreference::Newest) => None,
+                Some(MergePreference::Ours) => Some(&conflict.ours),
+                Some(MergePreference::Theirs) => Some(&conflict.theirs),
+                None => {
+                    println!("  {}", field);
+                    println!("    ours:   {}", conflict.ours.show_field(field));
+                    println!("    theirs: {}", conflict.theirs.show_field(field));
+                    println!("  Keep [o]urs or [t]heirs, or [s]kip this task?");
+                    match read_key().map(|k| k.to_ascii_lowercase()) {
+                        Some('o') => Some(&conflict.ours),
+                        Some('t') => Some(&conflict.theirs),
+                        _ => continue 'conflicts,
+                    }
+                }
+            };
+            choices.extend(side.map(|side| (side, field)));
+        }
+
+        db_manager.begin();
+        for (side, field) in choices {
+            if let Err(e) = db_manager.take_field(side, field) {
+                println!("{}", e);
+            }
+        }
+        let saved = db_manager.commit();
+        match saved.and_then(|()| sync::dismiss_conflict(&state_path, conflict.ours.id())) {
+            Ok(()) => resolved += 1,
+            Err(e) => {
+                println!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    println!(
+        "Resolved {} conflicts, {} left",
+        resolved,
+        conflicts.len() - resolved
+    );
+    ExitCode::SUCCESS
+}
+
+/// Syncs the tasks with the CalDAV task list from the `[caldav]` section.
+fn handle_caldav(
+    command: CaldavCommands,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let CaldavCommands::Sync = command;
+
+    let collection = match caldav::CalDavCollection::new(&config.caldav) {
+        Ok(collection) => collection,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match caldav::sync(db_manager, &collection, &paths.state_file("caldav")) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    print_service_report(report.pulled, report.pushed, &report.failures)
+}
+
+/// Imports the open Todoist tasks, or with `prefer` syncs with Todoist both
+/// ways.
+fn handle_todoist(
+    prefer: Option<MergePreference>,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let client = match todoist::TodoistClient::new(&config.todoist) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let state_file = paths.state_file("todoist");
+    let result = match prefer {
+        Some(prefer) => todoist::sync(db_manager, &client, &state_file, prefer),
+        None => todoist::import(db_manager, &client, &state_file),
+    };
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    print_service_report(report.pulled, report.pushed, &report.failures)
+}
+
+/// Syncs with the notes in `vault`, or the one from the `[markdown]` section.
+fn handle_markdown(
+    vault: Option<&Path>,
+    prefer: MergePreference,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let Some(vault) = vault.or(config.markdown.vault.as_deref()) else {
+        println!("No vault; pass --vault or set vault in the [markdown] config section");
+        return ExitCode::FAILURE;
+    };
+    let inbox = config
+        .markdown
+        .inbox
+        .as_deref()
+        .unwrap_or(Path::new(markdown::DEFAULT_INBOX));
+
+    match markdown::sync(
+        db_manager,
+        vault,
+        inbox,
+        &paths.state_file("markdown"),
+        prefer,
+    ) {
+        Ok(report) => print_service_report(report.pulled, report.pushed, &[]),
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Syncs with the calendar from the `[gcal]` section, signing in first with
+/// `login`.
+fn handle_gcal(
+    prefer: MergePreference,
+    login: bool,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    if login {
+        let signed_in = gcal::login(&config.gcal, |code| {
+            println!(
+                "Visit {} and enter the code {}",
+                code.verification_url, code.user_code
+            )
+        });
+        if let Err(e) = signed_in {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    let client = match gcal::GcalClient::new(&config.gcal) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match gcal::sync(
+        db_manager,
+        &client,
+        config.gcal.calendar(),
+        &paths.state_file("gcal"),
+        prefer,
+    ) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    print_service_report(report.pulled, report.pushed, &report.failures)
+}
+
+/// Imports the issues a JQL query finds, or the one from the `[jira]` section.
+fn handle_jira_import(
+    jql: Option<&str>,
+    config: &Config,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let client = match jira::JiraClient::new(&config.jira) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let jql = jql
+        .or(config.jira.jql.as_deref())
+        .unwrap_or(jira::DEFAULT_JQL);
+
+    match jira::import(db_manager, &client, &config.jira, jql) {
+        Ok(report) => {
+            println!(
+                "Added {} tasks and updated {}",
+                report.added, report.updated
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_microsoft_todo_import(
+    config: &Config,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let client = match microsoft_todo::MicrosoftTodoClient::new(&config.microsoft_todo) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match microsoft_todo::import(db_manager, &client) {
+        Ok(report) => {
+            println!(
+                "Added {} tasks and updated {} from {} lists",
+                report.added, report.updated, report.lists
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Mirrors the issues of `repo` assigned to the owner of the token.
+fn handle_github(
+    repo: &str,
+    close: bool,
+    save_token: bool,
+    config: &Config,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let repo = match github::parse_repo(repo) {
+        Ok(repo) => repo,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if save_token {
+        let stored = encryption::prompt_secret("GitHub token", false)
+            .and_then(|token| github::store_token(&token));
+        if let Err(e) = stored {
+            println!("Failed to store the token: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let client = match github::GitHubClient::new(&config.github) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let close = close || config.github.close_issues;
+    let report = match github::sync(db_manager, &client, repo, close) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "Added {} tasks, updated {} and completed {}; closed {} issues",
+        report.added, report.updated, report.completed, report.closed
+    );
+    for e in &report.failures {
+        println!("{}", e);
+    }
+    if report.failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Prints what a sync with a service changed, failing if any task failed.
+fn print_service_report(pulled: usize, pushed: usize, failures: &[ToNotDoError]) -> ExitCode {
+    println!("Pulled {} changes, pushed {} changes", pulled, pushed);
+    for e in failures {
+        println!("{}", e);
+    }
+    if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn handle_serve(
+    bind: std::net::IpAddr,
+    port: u16,
+    token: Option<String>,
+    new_feed_token: bool,
+    config: &Config,
+    paths: &AppPaths,
+) -> ExitCode {
+    let token = token.or_else(|| std::env::var(sync::SYNC_TOKEN_ENV).ok());
+    if token.is_none() && !bind.is_loopback() {
+        println!(
+            "Warning: serving on {} without a token; anyone on the network can change your tasks",
+            bind
+        );
+    }
+
+    let listener = match std::net::TcpListener::bind((bind, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to listen on {}:{}: {}", bind, port, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let feed_token = match feed::feed_token(&paths.state_file("feed"), new_feed_token) {
+        Ok(feed_token) => feed_token,
+        Err(e) => {
+            println!("Failed to set up the calendar feed: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "Serving {} on http://{}:{}",
+        paths.db_file.display(),
+        bind,
+        port
+    );
+    for (feed, endpoint) in [
+        ("Calendar", feed::FEED_ENDPOINT),
+        ("Completed tasks", feed::ATOM_ENDPOINT),
+    ] {
+        println!(
+            "{} feed: http://{}:{}{}?token={}",
+            feed, bind, port, endpoint, feed_token
+        );
+    }
+
+    let name = match &paths.profile {
+        Some(profile) => format!("{} ({})", APP_NAME, profile),
+        None => APP_NAME.to_string(),
+    };
+    let state = serve::ServerState::new(paths.db_file.clone(), config.storage.clone(), token)
+        .with_journal(paths.journal_file())
+        .with_feed(feed_token, name);
+    match serve::serve(listener, state) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_compact(config: &Config, paths: &AppPaths) -> ExitCode {
+    if !paths.db_file.is_file() {
+        println!("No database file at {}", paths.db_file.display());
+        return ExitCode::SUCCESS;
+    }
+
+    let report = match compact::compact(
+        &paths.db_file,
+        &paths.journal_file(),
+        &config.storage,
+        &config.compact,
+    ) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("Failed to compact database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "Forgot {} old deletions, folded {} history entries and {} journal events",
+        report.deleted, report.history, report.events
+    );
+    println!(
+        "Reclaimed {} bytes ({} -> {})",
+        report.size_before.saturating_sub(report.size_after),
+        report.size_before,
+        report.size_after
+    );
+
+    ExitCode::SUCCESS
+}
+
+fn handle_rpc(db_manager: &mut file_management::DatabaseManager) -> ExitCode {
+    match rpc::run(
+        db_manager,
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+    ) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Failed to answer requests: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn handle_dbus(db_manager: &mut file_management::DatabaseManager) -> ExitCode {
+    println!(
+        "Serving {} on the session bus; press Ctrl-C to stop",
+        dbus::BUS_NAME
+    );
+    match dbus::serve(db_manager) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_mcp(db_manager: &mut file_management::DatabaseManager) -> ExitCode {
+    match mcp::run(
+        db_manager,
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+    ) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Failed to answer requests: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints the tasks as events, or with `follow` the changes until stopped.
+fn handle_events(follow: bool, db_manager: &mut file_management::DatabaseManager) -> ExitCode {
+    use std::io::Write;
+
+    let print = |events: Vec<events::TaskEvent>| -> std::io::Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        for event in events {
+            let line = events::EventLine {
+                at: chrono::Utc::now(),
+                event,
+            };
+            serde_json::to_writer(&mut stdout, &line)?;
+            writeln!(stdout)?;
+        }
+        stdout.flush()
+    };
+
+    let (mut follower, existing) = match events::Follower::start(db_manager) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if !follow {
+        return match print(existing) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Failed to print events: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    loop {
+        std::thread::sleep(events::POLL_INTERVAL);
+        let printed = match follower.poll(db_manager) {
+            Ok(changes) => print(changes),
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        match printed {
+            Ok(()) => {}
+            // The reader went away.
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Failed to print events: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+}
+
+fn handle_publish(
+    output: &Path,
+    exclude_private: bool,
+    title: Option<String>,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let context = db_manager.context().map(str::to_string);
+    let tasks = match db_manager.get_tasks() {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let tasks = publish::select(tasks, context.as_deref(), !exclude_private);
+    let title = title.or(context).unwrap_or_else(|| "Tasks".to_string());
+    let page = publish::render(&title, &tasks, chrono::Utc::now().date_naive());
+    match publish::write(output, &page) {
+        Ok(path) => {
+            println!("Published {} tasks to {}", tasks.len(), path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_webhook(command: &WebhookCommands, config: &Config) -> ExitCode {
+    let WebhookCommands::Test { event, url } = command;
+
+    let webhooks = match url {
+        Some(url) => vec![WebhookConfig {
+            events: vec![*event],
+            retries: 0,
+            ..WebhookConfig::new(url)
+        }],
+        None => config.webhooks.clone(),
+    };
+    if webhooks.is_empty() {
+        println!("No webhooks configured; add a [[webhooks]] section with a url to the config");
+        return ExitCode::FAILURE;
+    }
+
+    let payload = match event {
+        WebhookEvent::Summary => {
+            webhook::Payload::summary("Test summary from to-not-do".to_string())
+        }
+        _ => webhook::Payload::new(*event, Task::new("Test task from to-not-do")),
+    };
+    let mut code = ExitCode::SUCCESS;
+    for hook in &webhooks {
+        match webhook::deliver(hook, &payload) {
+            Ok(()) => println!("Delivered to {}", hook.url),
+            Err(e) => {
+                println!("{}", e);
+                code = ExitCode::FAILURE;
+            }
+        }
+    }
+    code
+}
+
+/// Loads the plugins in the data directory, showing the ones that fail.
+#[cfg(feature = "plugins")]
+fn load_plugins(paths: &AppPaths) -> PluginHost {
+    let (host, failures) = PluginHost::load(&paths.data_dir.join(PLUGINS_DIR));
+    for e in &failures {
+        eprintln!("{}", e);
+    }
+    host
+}
+
+#[cfg(feature = "plugins")]
+fn handle_plugin(command: &PluginCommands, paths: &AppPaths) -> ExitCode {
+    let PluginCommands::List = command;
+
+    let host = load_plugins(paths);
+    if host.plugins().is_empty() {
+        println!(
+            "No plugins in {}",
+            paths.data_dir.join(PLUGINS_DIR).display()
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    for plugin in host.plugins() {
+        let manifest = plugin.manifest();
+        println!("{}", manifest.name);
+        for command in &manifest.commands {
+            println!("  command {:<16} {}", command.name, command.about);
+        }
+        for filter in &manifest.filters {
+            println!("  filter  {}", filter);
+        }
+        if !manifest.events.is_empty() {
+            println!("  events  {}", manifest.events.join(", "));
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+fn unknown_command(name: &str) -> ExitCode {
+    if cfg!(feature = "plugins") {
+        println!(
+            "Unknown command {}; see `{} --help`, `{} plugin list` for plugin commands, \
+             or put a {}-{} program on the PATH",
+            name, APP_NAME, APP_NAME, APP_NAME, name
+        );
+    } else {
+        println!(
+            "Unknown command {}; see `{} --help`, or put a {}-{} program on the PATH",
+            name, APP_NAME, APP_NAME, name
+        );
+    }
+    ExitCode::FAILURE
+}
+
+/// Runs the `to-not-do-<name>` program on the `PATH` for an unknown
+/// command, before the database is opened so the program can open it
+/// itself. A plugin command of the same name comes first.
+fn handle_external(args: &[String], paths: &AppPaths) -> Option<ExitCode> {
+    let (name, args) = args.split_first()?;
+    let program = external::find(name, std::env::var_os("PATH"))?;
+    #[cfg(feature = "plugins")]
+    if load_plugins(paths).has_command(name) {
+        return None;
+    }
+
+    let mut command = external::command(&program, args, paths);
+    #[cfg(unix)]
+    let result = {
+        use std::os::unix::process::CommandExt;
+        // Only returns if the program could not be run.
+        Err::<std::process::ExitStatus, _>(command.exec())
+    };
+    #[cfg(not(unix))]
+    let result = command.status();
+
+    Some(match result {
+        Ok(status) => status
+            .code()
+            .map_or(ExitCode::FAILURE, |code| ExitCode::from(code as u8)),
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", program.display(), e);
+            ExitCode::FAILURE
+        }
+    })
+}
+
+/// Runs a plugin command, then adds and completes the tasks it asks for.
+#[cfg(feature = "plugins")]
+fn handle_plugin_command(
+    args: &[String],
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let Some((name, args)) = args.split_first() else {
+        return ExitCode::FAILURE;
+    };
+
+    let mut host = load_plugins(paths);
+    if !host.has_command(name) {
+        return unknown_command(name);
+    }
+
+    let output = match db_manager
+        .get_tasks()
+        .and_then(|tasks| host.run_command(name, args, tasks))
+    {
+        Ok(output) => output,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !output.output.is_empty() {
+        println!("{}", output.output.trim_end());
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    db_manager.begin();
+    for new_task in output.add {
+        if let Err(e) = db_manager.add_task(&new_task.into_task()) {
+            println!("Failed to add task: {}", e);
+            code = ExitCode::FAILURE;
+        }
+    }
+    for id in output.done {
+        if let Err(e) = db_manager.set_task_state(id, TaskState::Done) {
+            println!("{}", e);
+            code = ExitCode::FAILURE;
+        }
+    }
+    if let Err(e) = db_manager.commit() {
+        println!("Failed to save changes: {}", e);
+        code = ExitCode::FAILURE;
+    }
+    if let Some(error) = output.error {
+        println!("{}", error);
+        code = ExitCode::FAILURE;
+    }
+    code
+}
+
+#[cfg(feature = "scripting")]
+fn handle_script(
+    command: ScriptCommands,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let (name, source, args) = match command {
+        ScriptCommands::Run { file, args } => match std::fs::read_to_string(&file) {
+            Ok(source) => (file.display().to_string(), source, args),
+            Err(e) => {
+                println!("Failed to read {}: {}", file.display(), e);
+                return ExitCode::FAILURE;
+            }
+        },
+        ScriptCommands::List => {
+            if config.scripts.is_empty() {
+                println!("No scripts configured; add a [scripts.<name>] section with a file or source to the config");
+            }
+            for (name, script) in &config.scripts {
+                println!("{:<16} {}", name, script.about);
+            }
+            return ExitCode::SUCCESS;
+        }
+        ScriptCommands::Named(mut words) => {
+            let name = words.remove(0);
+            let Some(script) = config.scripts.get(&name) else {
+                println!(
+                    "No script {} in the config; see `{} script list`",
+                    name, APP_NAME
+                );
+                return ExitCode::FAILURE;
+            };
+
+            let source = match (&script.source, &script.file) {
+                (Some(source), _) => source.clone(),
+                (None, file) => {
+                    let config_dir = paths.base_config_file();
+                    let file = config_dir
+                        .parent()
+                        .unwrap_or(Path::new("."))
+                        .join(file.as_deref().unwrap_or(Path::new("")));
+                    match std::fs::read_to_string(&file) {
+                        Ok(source) => source,
+                        Err(e) => {
+                            println!("Failed to read {}: {}", file.display(), e);
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+            };
+            (name, source, words)
+        }
+    };
+
+    match script::run(db_manager, &name, &source, &args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_digest(
+    period: DigestPeriod,
+    email: bool,
+    stdout: bool,
+    config: &Config,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let today = chrono::Local::now().date_naive();
+    let digest = match db_manager.get_tasks() {
+        Ok(tasks) => Digest::new(tasks, today, period),
+        Err(e) => {
+            println!("Failed to retrieve tasks: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !email && !stdout {
+        println!("{}\n", digest.subject());
+        print!("{}", digest.body());
+        return ExitCode::SUCCESS;
+    }
+
+    let message = match digest.message(&config.email) {
+        Ok(message) => message,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if stdout {
+        use std::io::Write;
+        let _ = std::io::stdout().write_all(&message.formatted());
+        return ExitCode::SUCCESS;
+    }
+
+    match digest::send(&message, &config.email) {
+        Ok(()) => {
+            println!("Sent {}", digest.subject());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Writes the units for the profile and database picked by `--profile` and
+/// `--db`, if given.
+fn handle_generate(
+    command: &GenerateCommands,
+    profile: Option<&str>,
+    custom_db: bool,
+    paths: &AppPaths,
+) -> ExitCode {
+    let GenerateCommands::Systemd { tick, output_dir } = command;
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            println!("Failed to find the {} executable: {}", APP_NAME, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut global_args = Vec::new();
+    if let Some(profile) = profile {
+        global_args.extend(["--profile".to_string(), profile.to_string()]);
+    }
+    if custom_db {
+        global_args.extend([
+            "--db".to_string(),
+            paths.db_file.to_string_lossy().into_owned(),
+        ]);
+    }
+
+    let units = systemd::units(&systemd::UnitOptions {
+        exe: &exe,
+        global_args: &global_args,
+        suffix: profile,
+        tick_calendar: tick,
+        notify: cfg!(feature = "notifications"),
+    });
+
+    let Some(output_dir) = output_dir else {
+        for (i, unit) in units.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            println!("# {}", unit.name);
+            print!("{}", unit.contents);
+        }
+        return ExitCode::SUCCESS;
+    };
+
+    if let Err(e) = std::fs::create_dir_all(output_dir) {
+        println!("Failed to create {}: {}", output_dir.display(), e);
+        return ExitCode::FAILURE;
+    }
+    for unit in &units {
+        let path = output_dir.join(&unit.name);
+        if let Err(e) = std::fs::write(&path, &unit.contents) {
+            println!("Failed to write {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+        println!("Wrote {}", path.display());
+    }
+
+    // The tick service has no [Install] section; its timer starts it.
+    let enabled: Vec<&str> = units
+        .iter()
+        .filter(|unit| unit.contents.contains("[Install]"))
+        .map(|unit| unit.name.as_str())
+        .collect();
+    println!(
+        "Enable them with: systemctl --user daemon-reload && systemctl --user enable --now {}",
+        enabled.join(" ")
+    );
+    ExitCode::SUCCESS
+}
+
+/// Installs the git hook in `repo`, running this executable with the profile
+/// and database picked by `--profile` and `--db`, if given.
+fn handle_git_hook_install(
+    repo: &Path,
+    force: bool,
+    profile: Option<&str>,
+    custom_db: bool,
+    paths: &AppPaths,
+) -> ExitCode {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            println!("Failed to find the {} executable: {}", APP_NAME, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut global_args = Vec::new();
+    if let Some(profile) = profile {
+        global_args.extend(["--profile".to_string(), profile.to_string()]);
+    }
+    if custom_db {
+        global_args.extend([
+            "--db".to_string(),
+            paths.db_file.to_string_lossy().into_owned(),
+        ]);
+    }
+
+    match git_hook::install(repo, &git_hook::hook_script(&exe, &global_args), force) {
+        Ok(path) => {
+            println!("Installed {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Closes the tasks the commit just made says it closes.
+fn handle_post_commit(db_manager: &mut file_management::DatabaseManager) -> ExitCode {
+    let closed = git_hook::last_commit(Path::new("."))
+        .and_then(|(hash, message)| git_hook::close(db_manager, &message, &hash));
+    let report = match closed {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for task in &report.closed {
+        println!("Task marked as done: {}", task.description());
+    }
+    for unknown in &report.unknown {
+        println!("{}", unknown);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Locks the state file `<name>.lock`, so only one `name` runs per database.
+/// The lock lasts as long as the returned file is open.
+fn lock_instance(paths: &AppPaths, name: &str) -> Option<std::fs::File> {
+    let lock_path = paths.state_file(&format!("{}.lock", name));
+    let lock = match std::fs::File::create(&lock_path) {
+        Ok(lock) => lock,
+        Err(e) => {
+            println!("Failed to create {}: {}", lock_path.display(), e);
+            return None;
+        }
+    };
+    match lock.try_lock() {
+        Ok(()) => Some(lock),
+        Err(std::fs::TryLockError::WouldBlock) => {
+            println!("A {} is already running for this database", name);
+            None
+        }
+        Err(std::fs::TryLockError::Error(e)) => {
+            println!("Failed to lock {}: {}", lock_path.display(), e);
+            None
+        }
+    }
+}
+
+/// Runs one tick; see [`schedule::tick`].
+fn handle_tick(
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let now = chrono::Local::now().naive_local();
+    let report = match schedule::tick(db_manager, config, now, &paths.state_file("webhooks")) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !report.purged.is_empty() {
+        println!(
+            "Deleted {} archived tasks, forgot {} old deletions and folded {} history entries",
+            report.purged.archived, report.purged.deleted, report.purged.history
+        );
+    }
+    if report.overdue > 0 {
+        println!("Reported {} overdue tasks", report.overdue);
+    }
+    if report.summaries > 0 {
+        println!("Sent {} daily summaries", report.summaries);
+    }
+    for e in &report.failures {
+        println!("{}", e);
+    }
+
+    if report.failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Ticks every `every` minutes until stopped. Failures are shown and the
+/// next tick tries again.
+fn handle_daemon(
+    every: u64,
+    socket: Option<PathBuf>,
+    no_socket: bool,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let Some(_lock) = lock_instance(paths, "daemon") else {
+        return ExitCode::FAILURE;
+    };
+
+    #[cfg(unix)]
+    let requests = if no_socket {
+        None
+    } else {
+        let path = socket.unwrap_or_else(|| paths.state_file("sock"));
+        match to_not_do::socket::listen(&path) {
+            Ok(requests) => {
+                println!("Answering requests on {}", path.display());
+                Some(requests)
+            }
+            Err(e) => {
+                println!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let requests: Option<
+        std::sync::mpsc::Receiver<Box<dyn FnOnce(&mut file_management::DatabaseManager)>>,
+    > = {
+        let _ = (socket, no_socket);
+        None
+    };
+
+    let interval = std::time::Duration::from_secs(every.saturating_mul(60));
+    println!("Ticking every {} minutes; press Ctrl-C to stop", every);
+    loop {
+        handle_tick(config, paths, db_manager);
+
+        // Answering requests on the socket until the next tick, which an
+        // interval too long for the clock never reaches.
+        let next_tick = std::time::Instant::now().checked_add(interval);
+        loop {
+            let left = next_tick.map_or(std::time::Duration::MAX, |next_tick| {
+                next_tick.saturating_duration_since(std::time::Instant::now())
+            });
+            if left.is_zero() {
+                break;
+            }
+            match requests
+                .as_ref()
+                .map(|requests| requests.recv_timeout(left))
+            {
+                Some(Ok(job)) => job(db_manager),
+                Some(Err(std::sync::mpsc::RecvTimeoutError::Timeout)) => {}
+                Some(Err(std::sync::mpsc::RecvTimeoutError::Disconnected)) | None => {
+                    std::thread::sleep(left)
+                }
+            }
+        }
+    }
+}
+
+/// Runs a bot until stopped.
+fn handle_bot(
+    command: BotCommands,
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    match command {
+        BotCommands::Telegram => handle_telegram_bot(config, paths, db_manager),
+        BotCommands::Matrix => handle_matrix_bot(config, paths, db_manager),
+    }
+}
+
+/// Answers the Telegram chats until stopped. Only one runs per database, as
+/// Telegram hands each update to a single poller.
+fn handle_telegram_bot(
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let Some(_lock) = lock_instance(paths, "telegram-bot") else {
+        return ExitCode::FAILURE;
+    };
+
+    let client = match telegram::TelegramClient::new(&config.telegram) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match client.username() {
+        Ok(username) => println!("Answering as @{}; press Ctrl-C to stop", username),
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if config.telegram.allowed_chats.is_empty() {
+        println!("No chats are allowed yet; message the bot to learn the ID of yours");
+    }
+
+    let mut offset = 0;
+    loop {
+        let updates = match client.updates(offset) {
+            Ok(updates) => updates,
+            Err(e) => {
+                // Waiting out network trouble rather than giving up.
+                eprintln!("{}", e);
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                continue;
+            }
+        };
+        for update in updates {
+            offset = update.update_id + 1;
+            let today = chrono::Local::now().date_naive();
+            if let Err(e) = telegram::handle(db_manager, &client, &config.telegram, &update, today)
+            {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+/// Answers commands in the Matrix room until stopped. Only one runs per
+/// database, so commands are answered once.
+fn handle_matrix_bot(
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let Some(_lock) = lock_instance(paths, "matrix-bot") else {
+        return ExitCode::FAILURE;
+    };
+
+    let client = match matrix::MatrixClient::new(&config.matrix) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "Answering {} commands as {}; press Ctrl-C to stop",
+        matrix::COMMAND_PREFIX,
+        client.user_id()
+    );
+    if config.matrix.allowed_users.is_empty() {
+        println!("No users are allowed yet; add their IDs to allowed_users in [matrix]");
+    }
+
+    let state_path = paths.state_file("matrix");
+    let mut state = matrix::MatrixState::read(&state_path);
+    loop {
+        let now = chrono::Local::now().naive_local();
+        if let Err(e) = matrix::step(db_manager, &client, &config.matrix, &mut state, now) {
+            // Waiting out network trouble rather than giving up.
+            eprintln!("{}", e);
+            std::thread::sleep(std::time::Duration::from_secs(10));
+        }
+        if let Err(e) = state.write(&state_path) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Shows reminders for tasks as they come due until stopped. Only one daemon
+/// runs per database, guarded by a lock file in the state directory.
+#[cfg(feature = "notifications")]
+fn handle_notify_daemon(
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let Some(_lock) = lock_instance(paths, "notify-daemon") else {
+        return ExitCode::FAILURE;
+    };
+
+    let state_path = paths.state_file("reminders");
+    let mut state = ReminderState::read(&state_path);
+    let (actions, picked) = std::sync::mpsc::channel();
+    let interval = std::time::Duration::from_secs(config.notify.interval_seconds.max(1));
+
+    println!(
+        "Reminding of due tasks at {}; press Ctrl-C to stop",
+        config.notify.time.format("%H:%M")
+    );
+    loop {
+        let now = chrono::Local::now().naive_local();
+        let due = match db_manager.database() {
+            Ok(db) => state.take_due(db, now, config.notify.time),
+            Err(e) => {
+                eprintln!("{}", e);
+                Vec::new()
+            }
+        };
+        for task in &due {
+            if let Err(e) = reminder::show(task, actions.clone()) {
+                eprintln!("{}", e);
+            }
+        }
+
+        if !due.is_empty() {
+            if let Err(e) = state.write(&state_path) {
+                eprintln!("{}", e);
+            }
+        }
+
+        // Sleeping until the next look, unless the user acts on a reminder.
+        match picked.recv_timeout(interval) {
+            Ok((id, ReminderAction::Done)) => {
+                if let Err(e) = db_manager.set_task_state(id, TaskState::Done) {
+                    eprintln!("{}", e);
+                }
+            }
+            Ok((id, ReminderAction::Snooze)) => {
+                state.snooze(
+                    id,
+                    chrono::Local::now().naive_local() + config.notify.snooze(),
+                );
+                if let Err(e) = state.write(&state_path) {
+                    eprintln!("{}", e);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Whether anything in the data directory listens for tasks being added,
+/// done or deleted.
+pub fn has_lifecycle_handlers(data_dir: &Path) -> bool {
+    #[cfg(feature = "plugins")]
+    if data_dir.join(PLUGINS_DIR).is_dir() {
+        return true;
+    }
+    data_dir.join(hooks::HOOKS_DIR).is_dir()
+}
+
+/// Runs the hook scripts and plugin event handlers in `data_dir` for the
+/// tasks added, done or deleted since `before`. Failures are shown; returns
+/// whether there were none.
+pub fn run_lifecycle_hooks(
+    data_dir: &Path,
+    before: &file_management::Database,
+    db_manager: &mut file_management::DatabaseManager,
+) -> bool {
+    let after = match db_manager.database() {
+        Ok(after) => after,
+        Err(e) => {
+            eprintln!("Failed to read database for hooks: {}", e);
+            return false;
+        }
+    };
+
+    let events = hooks::lifecycle(before, after);
+    #[allow(unused_mut)]
+    let mut failures = hooks::run_hooks(&data_dir.join(hooks::HOOKS_DIR), &events);
+
+    #[cfg(feature = "plugins")]
+    if !events.is_empty() && data_dir.join(PLUGINS_DIR).is_dir() {
+        let (mut host, load_failures) = PluginHost::load(&data_dir.join(PLUGINS_DIR));
+        failures.extend(load_failures);
+        for (event, task) in &events {
+            failures.extend(host.dispatch(*event, task));
+        }
+    }
+
+    for e in &failures {
+        eprintln!("{}", e);
+    }
+    failures.is_empty()
+}
+
+/// Tells the configured webhooks about the tasks created or completed since
+/// `before`. Failures are shown and queued for `tick` to retry.
+pub fn notify_webhooks(
+    webhooks: &[WebhookConfig],
+    paths: &AppPaths,
+    before: &file_management::Database,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    let after = match db_manager.database() {
+        Ok(after) => after,
+        Err(e) => {
+            eprintln!("Failed to read database for webhooks: {}", e);
+            return;
+        }
+    };
+
+    let changes = webhook::changes(before, after);
+    for e in webhook::notify(webhooks, &changes, &paths.state_file("webhooks")) {
+        eprintln!("{}", e);
+    }
+}
+
+fn handle_purge(
+    yes: bool,
+    options: &CompactConfig,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    // Preview on a copy, so only archived tasks need confirming.
+    let preview = db_manager.database().map(|db| {
+        let mut db = db.clone();
+        compact::purge_expired(&mut db, options)
+    });
+    let preview = match preview {
+        Ok(preview) => preview,
+        Err(e) => {
+            println!("Failed to read database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if preview.is_empty() {
+        println!("Nothing has expired");
+        return ExitCode::SUCCESS;
+    }
+
+    if preview.archived > 0
+        && !yes
+        && !confirm(&format!(
+            "Delete {} tasks archived more than {} days ago?",
+            preview.archived, options.archived_days
+        ))
+    {
+        println!("Cancelled");
+        return ExitCode::SUCCESS;
+    }
+
+    match db_manager.purge_expired(options) {
+        Ok(report) => {
+            println!(
+                "Deleted {} archived tasks, forgot {} old deletions and folded {} history entries",
+                report.archived, report.deleted, report.history
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to purge database: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_db(command: &DbCommands, config: &Config, paths: &AppPaths) -> ExitCode {
+    match command {
+        DbCommands::Stats => handle_db_stats(config, paths),
+    }
+}
+
+fn handle_db_stats(config: &Config, paths: &AppPaths) -> ExitCode {
+    if !paths.db_file.is_file() {
+        println!("No database file at {}", paths.db_file.display());
+        return ExitCode::SUCCESS;
+    }
+
+    let stats = match stats::db_stats(paths, &config.storage) {
+        Ok(stats) => stats,
+        Err(e) => {
+            println!("Failed to read database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("Database:       {}", paths.db_file.display());
+    println!("Storage:        {}", stats.backend);
+    println!("Schema version: {}", stats.schema_version);
+    println!("File size:      {} bytes", stats.file_size);
+    println!(
+        "Tasks:          {} ({} todo, {} in progress, {} done, {} archived)",
+        stats.tasks(),
+        stats.todo,
+        stats.in_progress,
+        stats.done,
+        stats.archived
+    );
+    println!("History:        {} entries", stats.history_entries);
+    println!(
+        "Journal:        {} events, {} bytes",
+        stats.journal_events, stats.journal_size
+    );
+    match stats.last_backup {
+        Some(at) => println!("Last backup:    {}", at.format("%Y-%m-%d %H:%M:%S UTC")),
+        None => println!("Last backup:    never"),
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn handle_verify(fix: bool, config: &Config, paths: &AppPaths) -> ExitCode {
+    let mut storage = storage::file_storage(&paths.db_file, &config.storage);
+    if !storage.exists() {
+        println!("No database file at {}", paths.db_file.display());
+        return ExitCode::SUCCESS;
+    }
+
+    let mut db = match storage.load_value() {
+        Ok(db) => db,
+        Err(e) => {
+            println!("{}", e);
+            println!("Run `{} repair` to recover what can be salvaged", APP_NAME);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let violations = verify::verify(&mut db, fix);
+    let count = db["tasks"].as_array().map_or(0, Vec::len);
+
+    if violations.is_empty() {
+        println!("No problems found in {} tasks", count);
+        return ExitCode::SUCCESS;
+    }
+
+    for violation in &violations {
+        let note = match (violation.fixable, fix) {
+            (false, _) => " (needs fixing by hand)",
+            (true, true) => " (fixed)",
+            (true, false) => "",
+        };
+        println!("{}{}", violation, note);
+    }
+
+    let unfixable = violations.iter().filter(|v| !v.fixable).count();
+    if !fix {
+        println!(
+            "Found {} problems; run `{} verify --fix` to repair {} of them",
+            violations.len(),
+            APP_NAME,
+            violations.len() - unfixable
+        );
+        return ExitCode::FAILURE;
+    }
+
+    if unfixable > 0 {
+        println!("Left the database unchanged until the remaining problems are fixed");
+        return ExitCode::FAILURE;
+    }
+
+    let saved = migration::migrate(&mut db)
+        .and_then(|_| {
+            serde_json::from_value(db)
+                .map_err(|e| ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(e.into())))
+        })
+        .and_then(|db| storage.save(&db))
+        .and_then(|()| checksum::record(&paths.db_file));
+
+    match saved {
+        Ok(()) => {
+            println!("Fixed {} problems", violations.len());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to save the fixed database: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_convert(input: &Path, output: &Path, format: Option<Format>) -> ExitCode {
+    if !input.is_file() {
+        println!("No database file at {}", input.display());
+        return ExitCode::FAILURE;
+    }
+
+    let mut source =
+        FileStorage::new(input).with_encryption(false, Box::new(KeyringKeySource::new(input)));
+    let mut target = FileStorage::new(output);
+    if let Some(format) = format {
+        target = target.with_format(format);
+    }
+
+    match source.load().and_then(|db| target.save(&db)) {
+        Ok(()) => {
+            println!("Converted {} to {}", input.display(), output.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Failed to convert database: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn handle_search(
+    query: &str,
+    count: bool,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    let results = db_manager.search(query);
+
+    if count {
+        println!("{}", results.len());
+    } else if results.is_empty() {
+        println!("No tasks match \"{}\"", query);
+    } else {
+        for result in &results {
+            println!(
+                "{} {}",
+                result.task.short_id(),
+                highlight(result.task.description(), query)
+            );
+
+            for (field, text) in &result.fields {
+                if *field != file_management::SearchField::Description {
+                    println!("    {}: {}", field, highlight(text, query));
+                }
+            }
+        }
+    }
+
+    if results.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Wraps every case-insensitive occurrence of `query` in `text` in color.
+/// Coloring is dropped automatically when stdout is not a terminal.
+fn highlight(text: &str, query: &str) -> String {
+    let mut highlighted = String::new();
+    let mut last = 0;
+
+    for range in find_matches(text, query) {
+        highlighted.push_str(&text[last..range.start]);
+        highlighted.push_str(
+            &console::style(&text[range.clone()])
+                .yellow()
+                .bold()
+                .to_string(),
+        );
+        last = range.end;
+    }
+
+    highlighted.push_str(&text[last..]);
+    highlighted
+}
+
+/// Byte ranges of the non-overlapping, case-insensitive occurrences of
+/// `query` in `text`.
+fn find_matches(text: &str, query: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+
+    if query.is_empty() {
+        return ranges;
+    }
+
+    let mut start = 0;
+    while start < text.len() {
+        let mut haystack = text[start..].char_indices();
+        let mut needle = query.chars();
+        let mut end = None;
+
+        loop {
+            let Some(expected) = needle.next() else {
+                end = Some(haystack.next().map_or(text.len(), |(i, _)| start + i));
+                break;
+            };
+
+            match haystack.next() {
+                Some((_, c)) if c.to_lowercase().eq(expected.to_lowercase()) => {}
+                _ => break,
+            }
+        }
+
+        match end {
+            Some(end) => {
+                ranges.push(start..end);
+                start = end;
+            }
+            None => {
+                start += text[start..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+
+    ranges
+}
+
+fn handle_context(
+    command: Option<ContextCommands>,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    match command {
+        Some(ContextCommands::Use { project }) => match db_manager.set_context(Some(&project)) {
+            Ok(()) => println!("Context set to {}", project),
+            Err(e) => println!("Failed to set context: {}", e),
+        },
+        Some(ContextCommands::Clear) => match db_manager.set_context(None) {
+            Ok(()) => println!("Context cleared"),
+            Err(e) => println!("Failed to clear context: {}", e),
+        },
+        None => match db_manager.context() {
+            Some(context) => println!("Context: {}", context),
+            None => println!("No active context"),
+        },
+    }
+}
+
+fn handle_focus(
+    task_id: Option<Uuid>,
+    clear: bool,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    if clear {
+        match db_manager.clear_focus() {
+            Ok(()) => println!("Focus cleared"),
+            Err(e) => println!("Failed to clear focus: {}", e),
+        }
+        return;
+    }
+
+    if let Some(task_id) = task_id {
+        match db_manager.set_focus(task_id) {
+            Ok(_) => println!("Task focused"),
+            Err(e) => println!("{}", e),
+        };
+        return;
+    }
+
+    match db_manager.focused_task() {
+        Some(task) => {
+            println!("==================");
+            println!("FOCUS: {}", task.description());
+            println!("==================");
+            println!("{}", task);
+        }
+        None => println!("No task is focused"),
+    }
+}
+
+/// Prints the summary for `bar` kept from an earlier `status`, if the
+/// database has not changed since.
+fn print_cached_status(bar: Bar, paths: &AppPaths) -> Option<ExitCode> {
+    let output = statusbar::cached(&paths.state_file(bar.name()), paths, chrono::Local::now())?;
+    println!("{}", output);
+    Some(ExitCode::SUCCESS)
+}
+
+fn handle_status(
+    bar: Option<Bar>,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) {
+    let context = db_manager.context().map(str::to_string);
+
+    let tasks: Vec<Task> = match db_manager.get_active_tasks() {
+        Ok(tasks) => tasks
+            .into_iter()
+            .filter(|t| in_context(t, context.as_deref()))
+            .collect(),
+        Err(_) => {
+            println!("Failed to retrieve tasks");
+            return;
+        }
+    };
+
+    if let Some(bar) = bar {
+        let now = chrono::Local::now();
+        let output = bar.render(db_manager.focused_task(), &tasks, now.date_naive());
+        println!("{}", output);
+        // Without the cache the next run is only slower.
+        let _ = statusbar::store(&paths.state_file(bar.name()), paths, &output, now);
+        return;
+    }
+
+    let todo = tasks
+        .iter()
+        .filter(|t| t.state() == TaskState::Todo)
+        .count();
+    let in_progress = tasks
+        .iter()
+        .filter(|t| t.state() == TaskState::InProgress)
+        .count();
+
+    let mut summary = format!("{} todo, {} in progress", todo, in_progress);
+    if let Some(context) = context {
+        summary = format!("[{}] {}", context, summary);
+    }
+
+    match db_manager.focused_task() {
+        Some(task) => println!("Focus: {} | {}", task.description(), summary),
+        None => println!("{}", summary),
+    }
+}
+
+/// Commands a batch script runs between saves, so a long or endless script
+/// piped in does not keep its changes in memory only.
+const BATCH_FLUSH_COMMANDS: usize = 500;
+
+/// Runs the commands piped in, one per line. Fails if any line did.
+fn handle_batch(
+    config: &Config,
+    paths: &AppPaths,
+    db_manager: &mut file_management::DatabaseManager,
+) -> ExitCode {
+    db_manager.begin();
+
+    let mut code = ExitCode::SUCCESS;
+    let mut commands = 0;
+    for (index, line) in std::io::stdin().lines().enumerate() {
+        let line_number = index + 1;
+
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => {
+                println!("Failed to read line {}", line_number);
+                code = ExitCode::FAILURE;
+                break;
+            }
+        };
+
+        match parse_batch_line(&line) {
+            Some(Ok(args)) => {
+                if handle_commands(args, config, paths, db_manager) != ExitCode::SUCCESS {
+                    code = ExitCode::FAILURE;
+                }
+                commands += 1;
+                if commands % BATCH_FLUSH_COMMANDS == 0 {
+                    if let Err(e) = db_manager.flush() {
+                        println!("Failed to save changes: {}", e);
+                        code = ExitCode::FAILURE;
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                println!("Line {}: {}", line_number, e);
+                code = ExitCode::FAILURE;
+            }
+            None => {}
+        }
+    }
+
+    if let Err(e) = db_manager.commit() {
+        println!("Failed to save changes: {}", e);
+        return ExitCode::FAILURE;
+    }
+    code
+}
+
+/// Parses one line of a batch script into command arguments. Blank lines and
+/// lines starting with `#` are skipped. Options that pick the database, and
+/// commands that work on its file rather than through the open database,
+/// apply to the whole batch and are refused on a line.
+fn parse_batch_line(line: &str) -> Option<Result<Args, String>> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let Some(words) = shlex::split(line) else {
+        return Some(Err("Unbalanced quotes".to_string()));
+    };
+
+    let name = words.first().cloned().unwrap_or_default();
+    let args = match Args::try_parse_from(std::iter::once(APP_NAME.to_string()).chain(words)) {
+        Ok(args) => args,
+        Err(e) => return Some(Err(e.to_string().trim_end().to_string())),
+    };
+
+    if let Commands::Batch = args.command {
+        return Some(Err("Batch commands cannot be nested".to_string()));
+    }
+    if let Commands::External(words) = &args.command {
+        return Some(Err(format!("Unknown command {}", words[0])));
+    }
+    if args.db.is_some() || args.profile.is_some() || args.read_only {
+        return Some(Err(
+            "--db, --profile and --read-only apply to the whole batch, not one line".to_string(),
+        ));
+    }
+    if needs_database_file(&args.command)
+        || matches!(
+            args.command,
+            Commands::Restore { .. } | Commands::Daemon { .. }
+        )
+    {
+        return Some(Err(format!("{} cannot run in a batch", name)));
+    }
+
+    Some(Ok(args))
+}
+
+/// How far the triage `defer` action pushes a task's due date.
+const DEFER_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TriageAction {
+    Done,
+    Delete,
+    Defer,
+    SetDue,
+    SetPriority,
+    Skip,
+    Quit,
+}
+
+impl TriageAction {
+    fn from_key(key: char) -> Option<Self> {
+        match key.to_ascii_lowercase() {
+            'd' => Some(Self::Done),
+            'x' => Some(Self::Delete),
+            'f' => Some(Self::Defer),
+            'u' => Some(Self::SetDue),
+            'p' => Some(Self::SetPriority),
+            's' => Some(Self::Skip),
+            'q' => Some(Self::Quit),
+            _ => None,
+        }
+    }
+}
+
+fn handle_triage(db_manager: &mut file_management::DatabaseManager) {
+    let tasks = match db_manager.get_active_tasks() {
+        Ok(tasks) => tasks,
+        Err(_) => {
+            println!("Failed to retrieve tasks");
+            return;
+        }
+    };
+
+    let todo: Vec<Task> = tasks
+        .iter()
+        .filter(|t| t.state() == TaskState::Todo)
+        .cloned()
+        .collect();
+
+    if todo.is_empty() {
+        println!("No tasks to triage");
+        return;
+    }
+
+    let total = todo.len();
+    let mut triaged = 0;
+
+    'tasks: for (index, task) in todo.iter().enumerate() {
+        reload_external_changes(db_manager);
+        let Some(task) = db_manager.get_task(task.id()).cloned() else {
+            println!("Task {} was deleted elsewhere, skipping", task.short_id());
+            continue;
+        };
+
+        println!("------------------ {}/{}", index + 1, total);
+        println!("{}", task);
+        println!("------------------");
+
+        loop {
+            println!(
+                "[d]one, delete [x], de[f]er {} days, set d[u]e, set [p]riority, [s]kip, [q]uit",
+                DEFER_DAYS
+            );
+
+            let Some(key) = read_key() else {
+                break 'tasks;
+            };
+
+            let Some(action) = TriageAction::from_key(key) else {
+                println!("Unknown action: {}", key);
+                continue;
+            };
+
+            let result = match action {
+                TriageAction::Done => db_manager.set_task_state(task.id(), TaskState::Done),
+                TriageAction::Delete => db_manager.delete_task(task.id()),
+                TriageAction::Defer => db_manager
+                    .postpone_task(task.id(), Duration::days(DEFER_DAYS))
+                    .map(|_| ()),
+                TriageAction::SetDue => {
+                    println!("Due date (YYYY-MM-DD, empty to clear):");
+                    let Some(input) = read_line() else {
+                        break 'tasks;
+                    };
+
+                    let input = input.trim();
+                    if input.is_empty() {
+                        db_manager.set_due(task.id(), None)
+                    } else if let Ok(due) = input.parse::<NaiveDate>() {
+                        db_manager.set_due(task.id(), Some(due))
+                    } else {
+                        println!("Invalid date: {}", input);
+                        continue;
+                    }
+                }
+                TriageAction::SetPriority => {
+                    println!("Priority: [l]ow, [m]edium, [h]igh, [n]one");
+                    let priority = match read_key().map(|k| k.to_ascii_lowercase()) {
+                        Some('l') => Some(Priority::Low),
+                        Some('m') => Some(Priority::Medium),
+                        Some('h') => Some(Priority::High),
+                        Some('n') => None,
+                        Some(key) => {
+                            println!("Unknown priority: {}", key);
+                            continue;
+                        }
+                        None => break 'tasks,
+                    };
+                    db_manager.set_priority(task.id(), priority)
+                }
+                TriageAction::Skip => break,
+                TriageAction::Quit => break 'tasks,
+            };
+
+            match result {
+                Ok(()) => triaged += 1,
+                Err(_) => println!("Task not found"),
+            }
+            break;
+        }
+    }
+
+    println!("Triaged {} of {} tasks", triaged, total);
+}
+
+/// Picks up changes made to the database while an interactive command waits
+/// for input, asking which version to keep of tasks edited on both sides.
+fn reload_external_changes(db_manager: &mut file_management::DatabaseManager) {
+    let mut take_theirs = |ours: &Task, theirs: &Task| {
+        println!("Task {} was also changed elsewhere", ours.short_id());
+        println!("Yours:\n{}", ours);
+        println!("Theirs:\n{}", theirs);
+        println!("Keep [m]ine or take [t]heirs?");
+        matches!(read_key().map(|k| k.to_ascii_lowercase()), Some('t'))
+    };
+
+    match db_manager.reload(&mut take_theirs) {
+        Ok(Reload::Unchanged) => {}
+        Ok(Reload::Reloaded) => println!("Database changed elsewhere, reloaded"),
+        Ok(Reload::Merged { conflicts }) => println!(
+            "Database changed elsewhere, merged with your changes ({} conflicts)",
+            conflicts
+        ),
+        Err(e) => println!("Failed to reload database: {}", e),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BulkAction {
+    MarkDone,
+    Retag,
+    Delete,
+}
+
+impl BulkAction {
+    const ALL: [BulkAction; 3] = [BulkAction::MarkDone, BulkAction::Retag, BulkAction::Delete];
+
+    fn label(self) -> &'static str {
+        match self {
+            BulkAction::MarkDone => "Mark done",
+            BulkAction::Retag => "Retag",
+            BulkAction::Delete => "Delete",
+        }
+    }
+}
+
+fn handle_select(db_manager: &mut file_management::DatabaseManager) {
+    let context = db_manager.context().map(str::to_string);
+
+    let tasks: Vec<Task> = match db_manager.get_active_tasks() {
+        Ok(tasks) => tasks
+            .into_iter()
+            .filter(|t| in_context(t, context.as_deref()))
+            .collect(),
+        Err(_) => {
+            println!("Failed to retrieve tasks");
+            return;
+        }
+    };
+
+    if tasks.is_empty() {
+        println!("No tasks found");
+        return;
+    }
+
+    let theme = dialoguer::theme::ColorfulTheme::default();
+    let items: Vec<String> = tasks.iter().map(Task::oneline).collect();
+
+    let selection = match dialoguer::MultiSelect::with_theme(&theme)
+        .with_prompt("Select tasks (space to toggle, enter to confirm)")
+        .items(&items)
+        .interact_opt()
+    {
+        Ok(Some(selection)) if !selection.is_empty() => selection,
+        Ok(_) => {
+            println!("Nothing selected");
+            return;
+        }
+        Err(e) => {
+            println!("Failed to show selection: {}", e);
+            return;
+        }
+    };
+
+    let labels: Vec<&str> = BulkAction::ALL.iter().map(|a| a.label()).collect();
+    let action = match dialoguer::Select::with_theme(&theme)
+        .with_prompt("Action")
+        .items(&labels)
+        .default(0)
+        .interact_opt()
+    {
+        Ok(Some(index)) => BulkAction::ALL[index],
+        Ok(None) => {
+            println!("Cancelled");
+            return;
+        }
+        Err(e) => {
+            println!("Failed to show actions: {}", e);
+            return;
+        }
+    };
+
+    let tags = if action == BulkAction::Retag {
+        match dialoguer::Input::<String>::with_theme(&theme)
+            .with_prompt("Tags (comma-separated, empty to clear)")
+            .allow_empty(true)
+            .interact_text()
+        {
+            Ok(input) => parse_tags(&input),
+            Err(e) => {
+                println!("Failed to read tags: {}", e);
+                return;
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let confirmed = dialoguer::Confirm::with_theme(&theme)
+        .with_prompt(format!("{} {} tasks?", action.label(), selection.len()))
+        .default(false)
+        .interact()
+        .unwrap_or(false);
+
+    if !confirmed {
+        println!("Cancelled");
+        return;
+    }
+
+    db_manager.begin();
+
+    let mut applied = 0;
+    for task in selection.iter().map(|&index| &tasks[index]) {
+        let result = match action {
+            BulkAction::MarkDone => db_manager.set_task_state(task.id(), TaskState::Done),
+            BulkAction::Retag => db_manager.set_tags(task.id(), &tags),
+            BulkAction::Delete => db_manager.delete_task(task.id()),
+        };
+
+        if result.is_ok() {
+            applied += 1;
+        }
+    }
+
+    if let Err(e) = db_manager.commit() {
+        println!("Failed to save changes: {}", e);
+        return;
+    }
+
+    println!("{}: {} tasks updated", action.label(), applied);
+}
+
+/// Splits comma-separated tags, dropping blanks and surrounding whitespace.
+fn parse_tags(input: &str) -> Vec<String> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads a single key press when attached to a terminal, falling back to the
+/// first character of a line when input is piped.
+fn read_key() -> Option<char> {
+    if std::io::stdin().is_terminal() {
+        return console::Term::stdout().read_char().ok();
+    }
+
+    read_line().and_then(|line| line.trim().chars().next())
+}
+
+fn confirm(question: &str) -> bool {
+    println!("{} [y/N]", question);
+
+    read_yes()
+}
+
+fn read_yes() -> bool {
+    read_line().is_some_and(|line| matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn read_line() -> Option<String> {
+    let mut line = String::new();
+
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_commands_against_memory_database() {
+        let paths = AppPaths::new(std::path::Path::new(""));
+        let config = Config::default();
+        let mut db_manager =
+            file_management::DatabaseManager::with_storage(Box::new(storage::MemoryStorage::new()))
+                .unwrap();
+        let mut run = |args: &[&str]| {
+            let args = Args::parse_from(std::iter::once("to-not-do").chain(args.iter().copied()));
+            handle_commands(args, &config, &paths, &mut db_manager)
+        };
+
+        assert_eq!(run(&["add", "Write the release notes"]), ExitCode::SUCCESS);
+        assert_eq!(run(&["add", "Tag the release"]), ExitCode::SUCCESS);
+        assert_eq!(run(&["done", "release notes"]), ExitCode::SUCCESS);
+        assert_eq!(run(&["search", "release"]), ExitCode::SUCCESS);
+        assert_eq!(run(&["search", "changelog"]), ExitCode::FAILURE);
+
+        let tasks = db_manager.get_tasks().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].state(), TaskState::Done);
+        assert_eq!(tasks[1].state(), TaskState::Todo);
+    }
+
+    #[test]
+    fn test_add_command() {
+        let args = Args::parse_from(["to-not-do", "add", "Test task"]);
+        if let Commands::Add {
+            task_description, ..
+        } = args.command
+        {
+            assert_eq!(task_description.as_deref(), Some("Test task"));
+        } else {
+            panic!("Expected Add command");
+        }
+    }
+
+    #[test]
+    fn test_add_from_clipboard_command() {
+        let args = Args::parse_from(["to-not-do", "add", "--from-clipboard"]);
+        if let Commands::Add {
+            task_description,
+            from_clipboard,
+            ..
+        } = args.command
+        {
+            assert!(from_clipboard);
+            assert_eq!(task_description, None);
+        } else {
+            panic!("Expected Add command");
+        }
+
+        assert!(Args::try_parse_from(["to-not-do", "add"]).is_err());
+    }
+
+    #[test]
+    fn test_split_description_and_notes() {
+        assert_eq!(
+            split_description_and_notes("\n  Buy milk  \n\nsemi-skimmed\n2 litres\n"),
+            Some((
+                "Buy milk".to_string(),
+                Some("semi-skimmed\n2 litres".to_string())
+            ))
+        );
+        assert_eq!(
+            split_description_and_notes("Single line"),
+            Some(("Single line".to_string(), None))
+        );
+        assert_eq!(split_description_and_notes(" \n "), None);
+    }
+
+    #[test]
+    fn test_update_command() {
+        let task_id = Uuid::new_v4();
+        let args = Args::parse_from(["to-not-do", "update", &task_id.to_string(), "Updated task"]);
+        if let Commands::Update {
+            task_id: id,
+            task_description,
+        } = args.command
+        {
+            assert_eq!(id, task_id);
+            assert_eq!(task_description, "Updated task");
+        } else {
+            panic!("Expected Update command");
+        }
+    }
+
+    #[test]
+    fn test_delete_command() {
+        let task_id = Uuid::new_v4();
+        let args = Args::parse_from(["to-not-do", "delete", &task_id.to_string()]);
+        if let Commands::Delete { task_id: id } = args.command {
+            assert_eq!(id, task_id);
+        } else {
+            panic!("Expected Delete command");
+        }
+    }
+
+    #[test]
+    fn test_list_command_with_filter() {
+        let args = Args::parse_from(["to-not-do", "list", "done"]);
+        if let Commands::List { filter, .. } = args.command {
+            assert_eq!(filter, Some(TaskState::Done));
+        } else {
+            panic!("Expected List command with filter");
+        }
+    }
+
+    #[test]
+    fn test_list_command_without_filter() {
+        let args = Args::parse_from(["to-not-do", "list"]);
+        if let Commands::List { filter, .. } = args.command {
+            assert_eq!(filter, None);
+        } else {
+            panic!("Expected List command without filter");
+        }
+    }
+
+    #[test]
+    fn test_list_command_with_columns() {
+        let args = Args::parse_from(["to-not-do", "list", "--columns", "id,desc,due,tags"]);
+        if let Commands::List { columns, .. } = args.command {
+            assert_eq!(
+                columns,
+                Some(vec![Column::Id, Column::Desc, Column::Due, Column::Tags])
+            );
+        } else {
+            panic!("Expected List command with columns");
+        }
+
+        assert!(Args::try_parse_from(["to-not-do", "list", "--columns", "id,bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_write_tasks() {
+        let tasks = [Task::new("First"), Task::new("Second")];
+
+        let mut out = Vec::new();
+        assert_eq!(
+            write_tasks(&mut out, &tasks, &ListFormat::OneLine).unwrap(),
+            2
+        );
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+
+        let mut out = Vec::new();
+        assert_eq!(write_tasks(&mut out, [], &ListFormat::Detailed).unwrap(), 0);
+        assert!(out.is_empty());
+
+        // Stops at the first failed write.
+        let mut closed: &mut [u8] = &mut [];
+        let written = write_tasks(&mut closed, &tasks, &ListFormat::OneLine);
+        assert_eq!(written.unwrap_err().kind(), std::io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn test_render_table() {
+        let long_description = "x".repeat(60);
+        let short = Task::new("Short").with_tags(&["home".to_string()]);
+        let long = Task::new(&long_description);
+
+        let lines = render_table(&[&short, &long], &[Column::Desc, Column::Tags]);
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("DESCRIPTION"));
+        assert!(lines[0].ends_with("TAGS"));
+        assert!(lines[1].starts_with("Short "));
+        assert!(lines[1].ends_with("home"));
+        assert_eq!(lines[2].chars().count(), Column::Desc.max_width());
+        assert!(lines[2].ends_with('…'));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly10!", 10), "exactly10!");
+        assert_eq!(truncate("much too long", 5), "much…");
+    }
+
+    #[test]
+    fn test_list_command_with_tree() {
+        let args = Args::parse_from(["to-not-do", "list", "--tree", "--all"]);
+        if let Commands::List { tree, all, .. } = args.command {
+            assert!(tree);
+            assert!(all);
+        } else {
+            panic!("Expected List command with tree");
+        }
+
+        assert!(Args::try_parse_from(["to-not-do", "list", "--all"]).is_err());
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let root = Task::new("Root");
+        let first = Task::new("First").with_parent(root.id());
+        let grandchild = Task::new("Grandchild").with_parent(first.id());
+        let second = Task::new("Second").with_parent(root.id());
+        let tasks = vec![root, first, grandchild, second];
+
+        let lines = render_tree(&tasks, false);
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("[ ] Root ("));
+        assert!(lines[1].starts_with("├── [ ] First ("));
+        assert!(lines[2].starts_with("│   └── [ ] Grandchild ("));
+        assert!(lines[3].starts_with("└── [ ] Second ("));
+    }
+
+    #[test]
+    fn test_render_tree_shows_parent_loops_once() {
+        let mut first = Task::new("First");
+        let second = Task::new("Second").with_parent(first.id());
+        first = first.with_parent(second.id());
+        let own = Task::new("Own parent");
+        let own = own.clone().with_parent(own.id());
+        let tasks = vec![first, second, own];
+
+        let lines = render_tree(&tasks, false);
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("[ ] First ("));
+        assert!(lines[1].starts_with("└── [ ] Second ("));
+        assert!(lines[2].starts_with("[ ] Own parent ("));
+    }
+
+    #[test]
+    fn test_render_tree_collapses_done_subtrees() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db_manager =
+            file_management::DatabaseManager::open(&dir.path().join("tasks.json")).unwrap();
+
+        let parent = Task::new("Parent");
+        let child = Task::new("Child").with_parent(parent.id());
+        for task in [&parent, &child] {
+            db_manager.add_task(task).unwrap();
+            db_manager
+                .set_task_state(task.id(), TaskState::Done)
+                .unwrap();
+        }
+        let tasks = db_manager.get_tasks().unwrap();
+
+        let collapsed = render_tree(tasks, false);
+        assert_eq!(collapsed.len(), 1);
+        assert!(collapsed[0].ends_with("[+1 done]"));
+
+        assert_eq!(render_tree(tasks, true).len(), 2);
+    }
+
+    #[test]
+    fn test_project_list_command() {
+        let args = Args::parse_from(["to-not-do", "project", "list"]);
+        assert!(matches!(
+            args.command,
+            Commands::Project {
+                command: ProjectCommands::List
+            }
+        ));
+
+        let args = Args::parse_from(["to-not-do", "list", "--by-project"]);
+        if let Commands::List { by_project, .. } = args.command {
+            assert!(by_project);
+        } else {
+            panic!("Expected List command grouped by project");
+        }
+    }
+
+    #[test]
+    fn test_prune_command() {
+        let args = Args::parse_from([
+            "to-not-do",
+            "prune",
+            "--older-than",
+            "90d",
+            "--state",
+            "todo",
+            "--yes",
+        ]);
+        if let Commands::Prune {
+            older_than,
+            state,
+            delete,
+            yes,
+        } = args.command
+        {
+            assert_eq!(older_than, Duration::days(90));
+            assert_eq!(state, Some(TaskState::Todo));
+            assert!(!delete);
+            assert!(yes);
+        } else {
+            panic!("Expected Prune command");
+        }
+
+        assert!(Args::try_parse_from(["to-not-do", "prune"]).is_err());
+    }
+
+    #[test]
+    fn test_list_command_oneline() {
+        let args = Args::parse_from(["to-not-do", "list", "--oneline", "todo"]);
+        if let Commands::List {
+            oneline, filter, ..
+        } = args.command
+        {
+            assert!(oneline);
+            assert_eq!(filter, Some(TaskState::Todo));
+        } else {
+            panic!("Expected List command");
+        }
+
+        assert!(
+            Args::try_parse_from(["to-not-do", "list", "--oneline", "--columns", "id"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_list_command_by() {
+        let args = Args::parse_from(["to-not-do", "list", "--by", "sam", "done"]);
+        if let Commands::List { by, filter, .. } = args.command {
+            assert_eq!(by.as_deref(), Some("sam"));
+            assert_eq!(filter, Some(TaskState::Done));
         } else {
-            panic!("Expected Delete command");
+            panic!("Expected List command");
         }
     }
 
     #[test]
-    fn test_list_command_with_filter() {
-        let args = Args::parse_from(["to-not-do", "list", "done"]);
-        if let Commands::List { filter } = args.command {
-            assert_eq!(filter, Some(TaskState::Done));
+    fn test_context_command() {
+        let args = Args::parse_from(["to-not-do", "context", "use", "work"]);
+        if let Commands::Context {
+            command: Some(ContextCommands::Use { project }),
+        } = args.command
+        {
+            assert_eq!(project, "work");
         } else {
-            panic!("Expected List command with filter");
+            panic!("Expected Context use command");
         }
+
+        let args = Args::parse_from(["to-not-do", "context", "clear"]);
+        assert!(matches!(
+            args.command,
+            Commands::Context {
+                command: Some(ContextCommands::Clear)
+            }
+        ));
+
+        let args = Args::parse_from(["to-not-do", "context"]);
+        assert!(matches!(args.command, Commands::Context { command: None }));
     }
 
     #[test]
-    fn test_list_command_without_filter() {
-        let args = Args::parse_from(["to-not-do", "list"]);
-        if let Commands::List { filter } = args.command {
-            assert_eq!(filter, None);
+    fn test_in_context() {
+        let work = Task::new("Work task").with_project("work");
+        let loose = Task::new("Loose task");
+
+        assert!(in_context(&work, None));
+        assert!(in_context(&loose, None));
+        assert!(in_context(&work, Some("work")));
+        assert!(!in_context(&loose, Some("work")));
+        assert!(!in_context(&work, Some("home")));
+    }
+
+    #[test]
+    fn test_select_command() {
+        let args = Args::parse_from(["to-not-do", "select"]);
+        assert!(matches!(args.command, Commands::Select));
+    }
+
+    #[test]
+    fn test_export_and_import_commands() {
+        let args = Args::parse_from(["to-not-do", "export", "--format", "dump"]);
+        assert!(matches!(
+            args.command,
+            Commands::Export {
+                format: ExportFormat::Dump,
+                output: None,
+                archive: None,
+                encrypt: false,
+            }
+        ));
+
+        let args = Args::parse_from(["to-not-do", "import", "tasks.dump", "--yes"]);
+        match args.command {
+            Commands::Import {
+                file,
+                archive,
+                from,
+                jql,
+                format,
+                yes,
+            } => {
+                assert_eq!(file, Some(PathBuf::from("tasks.dump")));
+                assert_eq!(archive, None);
+                assert_eq!(from, None);
+                assert_eq!(jql, None);
+                assert_eq!(format, ExportFormat::Dump);
+                assert!(yes);
+            }
+            _ => panic!("Expected Import command"),
+        }
+
+        let args = Args::parse_from(["to-not-do", "export", "--archive", "out.tnd", "--encrypt"]);
+        assert!(matches!(
+            args.command,
+            Commands::Export {
+                archive: Some(_),
+                encrypt: true,
+                ..
+            }
+        ));
+        assert!(Args::try_parse_from(["to-not-do", "export", "--encrypt"]).is_err());
+        assert!(Args::try_parse_from([
+            "to-not-do",
+            "export",
+            "--archive",
+            "out.tnd",
+            "--format",
+            "taskwarrior"
+        ])
+        .is_err());
+        assert!(Args::try_parse_from(["to-not-do", "import"]).is_err());
+        assert!(Args::try_parse_from(["to-not-do", "import", "--archive", "out.tnd"]).is_ok());
+        assert!(matches!(
+            Args::parse_from(["to-not-do", "import", "--from", "todoist"]).command,
+            Commands::Import {
+                from: Some(ImportSource::Todoist),
+                ..
+            }
+        ));
+        assert!(
+            Args::try_parse_from(["to-not-do", "import", "x.dump", "--from", "todoist"]).is_err()
+        );
+        assert!(matches!(
+            Args::parse_from([
+                "to-not-do",
+                "import",
+                "--from",
+                "jira",
+                "--jql",
+                "project = OPS"
+            ])
+            .command,
+            Commands::Import {
+                from: Some(ImportSource::Jira),
+                jql: Some(_),
+                ..
+            }
+        ));
+        assert!(Args::try_parse_from(["to-not-do", "import", "--jql", "project = OPS"]).is_err());
+        assert!(matches!(
+            Args::parse_from(["to-not-do", "import", "--from", "microsoft-todo"]).command,
+            Commands::Import {
+                from: Some(ImportSource::MicrosoftTodo),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_backup_command() {
+        let args = Args::parse_from(["to-not-do", "backup"]);
+        assert!(matches!(args.command, Commands::Backup { output: None }));
+
+        let args = Args::parse_from(["to-not-do", "backup", "-o", "/tmp/backup.json"]);
+        match args.command {
+            Commands::Backup { output } => {
+                assert_eq!(output, Some(PathBuf::from("/tmp/backup.json")))
+            }
+            _ => panic!("Expected Backup command"),
+        }
+    }
+
+    #[test]
+    fn test_restore_command() {
+        let args = Args::parse_from(["to-not-do", "restore", "backup.json", "--merge"]);
+        match args.command {
+            Commands::Restore {
+                backup_file,
+                merge,
+                yes,
+            } => {
+                assert_eq!(backup_file, PathBuf::from("backup.json"));
+                assert!(merge);
+                assert!(!yes);
+            }
+            _ => panic!("Expected Restore command"),
+        }
+
+        assert!(Args::try_parse_from(["to-not-do", "restore"]).is_err());
+    }
+
+    #[test]
+    fn test_lock_and_unlock_commands() {
+        let args = Args::parse_from(["to-not-do", "unlock"]);
+        assert!(matches!(args.command, Commands::Unlock));
+
+        let args = Args::parse_from(["to-not-do", "lock"]);
+        assert!(matches!(args.command, Commands::Lock));
+    }
+
+    #[test]
+    fn test_convert_command() {
+        let args = Args::parse_from([
+            "to-not-do",
+            "convert",
+            "tasks.json",
+            "tasks.bin",
+            "--format",
+            "msgpack",
+        ]);
+        match args.command {
+            Commands::Convert {
+                input,
+                output,
+                format,
+            } => {
+                assert_eq!(input, PathBuf::from("tasks.json"));
+                assert_eq!(output, PathBuf::from("tasks.bin"));
+                assert_eq!(format, Some(Format::MessagePack));
+            }
+            _ => panic!("Expected Convert command"),
+        }
+    }
+
+    #[test]
+    fn test_compact_command() {
+        let args = Args::parse_from(["to-not-do", "compact"]);
+        assert!(matches!(args.command, Commands::Compact));
+    }
+
+    #[test]
+    fn test_purge_command() {
+        let args = Args::parse_from(["to-not-do", "purge", "--expired", "-y"]);
+        assert!(matches!(
+            args.command,
+            Commands::Purge {
+                expired: true,
+                yes: true
+            }
+        ));
+        assert!(Args::try_parse_from(["to-not-do", "purge"]).is_err());
+    }
+
+    #[test]
+    fn test_webhook_test_command() {
+        let args = Args::parse_from(["to-not-do", "webhook", "test", "--event", "overdue"]);
+        assert!(matches!(
+            args.command,
+            Commands::Webhook {
+                command: WebhookCommands::Test {
+                    event: WebhookEvent::Overdue,
+                    url: None
+                }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_rpc_command() {
+        let args = Args::parse_from(["to-not-do", "rpc"]);
+        assert!(matches!(args.command, Commands::Rpc));
+    }
+
+    #[test]
+    fn test_mcp_command() {
+        let args = Args::parse_from(["to-not-do", "mcp"]);
+        assert!(matches!(args.command, Commands::Mcp));
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn test_script_commands() {
+        let args = Args::parse_from(["to-not-do", "script", "run", "tidy.lua", "--dry-run"]);
+        match args.command {
+            Commands::Script {
+                command: ScriptCommands::Run { file, args },
+            } => {
+                assert_eq!(file, PathBuf::from("tidy.lua"));
+                assert_eq!(args, ["--dry-run"]);
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        let args = Args::parse_from(["to-not-do", "script", "standup", "3"]);
+        match args.command {
+            Commands::Script {
+                command: ScriptCommands::Named(words),
+            } => assert_eq!(words, ["standup", "3"]),
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_digest_command() {
+        let args = Args::parse_from(["to-not-do", "digest", "--period", "weekly", "--email"]);
+        assert!(matches!(
+            args.command,
+            Commands::Digest {
+                period: DigestPeriod::Weekly,
+                email: true,
+                stdout: false
+            }
+        ));
+        assert!(Args::try_parse_from(["to-not-do", "digest", "--email", "--stdout"]).is_err());
+    }
+
+    #[test]
+    fn test_generate_systemd_command() {
+        let args = Args::parse_from(["to-not-do", "generate", "systemd"]);
+        match args.command {
+            Commands::Generate {
+                command: GenerateCommands::Systemd { tick, output_dir },
+            } => {
+                assert_eq!(tick, "hourly");
+                assert_eq!(output_dir, None);
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_daemon_command() {
+        let args = Args::parse_from(["to-not-do", "daemon"]);
+        assert!(matches!(
+            args.command,
+            Commands::Daemon {
+                every: 60,
+                socket: None,
+                no_socket: false
+            }
+        ));
+
+        let args = Args::parse_from(["to-not-do", "daemon", "--every", "5"]);
+        assert!(matches!(args.command, Commands::Daemon { every: 5, .. }));
+        assert!(Args::try_parse_from(["to-not-do", "daemon", "--every", "0"]).is_err());
+
+        let args = Args::parse_from(["to-not-do", "daemon", "--socket", "/tmp/t.sock"]);
+        match args.command {
+            Commands::Daemon { socket, .. } => {
+                assert_eq!(socket.as_deref(), Some(Path::new("/tmp/t.sock")))
+            }
+            _ => panic!("Expected Daemon command"),
+        }
+        assert!(
+            Args::try_parse_from(["to-not-do", "daemon", "--socket", "t.sock", "--no-socket"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_events_command() {
+        let args = Args::parse_from(["to-not-do", "events", "-f"]);
+        assert!(matches!(args.command, Commands::Events { follow: true }));
+        let args = Args::parse_from(["to-not-do", "events"]);
+        assert!(matches!(args.command, Commands::Events { follow: false }));
+    }
+
+    #[test]
+    fn test_publish_command() {
+        let args = Args::parse_from([
+            "to-not-do",
+            "publish",
+            "--output",
+            "site/",
+            "--exclude-private",
+        ]);
+        match args.command {
+            Commands::Publish {
+                output,
+                exclude_private,
+                title,
+            } => {
+                assert_eq!(output, Path::new("site/"));
+                assert!(exclude_private);
+                assert_eq!(title, None);
+            }
+            _ => panic!("Expected Publish command"),
+        }
+        assert!(Args::try_parse_from(["to-not-do", "publish"]).is_err());
+    }
+
+    #[test]
+    fn test_git_hook_command() {
+        let args = Args::parse_from(["to-not-do", "git-hook", "install"]);
+        match args.command {
+            Commands::GitHook {
+                command: GitHookCommands::Install { repo, force },
+            } => {
+                assert_eq!(repo, Path::new("."));
+                assert!(!force);
+            }
+            _ => panic!("Expected GitHook install command"),
+        }
+        let args = Args::parse_from(["to-not-do", "git-hook", "post-commit"]);
+        assert!(matches!(
+            args.command,
+            Commands::GitHook {
+                command: GitHookCommands::PostCommit
+            }
+        ));
+    }
+
+    #[test]
+    fn test_bot_command() {
+        let args = Args::parse_from(["to-not-do", "bot", "telegram"]);
+        assert!(matches!(
+            args.command,
+            Commands::Bot {
+                command: BotCommands::Telegram
+            }
+        ));
+        let args = Args::parse_from(["to-not-do", "bot", "matrix"]);
+        assert!(matches!(
+            args.command,
+            Commands::Bot {
+                command: BotCommands::Matrix
+            }
+        ));
+        assert!(Args::try_parse_from(["to-not-do", "bot"]).is_err());
+    }
+
+    #[cfg(feature = "notifications")]
+    #[test]
+    fn test_notify_daemon_command() {
+        let args = Args::parse_from(["to-not-do", "notify-daemon"]);
+        assert!(matches!(args.command, Commands::NotifyDaemon));
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_plugin_commands() {
+        let args = Args::parse_from(["to-not-do", "greet", "--loud", "world"]);
+        match args.command {
+            Commands::External(args) => assert_eq!(args, ["greet", "--loud", "world"]),
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        let args = Args::parse_from(["to-not-do", "list", "--plugin-filter", "stale"]);
+        if let Commands::List { plugin_filter, .. } = args.command {
+            assert_eq!(plugin_filter.as_deref(), Some("stale"));
         } else {
-            panic!("Expected List command without filter");
+            panic!("Expected List command");
+        }
+    }
+
+    #[test]
+    fn test_db_stats_command() {
+        let args = Args::parse_from(["to-not-do", "db", "stats"]);
+        assert!(matches!(
+            args.command,
+            Commands::Db {
+                command: DbCommands::Stats
+            }
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_commands() {
+        let args = Args::parse_from(["to-not-do", "snapshot", "create", "before-edit"]);
+        assert!(matches!(
+            args.command,
+            Commands::Snapshot {
+                command: SnapshotCommands::Create { name: Some(name) }
+            } if name == "before-edit"
+        ));
+
+        let args = Args::parse_from(["to-not-do", "snapshot", "restore", "before-edit", "-y"]);
+        assert!(matches!(
+            args.command,
+            Commands::Snapshot {
+                command: SnapshotCommands::Restore { yes: true, .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_verify_command() {
+        let args = Args::parse_from(["to-not-do", "verify", "--fix"]);
+        assert!(matches!(args.command, Commands::Verify { fix: true }));
+    }
+
+    #[test]
+    fn test_read_only_flag() {
+        let args = Args::parse_from(["to-not-do", "list", "--read-only"]);
+        assert!(args.read_only);
+        assert!(!writes_database_file(&args.command));
+
+        let args = Args::parse_from(["to-not-do", "--read-only", "verify", "--fix"]);
+        assert!(writes_database_file(&args.command));
+        assert!(!Args::parse_from(["to-not-do", "list"]).read_only);
+    }
+
+    #[test]
+    fn test_repair_command() {
+        let args = Args::parse_from(["to-not-do", "repair"]);
+        assert!(matches!(args.command, Commands::Repair));
+    }
+
+    #[test]
+    fn test_sync_and_serve_commands() {
+        let args = Args::parse_from(["to-not-do", "sync", "--remote", "http://nas:8080"]);
+        match args.command {
+            Commands::Sync {
+                remote,
+                token,
+                service,
+            } => {
+                assert_eq!(remote.as_deref(), Some("http://nas:8080"));
+                assert_eq!(token, None);
+                assert!(service.is_none());
+            }
+            _ => panic!("Expected Sync command"),
+        }
+
+        let args = Args::parse_from(["to-not-do", "sync", "todoist", "--prefer", "theirs"]);
+        assert!(matches!(
+            args.command,
+            Commands::Sync {
+                service: Some(SyncService::Todoist {
+                    prefer: MergePreference::Theirs
+                }),
+                ..
+            }
+        ));
+        assert!(Args::try_parse_from(["to-not-do", "sync", "--remote", "x", "todoist"]).is_err());
+
+        let args = Args::parse_from(["to-not-do", "sync", "github", "--repo", "octo/tools"]);
+        match args.command {
+            Commands::Sync {
+                service:
+                    Some(SyncService::Github {
+                        repo,
+                        close,
+                        save_token,
+                    }),
+                ..
+            } => {
+                assert_eq!(repo, "octo/tools");
+                assert!(!close && !save_token);
+            }
+            _ => panic!("Expected Sync github command"),
+        }
+
+        let args = Args::parse_from(["to-not-do", "sync", "markdown", "--vault", "notes"]);
+        match args.command {
+            Commands::Sync {
+                service: Some(SyncService::Markdown { vault, prefer }),
+                ..
+            } => {
+                assert_eq!(vault.as_deref(), Some(Path::new("notes")));
+                assert_eq!(prefer, MergePreference::Ours);
+            }
+            _ => panic!("Expected Sync markdown command"),
+        }
+
+        let args = Args::parse_from(["to-not-do", "sync", "gcal", "--login"]);
+        assert!(matches!(
+            args.command,
+            Commands::Sync {
+                service: Some(SyncService::Gcal {
+                    prefer: MergePreference::Ours,
+                    login: true
+                }),
+                ..
+            }
+        ));
+
+        let args = Args::parse_from(["to-not-do", "sync", "status"]);
+        assert!(matches!(
+            args.command,
+            Commands::Sync {
+                service: Some(SyncService::Status),
+                ..
+            }
+        ));
+        let args = Args::parse_from(["to-not-do", "sync", "resolve", "--prefer", "newest"]);
+        assert!(matches!(
+            args.command,
+            Commands::Sync {
+                service: Some(SyncService::Resolve {
+                    prefer: Some(MergePreference::Newest)
+                }),
+                ..
+            }
+        ));
+
+        let args = Args::parse_from(["to-not-do", "serve", "--port", "9000", "--token", "t"]);
+        match args.command {
+            Commands::Serve {
+                port,
+                bind,
+                token,
+                new_feed_token,
+            } => {
+                assert!(!new_feed_token);
+                assert_eq!(port, 9000);
+                assert!(bind.is_loopback());
+                assert_eq!(token.as_deref(), Some("t"));
+            }
+            _ => panic!("Expected Serve command"),
+        }
+    }
+
+    #[test]
+    fn test_db_flag() {
+        let args = Args::parse_from(["to-not-do", "--db", "work.json", "list"]);
+        assert_eq!(args.db, Some(PathBuf::from("work.json")));
+
+        let args = Args::parse_from(["to-not-do", "list", "--db", "work.json"]);
+        assert_eq!(args.db, Some(PathBuf::from("work.json")));
+
+        assert_eq!(Args::parse_from(["to-not-do", "list"]).db, None);
+    }
+
+    #[test]
+    fn test_profile_commands() {
+        let args = Args::parse_from(["to-not-do", "profile", "create", "work"]);
+        match args.command {
+            Commands::Profile {
+                command: ProfileCommands::Create { name },
+            } => assert_eq!(name, "work"),
+            _ => panic!("Expected profile create command"),
+        }
+
+        let args = Args::parse_from(["to-not-do", "--profile", "work", "list"]);
+        assert_eq!(args.profile.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn test_init_command() {
+        let args = Args::parse_from(["to-not-do", "init", "--local"]);
+        assert!(matches!(args.command, Commands::Init { local: true }));
+
+        let args = Args::parse_from(["to-not-do", "init"]);
+        assert!(matches!(args.command, Commands::Init { local: false }));
+    }
+
+    #[test]
+    fn test_merge_command() {
+        let args = Args::parse_from(["to-not-do", "merge", "other.json", "--prefer", "newest"]);
+        match args.command {
+            Commands::Merge { other_db, prefer } => {
+                assert_eq!(other_db, PathBuf::from("other.json"));
+                assert_eq!(prefer, Some(MergePreference::Newest));
+            }
+            _ => panic!("Expected Merge command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags(" home, errands ,,"), vec!["home", "errands"]);
+        assert!(parse_tags("  ").is_empty());
+    }
+
+    #[test]
+    fn test_search_command() {
+        let args = Args::parse_from(["to-not-do", "search", "milk", "--count"]);
+        if let Commands::Search { query, count } = args.command {
+            assert_eq!(query, "milk");
+            assert!(count);
+        } else {
+            panic!("Expected Search command");
         }
     }
 
+    #[test]
+    fn test_find_matches() {
+        assert_eq!(find_matches("Buy milk, MILK!", "milk"), vec![4..8, 10..14]);
+        assert_eq!(find_matches("Café au lait", "CAFÉ"), vec![0..5]);
+        assert_eq!(find_matches("aaa", "aa"), vec![0..2]);
+        assert!(find_matches("Buy bread", "milk").is_empty());
+        assert!(find_matches("Buy bread", "").is_empty());
+    }
+
+    #[test]
+    fn test_highlight() {
+        console::set_colors_enabled(true);
+        let highlighted = highlight("Buy milk", "MILK");
+        assert!(highlighted.starts_with("Buy \u{1b}["));
+        assert!(highlighted.contains("milk"));
+
+        console::set_colors_enabled(false);
+        assert_eq!(highlight("Buy milk", "MILK"), "Buy milk");
+    }
+
+    #[test]
+    fn test_uri_commands() {
+        let task_id = Uuid::new_v4();
+
+        let args = Args::parse_from(["to-not-do", "link-uri", &task_id.to_string()]);
+        assert!(matches!(args.command, Commands::LinkUri { task_id: id } if id == task_id));
+
+        let link = uri::task_uri(task_id);
+        let args = Args::parse_from(["to-not-do", "handle-uri", &link]);
+        assert!(matches!(args.command, Commands::HandleUri { uri } if uri == link));
+
+        let args = Args::parse_from(["to-not-do", "show", &task_id.to_string()]);
+        assert!(matches!(args.command, Commands::Show { task_id: id } if id == task_id));
+    }
+
     #[test]
     fn test_mark_done_command() {
         let task_id = Uuid::new_v4();
@@ -217,6 +5849,166 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_status_command() {
+        let args = Args::parse_from(["to-not-do", "status"]);
+        assert!(matches!(
+            args.command,
+            Commands::Status {
+                format: None,
+                tmux: false
+            }
+        ));
+        let args = Args::parse_from(["to-not-do", "status", "--tmux"]);
+        assert!(matches!(args.command, Commands::Status { tmux: true, .. }));
+        let args = Args::parse_from(["to-not-do", "status", "--format", "waybar"]);
+        assert!(matches!(
+            args.command,
+            Commands::Status {
+                format: Some(Bar::Waybar),
+                ..
+            }
+        ));
+        assert!(
+            Args::try_parse_from(["to-not-do", "status", "--tmux", "--format", "polybar"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_focus_command() {
+        let task_id = Uuid::new_v4();
+        let args = Args::parse_from(["to-not-do", "focus", &task_id.to_string()]);
+        if let Commands::Focus { task_id: id, clear } = args.command {
+            assert_eq!(id, Some(task_id));
+            assert!(!clear);
+        } else {
+            panic!("Expected Focus command");
+        }
+
+        let args = Args::parse_from(["to-not-do", "focus"]);
+        if let Commands::Focus { task_id, clear } = args.command {
+            assert_eq!(task_id, None);
+            assert!(!clear);
+        } else {
+            panic!("Expected Focus command");
+        }
+    }
+
+    #[test]
+    fn test_parse_batch_line() {
+        assert!(parse_batch_line("").is_none());
+        assert!(parse_batch_line("  # a comment").is_none());
+
+        let args = parse_batch_line(r#"add "Buy milk""#).unwrap().unwrap();
+        if let Commands::Add {
+            task_description, ..
+        } = args.command
+        {
+            assert_eq!(task_description.as_deref(), Some("Buy milk"));
+        } else {
+            panic!("Expected Add command");
+        }
+
+        assert!(parse_batch_line(r#"add "Buy milk"#).unwrap().is_err());
+        assert!(parse_batch_line("frobnicate").unwrap().is_err());
+        assert!(parse_batch_line("batch").unwrap().is_err());
+
+        assert!(parse_batch_line("--db other.json list").unwrap().is_err());
+        assert!(parse_batch_line("--read-only list").unwrap().is_err());
+        assert!(parse_batch_line("list --profile work").unwrap().is_err());
+        for line in [
+            "repair",
+            "compact",
+            "restore backup.json",
+            "serve",
+            "daemon",
+        ] {
+            let Some(Err(e)) = parse_batch_line(line) else {
+                panic!("{} ran in a batch", line);
+            };
+            assert_eq!(
+                e,
+                format!("{} cannot run in a batch", line.split(' ').next().unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_command_with_due_and_priority() {
+        let args = Args::parse_from([
+            "to-not-do",
+            "add",
+            "Test task",
+            "--due",
+            "2030-01-31",
+            "--priority",
+            "high",
+        ]);
+        if let Commands::Add { due, priority, .. } = args.command {
+            assert_eq!(due, NaiveDate::from_ymd_opt(2030, 1, 31));
+            assert_eq!(priority, Some(Priority::High));
+        } else {
+            panic!("Expected Add command");
+        }
+    }
+
+    #[test]
+    fn test_triage_action_from_key() {
+        assert_eq!(TriageAction::from_key('d'), Some(TriageAction::Done));
+        assert_eq!(TriageAction::from_key('X'), Some(TriageAction::Delete));
+        assert_eq!(TriageAction::from_key('f'), Some(TriageAction::Defer));
+        assert_eq!(TriageAction::from_key('u'), Some(TriageAction::SetDue));
+        assert_eq!(TriageAction::from_key('p'), Some(TriageAction::SetPriority));
+        assert_eq!(TriageAction::from_key('s'), Some(TriageAction::Skip));
+        assert_eq!(TriageAction::from_key('q'), Some(TriageAction::Quit));
+        assert_eq!(TriageAction::from_key('z'), None);
+    }
+
+    #[test]
+    fn test_done_command() {
+        let args = Args::parse_from(["to-not-do", "done", "milk"]);
+        if let Commands::Done { text } = args.command {
+            assert_eq!(text, "milk");
+        } else {
+            panic!("Expected Done command");
+        }
+    }
+
+    #[test]
+    fn test_postpone_command() {
+        let task_id = Uuid::new_v4();
+        let args = Args::parse_from(["to-not-do", "postpone", &task_id.to_string(), "2d"]);
+        if let Commands::Postpone {
+            task_id: id,
+            duration,
+            all_overdue,
+        } = args.command
+        {
+            assert_eq!(id, Some(task_id));
+            assert_eq!(duration, Some(Duration::days(2)));
+            assert_eq!(all_overdue, None);
+        } else {
+            panic!("Expected Postpone command");
+        }
+
+        let args = Args::parse_from(["to-not-do", "postpone", "--all-overdue", "1w"]);
+        if let Commands::Postpone {
+            task_id,
+            duration,
+            all_overdue,
+        } = args.command
+        {
+            assert_eq!(task_id, None);
+            assert_eq!(duration, None);
+            assert_eq!(all_overdue, Some(Duration::weeks(1)));
+        } else {
+            panic!("Expected Postpone command");
+        }
+
+        assert!(Args::try_parse_from(["to-not-do", "postpone"]).is_err());
+        assert!(Args::try_parse_from(["to-not-do", "postpone", "abc", "2x"]).is_err());
+    }
+
     #[test]
     fn test_mark_in_progress_command() {
         let task_id = Uuid::new_v4();
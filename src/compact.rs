@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    checksum,
+    config::{CompactConfig, StorageConfig},
+    error::{DatabaseError, ToNotDoError},
+    file_management::Database,
+    journal::{folding_path, read_events},
+    storage::{file_storage, Storage},
+};
+
+/// What [`compact`] removed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CompactReport {
+    /// Deleted tasks that are no longer remembered.
+    pub deleted: usize,
+    /// History entries folded into summaries.
+    pub history: usize,
+    /// Journal events folded into the database file.
+    pub events: usize,
+    /// Bytes taken by the database and journal before and after.
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// What [`purge_expired`] removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PurgeReport {
+    /// Archived tasks deleted for good.
+    pub archived: usize,
+    /// Deleted tasks that are no longer remembered.
+    pub deleted: usize,
+    /// History entries folded into summaries.
+    pub history: usize,
+}
+
+impl PurgeReport {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The instant `days` days before `now`. A retention longer than the
+/// calendar goes back means nothing is old enough to drop.
+fn days_before(now: DateTime<Utc>, days: u32) -> DateTime<Utc> {
+    now.checked_sub_signed(Duration::days(days.into()))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// When deletions and history stop being kept, as of `now`.
+fn horizons(options: &CompactConfig, now: DateTime<Utc>) -> (DateTime<Utc>, Option<DateTime<Utc>>) {
+    let deleted_before = days_before(now, options.deleted_days);
+    let history_before = (options.history_days > 0).then(|| days_before(now, options.history_days));
+    (deleted_before, history_before)
+}
+
+/// Applies the retention policies in `options` to `db`: archived tasks,
+/// deletions and history older than their horizons are dropped. Purged
+/// archived tasks are deleted like any other, so replicas drop them too.
+pub fn purge_expired(db: &mut Database, options: &CompactConfig) -> PurgeReport {
+    let now = Utc::now();
+
+    let archived = match options.archived_days {
+        0 => 0,
+        days => db.purge_archived(days_before(now, days)),
+    };
+    let (deleted_before, history_before) = horizons(options, now);
+    let (deleted, history) = db.compact(deleted_before, history_before);
+
+    PurgeReport {
+        archived,
+        deleted,
+        history,
+    }
+}
+
+fn size_on_disk(db_file: &Path, journal: &Path) -> u64 {
+    [db_file, journal]
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Rewrites the database at `db_file` as small as it gets: deletions and
+/// history past the horizons in `options` are dropped, its `journal` is
+/// folded into the file, and JSON is written without indentation.
+pub fn compact(
+    db_file: &Path,
+    journal: &Path,
+    storage: &StorageConfig,
+    options: &CompactConfig,
+) -> Result<CompactReport, ToNotDoError> {
+    let size_before = size_on_disk(db_file, journal);
+
+    let mut file = file_storage(db_file, storage).with_minified(true);
+    let mut db = file.load()?;
+
+    let journals = [folding_path(journal), journal.to_path_buf()];
+    let mut events = 0;
+    if storage.journal {
+        for path in &journals {
+            let (journal_events, _) = read_events(path)?;
+            events += journal_events.len();
+            for event in journal_events {
+                db.apply(event.change);
+            }
+        }
+    }
+
+    let (deleted_before, history_before) = horizons(options, Utc::now());
+    let (deleted, history) = db.compact(deleted_before, history_before);
+
+    file.save(&db)?;
+    checksum::record(db_file)?;
+
+    // Replaying the journal over the new file would be harmless, so it is
+    // only removed once the file is safely written.
+    for path in journals
+        .iter()
+        .filter(|path| storage.journal && path.is_file())
+    {
+        std::fs::remove_file(path)
+            .map_err(|e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e)))?;
+    }
+
+    Ok(CompactReport {
+        deleted,
+        history,
+        events,
+        size_before,
+        size_after: size_on_disk(db_file, journal),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_management::{DatabaseManager, Task},
+        journal::journal_path,
+        storage::open_storage,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compact_folds_journal_and_minifies() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let config = StorageConfig {
+            journal: true,
+            ..StorageConfig::default()
+        };
+
+        let mut db_manager =
+            DatabaseManager::with_storage(open_storage(&db_file, &config)).unwrap();
+        let kept = Task::new("Kept");
+        let removed = Task::new("Removed");
+        db_manager.add_task(&kept).unwrap();
+        db_manager.add_task(&removed).unwrap();
+        db_manager.delete_task(removed.id()).unwrap();
+        db_manager
+            .postpone_task(kept.id(), Duration::days(1))
+            .unwrap();
+        db_manager
+            .postpone_task(kept.id(), Duration::days(1))
+            .unwrap();
+
+        let options = CompactConfig {
+            deleted_days: 0,
+            history_days: 0,
+            ..CompactConfig::default()
+        };
+        let report = compact(&db_file, &journal_path(&db_file), &config, &options).unwrap();
+
+        assert_eq!(report.deleted, 1);
+        assert_eq!(report.history, 0);
+        assert_eq!(report.events, 5);
+        assert!(report.size_after < report.size_before);
+        assert!(!journal_path(&db_file).exists());
+        assert!(!std::fs::read(&db_file).unwrap().contains(&b'\n'));
+
+        let db_manager = DatabaseManager::with_storage(open_storage(&db_file, &config)).unwrap();
+        assert_eq!(db_manager.get_task(kept.id()).unwrap().history().len(), 2);
+
+        // Later saves keep the file minified.
+        let mut file = file_storage(&db_file, &config);
+        let db = file.load().unwrap();
+        file.save(&db).unwrap();
+        assert!(!std::fs::read(&db_file).unwrap().contains(&b'\n'));
+    }
+
+    #[test]
+    fn test_purge_with_unbounded_retention() {
+        let dir = tempdir().unwrap();
+        let mut db_manager = DatabaseManager::open(&dir.path().join("tasks.json")).unwrap();
+        let archived = Task::new("Archived");
+        let removed = Task::new("Removed");
+        db_manager.add_task(&archived).unwrap();
+        db_manager.add_task(&removed).unwrap();
+        db_manager.archive_task(archived.id()).unwrap();
+        db_manager.delete_task(removed.id()).unwrap();
+
+        let options = CompactConfig {
+            deleted_days: u32::MAX,
+            history_days: u32::MAX,
+            archived_days: u32::MAX,
+            ..CompactConfig::default()
+        };
+        assert!(db_manager.purge_expired(&options).unwrap().is_empty());
+        assert!(db_manager.get_task(archived.id()).is_some());
+    }
+
+    #[test]
+    fn test_compact_keeps_checksum() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let config = StorageConfig::default();
+
+        let mut db_manager =
+            DatabaseManager::with_storage(open_storage(&db_file, &config)).unwrap();
+        db_manager.add_task(&Task::new("Kept")).unwrap();
+        assert_eq!(checksum::check(&db_file), None);
+
+        compact(
+            &db_file,
+            &journal_path(&db_file),
+            &config,
+            &CompactConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(checksum::check(&db_file), None);
+        let mut db_manager =
+            DatabaseManager::with_storage(open_storage(&db_file, &config)).unwrap();
+        assert_eq!(db_manager.get_tasks().unwrap().len(), 1);
+    }
+}
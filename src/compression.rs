@@ -0,0 +1,74 @@
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Works out how `data` was compressed from its leading magic bytes.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(data, ZSTD_LEVEL),
+        }
+    }
+
+    /// Decompresses `data` with whatever compression it was written with.
+    pub fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match Self::detect(data) {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut out = Vec::new();
+                flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            Compression::Zstd => zstd::decode_all(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let data = br#"{"tasks": [], "name": "to-not-do"}"#.repeat(20);
+
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(&data).unwrap();
+
+            assert_eq!(Compression::detect(&compressed), compression);
+            assert_eq!(Compression::decompress(&compressed).unwrap(), data);
+        }
+
+        assert!(Compression::Zstd.compress(&data).unwrap().len() < data.len());
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    caldav::CalDavConfig, digest::EmailConfig, gcal::GcalConfig, github::GitHubConfig,
+    jira::JiraConfig, matrix::MatrixConfig, microsoft_todo::MicrosoftTodoConfig,
+    telegram::TelegramConfig, todoist::TodoistConfig, webhook::WebhookConfig,
+};
+use crate::{
+    compression::Compression, error::ToNotDoError, format::Format, hooks::HooksConfig,
+    markdown::MarkdownConfig, reminder::NotifyConfig,
+};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Config {
+    /// Profile used when `--profile` is not given.
+    pub default_profile: Option<String>,
+    /// Name recorded as who created, completed or changed tasks, so a
+    /// database shared by several people shows who did what.
+    pub user: Option<String>,
+    pub list: ListConfig,
+    pub storage: StorageConfig,
+    pub compact: CompactConfig,
+    /// URLs notified when tasks are created, completed or, as found by
+    /// `tick`, fall overdue.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub webhooks: Vec<WebhookConfig>,
+    pub hooks: HooksConfig,
+    /// Lua scripts run by `script <name>`.
+    pub scripts: BTreeMap<String, ScriptConfig>,
+    pub notify: NotifyConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub email: EmailConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub caldav: CalDavConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub todoist: TodoistConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub github: GitHubConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub jira: JiraConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub gcal: GcalConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub telegram: TelegramConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub matrix: MatrixConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub microsoft_todo: MicrosoftTodoConfig,
+    pub markdown: MarkdownConfig,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ListConfig {
+    /// Columns shown by `list` when `--columns` is not given.
+    pub columns: Option<Vec<Column>>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Encrypt the database file the next time it is saved.
+    pub encrypt: bool,
+    /// Compression used when saving the database file.
+    pub compression: Compression,
+    /// Format used when saving the database file, instead of the one matching
+    /// its extension.
+    pub format: Option<Format>,
+    /// Record changes in an append-only journal instead of rewriting the
+    /// database file on every save. The journal is plain text, so this
+    /// cannot be combined with `encrypt`.
+    pub journal: bool,
+    /// Log every change to a write-ahead log before saving, so a crash part
+    /// way through a save or batch loses nothing. Like the journal, the log
+    /// is plain text and cannot be combined with `encrypt`.
+    pub wal: bool,
+    /// Keep the tasks of each project in a file of their own, so saving only
+    /// rewrites the projects that changed.
+    pub partition: bool,
+}
+
+/// A `[scripts.<name>]` section, giving a Lua script either inline or as a
+/// file relative to the directory of the main config file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ScriptConfig {
+    /// Shown by `script list`.
+    pub about: String,
+    pub file: Option<PathBuf>,
+    pub source: Option<String>,
+}
+
+/// Retention policies, applied by `compact` and `purge --expired`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CompactConfig {
+    /// Days a deleted task is remembered, so that replicas which have not
+    /// synced since cannot bring it back.
+    pub deleted_days: u32,
+    /// Days of task history kept in full; older entries are folded into one.
+    /// `0` keeps all history.
+    pub history_days: u32,
+    /// Days an archived task is kept before it is deleted for good. `0`
+    /// keeps archived tasks forever.
+    pub archived_days: u32,
+    /// Apply the retention policies every time the database is saved,
+    /// instead of only on `purge --expired`.
+    pub auto: bool,
+}
+
+impl Default for CompactConfig {
+    fn default() -> Self {
+        Self {
+            deleted_days: 90,
+            history_days: 365,
+            archived_days: 0,
+            auto: false,
+        }
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Id,
+    Desc,
+    State,
+    Due,
+    Priority,
+    Tags,
+    Project,
+    Created,
+    Updated,
+}
+
+impl Column {
+    pub fn header(self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Desc => "DESCRIPTION",
+            Column::State => "STATE",
+            Column::Due => "DUE",
+            Column::Priority => "PRIORITY",
+            Column::Tags => "TAGS",
+            Column::Project => "PROJECT",
+            Column::Created => "CREATED",
+            Column::Updated => "UPDATED",
+        }
+    }
+
+    /// Widest a cell in this column may get before it is truncated.
+    pub fn max_width(self) -> usize {
+        match self {
+            Column::Id => 8,
+            Column::Desc => 40,
+            Column::State => 11,
+            Column::Due | Column::Created | Column::Updated => 10,
+            Column::Priority => 8,
+            Column::Tags => 24,
+            Column::Project => 16,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file, falling back to defaults when it does not
+    /// exist.
+    pub fn load(path: &Path) -> Result<Self, ToNotDoError> {
+        Self::from_table(read_table(path)?, path)
+    }
+
+    /// Loads the configuration file at `base` with the settings from
+    /// `overrides` laid on top, table by table.
+    pub fn load_layered(base: &Path, overrides: &Path) -> Result<Self, ToNotDoError> {
+        let mut table = read_table(base)?;
+        merge_tables(&mut table, read_table(overrides)?);
+
+        Self::from_table(table, overrides)
+    }
+
+    fn from_table(table: toml::Table, path: &Path) -> Result<Self, ToNotDoError> {
+        let config: Self = table
+            .try_into()
+            .map_err(|e| ToNotDoError::ConfigError(format!("{}: {}", path.display(), e)))?;
+
+        // The journal and the write-ahead log are appended to in plain text,
+        // which would leak the contents of an encrypted database.
+        if config.storage.encrypt {
+            for (name, enabled) in [
+                ("journal", config.storage.journal),
+                ("wal", config.storage.wal),
+            ] {
+                if enabled {
+                    return Err(ToNotDoError::ConfigError(format!(
+                        "{}: storage.{} cannot be combined with storage.encrypt",
+                        path.display(),
+                        name
+                    )));
+                }
+            }
+        }
+
+        for (name, script) in &config.scripts {
+            if script.file.is_some() == script.source.is_some() {
+                return Err(ToNotDoError::ConfigError(format!(
+                    "{}: scripts.{} needs either a file or a source",
+                    path.display(),
+                    name
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, ToNotDoError> {
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| ToNotDoError::ConfigError(format!("{}: {}", path.display(), e)))?;
+
+    toml::from_str(&content)
+        .map_err(|e| ToNotDoError::ConfigError(format!("{}: {}", path.display(), e)))
+}
+
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge_tables(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_missing_config() {
+        let dir = tempdir().unwrap();
+
+        let config = Config::load(&dir.path().join(CONFIG_FILE_NAME)).unwrap();
+
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_load_list_columns() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(&path, "[list]\ncolumns = [\"id\", \"desc\", \"due\"]\n").unwrap();
+
+        let config = Config::load(&path).unwrap();
+
+        assert_eq!(
+            config.list.columns,
+            Some(vec![Column::Id, Column::Desc, Column::Due])
+        );
+    }
+
+    #[test]
+    fn test_load_storage_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(
+            &path,
+            "[storage]\nencrypt = true\ncompression = \"zstd\"\nformat = \"msgpack\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert!(config.storage.encrypt);
+        assert_eq!(config.storage.compression, Compression::Zstd);
+        assert_eq!(config.storage.format, Some(Format::MessagePack));
+    }
+
+    #[test]
+    fn test_load_webhooks() {
+        use crate::webhook::{WebhookEvent, WebhookFormat};
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(
+            &path,
+            "[[webhooks]]\nurl = \"https://example.com/all\"\n\
+             [[webhooks]]\nurl = \"https://example.com/done\"\nevents = [\"completed\"]\nretries = 0\n\
+             format = \"slack\"\nsummary_time = \"18:30:00\"\n\
+             [webhooks.templates]\ncompleted = \"Finished {description}\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.webhooks.len(), 2);
+        assert_eq!(config.webhooks[0].events.len(), 3);
+        assert_eq!(config.webhooks[0].retries, 3);
+        assert_eq!(config.webhooks[1].events, [WebhookEvent::Completed]);
+        assert_eq!(config.webhooks[1].retries, 0);
+        assert_eq!(config.webhooks[0].format, WebhookFormat::Json);
+        assert_eq!(config.webhooks[1].format, WebhookFormat::Slack);
+        assert_eq!(
+            config.webhooks[1].templates[&WebhookEvent::Completed],
+            "Finished {description}"
+        );
+        assert_eq!(
+            config.webhooks[1].summary_time,
+            chrono::NaiveTime::from_hms_opt(18, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_load_scripts() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(
+            &path,
+            "[scripts.standup]\nabout = \"Show yesterday\"\nfile = \"standup.lua\"\n\
+             [scripts.hello]\nsource = \"print('hello')\"\n",
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.scripts.len(), 2);
+        assert_eq!(config.scripts["standup"].about, "Show yesterday");
+        assert_eq!(
+            config.scripts["standup"].file.as_deref(),
+            Some(Path::new("standup.lua"))
+        );
+        assert_eq!(
+            config.scripts["hello"].source.as_deref(),
+            Some("print('hello')")
+        );
+
+        std::fs::write(&path, "[scripts.empty]\nabout = \"Nothing to run\"\n").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_load_email() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(
+            &path,
+            "[email]\nsmtp_host = \"smtp.example.com\"\nsecurity = \"tls\"\n\
+             from = \"tasks@example.com\"\nto = [\"me@example.com\"]\n",
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.email.smtp_host.as_deref(), Some("smtp.example.com"));
+        assert_eq!(config.email.security, crate::digest::SmtpSecurity::Tls);
+        assert_eq!(config.email.to, ["me@example.com"]);
+
+        std::fs::write(&path, "[email]\nsmtp_server = \"smtp.example.com\"\n").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_journal_requires_plain_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(&path, "[storage]\njournal = true\nencrypt = true\n").unwrap();
+
+        assert!(Config::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_wal_requires_plain_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(&path, "[storage]\nwal = true\nencrypt = true\n").unwrap();
+
+        assert!(Config::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_load_layered_config() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join(CONFIG_FILE_NAME);
+        let overrides = dir.path().join("profile.toml");
+
+        std::fs::write(
+            &base,
+            "[list]\ncolumns = [\"id\"]\n[storage]\ncompression = \"gzip\"\n",
+        )
+        .unwrap();
+        std::fs::write(&overrides, "[storage]\nencrypt = true\n").unwrap();
+
+        let config = Config::load_layered(&base, &overrides).unwrap();
+
+        assert_eq!(config.list.columns, Some(vec![Column::Id]));
+        assert_eq!(config.storage.compression, Compression::Gzip);
+        assert!(config.storage.encrypt);
+    }
+
+    #[test]
+    fn test_load_invalid_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        std::fs::write(&path, "[list]\ncolumns = [\"bogus\"]\n").unwrap();
+
+        assert!(Config::load(&path).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+/// Whether `candidate` is a copy of `db_file` that a file-sync service left
+/// behind after a conflicting edit. Recognizes the names used by Dropbox
+/// (`tasks (Sam's conflicted copy 2024-01-01).json`), Nextcloud
+/// (`tasks (conflicted copy 2024-01-01 101010).json`) and Syncthing
+/// (`tasks.sync-conflict-20240101-101010-ABCDEFG.json`).
+pub fn is_conflict_copy(db_file: &Path, candidate: &Path) -> bool {
+    let (Some(stem), Some(name)) = (
+        db_file.file_stem().and_then(|s| s.to_str()),
+        candidate.file_name().and_then(|s| s.to_str()),
+    ) else {
+        return false;
+    };
+
+    let suffix = match db_file.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!(".{}", ext),
+        None => String::new(),
+    };
+
+    let Some(middle) = name
+        .strip_prefix(stem)
+        .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+    else {
+        return false;
+    };
+
+    middle.starts_with(".sync-conflict-")
+        || (middle.starts_with(" (") && middle.ends_with(')') && middle.contains("conflicted copy"))
+}
+
+/// Lists the conflict copies of `db_file` in its directory, oldest name
+/// first.
+pub fn find_conflict_copies(db_file: &Path) -> Vec<PathBuf> {
+    let Some(dir) = db_file.parent() else {
+        return Vec::new();
+    };
+
+    let mut copies: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && is_conflict_copy(db_file, path))
+        .collect();
+    copies.sort();
+    copies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_conflict_copy() {
+        let db = Path::new("/data/tasks.json");
+
+        for name in [
+            "tasks (Sam's conflicted copy 2024-01-01).json",
+            "tasks (conflicted copy 2024-01-01 101010).json",
+            "tasks.sync-conflict-20240101-101010-ABCDEFG.json",
+        ] {
+            assert!(is_conflict_copy(db, Path::new(name)), "{}", name);
+        }
+
+        for name in [
+            "tasks.json",
+            "tasks.journal",
+            "tasks (1).json",
+            "other (conflicted copy 2024-01-01).json",
+            "tasks.sync-conflict-20240101-101010-ABCDEFG.journal",
+        ] {
+            assert!(!is_conflict_copy(db, Path::new(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_find_conflict_copies() {
+        let dir = tempdir().unwrap();
+        let db = dir.path().join("tasks.json");
+
+        for name in [
+            "tasks.json",
+            "tasks.sync-conflict-20240102-101010-ABCDEFG.json",
+            "tasks (Sam's conflicted copy 2024-01-01).json",
+            "config.toml",
+        ] {
+            std::fs::write(dir.path().join(name), "{}").unwrap();
+        }
+
+        assert_eq!(
+            find_conflict_copies(&db),
+            vec![
+                dir.path()
+                    .join("tasks (Sam's conflicted copy 2024-01-01).json"),
+                dir.path()
+                    .join("tasks.sync-conflict-20240102-101010-ABCDEFG.json"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// When and by whom a value was last written. Stamps are totally ordered, by
+/// time and then by replica, so every replica picks the same winner for
+/// concurrent writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Stamp {
+    pub at: DateTime<Utc>,
+    pub replica: Uuid,
+}
+
+impl Stamp {
+    pub fn now() -> Self {
+        Self {
+            at: Utc::now(),
+            replica: replica_id(),
+        }
+    }
+}
+
+/// Identifies this process among the replicas writing a database. It only
+/// breaks ties between writes made at the same instant, so it does not need
+/// to outlive the process.
+pub fn replica_id() -> Uuid {
+    static REPLICA: OnceLock<Uuid> = OnceLock::new();
+    *REPLICA.get_or_init(Uuid::new_v4)
+}
+
+/// Joins two last-writer-wins registers: `ours` takes `theirs` when it was
+/// written later. Registers that were never stamped fall back to comparing
+/// their values, so the outcome never depends on which side merges into
+/// which.
+pub fn join_register<T: Clone + PartialEq + Serialize>(
+    ours: (&mut T, Option<Stamp>),
+    theirs: (&T, Option<Stamp>),
+) -> bool {
+    let take_theirs = if ours.1 != theirs.1 {
+        theirs.1 > ours.1
+    } else {
+        *ours.0 != *theirs.0
+            && serde_json::to_string(theirs.0).ok() > serde_json::to_string(&*ours.0).ok()
+    };
+
+    if take_theirs {
+        *ours.0 = theirs.0.clone();
+    }
+
+    take_theirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_register_is_commutative() {
+        let earlier = Stamp::now();
+        let later = Stamp {
+            at: earlier.at + chrono::Duration::seconds(1),
+            replica: Uuid::nil(),
+        };
+
+        let mut ours = "ours";
+        assert!(join_register(
+            (&mut ours, Some(earlier)),
+            (&"theirs", Some(later))
+        ));
+        assert_eq!(ours, "theirs");
+
+        let mut theirs = "theirs";
+        assert!(!join_register(
+            (&mut theirs, Some(later)),
+            (&"ours", Some(earlier))
+        ));
+        assert_eq!(theirs, "theirs");
+
+        // Without stamps both sides still settle on the same value.
+        let (mut a, mut b) = ("a", "b");
+        join_register((&mut a, None), (&"b", None));
+        join_register((&mut b, None), (&"a", None));
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,270 @@
+//! The `org.tonotdo.Tasks` D-Bus service on the session bus, run by `dbus`
+//! for desktop widgets, shell extensions and other apps.
+//!
+//! The object at `/org/tonotdo/Tasks` has the methods
+//!
+//! - `List(state: s, project: s) -> a(sssssss)`: the active tasks, only those
+//!   in `state` and `project` unless they are empty
+//! - `Add(description: s) -> s`: adds a task, returning its ID
+//! - `Complete(id: s)`: marks a task done
+//!
+//! and the signals `TaskChanged((sssssss))` and `TaskRemoved(s)`, sent for
+//! every change, also those other processes make to the database. A task is
+//! its ID, description, state, due date, priority, project and parent, with
+//! states and priorities spelled as on the command line and empty strings
+//! for what is not set.
+
+use std::{sync::mpsc, time::Duration};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+use zbus::{fdo, object_server::SignalEmitter, zvariant::Type};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task, TaskState},
+    filter::TaskFilter,
+    journal::Change,
+};
+
+pub const BUS_NAME: &str = "org.tonotdo.Tasks";
+pub const OBJECT_PATH: &str = "/org/tonotdo/Tasks";
+
+/// How often the database is checked for changes made elsewhere.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn dbus_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::DbusError(reason.to_string())
+}
+
+/// A task as the service hands it out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct TaskInfo {
+    pub id: String,
+    pub description: String,
+    pub state: String,
+    pub due: String,
+    pub priority: String,
+    pub project: String,
+    pub parent: String,
+}
+
+/// The name of a value as `--help` lists it.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+impl TaskInfo {
+    pub fn of(task: &Task) -> Self {
+        Self {
+            id: task.id().to_string(),
+            description: task.description().to_string(),
+            state: value_name(task.state()),
+            due: task.due().map(|due| due.to_string()).unwrap_or_default(),
+            priority: task.priority().map(value_name).unwrap_or_default(),
+            project: task.project().unwrap_or_default().to_string(),
+            parent: task
+                .parent()
+                .map(|parent| parent.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Work for the thread that owns the database.
+type Job = Box<dyn FnOnce(&mut DatabaseManager) + Send>;
+
+/// The `org.tonotdo.Tasks` interface. Its methods hand their work to the
+/// thread running [`serve`] and wait for the answer.
+struct Tasks {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Tasks {
+    async fn call<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut DatabaseManager) -> Result<T, ToNotDoError> + Send + 'static,
+    ) -> fdo::Result<T> {
+        let (reply, answer) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move |db_manager| {
+                let _ = reply.send(job(db_manager));
+            }))
+            .map_err(|_| fdo::Error::Failed("The service is stopping".to_string()))?;
+        answer
+            .await
+            .map_err(|_| fdo::Error::Failed("The service is stopping".to_string()))?
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+}
+
+fn parse_id(id: &str) -> fdo::Result<Uuid> {
+    Uuid::parse_str(id).map_err(|e| fdo::Error::InvalidArgs(format!("{}: {}", id, e)))
+}
+
+#[zbus::interface(name = "org.tonotdo.Tasks")]
+impl Tasks {
+    async fn list(&self, state: &str, project: &str) -> fdo::Result<Vec<TaskInfo>> {
+        let filter = TaskFilter {
+            state: match state {
+                "" => None,
+                state => Some(TaskState::from_str(state, true).map_err(fdo::Error::InvalidArgs)?),
+            },
+            project: Some(project.to_string()).filter(|project| !project.is_empty()),
+            archived: false,
+        };
+        self.call(move |db_manager| {
+            Ok(filter
+                .apply(db_manager.get_tasks()?)
+                .iter()
+                .map(TaskInfo::of)
+                .collect())
+        })
+        .await
+    }
+
+    async fn add(&self, description: &str) -> fdo::Result<String> {
+        if description.trim().is_empty() {
+            return Err(fdo::Error::InvalidArgs(
+                "The description is empty".to_string(),
+            ));
+        }
+        let task = Task::new(description);
+        let id = task.id();
+        self.call(move |db_manager| db_manager.add_task(&task))
+            .await?;
+        Ok(id.to_string())
+    }
+
+    async fn complete(&self, id: &str) -> fdo::Result<()> {
+        let id = parse_id(id)?;
+        self.call(move |db_manager| db_manager.set_task_state(id, TaskState::Done))
+            .await
+    }
+
+    #[zbus(signal)]
+    async fn task_changed(emitter: &SignalEmitter<'_>, task: TaskInfo) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn task_removed(emitter: &SignalEmitter<'_>, id: &str) -> zbus::Result<()>;
+}
+
+/// Sends a signal for every change made to the tasks since the last look.
+async fn announce(
+    db_manager: &mut DatabaseManager,
+    emitter: &SignalEmitter<'_>,
+) -> Result<(), ToNotDoError> {
+    for change in db_manager.watch()? {
+        let sent = match change {
+            Change::PutTask { task } => Tasks::task_changed(emitter, TaskInfo::of(&task)).await,
+            Change::RemoveTask { id } => Tasks::task_removed(emitter, &id.to_string()).await,
+            _ => Ok(()),
+        };
+        sent.map_err(dbus_error)?;
+    }
+    Ok(())
+}
+
+/// Owns the bus name on the session bus and answers calls until the
+/// process is stopped.
+pub fn serve(db_manager: &mut DatabaseManager) -> Result<(), ToNotDoError> {
+    let (jobs, queued) = mpsc::channel();
+    // The first look reports every task, which is not a change.
+    db_manager.watch()?;
+
+    tokio::runtime::Runtime::new()
+        .map_err(dbus_error)?
+        .block_on(async {
+            let connection = zbus::connection::Builder::session()
+                .and_then(|builder| builder.name(BUS_NAME))
+                .and_then(|builder| builder.serve_at(OBJECT_PATH, Tasks { jobs }))
+                .map_err(dbus_error)?
+                .build()
+                .await
+                .map_err(dbus_error)?;
+            let emitter = SignalEmitter::new(&connection, OBJECT_PATH).map_err(dbus_error)?;
+
+            loop {
+                // The calls come in on threads of their own, so a blocking
+                // wait here holds none of them up.
+                let job = tokio::task::block_in_place(|| queued.recv_timeout(POLL_INTERVAL));
+                if let Ok(job) = job {
+                    job(db_manager);
+                }
+                announce(db_manager, &emitter).await?;
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{file_management::Priority, storage::MemoryStorage};
+
+    #[test]
+    fn test_task_info() {
+        let parent = Task::new("Plan");
+        let task = Task::new("Book flights")
+            .with_state(TaskState::InProgress)
+            .with_priority(Priority::High)
+            .with_project("travel")
+            .with_parent(parent.id());
+        assert_eq!(
+            TaskInfo::of(&task),
+            TaskInfo {
+                id: task.id().to_string(),
+                description: "Book flights".to_string(),
+                state: "in-progress".to_string(),
+                due: String::new(),
+                priority: "high".to_string(),
+                project: "travel".to_string(),
+                parent: parent.id().to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        let (jobs, queued) = mpsc::channel::<Job>();
+        let owner = std::thread::spawn(move || {
+            let mut db_manager =
+                DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+            for job in queued {
+                job(&mut db_manager);
+            }
+        });
+
+        let tasks = Tasks { jobs };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let id = tasks.add("Water the plants").await.unwrap();
+            tasks.add("Call Sam").await.unwrap();
+            assert!(tasks.add(" ").await.is_err());
+
+            tasks.complete(&id).await.unwrap();
+            assert!(matches!(
+                tasks.complete("nope").await,
+                Err(fdo::Error::InvalidArgs(_))
+            ));
+            assert!(matches!(
+                tasks.complete(&Uuid::new_v4().to_string()).await,
+                Err(fdo::Error::Failed(_))
+            ));
+
+            let done = tasks.list("done", "").await.unwrap();
+            assert_eq!(done.len(), 1);
+            assert_eq!(done[0].id, id);
+            assert_eq!(tasks.list("", "").await.unwrap().len(), 2);
+            assert!(tasks.list("", "work").await.unwrap().is_empty());
+            assert!(tasks.list("later", "").await.is_err());
+        });
+
+        drop(tasks);
+        owner.join().unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+//! A summary of the tasks due, overdue and completed over a day or a week,
+//! printed or sent by email by `digest`.
+
+use std::{fmt::Write, time::Duration};
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{Task, TaskState, APP_NAME},
+};
+
+/// Read for the SMTP password when the `[email]` section gives none.
+pub const SMTP_PASSWORD_ENV: &str = "TO_NOT_DO_SMTP_PASSWORD";
+
+/// How long talking to the SMTP server may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+impl DigestPeriod {
+    fn days(self) -> i64 {
+        match self {
+            Self::Daily => 1,
+            Self::Weekly => 7,
+        }
+    }
+
+    fn span(self) -> &'static str {
+        match self {
+            Self::Daily => "today",
+            Self::Weekly => "this week",
+        }
+    }
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with STARTTLS, on port 587 by default.
+    #[default]
+    Starttls,
+    /// TLS from the start, on port 465 by default.
+    Tls,
+    /// No encryption, on port 25 by default; only for local relays.
+    None,
+}
+
+/// Settings from the `[email]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    pub smtp_host: Option<String>,
+    /// Port of the SMTP server, instead of the one `security` implies.
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    /// Falls back to the `TO_NOT_DO_SMTP_PASSWORD` environment variable.
+    pub password: Option<String>,
+    pub from: Option<String>,
+    pub to: Vec<String>,
+}
+
+/// The tasks a digest covers, as of `today`.
+#[derive(Debug, Clone)]
+pub struct Digest {
+    pub period: DigestPeriod,
+    pub today: NaiveDate,
+    pub overdue: Vec<Task>,
+    /// Tasks due from today until the end of the period.
+    pub due: Vec<Task>,
+    /// Tasks completed during the period, ending today.
+    pub completed: Vec<Task>,
+}
+
+impl Digest {
+    pub fn new(tasks: &[Task], today: NaiveDate, period: DigestPeriod) -> Self {
+        let days = chrono::Duration::days(period.days());
+        let pending = || {
+            tasks
+                .iter()
+                .filter(|task| task.state() != TaskState::Done && !task.is_archived())
+        };
+
+        let mut overdue: Vec<Task> = pending()
+            .filter(|task| task.due().is_some_and(|due| due < today))
+            .cloned()
+            .collect();
+        overdue.sort_by_key(Task::due);
+
+        let mut due: Vec<Task> = pending()
+            .filter(|task| {
+                task.due()
+                    .is_some_and(|due| due >= today && due < today + days)
+            })
+            .cloned()
+            .collect();
+        due.sort_by_key(Task::due);
+
+        let mut completed: Vec<Task> = tasks
+            .iter()
+            .filter(|task| task.state() == TaskState::Done)
+            .filter(|task| {
+                task.completed_at()
+                    .is_some_and(|at| at <= today && at > today - days)
+            })
+            .cloned()
+            .collect();
+        completed.sort_by_key(Task::completed_at);
+
+        Self {
+            period,
+            today,
+            overdue,
+            due,
+            completed,
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        format!(
+            "{} {} digest for {}: {} due, {} overdue, {} completed",
+            APP_NAME,
+            match self.period {
+                DigestPeriod::Daily => "daily",
+                DigestPeriod::Weekly => "weekly",
+            },
+            self.today,
+            self.due.len(),
+            self.overdue.len(),
+            self.completed.len()
+        )
+    }
+
+    /// The digest as plain text.
+    pub fn body(&self) -> String {
+        let span = self.period.span();
+        let mut body = String::new();
+
+        for (title, tasks) in [
+            ("Overdue".to_string(), &self.overdue),
+            (format!("Due {}", span), &self.due),
+            (format!("Completed {}", span), &self.completed),
+        ] {
+            if !body.is_empty() {
+                body.push('\n');
+            }
+            let _ = writeln!(body, "{} ({})", title, tasks.len());
+            if tasks.is_empty() {
+                body.push_str("  Nothing\n");
+            }
+            for task in tasks {
+                let _ = match (task.completed_at(), task.due()) {
+                    (Some(at), _) if task.state() == TaskState::Done => {
+                        writeln!(body, "  - {} (done {})", task.description(), at)
+                    }
+                    (_, Some(due)) => writeln!(body, "  - {} (due {})", task.description(), due),
+                    _ => writeln!(body, "  - {}", task.description()),
+                };
+            }
+        }
+        body
+    }
+
+    /// The digest as an email with the addresses from `config`.
+    pub fn message(&self, config: &EmailConfig) -> Result<Message, ToNotDoError> {
+        let mailbox = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| email_error(format!("Invalid address {}: {}", address, e)))
+        };
+
+        let from = config
+            .from
+            .as_deref()
+            .ok_or_else(|| email_error("No sender; set from in the [email] config section"))?;
+        if config.to.is_empty() {
+            return Err(email_error(
+                "No recipients; set to in the [email] config section",
+            ));
+        }
+
+        let mut builder = Message::builder()
+            .from(mailbox(from)?)
+            .subject(self.subject());
+        for to in &config.to {
+            builder = builder.to(mailbox(to)?);
+        }
+        builder
+            .header(ContentType::TEXT_PLAIN)
+            .body(self.body())
+            .map_err(|e| email_error(e.to_string()))
+    }
+}
+
+/// Sends `message` through the SMTP server from `config`.
+pub fn send(message: &Message, config: &EmailConfig) -> Result<(), ToNotDoError> {
+    let host = config.smtp_host.as_deref().ok_or_else(|| {
+        email_error("No SMTP server; set smtp_host in the [email] config section")
+    })?;
+
+    let mut transport = match config.security {
+        SmtpSecurity::Starttls => SmtpTransport::starttls_relay(host),
+        SmtpSecurity::Tls => SmtpTransport::relay(host),
+        SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(host)),
+    }
+    .map_err(|e| email_error(e.to_string()))?
+    .timeout(Some(TIMEOUT));
+
+    if let Some(port) = config.port {
+        transport = transport.port(port);
+    }
+    if let Some(username) = &config.username {
+        let password = config
+            .password
+            .clone()
+            .or_else(|| std::env::var(SMTP_PASSWORD_ENV).ok())
+            .unwrap_or_default();
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+
+    transport
+        .build()
+        .send(message)
+        .map(|_| ())
+        .map_err(|e| email_error(e.to_string()))
+}
+
+fn email_error(message: impl ToString) -> ToNotDoError {
+    ToNotDoError::EmailError(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let days = chrono::Duration::days;
+
+        let tasks = vec![
+            Task::new("Pay rent").with_due(today - days(2)),
+            Task::new("Dentist").with_due(today),
+            Task::new("Team offsite").with_due(today + days(3)),
+            Task::new("Renew passport").with_due(today + days(30)),
+            Task::new("Someday"),
+            Task::new("Filed taxes").with_completed_at(today - days(1)),
+            Task::new("Old news").with_completed_at(today - days(9)),
+        ];
+        let digest = Digest::new(&tasks, today, DigestPeriod::Daily);
+        assert_eq!(digest.overdue.len(), 1);
+        assert_eq!(digest.due.len(), 1);
+        assert_eq!(digest.due[0].description(), "Dentist");
+        assert!(digest.completed.is_empty());
+
+        let digest = Digest::new(&tasks, today, DigestPeriod::Weekly);
+        assert_eq!(digest.due.len(), 2);
+        assert_eq!(
+            digest.subject(),
+            "to-not-do weekly digest for 2025-03-10: 2 due, 1 overdue, 1 completed"
+        );
+
+        let body = digest.body();
+        assert!(body.starts_with("Overdue (1)\n  - Pay rent (due 2025-03-08)\n"));
+        assert!(body.contains("Due this week (2)\n  - Dentist (due 2025-03-10)\n"));
+        assert!(body.ends_with("Completed this week (1)\n  - Filed taxes (done 2025-03-09)\n"));
+
+        let body = Digest::new(&[], today, DigestPeriod::Daily).body();
+        assert!(body.contains("Due today (0)\n  Nothing\n"));
+    }
+
+    #[test]
+    fn test_digest_message() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let digest = Digest::new(&[], today, DigestPeriod::Daily);
+
+        let mut config = EmailConfig::default();
+        assert!(digest.message(&config).is_err());
+
+        config.from = Some("Tasks <tasks@example.com>".to_string());
+        config.to = vec!["me@example.com".to_string()];
+        let message = String::from_utf8(digest.message(&config).unwrap().formatted()).unwrap();
+        assert!(message.contains("From: Tasks <tasks@example.com>\r\n"));
+        assert!(message.contains("To: me@example.com\r\n"));
+        assert!(message.contains("Subject: to-not-do daily digest for 2025-03-10"));
+
+        config.to = vec!["not an address".to_string()];
+        assert!(digest.message(&config).is_err());
+    }
+}
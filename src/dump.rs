@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Database, APP_NAME, VERSION},
+};
+
+/// Version of the dump layout, bumped whenever it changes incompatibly.
+pub const DUMP_VERSION: u32 = 1;
+
+/// Everything the application stores, in one versioned JSON document that
+/// any build supporting its version reads back exactly as it was written.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Dump {
+    pub app: String,
+    pub dump_version: u32,
+    /// Version of the build that wrote the dump.
+    pub version: String,
+    pub created_at: DateTime<Utc>,
+    /// Every project in use, for readers that do not want to scan the tasks.
+    pub projects: Vec<String>,
+    /// Every tag in use, likewise.
+    pub tags: Vec<String>,
+    pub config: Option<String>,
+    /// The whole database, history and deleted task IDs included.
+    pub database: Database,
+}
+
+fn invalid(reason: impl ToString) -> ToNotDoError {
+    ToNotDoError::DatabaseError(DatabaseError::InvalidDump(reason.to_string()))
+}
+
+impl Dump {
+    pub fn new(database: Database, config: Option<String>) -> Self {
+        let projects: BTreeSet<&str> = database
+            .tasks()
+            .iter()
+            .filter_map(|t| t.project())
+            .collect();
+        let tags: BTreeSet<&str> = database
+            .tasks()
+            .iter()
+            .flat_map(|t| t.tags())
+            .map(String::as_str)
+            .collect();
+
+        Self {
+            app: APP_NAME.to_string(),
+            dump_version: DUMP_VERSION,
+            version: VERSION.to_string(),
+            created_at: Utc::now(),
+            projects: projects.into_iter().map(str::to_string).collect(),
+            tags: tags.into_iter().map(str::to_string).collect(),
+            config,
+            database,
+        }
+    }
+
+    /// Serializes the dump, checking that reading it back gives the same
+    /// document so a dump that would not round-trip is never written.
+    pub fn to_json(&self) -> Result<String, ToNotDoError> {
+        let json = serde_json::to_string_pretty(self).map_err(invalid)?;
+
+        let written = serde_json::to_value(self).map_err(invalid)?;
+        let read = serde_json::to_value(Self::from_json(&json)?).map_err(invalid)?;
+        if written != read {
+            return Err(invalid("the dump does not read back as written"));
+        }
+
+        Ok(json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ToNotDoError> {
+        let mut dump: serde_json::Value = serde_json::from_str(json).map_err(invalid)?;
+
+        let dump_version = dump
+            .get("dump_version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| invalid("missing dump_version"))?;
+        if dump_version > DUMP_VERSION as u64 {
+            return Err(invalid(format!(
+                "dump version {} is newer than this build supports ({})",
+                dump_version, DUMP_VERSION
+            )));
+        }
+
+        if let Some(database) = dump.get_mut("database") {
+            crate::migration::migrate(database)?;
+        }
+
+        let dump: Self = serde_json::from_value(dump).map_err(invalid)?;
+        if dump.app != APP_NAME {
+            return Err(invalid(format!(
+                "written by {}, not {}",
+                dump.app, APP_NAME
+            )));
+        }
+
+        Ok(dump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_management::{Task, TaskState},
+        journal::Change,
+    };
+
+    #[test]
+    fn test_dump_round_trip() {
+        let mut db = Database::default();
+        let task = Task::new("Dumped")
+            .with_notes("Some notes")
+            .with_project("home")
+            .with_tags(&["b".to_string(), "a".to_string()]);
+        db.insert_task(task).unwrap();
+        db.insert_task(Task::new("Second").with_project("work"))
+            .unwrap();
+        let removed = Task::new("Removed");
+        db.insert_task(removed.clone()).unwrap();
+        db.apply(Change::RemoveTask { id: removed.id() });
+        db.apply(Change::SetContext {
+            context: Some("home".to_string()),
+        });
+
+        let dump = Dump::new(db, Some("[list]\n".to_string()));
+        assert_eq!(dump.projects, vec!["home", "work"]);
+        assert_eq!(dump.tags, vec!["a", "b"]);
+
+        let json = dump.to_json().unwrap();
+        let read = Dump::from_json(&json).unwrap();
+
+        assert_eq!(read.to_json().unwrap(), json);
+        assert_eq!(read.config.as_deref(), Some("[list]\n"));
+        assert_eq!(read.database.tasks()[0].state(), TaskState::Todo);
+    }
+
+    #[test]
+    fn test_reject_newer_dump() {
+        let json = Dump::new(Database::default(), None)
+            .to_json()
+            .unwrap()
+            .replace("\"dump_version\": 1", "\"dump_version\": 99");
+
+        assert!(Dump::from_json(&json).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::Duration;
+
+/// Parses a duration such as `2d` or `1w` into a number of days.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+
+    let Some(unit) = input.chars().last() else {
+        return Err("Duration cannot be empty".to_string());
+    };
+
+    let amount = input[..input.len() - unit.len_utf8()]
+        .parse::<i64>()
+        .map_err(|_| format!("Invalid duration: {}", input))?;
+
+    if amount < 0 {
+        return Err(format!("Duration cannot be negative: {}", input));
+    }
+
+    let duration = match unit {
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => {
+            return Err(format!(
+                "Invalid duration unit in {}, expected d (days) or w (weeks)",
+                input
+            ))
+        }
+    };
+    duration.ok_or_else(|| format!("Duration is too long: {}", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2d"), Ok(Duration::days(2)));
+        assert_eq!(parse_duration("1w"), Ok(Duration::weeks(1)));
+        assert_eq!(parse_duration(" 90d "), Ok(Duration::days(90)));
+    }
+
+    #[test]
+    fn test_parse_invalid_duration() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("2h").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("1é").is_err());
+        assert!(parse_duration("99999999999999d").is_err());
+        assert!(parse_duration("99999999999999w").is_err());
+    }
+}
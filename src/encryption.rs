@@ -0,0 +1,306 @@
+use std::path::Path;
+
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+
+use crate::{error::ToNotDoError, file_management::APP_NAME};
+
+/// Marks an encrypted database file: `MAGIC`, salt, nonce, then ciphertext.
+pub const MAGIC: &[u8; 4] = b"TNDE";
+pub const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+/// A key derived from a passphrase together with the salt it was derived with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cipher {
+    salt: [u8; SALT_LEN],
+    key: [u8; KEY_LEN],
+}
+
+impl Cipher {
+    pub fn derive(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<Self, ToNotDoError> {
+        let mut key = [0; KEY_LEN];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| ToNotDoError::EncryptionError(e.to_string()))?;
+
+        Ok(Self { salt, key })
+    }
+
+    /// Derives a key for a database that is not encrypted yet.
+    pub fn generate(passphrase: &str) -> Result<Self, ToNotDoError> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        Self::derive(passphrase, salt)
+    }
+
+    /// Derives the key for the file contents `data` and checks that it opens
+    /// them. Files that are not encrypted yet get a fresh salt.
+    pub fn for_file(data: &[u8], passphrase: &str) -> Result<Self, ToNotDoError> {
+        match salt_of(data) {
+            Some(salt) => {
+                let cipher = Self::derive(passphrase, salt)?;
+                cipher.decrypt(data)?;
+                Ok(cipher)
+            }
+            None => Self::generate(passphrase),
+        }
+    }
+
+    pub fn salt(&self) -> [u8; SALT_LEN] {
+        self.salt
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, ToNotDoError> {
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = ChaCha20Poly1305::new(&self.key.into())
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| ToNotDoError::EncryptionError("Failed to encrypt database".into()))?;
+
+        let mut out = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.salt);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, ToNotDoError> {
+        if salt_of(data) != Some(self.salt) {
+            return Err(wrong_key());
+        }
+
+        let nonce = Nonce::from_slice(&data[MAGIC.len() + SALT_LEN..HEADER_LEN]);
+
+        ChaCha20Poly1305::new(&self.key.into())
+            .decrypt(nonce, &data[HEADER_LEN..])
+            .map_err(|_| wrong_key())
+    }
+
+    fn to_hex(&self) -> String {
+        self.salt
+            .iter()
+            .chain(&self.key)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        Some(Self {
+            salt: bytes.get(..SALT_LEN)?.try_into().ok()?,
+            key: bytes.get(SALT_LEN..)?.try_into().ok()?,
+        })
+    }
+}
+
+fn wrong_key() -> ToNotDoError {
+    ToNotDoError::EncryptionError("Wrong passphrase or corrupted database".into())
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data.starts_with(MAGIC)
+}
+
+/// Returns the salt stored in the header of an encrypted file.
+pub fn salt_of(data: &[u8]) -> Option<[u8; SALT_LEN]> {
+    if !is_encrypted(data) {
+        return None;
+    }
+
+    data[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into().ok()
+}
+
+/// Supplies the key used to encrypt a database.
+pub trait KeySource: Send {
+    /// Returns the key for a file encrypted with `salt`, or a key for a file
+    /// that is about to be encrypted for the first time when `salt` is `None`.
+    fn cipher(&mut self, salt: Option<[u8; SALT_LEN]>) -> Result<Cipher, ToNotDoError>;
+}
+
+/// Looks the key up in the OS keychain, asking for the passphrase when it has
+/// not been stored there with `unlock`.
+pub struct KeyringKeySource {
+    account: String,
+}
+
+impl KeyringKeySource {
+    pub fn new(db_path: &Path) -> Self {
+        Self {
+            account: db_path.display().to_string(),
+        }
+    }
+
+    fn entry(&self) -> Result<keyring::Entry, ToNotDoError> {
+        keyring::Entry::new(APP_NAME, &self.account)
+            .map_err(|e| ToNotDoError::EncryptionError(e.to_string()))
+    }
+
+    /// Returns the key saved by [`KeyringKeySource::store`], if any.
+    pub fn stored(&self) -> Option<Cipher> {
+        let secret = self.entry().ok()?.get_password().ok()?;
+        Cipher::from_hex(&secret)
+    }
+
+    pub fn store(&self, cipher: &Cipher) -> Result<(), ToNotDoError> {
+        self.entry()?
+            .set_password(&cipher.to_hex())
+            .map_err(|e| ToNotDoError::EncryptionError(e.to_string()))
+    }
+
+    /// Removes the stored key, returning whether there was one.
+    pub fn forget(&self) -> Result<bool, ToNotDoError> {
+        match self.entry()?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(ToNotDoError::EncryptionError(e.to_string())),
+        }
+    }
+}
+
+impl KeySource for KeyringKeySource {
+    fn cipher(&mut self, salt: Option<[u8; SALT_LEN]>) -> Result<Cipher, ToNotDoError> {
+        if let Some(cipher) = self
+            .stored()
+            .filter(|cipher| salt.is_none_or(|salt| cipher.salt == salt))
+        {
+            return Ok(cipher);
+        }
+
+        let passphrase = prompt_passphrase(salt.is_none())?;
+        match salt {
+            Some(salt) => Cipher::derive(&passphrase, salt),
+            None => Cipher::generate(&passphrase),
+        }
+    }
+}
+
+/// Remembers the last key another source gave, so reading several files
+/// encrypted with it asks for the passphrase only once.
+pub struct CachedKeySource<K> {
+    inner: K,
+    last: Option<Cipher>,
+}
+
+impl<K: KeySource> CachedKeySource<K> {
+    pub fn new(inner: K) -> Self {
+        Self { inner, last: None }
+    }
+}
+
+impl<K: KeySource> KeySource for CachedKeySource<K> {
+    fn cipher(&mut self, salt: Option<[u8; SALT_LEN]>) -> Result<Cipher, ToNotDoError> {
+        if let Some(cipher) = self
+            .last
+            .as_ref()
+            .filter(|cipher| salt.is_some_and(|salt| cipher.salt == salt))
+        {
+            return Ok(cipher.clone());
+        }
+
+        let cipher = self.inner.cipher(salt)?;
+        self.last = Some(cipher.clone());
+        Ok(cipher)
+    }
+}
+
+/// The key copies of the database file at `db_file`, such as backups and
+/// snapshots, are encrypted with: the database's own, or `None` while the
+/// database is not encrypted.
+pub fn copy_cipher(db_file: &Path) -> Result<Option<Cipher>, ToNotDoError> {
+    salt_of_file(db_file)
+        .map(|salt| KeyringKeySource::new(db_file).cipher(Some(salt)))
+        .transpose()
+}
+
+/// Returns the salt of the file at `path` when it is encrypted, reading
+/// only its header.
+pub fn salt_of_file(path: &Path) -> Option<[u8; SALT_LEN]> {
+    use std::io::Read;
+
+    let mut header = Vec::new();
+    let file = std::fs::File::open(path).ok()?;
+    file.take(HEADER_LEN as u64).read_to_end(&mut header).ok()?;
+    salt_of(&header)
+}
+
+/// Asks for the database passphrase, twice when `confirm` is set.
+pub fn prompt_passphrase(confirm: bool) -> Result<String, ToNotDoError> {
+    prompt_secret("Database passphrase", confirm)
+}
+
+/// Asks for a secret without echoing it, twice when `confirm` is set.
+pub fn prompt_secret(prompt: &str, confirm: bool) -> Result<String, ToNotDoError> {
+    let mut prompt = dialoguer::Password::new().with_prompt(prompt);
+
+    if confirm {
+        prompt = prompt.with_confirmation("Repeat passphrase", "Passphrases do not match");
+    }
+
+    prompt
+        .interact()
+        .map_err(|e| ToNotDoError::EncryptionError(e.to_string()))
+}
+
+/// A fixed passphrase, for tests that encrypt without a keychain or prompt.
+#[cfg(test)]
+pub(crate) struct Passphrase(pub(crate) &'static str);
+
+#[cfg(test)]
+impl KeySource for Passphrase {
+    fn cipher(&mut self, salt: Option<[u8; SALT_LEN]>) -> Result<Cipher, ToNotDoError> {
+        match salt {
+            Some(salt) => Cipher::derive(self.0, salt),
+            None => Cipher::generate(self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let cipher = Cipher::generate("correct horse").unwrap();
+
+        let data = cipher.encrypt(b"{\"tasks\": []}").unwrap();
+
+        assert!(is_encrypted(&data));
+        assert_eq!(salt_of(&data), Some(cipher.salt()));
+        assert_eq!(cipher.decrypt(&data).unwrap(), b"{\"tasks\": []}");
+
+        let wrong = Cipher::derive("battery staple", cipher.salt()).unwrap();
+        assert!(wrong.decrypt(&data).is_err());
+        assert!(!is_encrypted(b"{\"tasks\": []}"));
+    }
+
+    #[test]
+    fn test_cipher_for_file() {
+        let cipher = Cipher::generate("correct horse").unwrap();
+        let data = cipher.encrypt(b"{}").unwrap();
+
+        assert!(Cipher::for_file(&data, "correct horse").unwrap() == cipher);
+        assert!(Cipher::for_file(&data, "battery staple").is_err());
+        assert!(Cipher::for_file(b"{}", "correct horse").is_ok());
+    }
+
+    #[test]
+    fn test_cipher_hex_round_trip() {
+        let cipher = Cipher::generate("correct horse").unwrap();
+
+        assert!(Cipher::from_hex(&cipher.to_hex()) == Some(cipher));
+        assert!(Cipher::from_hex("not hex").is_none());
+    }
+}
@@ -1,9 +1,62 @@
+use std::path::PathBuf;
+
 use uuid::Uuid;
 
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum ToNotDoError {
-    #[error("Task not found: {0}")]
+    #[error("{0}")]
     DatabaseError(DatabaseError),
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+    #[error("{0}")]
+    EncryptionError(String),
+    #[error("Sync failed: {0}")]
+    SyncError(String),
+    #[error("Remote storage failed: {0}")]
+    RemoteError(String),
+    #[error("{0} was changed elsewhere since it was loaded; run the command again")]
+    RemoteConflict(String),
+    #[error("Webhook failed: {0}")]
+    WebhookError(String),
+    #[error("Hook {hook} failed: {reason}")]
+    HookError { hook: String, reason: String },
+    #[error("Plugin failed: {0}")]
+    PluginError(String),
+    #[error("Script failed: {0}")]
+    ScriptError(String),
+    #[error("Notification failed: {0}")]
+    NotificationError(String),
+    #[error("Email failed: {0}")]
+    EmailError(String),
+    #[error("CalDAV sync failed: {0}")]
+    CalDavError(String),
+    #[error("Todoist sync failed: {0}")]
+    TodoistError(String),
+    #[error("Google Calendar sync failed: {0}")]
+    GcalError(String),
+    #[error("Git hook failed: {0}")]
+    GitError(String),
+    #[error("GitHub sync failed: {0}")]
+    GitHubError(String),
+    #[error("Jira import failed: {0}")]
+    JiraError(String),
+    #[error("D-Bus service failed: {0}")]
+    DbusError(String),
+    #[error("Markdown sync failed: {0}")]
+    MarkdownError(String),
+    #[error("Matrix bot failed: {0}")]
+    MatrixError(String),
+    #[error("Microsoft To Do import failed: {0}")]
+    MicrosoftTodoError(String),
+    #[error("Telegram bot failed: {0}")]
+    TelegramError(String),
+    #[error("Publishing failed: {0}")]
+    PublishError(String),
+    #[error("Control socket failed: {0}")]
+    SocketError(String),
+    #[error("Browser storage failed: {0}")]
+    BrowserError(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -14,4 +67,21 @@ pub enum DatabaseError {
     UuidAlreadyExists(Uuid),
     #[error("Failed to read file {0}")]
     FailedToReadFile(#[from] std::io::Error),
+    #[error("Failed to write file {0}")]
+    FailedToWriteFile(std::io::Error),
+    #[error("{} is read-only; changes cannot be saved", .0.display())]
+    ReadOnly(PathBuf),
+    #[error("Postponing task {0} would move its due date out of range")]
+    DueDateOutOfRange(Uuid),
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+    #[error("Invalid dump: {0}")]
+    InvalidDump(String),
+    #[error(
+        "Database schema version {found} is newer than this build supports ({supported}); \
+         upgrade to-not-do or restore an older backup"
+    )]
+    UnsupportedSchema { found: u32, supported: u32 },
+    #[error("Failed to migrate database from schema version {from}: {reason}")]
+    MigrationFailed { from: u32, reason: String },
 }
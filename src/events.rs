@@ -0,0 +1,167 @@
+//! Task change events for `events`, printed as NDJSON so other programs
+//! can react to changes without polling the database themselves:
+//!
+//! ```text
+//! {"at":"2025-03-10T08:00:01Z","event":"created","task":{...}}
+//! {"at":"2025-03-10T08:02:15Z","event":"completed","task":{...}}
+//! {"at":"2025-03-10T08:03:40Z","event":"deleted","id":"67e55044-10b1-426f-9247-bb680e5fe0c8"}
+//! ```
+//!
+//! Without `--follow`, `events` prints the tasks there are as `existing`
+//! events and stops; with it, it prints the changes from then on instead.
+//! Changes are found by looking at the database every [`POLL_INTERVAL`],
+//! so they are seen whichever process makes them. Several changes to a
+//! task between two looks come out as one event.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task, TaskState},
+    journal::Change,
+};
+
+/// How often the database is looked at for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What happened to a task.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TaskEvent {
+    /// The task was there before following started.
+    Existing {
+        task: Task,
+    },
+    Created {
+        task: Task,
+    },
+    Updated {
+        task: Task,
+    },
+    /// The task was marked done; other changes made with it are included.
+    Completed {
+        task: Task,
+    },
+    Deleted {
+        id: Uuid,
+    },
+}
+
+/// One line of the stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventLine {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: TaskEvent,
+}
+
+/// Turns the changes [`DatabaseManager::watch`] finds into events.
+#[derive(Debug, Default)]
+pub struct Follower {
+    /// The tasks as of the last look.
+    known: HashMap<Uuid, Task>,
+}
+
+impl Follower {
+    /// Starts following the tasks of `db_manager`, returning those already
+    /// there as [`TaskEvent::Existing`].
+    pub fn start(db_manager: &mut DatabaseManager) -> Result<(Self, Vec<TaskEvent>), ToNotDoError> {
+        let mut follower = Self::default();
+        // The first look reports every task as added.
+        db_manager.watch()?;
+        let existing = db_manager
+            .get_tasks()?
+            .iter()
+            .map(|task| {
+                follower.known.insert(task.id(), task.clone());
+                TaskEvent::Existing { task: task.clone() }
+            })
+            .collect();
+        Ok((follower, existing))
+    }
+
+    /// The events since the last look.
+    pub fn poll(
+        &mut self,
+        db_manager: &mut DatabaseManager,
+    ) -> Result<Vec<TaskEvent>, ToNotDoError> {
+        let mut events = Vec::new();
+        for change in db_manager.watch()? {
+            match change {
+                Change::PutTask { task } => {
+                    let task = *task;
+                    let event = match self.known.insert(task.id(), task.clone()) {
+                        None => TaskEvent::Created { task },
+                        Some(before)
+                            if before.state() != TaskState::Done
+                                && task.state() == TaskState::Done =>
+                        {
+                            TaskEvent::Completed { task }
+                        }
+                        Some(_) => TaskEvent::Updated { task },
+                    };
+                    events.push(event);
+                }
+                Change::RemoveTask { id } if self.known.remove(&id).is_some() => {
+                    events.push(TaskEvent::Deleted { id });
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_follow() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let mut ours = DatabaseManager::open(&db_file).unwrap();
+        let old = Task::new("Water the plants");
+        ours.add_task(&old).unwrap();
+
+        let mut follower_db = DatabaseManager::open(&db_file).unwrap();
+        let (mut follower, existing) = Follower::start(&mut follower_db).unwrap();
+        assert_eq!(existing, [TaskEvent::Existing { task: old.clone() }]);
+        assert!(follower.poll(&mut follower_db).unwrap().is_empty());
+
+        // Changes made by another process.
+        let new = Task::new("Call Sam");
+        ours.add_task(&new).unwrap();
+        ours.update_description(old.id(), "Water the cactus")
+            .unwrap();
+        let events = follower.poll(&mut follower_db).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&TaskEvent::Created { task: new.clone() }));
+        assert!(matches!(
+            events.iter().find(|e| matches!(e, TaskEvent::Updated { .. })),
+            Some(TaskEvent::Updated { task }) if task.description() == "Water the cactus"
+        ));
+
+        ours.set_task_state(new.id(), TaskState::Done).unwrap();
+        ours.delete_task(old.id()).unwrap();
+        let events = follower.poll(&mut follower_db).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&TaskEvent::Deleted { id: old.id() }));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, TaskEvent::Completed { task } if task.id() == new.id())));
+
+        let line = serde_json::to_value(EventLine {
+            at: Utc::now(),
+            event: TaskEvent::Deleted { id: old.id() },
+        })
+        .unwrap();
+        assert_eq!(line["event"], "deleted");
+        assert_eq!(line["id"], old.id().to_string());
+    }
+}
@@ -0,0 +1,135 @@
+//! Commands other programs add to the command line tool, found the way git
+//! finds its own: when there is no built-in or plugin command `foo`,
+//! `to-not-do foo --bar` runs the first executable named `to-not-do-foo`
+//! on the `PATH` with `--bar`.
+//!
+//! The program is told which tasks it was run for through the environment:
+//! [`DB_ENV`] is the database file or URL, [`PROFILE_ENV`] the profile,
+//! unset for the default one, [`CONFIG_ENV`] the config file and
+//! [`EXE_ENV`] the tool itself, so the program can run
+//! `"$TO_NOT_DO_EXE" --db "$TO_NOT_DO_DB" list` to work on the same tasks.
+//! The tool picks [`PROFILE_ENV`] up when run without `--profile`, and the
+//! rest of the environment is passed on as it is, including
+//! [`DATA_DIR_ENV`](crate::file_management::DATA_DIR_ENV) when set, so it
+//! finds the same config, journal and state as well.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::file_management::{AppPaths, APP_NAME};
+
+/// The database of the invocation that ran the program.
+pub const DB_ENV: &str = "TO_NOT_DO_DB";
+/// The profile of the invocation that ran the program, if not the default;
+/// also the profile used when `--profile` is not given.
+pub const PROFILE_ENV: &str = "TO_NOT_DO_PROFILE";
+/// The config file of the invocation that ran the program.
+pub const CONFIG_ENV: &str = "TO_NOT_DO_CONFIG";
+/// The executable that ran the program.
+pub const EXE_ENV: &str = "TO_NOT_DO_EXE";
+
+/// The program providing the command `name`, from the directories in
+/// `search_path`, a `PATH`-style list.
+pub fn find(name: &str, search_path: Option<OsString>) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    let file_name = format!("{}-{}{}", APP_NAME, name, std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&search_path?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// The variables describing the database and profile in `paths` for the
+/// program.
+pub fn environment(paths: &AppPaths) -> Vec<(&'static str, OsString)> {
+    let mut variables = vec![
+        (DB_ENV, paths.db_file.clone().into_os_string()),
+        (CONFIG_ENV, paths.config_file.clone().into_os_string()),
+    ];
+    if let Some(profile) = &paths.profile {
+        variables.push((PROFILE_ENV, profile.into()));
+    }
+    if let Ok(exe) = std::env::current_exe() {
+        variables.push((EXE_ENV, exe.into_os_string()));
+    }
+    variables
+}
+
+/// The command running `program` with `args` for the tasks in `paths`.
+pub fn command(program: &Path, args: &[String], paths: &AppPaths) -> Command {
+    let mut command = Command::new(program);
+    command.args(args).envs(environment(paths));
+    if paths.profile.is_none() {
+        command.env_remove(PROFILE_ENV);
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find() {
+        let dir = tempdir().unwrap();
+        let other = tempdir().unwrap();
+        let program = dir
+            .path()
+            .join(format!("to-not-do-hello{}", std::env::consts::EXE_SUFFIX));
+        std::fs::write(&program, "#!/bin/sh\necho \"$TO_NOT_DO_DB\"\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let not_executable = other.path().join("to-not-do-hello");
+            std::fs::write(&not_executable, "").unwrap();
+            std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let search_path = std::env::join_paths([other.path(), dir.path()]).unwrap();
+        assert_eq!(
+            find("hello", Some(search_path.clone())),
+            Some(program.clone())
+        );
+        assert_eq!(find("bye", Some(search_path.clone())), None);
+        assert_eq!(find("../hello", Some(search_path)), None);
+        assert_eq!(find("hello", None), None);
+
+        let mut paths = AppPaths::new(dir.path());
+        paths.profile = Some("work".to_string());
+        let variables = environment(&paths);
+        assert!(variables.contains(&(DB_ENV, paths.db_file.clone().into_os_string())));
+        assert!(variables.contains(&(PROFILE_ENV, "work".into())));
+        assert!(!variables
+            .iter()
+            .any(|(name, _)| *name == crate::file_management::DATA_DIR_ENV));
+
+        #[cfg(unix)]
+        {
+            let output = command(&program, &[], &paths).output().unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout).trim(),
+                paths.db_file.to_string_lossy()
+            );
+        }
+    }
+}
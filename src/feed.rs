@@ -0,0 +1,247 @@
+//! The feeds `serve` offers for other apps to subscribe to.
+//!
+//! The calendar feed at [`FEED_ENDPOINT`] is for Apple Calendar, Google
+//! Calendar and the like. Every open task with a due date is an all-day
+//! event on that date; done and archived tasks drop out the next time the
+//! calendar refreshes.
+//!
+//! The Atom feed at [`ATOM_ENDPOINT`], also written by `export --format
+//! atom`, is for feed readers and dashboards: it has the
+//! [`ATOM_ENTRIES`] tasks completed last, newest first.
+//!
+//! Calendar apps and feed readers cannot send a bearer token, so the feeds
+//! take their own secret in the URL instead, as `/feed.ics?token=...`. Each
+//! profile has one, kept in its state directory, and it only opens the
+//! feeds: it is no use for the rest of the API.
+
+use std::path::Path;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::{
+    caldav::{escape, fold},
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Task, TaskState, APP_NAME},
+    gcal::encode_segment,
+    publish,
+};
+
+/// Where `serve` answers with the calendar feed.
+pub const FEED_ENDPOINT: &str = "/feed.ics";
+
+/// Where `serve` answers with the Atom feed of completed tasks.
+pub const ATOM_ENDPOINT: &str = "/completed.atom";
+
+/// How many completed tasks the Atom feed has.
+pub const ATOM_ENTRIES: usize = 50;
+
+/// The feed token kept at `path`, made and saved there first if there is
+/// none yet or `renew` is set.
+pub fn feed_token(path: &Path, renew: bool) -> Result<String, ToNotDoError> {
+    if !renew {
+        match std::fs::read_to_string(path) {
+            Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(ToNotDoError::DatabaseError(
+                    DatabaseError::FailedToReadFile(e),
+                ))
+            }
+        }
+    }
+
+    let token = Uuid::new_v4().simple().to_string();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e)))?;
+    }
+    crate::storage::write_atomically(path, token.as_bytes())
+        .map_err(|e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e)))?;
+    Ok(token)
+}
+
+/// The feed named `name` with an event for every open task in `tasks` that
+/// has a due date.
+pub fn render(tasks: &[Task], name: &str, now: DateTime<Utc>) -> String {
+    let stamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//{}//EN", APP_NAME),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ];
+
+    for task in tasks
+        .iter()
+        .filter(|task| task.state() != TaskState::Done && !task.is_archived())
+    {
+        let Some(due) = task.due() else {
+            continue;
+        };
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}@{}", task.id(), APP_NAME));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!("DTSTART;VALUE=DATE:{}", due.format("%Y%m%d")));
+        lines.push(format!(
+            "DTEND;VALUE=DATE:{}",
+            (due + Duration::days(1)).format("%Y%m%d")
+        ));
+        lines.push(format!("SUMMARY:{}", escape(task.description())));
+        if let Some(notes) = task.notes() {
+            lines.push(format!("DESCRIPTION:{}", escape(notes)));
+        }
+        if !task.tags().is_empty() {
+            let categories: Vec<String> = task.tags().iter().map(|tag| escape(tag)).collect();
+            lines.push(format!("CATEGORIES:{}", categories.join(",")));
+        }
+        lines.push("TRANSP:TRANSPARENT".to_string());
+        lines.push("END:VEVENT".to_string());
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// The Atom feed named `name` of the tasks in `tasks` completed last.
+pub fn render_atom(tasks: &[Task], name: &str) -> String {
+    let mut completed: Vec<(NaiveDate, &Task)> = tasks
+        .iter()
+        .filter(|task| task.state() == TaskState::Done)
+        .filter_map(|task| Some((task.completed_at()?, task)))
+        .collect();
+    // Stable, so tasks completed the same day keep their order.
+    completed.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+    completed.truncate(ATOM_ENTRIES);
+
+    let timestamp = |date: NaiveDate| format!("{}T00:00:00Z", date);
+    let updated = completed.first().map_or_else(
+        || "1970-01-01T00:00:00Z".to_string(),
+        |(date, _)| timestamp(*date),
+    );
+    let id = encode_segment(name);
+    let name = publish::escape(name);
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <title>{name}: completed tasks</title>\n\
+         <id>urn:{APP_NAME}:completed:{id}</id>\n\
+         <updated>{updated}</updated>\n\
+         <author><name>{name}</name></author>\n\
+         <generator>{APP_NAME}</generator>\n"
+    );
+
+    for (date, task) in completed {
+        feed.push_str(&format!(
+            "<entry>\n<title>{}</title>\n<id>urn:uuid:{}</id>\n<updated>{}</updated>\n",
+            publish::escape(task.description()),
+            task.id(),
+            timestamp(date)
+        ));
+        if let Some(by) = task.completed_by() {
+            feed.push_str(&format!(
+                "<author><name>{}</name></author>\n",
+                publish::escape(by)
+            ));
+        }
+        if let Some(project) = task.project() {
+            feed.push_str(&format!(
+                "<category term=\"{}\"/>\n",
+                publish::escape(project)
+            ));
+        }
+        for tag in task.tags() {
+            feed.push_str(&format!("<category term=\"{}\"/>\n", publish::escape(tag)));
+        }
+        if let Some(notes) = task.notes() {
+            feed.push_str(&format!(
+                "<content type=\"text\">{}</content>\n",
+                publish::escape(notes)
+            ));
+        }
+        feed.push_str("</entry>\n");
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render() {
+        let due = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let call = Task::new("Call Sam, then email")
+            .with_due(due)
+            .with_notes("About the lease")
+            .with_tags(&["home".to_string()]);
+        let tasks = [
+            call.clone(),
+            Task::new("Undated"),
+            Task::new("Finished").with_due(due).with_completed_at(due),
+            Task::new("Shelved").with_due(due).with_archived(),
+        ];
+        let now = "2025-03-01T08:00:00Z".parse().unwrap();
+
+        let feed = render(&tasks, "to-not-do (work)", now);
+        assert_eq!(feed.matches("BEGIN:VEVENT").count(), 1);
+        assert!(feed.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(feed.ends_with("END:VCALENDAR\r\n"));
+        for line in [
+            format!("UID:{}@to-not-do", call.id()),
+            "X-WR-CALNAME:to-not-do (work)".to_string(),
+            "DTSTAMP:20250301T080000Z".to_string(),
+            "DTSTART;VALUE=DATE:20250310".to_string(),
+            "DTEND;VALUE=DATE:20250311".to_string(),
+            "SUMMARY:Call Sam\\, then email".to_string(),
+            "DESCRIPTION:About the lease".to_string(),
+            "CATEGORIES:home".to_string(),
+        ] {
+            assert!(feed.contains(&format!("{}\r\n", line)), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_render_atom() {
+        let day = |d| NaiveDate::from_ymd_opt(2025, 3, d).unwrap();
+        let mut tasks: Vec<Task> = (1..=ATOM_ENTRIES as u32 + 5)
+            .map(|n| Task::new(&format!("Chore {}", n)).with_completed_at(day(1)))
+            .collect();
+        let newest = Task::new("Fix <b> & \"quotes\"")
+            .with_completed_at(day(9))
+            .with_project("home")
+            .with_notes("Took an hour");
+        tasks.push(newest.clone());
+        tasks.push(Task::new("Still open"));
+
+        let feed = render_atom(&tasks, "to-not-do");
+        assert!(feed.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed"));
+        assert!(feed.ends_with("</feed>\n"));
+        assert_eq!(feed.matches("<entry>").count(), ATOM_ENTRIES);
+        assert!(feed.contains("<updated>2025-03-09T00:00:00Z</updated>\n<author>"));
+        assert!(!feed.contains("Still open"));
+
+        let entry = &feed[feed.find("<entry>").unwrap()..];
+        assert!(entry.starts_with(&format!(
+            "<entry>\n<title>Fix &lt;b&gt; &amp; &quot;quotes&quot;</title>\n<id>urn:uuid:{}</id>",
+            newest.id()
+        )));
+        assert!(entry
+            .contains("<category term=\"home\"/>\n<content type=\"text\">Took an hour</content>"));
+    }
+
+    #[test]
+    fn test_feed_token() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.feed");
+        let token = feed_token(&path, false).unwrap();
+        assert_eq!(token.len(), 32);
+        assert_eq!(feed_token(&path, false).unwrap(), token);
+        assert_ne!(feed_token(&path, true).unwrap(), token);
+    }
+}
@@ -0,0 +1,276 @@
+//! A C ABI over the task store, so GUI shells in other languages can use
+//! the same logic as the command line tool. Build the shared library with
+//! the `ffi` feature, declared in `include/to_not_do.h`:
+//!
+//! ```sh
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! The build generates the header from this module into its output
+//! directory; a test fails while the copy in `include/` differs from it.
+//!
+//! Strings go in and out as NUL-terminated UTF-8. Strings returned by the
+//! library belong to the caller, who frees them with [`tnd_string_free`].
+//! Failing calls return `NULL` or `-1` and leave a message for
+//! [`tnd_last_error`].
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+    ptr,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    config::StorageConfig,
+    file_management::{DatabaseManager, Task, TaskState},
+    filter::TaskFilter,
+    storage::open_storage,
+};
+
+/// An open database, as returned by [`tnd_open`].
+pub struct TndDatabase {
+    manager: DatabaseManager,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `call`, turning errors and panics into `failed` and a message for
+/// [`tnd_last_error`].
+fn guard<T>(failed: T, call: impl FnOnce() -> Result<T, String>) -> T {
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            failed
+        }
+        Err(_) => {
+            set_last_error("to-not-do panicked");
+            failed
+        }
+    }
+}
+
+/// The string `text` points to, which must be NUL-terminated.
+unsafe fn read_str<'a>(text: *const c_char, name: &str) -> Result<&'a str, String> {
+    if text.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|e| format!("{} is not UTF-8: {}", name, e))
+}
+
+fn to_c_string(text: String) -> Result<*mut c_char, String> {
+    CString::new(text)
+        .map(CString::into_raw)
+        .map_err(|e| e.to_string())
+}
+
+unsafe fn database<'a>(db: *mut TndDatabase) -> Result<&'a mut DatabaseManager, String> {
+    db.as_mut()
+        .map(|db| &mut db.manager)
+        .ok_or_else(|| "db is NULL".to_string())
+}
+
+/// Opens the database file at `path`, creating it when there is none yet.
+/// Returns `NULL` on failure. Close it with [`tnd_close`].
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tnd_open(path: *const c_char) -> *mut TndDatabase {
+    guard(ptr::null_mut(), || {
+        let path = read_str(path, "path")?;
+        let manager =
+            DatabaseManager::with_storage(open_storage(Path::new(path), &StorageConfig::default()))
+                .map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(TndDatabase { manager })))
+    })
+}
+
+/// Closes a database opened by [`tnd_open`]. Does nothing for `NULL`.
+///
+/// # Safety
+///
+/// `db` must come from [`tnd_open`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn tnd_close(db: *mut TndDatabase) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Adds a task described by `description`, returning its ID, or `NULL` on
+/// failure.
+///
+/// # Safety
+///
+/// `db` must come from [`tnd_open`] and `description` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tnd_add(db: *mut TndDatabase, description: *const c_char) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let manager = database(db)?;
+        let description = read_str(description, "description")?;
+        if description.trim().is_empty() {
+            return Err("The description is empty".to_string());
+        }
+        let task = Task::new(description);
+        manager.add_task(&task).map_err(|e| e.to_string())?;
+        to_c_string(task.id().to_string())
+    })
+}
+
+/// The tasks matching `filter`, a JSON object like the query parameters of
+/// `GET /api/tasks` (`state`, `project` and `archived`), as a JSON array.
+/// `NULL` lists the active tasks. Returns `NULL` on failure.
+///
+/// # Safety
+///
+/// `db` must come from [`tnd_open`] and `filter` must be `NULL` or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tnd_list(db: *mut TndDatabase, filter: *const c_char) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let manager = database(db)?;
+        let filter: TaskFilter = if filter.is_null() {
+            TaskFilter::default()
+        } else {
+            serde_json::from_str(read_str(filter, "filter")?)
+                .map_err(|e| format!("Invalid filter: {}", e))?
+        };
+        let tasks = manager
+            .get_tasks()
+            .map(|tasks| filter.apply(tasks))
+            .map_err(|e| e.to_string())?;
+        to_c_string(serde_json::to_string(&tasks).map_err(|e| e.to_string())?)
+    })
+}
+
+/// Marks the task with ID `id` done. Returns 0, or -1 on failure.
+///
+/// # Safety
+///
+/// `db` must come from [`tnd_open`] and `id` must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn tnd_complete(db: *mut TndDatabase, id: *const c_char) -> c_int {
+    guard(-1, || {
+        let manager = database(db)?;
+        let id = read_str(id, "id")?;
+        let id = Uuid::parse_str(id).map_err(|e| format!("Invalid ID {}: {}", id, e))?;
+        manager
+            .set_task_state(id, TaskState::Done)
+            .map_err(|e| e.to_string())?;
+        Ok(0)
+    })
+}
+
+/// The message of the last failed call on this thread, or `NULL` if none
+/// failed. It stays valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn tnd_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Frees a string returned by the library. Does nothing for `NULL`.
+///
+/// # Safety
+///
+/// `text` must come from the library and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn tnd_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Takes ownership of a string the library returned.
+    unsafe fn take(text: *mut c_char) -> String {
+        assert!(!text.is_null());
+        let owned = CStr::from_ptr(text).to_str().unwrap().to_string();
+        tnd_string_free(text);
+        owned
+    }
+
+    unsafe fn last_error() -> String {
+        CStr::from_ptr(tnd_last_error())
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_c_abi() {
+        let dir = tempdir().unwrap();
+        let path = CString::new(dir.path().join("tasks.json").to_str().unwrap()).unwrap();
+
+        unsafe {
+            let db = tnd_open(path.as_ptr());
+            assert!(!db.is_null());
+
+            let description = CString::new("Water the plants").unwrap();
+            let id = take(tnd_add(db, description.as_ptr()));
+            take(tnd_add(db, CString::new("Call Sam").unwrap().as_ptr()));
+            assert!(tnd_add(db, ptr::null()).is_null());
+            assert_eq!(last_error(), "description is NULL");
+
+            let id = CString::new(id).unwrap();
+            assert_eq!(tnd_complete(db, id.as_ptr()), 0);
+            assert_eq!(tnd_complete(db, c"nope".as_ptr()), -1);
+            assert!(last_error().starts_with("Invalid ID nope"));
+
+            let tasks: serde_json::Value =
+                serde_json::from_str(&take(tnd_list(db, ptr::null()))).unwrap();
+            assert_eq!(tasks.as_array().unwrap().len(), 2);
+            let done: serde_json::Value =
+                serde_json::from_str(&take(tnd_list(db, c"{\"state\":\"Done\"}".as_ptr())))
+                    .unwrap();
+            assert_eq!(done[0]["description"], "Water the plants");
+            assert!(tnd_list(db, c"[".as_ptr()).is_null());
+            tnd_close(db);
+
+            // The changes were saved.
+            let db = tnd_open(path.as_ptr());
+            let tasks: serde_json::Value =
+                serde_json::from_str(&take(tnd_list(db, ptr::null()))).unwrap();
+            assert_eq!(tasks.as_array().unwrap().len(), 2);
+            tnd_close(db);
+
+            assert!(tnd_list(ptr::null_mut(), ptr::null()).is_null());
+            assert_eq!(last_error(), "db is NULL");
+        }
+    }
+
+    #[test]
+    fn test_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/to_not_do.h"));
+        let committed = include_str!("../include/to_not_do.h");
+        assert!(
+            generated == committed,
+            "include/to_not_do.h is out of date; copy it from {}/to_not_do.h",
+            env!("OUT_DIR")
+        );
+    }
+}
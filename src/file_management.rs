@@ -1,45 +1,494 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt::{self, Display, Formatter},
-    fs::{File, OpenOptions},
-    io::Write,
+    ops::Deref,
     path::{Path, PathBuf},
 };
 
-use chrono::NaiveDate;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{cli::TaskState, error::ToNotDoError};
+use crate::{
+    compact::{purge_expired, PurgeReport},
+    config::{Column, CompactConfig},
+    crdt::{join_register, Stamp},
+    encryption::{self, Cipher, KeySource},
+    error::ToNotDoError,
+    journal::Change,
+    storage::{Revision, Storage},
+};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
 pub const DB_FILE_NAME: &str = "task_manager.json";
+pub const BACKUP_DIR_NAME: &str = "backups";
+/// Overrides the data directory holding the database, config and backups.
+pub const DATA_DIR_ENV: &str = "TO_NOT_DO_DATA_DIR";
+/// Directory holding a project-local database, found like `.git`.
+pub const LOCAL_DIR_NAME: &str = ".tonotdo";
+
+/// The directories the application keeps its files in. By default they
+/// follow the XDG base directory spec: the config file in
+/// `$XDG_CONFIG_HOME/to-not-do`, databases and backups in
+/// `$XDG_DATA_HOME/to-not-do`, and per-machine state such as sync cursors
+/// and the journal in `$XDG_STATE_HOME/to-not-do`. Platforms without a
+/// state directory keep state with the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppDirs {
+    pub config: PathBuf,
+    pub data: PathBuf,
+    pub state: PathBuf,
+}
+
+impl AppDirs {
+    /// Keeps everything in `dir`, as [`DATA_DIR_ENV`] asks for.
+    pub fn single(dir: &Path) -> Self {
+        Self {
+            config: dir.to_path_buf(),
+            data: dir.to_path_buf(),
+            state: dir.to_path_buf(),
+        }
+    }
+
+    /// Returns the directories to use: [`DATA_DIR_ENV`] for all of them when
+    /// it is set and not empty, otherwise the platform ones. The directories
+    /// are created if needed, and files left in the data directory by
+    /// versions that kept everything there are moved where they belong.
+    pub fn resolve(env_override: Option<std::ffi::OsString>) -> Self {
+        if let Some(dir) = env_override.filter(|dir| !dir.is_empty()) {
+            let dir = PathBuf::from(dir);
+            std::fs::create_dir_all(&dir).expect("Failed to create data directory");
+            return Self::single(&dir);
+        }
+
+        let data_base = dirs::data_dir().expect("Failed to get data directory");
+        let config_base = dirs::config_dir().unwrap_or_else(|| data_base.clone());
+        let state_base = dirs::state_dir().unwrap_or_else(|| data_base.clone());
+
+        let dirs = Self {
+            config: create_data_directory(&config_base),
+            data: create_data_directory(&data_base),
+            state: create_data_directory(&state_base),
+        };
+
+        if let Err(e) = dirs.migrate_single_directory() {
+            eprintln!("Failed to move files out of {}: {}", dirs.data.display(), e);
+        }
+
+        dirs
+    }
+
+    /// Moves config files, sync state and journals out of the data
+    /// directory, where they all used to live. Files already in their new
+    /// place win.
+    pub fn migrate_single_directory(&self) -> std::io::Result<()> {
+        let mut moves = Vec::new();
+
+        let mut data_dirs = vec![(self.data.clone(), PathBuf::new())];
+        for entry in std::fs::read_dir(self.data.join(crate::profile::PROFILES_DIR_NAME))
+            .into_iter()
+            .flatten()
+        {
+            let entry = entry?;
+            if entry.path().is_dir() {
+                let relative = Path::new(crate::profile::PROFILES_DIR_NAME).join(entry.file_name());
+                data_dirs.push((entry.path(), relative));
+            }
+        }
+
+        for (dir, relative) in data_dirs {
+            if self.config != self.data {
+                let config = crate::config::CONFIG_FILE_NAME;
+                moves.push((dir.join(config), self.config.join(&relative).join(config)));
+            }
+
+            if self.state != self.data {
+                for entry in std::fs::read_dir(&dir)? {
+                    let path = entry?.path();
+                    let state = path
+                        .extension()
+                        .is_some_and(|ext| ext == "sync" || ext == "journal" || ext == "folding");
+                    if path.is_file() && state {
+                        let target = self.state.join(&relative).join(path.file_name().unwrap());
+                        moves.push((path, target));
+                    }
+                }
+            }
+        }
+
+        for (from, to) in moves {
+            if !from.is_file() || to.exists() {
+                continue;
+            }
+
+            if let Some(parent) = to.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(&from, &to)?;
+            std::fs::remove_file(&from)?;
+            eprintln!("Moved {} to {}", from.display(), to.display());
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the application keeps its files for this invocation.
+#[derive(Debug, Clone)]
+pub struct AppPaths {
+    /// The data directory itself, holding the databases of every profile.
+    pub root_dir: PathBuf,
+    /// The config directory, holding the base config and profile overrides.
+    pub config_root: PathBuf,
+    /// The active profile, `None` for the default one.
+    pub profile: Option<String>,
+    /// Directory of the active profile; the same as `root_dir` by default.
+    pub data_dir: PathBuf,
+    /// Directory for per-machine state of the active profile.
+    pub state_dir: PathBuf,
+    pub db_file: PathBuf,
+    pub config_file: PathBuf,
+}
+
+impl AppPaths {
+    /// Paths for the default profile with everything in `data_dir`.
+    pub fn new(data_dir: &Path) -> Self {
+        Self::in_dirs(&AppDirs::single(data_dir))
+    }
+
+    /// Paths for the default profile in `dirs`.
+    pub fn in_dirs(dirs: &AppDirs) -> Self {
+        Self {
+            root_dir: dirs.data.clone(),
+            config_root: dirs.config.clone(),
+            profile: None,
+            data_dir: dirs.data.clone(),
+            state_dir: dirs.state.clone(),
+            db_file: dirs.data.join(DB_FILE_NAME),
+            config_file: dirs.config.join(crate::config::CONFIG_FILE_NAME),
+        }
+    }
+
+    /// Paths for the profile `name` in `dirs`.
+    pub fn for_profile(dirs: &AppDirs, name: &str) -> Result<Self, ToNotDoError> {
+        if name == crate::profile::DEFAULT_PROFILE {
+            return Ok(Self::in_dirs(dirs));
+        }
+
+        crate::profile::validate_name(name).map_err(ToNotDoError::ConfigError)?;
+
+        if !crate::profile::profile_exists(&dirs.data, name) {
+            return Err(ToNotDoError::ConfigError(format!(
+                "Profile '{}' does not exist; create it with `{} profile create {}`",
+                name, APP_NAME, name
+            )));
+        }
+
+        let profile_dirs = AppDirs {
+            config: crate::profile::profile_dir(&dirs.config, name),
+            data: crate::profile::profile_dir(&dirs.data, name),
+            state: crate::profile::profile_dir(&dirs.state, name),
+        };
+        Ok(Self {
+            root_dir: dirs.data.clone(),
+            config_root: dirs.config.clone(),
+            profile: Some(name.to_string()),
+            ..Self::in_dirs(&profile_dirs)
+        })
+    }
+
+    /// Paths for the project-local database directory `local_dir`, which
+    /// keeps its config and state alongside the database.
+    pub fn for_local(dirs: &AppDirs, local_dir: &Path) -> Self {
+        Self {
+            root_dir: dirs.data.clone(),
+            config_root: dirs.config.clone(),
+            ..Self::new(local_dir)
+        }
+    }
+
+    /// Whether the config file is layered over the base config, which is
+    /// the case for profiles and local databases.
+    pub fn has_config_overrides(&self) -> bool {
+        self.config_file != self.base_config_file()
+    }
+
+    /// Config file shared by every profile.
+    pub fn base_config_file(&self) -> PathBuf {
+        self.config_root.join(crate::config::CONFIG_FILE_NAME)
+    }
+
+    pub fn backup_dir(&self) -> PathBuf {
+        self.data_dir.join(BACKUP_DIR_NAME)
+    }
+
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.data_dir.join(crate::snapshot::SNAPSHOTS_DIR_NAME)
+    }
+
+    /// State file with `extension` kept for the database, in the state
+    /// directory unless `--db` points somewhere else, in which case it sits
+    /// next to the database.
+    pub fn state_file(&self, extension: &str) -> PathBuf {
+        if self.db_file.parent() != Some(self.data_dir.as_path()) {
+            return self.db_file.with_extension(extension);
+        }
+
+        let name =
+            Path::new(self.db_file.file_name().unwrap_or_default()).with_extension(extension);
+        self.state_dir.join(name)
+    }
+
+    /// Journal of the database, kept with the rest of its state.
+    pub fn journal_file(&self) -> PathBuf {
+        self.state_file("journal")
+    }
+}
+
+/// Walks up from `start` looking for a [`LOCAL_DIR_NAME`] directory.
+pub fn find_local_directory(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(LOCAL_DIR_NAME))
+        .find(|dir| dir.is_dir())
+}
 
 pub fn create_data_directory(data_dir: &Path) -> PathBuf {
     let app_dir = data_dir.join(APP_NAME);
 
     if !app_dir.exists() {
-        std::fs::create_dir(&app_dir).expect("Failed to create data directory");
+        std::fs::create_dir_all(&app_dir).expect("Failed to create data directory");
     }
 
     app_dir
 }
 
+/// Where a task stands.
+#[derive(Debug, ValueEnum, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TaskState {
+    Todo,
+    InProgress,
+    Done,
+}
+
+/// How urgent a task is.
+#[derive(Debug, ValueEnum, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+/// Which side wins when `merge` finds a task that differs between databases.
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum MergePreference {
+    Newest,
+    Ours,
+    Theirs,
+}
+
+impl MergePreference {
+    /// Whether the local side of a sync wins a field both sides changed;
+    /// `local_newer` settles [`MergePreference::Newest`].
+    pub fn local_wins(self, local_newer: bool) -> bool {
+        match self {
+            MergePreference::Ours => true,
+            MergePreference::Theirs => false,
+            MergePreference::Newest => local_newer,
+        }
+    }
+}
+
+/// One field of a three-way merge: from the side that changed it since
+/// `base`, and where both did, from `local` if `local_wins`.
+pub fn merge_field<T: PartialEq + Clone>(base: &T, local: &T, remote: &T, local_wins: bool) -> T {
+    let local_changed = local != base;
+    let remote_changed = remote != base;
+    if local_changed && (local_wins || !remote_changed) {
+        local.clone()
+    } else {
+        remote.clone()
+    }
+}
+
+/// A single task. Build one with [`Task::new`] and the `with_*` methods;
+/// once stored, it is changed through [`DatabaseManager`] so every edit is
+/// timestamped for merging and recorded in its history.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Task {
     id: Uuid,
     description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
     state: TaskState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    due: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<Priority>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
     created_at: NaiveDate,
     updated_at: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed_at: Option<NaiveDate>,
+    /// Who added the task, in a database shared by several people.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<String>,
+    /// Who marked the task done, in a database shared by several people.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed_by: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    archived: bool,
+    /// Values that only other tools use, such as Taskwarrior's user defined
+    /// attributes, kept so they survive a round trip.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<HistoryEntry>,
+    /// When each field was last edited, so copies of the task edited on
+    /// different machines can be merged field by field.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    clock: BTreeMap<TaskField, Stamp>,
+}
+
+/// Fields of a task that are merged as separate last-writer-wins registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskField {
+    Description,
+    Notes,
+    /// The state together with when and by whom the task was completed.
+    State,
+    Due,
+    Priority,
+    Tags,
+    Parent,
+    Project,
+    Archived,
+    Metadata,
+}
+
+impl TaskField {
+    const ALL: [TaskField; 10] = [
+        TaskField::Description,
+        TaskField::Notes,
+        TaskField::State,
+        TaskField::Due,
+        TaskField::Priority,
+        TaskField::Tags,
+        TaskField::Parent,
+        TaskField::Project,
+        TaskField::Archived,
+        TaskField::Metadata,
+    ];
+}
+
+impl Display for TaskField {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            TaskField::Description => "description",
+            TaskField::Notes => "notes",
+            TaskField::State => "state",
+            TaskField::Due => "due",
+            TaskField::Priority => "priority",
+            TaskField::Tags => "tags",
+            TaskField::Parent => "parent",
+            TaskField::Project => "project",
+            TaskField::Archived => "archived",
+            TaskField::Metadata => "metadata",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Task field that a search query matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+    Description,
+    Notes,
+    Tag,
+}
+
+impl Display for SearchField {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SearchField::Description => write!(f, "description"),
+            SearchField::Notes => write!(f, "notes"),
+            SearchField::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub task: Task,
+    /// Every matching field with the text it matched in. Notes are reduced
+    /// to the lines that contain the query.
+    pub fields: Vec<(SearchField, String)>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    pub event: String,
+    /// Who made the change, in a database shared by several people.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
 }
 
 impl Display for Task {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Task: {}", self.description)?;
+
+        if let Some(notes) = &self.notes {
+            write!(f, "\nNotes: {}", notes)?;
+        }
+
+        write!(f, "\nState: {:?}", self.state)?;
+
+        if let Some(due) = self.due {
+            write!(f, "\nDue: {}", due)?;
+        }
+
+        if let Some(priority) = self.priority {
+            write!(f, "\nPriority: {:?}", priority)?;
+        }
+
+        if !self.tags.is_empty() {
+            write!(f, "\nTags: {}", self.tags.join(", "))?;
+        }
+
+        if let Some(project) = &self.project {
+            write!(f, "\nProject: {}", project)?;
+        }
+
+        if let Some(created_by) = &self.created_by {
+            write!(f, "\nCreated by: {}", created_by)?;
+        }
+
+        if let Some(completed_by) = &self.completed_by {
+            write!(f, "\nCompleted by: {}", completed_by)?;
+        }
+
+        if !self.metadata.is_empty() {
+            let metadata: Vec<String> = self
+                .metadata
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            write!(f, "\nMetadata: {}", metadata.join(", "))?;
+        }
+
         write!(
             f,
-            "Task: {}\nState: {:?}\nCreated at: {}\nUpdated at: {}\nId: {}",
-            self.description, self.state, self.created_at, self.updated_at, self.id
+            "\nCreated at: {}\nUpdated at: {}\nId: {}",
+            self.created_at, self.updated_at, self.id
         )
     }
 }
@@ -49,380 +498,2944 @@ impl Task {
         Self {
             id: Uuid::new_v4(),
             description: description.to_string(),
+            notes: None,
+            due: None,
+            priority: None,
+            tags: Vec::new(),
+            parent: None,
+            project: None,
             state: TaskState::Todo,
             created_at: chrono::Utc::now().date_naive(),
             updated_at: chrono::Utc::now().date_naive(),
+            completed_at: None,
+            created_by: None,
+            completed_by: None,
+            archived: false,
+            metadata: BTreeMap::new(),
+            history: Vec::new(),
+            clock: BTreeMap::new(),
         }
     }
 
-    fn set_state(&mut self, state: TaskState) {
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    pub fn with_notes(mut self, notes: &str) -> Self {
+        self.notes = Some(notes.to_string());
+        self
+    }
+
+    pub fn with_due(mut self, due: NaiveDate) -> Self {
+        self.due = Some(due);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags = tags.to_vec();
+        self
+    }
+
+    pub fn with_parent(mut self, parent: Uuid) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn with_project(mut self, project: &str) -> Self {
+        self.project = Some(project.to_string());
+        self
+    }
+
+    /// Keeps the ID a task had in another tool, so importing it twice does
+    /// not duplicate it.
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn with_state(mut self, state: TaskState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn with_created_at(mut self, created_at: NaiveDate) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    pub fn with_archived(mut self) -> Self {
+        self.archived = true;
+        self
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Marks the task done on `completed_at`.
+    pub fn with_completed_at(mut self, completed_at: NaiveDate) -> Self {
+        self.state = TaskState::Done;
+        self.completed_at = Some(completed_at);
+        self
+    }
+
+    pub fn with_created_by(mut self, user: &str) -> Self {
+        self.created_by = Some(user.to_string());
+        self
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    pub fn state(&self) -> TaskState {
+        self.state
+    }
+
+    pub fn due(&self) -> Option<NaiveDate> {
+        self.due
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    pub fn parent(&self) -> Option<Uuid> {
+        self.parent
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn created_at(&self) -> NaiveDate {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> NaiveDate {
+        self.updated_at
+    }
+
+    pub fn completed_at(&self) -> Option<NaiveDate> {
+        self.completed_at
+    }
+
+    pub fn created_by(&self) -> Option<&str> {
+        self.created_by.as_deref()
+    }
+
+    pub fn completed_by(&self) -> Option<&str> {
+        self.completed_by.as_deref()
+    }
+
+    /// Whether `user` added or completed the task, or made a change recorded
+    /// in its history.
+    pub fn touched_by(&self, user: &str) -> bool {
+        self.created_by.as_deref() == Some(user)
+            || self.completed_by.as_deref() == Some(user)
+            || self.history.iter().any(|e| e.by.as_deref() == Some(user))
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Sets the state, noting `user` as who completed the task when it is
+    /// newly done.
+    fn set_state(&mut self, state: TaskState, user: Option<&str>) {
+        let today = chrono::Utc::now().date_naive();
+
+        if state == TaskState::Done && self.state != TaskState::Done {
+            self.completed_at = Some(today);
+            self.completed_by = user.map(str::to_string);
+        } else if state != TaskState::Done {
+            self.completed_at = None;
+            self.completed_by = None;
+        }
+
         self.state = state;
-        self.updated_at = chrono::Utc::now().date_naive();
+        self.touch(TaskField::State);
     }
 
     fn set_description(&mut self, description: &str) {
         self.description = description.to_string();
-        self.updated_at = chrono::Utc::now().date_naive();
+        self.touch(TaskField::Description);
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Database {
-    name: String,
-    version: String,
-    tasks: Vec<Task>,
-}
+    fn set_notes(&mut self, notes: Option<&str>) {
+        self.notes = notes.map(str::to_string);
+        self.touch(TaskField::Notes);
+    }
 
-impl Default for Database {
-    fn default() -> Self {
-        Self {
-            name: APP_NAME.to_string(),
-            version: VERSION.to_string(),
-            tasks: Vec::new(),
+    fn set_due(&mut self, due: Option<NaiveDate>) {
+        self.due = due;
+        self.touch(TaskField::Due);
+    }
+
+    fn set_priority(&mut self, priority: Option<Priority>) {
+        self.priority = priority;
+        self.touch(TaskField::Priority);
+    }
+
+    fn set_metadata(&mut self, key: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self.metadata.insert(key.to_string(), value.to_string()),
+            None => self.metadata.remove(key),
+        };
+        self.touch(TaskField::Metadata);
+    }
+
+    fn set_project(&mut self, project: Option<&str>) {
+        self.project = project.map(str::to_string);
+        self.touch(TaskField::Project);
+    }
+
+    fn set_parent(&mut self, parent: Option<Uuid>) {
+        self.parent = parent;
+        self.touch(TaskField::Parent);
+    }
+
+    /// Marks `field` as edited now.
+    fn touch(&mut self, field: TaskField) {
+        self.updated_at = Utc::now().date_naive();
+        self.clock.insert(field, Stamp::now());
+    }
+
+    /// Whether every field that is edited holds the same value here and in
+    /// `other`.
+    pub(crate) fn same_fields(&self, other: &Task) -> bool {
+        TaskField::ALL
+            .into_iter()
+            .all(|field| self.same_field(other, field))
+    }
+
+    /// The fields edited both here and in `theirs` since they were as in
+    /// `base`, to different values. Merging keeps whichever was edited last
+    /// and drops the other.
+    pub fn conflicting_fields(&self, base: &Task, theirs: &Task) -> Vec<TaskField> {
+        TaskField::ALL
+            .into_iter()
+            .filter(|&field| {
+                !self.same_field(base, field)
+                    && !theirs.same_field(base, field)
+                    && !self.same_field(theirs, field)
+            })
+            .collect()
+    }
+
+    /// `field` of this task written out for people.
+    pub fn show_field(&self, field: TaskField) -> String {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+        match field {
+            TaskField::Description => self.description.clone(),
+            TaskField::Notes => or_none(self.notes.clone()),
+            TaskField::State => {
+                let mut state = format!("{:?}", self.state);
+                if let Some(completed_at) = self.completed_at {
+                    state.push_str(&format!(" on {}", completed_at));
+                }
+                if let Some(completed_by) = &self.completed_by {
+                    state.push_str(&format!(" by {}", completed_by));
+                }
+                state
+            }
+            TaskField::Due => or_none(self.due.map(|due| due.to_string())),
+            TaskField::Priority => or_none(self.priority.map(|p| format!("{:?}", p))),
+            TaskField::Tags if self.tags.is_empty() => or_none(None),
+            TaskField::Tags => self.tags.join(", "),
+            TaskField::Parent => or_none(self.parent.map(|parent| parent.to_string())),
+            TaskField::Project => or_none(self.project.clone()),
+            TaskField::Archived => if self.archived { "yes" } else { "no" }.to_string(),
+            TaskField::Metadata if self.metadata.is_empty() => or_none(None),
+            TaskField::Metadata => self
+                .metadata
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(", "),
         }
     }
-}
 
-pub struct DatabaseManager {
-    db_path: PathBuf,
-    db: Database,
-}
+    /// Whether `field` holds the same value here and in `other`.
+    fn same_field(&self, other: &Task, field: TaskField) -> bool {
+        match field {
+            TaskField::Description => self.description == other.description,
+            TaskField::Notes => self.notes == other.notes,
+            TaskField::State => {
+                (self.state, self.completed_at, &self.completed_by)
+                    == (other.state, other.completed_at, &other.completed_by)
+            }
+            TaskField::Due => self.due == other.due,
+            TaskField::Priority => self.priority == other.priority,
+            TaskField::Tags => self.tags == other.tags,
+            TaskField::Parent => self.parent == other.parent,
+            TaskField::Project => self.project == other.project,
+            TaskField::Archived => self.archived == other.archived,
+            TaskField::Metadata => self.metadata == other.metadata,
+        }
+    }
+
+    /// Takes `field` from `other`, along with when it was edited there.
+    fn copy_field(&mut self, other: &Task, field: TaskField) {
+        match field {
+            TaskField::Description => self.description = other.description.clone(),
+            TaskField::Notes => self.notes = other.notes.clone(),
+            TaskField::State => {
+                (self.state, self.completed_at, self.completed_by) =
+                    (other.state, other.completed_at, other.completed_by.clone());
+            }
+            TaskField::Due => self.due = other.due,
+            TaskField::Priority => self.priority = other.priority,
+            TaskField::Tags => self.tags = other.tags.clone(),
+            TaskField::Parent => self.parent = other.parent,
+            TaskField::Project => self.project = other.project.clone(),
+            TaskField::Archived => self.archived = other.archived,
+            TaskField::Metadata => self.metadata = other.metadata.clone(),
+        }
+
+        match other.clock.get(&field) {
+            Some(&stamp) => self.clock.insert(field, stamp),
+            None => self.clock.remove(&field),
+        };
+    }
+
+    /// Merges another copy of this task into this one: every field keeps the
+    /// value that was edited last, and the histories are combined. Merging
+    /// copies in any order gives the same task.
+    fn merge(&mut self, other: &Task) {
+        fn join<T: Clone + PartialEq + Serialize>(
+            clock: &mut BTreeMap<TaskField, Stamp>,
+            other_clock: &BTreeMap<TaskField, Stamp>,
+            field: TaskField,
+            ours: &mut T,
+            theirs: &T,
+        ) {
+            let their_stamp = other_clock.get(&field).copied();
+            if join_register((ours, clock.get(&field).copied()), (theirs, their_stamp)) {
+                if let Some(stamp) = their_stamp {
+                    clock.insert(field, stamp);
+                }
+            }
+        }
+
+        let clock = &mut self.clock;
+        let theirs = &other.clock;
+        join(
+            clock,
+            theirs,
+            TaskField::Description,
+            &mut self.description,
+            &other.description,
+        );
+        join(
+            clock,
+            theirs,
+            TaskField::Notes,
+            &mut self.notes,
+            &other.notes,
+        );
+        join(clock, theirs, TaskField::Due, &mut self.due, &other.due);
+        join(
+            clock,
+            theirs,
+            TaskField::Priority,
+            &mut self.priority,
+            &other.priority,
+        );
+        join(clock, theirs, TaskField::Tags, &mut self.tags, &other.tags);
+        join(
+            clock,
+            theirs,
+            TaskField::Parent,
+            &mut self.parent,
+            &other.parent,
+        );
+        join(
+            clock,
+            theirs,
+            TaskField::Project,
+            &mut self.project,
+            &other.project,
+        );
+        join(
+            clock,
+            theirs,
+            TaskField::Archived,
+            &mut self.archived,
+            &other.archived,
+        );
+        join(
+            clock,
+            theirs,
+            TaskField::Metadata,
+            &mut self.metadata,
+            &other.metadata,
+        );
+
+        let mut state = (self.state, self.completed_at, self.completed_by.take());
+        join(
+            clock,
+            theirs,
+            TaskField::State,
+            &mut state,
+            &(other.state, other.completed_at, other.completed_by.clone()),
+        );
+        (self.state, self.completed_at, self.completed_by) = state;
+
+        self.merge_history(other);
+    }
+
+    /// Combines the histories of two copies of this task, along with when
+    /// they were created and last updated.
+    fn merge_history(&mut self, other: &Task) {
+        for entry in &other.history {
+            if !self.history.contains(entry) {
+                self.history.push(entry.clone());
+            }
+        }
+        self.history
+            .sort_by(|a, b| (a.at, &a.event).cmp(&(b.at, &b.event)));
+
+        self.created_at = self.created_at.min(other.created_at);
+        self.updated_at = self.updated_at.max(other.updated_at);
+    }
+
+    /// The first eight characters of the task's UUID.
+    pub fn short_id(&self) -> String {
+        self.id.simple().to_string()[..8].to_string()
+    }
+
+    /// Renders the task as `<short-id> <state> <due> <description>`, with `-`
+    /// standing in for a missing due date.
+    pub fn oneline(&self) -> String {
+        let state = self
+            .state
+            .to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default();
+        let due = self
+            .due
+            .map_or_else(|| "-".to_string(), |due| due.to_string());
+
+        format!("{} {} {} {}", self.short_id(), state, due, self.description)
+    }
+
+    /// Renders the value shown for this task in a `list --columns` table.
+    pub fn column_value(&self, column: Column) -> String {
+        let date = |date: Option<NaiveDate>| date.map(|d| d.to_string()).unwrap_or_default();
+
+        match column {
+            Column::Id => self.short_id(),
+            Column::Desc => self.description.clone(),
+            Column::State => format!("{:?}", self.state),
+            Column::Due => date(self.due),
+            Column::Priority => self
+                .priority
+                .map(|p| format!("{:?}", p))
+                .unwrap_or_default(),
+            Column::Tags => self.tags.join(","),
+            Column::Project => self.project.clone().unwrap_or_default(),
+            Column::Created => date(Some(self.created_at)),
+            Column::Updated => date(Some(self.updated_at)),
+        }
+    }
+
+    /// When the task was last changed, as precisely as it is known: the
+    /// update date, refined by the latest history entry.
+    fn last_modified(&self) -> (NaiveDate, Option<DateTime<Utc>>) {
+        (self.updated_at, self.history.last().map(|entry| entry.at))
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// Whether the task is open, not archived and was due before `today`.
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.state != TaskState::Done && !self.archived && self.due.is_some_and(|due| due < today)
+    }
+
+    /// The due date `by` after the current one, or after today when the
+    /// task has no due date or is already overdue; `None` when that is past
+    /// the last date there is.
+    fn postponed_due(&self, by: Duration) -> Option<NaiveDate> {
+        let today = Utc::now().date_naive();
+        let from = self.due.map_or(today, |due| due.max(today));
+        from.checked_add_signed(by)
+    }
+
+    /// Pushes the due date forward by `by` as [`Task::postponed_due`] tells,
+    /// and records that `user` did. The task is left alone when the date
+    /// would be out of range.
+    fn postpone(&mut self, by: Duration, user: Option<&str>) -> Result<NaiveDate, ToNotDoError> {
+        let due = self.postponed_due(by).ok_or(ToNotDoError::DatabaseError(
+            crate::error::DatabaseError::DueDateOutOfRange(self.id),
+        ))?;
+
+        let previous = self
+            .due
+            .map_or_else(|| "none".to_string(), |due| due.to_string());
+        self.record(
+            &format!("Postponed due date from {} to {}", previous, due),
+            user,
+        );
+        self.set_due(Some(due));
+
+        Ok(due)
+    }
+
+    fn set_tags(&mut self, tags: &[String]) {
+        self.tags = tags.to_vec();
+        self.touch(TaskField::Tags);
+    }
+
+    fn archive(&mut self, user: Option<&str>) {
+        self.archived = true;
+        self.touch(TaskField::Archived);
+        self.record("Archived", user);
+    }
+
+    /// When the task was archived, going by its last update for tasks
+    /// archived before edits were timestamped.
+    fn archived_at(&self) -> Option<DateTime<Utc>> {
+        if !self.archived {
+            return None;
+        }
+
+        Some(match self.clock.get(&TaskField::Archived) {
+            Some(stamp) => stamp.at,
+            None => self.updated_at.and_time(chrono::NaiveTime::MIN).and_utc(),
+        })
+    }
+
+    /// Replaces the history entries made before `before` with a single one
+    /// saying how many there were, returning how many entries were dropped.
+    fn compact_history(&mut self, before: DateTime<Utc>) -> usize {
+        let old = self.history.iter().take_while(|e| e.at < before).count();
+        if old < 2 {
+            return 0;
+        }
+
+        let summary = HistoryEntry {
+            at: self.history[old - 1].at,
+            event: format!("{} earlier events compacted", old),
+            by: None,
+        };
+        self.history.splice(..old, [summary]);
+
+        old - 1
+    }
+
+    fn record(&mut self, event: &str, user: Option<&str>) {
+        self.history.push(HistoryEntry {
+            at: Utc::now(),
+            event: event.to_string(),
+            by: user.map(str::to_string),
+        });
+    }
+}
+
+/// The tasks of a database in the order they were added, indexed by ID so
+/// finding one does not go through them all. Saved as a plain list.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Tasks {
+    list: Vec<Task>,
+    /// Where each task is in `list`.
+    index: HashMap<Uuid, usize>,
+}
+
+impl Tasks {
+    fn get(&self, id: Uuid) -> Option<&Task> {
+        self.index.get(&id).map(|&i| &self.list[i])
+    }
+
+    fn get_mut(&mut self, id: Uuid) -> Option<&mut Task> {
+        self.index.get(&id).map(|&i| &mut self.list[i])
+    }
+
+    /// Adds `task` at the end; there must be no task with its ID yet.
+    fn push(&mut self, task: Task) {
+        self.index.entry(task.id).or_insert(self.list.len());
+        self.list.push(task);
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&Task) -> bool) {
+        self.list.retain(keep);
+        self.reindex();
+    }
+
+    fn iter_mut(&mut self) -> std::slice::IterMut<'_, Task> {
+        self.list.iter_mut()
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, task) in self.list.iter().enumerate() {
+            // A damaged file may repeat an ID; the first task wins, as
+            // when looking it up in the list.
+            self.index.entry(task.id).or_insert(i);
+        }
+    }
+}
+
+impl Deref for Tasks {
+    type Target = [Task];
+
+    fn deref(&self) -> &[Task] {
+        &self.list
+    }
+}
+
+impl From<Vec<Task>> for Tasks {
+    fn from(list: Vec<Task>) -> Self {
+        let mut tasks = Self {
+            list,
+            index: HashMap::new(),
+        };
+        tasks.reindex();
+        tasks
+    }
+}
+
+impl IntoIterator for Tasks {
+    type Item = Task;
+    type IntoIter = std::vec::IntoIter<Task>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.into_iter()
+    }
+}
+
+impl Serialize for Tasks {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.list.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tasks {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Every task in a to-do list, as saved to disk, plus what is needed to
+/// merge it with other copies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Database {
+    name: String,
+    version: String,
+    #[serde(default)]
+    schema_version: u32,
+    tasks: Tasks,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    focus: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+    /// IDs of deleted tasks and when they were deleted, kept so a merge does
+    /// not bring them back.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    deleted: BTreeMap<Uuid, DateTime<Utc>>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            name: APP_NAME.to_string(),
+            version: VERSION.to_string(),
+            schema_version: crate::migration::SCHEMA_VERSION,
+            tasks: Tasks::default(),
+            focus: None,
+            context: None,
+            deleted: BTreeMap::new(),
+        }
+    }
+}
+
+impl Database {
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    /// The task with ID `id`, looked up through the index.
+    pub fn task(&self, id: Uuid) -> Option<&Task> {
+        self.tasks.get(id)
+    }
+
+    pub fn insert_task(&mut self, task: Task) -> Result<(), ToNotDoError> {
+        if self.tasks.get(task.id).is_some() {
+            return Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::UuidAlreadyExists(task.id),
+            ));
+        }
+
+        self.tasks.push(task);
+        Ok(())
+    }
+
+    /// Adds `task`, replacing the task with the same ID if there is one.
+    pub fn put_task(&mut self, task: Task) {
+        match self.tasks.get_mut(task.id) {
+            Some(existing) => *existing = task,
+            None => self.tasks.push(task),
+        }
+    }
+
+    /// Moves the tasks of each project into a database of their own, leaving
+    /// the tasks without a project and everything else here.
+    pub fn split_by_project(&mut self) -> BTreeMap<String, Database> {
+        let mut projects: BTreeMap<String, Database> = BTreeMap::new();
+
+        let (in_project, rest): (Vec<Task>, Vec<Task>) = std::mem::take(&mut self.tasks)
+            .into_iter()
+            .partition(|t| t.project.is_some());
+        self.tasks = rest.into();
+
+        for task in in_project {
+            let project = task.project.clone().unwrap_or_default();
+            projects.entry(project).or_default().tasks.push(task);
+        }
+
+        projects
+    }
+
+    /// Lists the changes that turn this database into `newer`. Tasks are
+    /// matched up through the id index on both sides, so this takes time
+    /// linear in the number of tasks.
+    pub fn changes_to(&self, newer: &Database) -> Vec<Change> {
+        let removed: BTreeSet<Uuid> = self
+            .tasks
+            .iter()
+            .filter(|task| newer.tasks.get(task.id).is_none())
+            .map(|task| task.id)
+            .chain(
+                newer
+                    .deleted
+                    .keys()
+                    .filter(|id| !self.deleted.contains_key(id))
+                    .copied(),
+            )
+            .collect();
+
+        let mut changes: Vec<Change> = removed
+            .into_iter()
+            .map(|id| Change::RemoveTask { id })
+            .collect();
+
+        changes.extend(
+            newer
+                .tasks
+                .iter()
+                .filter(|task| self.tasks.get(task.id) != Some(task))
+                .map(|task| Change::PutTask {
+                    task: Box::new(task.clone()),
+                }),
+        );
+
+        if self.focus != newer.focus {
+            changes.push(Change::SetFocus { focus: newer.focus });
+        }
+
+        if self.context != newer.context {
+            changes.push(Change::SetContext {
+                context: newer.context.clone(),
+            });
+        }
+
+        changes
+    }
+
+    /// Applies a change recorded by [`Database::changes_to`]. Adding a task
+    /// that already exists replaces it in place.
+    pub fn apply(&mut self, change: Change) {
+        match change {
+            Change::PutTask { task } => self.put_task(*task),
+            Change::RemoveTask { id } => self.remove_task(id),
+            Change::SetFocus { focus } => self.focus = focus,
+            Change::SetContext { context } => self.context = context,
+        }
+    }
+
+    /// Removes the task with `id` for good: merges will not bring it back.
+    fn remove_task(&mut self, id: Uuid) {
+        self.tasks.retain(|t| t.id != id);
+        self.deleted.entry(id).or_insert_with(Utc::now);
+    }
+
+    /// Merges `other` into this database. Tasks present in both are merged
+    /// field by field and deletions on either side win, so replicas that
+    /// merge each other's copies in any order end up with the same tasks.
+    /// The focus and context stay as they are here.
+    pub fn merge(&mut self, other: &Database) {
+        for (&id, &at) in &other.deleted {
+            let ours = self.deleted.entry(id).or_insert(at);
+            *ours = (*ours).min(at);
+        }
+
+        for theirs in other.tasks.iter() {
+            self.merge_task(theirs);
+        }
+
+        let deleted = &self.deleted;
+        self.tasks.retain(|t| !deleted.contains_key(&t.id));
+    }
+
+    /// Forgets deletions made before `deleted_before`, after which a replica
+    /// that still has the task could bring it back, and folds the history
+    /// entries of every task made before `history_before` into one. Returns
+    /// how many deletions and history entries were dropped.
+    pub fn compact(
+        &mut self,
+        deleted_before: DateTime<Utc>,
+        history_before: Option<DateTime<Utc>>,
+    ) -> (usize, usize) {
+        let deleted = self.deleted.len();
+        self.deleted.retain(|_, at| *at >= deleted_before);
+
+        let history = history_before.map_or(0, |before| {
+            self.tasks
+                .iter_mut()
+                .map(|task| task.compact_history(before))
+                .sum()
+        });
+
+        (deleted - self.deleted.len(), history)
+    }
+
+    /// Deletes the tasks archived before `before`, returning how many there
+    /// were.
+    pub fn purge_archived(&mut self, before: DateTime<Utc>) -> usize {
+        let expired: Vec<Uuid> = self
+            .tasks
+            .iter()
+            .filter(|t| t.archived_at().is_some_and(|at| at < before))
+            .map(|t| t.id)
+            .collect();
+
+        for &id in &expired {
+            self.remove_task(id);
+        }
+
+        expired.len()
+    }
+
+    /// Applies a change received from another replica. Unlike
+    /// [`Database::apply`], tasks are merged rather than replaced, and the
+    /// focus and context stay as they are here.
+    pub fn merge_change(&mut self, change: &Change) {
+        match change {
+            Change::PutTask { task } => self.merge_task(task),
+            Change::RemoveTask { id } => self.remove_task(*id),
+            Change::SetFocus { .. } | Change::SetContext { .. } => {}
+        }
+    }
+
+    fn merge_task(&mut self, theirs: &Task) {
+        if self.deleted.contains_key(&theirs.id) {
+            return;
+        }
+
+        match self.tasks.get_mut(theirs.id) {
+            Some(ours) => ours.merge(theirs),
+            None => self.tasks.push(theirs.clone()),
+        }
+    }
+}
+
+/// A task that exists in both merged databases with different contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub ours: Task,
+    pub theirs: Task,
+    /// Whether the other database's version was kept.
+    pub took_theirs: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub added: usize,
+    pub identical: usize,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// A self-contained copy of the database and configuration file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub app: String,
+    pub version: String,
+    pub created_at: DateTime<Utc>,
+    pub database: Database,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+}
+
+impl Backup {
+    /// File name for a backup taken at `at`, e.g.
+    /// `to-not-do-backup-20240131-120000.json`.
+    pub fn file_name(at: DateTime<Utc>) -> String {
+        format!("{}-backup-{}.json", APP_NAME, at.format("%Y%m%d-%H%M%S"))
+    }
+
+    /// Reads and validates a backup written by [`Backup::write`], decrypting
+    /// it with a key from `keys` when it is encrypted.
+    pub fn read(path: &Path, keys: Option<&mut dyn KeySource>) -> Result<Self, ToNotDoError> {
+        let invalid = |reason: String| {
+            ToNotDoError::DatabaseError(crate::error::DatabaseError::InvalidBackup(reason))
+        };
+
+        let mut contents = std::fs::read(path).map_err(|e| {
+            ToNotDoError::DatabaseError(crate::error::DatabaseError::FailedToReadFile(e))
+        })?;
+        if let Some(salt) = encryption::salt_of(&contents) {
+            let keys = keys.ok_or_else(|| {
+                ToNotDoError::EncryptionError(format!("{} is encrypted", path.display()))
+            })?;
+            contents = keys.cipher(Some(salt))?.decrypt(&contents)?;
+        }
+
+        let mut backup: serde_json::Value =
+            serde_json::from_slice(&contents).map_err(|e| invalid(e.to_string()))?;
+        if let Some(database) = backup.get_mut("database") {
+            crate::migration::migrate(database)?;
+        }
+
+        let backup: Self = serde_json::from_value(backup).map_err(|e| invalid(e.to_string()))?;
+
+        if backup.app != APP_NAME {
+            return Err(invalid(format!(
+                "written by {}, not {}",
+                backup.app, APP_NAME
+            )));
+        }
+
+        let mut ids = std::collections::HashSet::new();
+        if let Some(task) = backup.database.tasks.iter().find(|t| !ids.insert(t.id)) {
+            return Err(invalid(format!("duplicate task {}", task.id)));
+        }
+
+        Ok(backup)
+    }
+
+    /// Writes the backup to `output`, encrypted with `cipher` if given. When
+    /// `output` is a directory, or a path without an extension, the backup
+    /// gets a timestamped file name inside it.
+    pub fn write(&self, output: &Path, cipher: Option<&Cipher>) -> Result<PathBuf, ToNotDoError> {
+        let write_error =
+            |e| ToNotDoError::DatabaseError(crate::error::DatabaseError::FailedToWriteFile(e));
+
+        let path = if output.is_dir() || output.extension().is_none() {
+            output.join(Self::file_name(self.created_at))
+        } else {
+            output.to_path_buf()
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(write_error)?;
+        }
+
+        let mut data = serde_json::to_vec_pretty(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        if let Some(cipher) = cipher {
+            data = cipher.encrypt(&data)?;
+        }
+
+        crate::storage::write_atomically(&path, &data).map_err(write_error)?;
+
+        Ok(path)
+    }
+}
+
+/// Reads and changes the tasks in a [`Database`], saving every change
+/// through its [`Storage`] unless a batch started with
+/// [`DatabaseManager::begin`] is in progress.
+pub struct DatabaseManager {
+    storage: Box<dyn Storage>,
+    db: Database,
+    /// The database as last loaded from or saved to storage.
+    base: Database,
+    in_batch: bool,
+    dirty: bool,
+    /// Retention policies applied on every save, when `compact.auto` is set.
+    retention: Option<CompactConfig>,
+    /// Who is making the changes, recorded on the tasks they touch.
+    user: Option<String>,
+    /// The database as of the last [`DatabaseManager::watch`].
+    watched: Option<Database>,
+    /// The storage's revision when it was last loaded or saved; it is only
+    /// loaded again once something else wrote to it.
+    revision: Option<Revision>,
+}
+
+impl DatabaseManager {
+    /// Opens the plain JSON database at `path_to_db`, creating it if needed.
+    pub fn open(path_to_db: &Path) -> Result<Self, ToNotDoError> {
+        Self::with_storage(Box::new(crate::storage::FileStorage::new(path_to_db)))
+    }
+
+    pub fn with_storage(mut storage: Box<dyn Storage>) -> Result<Self, ToNotDoError> {
+        let revision = storage.revision();
+        let db = storage.open()?;
+
+        Ok(Self {
+            storage,
+            base: db.clone(),
+            db,
+            in_batch: false,
+            dirty: false,
+            retention: None,
+            user: None,
+            watched: None,
+            revision,
+        })
+    }
+
+    /// Applies the retention policies in `options` every time the database
+    /// is saved from now on.
+    pub fn set_retention(&mut self, options: CompactConfig) {
+        self.retention = Some(options);
+    }
+
+    /// Records `user` as who made the changes from now on, for databases
+    /// shared by several people.
+    pub fn set_user(&mut self, user: Option<&str>) {
+        self.user = user.map(str::to_string);
+    }
+
+    /// Applies the retention policies in `options` now, see
+    /// [`purge_expired`].
+    pub fn purge_expired(&mut self, options: &CompactConfig) -> Result<PurgeReport, ToNotDoError> {
+        self.refresh()?;
+
+        let report = purge_expired(&mut self.db, options);
+        if !report.is_empty() {
+            self.persist()?;
+        }
+
+        Ok(report)
+    }
+
+    pub fn update_description(
+        &mut self,
+        task_id: Uuid,
+        description: &str,
+    ) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_description(description);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn get_task(&self, task_id: Uuid) -> Option<&Task> {
+        self.db.tasks.get(task_id)
+    }
+
+    pub fn contains_task(&mut self, task_id: Uuid) -> bool {
+        self.db.tasks.get(task_id).is_some()
+    }
+
+    /// Replaces the stored task with the same ID by `task`. Only the fields
+    /// that differ count as edited, so a merge still keeps edits made to the
+    /// other fields elsewhere. The history and creation date stay as stored.
+    pub fn update_task(&mut self, task: &Task) -> Result<(), ToNotDoError> {
+        self.refresh()?;
+
+        let Some(stored) = self.db.tasks.get_mut(task.id) else {
+            return Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task.id),
+            ));
+        };
+
+        let was_done = stored.state == TaskState::Done;
+        let mut changed = false;
+        for field in TaskField::ALL {
+            if !stored.same_field(task, field) {
+                stored.copy_field(task, field);
+                stored.touch(field);
+                changed = true;
+            }
+        }
+
+        if !was_done && stored.state == TaskState::Done && stored.completed_by.is_none() {
+            stored.completed_by = self.user.clone();
+        }
+
+        if changed {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    /// Sets `field` of the stored task with the same ID to its value in
+    /// `source`, as a new edit, so it wins when merged with the copies
+    /// elsewhere.
+    pub fn take_field(&mut self, source: &Task, field: TaskField) -> Result<(), ToNotDoError> {
+        self.refresh()?;
+
+        let Some(stored) = self.db.tasks.get_mut(source.id) else {
+            return Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(source.id),
+            ));
+        };
+        stored.copy_field(source, field);
+        stored.touch(field);
+        self.persist()
+    }
+
+    /// Returns how the tasks changed since the previous call, whether here
+    /// or by another process sharing the storage. The first call reports
+    /// every task as added.
+    pub fn watch(&mut self) -> Result<Vec<Change>, ToNotDoError> {
+        self.reload(&mut |_, _| false)?;
+
+        let previous = self.watched.replace(self.db.clone()).unwrap_or_default();
+        Ok(previous
+            .changes_to(&self.db)
+            .into_iter()
+            .filter(|change| matches!(change, Change::PutTask { .. } | Change::RemoveTask { .. }))
+            .collect())
+    }
+
+    pub fn delete_task(&mut self, task_id: Uuid) -> Result<(), ToNotDoError> {
+        if let Some(removed) = self.db.tasks.get(task_id).cloned() {
+            self.db.remove_task(task_id);
+
+            for child in self
+                .db
+                .tasks
+                .iter_mut()
+                .filter(|t| t.parent == Some(task_id))
+            {
+                child.set_parent(removed.parent);
+            }
+
+            if self.db.focus == Some(task_id) {
+                self.db.focus = None;
+            }
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn set_task_state(&mut self, task_id: Uuid, state: TaskState) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_state(state, self.user.as_deref());
+            if state == TaskState::Done && self.db.focus == Some(task_id) {
+                self.db.focus = None;
+            }
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn set_project(
+        &mut self,
+        task_id: Uuid,
+        project: Option<&str>,
+    ) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_project(project);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn set_notes(&mut self, task_id: Uuid, notes: Option<&str>) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_notes(notes);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    /// Sets the metadata `key` of a task to `value`, or removes it.
+    pub fn set_metadata(
+        &mut self,
+        task_id: Uuid,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_metadata(key, value);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn set_due(&mut self, task_id: Uuid, due: Option<NaiveDate>) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_due(due);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn set_priority(
+        &mut self,
+        task_id: Uuid,
+        priority: Option<Priority>,
+    ) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_priority(priority);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn set_tags(&mut self, task_id: Uuid, tags: &[String]) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.set_tags(tags);
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    pub fn postpone_task(
+        &mut self,
+        task_id: Uuid,
+        by: Duration,
+    ) -> Result<NaiveDate, ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            let due = task.postpone(by, self.user.as_deref())?;
+            self.persist()?;
+            Ok(due)
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    /// Postpones every overdue task by `by`, returning how many were moved.
+    /// None are moved when any would end up out of range.
+    pub fn postpone_overdue(&mut self, by: Duration) -> Result<usize, ToNotDoError> {
+        let today = Utc::now().date_naive();
+        let mut postponed = 0;
+
+        if let Some(task) = self
+            .db
+            .tasks
+            .iter()
+            .find(|t| t.is_overdue(today) && t.postponed_due(by).is_none())
+        {
+            return Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::DueDateOutOfRange(task.id),
+            ));
+        }
+
+        for task in self.db.tasks.iter_mut().filter(|t| t.is_overdue(today)) {
+            task.postpone(by, self.user.as_deref())?;
+            postponed += 1;
+        }
+
+        if postponed > 0 {
+            self.persist()?;
+        }
+
+        Ok(postponed)
+    }
+
+    pub fn set_focus(&mut self, task_id: Uuid) -> Result<(), ToNotDoError> {
+        if !self.contains_task(task_id) {
+            return Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ));
+        }
+
+        self.db.focus = Some(task_id);
+        self.persist()
+    }
+
+    pub fn clear_focus(&mut self) -> Result<(), ToNotDoError> {
+        self.db.focus = None;
+        self.persist()
+    }
+
+    pub fn focused_task(&self) -> Option<&Task> {
+        let focus = self.db.focus?;
+        self.db.tasks.get(focus)
+    }
+
+    /// Sets the project that commands are implicitly scoped to, or clears it.
+    pub fn set_context(&mut self, project: Option<&str>) -> Result<(), ToNotDoError> {
+        self.db.context = project.map(str::to_string);
+        self.persist()
+    }
+
+    pub fn context(&self) -> Option<&str> {
+        self.db.context.as_deref()
+    }
+
+    pub fn get_tasks(&mut self) -> Result<&[Task], ToNotDoError> {
+        self.refresh()?;
+
+        Ok(self.db.tasks())
+    }
+
+    /// The whole database as it is on disk, unless there are unsaved changes.
+    pub fn database(&mut self) -> Result<&Database, ToNotDoError> {
+        self.refresh()?;
+
+        Ok(&self.db)
+    }
+
+    /// Returns every task that has not been archived.
+    pub fn get_active_tasks(&mut self) -> Result<Vec<Task>, ToNotDoError> {
+        Ok(self
+            .get_tasks()?
+            .iter()
+            .filter(|t| !t.archived)
+            .cloned()
+            .collect())
+    }
+
+    pub fn filter_tasks(&mut self, state: TaskState) -> Vec<Task> {
+        self.db
+            .tasks
+            .iter()
+            .filter(|t| t.state == state && !t.archived)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns tasks that are not done and whose description contains `query`,
+    /// ignoring case.
+    pub fn find_open_tasks(&self, query: &str) -> Vec<Task> {
+        let query = query.to_lowercase();
+
+        self.db
+            .tasks
+            .iter()
+            .filter(|t| t.state != TaskState::Done && !t.archived)
+            .filter(|t| t.description.to_lowercase().contains(&query))
+            .cloned()
+            .collect()
+    }
+
+    /// Searches the description, notes and tags of unarchived tasks for
+    /// `query`, ignoring case.
+    pub fn search(&self, query: &str) -> Vec<SearchMatch> {
+        let query = query.to_lowercase();
+        let matches = |text: &str| text.to_lowercase().contains(&query);
+
+        self.db
+            .tasks
+            .iter()
+            .filter(|t| !t.archived)
+            .filter_map(|task| {
+                let mut fields = Vec::new();
+
+                if matches(&task.description) {
+                    fields.push((SearchField::Description, task.description.clone()));
+                }
+
+                if let Some(notes) = &task.notes {
+                    fields.extend(
+                        notes
+                            .lines()
+                            .filter(|line| matches(line))
+                            .map(|line| (SearchField::Notes, line.trim().to_string())),
+                    );
+                }
+
+                fields.extend(
+                    task.tags
+                        .iter()
+                        .filter(|tag| matches(tag))
+                        .map(|tag| (SearchField::Tag, tag.clone())),
+                );
+
+                if fields.is_empty() {
+                    None
+                } else {
+                    Some(SearchMatch {
+                        task: task.clone(),
+                        fields,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Returns unarchived tasks last updated before `before`, optionally
+    /// limited to one state.
+    pub fn stale_tasks(&self, before: NaiveDate, state: Option<TaskState>) -> Vec<Task> {
+        self.db
+            .tasks
+            .iter()
+            .filter(|t| !t.archived && t.updated_at < before)
+            .filter(|t| state.is_none_or(|state| t.state == state))
+            .cloned()
+            .collect()
+    }
+
+    pub fn archive_task(&mut self, task_id: Uuid) -> Result<(), ToNotDoError> {
+        if let Some(task) = self.db.tasks.get_mut(task_id) {
+            task.archive(self.user.as_deref());
+            if self.db.focus == Some(task_id) {
+                self.db.focus = None;
+            }
+            self.persist()
+        } else {
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::TaskNotFound(task_id),
+            ))
+        }
+    }
+
+    /// Adds `task`, recording the current user as who created it unless it
+    /// says otherwise. Outside of a batch it is inserted into the latest
+    /// state on disk, so concurrent additions from other processes are kept.
+    pub fn add_task(&mut self, task: &Task) -> Result<(), ToNotDoError> {
+        let mut task = task.clone();
+        if task.created_by.is_none() {
+            task.created_by = self.user.clone();
+        }
+
+        if self.in_batch {
+            self.db.insert_task(task)?;
+            return self.persist();
+        }
+
+        let mut db = self.storage.load()?;
+        db.insert_task(task)?;
+        self.revision = self.storage.save_revised(&db)?;
+        self.db = db;
+        self.base = self.db.clone();
+        Ok(())
+    }
+
+    /// Bundles the current database with the configuration file at
+    /// `config_path`, if it exists, into a [`Backup`] written to `output`,
+    /// encrypted with `cipher` if given.
+    pub fn backup(
+        &mut self,
+        config_path: &Path,
+        output: &Path,
+        cipher: Option<&Cipher>,
+    ) -> Result<PathBuf, ToNotDoError> {
+        self.refresh()?;
+
+        let config = if config_path.exists() {
+            Some(std::fs::read_to_string(config_path).map_err(|e| {
+                ToNotDoError::DatabaseError(crate::error::DatabaseError::FailedToReadFile(e))
+            })?)
+        } else {
+            None
+        };
+
+        let backup = Backup {
+            app: APP_NAME.to_string(),
+            version: VERSION.to_string(),
+            created_at: Utc::now(),
+            database: self.db.clone(),
+            config,
+        };
+
+        backup.write(output, cipher)
+    }
+
+    /// Replaces the whole database with `db`.
+    pub fn restore(&mut self, db: Database) -> Result<(), ToNotDoError> {
+        self.db = db;
+        self.persist()
+    }
+
+    /// Adds every task from `db` whose ID is not already present, returning
+    /// how many were added and how many were skipped.
+    pub fn merge_tasks(&mut self, db: Database) -> Result<(usize, usize), ToNotDoError> {
+        self.refresh()?;
+
+        let mut added = 0;
+        let mut skipped = 0;
+
+        for task in db.tasks {
+            if self.db.insert_task(task).is_ok() {
+                added += 1;
+            } else {
+                skipped += 1;
+            }
+        }
+
+        if added > 0 {
+            self.persist()?;
+        }
+
+        Ok((added, skipped))
+    }
+
+    /// Imports the tasks of `other`, matching tasks by ID. Tasks that differ
+    /// are resolved as `prefer` says, or left alone when it is `None`; either
+    /// way they are reported as conflicts.
+    pub fn merge_database(
+        &mut self,
+        other: Database,
+        prefer: Option<MergePreference>,
+    ) -> Result<MergeReport, ToNotDoError> {
+        self.refresh()?;
+
+        let mut report = MergeReport::default();
+
+        for theirs in other.tasks {
+            let Some(ours) = self.db.tasks.get_mut(theirs.id) else {
+                self.db.tasks.push(theirs);
+                report.added += 1;
+                continue;
+            };
+
+            if *ours == theirs {
+                report.identical += 1;
+                continue;
+            }
+
+            let took_theirs = match prefer {
+                Some(MergePreference::Theirs) => true,
+                Some(MergePreference::Newest) => theirs.last_modified() > ours.last_modified(),
+                Some(MergePreference::Ours) | None => false,
+            };
+
+            report.conflicts.push(MergeConflict {
+                ours: ours.clone(),
+                theirs: theirs.clone(),
+                took_theirs,
+            });
+
+            if took_theirs {
+                *ours = theirs;
+            }
+        }
+
+        if report.added > 0 || report.conflicts.iter().any(|c| c.took_theirs) {
+            self.persist()?;
+        }
+
+        Ok(report)
+    }
+
+    /// Merges another replica of this database into it, see
+    /// [`Database::merge`]. Returns how many changes the merge made here.
+    pub fn sync(&mut self, other: &Database) -> Result<usize, ToNotDoError> {
+        self.refresh()?;
+
+        let before = self.db.clone();
+        self.db.merge(other);
+
+        let changes = before.changes_to(&self.db).len();
+        if changes > 0 {
+            self.persist()?;
+        }
+
+        Ok(changes)
+    }
+
+    /// Defers saving until [`DatabaseManager::commit`] is called, so a series
+    /// of mutations is written to disk only once.
+    pub fn begin(&mut self) {
+        self.in_batch = true;
+    }
+
+    /// Writes any pending changes to disk and resumes saving after every
+    /// mutation. Changes made elsewhere while the batch ran are kept, with
+    /// the batch winning where both touched the same field.
+    pub fn commit(&mut self) -> Result<(), ToNotDoError> {
+        self.in_batch = false;
+
+        if self.dirty && self.reload(&mut |_, _| false)? == Reload::Unchanged {
+            self.persist()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes any pending changes to disk like [`DatabaseManager::commit`],
+    /// but keeps deferring the saves of a batch that is running.
+    pub fn flush(&mut self) -> Result<(), ToNotDoError> {
+        let in_batch = self.in_batch;
+        let result = self.commit();
+        self.in_batch = in_batch;
+        result
+    }
+
+    fn persist(&mut self) -> Result<(), ToNotDoError> {
+        if self.in_batch {
+            self.dirty = true;
+            return self.storage.stage(&self.db);
+        }
+
+        if let Some(retention) = &self.retention {
+            purge_expired(&mut self.db, retention);
+        }
+
+        // Taken from the save itself, so a write by another process right
+        // after it is not mistaken for ours and still loaded on refresh.
+        match self.storage.save_revised(&self.db) {
+            Ok(revision) => self.revision = revision,
+            Err(e) => {
+                // Drop the change that was not saved on the next refresh.
+                self.revision = None;
+                return Err(e);
+            }
+        }
+        self.base = self.db.clone();
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Reloads the database from storage unless there are unsaved changes,
+    /// or nothing wrote to it since it was last loaded or saved.
+    fn refresh(&mut self) -> Result<(), ToNotDoError> {
+        if self.dirty {
+            return Ok(());
+        }
+
+        let revision = self.storage.revision();
+        if revision.is_none() || revision != self.revision {
+            self.db = self.storage.load()?;
+            self.base = self.db.clone();
+            self.revision = revision;
+        }
+
+        Ok(())
+    }
+
+    /// Picks up changes made to the database behind our back, by another
+    /// process, a sync or a hand edit, without losing the ones made here.
+    /// Edits made here since the last load or save are replayed onto the
+    /// latest database field by field; when both sides changed the same field
+    /// of a task differently, `take_theirs` is asked whether to keep the
+    /// other version of that task's conflicting fields. A task deleted on
+    /// either side stays deleted.
+    pub fn reload(
+        &mut self,
+        take_theirs: &mut dyn FnMut(&Task, &Task) -> bool,
+    ) -> Result<Reload, ToNotDoError> {
+        let revision = self.storage.revision();
+        let latest = self.storage.load()?;
+        if self.base.changes_to(&latest).is_empty() {
+            self.revision = revision;
+            return Ok(Reload::Unchanged);
+        }
+
+        let ours = self.base.changes_to(&self.db);
+        let mut merged = latest.clone();
+        let mut conflicts = 0;
+
+        for change in &ours {
+            let Change::PutTask { task } = change else {
+                merged.apply(change.clone());
+                continue;
+            };
+
+            let base = self.base.tasks.get(task.id);
+            let theirs = merged.tasks.get_mut(task.id);
+
+            match (base, theirs) {
+                (Some(base), Some(theirs)) => {
+                    let changed: Vec<TaskField> = TaskField::ALL
+                        .into_iter()
+                        .filter(|&field| !task.same_field(base, field))
+                        .collect();
+                    let conflicting: Vec<TaskField> = changed
+                        .iter()
+                        .copied()
+                        .filter(|&field| {
+                            !theirs.same_field(base, field) && !theirs.same_field(task, field)
+                        })
+                        .collect();
+
+                    let keep_theirs = !conflicting.is_empty() && {
+                        conflicts += 1;
+                        take_theirs(task, theirs)
+                    };
+
+                    for field in changed {
+                        if !(keep_theirs && conflicting.contains(&field)) {
+                            theirs.copy_field(task, field);
+                        }
+                    }
+                    theirs.merge_history(task);
+                }
+                (None, Some(theirs)) => theirs.merge(task),
+                (None, None) => merged.merge_task(task),
+                // Deleted elsewhere.
+                (Some(_), None) => {}
+            }
+        }
+
+        self.db = merged;
+        self.base = latest;
+        self.revision = revision;
+
+        if ours.is_empty() {
+            return Ok(Reload::Reloaded);
+        }
+
+        self.persist()?;
+        Ok(Reload::Merged { conflicts })
+    }
+}
+
+/// What [`DatabaseManager::reload`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reload {
+    /// The database had not changed.
+    Unchanged,
+    /// The database had changed and there was nothing unsaved to keep.
+    Reloaded,
+    /// The database had changed and unsaved changes were merged into it,
+    /// `conflicts` of the tasks having been edited on both sides.
+    Merged { conflicts: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+    use chrono::Utc;
+    use std::{fs::File, io::Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_create_database() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        assert!(db_path.exists());
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+
+        assert!(tasks.is_empty());
+    }
+
+    #[test]
+    fn test_open_existing_database() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        assert!(db_path.exists());
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+
+        assert!(tasks.is_empty());
+    }
+
+    #[test]
+    fn test_add_task() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let task = Task {
+            id: Uuid::new_v4(),
+            description: "New task".to_string(),
+            notes: None,
+            due: None,
+            priority: None,
+            tags: Vec::new(),
+            parent: None,
+            project: None,
+            state: TaskState::Todo,
+            created_at: Utc::now().date_naive(),
+            updated_at: Utc::now().date_naive(),
+            completed_at: None,
+            created_by: None,
+            completed_by: None,
+            archived: false,
+            metadata: BTreeMap::new(),
+            history: Vec::new(),
+            clock: BTreeMap::new(),
+        };
+
+        db_manager.add_task(&task).expect("Failed to add task");
+
+        assert_eq!(db_manager.db.tasks.len(), 1);
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        let new_task = tasks.first().expect("Failed to get task");
+
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(*new_task, task);
+    }
+
+    #[test]
+    fn test_save_and_load_database() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let task = Task {
+            id: Uuid::new_v4(),
+            description: "Persistent task".to_string(),
+            notes: None,
+            due: None,
+            priority: None,
+            tags: Vec::new(),
+            parent: None,
+            project: None,
+            state: TaskState::Todo,
+            created_at: Utc::now().date_naive(),
+            updated_at: Utc::now().date_naive(),
+            completed_at: None,
+            created_by: None,
+            completed_by: None,
+            archived: false,
+            metadata: BTreeMap::new(),
+            history: Vec::new(),
+            clock: BTreeMap::new(),
+        };
+
+        db_manager.add_task(&task).expect("Failed to add task");
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        println!("{:?}", tasks.len());
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0], task);
+    }
+
+    #[test]
+    fn test_create_data_directory() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+
+        assert!(data_dir.exists());
+        assert!(data_dir.is_dir());
+    }
+
+    #[test]
+    fn test_add_multiple_tasks() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        for i in 0..100 {
+            let task = Task {
+                id: Uuid::new_v4(),
+                description: format!("Task {}", i),
+                notes: None,
+                due: None,
+                priority: None,
+                tags: Vec::new(),
+                parent: None,
+                project: None,
+                state: TaskState::Todo,
+                created_at: Utc::now().date_naive(),
+                updated_at: Utc::now().date_naive(),
+                completed_at: None,
+                created_by: None,
+                completed_by: None,
+                archived: false,
+                metadata: BTreeMap::new(),
+                history: Vec::new(),
+                clock: BTreeMap::new(),
+            };
+
+            db_manager.add_task(&task).expect("Failed to add task");
+        }
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+
+        assert_eq!(tasks.len(), 100);
+    }
+
+    #[test]
+    fn test_update_task_state() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let task = Task {
+            id: Uuid::new_v4(),
+            description: "Task to update".to_string(),
+            notes: None,
+            due: None,
+            priority: None,
+            tags: Vec::new(),
+            parent: None,
+            project: None,
+            state: TaskState::Todo,
+            created_at: Utc::now().date_naive(),
+            updated_at: Utc::now().date_naive(),
+            completed_at: None,
+            created_by: None,
+            completed_by: None,
+            archived: false,
+            metadata: BTreeMap::new(),
+            history: Vec::new(),
+            clock: BTreeMap::new(),
+        };
+
+        db_manager.add_task(&task).expect("Failed to add task");
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+
+        let task_id = tasks.first().expect("Failed to get task").id;
+        db_manager
+            .set_task_state(task_id, TaskState::Done)
+            .expect("Failed to update task state");
+
+        let new_tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        let updated_task = new_tasks.iter().find(|t| t.id == task_id).unwrap();
+
+        assert_eq!(updated_task.state, TaskState::Done);
+        assert_eq!(updated_task.completed_at, Some(Utc::now().date_naive()));
+    }
+
+    #[test]
+    fn test_remove_task() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let task = Task {
+            id: Uuid::new_v4(),
+            description: "Task to remove".to_string(),
+            notes: None,
+            due: None,
+            priority: None,
+            tags: Vec::new(),
+            parent: None,
+            project: None,
+            state: TaskState::Todo,
+            created_at: Utc::now().date_naive(),
+            updated_at: Utc::now().date_naive(),
+            completed_at: None,
+            created_by: None,
+            completed_by: None,
+            archived: false,
+            metadata: BTreeMap::new(),
+            history: Vec::new(),
+            clock: BTreeMap::new(),
+        };
+
+        db_manager.add_task(&task).expect("Failed to add task");
+        assert_eq!(db_manager.db.tasks.len(), 1);
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        let task_id = tasks.first().expect("Failed to get task").id;
+
+        db_manager
+            .delete_task(task_id)
+            .expect("Failed to remove task");
+
+        assert_eq!(db_manager.db.tasks.len(), 0);
+    }
+
+    #[test]
+    fn test_focus_task() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let first = Task::new("First");
+        let second = Task::new("Second");
+        db_manager.add_task(&first).expect("Failed to add task");
+        db_manager.add_task(&second).expect("Failed to add task");
+
+        db_manager
+            .set_focus(first.id)
+            .expect("Failed to focus task");
+        db_manager
+            .set_focus(second.id)
+            .expect("Failed to focus task");
+        assert_eq!(db_manager.focused_task(), Some(&second));
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+        assert_eq!(db_manager.focused_task(), Some(&second));
+
+        db_manager
+            .set_task_state(second.id, TaskState::Done)
+            .expect("Failed to update task state");
+        assert_eq!(db_manager.focused_task(), None);
+
+        assert!(db_manager.set_focus(Uuid::new_v4()).is_err());
+    }
+
+    #[test]
+    fn test_batch_saves_once_on_commit() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        db_manager.begin();
+        for i in 0..10 {
+            db_manager
+                .add_task(&Task::new(&format!("Task {}", i)))
+                .expect("Failed to add task");
+        }
+
+        let mut storage = FileStorage::new(&db_path);
+
+        assert_eq!(db_manager.get_tasks().unwrap().len(), 10);
+        assert!(storage.load().unwrap().tasks.is_empty());
+
+        db_manager.flush().unwrap();
+        assert_eq!(storage.load().unwrap().tasks.len(), 10);
+
+        // Still in the batch after a flush.
+        db_manager.add_task(&Task::new("Task 10")).unwrap();
+        assert_eq!(storage.load().unwrap().tasks.len(), 10);
+
+        db_manager.commit().unwrap();
+
+        assert_eq!(storage.load().unwrap().tasks.len(), 11);
+    }
+
+    #[test]
+    fn test_set_due_priority_and_tags() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
+
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let task = Task::new("Task with a deadline");
+        db_manager.add_task(&task).expect("Failed to add task");
+
+        let due = NaiveDate::from_ymd_opt(2030, 1, 31).unwrap();
+        db_manager
+            .set_due(task.id, Some(due))
+            .expect("Failed to set due date");
+        db_manager
+            .set_priority(task.id, Some(Priority::High))
+            .expect("Failed to set priority");
+
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+
+        assert_eq!(tasks[0].due, Some(due));
+        assert_eq!(tasks[0].priority, Some(Priority::High));
+
+        let tags = vec!["urgent".to_string()];
+        db_manager
+            .set_tags(task.id, &tags)
+            .expect("Failed to set tags");
+        assert_eq!(db_manager.get_tasks().unwrap()[0].tags, tags);
+        assert!(db_manager.set_due(Uuid::new_v4(), None).is_err());
+    }
+
+    #[test]
+    fn test_find_open_tasks() {
+        let dir = tempdir().unwrap();
+
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
 
-impl DatabaseManager {
-    pub fn open(path_to_db: &Path) -> Self {
-        if !Self::is_valid_path(path_to_db) {
-            return Self::create(path_to_db);
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let groceries = Task::new("Buy groceries");
+        let milk = Task::new("Buy MILK");
+        let report = Task::new("Write report");
+        for task in [&groceries, &milk, &report] {
+            db_manager.add_task(task).expect("Failed to add task");
         }
 
-        let db = Self::read(path_to_db).expect("Failed to read database file");
+        db_manager
+            .set_task_state(groceries.id, TaskState::Done)
+            .expect("Failed to update task state");
 
-        Self {
-            db_path: path_to_db.to_path_buf(),
-            db,
-        }
+        assert_eq!(db_manager.find_open_tasks("milk"), vec![milk]);
+        assert_eq!(db_manager.find_open_tasks("buy").len(), 1);
+        assert!(db_manager.find_open_tasks("nothing").is_empty());
     }
 
-    pub fn update_description(
-        &mut self,
-        task_id: Uuid,
-        description: &str,
-    ) -> Result<(), ToNotDoError> {
-        if let Some(task) = self.db.tasks.iter_mut().find(|t| t.id == task_id) {
-            task.set_description(description);
-            Self::save(&self.db_path, &self.db);
-            Ok(())
-        } else {
-            Err(ToNotDoError::DatabaseError(
-                crate::error::DatabaseError::TaskNotFound(task_id),
-            ))
-        }
-    }
+    #[test]
+    fn test_postpone_task() {
+        let dir = tempdir().unwrap();
 
-    pub fn contains_task(&mut self, task_id: Uuid) -> bool {
-        self.db.tasks.iter().any(|t| t.id == task_id)
-    }
+        let data_dir = create_data_directory(dir.path());
+        let db_path = data_dir.join(DB_FILE_NAME);
 
-    pub fn delete_task(&mut self, task_id: Uuid) -> Result<(), ToNotDoError> {
-        if self.contains_task(task_id) {
-            self.db.tasks.retain(|t| t.id != task_id);
-            Self::save(&self.db_path, &self.db);
-            Ok(())
-        } else {
-            Err(ToNotDoError::DatabaseError(
-                crate::error::DatabaseError::TaskNotFound(task_id),
-            ))
-        }
-    }
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
 
-    pub fn set_task_state(&mut self, task_id: Uuid, state: TaskState) -> Result<(), ToNotDoError> {
-        if let Some(task) = self.db.tasks.iter_mut().find(|t| t.id == task_id) {
-            task.set_state(state);
-            Self::save(&self.db_path, &self.db);
-            Ok(())
-        } else {
-            Err(ToNotDoError::DatabaseError(
-                crate::error::DatabaseError::TaskNotFound(task_id),
-            ))
+        let today = Utc::now().date_naive();
+        let upcoming = Task::new("Upcoming").with_due(today + Duration::days(3));
+        let overdue = Task::new("Overdue").with_due(today - Duration::days(10));
+        let undated = Task::new("Undated");
+        for task in [&upcoming, &overdue, &undated] {
+            db_manager.add_task(task).expect("Failed to add task");
         }
-    }
-
-    pub fn get_tasks(&mut self) -> Result<&Vec<Task>, ToNotDoError> {
-        self.db = Self::read(&self.db_path)?;
 
-        Ok(&self.db.tasks)
-    }
+        let due = db_manager
+            .postpone_task(upcoming.id, Duration::days(2))
+            .expect("Failed to postpone task");
+        assert_eq!(due, today + Duration::days(5));
 
-    pub fn filter_tasks(&mut self, state: TaskState) -> Vec<Task> {
-        self.db
-            .tasks
-            .iter()
-            .filter(|t| t.state == state)
-            .cloned()
-            .collect()
-    }
+        assert_eq!(db_manager.postpone_overdue(Duration::weeks(1)).unwrap(), 1);
 
-    pub fn add_task(&mut self, task: &Task) -> Result<(), ToNotDoError> {
-        if self.contains_task(task.id) {
-            return Err(ToNotDoError::DatabaseError(
-                crate::error::DatabaseError::UuidAlreadyExists(task.id),
-            ));
-        }
+        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
 
-        self.db.tasks.push(task.clone());
-        Self::save(&self.db_path, &self.db);
-        Ok(())
+        assert_eq!(tasks[1].due, Some(today + Duration::weeks(1)));
+        assert_eq!(tasks[1].history.len(), 1);
+        assert!(tasks[1].history[0].event.starts_with("Postponed due date"));
+        assert_eq!(tasks[2].due, None);
+        assert!(db_manager
+            .postpone_task(Uuid::new_v4(), Duration::days(1))
+            .is_err());
     }
 
-    fn read(db_file_path: &Path) -> Result<Database, ToNotDoError> {
-        let db_file = match File::open(db_file_path) {
-            Ok(file) => file,
-            Err(_) => {
-                return Err(ToNotDoError::DatabaseError(
-                    crate::error::DatabaseError::FailedToReadFile(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        "Database file not found",
-                    )),
-                ))
-            }
-        };
+    #[test]
+    fn test_postpone_out_of_range() {
+        let dir = tempdir().unwrap();
+        let mut db_manager = DatabaseManager::open(&dir.path().join(DB_FILE_NAME)).unwrap();
 
-        let reader = std::io::BufReader::new(db_file);
+        let today = Utc::now().date_naive();
+        let overdue = Task::new("Overdue").with_due(today - Duration::days(1));
+        db_manager.add_task(&overdue).unwrap();
 
-        match serde_json::from_reader(reader) {
-            Ok(db) => Ok(db),
-            Err(_) => Err(ToNotDoError::DatabaseError(
-                crate::error::DatabaseError::FailedToReadFile(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Failed to read database file",
-                )),
-            )),
-        }
+        let too_far = Duration::days(999_999_999);
+        assert!(matches!(
+            db_manager.postpone_task(overdue.id, too_far),
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::DueDateOutOfRange(id)
+            )) if id == overdue.id
+        ));
+        assert!(db_manager.postpone_overdue(too_far).is_err());
+
+        let task = db_manager.get_task(overdue.id).unwrap();
+        assert_eq!(task.due, overdue.due);
+        assert!(task.history.is_empty());
     }
 
-    fn save(db_path: &Path, db: &Database) {
-        let mut db_file = OpenOptions::new()
-            .write(true)
-            .open(db_path)
-            .expect("Failed to open database file");
-        let json_db = serde_json::to_string_pretty(db).expect("Failed to serialize database");
+    #[test]
+    fn test_postpone_overdue_skips_archived() {
+        let dir = tempdir().unwrap();
+        let mut db_manager = DatabaseManager::open(&dir.path().join(DB_FILE_NAME)).unwrap();
 
-        db_file
-            .write_all(json_db.as_bytes())
-            .expect("Failed to write to database file");
-    }
+        let today = Utc::now().date_naive();
+        let stale = Task::new("Stale").with_due(today - Duration::days(30));
+        db_manager.add_task(&stale).unwrap();
+        db_manager.archive_task(stale.id).unwrap();
 
-    fn is_valid_path(path_to_db: &Path) -> bool {
-        path_to_db.exists() && path_to_db.is_file()
+        let archived = db_manager.get_task(stale.id).unwrap().clone();
+        assert!(!archived.is_overdue(today));
+        assert_eq!(db_manager.postpone_overdue(Duration::days(1)).unwrap(), 0);
+        assert_eq!(db_manager.get_task(stale.id).unwrap(), &archived);
     }
 
-    fn create(path: &Path) -> Self {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(path)
-            .expect("Failed to create database file");
-
-        let db = Database::default();
+    #[test]
+    fn test_attribution() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join(DB_FILE_NAME);
+        let mut alice = DatabaseManager::open(&db_path).unwrap();
+        alice.set_user(Some("alice"));
+        let mut bob = DatabaseManager::open(&db_path).unwrap();
+        bob.set_user(Some("bob"));
+
+        let dishes = Task::new("Do the dishes");
+        let plants = Task::new("Water the plants").with_created_by("carol");
+        alice.add_task(&dishes).unwrap();
+        alice.add_task(&plants).unwrap();
+
+        bob.get_tasks().unwrap();
+        bob.set_task_state(dishes.id(), TaskState::Done).unwrap();
+        bob.postpone_task(plants.id(), Duration::days(1)).unwrap();
+
+        let tasks = alice.get_tasks().unwrap().to_vec();
+        assert_eq!(tasks[0].created_by(), Some("alice"));
+        assert_eq!(tasks[0].completed_by(), Some("bob"));
+        assert_eq!(tasks[1].created_by(), Some("carol"));
+        assert_eq!(tasks[1].history[0].by.as_deref(), Some("bob"));
+        assert!(tasks[0].touched_by("alice") && tasks[1].touched_by("bob"));
+        assert!(!tasks[1].touched_by("alice"));
+        assert!(tasks[0].to_string().contains("Completed by: bob"));
+
+        // Completing through an edit counts too, and reopening forgets it.
+        alice
+            .update_task(&tasks[1].clone().with_completed_at(Utc::now().date_naive()))
+            .unwrap();
+        assert_eq!(
+            alice.get_task(plants.id()).unwrap().completed_by(),
+            Some("alice")
+        );
+        alice.set_task_state(dishes.id(), TaskState::Todo).unwrap();
+        assert_eq!(alice.get_task(dishes.id()).unwrap().completed_by(), None);
+    }
 
-        serde_json::to_writer(&file, &db).expect("Failed to write to database file");
+    #[test]
+    fn test_take_field() {
+        let mut db_manager =
+            DatabaseManager::with_storage(Box::new(crate::storage::MemoryStorage::new())).unwrap();
+        let base = Task::new("Buy milk").with_tags(&["home".to_string()]);
+        db_manager.add_task(&base).unwrap();
+
+        let ours = base
+            .clone()
+            .with_description("Buy oat milk")
+            .with_project("home");
+        let theirs = base
+            .clone()
+            .with_description("Buy soy milk")
+            .with_priority(Priority::High);
+        assert_eq!(
+            ours.conflicting_fields(&base, &theirs),
+            [TaskField::Description]
+        );
+        assert_eq!(ours.show_field(TaskField::Priority), "none");
+        assert_eq!(theirs.show_field(TaskField::Priority), "High");
+        assert_eq!(base.show_field(TaskField::Tags), "home");
 
-        Self {
-            db_path: path.to_path_buf(),
-            db,
-        }
+        db_manager
+            .take_field(&theirs, TaskField::Description)
+            .unwrap();
+        let stored = db_manager.get_task(base.id()).unwrap();
+        assert_eq!(stored.description(), "Buy soy milk");
+        assert_eq!(stored.priority(), None);
+        assert!(stored.clock.contains_key(&TaskField::Description));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use chrono::Utc;
-    use tempfile::tempdir;
+    #[test]
+    fn test_column_value() {
+        let due = NaiveDate::from_ymd_opt(2030, 1, 31).unwrap();
+        let task = Task::new("Tagged task")
+            .with_due(due)
+            .with_tags(&["home".to_string(), "errands".to_string()]);
+
+        assert_eq!(task.column_value(Column::Id), task.short_id());
+        assert_eq!(task.column_value(Column::Id).len(), 8);
+        assert_eq!(task.column_value(Column::Desc), "Tagged task");
+        assert_eq!(task.column_value(Column::State), "Todo");
+        assert_eq!(task.column_value(Column::Due), "2030-01-31");
+        assert_eq!(task.column_value(Column::Priority), "");
+        assert_eq!(task.column_value(Column::Tags), "home,errands");
+    }
 
     #[test]
-    fn test_create_database() {
+    fn test_delete_parent_reattaches_children() {
         let dir = tempdir().unwrap();
 
         let data_dir = create_data_directory(dir.path());
         let db_path = data_dir.join(DB_FILE_NAME);
 
-        let mut db_manager = DatabaseManager::open(&db_path);
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
 
-        assert!(db_path.exists());
+        let root = Task::new("Root");
+        let middle = Task::new("Middle").with_parent(root.id);
+        let leaf = Task::new("Leaf").with_parent(middle.id);
+        for task in [&root, &middle, &leaf] {
+            db_manager.add_task(task).expect("Failed to add task");
+        }
+
+        db_manager
+            .delete_task(middle.id)
+            .expect("Failed to remove task");
 
         let tasks = db_manager.get_tasks().expect("Failed to get tasks");
 
-        assert!(tasks.is_empty());
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[1].parent, Some(root.id));
     }
 
     #[test]
-    fn test_open_existing_database() {
+    fn test_stale_tasks_and_archive() {
         let dir = tempdir().unwrap();
 
         let data_dir = create_data_directory(dir.path());
         let db_path = data_dir.join(DB_FILE_NAME);
 
-        let mut db_manager = DatabaseManager::open(&db_path);
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+
+        let today = Utc::now().date_naive();
+        let mut old_todo = Task::new("Old todo");
+        old_todo.updated_at = today - Duration::days(120);
+        let mut old_done = Task::new("Old done");
+        old_done.state = TaskState::Done;
+        old_done.updated_at = today - Duration::days(120);
+        let fresh = Task::new("Fresh");
+        for task in [&old_todo, &old_done, &fresh] {
+            db_manager.add_task(task).expect("Failed to add task");
+        }
+
+        let cutoff = today - Duration::days(90);
+        assert_eq!(db_manager.stale_tasks(cutoff, None).len(), 2);
+        assert_eq!(
+            db_manager.stale_tasks(cutoff, Some(TaskState::Todo)),
+            vec![old_todo.clone()]
+        );
 
-        assert!(db_path.exists());
+        db_manager
+            .archive_task(old_todo.id)
+            .expect("Failed to archive task");
 
         let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        assert!(tasks[0].archived);
+        assert_eq!(tasks[0].updated_at, today);
+        assert!(db_manager
+            .stale_tasks(cutoff, Some(TaskState::Todo))
+            .is_empty());
+        assert!(db_manager.find_open_tasks("old").is_empty());
+    }
 
-        assert!(tasks.is_empty());
+    #[test]
+    fn test_oneline() {
+        let task = Task::new("Buy milk");
+        assert_eq!(
+            task.oneline(),
+            format!("{} todo - Buy milk", task.short_id())
+        );
+
+        let mut task = task.with_due(NaiveDate::from_ymd_opt(2030, 1, 31).unwrap());
+        task.state = TaskState::InProgress;
+        assert_eq!(
+            task.oneline(),
+            format!("{} in-progress 2030-01-31 Buy milk", task.short_id())
+        );
     }
 
     #[test]
-    fn test_add_task() {
+    fn test_set_context() {
         let dir = tempdir().unwrap();
 
         let data_dir = create_data_directory(dir.path());
         let db_path = data_dir.join(DB_FILE_NAME);
 
-        let mut db_manager = DatabaseManager::open(&db_path);
-
-        let task = Task {
-            id: Uuid::new_v4(),
-            description: "New task".to_string(),
-            state: TaskState::Todo,
-            created_at: Utc::now().date_naive(),
-            updated_at: Utc::now().date_naive(),
-        };
-
-        db_manager.add_task(&task).expect("Failed to add task");
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+        assert_eq!(db_manager.context(), None);
 
-        assert_eq!(db_manager.db.tasks.len(), 1);
+        db_manager.set_context(Some("work")).unwrap();
 
-        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
-        let new_task = tasks.first().expect("Failed to get task");
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
+        assert_eq!(db_manager.context(), Some("work"));
 
-        assert_eq!(tasks.len(), 1);
-        assert_eq!(*new_task, task);
+        db_manager.set_context(None).unwrap();
+        assert_eq!(DatabaseManager::open(&db_path).unwrap().context(), None);
     }
 
     #[test]
-    fn test_save_and_load_database() {
+    fn test_search() {
         let dir = tempdir().unwrap();
 
         let data_dir = create_data_directory(dir.path());
         let db_path = data_dir.join(DB_FILE_NAME);
 
-        let mut db_manager = DatabaseManager::open(&db_path);
+        let mut db_manager = DatabaseManager::open(&db_path).unwrap();
 
-        let task = Task {
-            id: Uuid::new_v4(),
-            description: "Persistent task".to_string(),
-            state: TaskState::Todo,
-            created_at: Utc::now().date_naive(),
-            updated_at: Utc::now().date_naive(),
-        };
+        let described = Task::new("Call the Plumber");
+        let noted = Task::new("Fix sink").with_notes("Leaking pipe\nAsk the plumber first");
+        let tagged = Task::new("Buy washers").with_tags(&["plumbing".to_string()]);
+        let unrelated = Task::new("Water plants");
+        for task in [&described, &noted, &tagged, &unrelated] {
+            db_manager.add_task(task).expect("Failed to add task");
+        }
+
+        let results = db_manager.search("PLUMB");
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].fields,
+            vec![(SearchField::Description, "Call the Plumber".to_string())]
+        );
+        assert_eq!(
+            results[1].fields,
+            vec![(SearchField::Notes, "Ask the plumber first".to_string())]
+        );
+        assert_eq!(
+            results[2].fields,
+            vec![(SearchField::Tag, "plumbing".to_string())]
+        );
+        assert!(db_manager.search("nothing").is_empty());
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = tempdir().unwrap();
 
+        let paths = AppPaths::new(&create_data_directory(dir.path()));
+        std::fs::write(&paths.config_file, "[list]\ncolumns = [\"id\"]\n").unwrap();
+
+        let mut db_manager = DatabaseManager::open(&paths.db_file).unwrap();
+        let task = Task::new("Back me up");
         db_manager.add_task(&task).expect("Failed to add task");
 
-        let mut db_manager = DatabaseManager::open(&db_path);
-        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
-        println!("{:?}", tasks.len());
-        assert_eq!(tasks.len(), 1);
-        assert_eq!(tasks[0], task);
+        let path = db_manager
+            .backup(&paths.config_file, &paths.backup_dir(), None)
+            .expect("Failed to write backup");
+
+        assert_eq!(path.parent(), Some(paths.backup_dir().as_path()));
+
+        let backup: Backup =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(backup.database.tasks(), &[task]);
+        assert_eq!(
+            backup.config.as_deref(),
+            Some("[list]\ncolumns = [\"id\"]\n")
+        );
+
+        let explicit = dir.path().join("explicit.json");
+        assert_eq!(
+            db_manager
+                .backup(&dir.path().join("missing.toml"), &explicit, None)
+                .unwrap(),
+            explicit
+        );
+        let backup: Backup =
+            serde_json::from_str(&std::fs::read_to_string(&explicit).unwrap()).unwrap();
+        assert!(backup.config.is_none());
     }
 
     #[test]
-    fn test_create_data_directory() {
+    fn test_restore_and_merge_backup() {
         let dir = tempdir().unwrap();
 
-        let data_dir = create_data_directory(dir.path());
+        let paths = AppPaths::new(&create_data_directory(dir.path()));
+        let backup_path = dir.path().join("backup.json");
 
-        assert!(data_dir.exists());
-        assert!(data_dir.is_dir());
+        let mut db_manager = DatabaseManager::open(&paths.db_file).unwrap();
+        let kept = Task::new("In the backup");
+        db_manager.add_task(&kept).expect("Failed to add task");
+        db_manager
+            .backup(&paths.config_file, &backup_path, None)
+            .expect("Failed to write backup");
+
+        let later = Task::new("Added after the backup");
+        db_manager.add_task(&later).expect("Failed to add task");
+
+        let backup = Backup::read(&backup_path, None).expect("Failed to read backup");
+        assert_eq!(db_manager.merge_tasks(backup.database).unwrap(), (0, 1));
+
+        let backup = Backup::read(&backup_path, None).expect("Failed to read backup");
+        db_manager.restore(backup.database).unwrap();
+
+        let mut db_manager = DatabaseManager::open(&paths.db_file).unwrap();
+        assert_eq!(db_manager.get_tasks().unwrap(), std::slice::from_ref(&kept));
+
+        let mut other = DatabaseManager::open(&dir.path().join("other.json")).unwrap();
+        other.add_task(&later).expect("Failed to add task");
+        other
+            .backup(&paths.config_file, &backup_path, None)
+            .expect("Failed to write backup");
+
+        let backup = Backup::read(&backup_path, None).expect("Failed to read backup");
+        assert_eq!(db_manager.merge_tasks(backup.database).unwrap(), (1, 0));
+        assert_eq!(
+            DatabaseManager::open(&paths.db_file)
+                .unwrap()
+                .get_tasks()
+                .unwrap(),
+            &[kept, later]
+        );
     }
 
     #[test]
-    fn test_add_multiple_tasks() {
+    fn test_sync_merges_offline_edits() {
         let dir = tempdir().unwrap();
 
-        let data_dir = create_data_directory(dir.path());
-        let db_path = data_dir.join(DB_FILE_NAME);
+        let shared = Task::new("Shared");
+        let doomed = Task::new("Deleted on laptop");
+        let mut laptop = DatabaseManager::open(&dir.path().join("laptop.json")).unwrap();
+        laptop.add_task(&shared).unwrap();
+        laptop.add_task(&doomed).unwrap();
+        std::fs::copy(
+            dir.path().join("laptop.json"),
+            dir.path().join("phone.json"),
+        )
+        .unwrap();
+        let mut phone = DatabaseManager::open(&dir.path().join("phone.json")).unwrap();
+
+        laptop
+            .update_description(shared.id(), "Renamed on laptop")
+            .unwrap();
+        laptop.delete_task(doomed.id()).unwrap();
+        phone.set_task_state(shared.id(), TaskState::Done).unwrap();
+        phone
+            .set_priority(doomed.id(), Some(Priority::High))
+            .unwrap();
+        let added = Task::new("Added on phone");
+        phone.add_task(&added).unwrap();
+
+        let laptop_db = laptop.storage.load().unwrap();
+        let phone_db = phone.storage.load().unwrap();
+        assert!(laptop.sync(&phone_db).unwrap() > 0);
+        phone.sync(&laptop_db).unwrap();
+
+        let sorted = |db: &mut DatabaseManager| {
+            let mut tasks = db.get_tasks().unwrap().to_vec();
+            tasks.sort_by_key(|t| t.id());
+            tasks
+        };
+        let tasks = sorted(&mut laptop);
+        assert_eq!(tasks, sorted(&mut phone));
+        assert_eq!(tasks.len(), 2);
+
+        let merged = laptop.get_task(shared.id()).unwrap();
+        assert_eq!(merged.description(), "Renamed on laptop");
+        assert_eq!(merged.state(), TaskState::Done);
+        assert!(laptop.contains_task(added.id()));
+        assert!(!laptop.contains_task(doomed.id()));
+
+        // Merging again changes nothing.
+        assert_eq!(laptop.sync(&phone_db).unwrap(), 0);
+    }
 
-        let mut db_manager = DatabaseManager::open(&db_path);
+    #[test]
+    fn test_reload_keeps_unsaved_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        let task = Task::new("Shared");
+        let mut here = DatabaseManager::open(&path).unwrap();
+        here.add_task(&task).unwrap();
+        let mut elsewhere = DatabaseManager::open(&path).unwrap();
+
+        assert_eq!(here.reload(&mut |_, _| true).unwrap(), Reload::Unchanged);
+
+        here.begin();
+        here.update_description(task.id(), "Renamed here").unwrap();
+        elsewhere
+            .set_priority(task.id(), Some(Priority::High))
+            .unwrap();
+        let added = Task::new("Added elsewhere");
+        elsewhere.add_task(&added).unwrap();
+
+        let mut asked = false;
+        let reload = here.reload(&mut |_, _| {
+            asked = true;
+            true
+        });
+        assert_eq!(reload.unwrap(), Reload::Merged { conflicts: 0 });
+        assert!(!asked);
+        here.commit().unwrap();
+
+        let merged = elsewhere.get_tasks().unwrap().to_vec();
+        assert_eq!(merged.len(), 2);
+        let merged = elsewhere.get_task(task.id()).unwrap();
+        assert_eq!(merged.description(), "Renamed here");
+        assert_eq!(merged.priority, Some(Priority::High));
+
+        // Both sides renaming the task is a conflict, settled by the caller.
+        here.begin();
+        here.update_description(task.id(), "Mine").unwrap();
+        elsewhere.update_description(task.id(), "Theirs").unwrap();
+
+        let reload = here.reload(&mut |ours, theirs| {
+            assert_eq!(
+                (ours.description(), theirs.description()),
+                ("Mine", "Theirs")
+            );
+            true
+        });
+        assert_eq!(reload.unwrap(), Reload::Merged { conflicts: 1 });
+        assert_eq!(here.get_task(task.id()).unwrap().description(), "Theirs");
+    }
 
-        for i in 0..100 {
-            let task = Task {
-                id: Uuid::new_v4(),
-                description: format!("Task {}", i),
-                state: TaskState::Todo,
-                created_at: Utc::now().date_naive(),
-                updated_at: Utc::now().date_naive(),
-            };
+    #[test]
+    fn test_refresh_skips_unchanged_storage() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
 
-            db_manager.add_task(&task).expect("Failed to add task");
-        }
+        struct Counting(FileStorage, Arc<AtomicUsize>);
 
-        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        impl Storage for Counting {
+            fn exists(&self) -> bool {
+                self.0.exists()
+            }
 
-        assert_eq!(tasks.len(), 100);
+            fn load(&mut self) -> Result<Database, ToNotDoError> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.load()
+            }
+
+            fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+                self.0.save(db)
+            }
+
+            fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+                self.0.save_revised(db)
+            }
+
+            fn revision(&self) -> Option<Revision> {
+                self.0.revision()
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+        let loads = Arc::new(AtomicUsize::new(0));
+        let mut here = DatabaseManager::with_storage(Box::new(Counting(
+            FileStorage::new(&path),
+            loads.clone(),
+        )))
+        .unwrap();
+        here.add_task(&Task::new("Mine")).unwrap();
+        let loaded = loads.load(Ordering::SeqCst);
+
+        for _ in 0..3 {
+            assert_eq!(here.get_tasks().unwrap().len(), 1);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), loaded);
+
+        let theirs = Task::new("Theirs");
+        DatabaseManager::open(&path)
+            .unwrap()
+            .add_task(&theirs)
+            .unwrap();
+        assert_eq!(here.get_tasks().unwrap().len(), 2);
+        assert_eq!(loads.load(Ordering::SeqCst), loaded + 1);
     }
 
     #[test]
-    fn test_update_task_state() {
-        let dir = tempdir().unwrap();
+    fn test_refresh_loads_write_right_after_save() {
+        /// Has another process add a task right after the first task is
+        /// saved.
+        struct Interrupted(FileStorage, PathBuf);
+
+        impl Storage for Interrupted {
+            fn exists(&self) -> bool {
+                self.0.exists()
+            }
 
-        let data_dir = create_data_directory(dir.path());
-        let db_path = data_dir.join(DB_FILE_NAME);
+            fn load(&mut self) -> Result<Database, ToNotDoError> {
+                self.0.load()
+            }
 
-        let mut db_manager = DatabaseManager::open(&db_path);
+            fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+                self.save_revised(db).map(|_| ())
+            }
 
-        let task = Task {
-            id: Uuid::new_v4(),
-            description: "Task to update".to_string(),
-            state: TaskState::Todo,
-            created_at: Utc::now().date_naive(),
-            updated_at: Utc::now().date_naive(),
-        };
+            fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+                let revision = self.0.save_revised(db)?;
+                if db.tasks().len() == 1 {
+                    let mut theirs = db.clone();
+                    theirs.insert_task(Task::new("Theirs"))?;
+                    FileStorage::new(&self.1).save(&theirs)?;
+                }
+                Ok(revision)
+            }
 
-        db_manager.add_task(&task).expect("Failed to add task");
+            fn revision(&self) -> Option<Revision> {
+                self.0.revision()
+            }
+        }
 
-        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+        let mut here = DatabaseManager::with_storage(Box::new(Interrupted(
+            FileStorage::new(&path),
+            path.clone(),
+        )))
+        .unwrap();
+
+        here.add_task(&Task::new("Mine")).unwrap();
+        assert_eq!(here.get_tasks().unwrap().len(), 2);
+    }
 
-        let task_id = tasks.first().expect("Failed to get task").id;
+    #[test]
+    fn test_compact_database() {
+        let mut db = Database::default();
+        let mut task = Task::new("Postponed a lot");
+        for _ in 0..3 {
+            task.postpone(Duration::days(1), None).unwrap();
+        }
+        let removed = Task::new("Removed");
+        db.insert_task(task.clone()).unwrap();
+        db.insert_task(removed.clone()).unwrap();
+        db.remove_task(removed.id());
+
+        let past = Utc::now() - Duration::days(1);
+        assert_eq!(db.compact(past, Some(past)), (0, 0));
+
+        let future = Utc::now() + Duration::days(1);
+        assert_eq!(db.compact(future, Some(future)), (1, 2));
+        let history = db.tasks()[0].history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].event, "3 earlier events compacted");
+        assert_eq!(history[0].at, task.history()[2].at);
+    }
+
+    #[test]
+    fn test_purge_expired_archived_tasks() {
+        let dir = tempdir().unwrap();
+        let mut db_manager = DatabaseManager::open(&dir.path().join("tasks.json")).unwrap();
+
+        let old = Task::new("Archived long ago");
+        let recent = Task::new("Archived today");
+        let active = Task::new("Active");
+        for task in [&old, &recent, &active] {
+            db_manager.add_task(task).unwrap();
+        }
+        db_manager.archive_task(old.id()).unwrap();
+        db_manager.archive_task(recent.id()).unwrap();
+        let stamp = db_manager
+            .db
+            .tasks
+            .iter_mut()
+            .next()
+            .unwrap()
+            .clock
+            .get_mut(&TaskField::Archived)
+            .unwrap();
+        stamp.at -= Duration::days(40);
+        db_manager.persist().unwrap();
+
+        let forever = CompactConfig::default();
+        assert!(db_manager.purge_expired(&forever).unwrap().is_empty());
+
+        let month = CompactConfig {
+            archived_days: 30,
+            ..CompactConfig::default()
+        };
+        assert_eq!(db_manager.purge_expired(&month).unwrap().archived, 1);
+        assert!(db_manager.get_task(old.id()).is_none());
+        assert!(db_manager.get_task(recent.id()).is_some());
+
+        // With automatic retention the policies apply on every save.
+        db_manager.set_retention(CompactConfig {
+            archived_days: 1,
+            ..CompactConfig::default()
+        });
+        let stamp = db_manager
+            .db
+            .tasks
+            .iter_mut()
+            .next()
+            .unwrap()
+            .clock
+            .get_mut(&TaskField::Archived)
+            .unwrap();
+        stamp.at -= Duration::days(2);
         db_manager
-            .set_task_state(task_id, TaskState::Done)
-            .expect("Failed to update task state");
+            .update_description(active.id(), "Still active")
+            .unwrap();
+        assert!(db_manager.get_task(recent.id()).is_none());
+        assert!(db_manager.get_task(active.id()).is_some());
+    }
 
-        let new_tasks = db_manager.get_tasks().expect("Failed to get tasks");
-        let updated_task = new_tasks.iter().find(|t| t.id == task_id).unwrap();
+    #[test]
+    fn test_task_index() {
+        let mut db = Database::default();
+        let tasks: Vec<Task> = (0..5).map(|n| Task::new(&format!("Task {}", n))).collect();
+        for task in &tasks {
+            db.insert_task(task.clone()).unwrap();
+        }
+        assert!(db.insert_task(tasks[2].clone()).is_err());
+
+        db.apply(Change::RemoveTask { id: tasks[1].id });
+        db.put_task(tasks[3].clone().with_description("Replaced"));
+        let descriptions: Vec<&str> = db.tasks().iter().map(Task::description).collect();
+        assert_eq!(descriptions, ["Task 0", "Task 2", "Replaced", "Task 4"]);
+        assert_eq!(db.tasks.get(tasks[4].id), Some(&tasks[4]));
+        assert!(db.tasks.get(tasks[1].id).is_none());
+
+        // Loading builds the index too, keeping the first of repeated IDs.
+        let mut json = serde_json::to_value(&db).unwrap();
+        let repeated = json["tasks"][0].clone();
+        json["tasks"].as_array_mut().unwrap().push(repeated);
+        let loaded: Database = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.tasks().len(), 5);
+        assert_eq!(loaded.tasks.get(tasks[0].id), Some(&loaded.tasks()[0]));
+        assert_eq!(
+            loaded.tasks.get(tasks[3].id).unwrap().description(),
+            "Replaced"
+        );
+    }
 
-        assert_eq!(updated_task.state, TaskState::Done);
+    #[test]
+    fn test_merge_field() {
+        assert_eq!(merge_field(&1, &2, &1, false), 2);
+        assert_eq!(merge_field(&1, &1, &3, true), 3);
+        assert_eq!(merge_field(&1, &2, &3, true), 2);
+        assert_eq!(merge_field(&1, &2, &3, false), 3);
+
+        assert!(MergePreference::Ours.local_wins(false));
+        assert!(!MergePreference::Theirs.local_wins(true));
+        assert!(MergePreference::Newest.local_wins(true));
     }
 
     #[test]
-    fn test_remove_task() {
+    fn test_merge_database() {
         let dir = tempdir().unwrap();
 
-        let data_dir = create_data_directory(dir.path());
-        let db_path = data_dir.join(DB_FILE_NAME);
+        let mut ours = DatabaseManager::open(&dir.path().join("ours.json")).unwrap();
+        let mut theirs = DatabaseManager::open(&dir.path().join("theirs.json")).unwrap();
+
+        let shared = Task::new("Shared");
+        let mut edited = Task::new("Edited on both sides");
+        theirs.add_task(&shared).unwrap();
+        theirs.add_task(&edited).unwrap();
+        // Our copy was last touched earlier, so theirs is the newest.
+        edited.updated_at -= Duration::days(1);
+        ours.add_task(&shared).unwrap();
+        ours.add_task(&edited).unwrap();
+        let only_theirs = Task::new("Only theirs");
+        theirs.add_task(&only_theirs).unwrap();
+        theirs.set_task_state(edited.id(), TaskState::Done).unwrap();
+
+        let other = theirs.storage.load().unwrap();
+        let report = ours.merge_database(other.clone(), None).unwrap();
+
+        assert_eq!(report.added, 1);
+        assert_eq!(report.identical, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        assert!(!report.conflicts[0].took_theirs);
+        assert_eq!(ours.get_task(edited.id()).unwrap().state(), TaskState::Todo);
+        assert!(ours.contains_task(only_theirs.id()));
+
+        let report = ours
+            .merge_database(other, Some(MergePreference::Newest))
+            .unwrap();
+
+        assert_eq!(report.added, 0);
+        assert!(report.conflicts[0].took_theirs);
+        assert_eq!(ours.get_task(edited.id()).unwrap().state(), TaskState::Done);
+    }
 
-        let mut db_manager = DatabaseManager::open(&db_path);
+    #[test]
+    fn test_read_invalid_backup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("backup.json");
 
-        let task = Task {
-            id: Uuid::new_v4(),
-            description: "Task to remove".to_string(),
-            state: TaskState::Todo,
-            created_at: Utc::now().date_naive(),
-            updated_at: Utc::now().date_naive(),
-        };
+        std::fs::write(&path, "{\"tasks\": []}").unwrap();
+        assert!(matches!(
+            Backup::read(&path, None),
+            Err(ToNotDoError::DatabaseError(
+                crate::error::DatabaseError::InvalidBackup(_)
+            ))
+        ));
 
-        db_manager.add_task(&task).expect("Failed to add task");
-        assert_eq!(db_manager.db.tasks.len(), 1);
+        assert!(Backup::read(&dir.path().join("missing.json"), None).is_err());
+    }
 
-        let tasks = db_manager.get_tasks().expect("Failed to get tasks");
-        let task_id = tasks.first().expect("Failed to get task").id;
+    #[test]
+    fn test_resolve_data_directory_override() {
+        let dir = tempdir().unwrap();
+        let data_dir = dir.path().join("nested").join("state");
 
-        db_manager
-            .delete_task(task_id)
-            .expect("Failed to remove task");
+        assert_eq!(
+            AppDirs::resolve(Some(data_dir.clone().into_os_string())),
+            AppDirs::single(&data_dir)
+        );
+        assert!(data_dir.is_dir());
+    }
 
-        assert_eq!(db_manager.db.tasks.len(), 0);
+    #[test]
+    fn test_migrate_single_directory() {
+        let dir = tempdir().unwrap();
+        let dirs = AppDirs {
+            config: dir.path().join("config"),
+            data: dir.path().join("data"),
+            state: dir.path().join("state"),
+        };
+        let work = dirs.data.join("profiles").join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        std::fs::write(dirs.data.join("config.toml"), "[list]\n").unwrap();
+        std::fs::write(dirs.data.join(DB_FILE_NAME), "{}").unwrap();
+        std::fs::write(dirs.data.join("task_manager.sync"), "{}").unwrap();
+        std::fs::write(dirs.data.join("task_manager.journal"), "").unwrap();
+        std::fs::write(work.join("config.toml"), "[list]\n").unwrap();
+
+        dirs.migrate_single_directory().unwrap();
+
+        let paths = AppPaths::in_dirs(&dirs);
+        assert!(paths.config_file.is_file());
+        assert!(paths.db_file.is_file());
+        assert!(paths.state_file("sync").is_file());
+        assert!(paths.journal_file().starts_with(&dirs.state));
+        assert!(paths.journal_file().is_file());
+        assert!(!dirs.data.join("config.toml").exists());
+        assert!(AppPaths::for_profile(&dirs, "work")
+            .unwrap()
+            .config_file
+            .is_file());
+
+        let mut paths = paths;
+        paths.db_file = dir.path().join("elsewhere.json");
+        assert_eq!(paths.state_file("sync"), dir.path().join("elsewhere.sync"));
+    }
+
+    #[test]
+    fn test_find_local_directory() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("src").join("deep");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(find_local_directory(&nested), None);
+
+        std::fs::create_dir(dir.path().join(LOCAL_DIR_NAME)).unwrap();
+        assert_eq!(
+            find_local_directory(&nested),
+            Some(dir.path().join(LOCAL_DIR_NAME))
+        );
     }
 
     #[test]
@@ -435,10 +3448,6 @@ mod tests {
         let mut file = File::create(&db_path).unwrap();
         file.write_all(b"corrupted data").unwrap();
 
-        let result = std::panic::catch_unwind(|| {
-            DatabaseManager::open(&db_path);
-        });
-
-        assert!(result.is_err());
+        assert!(DatabaseManager::open(&db_path).is_err());
     }
 }
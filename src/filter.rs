@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+use crate::file_management::{Task, TaskState};
+
+/// Which tasks a listing shows. The default matches every active task.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TaskFilter {
+    pub state: Option<TaskState>,
+    /// Only tasks in this project, as with an active context.
+    pub project: Option<String>,
+    /// Match archived tasks instead of active ones.
+    pub archived: bool,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, task: &Task) -> bool {
+        task.is_archived() == self.archived
+            && self.state.is_none_or(|state| task.state() == state)
+            && self
+                .project
+                .as_deref()
+                .is_none_or(|project| task.project() == Some(project))
+    }
+
+    /// The tasks in `tasks` that match, in order.
+    pub fn apply(&self, tasks: &[Task]) -> Vec<Task> {
+        tasks.iter().filter(|t| self.matches(t)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_filter() {
+        let todo = Task::new("Todo").with_project("home");
+        let done = Task::new("Done").with_completed_at(chrono::Utc::now().date_naive());
+        let tasks = [todo.clone(), done.clone()];
+
+        assert_eq!(TaskFilter::default().apply(&tasks).len(), 2);
+
+        let only_done = TaskFilter {
+            state: Some(TaskState::Done),
+            ..TaskFilter::default()
+        };
+        assert_eq!(only_done.apply(&tasks), [done]);
+
+        let at_home = TaskFilter {
+            project: Some("home".to_string()),
+            ..TaskFilter::default()
+        };
+        assert_eq!(at_home.apply(&tasks), [todo]);
+
+        let archived = TaskFilter {
+            archived: true,
+            ..TaskFilter::default()
+        };
+        assert!(archived.apply(&tasks).is_empty());
+    }
+}
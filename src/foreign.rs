@@ -0,0 +1,639 @@
+use std::path::Path;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::Map;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Database, DatabaseManager, Priority, Task, TaskState},
+};
+
+/// Taskwarrior attributes that map onto task fields, or that are left out
+/// because they only make sense inside Taskwarrior: its working set IDs,
+/// urgency, dependencies and recurrence. Any other attribute, such as a user
+/// defined one, is kept as metadata.
+const TASKWARRIOR_ATTRIBUTES: [&str; 19] = [
+    "uuid",
+    "description",
+    "status",
+    "entry",
+    "modified",
+    "start",
+    "end",
+    "due",
+    "project",
+    "priority",
+    "tags",
+    "annotations",
+    "id",
+    "urgency",
+    "depends",
+    "recur",
+    "mask",
+    "imask",
+    "parent",
+];
+
+/// Task lists written by other tools that can be converted in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForeignFormat {
+    /// One task per line, see <https://github.com/todotxt/todo.txt>.
+    TodoTxt,
+    /// The output of `task export`.
+    Taskwarrior,
+}
+
+impl std::fmt::Display for ForeignFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ForeignFormat::TodoTxt => write!(f, "todo.txt"),
+            ForeignFormat::Taskwarrior => write!(f, "Taskwarrior export"),
+        }
+    }
+}
+
+fn invalid(format: ForeignFormat, reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::DatabaseError(DatabaseError::InvalidDump(format!(
+        "not a valid {}: {}",
+        format, reason
+    )))
+}
+
+/// Recognizes a task list written by another tool in the file at `path`
+/// holding `data`. Only meant for files that failed to load as a database,
+/// since a plain text database is hard to tell from a todo.txt.
+pub fn detect(path: &Path, data: &[u8]) -> Option<ForeignFormat> {
+    if taskwarrior_tasks(data).is_some() {
+        return Some(ForeignFormat::Taskwarrior);
+    }
+
+    let text = std::str::from_utf8(data).ok()?;
+    let first = text.trim_start().chars().next()?;
+    if matches!(first, '{' | '[') {
+        return None;
+    }
+
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let looks_like_config = |line: &&str| {
+        line.split_once([':', '='])
+            .is_some_and(|(key, _)| !key.trim().is_empty() && !key.trim().contains(' '))
+            && !line.starts_with("x ")
+    };
+    if lines.iter().any(looks_like_config) {
+        return None;
+    }
+
+    let named_txt = path.extension().is_some_and(|ext| ext == "txt");
+    let has_markers = lines.iter().any(|line| {
+        line.starts_with("x ")
+            || parse_priority(line.split_whitespace().next().unwrap_or_default()).is_some()
+            || line
+                .split_whitespace()
+                .any(|word| word.len() > 1 && (word.starts_with('+') || word.starts_with('@')))
+    });
+
+    (named_txt || has_markers).then_some(ForeignFormat::TodoTxt)
+}
+
+/// Converts a task list in `format` into a database.
+pub fn convert(format: ForeignFormat, data: &[u8]) -> Result<Database, ToNotDoError> {
+    let tasks = match format {
+        ForeignFormat::TodoTxt => {
+            let text = std::str::from_utf8(data).map_err(|e| invalid(format, e))?;
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(parse_todo_txt_line)
+                .collect()
+        }
+        ForeignFormat::Taskwarrior => taskwarrior_tasks(data)
+            .ok_or_else(|| invalid(format, "expected a JSON list of tasks"))?
+            .iter()
+            .filter_map(parse_taskwarrior_task)
+            .collect::<Vec<_>>(),
+    };
+
+    let mut db = Database::default();
+    for task in tasks {
+        db.insert_task(task)?;
+    }
+    Ok(db)
+}
+
+/// `(A)` is the most urgent todo.txt priority; everything after `(C)` is
+/// low too.
+fn parse_priority(word: &str) -> Option<Priority> {
+    let letter = word.strip_prefix('(')?.strip_suffix(')')?;
+    match letter {
+        "A" => Some(Priority::High),
+        "B" => Some(Priority::Medium),
+        _ if letter.len() == 1 && letter.chars().all(|c| c.is_ascii_uppercase()) => {
+            Some(Priority::Low)
+        }
+        _ => None,
+    }
+}
+
+fn parse_date(word: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()
+}
+
+/// Parses `x 2024-03-02 2024-03-01 (A) Call mom +family @phone due:2024-03-05`.
+fn parse_todo_txt_line(line: &str) -> Task {
+    let mut words = line.split_whitespace().peekable();
+
+    let done = words.next_if_eq(&"x").is_some();
+    let priority = words
+        .next_if(|w| parse_priority(w).is_some())
+        .and_then(parse_priority);
+    let first_date = words
+        .next_if(|w| parse_date(w).is_some())
+        .and_then(parse_date);
+    let second_date = words
+        .next_if(|w| parse_date(w).is_some())
+        .and_then(parse_date);
+    // Done tasks put the completion date first; pending ones only have one.
+    let (completed_at, created_at) = match (done, first_date, second_date) {
+        (true, completed, created) => (completed, created),
+        (false, created, _) => (None, created),
+    };
+
+    let mut description = Vec::new();
+    let mut project = None;
+    let mut tags = Vec::new();
+    let mut due = None;
+    let mut priority = priority;
+    for word in words {
+        if let Some(name) = word.strip_prefix('+').filter(|n| !n.is_empty()) {
+            match project {
+                None => project = Some(name.to_string()),
+                Some(_) => tags.push(name.to_string()),
+            }
+        } else if let Some(context) = word.strip_prefix('@').filter(|c| !c.is_empty()) {
+            tags.push(context.to_string());
+        } else if let Some(date) = word.strip_prefix("due:").and_then(parse_date) {
+            due = Some(date);
+        } else if let Some(pri) = word
+            .strip_prefix("pri:")
+            .and_then(|p| parse_priority(&format!("({})", p)))
+        {
+            priority = Some(pri);
+        } else {
+            description.push(word);
+        }
+    }
+
+    let mut task = Task::new(&description.join(" "));
+    if let Some(project) = project {
+        task = task.with_project(&project);
+    }
+    if !tags.is_empty() {
+        task = task.with_tags(&tags);
+    }
+    if let Some(due) = due {
+        task = task.with_due(due);
+    }
+    if let Some(priority) = priority {
+        task = task.with_priority(priority);
+    }
+    if let Some(created_at) = created_at {
+        task = task.with_created_at(created_at);
+    }
+    if done {
+        task =
+            task.with_completed_at(completed_at.unwrap_or_else(|| chrono::Utc::now().date_naive()));
+    }
+    task
+}
+
+fn is_taskwarrior_task(value: &Value) -> bool {
+    value.get("uuid").is_some_and(Value::is_string)
+        && value.get("description").is_some_and(Value::is_string)
+        && value.get("tasks").is_none()
+}
+
+/// The tasks of a Taskwarrior export, either a JSON list or one object per
+/// line as older versions wrote.
+fn taskwarrior_tasks(data: &[u8]) -> Option<Vec<Value>> {
+    let tasks = match serde_json::from_slice::<Value>(data) {
+        Ok(Value::Array(tasks)) => tasks,
+        Ok(task @ Value::Object(_)) => vec![task],
+        _ => std::str::from_utf8(data)
+            .ok()?
+            .lines()
+            .map(|line| line.trim().trim_end_matches(','))
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .ok()?,
+    };
+
+    (!tasks.is_empty() && tasks.iter().all(is_taskwarrior_task)).then_some(tasks)
+}
+
+/// Taskwarrior writes dates as `20240301T120000Z`.
+fn parse_taskwarrior_date(value: Option<&Value>) -> Option<NaiveDate> {
+    let value = value?.as_str()?;
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+        .map(|at| at.date())
+        .ok()
+        .or_else(|| value.get(..10).and_then(parse_date))
+}
+
+/// Converts one exported task, skipping deleted ones.
+fn parse_taskwarrior_task(value: &Value) -> Option<Task> {
+    let field = |name: &str| value.get(name).and_then(Value::as_str);
+
+    let status = field("status").unwrap_or("pending");
+    if status == "deleted" {
+        return None;
+    }
+
+    let mut task = Task::new(field("description")?);
+    if let Some(id) = field("uuid").and_then(|id| Uuid::parse_str(id).ok()) {
+        task = task.with_id(id);
+    }
+    if let Some(project) = field("project") {
+        task = task.with_project(project);
+    }
+    if let Some(priority) = field("priority") {
+        task = task.with_priority(match priority {
+            "H" => Priority::High,
+            "M" => Priority::Medium,
+            _ => Priority::Low,
+        });
+    }
+    if let Some(tags) = value.get("tags").and_then(Value::as_array) {
+        let tags: Vec<String> = tags
+            .iter()
+            .filter_map(|tag| tag.as_str().map(str::to_string))
+            .collect();
+        task = task.with_tags(&tags);
+    }
+    if let Some(due) = parse_taskwarrior_date(value.get("due")) {
+        task = task.with_due(due);
+    }
+    if let Some(created_at) = parse_taskwarrior_date(value.get("entry")) {
+        task = task.with_created_at(created_at);
+    }
+
+    let annotations: Vec<&str> = value
+        .get("annotations")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|annotation| annotation.get("description")?.as_str())
+        .collect();
+    if !annotations.is_empty() {
+        task = task.with_notes(&annotations.join("\n"));
+    }
+
+    for (key, value) in value.as_object().into_iter().flatten() {
+        if TASKWARRIOR_ATTRIBUTES.contains(&key.as_str()) {
+            continue;
+        }
+        match value {
+            Value::String(text) => task = task.with_metadata(key, text),
+            Value::Number(_) | Value::Bool(_) => task = task.with_metadata(key, &value.to_string()),
+            _ => {}
+        }
+    }
+
+    if status == "completed" {
+        let completed_at = parse_taskwarrior_date(value.get("end"))
+            .unwrap_or_else(|| chrono::Utc::now().date_naive());
+        task = task.with_completed_at(completed_at);
+    } else if value.get("start").is_some() {
+        task = task.with_state(TaskState::InProgress);
+    }
+
+    Some(task)
+}
+
+/// Taskwarrior keeps times in UTC; a date here is taken as its midnight.
+fn taskwarrior_date(date: NaiveDate) -> Value {
+    Value::String(date.format("%Y%m%dT000000Z").to_string())
+}
+
+/// One task as `task export` writes it.
+fn taskwarrior_task(task: &Task) -> Value {
+    let mut object: Map<String, Value> = task
+        .metadata()
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+        .collect();
+    let mut set = |key: &str, value: Value| {
+        object.insert(key.to_string(), value);
+    };
+
+    set("uuid", task.id().to_string().into());
+    set("description", task.description().into());
+    let status = match task.state() {
+        TaskState::Done => "completed",
+        TaskState::Todo | TaskState::InProgress => "pending",
+    };
+    set("status", status.into());
+    set("entry", taskwarrior_date(task.created_at()));
+    set("modified", taskwarrior_date(task.updated_at()));
+    if let Some(end) = task.completed_at() {
+        set("end", taskwarrior_date(end));
+    }
+    if task.state() == TaskState::InProgress {
+        set("start", taskwarrior_date(task.updated_at()));
+    }
+    if let Some(due) = task.due() {
+        set("due", taskwarrior_date(due));
+    }
+    if let Some(project) = task.project() {
+        set("project", project.into());
+    }
+    if let Some(priority) = task.priority() {
+        let priority = match priority {
+            Priority::High => "H",
+            Priority::Medium => "M",
+            Priority::Low => "L",
+        };
+        set("priority", priority.into());
+    }
+    if !task.tags().is_empty() {
+        set("tags", task.tags().into());
+    }
+    if let Some(notes) = task.notes() {
+        let annotations = notes
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::json!({
+                    "entry": taskwarrior_date(task.updated_at()),
+                    "description": line,
+                })
+            })
+            .collect();
+        set("annotations", Value::Array(annotations));
+    }
+
+    Value::Object(object)
+}
+
+/// Writes `tasks` as `task export` does, for `task import`. Each line of
+/// the notes becomes an annotation and metadata becomes attributes, so
+/// Taskwarrior keeps its user defined attributes.
+pub fn taskwarrior_export(tasks: &[Task]) -> String {
+    let tasks: Vec<String> = tasks
+        .iter()
+        .map(|task| taskwarrior_task(task).to_string())
+        .collect();
+    format!("[\n{}\n]\n", tasks.join(",\n"))
+}
+
+/// What [`import_taskwarrior`] changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TaskwarriorImport {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// Adds the tasks of a Taskwarrior export, updates the ones already here
+/// under the same UUID and deletes the ones deleted in Taskwarrior. Parents
+/// and archiving, which Taskwarrior does not know, stay as they are.
+pub fn import_taskwarrior(
+    db_manager: &mut DatabaseManager,
+    data: &[u8],
+) -> Result<TaskwarriorImport, ToNotDoError> {
+    let values = taskwarrior_tasks(data)
+        .ok_or_else(|| invalid(ForeignFormat::Taskwarrior, "expected a JSON list of tasks"))?;
+
+    let mut import = TaskwarriorImport::default();
+    db_manager.begin();
+    for value in &values {
+        let stored = value
+            .get("uuid")
+            .and_then(Value::as_str)
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .and_then(|id| db_manager.get_task(id))
+            .cloned();
+
+        match (parse_taskwarrior_task(value), stored) {
+            (None, Some(stored)) => {
+                db_manager.delete_task(stored.id())?;
+                import.deleted += 1;
+            }
+            (None, None) => {}
+            (Some(task), None) => {
+                db_manager.add_task(&task)?;
+                import.added += 1;
+            }
+            (Some(mut task), Some(stored)) => {
+                if let Some(parent) = stored.parent() {
+                    task = task.with_parent(parent);
+                }
+                if stored.is_archived() {
+                    task = task.with_archived();
+                }
+                if !task.same_fields(&stored) {
+                    db_manager.update_task(&task)?;
+                    import.updated += 1;
+                }
+            }
+        }
+    }
+    db_manager.commit()?;
+
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fields without getters, as saved.
+    fn field(task: &Task, name: &str) -> Value {
+        serde_json::to_value(task).unwrap()[name].clone()
+    }
+
+    #[test]
+    fn test_convert_todo_txt() {
+        let data = b"(A) 2024-03-01 Call mom +family @phone due:2024-03-05\n\
+            x 2024-03-02 2024-03-01 Pay rent +home pri:B\n\
+            \n\
+            Water the plants\n";
+        let path = Path::new("todo.txt");
+
+        assert_eq!(detect(path, data), Some(ForeignFormat::TodoTxt));
+        let db = convert(ForeignFormat::TodoTxt, data).unwrap();
+        let tasks = db.tasks();
+        assert_eq!(tasks.len(), 3);
+
+        assert_eq!(tasks[0].description(), "Call mom");
+        assert_eq!(tasks[0].project(), Some("family"));
+        assert_eq!(tasks[0].tags(), ["phone"]);
+        assert_eq!(field(&tasks[0], "priority"), "High");
+        assert_eq!(field(&tasks[0], "due"), "2024-03-05");
+        assert_eq!(tasks[0].state(), TaskState::Todo);
+
+        assert_eq!(tasks[1].description(), "Pay rent");
+        assert_eq!(tasks[1].state(), TaskState::Done);
+        assert_eq!(tasks[1].completed_at(), parse_date("2024-03-02"));
+        assert_eq!(field(&tasks[1], "priority"), "Medium");
+
+        assert_eq!(tasks[2].description(), "Water the plants");
+
+        // Without the extension only lines that look like todo.txt count.
+        assert_eq!(
+            detect(Path::new("tasks.json"), b"Buy milk +errands\n"),
+            Some(ForeignFormat::TodoTxt)
+        );
+        assert_eq!(detect(Path::new("tasks.json"), b"Buy milk\n"), None);
+        assert_eq!(
+            detect(Path::new("tasks.toml"), b"name = \"to-not-do\"\n"),
+            None
+        );
+        assert_eq!(detect(Path::new("tasks.json"), b"{\"tasks\": ["), None);
+    }
+
+    #[test]
+    fn test_convert_taskwarrior_export() {
+        let data = br#"[
+            {"uuid":"2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10","description":"Write report",
+             "status":"pending","entry":"20240301T120000Z","start":"20240302T090000Z",
+             "project":"work","priority":"H","tags":["q1"],"due":"20240310T000000Z",
+             "annotations":[{"entry":"20240301T130000Z","description":"Use the new template"}]},
+            {"uuid":"6b1c6f8e-6a4e-4a38-9b7e-0d6c1d4f0c21","description":"Old idea",
+             "status":"deleted","entry":"20240101T000000Z"},
+            {"uuid":"9f1d2a3b-4c5d-4e6f-8a9b-0c1d2e3f4a5b","description":"Renew passport",
+             "status":"completed","entry":"20240101T000000Z","end":"20240215T100000Z"}
+        ]"#;
+
+        assert_eq!(
+            detect(Path::new("tasks.json"), data),
+            Some(ForeignFormat::Taskwarrior)
+        );
+        let db = convert(ForeignFormat::Taskwarrior, data).unwrap();
+        let tasks = db.tasks();
+        assert_eq!(tasks.len(), 2);
+
+        assert_eq!(
+            tasks[0].id(),
+            Uuid::parse_str("2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10").unwrap()
+        );
+        assert_eq!(tasks[0].state(), TaskState::InProgress);
+        assert_eq!(tasks[0].project(), Some("work"));
+        assert_eq!(field(&tasks[0], "priority"), "High");
+        assert_eq!(tasks[0].tags(), ["q1"]);
+        assert_eq!(field(&tasks[0], "due"), "2024-03-10");
+        assert_eq!(field(&tasks[0], "notes"), "Use the new template");
+
+        assert_eq!(tasks[1].state(), TaskState::Done);
+        assert_eq!(tasks[1].completed_at(), parse_date("2024-02-15"));
+
+        // Older versions wrote one task per line.
+        let lines = b"{\"uuid\":\"2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10\",\"description\":\"a\",\"status\":\"pending\"},\n\
+            {\"uuid\":\"9f1d2a3b-4c5d-4e6f-8a9b-0c1d2e3f4a5b\",\"description\":\"b\",\"status\":\"pending\"}\n";
+        assert_eq!(
+            detect(Path::new("tasks.json"), lines),
+            Some(ForeignFormat::Taskwarrior)
+        );
+        assert_eq!(
+            convert(ForeignFormat::Taskwarrior, lines)
+                .unwrap()
+                .tasks()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_taskwarrior_round_trip() {
+        let data =
+            br#"[{"uuid":"2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10","description":"Write report",
+            "status":"pending","entry":"20240301T120000Z","project":"work","priority":"M",
+            "tags":["q1","writing"],"due":"20240310T000000Z","estimate":3,"client":"acme",
+            "urgency":7.4,"id":12,"depends":["9f1d2a3b-4c5d-4e6f-8a9b-0c1d2e3f4a5b"],
+            "annotations":[{"entry":"20240301T130000Z","description":"Use the new template"},
+                           {"entry":"20240302T130000Z","description":"Ask Sam for numbers"}]}]"#;
+
+        let db = convert(ForeignFormat::Taskwarrior, data).unwrap();
+        let task = &db.tasks()[0];
+        assert_eq!(
+            task.metadata().iter().collect::<Vec<_>>(),
+            [
+                (&"client".to_string(), &"acme".to_string()),
+                (&"estimate".to_string(), &"3".to_string())
+            ]
+        );
+
+        let export = taskwarrior_export(db.tasks());
+        let exported: Vec<Value> = serde_json::from_str(&export).unwrap();
+        let exported = &exported[0];
+        assert_eq!(exported["uuid"], "2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10");
+        assert_eq!(exported["status"], "pending");
+        assert_eq!(exported["entry"], "20240301T000000Z");
+        assert_eq!(exported["due"], "20240310T000000Z");
+        assert_eq!(exported["project"], "work");
+        assert_eq!(exported["priority"], "M");
+        assert_eq!(exported["tags"], serde_json::json!(["q1", "writing"]));
+        assert_eq!(exported["client"], "acme");
+        assert_eq!(
+            exported["annotations"][1]["description"],
+            "Ask Sam for numbers"
+        );
+        assert!(exported.get("urgency").is_none());
+
+        let reimported = convert(ForeignFormat::Taskwarrior, export.as_bytes()).unwrap();
+        assert!(reimported.tasks()[0].same_fields(task));
+        assert_eq!(taskwarrior_export(&[]), "[\n\n]\n");
+    }
+
+    #[test]
+    fn test_import_taskwarrior() {
+        let mut db_manager =
+            DatabaseManager::with_storage(Box::new(crate::storage::MemoryStorage::new())).unwrap();
+        let parent = Task::new("Quarterly review");
+        let report = Task::new("Write report")
+            .with_parent(parent.id())
+            .with_project("work");
+        let old = Task::new("Old idea");
+        for task in [&parent, &report, &old] {
+            db_manager.add_task(task).unwrap();
+        }
+
+        let mut export: Vec<Value> =
+            serde_json::from_str(&taskwarrior_export(&[report.clone(), old.clone()])).unwrap();
+        export[0]["description"] = "Write the report".into();
+        export[0]["client"] = "acme".into();
+        export[1]["status"] = "deleted".into();
+        export.push(serde_json::json!({
+            "uuid": "9f1d2a3b-4c5d-4e6f-8a9b-0c1d2e3f4a5b",
+            "description": "Renew passport",
+            "status": "pending",
+        }));
+        let data = serde_json::to_vec(&export).unwrap();
+
+        let import = import_taskwarrior(&mut db_manager, &data).unwrap();
+        assert_eq!(
+            import,
+            TaskwarriorImport {
+                added: 1,
+                updated: 1,
+                deleted: 1
+            }
+        );
+        let updated = db_manager.get_task(report.id()).unwrap();
+        assert_eq!(updated.description(), "Write the report");
+        assert_eq!(updated.parent(), Some(parent.id()), "parents stay");
+        assert_eq!(updated.metadata()["client"], "acme");
+        assert!(db_manager.get_task(old.id()).is_none());
+        assert_eq!(db_manager.get_tasks().unwrap().len(), 3);
+
+        let import = import_taskwarrior(&mut db_manager, &data).unwrap();
+        assert_eq!(import, TaskwarriorImport::default());
+        assert!(import_taskwarrior(&mut db_manager, b"[]").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Serialization used for the database file.
+#[derive(Debug, Default, ValueEnum, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    #[value(name = "msgpack")]
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Picks the format matching the file extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "msgpack" | "mp" => Some(Format::MessagePack),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Recognizes formats that can be told apart by their first byte. Text
+    /// formats other than JSON are left to the file extension or config.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data.iter().find(|b| !b.is_ascii_whitespace())? {
+            b'{' => Some(Format::Json),
+            0x80..=0x8f | 0xde | 0xdf => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec_pretty(value).map_err(|e| e.to_string()),
+            Format::MessagePack => {
+                // Keep IDs and dates as strings so files stay readable as
+                // generic values during migrations.
+                let mut data = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut data)
+                    .with_struct_map()
+                    .with_human_readable();
+                value
+                    .serialize(&mut serializer)
+                    .map_err(|e| e.to_string())?;
+                Ok(data)
+            }
+            Format::Toml => toml::to_string_pretty(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Parses `data` into a generic value so it can be migrated before being
+    /// turned into the current types.
+    pub fn to_value(self, data: &[u8]) -> Result<Value, String> {
+        match self {
+            Format::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(data).map_err(|e| e.to_string()),
+            Format::Toml => std::str::from_utf8(data)
+                .map_err(|e| e.to_string())
+                .and_then(|text| toml::from_str(text).map_err(|e| e.to_string())),
+            Format::Yaml => serde_yaml::from_slice(data).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_format_round_trip() {
+        let value = json!({"name": "to-not-do", "tasks": [{"id": "1", "tags": ["a"]}]});
+
+        for format in [
+            Format::Json,
+            Format::MessagePack,
+            Format::Toml,
+            Format::Yaml,
+        ] {
+            let data = format.to_bytes(&value).unwrap();
+
+            assert_eq!(format.to_value(&data).unwrap(), value);
+        }
+
+        assert_eq!(
+            Format::sniff(&Format::MessagePack.to_bytes(&value).unwrap()),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(Format::sniff(&Format::Toml.to_bytes(&value).unwrap()), None);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            Format::from_path(Path::new("tasks.json")),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("tasks.msgpack")),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("tasks.yml")),
+            Some(Format::Yaml)
+        );
+        assert_eq!(Format::from_path(Path::new("tasks")), None);
+    }
+}
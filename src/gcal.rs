@@ -0,0 +1,1029 @@
+//! Tasks with a due date as all-day events in a Google Calendar, kept in
+//! step by `sync gcal`.
+//!
+//! Every open task with a due date gets an event on that day, titled with
+//! its description. Moving the event moves the due date, and renaming it
+//! renames the task. A done task's event is titled with a leading
+//! [`DONE_MARK`], and adding the mark in the calendar completes the task.
+//! Deleting the event takes the due date away; deleting, archiving or
+//! taking the due date off the task deletes the event.
+//!
+//! Sign-in is OAuth's device flow, with the OAuth client from the `[gcal]`
+//! config section: `sync gcal --login` shows a code to enter at Google, and
+//! the tokens it gives are kept in the OS keychain.
+//!
+//! Each sync compares both sides with how the previous one left them, which
+//! is kept in the state directory, and takes every field from the side that
+//! changed it. When both changed a field, the [`MergePreference`] decides.
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{merge_field, DatabaseManager, MergePreference, Task, TaskState, APP_NAME},
+};
+
+/// Starts the title of the event of a done task.
+pub const DONE_MARK: &str = "✓";
+
+/// What the tokens may do: manage events, and nothing else.
+const SCOPE: &str = "https://www.googleapis.com/auth/calendar.events";
+
+const DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+const DEVICE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+const DEFAULT_API_URL: &str = "https://www.googleapis.com/calendar/v3";
+
+const DEFAULT_CALENDAR: &str = "primary";
+
+/// Private event property marking the events the sync added.
+const SOURCE_PROPERTY: &str = "source";
+
+/// Private event property holding the ID of the task an event shows.
+const TASK_PROPERTY: &str = "task";
+
+/// Keychain account the tokens are stored under.
+const KEYRING_ACCOUNT: &str = "gcal";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const PAGE_SIZE: &str = "2500";
+
+/// Settings from the `[gcal]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GcalConfig {
+    /// ID of an OAuth client of the "TVs and Limited Input devices" type,
+    /// from the Google Cloud console.
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// ID of the calendar the events go to; the primary one by default.
+    pub calendar: Option<String>,
+    /// Where the Calendar API is, when not at Google itself.
+    pub api_url: Option<String>,
+}
+
+impl GcalConfig {
+    pub fn calendar(&self) -> &str {
+        self.calendar.as_deref().unwrap_or(DEFAULT_CALENDAR)
+    }
+}
+
+fn gcal_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::GcalError(reason.to_string())
+}
+
+fn agent() -> ureq::Agent {
+    // Statuses like 404 are answers here, not failures.
+    ureq::Agent::new_with_config(
+        ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(TIMEOUT))
+            .build(),
+    )
+}
+
+/// What to show so the user can grant access, as handed out by Google.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DeviceCode {
+    device_code: String,
+    /// The code to enter.
+    pub user_code: String,
+    /// Where to enter it.
+    pub verification_url: String,
+    /// Seconds until the code expires.
+    pub expires_in: u64,
+    /// Seconds to wait between polls for the tokens.
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+/// The tokens kept in the keychain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl Tokens {
+    /// Whether the access token is expired by `now`, or is about to be.
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at - chrono::Duration::minutes(1) <= now
+    }
+
+    fn keyring_entry() -> Result<keyring::Entry, ToNotDoError> {
+        keyring::Entry::new(APP_NAME, KEYRING_ACCOUNT).map_err(gcal_error)
+    }
+
+    fn stored() -> Option<Self> {
+        let json = Self::keyring_entry().ok()?.get_password().ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    fn store(&self) -> Result<(), ToNotDoError> {
+        let json = serde_json::to_string(self).map_err(gcal_error)?;
+        Self::keyring_entry()?
+            .set_password(&json)
+            .map_err(gcal_error)
+    }
+}
+
+/// What the token endpoint answered.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    expires_in: Option<i64>,
+    refresh_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+impl TokenResponse {
+    /// The tokens handed out, keeping `refresh_token` when a refresh brings
+    /// no new one.
+    fn tokens(self, refresh_token: Option<&str>, now: DateTime<Utc>) -> Result<Tokens, String> {
+        if let Some(error) = self.error {
+            return Err(match self.error_description {
+                Some(description) => format!("{}: {}", error, description),
+                None => error,
+            });
+        }
+        Ok(Tokens {
+            access_token: self.access_token.ok_or("no access token")?,
+            refresh_token: self
+                .refresh_token
+                .or_else(|| refresh_token.map(str::to_string))
+                .ok_or("no refresh token")?,
+            expires_at: now + chrono::Duration::seconds(self.expires_in.unwrap_or(0)),
+        })
+    }
+}
+
+/// The OAuth client from the `[gcal]` section.
+struct Client<'a> {
+    id: &'a str,
+    secret: &'a str,
+}
+
+impl<'a> Client<'a> {
+    fn of(config: &'a GcalConfig) -> Result<Self, ToNotDoError> {
+        match (&config.client_id, &config.client_secret) {
+            (Some(id), Some(secret)) => Ok(Self { id, secret }),
+            _ => Err(gcal_error(
+                "No OAuth client; set client_id and client_secret in the [gcal] config section",
+            )),
+        }
+    }
+
+    fn request_tokens(
+        &self,
+        agent: &ureq::Agent,
+        form: &[(&str, &str)],
+    ) -> Result<TokenResponse, ToNotDoError> {
+        let form = [("client_id", self.id), ("client_secret", self.secret)]
+            .into_iter()
+            .chain(form.iter().copied());
+        agent
+            .post(TOKEN_URL)
+            .send_form(form)
+            .map_err(gcal_error)?
+            .body_mut()
+            .read_json()
+            .map_err(gcal_error)
+    }
+}
+
+/// Signs in to Google with the device flow, showing the code to enter with
+/// `show` and waiting until it is entered, and stores the tokens in the
+/// keychain.
+pub fn login(config: &GcalConfig, show: impl FnOnce(&DeviceCode)) -> Result<(), ToNotDoError> {
+    let client = Client::of(config)?;
+    let agent = agent();
+
+    let code: DeviceCode = agent
+        .post(DEVICE_CODE_URL)
+        .send_form([("client_id", client.id), ("scope", SCOPE)])
+        .map_err(gcal_error)?
+        .body_mut()
+        .read_json()
+        .map_err(gcal_error)?;
+    show(&code);
+
+    let deadline = Utc::now() + chrono::Duration::seconds(code.expires_in as i64);
+    let mut interval = code.interval;
+    while Utc::now() < deadline {
+        std::thread::sleep(Duration::from_secs(interval));
+        let response = client.request_tokens(
+            &agent,
+            &[
+                ("device_code", &code.device_code),
+                ("grant_type", DEVICE_GRANT),
+            ],
+        )?;
+        match response.error.as_deref() {
+            Some("authorization_pending") => continue,
+            Some("slow_down") => interval += 5,
+            _ => {
+                return response
+                    .tokens(None, Utc::now())
+                    .map_err(gcal_error)?
+                    .store()
+            }
+        }
+    }
+    Err(gcal_error("The code expired before it was entered"))
+}
+
+/// A valid access token, refreshing the stored one when it expired.
+fn access_token(config: &GcalConfig, agent: &ureq::Agent) -> Result<String, ToNotDoError> {
+    let tokens =
+        Tokens::stored().ok_or_else(|| gcal_error("Not signed in; run `sync gcal --login`"))?;
+    if !tokens.expired(Utc::now()) {
+        return Ok(tokens.access_token);
+    }
+
+    let tokens = Client::of(config)?
+        .request_tokens(
+            agent,
+            &[
+                ("refresh_token", &tokens.refresh_token),
+                ("grant_type", "refresh_token"),
+            ],
+        )?
+        .tokens(Some(&tokens.refresh_token), Utc::now())
+        .map_err(|e| gcal_error(format!("{}; sign in again with `sync gcal --login`", e)))?;
+    tokens.store()?;
+    Ok(tokens.access_token)
+}
+
+/// The day of an event; timed events have a `date_time` instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct EventTime {
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default, rename = "dateTime")]
+    pub date_time: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ExtendedProperties {
+    #[serde(default)]
+    pub private: BTreeMap<String, String>,
+}
+
+/// An event as the API returns it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Event {
+    pub id: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub start: EventTime,
+    #[serde(default)]
+    pub updated: Option<String>,
+    #[serde(default, rename = "extendedProperties")]
+    pub extended_properties: ExtendedProperties,
+}
+
+impl Event {
+    fn updated(&self) -> Option<DateTime<Utc>> {
+        self.updated
+            .as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    /// The task the event was added for.
+    fn task(&self) -> Option<Uuid> {
+        let id = self.extended_properties.private.get(TASK_PROPERTY)?;
+        Uuid::parse_str(id).ok()
+    }
+}
+
+/// The parts of a task an all-day event carries: the title, the day and
+/// whether it is done.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcalFields {
+    pub title: String,
+    pub due: Option<NaiveDate>,
+    pub done: bool,
+}
+
+impl GcalFields {
+    pub fn of(task: &Task) -> Self {
+        Self {
+            title: task.description().to_string(),
+            due: task.due(),
+            done: task.state() == TaskState::Done,
+        }
+    }
+
+    fn from_event(event: &Event) -> Self {
+        let summary = event.summary.trim();
+        let title = summary.strip_prefix(DONE_MARK);
+        Self {
+            title: title.unwrap_or(summary).trim().to_string(),
+            due: event
+                .start
+                .date
+                .as_deref()
+                .or(event.start.date_time.as_deref())
+                .and_then(|date| date.get(..10))
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()),
+            done: title.is_some(),
+        }
+    }
+
+    fn summary(&self) -> String {
+        if self.done {
+            format!("{} {}", DONE_MARK, self.title)
+        } else {
+            self.title.clone()
+        }
+    }
+
+    /// Each field as whichever side edited it since `base` left it, with
+    /// `prefer` picking when both did.
+    fn merge(
+        base: &Self,
+        local: &Self,
+        remote: &Self,
+        prefer: MergePreference,
+        local_newer: bool,
+    ) -> Self {
+        let local_wins = prefer.local_wins(local_newer);
+
+        Self {
+            title: merge_field(&base.title, &local.title, &remote.title, local_wins),
+            due: merge_field(&base.due, &local.due, &remote.due, local_wins),
+            done: merge_field(&base.done, &local.done, &remote.done, local_wins),
+        }
+    }
+}
+
+/// The JSON for the event of task `task`, which must have a due date.
+fn event_body(task: Uuid, fields: &GcalFields) -> serde_json::Value {
+    let due = fields.due.unwrap_or_default();
+    let day = |date: NaiveDate| serde_json::json!({ "date": date.format("%Y-%m-%d").to_string() });
+    serde_json::json!({
+        "summary": fields.summary(),
+        "start": day(due),
+        "end": day(due.succ_opt().unwrap_or(due)),
+        // A task does not make anyone busy.
+        "transparency": "transparent",
+        "extendedProperties": {
+            "private": {
+                SOURCE_PROPERTY: APP_NAME,
+                TASK_PROPERTY: task.to_string(),
+            }
+        },
+    })
+}
+
+/// What the sync needs of the Calendar API.
+pub trait GcalApi {
+    /// The events the sync added to the calendar.
+    fn events(&self) -> Result<Vec<Event>, ToNotDoError>;
+
+    /// Adds the event of task `task`, returning its ID.
+    fn add_event(&self, task: Uuid, fields: &GcalFields) -> Result<String, ToNotDoError>;
+
+    fn update_event(&self, id: &str, task: Uuid, fields: &GcalFields) -> Result<(), ToNotDoError>;
+
+    fn delete_event(&self, id: &str) -> Result<(), ToNotDoError>;
+}
+
+/// One page of events.
+#[derive(Deserialize)]
+struct Page {
+    #[serde(default)]
+    items: Vec<Event>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+/// `text` with anything but unreserved URL characters percent-encoded.
+pub(crate) fn encode_segment(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The events of one calendar, through the Calendar API.
+pub struct GcalClient {
+    agent: ureq::Agent,
+    events_url: String,
+    authorization: String,
+}
+
+impl GcalClient {
+    /// Signs in with the stored tokens, refreshing them when needed.
+    pub fn new(config: &GcalConfig) -> Result<Self, ToNotDoError> {
+        let agent = agent();
+        let token = access_token(config, &agent)?;
+        let api_url = config
+            .api_url
+            .as_deref()
+            .unwrap_or(DEFAULT_API_URL)
+            .trim_end_matches('/');
+
+        Ok(Self {
+            agent,
+            events_url: format!(
+                "{}/calendars/{}/events",
+                api_url,
+                encode_segment(config.calendar())
+            ),
+            authorization: format!("Bearer {}", token),
+        })
+    }
+
+    /// The response if it succeeded; otherwise an error that says to sign
+    /// in again or check the calendar when that is the likely cause.
+    fn check(
+        &self,
+        response: ureq::http::Response<ureq::Body>,
+    ) -> Result<ureq::http::Response<ureq::Body>, ToNotDoError> {
+        match response.status().as_u16() {
+            200..=299 => Ok(response),
+            401 | 403 => Err(gcal_error(
+                "access denied; sign in again with `sync gcal --login`",
+            )),
+            404 => Err(gcal_error(
+                "no such calendar; check calendar in the [gcal] config section",
+            )),
+            status => Err(gcal_error(format!("the Calendar API answered {}", status))),
+        }
+    }
+
+    fn read<T: DeserializeOwned>(
+        &self,
+        response: Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    ) -> Result<T, ToNotDoError> {
+        self.check(response.map_err(gcal_error)?)?
+            .body_mut()
+            .read_json()
+            .map_err(gcal_error)
+    }
+
+    fn event_url(&self, id: &str) -> String {
+        format!("{}/{}", self.events_url, encode_segment(id))
+    }
+}
+
+impl GcalApi for GcalClient {
+    fn events(&self) -> Result<Vec<Event>, ToNotDoError> {
+        let source = format!("{}={}", SOURCE_PROPERTY, APP_NAME);
+        let mut events = Vec::new();
+        let mut page_token = None;
+        loop {
+            let mut request = self
+                .agent
+                .get(&self.events_url)
+                .header("Authorization", &self.authorization)
+                .query("privateExtendedProperty", &source)
+                .query("maxResults", PAGE_SIZE);
+            if let Some(page_token) = &page_token {
+                request = request.query("pageToken", page_token);
+            }
+            let page: Page = self.read(request.call())?;
+            events.extend(page.items);
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                return Ok(events);
+            }
+        }
+    }
+
+    fn add_event(&self, task: Uuid, fields: &GcalFields) -> Result<String, ToNotDoError> {
+        let response = self
+            .agent
+            .post(&self.events_url)
+            .header("Authorization", &self.authorization)
+            .send_json(event_body(task, fields));
+        let event: Event = self.read(response)?;
+        Ok(event.id)
+    }
+
+    fn update_event(&self, id: &str, task: Uuid, fields: &GcalFields) -> Result<(), ToNotDoError> {
+        // A patch keeps what was added in the calendar, like a location.
+        let response = self
+            .agent
+            .patch(self.event_url(id))
+            .header("Authorization", &self.authorization)
+            .send_json(event_body(task, fields));
+        self.check(response.map_err(gcal_error)?).map(|_| ())
+    }
+
+    fn delete_event(&self, id: &str) -> Result<(), ToNotDoError> {
+        let response = self
+            .agent
+            .delete(self.event_url(id))
+            .header("Authorization", &self.authorization)
+            .call()
+            .map_err(gcal_error)?;
+        match response.status().as_u16() {
+            404 | 410 => Ok(()),
+            _ => self.check(response).map(|_| ()),
+        }
+    }
+}
+
+/// What a [`sync`] changed on either side.
+#[derive(Debug, Default)]
+pub struct GcalReport {
+    /// Tasks changed here.
+    pub pulled: usize,
+    /// Events added, changed or deleted.
+    pub pushed: usize,
+    /// Tasks that failed to sync; they are tried again next time.
+    pub failures: Vec<ToNotDoError>,
+}
+
+/// A task paired with an event, with the fields both had after the last
+/// sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Synced {
+    event_id: String,
+    fields: GcalFields,
+}
+
+/// Which events stand for which tasks, saved in the state directory
+/// after each sync.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GcalState {
+    /// The calendar synced with; the pairings are for this one only.
+    calendar: String,
+    tasks: BTreeMap<Uuid, Synced>,
+}
+
+impl GcalState {
+    fn read(path: &Path, calendar: &str) -> Self {
+        let state: Self = std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        if state.calendar == calendar {
+            state
+        } else {
+            Self {
+                calendar: calendar.to_string(),
+                tasks: BTreeMap::new(),
+            }
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+/// Syncs the tasks of `db_manager` with the events on `calendar` both ways,
+/// remembering in `state_path` how both sides were left. Tasks already done
+/// are only given an event if they had one before.
+pub fn sync(
+    db_manager: &mut DatabaseManager,
+    api: &dyn GcalApi,
+    calendar: &str,
+    state_path: &Path,
+    prefer: MergePreference,
+) -> Result<GcalReport, ToNotDoError> {
+    let mut state = GcalState::read(state_path, calendar);
+    let snapshot = db_manager.database()?.clone();
+    let local = |id: Uuid| snapshot.task(id).filter(|task| !task.is_archived());
+
+    let mut events: BTreeMap<String, Event> = api
+        .events()?
+        .into_iter()
+        .map(|event| (event.id.clone(), event))
+        .collect();
+    // Events added for tasks here by a sync whose state was lost are taken
+    // up again instead of being added twice.
+    for event in events.values() {
+        let paired = state
+            .tasks
+            .values()
+            .any(|synced| synced.event_id == event.id);
+        if let Some(id) = event.task().filter(|&id| !paired && local(id).is_some()) {
+            state.tasks.entry(id).or_insert_with(|| Synced {
+                event_id: event.id.clone(),
+                fields: GcalFields::from_event(event),
+            });
+        }
+    }
+
+    let mut report = GcalReport::default();
+    let mut synced = BTreeMap::new();
+    db_manager.begin();
+
+    for (&id, entry) in &state.tasks {
+        let remote = events.remove(&entry.event_id);
+        match sync_known(db_manager, api, entry, id, local(id), remote, prefer) {
+            Ok((outcome, kept)) => {
+                report.pulled += usize::from(outcome.pulled);
+                report.pushed += usize::from(outcome.pushed);
+                synced.extend(kept.map(|synced| (id, synced)));
+            }
+            Err(e) => {
+                report.failures.push(e);
+                synced.insert(id, entry.clone());
+            }
+        }
+    }
+
+    for task in snapshot.tasks() {
+        let known = synced.contains_key(&task.id()) || state.tasks.contains_key(&task.id());
+        if known || task.is_archived() || task.state() == TaskState::Done || task.due().is_none() {
+            continue;
+        }
+        let fields = GcalFields::of(task);
+        match api.add_event(task.id(), &fields) {
+            Ok(event_id) => {
+                report.pushed += 1;
+                synced.insert(task.id(), Synced { event_id, fields });
+            }
+            Err(e) => report.failures.push(e),
+        }
+    }
+    db_manager.commit()?;
+
+    state.tasks = synced;
+    state.write(state_path)?;
+    Ok(report)
+}
+
+/// What syncing one task did.
+#[derive(Default)]
+struct Outcome {
+    pulled: bool,
+    pushed: bool,
+}
+
+/// Syncs a task that was paired with an event by an earlier sync. Either
+/// may be gone since. Returns the pairing to keep, if any.
+fn sync_known(
+    db_manager: &mut DatabaseManager,
+    api: &dyn GcalApi,
+    entry: &Synced,
+    id: Uuid,
+    task: Option<&Task>,
+    event: Option<Event>,
+    prefer: MergePreference,
+) -> Result<(Outcome, Option<Synced>), ToNotDoError> {
+    let mut outcome = Outcome::default();
+    let kept = match (task, event) {
+        (None, None) => None,
+        (None, Some(_)) => {
+            api.delete_event(&entry.event_id)?;
+            outcome.pushed = true;
+            None
+        }
+        // The event was deleted, so the task is no longer due, unless it
+        // changed since and is to be shown again.
+        (Some(task), None) => {
+            let local = GcalFields::of(task);
+            if local == entry.fields {
+                db_manager.set_due(id, None)?;
+                outcome.pulled = true;
+                None
+            } else if local.due.is_some() {
+                let event_id = api.add_event(id, &local)?;
+                outcome.pushed = true;
+                Some(Synced {
+                    event_id,
+                    fields: local,
+                })
+            } else {
+                None
+            }
+        }
+        (Some(task), Some(event)) => {
+            let local = GcalFields::of(task);
+            let remote = GcalFields::from_event(&event);
+            let local_modified = task
+                .history()
+                .last()
+                .map(|entry| entry.at)
+                .into_iter()
+                .chain(
+                    task.updated_at()
+                        .and_hms_opt(0, 0, 0)
+                        .map(|at| at.and_utc()),
+                )
+                .max();
+            let local_newer = match (local_modified, event.updated()) {
+                (Some(local), Some(remote)) => local > remote,
+                (_, None) => true,
+                (None, Some(_)) => false,
+            };
+            let fields = GcalFields::merge(&entry.fields, &local, &remote, prefer, local_newer);
+
+            if fields != local {
+                store(db_manager, task, &fields)?;
+                outcome.pulled = true;
+            }
+            if fields.due.is_none() {
+                api.delete_event(&entry.event_id)?;
+                outcome.pushed = true;
+                None
+            } else {
+                if fields != remote {
+                    api.update_event(&entry.event_id, id, &fields)?;
+                    outcome.pushed = true;
+                }
+                Some(Synced {
+                    event_id: entry.event_id.clone(),
+                    fields,
+                })
+            }
+        }
+    };
+    Ok((outcome, kept))
+}
+
+/// Gives `task` the synced `fields`.
+fn store(
+    db_manager: &mut DatabaseManager,
+    task: &Task,
+    fields: &GcalFields,
+) -> Result<(), ToNotDoError> {
+    let id = task.id();
+    if task.description() != fields.title {
+        db_manager.update_description(id, &fields.title)?;
+    }
+    if task.due() != fields.due {
+        db_manager.set_due(id, fields.due)?;
+    }
+    if (task.state() == TaskState::Done) != fields.done {
+        let state = if fields.done {
+            TaskState::Done
+        } else {
+            TaskState::Todo
+        };
+        db_manager.set_task_state(id, state)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+    use tempfile::tempdir;
+
+    const EVENT_JSON: &str = r#"{
+        "kind": "calendar#event",
+        "etag": "\"3391775473588000\"",
+        "id": "7cbh8rpc10lrc0ckih9tafss99",
+        "status": "confirmed",
+        "htmlLink": "https://www.google.com/calendar/event?eid=N2NiaDhycGMxMGxy",
+        "created": "2025-03-01T08:00:00.000Z",
+        "updated": "2025-03-02T09:30:00.123Z",
+        "summary": "✓  Buy milk",
+        "start": {"dateTime": "2025-03-10T18:00:00+01:00", "timeZone": "Europe/Berlin"},
+        "end": {"dateTime": "2025-03-10T19:00:00+01:00", "timeZone": "Europe/Berlin"},
+        "transparency": "transparent",
+        "extendedProperties": {"private": {"source": "to-not-do", "task": "67e55044-10b1-426f-9247-bb680e5fe0c8"}}
+    }"#;
+
+    /// A calendar kept in memory.
+    #[derive(Default)]
+    struct MemoryCalendar {
+        events: RefCell<BTreeMap<String, Event>>,
+        next_id: RefCell<usize>,
+    }
+
+    impl MemoryCalendar {
+        fn edit(&self, id: &str, edit: impl FnOnce(&mut Event)) {
+            edit(self.events.borrow_mut().get_mut(id).unwrap());
+        }
+
+        fn only(&self) -> Event {
+            let events = self.events.borrow();
+            assert_eq!(events.len(), 1);
+            events.values().next().unwrap().clone()
+        }
+    }
+
+    impl GcalApi for MemoryCalendar {
+        fn events(&self) -> Result<Vec<Event>, ToNotDoError> {
+            Ok(self.events.borrow().values().cloned().collect())
+        }
+
+        fn add_event(&self, task: Uuid, fields: &GcalFields) -> Result<String, ToNotDoError> {
+            *self.next_id.borrow_mut() += 1;
+            let id = format!("e{}", self.next_id.borrow());
+            let event = Event {
+                id: id.clone(),
+                summary: String::new(),
+                start: EventTime::default(),
+                updated: None,
+                extended_properties: ExtendedProperties::default(),
+            };
+            self.events.borrow_mut().insert(id.clone(), event);
+            self.update_event(&id, task, fields)?;
+            Ok(id)
+        }
+
+        fn update_event(
+            &self,
+            id: &str,
+            task: Uuid,
+            fields: &GcalFields,
+        ) -> Result<(), ToNotDoError> {
+            let body = event_body(task, fields);
+            self.edit(id, |event| {
+                event.summary = body["summary"].as_str().unwrap().to_string();
+                event.start.date = body["start"]["date"].as_str().map(str::to_string);
+                event.extended_properties.private =
+                    serde_json::from_value(body["extendedProperties"]["private"].clone()).unwrap();
+            });
+            Ok(())
+        }
+
+        fn delete_event(&self, id: &str) -> Result<(), ToNotDoError> {
+            self.events.borrow_mut().remove(id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_event_fields() {
+        let event: Event = serde_json::from_str(EVENT_JSON).unwrap();
+        assert_eq!(
+            GcalFields::from_event(&event),
+            GcalFields {
+                title: "Buy milk".to_string(),
+                due: NaiveDate::from_ymd_opt(2025, 3, 10),
+                done: true,
+            }
+        );
+        assert_eq!(
+            event.task(),
+            Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").ok()
+        );
+        assert_eq!(
+            event.updated().unwrap().to_rfc3339(),
+            "2025-03-02T09:30:00.123+00:00"
+        );
+
+        let fields = GcalFields {
+            title: "Buy milk".to_string(),
+            due: NaiveDate::from_ymd_opt(2025, 3, 31),
+            done: false,
+        };
+        let body = event_body(Uuid::nil(), &fields);
+        assert_eq!(body["summary"], "Buy milk");
+        assert_eq!(body["start"]["date"], "2025-03-31");
+        assert_eq!(body["end"]["date"], "2025-04-01");
+        assert_eq!(body["extendedProperties"]["private"]["source"], APP_NAME);
+        let body = event_body(
+            Uuid::nil(),
+            &GcalFields {
+                done: true,
+                ..fields
+            },
+        );
+        assert_eq!(body["summary"], "✓ Buy milk");
+
+        assert_eq!(encode_segment("me@example.com"), "me%40example.com");
+    }
+
+    #[test]
+    fn test_tokens() {
+        let now = Utc::now();
+        let response: TokenResponse = serde_json::from_str(
+            r#"{"access_token": "ya29.a0", "expires_in": 3599, "refresh_token": "1//0g",
+                "scope": "https://www.googleapis.com/auth/calendar.events", "token_type": "Bearer"}"#,
+        )
+        .unwrap();
+        let tokens = response.tokens(None, now).unwrap();
+        assert_eq!(tokens.refresh_token, "1//0g");
+        assert!(!tokens.expired(now));
+        assert!(tokens.expired(now + chrono::Duration::minutes(59)));
+
+        // A refresh keeps the refresh token it was made with.
+        let response: TokenResponse =
+            serde_json::from_str(r#"{"access_token": "ya29.a1", "expires_in": 3599}"#).unwrap();
+        let refreshed = response.tokens(Some("1//0g"), now).unwrap();
+        assert_eq!(refreshed.access_token, "ya29.a1");
+        assert_eq!(refreshed.refresh_token, "1//0g");
+
+        let response: TokenResponse = serde_json::from_str(
+            r#"{"error": "invalid_grant", "error_description": "Token has been expired or revoked."}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response.tokens(Some("1//0g"), now).unwrap_err(),
+            "invalid_grant: Token has been expired or revoked."
+        );
+
+        let code: DeviceCode = serde_json::from_str(
+            r#"{"device_code": "AH-1Ng", "user_code": "GQVQ-JKEC",
+                "verification_url": "https://www.google.com/device", "expires_in": 1800}"#,
+        )
+        .unwrap();
+        assert_eq!(code.interval, 5);
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempdir().unwrap();
+        let state = dir.path().join("tasks.gcal");
+        let calendar = MemoryCalendar::default();
+        let day = |day| NaiveDate::from_ymd_opt(2025, 3, day).unwrap();
+
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let milk = Task::new("Buy milk").with_due(day(10));
+        db_manager.add_task(&milk).unwrap();
+        db_manager.add_task(&Task::new("Someday")).unwrap();
+        db_manager
+            .add_task(&Task::new("Old").with_due(day(1)).with_completed_at(day(1)))
+            .unwrap();
+
+        let sync = |db_manager: &mut DatabaseManager, prefer| {
+            let report = sync(db_manager, &calendar, "primary", &state, prefer).unwrap();
+            assert!(report.failures.is_empty());
+            (report.pulled, report.pushed)
+        };
+
+        // Only the open task with a due date gets an event.
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 1));
+        let event = calendar.only();
+        assert_eq!(event.summary, "Buy milk");
+        assert_eq!(event.start.date.as_deref(), Some("2025-03-10"));
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 0));
+
+        // The event is moved and marked done.
+        calendar.edit(&event.id, |event| {
+            event.summary = "✓ Buy milk".to_string();
+            event.start.date = Some("2025-03-12".to_string());
+        });
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (1, 0));
+        let task = db_manager.get_task(milk.id()).unwrap();
+        assert_eq!(task.due(), Some(day(12)));
+        assert_eq!(task.state(), TaskState::Done);
+
+        // Both sides rename it.
+        db_manager
+            .update_description(milk.id(), "Buy oat milk")
+            .unwrap();
+        calendar.edit(&event.id, |event| {
+            event.summary = "✓ Buy soy milk".to_string()
+        });
+        assert_eq!(sync(&mut db_manager, MergePreference::Theirs), (1, 0));
+        assert_eq!(
+            db_manager.get_task(milk.id()).unwrap().description(),
+            "Buy soy milk"
+        );
+
+        // Reopened here.
+        db_manager
+            .set_task_state(milk.id(), TaskState::Todo)
+            .unwrap();
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 1));
+        assert_eq!(calendar.only().summary, "Buy soy milk");
+
+        // With the state lost, the event is taken up again.
+        std::fs::remove_file(&state).unwrap();
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 0));
+        assert_eq!(calendar.only().id, event.id);
+
+        // Deleting the event takes the due date away.
+        calendar.delete_event(&event.id).unwrap();
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (1, 0));
+        assert_eq!(db_manager.get_task(milk.id()).unwrap().due(), None);
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 0));
+
+        // A new due date brings it back, and deleting the task removes it.
+        db_manager.set_due(milk.id(), Some(day(20))).unwrap();
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 1));
+        db_manager.delete_task(milk.id()).unwrap();
+        assert_eq!(sync(&mut db_manager, MergePreference::Ours), (0, 1));
+        assert!(calendar.events.borrow().is_empty());
+    }
+}
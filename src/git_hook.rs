@@ -0,0 +1,261 @@
+//! A git `post-commit` hook, installed by `git-hook install`, that marks
+//! tasks done when a commit message says it closes them:
+//!
+//! ```text
+//! Fix the login form
+//!
+//! Closes task:1a2b3c4d.
+//! ```
+//!
+//! `close`, `closes`, `closed`, `fix`, `fixes` and `fixed` all work, in any
+//! case, and one message may close several tasks. The task is named by its
+//! short ID, or any longer start of its ID, and is annotated with the hash
+//! of the commit under the [`COMMIT_KEY`] metadata.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task, TaskState, APP_NAME},
+};
+
+/// The hook installed.
+pub const HOOK_NAME: &str = "post-commit";
+
+/// Metadata holding the hash of the commit that closed a task.
+pub const COMMIT_KEY: &str = "commit";
+
+/// Words that make a following `task:<ID>` close the task.
+const KEYWORDS: [&str; 6] = ["close", "closes", "closed", "fix", "fixes", "fixed"];
+
+/// Shortest start of an ID that names a task.
+const MIN_ID_LENGTH: usize = 4;
+
+fn git_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::GitError(reason.to_string())
+}
+
+/// The line marking hooks written by `git-hook install`, which it may
+/// replace.
+fn marker() -> String {
+    format!("# Installed by {} git-hook install", APP_NAME)
+}
+
+/// `arg` quoted for `sh`.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// The hook, running `exe` with `global_args`, such as `--profile`, before
+/// the command.
+pub fn hook_script(exe: &Path, global_args: &[String]) -> String {
+    let command: Vec<String> = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(global_args.iter().cloned())
+        .map(|arg| quote(&arg))
+        .chain(["git-hook".to_string(), HOOK_NAME.to_string()])
+        .collect();
+    format!("#!/bin/sh\n{}\nexec {}\n", marker(), command.join(" "))
+}
+
+/// Runs git in `repo`, returning what it printed.
+fn git(repo: &Path, args: &[&str]) -> Result<String, ToNotDoError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .map_err(|e| git_error(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(git_error(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Writes `script` as the hook of the repository at `repo`, in the hooks
+/// directory git uses there. A hook other than ours is only replaced with
+/// `force`. Returns where the hook went.
+pub fn install(repo: &Path, script: &str, force: bool) -> Result<PathBuf, ToNotDoError> {
+    let hooks = PathBuf::from(git(repo, &["rev-parse", "--git-path", "hooks"])?.trim());
+    let hooks = repo.join(hooks);
+    let path = hooks.join(HOOK_NAME);
+
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        if !force && !existing.contains(&marker()) {
+            return Err(git_error(format!(
+                "{} already exists; pass --force to replace it",
+                path.display()
+            )));
+        }
+    }
+
+    let write_error =
+        |e: std::io::Error| git_error(format!("Failed to write {}: {}", path.display(), e));
+    std::fs::create_dir_all(&hooks).map_err(write_error)?;
+    std::fs::write(&path, script).map_err(write_error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .map_err(write_error)?;
+    }
+    Ok(path)
+}
+
+/// The hash and message of the commit checked out in `repo`.
+pub fn last_commit(repo: &Path) -> Result<(String, String), ToNotDoError> {
+    let output = git(repo, &["log", "-1", "--format=%H%n%B"])?;
+    let (hash, message) = output.split_once('\n').unwrap_or((&output, ""));
+    Ok((hash.trim().to_string(), message.to_string()))
+}
+
+/// The IDs `message` says it closes, as written.
+pub fn closed_ids(message: &str) -> Vec<String> {
+    let words: Vec<&str> = message
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut ids: Vec<String> = Vec::new();
+    for pair in words.windows(2) {
+        let keyword = pair[0].trim_end_matches(':').to_lowercase();
+        let Some(id) = pair[1].strip_prefix("task:") else {
+            continue;
+        };
+        let id = id.trim_end_matches(|c: char| !c.is_ascii_alphanumeric());
+        if KEYWORDS.contains(&keyword.as_str()) && !id.is_empty() && !ids.iter().any(|i| i == id) {
+            ids.push(id.to_string());
+        }
+    }
+    ids
+}
+
+/// What [`close`] did.
+#[derive(Debug, Default)]
+pub struct CloseReport {
+    pub closed: Vec<Task>,
+    /// IDs that named no task, or several, with the reason.
+    pub unknown: Vec<String>,
+}
+
+/// Marks the tasks `message` says it closes done, noting `hash` on each.
+pub fn close(
+    db_manager: &mut DatabaseManager,
+    message: &str,
+    hash: &str,
+) -> Result<CloseReport, ToNotDoError> {
+    let mut report = CloseReport::default();
+    for id in closed_ids(message) {
+        let prefix = id.to_lowercase().replace('-', "");
+        let matches: Vec<Task> = db_manager
+            .get_tasks()?
+            .iter()
+            .filter(|task| task.id().simple().to_string().starts_with(&prefix))
+            .cloned()
+            .collect();
+        let task = match matches.as_slice() {
+            _ if prefix.len() < MIN_ID_LENGTH => {
+                report
+                    .unknown
+                    .push(format!("{} is too short to name a task", id));
+                continue;
+            }
+            [task] => task,
+            [] => {
+                report.unknown.push(format!("No task {}", id));
+                continue;
+            }
+            _ => {
+                report
+                    .unknown
+                    .push(format!("Several tasks start with {}", id));
+                continue;
+            }
+        };
+
+        db_manager.begin();
+        if task.state() != TaskState::Done {
+            db_manager.set_task_state(task.id(), TaskState::Done)?;
+        }
+        db_manager.set_metadata(task.id(), COMMIT_KEY, Some(hash))?;
+        db_manager.commit()?;
+        report.closed.push(task.clone());
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_closed_ids() {
+        assert_eq!(
+            closed_ids("Fix the login form\n\nCloses task:1a2b3c4d.\nfixes: task:99ff, FIXED task:1a2b3c4d"),
+            ["1a2b3c4d", "99ff"]
+        );
+        assert!(closed_ids("Mention task:1a2b3c4d without closing it").is_empty());
+        assert!(closed_ids("closes task:").is_empty());
+    }
+
+    #[test]
+    fn test_close() {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let task = Task::new("Fix the login form");
+        db_manager.add_task(&task).unwrap();
+        db_manager.add_task(&Task::new("Other")).unwrap();
+
+        let message = format!(
+            "Fix the login form\n\nCloses task:{}, closes task:abc, closes task:zzzz",
+            task.short_id()
+        );
+        let report = close(
+            &mut db_manager,
+            &message,
+            "e83c5163316f89bfbde7d9ab23ca2e25604af290",
+        )
+        .unwrap();
+        assert_eq!(report.closed.len(), 1);
+        assert_eq!(
+            report.unknown,
+            ["abc is too short to name a task", "No task zzzz"]
+        );
+
+        let closed = db_manager.get_task(task.id()).unwrap();
+        assert_eq!(closed.state(), TaskState::Done);
+        assert_eq!(
+            closed.metadata().get(COMMIT_KEY).map(String::as_str),
+            Some("e83c5163316f89bfbde7d9ab23ca2e25604af290")
+        );
+    }
+
+    #[test]
+    fn test_install() {
+        let dir = tempdir().unwrap();
+        if git(dir.path(), &["init", "-q"]).is_err() {
+            // No git to test with.
+            return;
+        }
+        let script = hook_script(
+            Path::new("/opt/to-not-do"),
+            &["--profile".into(), "it's".into()],
+        );
+        assert!(
+            script.ends_with("exec '/opt/to-not-do' '--profile' 'it'\\''s' git-hook post-commit\n")
+        );
+
+        let path = install(dir.path(), &script, false).unwrap();
+        assert_eq!(path, dir.path().join(".git/hooks/post-commit"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), script);
+        // Ours may be replaced, others only with force.
+        install(dir.path(), &script, false).unwrap();
+        std::fs::write(&path, "#!/bin/sh\nmake lint\n").unwrap();
+        assert!(install(dir.path(), &script, false).is_err());
+        install(dir.path(), &script, true).unwrap();
+    }
+}
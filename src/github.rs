@@ -0,0 +1,501 @@
+//! Issues of a GitHub repository mirrored as tasks by `sync github`.
+//!
+//! Every open issue assigned to the owner of the token becomes a task, with
+//! the labels as tags and `owner/name#number` in its `github_issue` metadata.
+//! GitHub stays the source of truth: titles are copied over, and a task is
+//! done once its issue is closed. Going the other way is optional: with
+//! `--close` or `close_issues`, issues whose task is done are closed.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task, TaskState, APP_NAME},
+};
+
+/// Read for the token when neither the `[github]` section nor the keychain
+/// has one.
+pub const GITHUB_TOKEN_ENV: &str = "TO_NOT_DO_GITHUB_TOKEN";
+
+/// Metadata holding the `owner/name#number` of the issue a task mirrors.
+pub const ISSUE_KEY: &str = "github_issue";
+
+/// Metadata holding the web address of the issue a task mirrors.
+pub const URL_KEY: &str = "github_url";
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Keychain account the token is stored under.
+const KEYRING_ACCOUNT: &str = "github";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const PER_PAGE: usize = 100;
+
+/// Settings from the `[github]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubConfig {
+    /// Personal access token with read access to issues, and write access
+    /// for `close_issues`. Falls back to the one stored in the keychain by
+    /// `sync github --save-token`, then to `TO_NOT_DO_GITHUB_TOKEN`.
+    pub token: Option<String>,
+    /// Close issues whose task is done.
+    pub close_issues: bool,
+    /// Where the API is, such as `https://github.example.com/api/v3` for
+    /// GitHub Enterprise.
+    pub api_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    pub html_url: String,
+    /// `open` or `closed`.
+    pub state: String,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    /// Only set for pull requests, which the issues API lists too.
+    #[serde(default)]
+    pub pull_request: Option<serde_json::Value>,
+}
+
+/// What the sync needs of the GitHub API.
+pub trait GitHubApi {
+    /// The login of the owner of the token.
+    fn login(&self) -> Result<String, ToNotDoError>;
+
+    /// The open issues and pull requests of `repo` assigned to `login`.
+    fn assigned_issues(&self, repo: &str, login: &str) -> Result<Vec<Issue>, ToNotDoError>;
+
+    /// Issue `number` of `repo`, or `None` once it is gone.
+    fn issue(&self, repo: &str, number: u64) -> Result<Option<Issue>, ToNotDoError>;
+
+    fn close_issue(&self, repo: &str, number: u64) -> Result<(), ToNotDoError>;
+}
+
+fn github_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::GitHubError(reason.to_string())
+}
+
+fn keyring_entry() -> Result<keyring::Entry, ToNotDoError> {
+    keyring::Entry::new(APP_NAME, KEYRING_ACCOUNT).map_err(github_error)
+}
+
+/// Stores `token` in the OS keychain for later syncs.
+pub fn store_token(token: &str) -> Result<(), ToNotDoError> {
+    keyring_entry()?.set_password(token).map_err(github_error)
+}
+
+/// The token from `config`, the keychain or the environment, in that order.
+pub fn token(config: &GitHubConfig) -> Option<String> {
+    config
+        .token
+        .clone()
+        .or_else(|| keyring_entry().ok()?.get_password().ok())
+        .or_else(|| std::env::var(GITHUB_TOKEN_ENV).ok())
+}
+
+/// Checks that `repo` reads `owner/name`.
+pub fn parse_repo(repo: &str) -> Result<&str, ToNotDoError> {
+    match repo.split_once('/') {
+        Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+            Ok(repo)
+        }
+        _ => Err(github_error(format!(
+            "Expected the repository as owner/name, not {}",
+            repo
+        ))),
+    }
+}
+
+/// The GitHub REST API, authenticated with a token.
+pub struct GitHubClient {
+    agent: ureq::Agent,
+    api_url: String,
+    authorization: String,
+}
+
+impl GitHubClient {
+    pub fn new(config: &GitHubConfig) -> Result<Self, ToNotDoError> {
+        let token = token(config).ok_or_else(|| {
+            github_error(format!(
+                "No token; set token in the [github] config section, store one with `sync github --save-token` or set {}",
+                GITHUB_TOKEN_ENV
+            ))
+        })?;
+
+        Ok(Self {
+            // Statuses like 404 are answers here, not failures.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            api_url: config
+                .api_url
+                .as_deref()
+                .unwrap_or(DEFAULT_API_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            authorization: format!("Bearer {}", token),
+        })
+    }
+
+    fn request<B>(&self, request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+        request
+            .header("Authorization", &self.authorization)
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header("User-Agent", APP_NAME)
+    }
+
+    /// Errors for a response that did not succeed, telling a denied token
+    /// apart from other failures at `path`.
+    fn check(
+        &self,
+        path: &str,
+        response: ureq::http::Response<ureq::Body>,
+    ) -> Result<ureq::http::Response<ureq::Body>, ToNotDoError> {
+        match response.status().as_u16() {
+            200..=299 => Ok(response),
+            401 | 403 => Err(github_error(format!(
+                "access to {} denied; check the token and its permissions",
+                path
+            ))),
+            status => Err(github_error(format!("{} answered {}", path, status))),
+        }
+    }
+
+    fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>, ToNotDoError> {
+        let mut request = self.request(self.agent.get(format!("{}{}", self.api_url, path)));
+        for (key, value) in query {
+            request = request.query(*key, *value);
+        }
+
+        let response = request.call().map_err(github_error)?;
+        if response.status() == 404 {
+            return Ok(None);
+        }
+        self.check(path, response)?
+            .body_mut()
+            .read_json()
+            .map(Some)
+            .map_err(github_error)
+    }
+}
+
+impl GitHubApi for GitHubClient {
+    fn login(&self) -> Result<String, ToNotDoError> {
+        #[derive(Deserialize)]
+        struct User {
+            login: String,
+        }
+
+        let user: User = self
+            .get("/user", &[])?
+            .ok_or_else(|| github_error("/user not found"))?;
+        Ok(user.login)
+    }
+
+    fn assigned_issues(&self, repo: &str, login: &str) -> Result<Vec<Issue>, ToNotDoError> {
+        let path = format!("/repos/{}/issues", repo);
+        let per_page = PER_PAGE.to_string();
+        let mut issues = Vec::new();
+        for page in 1.. {
+            let page = page.to_string();
+            let query = [
+                ("assignee", login),
+                ("state", "open"),
+                ("per_page", per_page.as_str()),
+                ("page", page.as_str()),
+            ];
+            let batch: Vec<Issue> = self
+                .get(&path, &query)?
+                .ok_or_else(|| github_error(format!("No repository {}", repo)))?;
+            let last = batch.len() < PER_PAGE;
+            issues.extend(batch);
+            if last {
+                break;
+            }
+        }
+        Ok(issues)
+    }
+
+    fn issue(&self, repo: &str, number: u64) -> Result<Option<Issue>, ToNotDoError> {
+        self.get(&format!("/repos/{}/issues/{}", repo, number), &[])
+    }
+
+    fn close_issue(&self, repo: &str, number: u64) -> Result<(), ToNotDoError> {
+        let path = format!("/repos/{}/issues/{}", repo, number);
+        let response = self
+            .request(self.agent.patch(format!("{}{}", self.api_url, path)))
+            .send_json(serde_json::json!({
+                "state": "closed",
+                "state_reason": "completed",
+            }))
+            .map_err(github_error)?;
+        self.check(&path, response).map(|_| ())
+    }
+}
+
+/// What [`sync`] changed on either side.
+#[derive(Debug, Default)]
+pub struct GitHubReport {
+    /// Tasks added for newly assigned issues.
+    pub added: usize,
+    /// Tasks whose issue was renamed.
+    pub updated: usize,
+    /// Tasks done because their issue was closed.
+    pub completed: usize,
+    /// Issues closed because their task is done.
+    pub closed: usize,
+    /// Issues that failed to sync; they are tried again next time.
+    pub failures: Vec<ToNotDoError>,
+}
+
+/// The issue number of `task` if it mirrors an issue of `repo`.
+fn issue_number(task: &Task, repo: &str) -> Option<u64> {
+    let (task_repo, number) = task.metadata().get(ISSUE_KEY)?.rsplit_once('#')?;
+    (task_repo == repo).then(|| number.parse().ok())?
+}
+
+/// Mirrors the open issues of `repo` assigned to the owner of the token
+/// into tasks, closing the issues of tasks that are done when `close` is set.
+pub fn sync(
+    db_manager: &mut DatabaseManager,
+    api: &dyn GitHubApi,
+    repo: &str,
+    close: bool,
+) -> Result<GitHubReport, ToNotDoError> {
+    let login = api.login()?;
+    let issues: Vec<Issue> = api
+        .assigned_issues(repo, &login)?
+        .into_iter()
+        .filter(|issue| issue.pull_request.is_none())
+        .collect();
+    let mut mirrored: BTreeMap<u64, Task> = db_manager
+        .get_tasks()?
+        .iter()
+        .filter_map(|task| Some((issue_number(task, repo)?, task.clone())))
+        .collect();
+
+    let mut report = GitHubReport::default();
+    db_manager.begin();
+
+    for issue in &issues {
+        match mirrored.remove(&issue.number) {
+            None => {
+                let labels: Vec<String> = issue
+                    .labels
+                    .iter()
+                    .map(|label| label.name.clone())
+                    .collect();
+                let task = Task::new(&issue.title)
+                    .with_tags(&labels)
+                    .with_metadata(ISSUE_KEY, &format!("{}#{}", repo, issue.number))
+                    .with_metadata(URL_KEY, &issue.html_url);
+                db_manager.add_task(&task)?;
+                report.added += 1;
+            }
+            Some(task) if task.state() == TaskState::Done => {
+                if close {
+                    match api.close_issue(repo, issue.number) {
+                        Ok(()) => report.closed += 1,
+                        Err(e) => report.failures.push(e),
+                    }
+                }
+            }
+            Some(task) => {
+                if task.description() != issue.title {
+                    db_manager.update_description(task.id(), &issue.title)?;
+                    report.updated += 1;
+                }
+            }
+        }
+    }
+
+    // Whatever is left is no longer open and assigned; only a closed issue
+    // finishes its task.
+    for (number, task) in mirrored {
+        if task.state() == TaskState::Done || task.is_archived() {
+            continue;
+        }
+        match api.issue(repo, number) {
+            Ok(Some(issue)) if issue.state == "closed" => {
+                db_manager.set_task_state(task.id(), TaskState::Done)?;
+                report.completed += 1;
+            }
+            Ok(_) => {}
+            Err(e) => report.failures.push(e),
+        }
+    }
+    db_manager.commit()?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+
+    const REPO: &str = "octo/tools";
+
+    /// A repository kept in memory, with issues assigned to `octocat` or
+    /// someone else.
+    #[derive(Default)]
+    struct MemoryGitHub {
+        issues: RefCell<BTreeMap<u64, (Issue, &'static str)>>,
+    }
+
+    impl MemoryGitHub {
+        fn add(&self, number: u64, title: &str, assignee: &'static str) {
+            let issue = Issue {
+                number,
+                title: title.to_string(),
+                html_url: format!("https://github.com/{}/issues/{}", REPO, number),
+                state: "open".to_string(),
+                labels: vec![Label {
+                    name: "bug".to_string(),
+                }],
+                pull_request: None,
+            };
+            self.issues.borrow_mut().insert(number, (issue, assignee));
+        }
+
+        fn state(&self, number: u64) -> String {
+            self.issues.borrow()[&number].0.state.clone()
+        }
+    }
+
+    impl GitHubApi for MemoryGitHub {
+        fn login(&self) -> Result<String, ToNotDoError> {
+            Ok("octocat".to_string())
+        }
+
+        fn assigned_issues(&self, _repo: &str, login: &str) -> Result<Vec<Issue>, ToNotDoError> {
+            Ok(self
+                .issues
+                .borrow()
+                .values()
+                .filter(|(issue, assignee)| issue.state == "open" && *assignee == login)
+                .map(|(issue, _)| issue.clone())
+                .collect())
+        }
+
+        fn issue(&self, _repo: &str, number: u64) -> Result<Option<Issue>, ToNotDoError> {
+            Ok(self
+                .issues
+                .borrow()
+                .get(&number)
+                .map(|(issue, _)| issue.clone()))
+        }
+
+        fn close_issue(&self, _repo: &str, number: u64) -> Result<(), ToNotDoError> {
+            self.issues.borrow_mut().get_mut(&number).unwrap().0.state = "closed".to_string();
+            Ok(())
+        }
+    }
+
+    fn task_for(db_manager: &mut DatabaseManager, number: u64) -> Option<Task> {
+        db_manager
+            .get_tasks()
+            .unwrap()
+            .iter()
+            .find(|task| issue_number(task, REPO) == Some(number))
+            .cloned()
+    }
+
+    #[test]
+    fn test_parse_repo() {
+        assert!(parse_repo("octo/tools").is_ok());
+        assert!(parse_repo("octo").is_err());
+        assert!(parse_repo("octo/").is_err());
+        assert!(parse_repo("octo/tools/issues").is_err());
+    }
+
+    #[test]
+    fn test_issue_json() {
+        let json = r#"[{"number": 7, "title": "Crash on start", "state": "open",
+            "html_url": "https://github.com/octo/tools/issues/7",
+            "labels": [{"id": 1, "name": "bug", "color": "d73a4a"}],
+            "assignees": [{"login": "octocat"}]},
+            {"number": 8, "title": "Fix crash", "state": "open",
+            "html_url": "https://github.com/octo/tools/pull/8",
+            "pull_request": {"url": "https://api.github.com/repos/octo/tools/pulls/8"}}]"#;
+        let issues: Vec<Issue> = serde_json::from_str(json).unwrap();
+        assert_eq!(issues[0].labels[0].name, "bug");
+        assert!(issues[0].pull_request.is_none());
+        assert!(issues[1].pull_request.is_some());
+    }
+
+    #[test]
+    fn test_sync() {
+        let github = MemoryGitHub::default();
+        github.add(1, "Crash on start", "octocat");
+        github.add(2, "Update docs", "octocat");
+        github.add(3, "Someone else's", "hubot");
+
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let report = sync(&mut db_manager, &github, REPO, false).unwrap();
+        assert_eq!(report.added, 2);
+        assert!(report.failures.is_empty());
+
+        let crash = task_for(&mut db_manager, 1).unwrap();
+        assert_eq!(crash.description(), "Crash on start");
+        assert_eq!(crash.tags(), ["bug"]);
+        assert_eq!(crash.metadata()[ISSUE_KEY], "octo/tools#1");
+        assert_eq!(
+            crash.metadata()[URL_KEY],
+            "https://github.com/octo/tools/issues/1"
+        );
+        assert!(task_for(&mut db_manager, 3).is_none());
+
+        let report = sync(&mut db_manager, &github, REPO, false).unwrap();
+        assert_eq!((report.added, report.updated), (0, 0), "nothing changed");
+
+        // The crash is renamed and closed, and the docs are done here.
+        github.issues.borrow_mut().get_mut(&1).unwrap().0.title = "Crash on launch".to_string();
+        let report = sync(&mut db_manager, &github, REPO, false).unwrap();
+        assert_eq!(report.updated, 1);
+        assert_eq!(
+            task_for(&mut db_manager, 1).unwrap().description(),
+            "Crash on launch"
+        );
+
+        github.close_issue(REPO, 1).unwrap();
+        let docs = task_for(&mut db_manager, 2).unwrap();
+        db_manager
+            .set_task_state(docs.id(), TaskState::Done)
+            .unwrap();
+
+        let report = sync(&mut db_manager, &github, REPO, false).unwrap();
+        assert_eq!((report.completed, report.closed), (1, 0));
+        assert_eq!(
+            task_for(&mut db_manager, 1).unwrap().state(),
+            TaskState::Done
+        );
+        assert_eq!(github.state(2), "open", "issues are only closed on request");
+
+        let report = sync(&mut db_manager, &github, REPO, true).unwrap();
+        assert_eq!(report.closed, 1);
+        assert_eq!(github.state(2), "closed");
+        assert_eq!(issue_number(&crash, "octo/other"), None);
+    }
+}
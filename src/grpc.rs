@@ -0,0 +1,328 @@
+//! The gRPC service of `serve`, generated from `proto/to_not_do.proto` and
+//! enabled by the `grpc` feature. It shares the port of the REST API, which
+//! it mirrors, and goes through the same database code.
+
+use std::sync::Arc;
+
+use axum::Router;
+use chrono::NaiveDate;
+use tonic::{server::NamedService, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Priority, Task, TaskState},
+    filter::TaskFilter,
+    repository::TaskRepository,
+    serve::{stored_task, NewTask, ServerState, TaskChanges},
+};
+
+/// Code generated from `proto/to_not_do.proto`.
+pub mod proto {
+    tonic::include_proto!("to_not_do.v1");
+}
+
+use proto::tasks_server::{Tasks, TasksServer};
+
+/// Adds the gRPC service to `router`, answering for the database of `state`.
+pub fn add_service(
+    router: Router<Arc<ServerState>>,
+    state: Arc<ServerState>,
+) -> Router<Arc<ServerState>> {
+    router.route_service(
+        &format!("/{}/{{*rest}}", TasksServer::<TaskService>::NAME),
+        TasksServer::new(TaskService { state }),
+    )
+}
+
+pub struct TaskService {
+    state: Arc<ServerState>,
+}
+
+impl TaskService {
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+
+        if self.state.accepts(authorization) {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated("Invalid or missing token"))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Tasks for TaskService {
+    async fn add_task(
+        &self,
+        request: Request<proto::AddTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        let task = NewTask {
+            description: request.description,
+            notes: request.notes,
+            due: request.due.as_deref().map(parse_date).transpose()?,
+            priority: priority(request.priority)?,
+            tags: request.tags,
+            parent: request.parent.as_deref().map(parse_id).transpose()?,
+            project: request.project,
+        }
+        .into_task();
+
+        self.state
+            .edit_tasks(|manager| {
+                manager.add(&task)?;
+                stored_task(manager, task.id())
+            })
+            .map(|task| Response::new(message(&task)))
+            .map_err(status)
+    }
+
+    async fn get_task(
+        &self,
+        request: Request<proto::GetTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        self.authorize(&request)?;
+        let id = parse_id(&request.get_ref().id)?;
+
+        self.state
+            .read_tasks(|manager| stored_task(manager, id))
+            .map(|task| Response::new(message(&task)))
+            .map_err(status)
+    }
+
+    async fn query_tasks(
+        &self,
+        request: Request<proto::QueryTasksRequest>,
+    ) -> Result<Response<proto::QueryTasksResponse>, Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        let filter = TaskFilter {
+            state: state(request.state)?,
+            project: request.project,
+            archived: request.archived,
+        };
+
+        self.state
+            .read_tasks(|manager| manager.query(&filter))
+            .map(|tasks| {
+                Response::new(proto::QueryTasksResponse {
+                    tasks: tasks.iter().map(message).collect(),
+                })
+            })
+            .map_err(status)
+    }
+
+    async fn update_task(
+        &self,
+        request: Request<proto::UpdateTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        let id = parse_id(&request.id)?;
+        let changes = TaskChanges {
+            description: request.description,
+            notes: request.notes,
+            state: state(request.state)?,
+            due: request.due.as_deref().map(parse_date).transpose()?,
+            priority: priority(request.priority)?,
+            tags: request.tags.map(|tags| tags.tags),
+            project: request.project,
+        };
+
+        self.state
+            .edit_tasks(|manager| changes.apply(manager, id))
+            .map(|task| Response::new(message(&task)))
+            .map_err(status)
+    }
+
+    async fn delete_task(
+        &self,
+        request: Request<proto::DeleteTaskRequest>,
+    ) -> Result<Response<proto::DeleteTaskResponse>, Status> {
+        self.authorize(&request)?;
+        let id = parse_id(&request.get_ref().id)?;
+
+        self.state
+            .edit_tasks(|manager| manager.delete(id))
+            .map(|()| Response::new(proto::DeleteTaskResponse {}))
+            .map_err(status)
+    }
+}
+
+fn status(e: ToNotDoError) -> Status {
+    match &e {
+        ToNotDoError::DatabaseError(DatabaseError::TaskNotFound(_)) => {
+            Status::not_found(e.to_string())
+        }
+        ToNotDoError::DatabaseError(DatabaseError::UuidAlreadyExists(_)) => {
+            Status::already_exists(e.to_string())
+        }
+        ToNotDoError::DatabaseError(DatabaseError::ReadOnly(_)) => {
+            Status::permission_denied(e.to_string())
+        }
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|e| Status::invalid_argument(format!("Invalid ID {}: {}", id, e)))
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, Status> {
+    date.parse()
+        .map_err(|e| Status::invalid_argument(format!("Invalid date {}: {}", date, e)))
+}
+
+/// The state a request asks for; unspecified means none in particular.
+fn state(value: i32) -> Result<Option<TaskState>, Status> {
+    match proto::TaskState::try_from(value) {
+        Ok(proto::TaskState::Unspecified) => Ok(None),
+        Ok(proto::TaskState::Todo) => Ok(Some(TaskState::Todo)),
+        Ok(proto::TaskState::InProgress) => Ok(Some(TaskState::InProgress)),
+        Ok(proto::TaskState::Done) => Ok(Some(TaskState::Done)),
+        Err(_) => Err(Status::invalid_argument(format!("Invalid state {}", value))),
+    }
+}
+
+fn priority(value: i32) -> Result<Option<Priority>, Status> {
+    match proto::Priority::try_from(value) {
+        Ok(proto::Priority::Unspecified) => Ok(None),
+        Ok(proto::Priority::Low) => Ok(Some(Priority::Low)),
+        Ok(proto::Priority::Medium) => Ok(Some(Priority::Medium)),
+        Ok(proto::Priority::High) => Ok(Some(Priority::High)),
+        Err(_) => Err(Status::invalid_argument(format!(
+            "Invalid priority {}",
+            value
+        ))),
+    }
+}
+
+fn message(task: &Task) -> proto::Task {
+    let state = match task.state() {
+        TaskState::Todo => proto::TaskState::Todo,
+        TaskState::InProgress => proto::TaskState::InProgress,
+        TaskState::Done => proto::TaskState::Done,
+    };
+    let priority = match task.priority() {
+        None => proto::Priority::Unspecified,
+        Some(Priority::Low) => proto::Priority::Low,
+        Some(Priority::Medium) => proto::Priority::Medium,
+        Some(Priority::High) => proto::Priority::High,
+    };
+
+    proto::Task {
+        id: task.id().to_string(),
+        description: task.description().to_string(),
+        notes: task.notes().map(str::to_string),
+        state: state.into(),
+        due: task.due().map(|due| due.to_string()),
+        priority: priority.into(),
+        tags: task.tags().to_vec(),
+        parent: task.parent().map(|parent| parent.to_string()),
+        project: task.project().map(str::to_string),
+        created_at: task.created_at().to_string(),
+        updated_at: task.updated_at().to_string(),
+        completed_at: task.completed_at().map(|date| date.to_string()),
+        archived: task.is_archived(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::StorageConfig, serve::serve};
+    use proto::tasks_client::TasksClient;
+    use tempfile::tempdir;
+
+    fn authorized<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_grpc_service() {
+        let dir = tempdir().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = ServerState::new(
+            dir.path().join("server.json"),
+            StorageConfig::default(),
+            Some("secret".to_string()),
+        );
+        std::thread::spawn(move || serve(listener, state));
+
+        let mut client = TasksClient::connect(url).await.unwrap();
+
+        let denied = client
+            .query_tasks(proto::QueryTasksRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::Unauthenticated);
+
+        let added = client
+            .add_task(authorized(proto::AddTaskRequest {
+                description: "Renew passport".to_string(),
+                due: Some("2026-12-01".to_string()),
+                priority: proto::Priority::High.into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(added.due.as_deref(), Some("2026-12-01"));
+
+        let updated = client
+            .update_task(authorized(proto::UpdateTaskRequest {
+                id: added.id.clone(),
+                state: proto::TaskState::Done.into(),
+                tags: Some(proto::Tags {
+                    tags: vec!["errands".to_string()],
+                }),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(updated.state(), proto::TaskState::Done);
+        assert_eq!(updated.tags, ["errands"]);
+        assert!(updated.completed_at.is_some());
+
+        let done = client
+            .query_tasks(authorized(proto::QueryTasksRequest {
+                state: proto::TaskState::Done.into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(done.tasks, [updated]);
+
+        client
+            .delete_task(authorized(proto::DeleteTaskRequest {
+                id: added.id.clone(),
+            }))
+            .await
+            .unwrap();
+        let missing = client
+            .get_task(authorized(proto::GetTaskRequest { id: added.id }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let invalid = client
+            .get_task(authorized(proto::GetTaskRequest {
+                id: "not-an-id".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+}
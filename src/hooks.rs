@@ -0,0 +1,198 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{Database, Task, TaskState},
+};
+
+/// Directory in the data directory holding the hook scripts.
+pub const HOOKS_DIR: &str = "hooks";
+
+/// Settings from the `[hooks]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Make the command fail when a hook fails. Its changes are saved
+    /// either way, as hooks run after them.
+    pub fail_on_error: bool,
+}
+
+/// Something that happened to a task during a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    Add,
+    Done,
+    Delete,
+}
+
+impl Lifecycle {
+    /// Name of the script run for this event.
+    pub fn hook_name(self) -> &'static str {
+        match self {
+            Lifecycle::Add => "on-add",
+            Lifecycle::Done => "on-done",
+            Lifecycle::Delete => "on-delete",
+        }
+    }
+}
+
+/// What happened to each task between `before` and `after`: added, marked
+/// done (including added as done) or deleted.
+pub fn lifecycle(before: &Database, after: &Database) -> Vec<(Lifecycle, Task)> {
+    let changed = after.tasks().iter().flat_map(|task| {
+        let previous = before.task(task.id());
+        let added = previous.is_none();
+        let done = task.state() == TaskState::Done
+            && previous.is_none_or(|previous| previous.state() != TaskState::Done);
+
+        [
+            added.then_some(Lifecycle::Add),
+            done.then_some(Lifecycle::Done),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|event| (event, task.clone()))
+    });
+    let deleted = before
+        .tasks()
+        .iter()
+        .filter(|task| after.task(task.id()).is_none())
+        .map(|task| (Lifecycle::Delete, task.clone()));
+
+    changed.chain(deleted).collect()
+}
+
+/// Runs the hook for each event that has a script in `hooks_dir`, giving it
+/// the task as JSON on stdin. What hooks print goes to stderr, so it never
+/// mixes with the output of the command. Returns the hooks that failed.
+pub fn run_hooks(hooks_dir: &Path, events: &[(Lifecycle, Task)]) -> Vec<ToNotDoError> {
+    events
+        .iter()
+        .filter_map(|(event, task)| {
+            let script = hook_script(hooks_dir, *event)?;
+            run_hook(&script, task).err()
+        })
+        .collect()
+}
+
+/// The script for `event`, if there is an executable one.
+fn hook_script(hooks_dir: &Path, event: Lifecycle) -> Option<PathBuf> {
+    let script = hooks_dir.join(event.hook_name());
+    let metadata = std::fs::metadata(&script).ok()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return None;
+        }
+    }
+
+    metadata.is_file().then_some(script)
+}
+
+fn run_hook(script: &Path, task: &Task) -> Result<(), ToNotDoError> {
+    let hook_error = |reason: String| ToNotDoError::HookError {
+        hook: script.display().to_string(),
+        reason,
+    };
+
+    let json = serde_json::to_vec(task).map_err(|e| hook_error(e.to_string()))?;
+    let mut child = Command::new(script)
+        .stdin(Stdio::piped())
+        .stdout(std::io::stderr())
+        .spawn()
+        .map_err(|e| hook_error(e.to_string()))?;
+
+    // A hook may exit without reading its input, so a broken pipe is fine.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(&json);
+    }
+
+    let status = child.wait().map_err(|e| hook_error(e.to_string()))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(hook_error(status.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn database(tasks: &[Task]) -> Database {
+        let mut db = Database::default();
+        for task in tasks {
+            db.put_task(task.clone());
+        }
+        db
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let finished = Task::new("Finished");
+        let deleted = Task::new("Deleted");
+        let added = Task::new("Added");
+
+        let before = database(&[finished.clone(), deleted.clone()]);
+        let after = database(&[finished.clone().with_state(TaskState::Done), added.clone()]);
+
+        let events: Vec<_> = lifecycle(&before, &after)
+            .into_iter()
+            .map(|(event, task)| (event, task.id()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (Lifecycle::Done, finished.id()),
+                (Lifecycle::Add, added.id()),
+                (Lifecycle::Delete, deleted.id()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_hooks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let hooks_dir = dir.path().join(HOOKS_DIR);
+        std::fs::create_dir(&hooks_dir).unwrap();
+        let received = dir.path().join("received.json");
+
+        let write_script = |name: &str, body: &str, mode: u32| {
+            let path = hooks_dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        write_script("on-add", &format!("cat > '{}'", received.display()), 0o755);
+        write_script("on-done", "exit 3", 0o755);
+        write_script("on-delete", "exit 1", 0o644);
+
+        let task = Task::new("Hooked");
+        let failures = run_hooks(
+            &hooks_dir,
+            &[
+                (Lifecycle::Add, task.clone()),
+                (Lifecycle::Done, task.clone()),
+                (Lifecycle::Delete, task.clone()),
+            ],
+        );
+
+        let json = std::fs::read_to_string(&received).unwrap();
+        assert_eq!(serde_json::from_str::<Task>(&json).unwrap(), task);
+
+        // The delete hook is not executable, so it is skipped like in git.
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].to_string().contains("on-done"));
+    }
+}
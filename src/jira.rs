@@ -0,0 +1,496 @@
+//! Issues from Jira, copied into tasks by `import --from jira`.
+//!
+//! The issues a JQL query finds become tasks linked back through their
+//! `jira_issue` and `jira_url` metadata, so importing again updates them
+//! instead of adding them twice. Jira owns the summary, status, due date and
+//! priority of an issue, which are copied over on every import; labels and
+//! the project are only set when the task is added.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Priority, Task, TaskState},
+};
+
+/// Read for the API token when the `[jira]` section gives none.
+pub const JIRA_TOKEN_ENV: &str = "TO_NOT_DO_JIRA_TOKEN";
+
+/// Metadata holding the key of the issue a task was imported from.
+pub const ISSUE_KEY: &str = "jira_issue";
+
+/// Metadata holding the web address of the issue a task was imported from.
+pub const URL_KEY: &str = "jira_url";
+
+/// Issues imported when neither `--jql` nor the `[jira]` section says.
+pub const DEFAULT_JQL: &str = "assignee = currentUser() AND statusCategory != Done";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const PAGE_SIZE: usize = 100;
+
+/// The fields asked for, leaving out the many others Jira has.
+const FIELDS: &str = "summary,status,duedate,priority,labels,project";
+
+/// Settings from the `[jira]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JiraConfig {
+    /// The site, such as `https://example.atlassian.net`.
+    pub url: Option<String>,
+    /// Account email for Jira Cloud. Without it the token is sent as a
+    /// personal access token, as Jira Server and Data Center expect.
+    pub email: Option<String>,
+    /// API token. Falls back to the `TO_NOT_DO_JIRA_TOKEN` environment
+    /// variable.
+    pub token: Option<String>,
+    /// Query used when `--jql` is not given.
+    pub jql: Option<String>,
+    /// States for statuses by name, such as `"In Review" = "InProgress"`.
+    /// Other statuses go by their category: to do, in progress or done.
+    pub statuses: BTreeMap<String, TaskState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Named {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusCategory {
+    /// `new`, `indeterminate` or `done`.
+    pub key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub name: String,
+    pub status_category: StatusCategory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct IssueFields {
+    pub summary: String,
+    pub status: Status,
+    #[serde(default)]
+    pub duedate: Option<String>,
+    #[serde(default)]
+    pub priority: Option<Named>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub project: Option<Named>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct JiraIssue {
+    /// Such as `OPS-42`.
+    pub key: String,
+    pub fields: IssueFields,
+}
+
+impl JiraIssue {
+    fn state(&self, statuses: &BTreeMap<String, TaskState>) -> TaskState {
+        let status = &self.fields.status;
+        if let Some((_, &state)) = statuses
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&status.name))
+        {
+            return state;
+        }
+        match status.status_category.key.as_str() {
+            "done" => TaskState::Done,
+            "indeterminate" => TaskState::InProgress,
+            _ => TaskState::Todo,
+        }
+    }
+
+    /// Jira's five default priorities folded into three.
+    fn priority(&self) -> Option<Priority> {
+        match self.fields.priority.as_ref()?.name.as_str() {
+            "Highest" | "High" | "Blocker" | "Critical" => Some(Priority::High),
+            "Medium" | "Major" => Some(Priority::Medium),
+            "Low" | "Lowest" | "Minor" | "Trivial" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    fn due(&self) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(self.fields.duedate.as_deref()?, "%Y-%m-%d").ok()
+    }
+}
+
+/// What the import needs of the Jira API.
+pub trait JiraApi {
+    /// The site the issues are on, for links back to them.
+    fn url(&self) -> &str;
+
+    /// Every issue `jql` finds.
+    fn search(&self, jql: &str) -> Result<Vec<JiraIssue>, ToNotDoError>;
+}
+
+fn jira_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::JiraError(reason.to_string())
+}
+
+/// One page of results from the enhanced search of Jira Cloud.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenPage {
+    issues: Vec<JiraIssue>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+/// One page of results from the older search Jira Server still has.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OffsetPage {
+    issues: Vec<JiraIssue>,
+    total: usize,
+}
+
+/// The Jira REST API, authenticated with a token.
+pub struct JiraClient {
+    agent: ureq::Agent,
+    url: String,
+    authorization: String,
+}
+
+impl JiraClient {
+    pub fn new(config: &JiraConfig) -> Result<Self, ToNotDoError> {
+        let url = config
+            .url
+            .as_deref()
+            .ok_or_else(|| jira_error("No site; set url in the [jira] config section"))?;
+        let token = config
+            .token
+            .clone()
+            .or_else(|| std::env::var(JIRA_TOKEN_ENV).ok())
+            .ok_or_else(|| {
+                jira_error(format!(
+                    "No API token; set token in the [jira] config section or {}",
+                    JIRA_TOKEN_ENV
+                ))
+            })?;
+
+        let authorization = match &config.email {
+            Some(email) => {
+                let credentials = format!("{}:{}", email, token);
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                )
+            }
+            None => format!("Bearer {}", token),
+        };
+
+        Ok(Self {
+            // Statuses like 404 are answers here, not failures.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            url: url.trim_end_matches('/').to_string(),
+            authorization,
+        })
+    }
+
+    /// Fetches `path` with `query`, or `None` when Jira has no such path.
+    fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>, ToNotDoError> {
+        let mut request = self
+            .agent
+            .get(format!("{}{}", self.url, path))
+            .header("Authorization", &self.authorization)
+            .header("Accept", "application/json");
+        for (key, value) in query {
+            request = request.query(*key, *value);
+        }
+
+        let mut response = request.call().map_err(jira_error)?;
+        match response.status().as_u16() {
+            200..=299 => response
+                .body_mut()
+                .read_json()
+                .map(Some)
+                .map_err(jira_error),
+            404 => Ok(None),
+            401 | 403 => Err(jira_error(
+                "access denied; check the email and token in the [jira] config section",
+            )),
+            // Jira explains a bad query in the body.
+            status => Err(jira_error(format!(
+                "{} answered {}: {}",
+                path,
+                status,
+                response.body_mut().read_to_string().unwrap_or_default()
+            ))),
+        }
+    }
+
+    /// Searches with the older endpoint, for Jira Server and Data Center.
+    fn search_by_offset(&self, jql: &str) -> Result<Vec<JiraIssue>, ToNotDoError> {
+        let path = "/rest/api/2/search";
+        let page_size = PAGE_SIZE.to_string();
+        let mut issues = Vec::new();
+        loop {
+            let start_at = issues.len().to_string();
+            let query = [
+                ("jql", jql),
+                ("fields", FIELDS),
+                ("maxResults", page_size.as_str()),
+                ("startAt", start_at.as_str()),
+            ];
+            let page: OffsetPage = self
+                .get(path, &query)?
+                .ok_or_else(|| jira_error(format!("No search API at {}", self.url)))?;
+            let last = page.issues.is_empty();
+            issues.extend(page.issues);
+            if last || issues.len() >= page.total {
+                return Ok(issues);
+            }
+        }
+    }
+}
+
+impl JiraApi for JiraClient {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn search(&self, jql: &str) -> Result<Vec<JiraIssue>, ToNotDoError> {
+        let path = "/rest/api/3/search/jql";
+        let page_size = PAGE_SIZE.to_string();
+        let mut issues = Vec::new();
+        let mut next_page_token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("jql", jql),
+                ("fields", FIELDS),
+                ("maxResults", page_size.as_str()),
+            ];
+            if let Some(token) = &next_page_token {
+                query.push(("nextPageToken", token.as_str()));
+            }
+            let page: TokenPage = match self.get(path, &query)? {
+                Some(page) => page,
+                None if issues.is_empty() => return self.search_by_offset(jql),
+                None => return Err(jira_error(format!("{} went away", path))),
+            };
+            issues.extend(page.issues);
+            next_page_token = page.next_page_token;
+            if next_page_token.is_none() {
+                return Ok(issues);
+            }
+        }
+    }
+}
+
+/// What [`import`] changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct JiraReport {
+    pub added: usize,
+    pub updated: usize,
+}
+
+/// Adds a task for every issue `jql` finds, or updates the one imported
+/// from it before.
+pub fn import(
+    db_manager: &mut DatabaseManager,
+    api: &dyn JiraApi,
+    config: &JiraConfig,
+    jql: &str,
+) -> Result<JiraReport, ToNotDoError> {
+    let issues = api.search(jql)?;
+    let imported: BTreeMap<String, Task> = db_manager
+        .get_tasks()?
+        .iter()
+        .filter_map(|task| Some((task.metadata().get(ISSUE_KEY)?.clone(), task.clone())))
+        .collect();
+
+    let mut report = JiraReport::default();
+    db_manager.begin();
+    for issue in &issues {
+        let state = issue.state(&config.statuses);
+        let Some(task) = imported.get(&issue.key) else {
+            let mut task = Task::new(&issue.fields.summary)
+                .with_state(state)
+                .with_tags(&issue.fields.labels)
+                .with_metadata(ISSUE_KEY, &issue.key)
+                .with_metadata(URL_KEY, &format!("{}/browse/{}", api.url(), issue.key));
+            if state == TaskState::Done {
+                task = task.with_completed_at(chrono::Utc::now().date_naive());
+            }
+            if let Some(project) = &issue.fields.project {
+                task = task.with_project(&project.name);
+            }
+            if let Some(due) = issue.due() {
+                task = task.with_due(due);
+            }
+            if let Some(priority) = issue.priority() {
+                task = task.with_priority(priority);
+            }
+            db_manager.add_task(&task)?;
+            report.added += 1;
+            continue;
+        };
+
+        let id = task.id();
+        let mut changed = false;
+        if task.description() != issue.fields.summary {
+            db_manager.update_description(id, &issue.fields.summary)?;
+            changed = true;
+        }
+        if task.state() != state {
+            db_manager.set_task_state(id, state)?;
+            changed = true;
+        }
+        if task.due() != issue.due() {
+            db_manager.set_due(id, issue.due())?;
+            changed = true;
+        }
+        if task.priority() != issue.priority() {
+            db_manager.set_priority(id, issue.priority())?;
+            changed = true;
+        }
+        report.updated += usize::from(changed);
+    }
+    db_manager.commit()?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+
+    const SEARCH_JSON: &str = r#"{
+        "issues": [{
+            "id": "10042",
+            "key": "OPS-42",
+            "self": "https://example.atlassian.net/rest/api/3/issue/10042",
+            "fields": {
+                "summary": "Rotate the TLS certificates",
+                "status": {"name": "In Review", "statusCategory": {"id": 4, "key": "indeterminate", "name": "In Progress"}},
+                "duedate": "2025-03-14",
+                "priority": {"id": "2", "name": "High"},
+                "labels": ["security"],
+                "project": {"id": "10000", "key": "OPS", "name": "Operations"}
+            }
+        }, {
+            "id": "10043",
+            "key": "OPS-43",
+            "fields": {
+                "summary": "Write the runbook",
+                "status": {"name": "Backlog", "statusCategory": {"key": "new"}},
+                "duedate": null,
+                "priority": null,
+                "labels": []
+            }
+        }],
+        "nextPageToken": "CAEaAggD"
+    }"#;
+
+    /// A Jira site kept in memory that finds every issue.
+    struct MemoryJira {
+        issues: RefCell<Vec<JiraIssue>>,
+    }
+
+    impl JiraApi for MemoryJira {
+        fn url(&self) -> &str {
+            "https://example.atlassian.net"
+        }
+
+        fn search(&self, _jql: &str) -> Result<Vec<JiraIssue>, ToNotDoError> {
+            Ok(self.issues.borrow().clone())
+        }
+    }
+
+    #[test]
+    fn test_issue_fields() {
+        let page: TokenPage = serde_json::from_str(SEARCH_JSON).unwrap();
+        assert_eq!(page.next_page_token.as_deref(), Some("CAEaAggD"));
+        let issue = &page.issues[0];
+        assert_eq!(issue.state(&BTreeMap::new()), TaskState::InProgress);
+        assert_eq!(issue.priority(), Some(Priority::High));
+        assert_eq!(issue.due(), chrono::NaiveDate::from_ymd_opt(2025, 3, 14));
+
+        let statuses = BTreeMap::from([("in review".to_string(), TaskState::Done)]);
+        assert_eq!(issue.state(&statuses), TaskState::Done);
+        assert_eq!(page.issues[1].state(&statuses), TaskState::Todo);
+        assert_eq!(page.issues[1].priority(), None);
+    }
+
+    #[test]
+    fn test_import() {
+        let page: TokenPage = serde_json::from_str(SEARCH_JSON).unwrap();
+        let jira = MemoryJira {
+            issues: RefCell::new(page.issues),
+        };
+        let config = JiraConfig::default();
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+
+        let report = import(&mut db_manager, &jira, &config, DEFAULT_JQL).unwrap();
+        assert_eq!(
+            report,
+            JiraReport {
+                added: 2,
+                updated: 0
+            }
+        );
+        let certificates = db_manager
+            .get_tasks()
+            .unwrap()
+            .iter()
+            .find(|task| task.metadata().get(ISSUE_KEY).map(String::as_str) == Some("OPS-42"))
+            .unwrap()
+            .clone();
+        assert_eq!(certificates.description(), "Rotate the TLS certificates");
+        assert_eq!(certificates.state(), TaskState::InProgress);
+        assert_eq!(certificates.project(), Some("Operations"));
+        assert_eq!(certificates.tags(), ["security"]);
+        assert_eq!(
+            certificates.metadata()[URL_KEY],
+            "https://example.atlassian.net/browse/OPS-42"
+        );
+
+        let report = import(&mut db_manager, &jira, &config, DEFAULT_JQL).unwrap();
+        assert_eq!(
+            report,
+            JiraReport::default(),
+            "imported issues are not added twice"
+        );
+
+        jira.issues.borrow_mut()[0].fields.status = Status {
+            name: "Done".to_string(),
+            status_category: StatusCategory {
+                key: "done".to_string(),
+            },
+        };
+        let report = import(&mut db_manager, &jira, &config, DEFAULT_JQL).unwrap();
+        assert_eq!(
+            report,
+            JiraReport {
+                added: 0,
+                updated: 1
+            }
+        );
+        let certificates = db_manager.get_task(certificates.id()).unwrap();
+        assert_eq!(certificates.state(), TaskState::Done);
+        assert!(certificates.completed_at().is_some());
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Database, Task},
+    storage::{Revision, Storage},
+};
+
+/// A single mutation of the database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    PutTask { task: Box<Task> },
+    RemoveTask { id: Uuid },
+    SetFocus { focus: Option<Uuid> },
+    SetContext { context: Option<String> },
+}
+
+/// A change together with when it was recorded; one per journal line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Journal events are folded into the snapshot once this many were
+/// recorded since the last time.
+pub const COMPACT_EVENTS: usize = 1000;
+
+/// Journal kept next to the database file at `db_file`; the CLI keeps it
+/// in the state directory instead, see
+/// [`AppPaths::journal_file`](crate::file_management::AppPaths::journal_file).
+pub fn journal_path(db_file: &Path) -> PathBuf {
+    db_file.with_extension("journal")
+}
+
+/// Where the journal at `journal` is moved while it is folded into the
+/// snapshot; new events go to a fresh journal meanwhile.
+pub fn folding_path(journal: &Path) -> PathBuf {
+    journal.with_extension("folding")
+}
+
+/// Lock file of the journal at `journal`. Reading the journal holds it
+/// shared, appending to it and folding it into the snapshot exclusively, so
+/// no event lands in a journal being folded.
+fn lock_path(journal: &Path) -> PathBuf {
+    journal.with_extension("journal.lock")
+}
+
+/// Reads the events in the journal at `path`. A last line that was only
+/// partly written is ignored; the returned length covers the complete lines.
+pub fn read_events(path: &Path) -> Result<(Vec<Event>, usize), ToNotDoError> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(ToNotDoError::DatabaseError(e.into())),
+    };
+
+    let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+
+    let events = data[..complete]
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            serde_json::from_slice(line).map_err(|e| {
+                ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    e,
+                )))
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((events, complete))
+}
+
+/// Drops a half-written trailing event, `complete` being the length
+/// [`read_events`] returned, so new events start on a fresh line.
+pub fn truncate_partial_event(path: &Path, complete: usize) -> Result<(), ToNotDoError> {
+    if std::fs::metadata(path).is_ok_and(|m| m.len() > complete as u64) {
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(complete as u64))
+            .map_err(|e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e)))?;
+    }
+
+    Ok(())
+}
+
+/// Appends `events` to the journal at `path`, one line each, and flushes
+/// them to disk. A new journal is only readable by its owner on Unix, like
+/// the database files.
+pub fn append_events(path: &Path, events: &[Event]) -> Result<(), ToNotDoError> {
+    let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+    let mut lines = Vec::new();
+    for event in events {
+        serde_json::to_writer(&mut lines, event)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        lines.push(b'\n');
+    }
+
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(write_error)?;
+    file.write_all(&lines).map_err(write_error)?;
+    file.sync_data().map_err(write_error)
+}
+
+/// Keeps a snapshot in another storage and records every later mutation as
+/// an event appended to a journal. Loading replays the journal on top of the
+/// snapshot, so saving only ever appends the changes since the last save.
+/// Once the journal grows long, it is folded into the snapshot.
+pub struct JournalStorage {
+    snapshot: Box<dyn Storage>,
+    path: PathBuf,
+    last: Option<Database>,
+    /// Events in the journal as far as this storage knows.
+    events: usize,
+    compact_after: usize,
+    read_only: bool,
+}
+
+impl JournalStorage {
+    pub fn new(snapshot: Box<dyn Storage>, journal: &Path) -> Self {
+        Self {
+            snapshot,
+            path: journal.to_path_buf(),
+            last: None,
+            events: 0,
+            compact_after: COMPACT_EVENTS,
+            read_only: false,
+        }
+    }
+
+    /// Folds the journal into the snapshot once it holds `events` events,
+    /// instead of [`COMPACT_EVENTS`].
+    pub fn with_compact_after(mut self, events: usize) -> Self {
+        self.compact_after = events;
+        self
+    }
+
+    /// Locks the journal's lock file, shared or `exclusive`ly, until the
+    /// returned file is dropped. The journal's directory is created along
+    /// with the file, as it is kept apart from the database. A read-only
+    /// storage creates neither, and reads without a lock when there is none.
+    fn lock(&self, exclusive: bool) -> Result<Option<File>, ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        if let Some(dir) = self.path.parent().filter(|_| !self.read_only) {
+            std::fs::create_dir_all(dir).map_err(write_error)?;
+        }
+
+        let lock = match OpenOptions::new()
+            .create(!self.read_only)
+            .truncate(false)
+            .write(!self.read_only)
+            .read(self.read_only)
+            .open(lock_path(&self.path))
+        {
+            Ok(lock) => lock,
+            Err(e) if self.read_only && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(e) => return Err(write_error(e)),
+        };
+        if exclusive {
+            lock.lock().map_err(write_error)?;
+        } else {
+            lock.lock_shared().map_err(write_error)?;
+        }
+        Ok(Some(lock))
+    }
+
+    /// Saves the snapshot with the journal applied and removes the journal.
+    ///
+    /// Other processes wait to read or append to the journal meanwhile.
+    /// Should this stop part way, loading replays the journal moved aside
+    /// as well, which is harmless over a snapshot that has it.
+    fn compact(&mut self) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+        let _lock = self.lock(true)?;
+
+        let folding = folding_path(&self.path);
+        if !folding.is_file() {
+            match std::fs::rename(&self.path, &folding) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(write_error(e)),
+            }
+        }
+
+        let mut db = if self.snapshot.exists() {
+            self.snapshot.load()?
+        } else {
+            Database::default()
+        };
+        let (events, _) = read_events(&folding)?;
+        for event in events {
+            db.apply(event.change);
+        }
+
+        self.snapshot.save(&db)?;
+        std::fs::remove_file(&folding).map_err(write_error)?;
+        self.events = 0;
+        Ok(())
+    }
+}
+
+impl Storage for JournalStorage {
+    fn exists(&self) -> bool {
+        self.snapshot.exists() || self.path.is_file()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        let _lock = self.lock(false)?;
+        let mut db = if self.snapshot.exists() {
+            self.snapshot.load()?
+        } else {
+            Database::default()
+        };
+
+        let (folded, _) = read_events(&folding_path(&self.path))?;
+        let (events, complete) = read_events(&self.path)?;
+        self.events = folded.len() + events.len();
+        for event in folded.into_iter().chain(events) {
+            db.apply(event.change);
+        }
+
+        if !self.read_only {
+            truncate_partial_event(&self.path, complete)?;
+        }
+
+        self.last = Some(db.clone());
+        Ok(db)
+    }
+
+    fn revision(&self) -> Option<Revision> {
+        Some(
+            self.snapshot
+                .revision()?
+                .and(Revision::of([folding_path(&self.path), self.path.clone()])),
+        )
+    }
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.save_revised(db).map(|_| ())
+    }
+
+    fn set_read_only(&mut self) {
+        self.read_only = true;
+        self.snapshot.set_read_only();
+    }
+
+    fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+        let previous = match self.last.take() {
+            Some(previous) => previous,
+            None if self.exists() => self.load()?,
+            None => {
+                self.snapshot.save(db)?;
+                self.last = Some(db.clone());
+                return Ok(None);
+            }
+        };
+
+        let at = Utc::now();
+        let events: Vec<Event> = previous
+            .changes_to(db)
+            .into_iter()
+            .map(|change| Event { at, change })
+            .collect();
+
+        // Nothing else writes while the lock is held, so the revision is
+        // that of the events just appended.
+        let result = if events.is_empty() {
+            Ok(None)
+        } else {
+            self.lock(true).and_then(|_lock| {
+                append_events(&self.path, &events)?;
+                Ok(self.revision())
+            })
+        };
+
+        self.last = Some(if result.is_ok() { db.clone() } else { previous });
+        let revision = result?;
+
+        self.events += events.len();
+        if self.events >= self.compact_after {
+            // The changes are safe in the journal already; folding it is
+            // tried again on the next save when it fails. Either way, the
+            // revision is not known after it.
+            let _ = self.compact();
+            return Ok(None);
+        }
+        Ok(revision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+    use tempfile::tempdir;
+
+    fn storage(dir: &Path) -> JournalStorage {
+        let db_file = dir.join("tasks.json");
+        JournalStorage::new(
+            Box::new(FileStorage::new(&db_file)),
+            &journal_path(&db_file),
+        )
+    }
+
+    #[test]
+    fn test_journal_replays_changes() {
+        let dir = tempdir().unwrap();
+
+        let mut journal = storage(dir.path());
+        journal.open().unwrap();
+
+        let first = Task::new("First");
+        let second = Task::new("Second");
+        journal
+            .update(&mut |db| db.insert_task(first.clone()))
+            .unwrap();
+        journal
+            .update(&mut |db| db.insert_task(second.clone()))
+            .unwrap();
+
+        let mut db = journal.load().unwrap();
+        db.apply(Change::RemoveTask { id: first.id() });
+        journal.save(&db).unwrap();
+
+        let (events, _) = read_events(&journal_path(&dir.path().join("tasks.json"))).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].change, Change::RemoveTask { id: first.id() });
+
+        // The snapshot is untouched; the state comes from replaying events.
+        let snapshot = FileStorage::new(&dir.path().join("tasks.json"))
+            .load()
+            .unwrap();
+        assert!(snapshot.tasks().is_empty());
+        assert_eq!(
+            storage(dir.path()).load().unwrap().tasks(),
+            std::slice::from_ref(&second)
+        );
+    }
+
+    #[test]
+    fn test_journal_creates_its_directory() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let journal = dir.path().join("state").join("tasks.journal");
+
+        let mut storage = JournalStorage::new(Box::new(FileStorage::new(&db_file)), &journal);
+        storage.open().unwrap();
+        storage
+            .update(&mut |db| db.insert_task(Task::new("Kept apart")))
+            .unwrap();
+
+        assert_eq!(read_events(&journal).unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn test_journal_ignores_partial_write() {
+        let dir = tempdir().unwrap();
+        let path = journal_path(&dir.path().join("tasks.json"));
+
+        let mut journal = storage(dir.path());
+        journal.open().unwrap();
+        let task = Task::new("Kept");
+        journal
+            .update(&mut |db| db.insert_task(task.clone()))
+            .unwrap();
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"at": "2024-01-01T00:00:00Z", "type": "put_"#)
+            .unwrap();
+
+        let mut journal = storage(dir.path());
+        assert_eq!(journal.load().unwrap().tasks(), std::slice::from_ref(&task));
+
+        let later = Task::new("Later");
+        journal
+            .update(&mut |db| db.insert_task(later.clone()))
+            .unwrap();
+        assert_eq!(read_events(&path).unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn test_journal_compacts_into_snapshot() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let path = journal_path(&db_file);
+
+        let mut journal = storage(dir.path()).with_compact_after(3);
+        journal.open().unwrap();
+        let tasks: Vec<Task> = (0..4).map(|i| Task::new(&format!("Task {}", i))).collect();
+        for task in &tasks[..2] {
+            journal
+                .update(&mut |db| db.insert_task(task.clone()))
+                .unwrap();
+        }
+        assert_eq!(read_events(&path).unwrap().0.len(), 2);
+
+        journal
+            .update(&mut |db| db.insert_task(tasks[2].clone()))
+            .unwrap();
+        assert!(!path.exists());
+        let snapshot = FileStorage::new(&db_file).load().unwrap();
+        assert_eq!(snapshot.tasks(), &tasks[..3]);
+
+        // A fold cut short is replayed along with the new journal.
+        journal
+            .update(&mut |db| db.insert_task(tasks[3].clone()))
+            .unwrap();
+        std::fs::rename(&path, folding_path(&path)).unwrap();
+        assert_eq!(storage(dir.path()).load().unwrap().tasks(), &tasks[..]);
+    }
+
+    #[test]
+    fn test_journal_folds_only_while_unlocked() {
+        let dir = tempdir().unwrap();
+        let path = journal_path(&dir.path().join("tasks.json"));
+
+        let mut journal = storage(dir.path()).with_compact_after(2);
+        journal.open().unwrap();
+        journal
+            .update(&mut |db| db.insert_task(Task::new("First")))
+            .unwrap();
+
+        // Another process reading holds the lock shared.
+        let reading = storage(dir.path()).lock(false).unwrap();
+        let folding = std::thread::spawn(move || {
+            journal
+                .update(&mut |db| db.insert_task(Task::new("Second")))
+                .unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(path.exists(), "the journal is not folded while locked");
+
+        drop(reading);
+        folding.join().unwrap();
+        assert!(!path.exists());
+        assert_eq!(storage(dir.path()).load().unwrap().tasks().len(), 2);
+    }
+}
@@ -0,0 +1,146 @@
+//! A task store that keeps a to-do list in a single file, shared by the
+//! `to-not-do` command line tool and any program that wants to embed it.
+//!
+//! The pieces most programs need are re-exported at the top level:
+//!
+//! - [`Task`] and [`Database`], the task model;
+//! - [`TaskRepository`], the stable interface for adding, querying,
+//!   updating and watching tasks;
+//! - [`DatabaseManager`], which implements it and saves every change
+//!   through a [`Storage`], the part to replace for other persistence;
+//! - [`open_storage`], which builds the storage for a database file as the
+//!   command line tool would, from a [`StorageConfig`];
+//! - [`TaskFilter`] for picking tasks and the [`reporting`] helpers for
+//!   summarizing them.
+//!
+//! With the `async` feature, [`asynchronous::AsyncDatabaseManager`] offers
+//! the same operations to async code without blocking on file I/O. With
+//! the `grpc` feature, [`serve`] also answers the gRPC service defined in
+//! `proto/to_not_do.proto`, and the `dbus` feature adds the [`dbus`]
+//! service for desktop integrations. Other languages can use the store
+//! through the C ABI of the `ffi` feature, in [`ffi`], or from Python
+//! through the `pyo3` feature, in [`python`]. The `plugins` and `scripting`
+//! features, on by default, add the [`plugin`] host for WASM plugins and
+//! [`script`] for Lua scripts. Built for `wasm32-unknown-unknown` with the
+//! `browser` feature, the library keeps tasks in the page's `localStorage`
+//! through [`browser`], leaving out the servers and network services.
+//!
+//! ```
+//! use to_not_do::{DatabaseManager, MemoryStorage, Task, TaskFilter, TaskState};
+//!
+//! let mut tasks = DatabaseManager::with_storage(Box::new(MemoryStorage::new()))?;
+//! let task = Task::new("Water the plants").with_project("home");
+//! tasks.add_task(&task)?;
+//! tasks.set_task_state(task.id(), TaskState::Done)?;
+//!
+//! let done = TaskFilter {
+//!     state: Some(TaskState::Done),
+//!     ..TaskFilter::default()
+//! };
+//! assert_eq!(done.apply(tasks.get_tasks()?).len(), 1);
+//! # Ok::<(), to_not_do::ToNotDoError>(())
+//! ```
+//!
+//! The remaining modules back the command line tool: backups and dumps,
+//! sync, repair and the like. They are public so the tool can be built on
+//! this crate, but are less settled than the items above.
+
+pub mod archive;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "browser")]
+pub mod browser;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod caldav;
+pub mod checksum;
+pub mod compact;
+pub mod compression;
+pub mod config;
+pub mod conflict;
+pub mod crdt;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod digest;
+pub mod dump;
+pub mod duration;
+pub mod encryption;
+pub mod error;
+pub mod events;
+pub mod external;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod feed;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod file_management;
+pub mod filter;
+pub mod foreign;
+pub mod format;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gcal;
+pub mod git_hook;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod github;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jira;
+pub mod journal;
+pub mod markdown;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod matrix;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mcp;
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod microsoft_todo;
+pub mod migration;
+pub mod org;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod profile;
+pub mod publish;
+#[cfg(feature = "pyo3")]
+pub mod python;
+pub mod reminder;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod remote;
+pub mod repair;
+pub mod reporting;
+pub mod repository;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rpc;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod schedule;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod serve;
+pub mod snapshot;
+#[cfg(unix)]
+pub mod socket;
+pub mod stats;
+pub mod statusbar;
+pub mod storage;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sync;
+pub mod systemd;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod telegram;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod todoist;
+pub mod uri;
+pub mod verify;
+pub mod wal;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod web;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod webhook;
+
+pub use config::{Config, StorageConfig};
+pub use error::{DatabaseError, ToNotDoError};
+pub use file_management::{Database, DatabaseManager, Priority, Task, TaskState};
+pub use filter::TaskFilter;
+pub use repository::TaskRepository;
+pub use storage::{open_storage, FileStorage, MemoryStorage, Storage};
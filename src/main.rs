@@ -1,19 +1,174 @@
 mod cli;
-mod error;
-mod file_management;
+
+use std::process::ExitCode;
 
 use clap::Parser;
-use cli::{handle_commands, Args};
-use file_management::{create_data_directory, DB_FILE_NAME};
+use cli::{
+    handle_commands, handle_file_commands, has_lifecycle_handlers, notify_webhooks,
+    offer_conflict_merge, offer_foreign_migration, run_lifecycle_hooks, Args,
+};
+use to_not_do::{
+    checksum,
+    config::{Config, CONFIG_FILE_NAME},
+    error::{DatabaseError, ToNotDoError},
+    external::PROFILE_ENV,
+    file_management::{
+        find_local_directory, AppDirs, AppPaths, DatabaseManager, APP_NAME, DATA_DIR_ENV,
+    },
+    remote::{cache_path, display_db, is_remote, open_remote, RemoteStorage},
+    storage::{is_read_only, open_app_storage, MemoryStorage, ReadOnlyStorage, Storage, MEMORY_DB},
+};
+
+fn main() -> ExitCode {
+    let dirs = AppDirs::resolve(std::env::var_os(DATA_DIR_ENV));
+    let mut args = Args::parse();
+
+    let (paths, config) = match resolve_paths(&args, &dirs) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !args.read_only && is_read_only(&paths.db_file) {
+        args.read_only = true;
+    }
+
+    if let Some(code) = handle_file_commands(&args, &config, &paths) {
+        return code;
+    }
+
+    let is_file = paths.db_file.as_os_str() != MEMORY_DB && !is_remote(&paths.db_file);
+    if is_file {
+        if let Some(mismatch) = checksum::check(&paths.db_file) {
+            eprintln!(
+                "WARNING: {} {} since it was last saved; check it with `{} verify`",
+                paths.db_file.display(),
+                mismatch,
+                APP_NAME
+            );
+        }
+    }
+
+    let open = || open_database(&args, &paths, &config);
+    let mut opened = open();
+    if let Err(ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(_))) = &opened {
+        if is_file && !args.read_only && offer_foreign_migration(&paths, &config) {
+            opened = open();
+        }
+    }
+    let mut db_manager = match opened {
+        Ok(db_manager) => db_manager,
+        Err(e) => {
+            eprintln!("{}", e);
+            if let ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(_)) = e {
+                eprintln!("Run `{} repair` to recover what can be salvaged", APP_NAME);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if config.compact.auto {
+        db_manager.set_retention(config.compact.clone());
+    }
+    db_manager.set_user(config.user.as_deref());
+
+    if !args.read_only && is_file {
+        offer_conflict_merge(&paths, &mut db_manager);
+    }
+
+    let before = if config.webhooks.is_empty() && !has_lifecycle_handlers(&paths.data_dir) {
+        None
+    } else {
+        db_manager.database().ok().cloned()
+    };
+
+    let mut code = handle_commands(args, &config, &paths, &mut db_manager);
+
+    if let Some(before) = before {
+        if !run_lifecycle_hooks(&paths.data_dir, &before, &mut db_manager)
+            && config.hooks.fail_on_error
+        {
+            code = ExitCode::FAILURE;
+        }
+
+        if !config.webhooks.is_empty() {
+            notify_webhooks(&config.webhooks, &paths, &before, &mut db_manager);
+        }
+    }
+    code
+}
+
+/// Opens the database named by `paths` with the storage it needs.
+fn open_database(
+    args: &Args,
+    paths: &AppPaths,
+    config: &Config,
+) -> Result<DatabaseManager, ToNotDoError> {
+    let mut storage: Box<dyn Storage> = if paths.db_file.as_os_str() == MEMORY_DB {
+        Box::new(MemoryStorage::new())
+    } else if is_remote(&paths.db_file) {
+        let url = paths.db_file.to_string_lossy();
+        Box::new(RemoteStorage::new(
+            open_remote(&url)?,
+            &cache_path(&paths.state_dir, &url),
+            &config.storage,
+        ))
+    } else {
+        open_app_storage(paths, &config.storage)
+    };
+    if args.read_only {
+        storage = Box::new(ReadOnlyStorage::new(storage, &display_db(&paths.db_file)));
+    }
+
+    DatabaseManager::with_storage(storage)
+}
+
+/// Picks the database and config for this invocation. In order of
+/// precedence: `--db`, `--profile` or [`PROFILE_ENV`], a `.tonotdo` directory above the current
+/// one, the `default_profile` setting, and finally the data directory.
+/// `--db :memory:` stands for a database that is never saved, and a URL for
+/// a remote one.
+fn resolve_paths(args: &Args, dirs: &AppDirs) -> Result<(AppPaths, Config), ToNotDoError> {
+    let base_config = Config::load(&dirs.config.join(CONFIG_FILE_NAME))?;
+
+    let local_dir = std::env::current_dir()
+        .ok()
+        .and_then(|dir| find_local_directory(&dir));
+
+    // Programs run as external commands are told the profile to use.
+    let profile = args.profile.clone().or_else(|| {
+        std::env::var(PROFILE_ENV)
+            .ok()
+            .filter(|profile| !profile.is_empty())
+    });
+
+    let mut paths = match (&profile, local_dir, &base_config.default_profile) {
+        (Some(name), _, _) => AppPaths::for_profile(dirs, name)?,
+        (None, Some(local_dir), _) => AppPaths::for_local(dirs, &local_dir),
+        (None, None, Some(name)) => AppPaths::for_profile(dirs, name)?,
+        (None, None, None) => AppPaths::in_dirs(dirs),
+    };
 
-fn main() {
-    let base_dir = dirs::data_dir().expect("Failed to get data directory");
-    let data_dir = create_data_directory(&base_dir);
-    let db_file = data_dir.join(DB_FILE_NAME);
+    if let Some(db) = &args.db {
+        paths.db_file = if db.as_os_str() == MEMORY_DB || is_remote(db) {
+            db.clone()
+        } else {
+            std::path::absolute(db).unwrap_or_else(|_| db.clone())
+        };
+    }
 
-    let mut db_manager = file_management::DatabaseManager::open(&db_file);
+    // Creating a profile only makes its data directory; the state directory
+    // is made once the profile is used.
+    std::fs::create_dir_all(&paths.state_dir)
+        .map_err(|e| ToNotDoError::ConfigError(format!("{}: {}", paths.state_dir.display(), e)))?;
 
-    let args = Args::parse();
+    let config = if paths.has_config_overrides() {
+        Config::load_layered(&paths.base_config_file(), &paths.config_file)?
+    } else {
+        base_config
+    };
 
-    handle_commands(args, &mut db_manager);
+    Ok((paths, config))
 }
@@ -0,0 +1,664 @@
+//! Tasks as `- [ ]` checkboxes in a folder of Markdown notes, such as an
+//! Obsidian vault, kept in step by `sync markdown`.
+//!
+//! Each checkbox carries the ID of its task in an HTML comment at the end of
+//! the line, which Markdown renderers hide, so it can be edited and moved
+//! between notes freely. Checkboxes without one are new tasks, subtasks of
+//! the checkbox they are indented under, and tasks without a checkbox are
+//! added to the end of the inbox note. The text of a checkbox is the
+//! description of its task, and `[ ]`, `[/]` and `[x]` are todo, in progress
+//! and done.
+//!
+//! Like `sync todoist`, each sync compares both sides with how the previous
+//! one left them, which is kept in the state directory, and takes every
+//! change from the side that made it. When both changed the same thing, the
+//! [`MergePreference`] decides.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{merge_field, DatabaseManager, MergePreference, Task, TaskState},
+};
+
+/// Note new tasks are added to when the `[markdown]` section names none.
+pub const DEFAULT_INBOX: &str = "Tasks.md";
+
+const ID_PREFIX: &str = "<!-- to-not-do:";
+const ID_SUFFIX: &str = " -->";
+
+/// Settings from the `[markdown]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    /// Folder of notes synced when `--vault` is not given.
+    pub vault: Option<PathBuf>,
+    /// Note new tasks are added to, relative to the vault.
+    pub inbox: Option<PathBuf>,
+}
+
+fn markdown_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::MarkdownError(reason.to_string())
+}
+
+/// A task as a checkbox line shows it: its text and whether it is ticked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkbox {
+    pub text: String,
+    pub state: TaskState,
+}
+
+impl Checkbox {
+    pub fn of(task: &Task) -> Self {
+        Self {
+            text: task.description().to_string(),
+            state: task.state(),
+        }
+    }
+
+    fn mark(&self) -> char {
+        match self.state {
+            TaskState::Todo => ' ',
+            TaskState::InProgress => '/',
+            TaskState::Done => 'x',
+        }
+    }
+
+    /// Each field from the side that changed it since `base`. Where both
+    /// did, or there is no `base`, from the local side if `local_wins`.
+    fn merge(base: Option<&Self>, local: &Self, remote: &Self, local_wins: bool) -> Self {
+        match base {
+            Some(base) => Self {
+                text: merge_field(&base.text, &local.text, &remote.text, local_wins),
+                state: merge_field(&base.state, &local.state, &remote.state, local_wins),
+            },
+            None if local_wins => local.clone(),
+            None => remote.clone(),
+        }
+    }
+}
+
+/// A checkbox line of a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    indent: String,
+    bullet: char,
+    checkbox: Checkbox,
+    id: Option<Uuid>,
+}
+
+impl Line {
+    fn parse(line: &str) -> Option<Self> {
+        let rest = line.trim_start();
+        let indent = line[..line.len() - rest.len()].to_string();
+        let mut chars = rest.chars();
+        let bullet = chars.next().filter(|c| "-*+".contains(*c))?;
+        let mut chars = chars.as_str().strip_prefix(" [")?.chars();
+        let state = match chars.next()? {
+            ' ' => TaskState::Todo,
+            '/' => TaskState::InProgress,
+            // Obsidian Tasks marks cancelled tasks with a dash.
+            'x' | 'X' | '-' => TaskState::Done,
+            _ => return None,
+        };
+        let text = chars.as_str().strip_prefix("] ")?.trim();
+
+        let (text, id) = text
+            .strip_suffix(ID_SUFFIX)
+            .and_then(|text| text.rsplit_once(ID_PREFIX))
+            .and_then(|(text, id)| Some((text.trim_end(), Some(Uuid::parse_str(id).ok()?))))
+            .unwrap_or((text, None));
+        if text.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            indent,
+            bullet,
+            checkbox: Checkbox {
+                text: text.to_string(),
+                state,
+            },
+            id,
+        })
+    }
+
+    fn render(&self, id: Uuid) -> String {
+        format!(
+            "{}{} [{}] {} {}{}{}",
+            self.indent,
+            self.bullet,
+            self.checkbox.mark(),
+            self.checkbox.text,
+            ID_PREFIX,
+            id,
+            ID_SUFFIX
+        )
+    }
+}
+
+/// A note of the vault, with the lines removed by the sync left as `None`.
+struct Note {
+    /// Relative to the vault.
+    path: PathBuf,
+    lines: Vec<Option<String>>,
+    modified: Option<DateTime<Utc>>,
+    changed: bool,
+}
+
+impl Note {
+    fn read(vault: &Path, path: PathBuf) -> Result<Self, ToNotDoError> {
+        let full_path = vault.join(&path);
+        let read_error = |e| markdown_error(format!("{}: {}", full_path.display(), e));
+        let text = std::fs::read_to_string(&full_path).map_err(read_error)?;
+        let modified = std::fs::metadata(&full_path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(read_error)?;
+
+        Ok(Self {
+            path,
+            lines: text.lines().map(|line| Some(line.to_string())).collect(),
+            modified: Some(modified.into()),
+            changed: false,
+        })
+    }
+
+    fn write(&self, vault: &Path) -> Result<(), ToNotDoError> {
+        let path = vault.join(&self.path);
+        let mut text: String = self
+            .lines
+            .iter()
+            .flatten()
+            .fold(String::new(), |text, line| text + line + "\n");
+        if text.trim().is_empty() {
+            text.clear();
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| markdown_error(format!("{}: {}", dir.display(), e)))?;
+        }
+        crate::storage::write_atomically(&path, text.as_bytes())
+            .map_err(|e| markdown_error(format!("{}: {}", path.display(), e)))
+    }
+
+    fn set(&mut self, index: usize, line: Option<String>) {
+        self.lines[index] = line;
+        self.changed = true;
+    }
+
+    fn push(&mut self, line: String) {
+        self.lines.push(Some(line));
+        self.changed = true;
+    }
+
+    /// The checkbox lines outside code blocks, by index.
+    fn checkboxes(&self) -> Vec<(usize, Line)> {
+        let mut in_code = false;
+        let mut checkboxes = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            let Some(line) = line else {
+                continue;
+            };
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_code = !in_code;
+            } else if !in_code {
+                checkboxes.extend(Line::parse(line).map(|line| (index, line)));
+            }
+        }
+        checkboxes
+    }
+
+    /// The task of the nearest checkbox above line `index` that it is
+    /// indented under.
+    fn parent(&self, index: usize) -> Option<Uuid> {
+        let indent = |line: &str| line.len() - line.trim_start().len();
+        let own = indent(self.lines[index].as_deref()?);
+        let above = self.lines[..index]
+            .iter()
+            .rev()
+            .flatten()
+            .filter(|line| !line.trim().is_empty())
+            .find(|line| indent(line) < own)?;
+        Line::parse(above)?.id
+    }
+}
+
+/// The notes under `dir`, relative to `vault`, leaving out hidden files and
+/// folders such as `.obsidian` and `.trash`.
+fn note_paths(vault: &Path, dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), ToNotDoError> {
+    let full_dir = vault.join(dir);
+    let read_error = |e| markdown_error(format!("{}: {}", full_dir.display(), e));
+    for entry in std::fs::read_dir(&full_dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = dir.join(&name);
+        if entry.file_type().map_err(read_error)?.is_dir() {
+            note_paths(vault, &path, paths)?;
+        } else if path.extension().is_some_and(|extension| extension == "md") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// What a [`sync`] changed on either side.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MarkdownReport {
+    /// Tasks added, changed or deleted here.
+    pub pulled: usize,
+    /// Checkboxes added, changed or removed in the notes.
+    pub pushed: usize,
+}
+
+/// The checkboxes as the last sync left them, for the vault it synced;
+/// kept in the state directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MarkdownState {
+    vault: PathBuf,
+    tasks: BTreeMap<Uuid, Checkbox>,
+}
+
+impl MarkdownState {
+    /// The state of the last sync with `vault`; a sync with another vault
+    /// starts afresh.
+    fn read(path: &Path, vault: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Self>(&json).ok())
+            .filter(|state| state.vault == vault)
+            .unwrap_or_else(|| Self {
+                vault: vault.to_path_buf(),
+                tasks: BTreeMap::new(),
+            })
+    }
+
+    fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+/// When the task was last changed, to the day for tasks without history.
+fn modified(task: &Task) -> Option<DateTime<Utc>> {
+    task.history()
+        .last()
+        .map(|entry| entry.at)
+        .into_iter()
+        .chain(
+            task.updated_at()
+                .and_hms_opt(0, 0, 0)
+                .map(|at| at.and_utc()),
+        )
+        .max()
+}
+
+/// Adds a task for `line`, under the ID `id`.
+fn add(
+    db_manager: &mut DatabaseManager,
+    id: Uuid,
+    line: &Line,
+    parent: Option<Uuid>,
+) -> Result<(), ToNotDoError> {
+    let mut task = Task::new(&line.checkbox.text).with_id(id);
+    task = match line.checkbox.state {
+        TaskState::Done => task.with_completed_at(Utc::now().date_naive()),
+        state => task.with_state(state),
+    };
+    if let Some(parent) = parent {
+        task = task.with_parent(parent);
+    }
+    db_manager.add_task(&task)
+}
+
+/// Syncs the tasks of `db_manager` with the checkboxes in the notes of
+/// `vault` both ways, remembering in `state_path` how both sides were left.
+/// New tasks go at the end of the `inbox` note; those done or archived are
+/// only added if they were synced before.
+pub fn sync(
+    db_manager: &mut DatabaseManager,
+    vault: &Path,
+    inbox: &Path,
+    state_path: &Path,
+    prefer: MergePreference,
+) -> Result<MarkdownReport, ToNotDoError> {
+    let vault = vault
+        .canonicalize()
+        .map_err(|e| markdown_error(format!("{}: {}", vault.display(), e)))?;
+    let state = MarkdownState::read(state_path, &vault);
+    let mut paths = Vec::new();
+    note_paths(&vault, Path::new(""), &mut paths)?;
+    paths.sort();
+    let mut notes = paths
+        .into_iter()
+        .map(|path| Note::read(&vault, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let snapshot = db_manager.database()?.clone();
+    let local = |id: Uuid| snapshot.task(id);
+
+    // The checkboxes by task; a copied one repeats the ID of another, and
+    // is a new task too.
+    let mut found: BTreeMap<Uuid, (usize, usize, Line)> = BTreeMap::new();
+    let mut new = Vec::new();
+    for (note, checkboxes) in notes.iter().map(Note::checkboxes).enumerate() {
+        for (index, line) in checkboxes {
+            match line.id {
+                Some(id) if !found.contains_key(&id) => {
+                    found.insert(id, (note, index, line));
+                }
+                _ => new.push((note, index, line)),
+            }
+        }
+    }
+
+    let mut report = MarkdownReport::default();
+    let mut synced = BTreeMap::new();
+    db_manager.begin();
+
+    // In note order, so a parent has its ID before its subtasks are added.
+    for (note, index, line) in new {
+        let id = Uuid::new_v4();
+        add(db_manager, id, &line, notes[note].parent(index))?;
+        notes[note].set(index, Some(line.render(id)));
+        synced.insert(id, line.checkbox);
+        report.pulled += 1;
+    }
+
+    let mut inbox_lines = Vec::new();
+    let ids: BTreeSet<Uuid> = state.tasks.keys().chain(found.keys()).copied().collect();
+    for id in ids {
+        let base = state.tasks.get(&id);
+        match (local(id), found.remove(&id)) {
+            (None, None) => {}
+            (None, Some((note, index, line))) if base == Some(&line.checkbox) => {
+                notes[note].set(index, None);
+                report.pushed += 1;
+            }
+            (None, Some((note, index, line))) => {
+                // Deleted here but changed there, or never here; the old ID
+                // may not be reused.
+                let id = Uuid::new_v4();
+                add(db_manager, id, &line, None)?;
+                notes[note].set(index, Some(line.render(id)));
+                synced.insert(id, line.checkbox);
+                report.pulled += 1;
+            }
+            (Some(task), None) if base == Some(&Checkbox::of(task)) => {
+                db_manager.delete_task(id)?;
+                report.pulled += 1;
+            }
+            (Some(task), None) => {
+                let checkbox = Checkbox::of(task);
+                inbox_lines.push(inbox_line(&checkbox, id));
+                synced.insert(id, checkbox);
+                report.pushed += 1;
+            }
+            (Some(task), Some((note, index, line))) => {
+                let local = Checkbox::of(task);
+                let local_wins = prefer.local_wins(modified(task) > notes[note].modified);
+                let checkbox = Checkbox::merge(base, &local, &line.checkbox, local_wins);
+
+                if checkbox.text != local.text {
+                    db_manager.update_description(id, &checkbox.text)?;
+                }
+                if checkbox.state != local.state {
+                    db_manager.set_task_state(id, checkbox.state)?;
+                }
+                if checkbox != local {
+                    report.pulled += 1;
+                }
+                if checkbox != line.checkbox {
+                    let line = Line {
+                        checkbox: checkbox.clone(),
+                        ..line
+                    };
+                    notes[note].set(index, Some(line.render(id)));
+                    report.pushed += 1;
+                }
+                synced.insert(id, checkbox);
+            }
+        }
+    }
+
+    for task in snapshot.tasks() {
+        let known = synced.contains_key(&task.id()) || state.tasks.contains_key(&task.id());
+        if known || task.is_archived() || task.state() == TaskState::Done {
+            continue;
+        }
+        let checkbox = Checkbox::of(task);
+        inbox_lines.push(inbox_line(&checkbox, task.id()));
+        synced.insert(task.id(), checkbox);
+        report.pushed += 1;
+    }
+
+    if !inbox_lines.is_empty() {
+        let position = notes.iter().position(|note| note.path == inbox);
+        let note = match position {
+            Some(position) => &mut notes[position],
+            None => {
+                notes.push(Note {
+                    path: inbox.to_path_buf(),
+                    lines: Vec::new(),
+                    modified: None,
+                    changed: true,
+                });
+                notes.last_mut().unwrap()
+            }
+        };
+        for line in inbox_lines {
+            note.push(line);
+        }
+    }
+
+    for note in notes.iter().filter(|note| note.changed) {
+        note.write(&vault)?;
+    }
+    db_manager.commit()?;
+
+    MarkdownState {
+        vault,
+        tasks: synced,
+    }
+    .write(state_path)?;
+    Ok(report)
+}
+
+fn inbox_line(checkbox: &Checkbox, id: Uuid) -> String {
+    Line {
+        indent: String::new(),
+        bullet: '-',
+        checkbox: checkbox.clone(),
+        id: Some(id),
+    }
+    .render(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_line() {
+        let id = Uuid::new_v4();
+        let line = Line::parse(&format!("  * [x] Call Sam {}{} -->", ID_PREFIX, id)).unwrap();
+        assert_eq!(line.indent, "  ");
+        assert_eq!(line.bullet, '*');
+        assert_eq!(line.checkbox.text, "Call Sam");
+        assert_eq!(line.checkbox.state, TaskState::Done);
+        assert_eq!(line.id, Some(id));
+        assert_eq!(Line::parse(&line.render(id)), Some(line));
+
+        let line = Line::parse("- [/] Draft <!-- a comment -->").unwrap();
+        assert_eq!(line.checkbox.text, "Draft <!-- a comment -->");
+        assert_eq!(line.checkbox.state, TaskState::InProgress);
+        assert_eq!(line.id, None);
+
+        assert_eq!(Line::parse("- [ ]"), None);
+        assert_eq!(Line::parse("- [?] Odd"), None);
+        assert_eq!(Line::parse("- Plain item"), None);
+        assert_eq!(Line::parse("[ ] Not a list item"), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let checkbox = |text: &str, state| Checkbox {
+            text: text.to_string(),
+            state,
+        };
+        let base = checkbox("Report", TaskState::Todo);
+        let local = checkbox("Write report", TaskState::Todo);
+        let remote = checkbox("Report", TaskState::Done);
+        assert_eq!(
+            Checkbox::merge(Some(&base), &local, &remote, false),
+            checkbox("Write report", TaskState::Done)
+        );
+
+        let remote = checkbox("Send report", TaskState::Todo);
+        assert_eq!(Checkbox::merge(Some(&base), &local, &remote, true), local);
+        assert_eq!(Checkbox::merge(Some(&base), &local, &remote, false), remote);
+        assert_eq!(Checkbox::merge(None, &local, &remote, false), remote);
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempdir().unwrap();
+        let vault = dir.path().join("vault");
+        std::fs::create_dir_all(vault.join("Projects")).unwrap();
+        std::fs::create_dir_all(vault.join(".trash")).unwrap();
+        std::fs::write(
+            vault.join("Projects/Work.md"),
+            "# Work\n\n- [ ] Write report\n  - [x] Collect figures\n\n```\n- [ ] Not a task\n```\n",
+        )
+        .unwrap();
+        std::fs::write(vault.join(".trash/Old.md"), "- [ ] Deleted\n").unwrap();
+        let state = dir.path().join("db.markdown");
+        let inbox = Path::new(DEFAULT_INBOX);
+        let sync = |db_manager: &mut DatabaseManager, prefer| {
+            sync(db_manager, &vault, inbox, &state, prefer).unwrap()
+        };
+
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let milk = Task::new("Buy milk");
+        db_manager.add_task(&milk).unwrap();
+        db_manager
+            .add_task(&Task::new("Old news").with_state(TaskState::Done))
+            .unwrap();
+
+        assert_eq!(
+            sync(&mut db_manager, MergePreference::Ours),
+            MarkdownReport {
+                pulled: 2,
+                pushed: 1
+            }
+        );
+        let tasks = db_manager.get_tasks().unwrap().to_vec();
+        assert_eq!(tasks.len(), 4);
+        let report = tasks
+            .iter()
+            .find(|task| task.description() == "Write report")
+            .unwrap();
+        let figures = tasks
+            .iter()
+            .find(|task| task.description() == "Collect figures")
+            .unwrap();
+        assert_eq!(figures.parent(), Some(report.id()));
+        assert_eq!(figures.state(), TaskState::Done);
+        assert!(figures.completed_at().is_some());
+
+        let work = std::fs::read_to_string(vault.join("Projects/Work.md")).unwrap();
+        assert!(work.contains(&format!(
+            "- [ ] Write report {}{}{}\n",
+            ID_PREFIX,
+            report.id(),
+            ID_SUFFIX
+        )));
+        assert!(work.contains(&format!(
+            "  - [x] Collect figures {}{}",
+            ID_PREFIX,
+            figures.id()
+        )));
+        assert!(work.contains("```\n- [ ] Not a task\n```\n"));
+        let tasks_note = std::fs::read_to_string(vault.join(DEFAULT_INBOX)).unwrap();
+        assert_eq!(
+            tasks_note,
+            format!("- [ ] Buy milk {}{}{}\n", ID_PREFIX, milk.id(), ID_SUFFIX)
+        );
+
+        assert_eq!(
+            sync(&mut db_manager, MergePreference::Ours),
+            MarkdownReport::default(),
+            "nothing changed"
+        );
+
+        // Checked off in the note, renamed here.
+        std::fs::write(vault.join(DEFAULT_INBOX), tasks_note.replace("[ ]", "[x]")).unwrap();
+        db_manager
+            .update_description(report.id(), "Write the report")
+            .unwrap();
+        assert_eq!(
+            sync(&mut db_manager, MergePreference::Ours),
+            MarkdownReport {
+                pulled: 1,
+                pushed: 1
+            }
+        );
+        assert_eq!(
+            db_manager.get_task(milk.id()).unwrap().state(),
+            TaskState::Done
+        );
+        let work = std::fs::read_to_string(vault.join("Projects/Work.md")).unwrap();
+        assert!(work.contains("- [ ] Write the report <!--"));
+
+        // Deleted on either side.
+        let work: String = work
+            .lines()
+            .filter(|line| !line.contains("Collect figures"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        std::fs::write(vault.join("Projects/Work.md"), work).unwrap();
+        db_manager.delete_task(milk.id()).unwrap();
+        assert_eq!(
+            sync(&mut db_manager, MergePreference::Ours),
+            MarkdownReport {
+                pulled: 1,
+                pushed: 1
+            }
+        );
+        assert!(db_manager.get_task(figures.id()).is_none());
+        assert_eq!(
+            std::fs::read_to_string(vault.join(DEFAULT_INBOX)).unwrap(),
+            ""
+        );
+
+        // Renamed on both sides.
+        let work = std::fs::read_to_string(vault.join("Projects/Work.md")).unwrap();
+        std::fs::write(
+            vault.join("Projects/Work.md"),
+            work.replace("Write the report", "Send the report"),
+        )
+        .unwrap();
+        db_manager
+            .update_description(report.id(), "Finish the report")
+            .unwrap();
+        sync(&mut db_manager, MergePreference::Theirs);
+        assert_eq!(
+            db_manager.get_task(report.id()).unwrap().description(),
+            "Send the report"
+        );
+    }
+}
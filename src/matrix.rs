@@ -0,0 +1,630 @@
+//! A Matrix bot for `bot matrix`, answering `!todo` commands in one room
+//! and posting the daily digest there:
+//!
+//! - `!todo add <description>` adds a task;
+//! - `!todo list` lists the open tasks, `!todo today` those due by today;
+//! - `!todo done <ID or text>` marks the open task with that short ID, or
+//!   the only one whose description contains the text, done.
+//!
+//! The bot signs in with the access token of an account of its own and
+//! joins the room from the `[matrix]` config section. It answers only the
+//! users in `allowed_users` there; anyone else is told their user ID, so it
+//! can be added. Rooms with end-to-end encryption are not supported.
+
+use std::{path::Path, time::Duration};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    digest::{Digest, DigestPeriod},
+    error::{DatabaseError, ToNotDoError},
+    file_management::{DatabaseManager, Task, TaskState},
+};
+
+/// Read for the access token when the `[matrix]` section gives none.
+pub const MATRIX_TOKEN_ENV: &str = "TO_NOT_DO_MATRIX_TOKEN";
+
+/// What commands to the bot start with.
+pub const COMMAND_PREFIX: &str = "!todo";
+
+/// How long a sync waits for new events, in milliseconds.
+const POLL_MILLISECONDS: u64 = 30_000;
+
+/// How long a single request may take, syncs included.
+const TIMEOUT: Duration = Duration::from_secs(POLL_MILLISECONDS / 1000 + 30);
+
+/// Most tasks a reply lists.
+const LIST_LIMIT: usize = 50;
+
+const HELP: &str = "!todo add <description>: add a task\n\
+                    !todo list: list the open tasks\n\
+                    !todo today: list the open tasks due by today\n\
+                    !todo done <ID or text>: mark an open task done";
+
+/// Settings from the `[matrix]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatrixConfig {
+    /// URL of the homeserver of the bot's account, like
+    /// `https://matrix.example.org`.
+    pub homeserver: Option<String>,
+    /// Access token of the bot's account. Falls back to the
+    /// `TO_NOT_DO_MATRIX_TOKEN` environment variable.
+    pub access_token: Option<String>,
+    /// ID or alias of the room, like `#tasks:example.org`.
+    pub room: Option<String>,
+    /// IDs of the users the bot answers, like `@sam:example.org`.
+    pub allowed_users: Vec<String>,
+    /// Time of day to post the daily digest at; none is posted without it.
+    pub digest_time: Option<NaiveTime>,
+}
+
+fn matrix_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::MatrixError(reason.to_string())
+}
+
+/// `text` with anything but unreserved URL characters percent-encoded.
+fn encode_segment(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// A text message posted in the room by someone other than the bot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomMessage {
+    pub sender: String,
+    pub body: String,
+}
+
+/// The messages since the previous sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    /// Where the next sync starts.
+    pub next_batch: String,
+    pub messages: Vec<RoomMessage>,
+}
+
+/// What the bot needs of the client-server API.
+pub trait MatrixApi {
+    /// The messages after `since`, waiting a while for one if there are
+    /// none yet. Without `since`, the messages are those from before the
+    /// bot started.
+    fn sync(&self, since: Option<&str>) -> Result<Batch, ToNotDoError>;
+
+    /// Posts `body` in the room as a notice, which bots do not answer.
+    fn send(&self, body: &str) -> Result<(), ToNotDoError>;
+}
+
+/// The room from the `[matrix]` section, through the client-server API.
+pub struct MatrixClient {
+    agent: ureq::Agent,
+    api_url: String,
+    authorization: String,
+    room_id: String,
+    /// The bot's own user ID, whose messages are skipped.
+    user_id: String,
+}
+
+/// The parts of a sync response the bot reads.
+#[derive(Deserialize)]
+struct SyncResponse {
+    next_batch: String,
+    #[serde(default)]
+    rooms: SyncRooms,
+}
+
+#[derive(Default, Deserialize)]
+struct SyncRooms {
+    #[serde(default)]
+    join: std::collections::BTreeMap<String, JoinedRoom>,
+}
+
+#[derive(Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[derive(Default, Deserialize)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    sender: String,
+    #[serde(default)]
+    content: Value,
+}
+
+impl MatrixClient {
+    /// Signs in with the access token and joins the room.
+    pub fn new(config: &MatrixConfig) -> Result<Self, ToNotDoError> {
+        let homeserver = config.homeserver.as_deref().ok_or_else(|| {
+            matrix_error("No homeserver; set homeserver in the [matrix] config section")
+        })?;
+        let room = config
+            .room
+            .as_deref()
+            .ok_or_else(|| matrix_error("No room; set room in the [matrix] config section"))?;
+        let token = config
+            .access_token
+            .clone()
+            .or_else(|| std::env::var(MATRIX_TOKEN_ENV).ok())
+            .ok_or_else(|| {
+                matrix_error(format!(
+                    "No access token; set access_token in the [matrix] config section or {}",
+                    MATRIX_TOKEN_ENV
+                ))
+            })?;
+
+        let mut client = Self {
+            // Statuses like 404 are answers here, not failures.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            api_url: format!("{}/_matrix/client/v3", homeserver.trim_end_matches('/')),
+            authorization: format!("Bearer {}", token),
+            room_id: String::new(),
+            user_id: String::new(),
+        };
+
+        #[derive(Deserialize)]
+        struct WhoAmI {
+            user_id: String,
+        }
+        #[derive(Deserialize)]
+        struct Joined {
+            room_id: String,
+        }
+
+        let who: WhoAmI = client.read(
+            client
+                .agent
+                .get(format!("{}/account/whoami", client.api_url))
+                .header("Authorization", &client.authorization)
+                .call(),
+        )?;
+        let joined: Joined = client.read(
+            client
+                .agent
+                .post(format!("{}/join/{}", client.api_url, encode_segment(room)))
+                .header("Authorization", &client.authorization)
+                .send_json(json!({})),
+        )?;
+        client.user_id = who.user_id;
+        client.room_id = joined.room_id;
+        Ok(client)
+    }
+
+    /// The account the bot signed in as.
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// The answer to a request, failing with the error the homeserver gave.
+    fn read<T: DeserializeOwned>(
+        &self,
+        response: Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    ) -> Result<T, ToNotDoError> {
+        let mut response = response.map_err(matrix_error)?;
+        let status = response.status().as_u16();
+        let body: Value = response.body_mut().read_json().map_err(matrix_error)?;
+        match status {
+            200..=299 => serde_json::from_value(body).map_err(matrix_error),
+            401 => Err(matrix_error(
+                "the access token was rejected; check access_token in the [matrix] config section",
+            )),
+            _ => Err(matrix_error(format!(
+                "the homeserver answered {}: {}",
+                status,
+                body["error"].as_str().unwrap_or("no reason given")
+            ))),
+        }
+    }
+}
+
+impl MatrixApi for MatrixClient {
+    fn sync(&self, since: Option<&str>) -> Result<Batch, ToNotDoError> {
+        let filter = json!({
+            "presence": { "types": [] },
+            "account_data": { "types": [] },
+            "room": {
+                "rooms": [self.room_id],
+                "state": { "types": [] },
+                "ephemeral": { "types": [] },
+                "account_data": { "types": [] },
+                "timeline": { "types": ["m.room.message"], "limit": LIST_LIMIT },
+            },
+        })
+        .to_string();
+        let mut request = self
+            .agent
+            .get(format!("{}/sync", self.api_url))
+            .header("Authorization", &self.authorization)
+            .query("filter", &filter);
+        if let Some(since) = since {
+            request = request
+                .query("since", since)
+                .query("timeout", POLL_MILLISECONDS.to_string());
+        }
+        let response: SyncResponse = self.read(request.call())?;
+
+        let messages = response
+            .rooms
+            .join
+            .get(&self.room_id)
+            .map(|room| room.timeline.events.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter(|event| event.kind == "m.room.message" && event.sender != self.user_id)
+            .filter(|event| event.content["msgtype"] == "m.text")
+            .filter_map(|event| {
+                Some(RoomMessage {
+                    sender: event.sender.clone(),
+                    body: event.content["body"].as_str()?.to_string(),
+                })
+            })
+            .collect();
+        Ok(Batch {
+            next_batch: response.next_batch,
+            messages,
+        })
+    }
+
+    fn send(&self, body: &str) -> Result<(), ToNotDoError> {
+        // A fresh transaction ID per message; a retry with the same one
+        // would not post twice.
+        let url = format!(
+            "{}/rooms/{}/send/m.room.message/{}",
+            self.api_url,
+            encode_segment(&self.room_id),
+            Uuid::new_v4().simple()
+        );
+        let _: Value = self.read(
+            self.agent
+                .put(url)
+                .header("Authorization", &self.authorization)
+                .send_json(json!({ "msgtype": "m.notice", "body": body })),
+        )?;
+        Ok(())
+    }
+}
+
+/// Kept in the state directory, so a restarted bot neither answers old
+/// commands again nor posts a digest twice.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixState {
+    /// Where the next sync starts.
+    pub since: Option<String>,
+    /// The day the digest was last posted.
+    pub digest_posted: Option<NaiveDate>,
+}
+
+impl MatrixState {
+    /// The state at `path`, or a fresh one if there is none or it is broken.
+    pub fn read(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+/// Answers the commands posted since the last step and posts the digest if
+/// it is time, as of `now`. The first step only catches up with the room.
+pub fn step(
+    db_manager: &mut DatabaseManager,
+    api: &dyn MatrixApi,
+    config: &MatrixConfig,
+    state: &mut MatrixState,
+    now: NaiveDateTime,
+) -> Result<(), ToNotDoError> {
+    let today = now.date();
+    let batch = api.sync(state.since.as_deref())?;
+    if state.since.is_some() {
+        for message in &batch.messages {
+            let Some((command, argument)) = parse(&message.body) else {
+                continue;
+            };
+            if !config.allowed_users.contains(&message.sender) {
+                api.send(&format!(
+                    "{} may not use this bot; add them to allowed_users in the [matrix] \
+                     config section.",
+                    message.sender
+                ))?;
+                continue;
+            }
+            let reply = respond(db_manager, command, argument, today);
+            api.send(&reply.unwrap_or_else(|e| e.to_string()))?;
+        }
+    }
+    state.since = Some(batch.next_batch);
+
+    let digest_due = config
+        .digest_time
+        .is_some_and(|time| now.time() >= time && state.digest_posted != Some(today));
+    if digest_due {
+        let digest = Digest::new(db_manager.get_tasks()?, today, DigestPeriod::Daily);
+        api.send(&format!("{}\n\n{}", digest.subject(), digest.body()))?;
+        state.digest_posted = Some(today);
+    }
+    Ok(())
+}
+
+/// The command and its argument, if `body` is a command to the bot.
+fn parse(body: &str) -> Option<(&str, &str)> {
+    let rest = body.trim().strip_prefix(COMMAND_PREFIX)?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim();
+    let (command, argument) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((command, argument.trim()))
+}
+
+/// The reply to `command` with `argument`.
+fn respond(
+    db_manager: &mut DatabaseManager,
+    command: &str,
+    argument: &str,
+    today: NaiveDate,
+) -> Result<String, ToNotDoError> {
+    match command {
+        "add" if !argument.is_empty() => {
+            let task = Task::new(argument);
+            db_manager
+                .add_task(&task)
+                .map(|()| format!("Added {}: {}", task.short_id(), task.description()))
+        }
+        "list" => db_manager.get_tasks().map(|tasks| {
+            let open: Vec<&Task> = tasks
+                .iter()
+                .filter(|task| !task.is_archived() && task.state() != TaskState::Done)
+                .collect();
+            listing("Open tasks", "No open tasks", &open)
+        }),
+        "today" => db_manager.get_tasks().map(|tasks| {
+            let mut due: Vec<&Task> = tasks
+                .iter()
+                .filter(|task| !task.is_archived() && task.state() != TaskState::Done)
+                .filter(|task| task.due().is_some_and(|due| due <= today))
+                .collect();
+            due.sort_by_key(|task| task.due());
+            listing("Due by today", "Nothing is due today", &due)
+        }),
+        "done" if !argument.is_empty() => done(db_manager, argument),
+        _ => Ok(HELP.to_string()),
+    }
+}
+
+/// `tasks` under `title`, one per line with its short ID, or `empty`.
+fn listing(title: &str, empty: &str, tasks: &[&Task]) -> String {
+    if tasks.is_empty() {
+        return empty.to_string();
+    }
+    let mut text = format!("{} ({}):", title, tasks.len());
+    for task in tasks.iter().take(LIST_LIMIT) {
+        text.push_str(&format!("\n• {} {}", task.short_id(), task.description()));
+        if let Some(due) = task.due() {
+            text.push_str(&format!(" (due {})", due));
+        }
+    }
+    if tasks.len() > LIST_LIMIT {
+        text.push_str(&format!("\nand {} more", tasks.len() - LIST_LIMIT));
+    }
+    text
+}
+
+/// Marks the open task with the short ID `query`, or the only one whose
+/// description contains it, done.
+fn done(db_manager: &mut DatabaseManager, query: &str) -> Result<String, ToNotDoError> {
+    let lowercase = query.to_lowercase();
+    let open = db_manager
+        .get_tasks()?
+        .iter()
+        .filter(|task| !task.is_archived() && task.state() != TaskState::Done);
+    let mut matches: Vec<Task> = open
+        .clone()
+        .filter(|task| task.short_id() == lowercase)
+        .cloned()
+        .collect();
+    if matches.is_empty() {
+        matches = open
+            .filter(|task| task.description().to_lowercase().contains(&lowercase))
+            .cloned()
+            .collect();
+    }
+
+    match matches.as_slice() {
+        [] => Ok(format!("No open task matches \"{}\"", query)),
+        [task] => {
+            db_manager.set_task_state(task.id(), TaskState::Done)?;
+            Ok(format!("Done: {}", task.description()))
+        }
+        _ => {
+            let matches: Vec<&Task> = matches.iter().collect();
+            Ok(listing(
+                &format!("Several open tasks match \"{}\"; say which by ID", query),
+                "",
+                &matches,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+
+    const USER: &str = "@sam:example.org";
+
+    /// A room whose messages, all from [`USER`], come from a list.
+    #[derive(Default)]
+    struct MemoryRoom {
+        incoming: RefCell<Vec<&'static str>>,
+        sent: RefCell<Vec<String>>,
+    }
+
+    impl MatrixApi for MemoryRoom {
+        fn sync(&self, since: Option<&str>) -> Result<Batch, ToNotDoError> {
+            let next: usize = since.map_or(0, |since| since.parse().unwrap()) + 1;
+            Ok(Batch {
+                next_batch: next.to_string(),
+                messages: self
+                    .incoming
+                    .take()
+                    .into_iter()
+                    .map(|body| RoomMessage {
+                        sender: USER.to_string(),
+                        body: body.to_string(),
+                    })
+                    .collect(),
+            })
+        }
+
+        fn send(&self, body: &str) -> Result<(), ToNotDoError> {
+            self.sent.borrow_mut().push(body.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sync_response() {
+        let response: SyncResponse = serde_json::from_str(
+            r#"{
+                "next_batch": "s72595_4483_1934",
+                "rooms": {"join": {"!abc:example.org": {"timeline": {"events": [{
+                    "type": "m.room.message",
+                    "sender": "@sam:example.org",
+                    "event_id": "$143273582443PhrSn:example.org",
+                    "origin_server_ts": 1432735824653,
+                    "content": {"msgtype": "m.text", "body": "!todo list"}
+                }], "limited": false}}}}
+            }"#,
+        )
+        .unwrap();
+        let event = &response.rooms.join["!abc:example.org"].timeline.events[0];
+        assert_eq!(event.content["body"], "!todo list");
+        assert_eq!(
+            encode_segment("#tasks:example.org"),
+            "%23tasks%3Aexample.org"
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let at = |hour| today.and_hms_opt(hour, 0, 0).unwrap();
+        let config = MatrixConfig {
+            digest_time: NaiveTime::from_hms_opt(8, 0, 0),
+            allowed_users: vec![USER.to_string()],
+            ..Default::default()
+        };
+        let room = MemoryRoom::default();
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let rent = Task::new("Pay the rent").with_due(today);
+        db_manager.add_task(&rent).unwrap();
+        db_manager.add_task(&Task::new("Call the bank")).unwrap();
+        let mut state = MatrixState::default();
+
+        // What was said before the bot started is not answered.
+        room.incoming.replace(vec!["!todo add Old"]);
+        step(&mut db_manager, &room, &config, &mut state, at(7)).unwrap();
+        assert!(room.sent.borrow().is_empty());
+        assert_eq!(state.since.as_deref(), Some("1"));
+
+        room.incoming.replace(vec![
+            "!todo add Buy milk",
+            "!todo today",
+            "!todo done the",
+            "!todo done rent",
+            "!todoist",
+            "hello",
+            "!todo",
+        ]);
+        step(&mut db_manager, &room, &config, &mut state, at(7)).unwrap();
+        let sent = room.sent.take();
+        assert_eq!(sent.len(), 5);
+        assert!(sent[0].starts_with("Added ") && sent[0].ends_with(": Buy milk"));
+        assert_eq!(
+            sent[1],
+            format!(
+                "Due by today (1):\n• {} Pay the rent (due 2025-03-10)",
+                rent.short_id()
+            )
+        );
+        assert!(sent[2].starts_with("Several open tasks match \"the\""));
+        assert_eq!(sent[3], "Done: Pay the rent");
+        assert_eq!(sent[4], HELP);
+
+        // The digest is posted once a day, from its time on.
+        step(&mut db_manager, &room, &config, &mut state, at(9)).unwrap();
+        step(&mut db_manager, &room, &config, &mut state, at(10)).unwrap();
+        let sent = room.sent.take();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].contains("daily digest for 2025-03-10: 0 due, 0 overdue"));
+        assert_eq!(state.digest_posted, Some(today));
+
+        room.incoming.replace(vec!["!todo list"]);
+        step(&mut db_manager, &room, &config, &mut state, at(10)).unwrap();
+        let list = room.sent.take();
+        assert!(list[0].starts_with("Open tasks (2):"));
+        assert!(!list[0].contains("Old"));
+    }
+
+    #[test]
+    fn test_step_refuses_other_users() {
+        let now = NaiveDate::from_ymd_opt(2025, 3, 10)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let config = MatrixConfig {
+            allowed_users: vec!["@alex:example.org".to_string()],
+            ..Default::default()
+        };
+        let room = MemoryRoom::default();
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let mut state = MatrixState::default();
+        step(&mut db_manager, &room, &config, &mut state, now).unwrap();
+
+        room.incoming.replace(vec!["!todo add Sneaky", "hello"]);
+        step(&mut db_manager, &room, &config, &mut state, now).unwrap();
+        assert_eq!(
+            room.sent.take(),
+            [format!(
+                "{} may not use this bot; add them to allowed_users in the [matrix] config \
+                 section.",
+                USER
+            )]
+        );
+        assert!(db_manager.get_tasks().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+//! A Model Context Protocol server on stdin and stdout, so AI assistants can
+//! manage the list through a few tools instead of running arbitrary
+//! commands. The tools can add and complete tasks but never delete them.
+
+use std::io::{BufRead, Write};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task, TaskState},
+    filter::TaskFilter,
+    repository::TaskRepository,
+    rpc::{self, params_as, Error, INVALID_PARAMS, METHOD_NOT_FOUND},
+    serve::{stored_task, NewTask},
+};
+
+/// Protocol revisions this server speaks, newest first.
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Answers MCP requests read from `input` on `output` until `input` ends.
+pub fn run(
+    db_manager: &mut DatabaseManager,
+    input: impl BufRead,
+    output: impl Write,
+) -> std::io::Result<()> {
+    rpc::serve(input, output, |method, params| {
+        call(db_manager, method, params)
+    })
+}
+
+fn call(db_manager: &mut DatabaseManager, method: &str, params: Value) -> Result<Value, Error> {
+    match method {
+        "initialize" => Ok(initialize(&params)),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => {
+            let ToolCall { name, arguments } = params_as(params)?;
+            call_tool(db_manager, &name, arguments)
+        }
+        _ => Err(Error::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}", method),
+        )),
+    }
+}
+
+fn initialize(params: &Value) -> Value {
+    let requested = params["protocolVersion"].as_str();
+    let version = PROTOCOL_VERSIONS
+        .into_iter()
+        .find(|version| Some(*version) == requested)
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": { "name": "to-not-do", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Manages the user's to-do list. Tasks are identified by UUID; \
+                         list or search first to find the one meant.",
+    })
+}
+
+fn tools() -> Value {
+    let state = json!({ "type": "string", "enum": ["Todo", "InProgress", "Done"] });
+    json!([
+        {
+            "name": "list_tasks",
+            "description": "List the active tasks, optionally only those in one state or project.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "state": state,
+                    "project": { "type": "string" },
+                },
+            },
+            "annotations": { "readOnlyHint": true },
+        },
+        {
+            "name": "add_task",
+            "description": "Add a task to the list.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "description": { "type": "string" },
+                    "notes": { "type": "string" },
+                    "due": { "type": "string", "format": "date" },
+                    "priority": { "type": "string", "enum": ["Low", "Medium", "High"] },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "project": { "type": "string" },
+                },
+                "required": ["description"],
+            },
+            "annotations": { "readOnlyHint": false, "destructiveHint": false },
+        },
+        {
+            "name": "complete_task",
+            "description": "Mark a task done, given its ID or text matching the description of \
+                            exactly one open task.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string", "format": "uuid" },
+                    "text": { "type": "string" },
+                },
+            },
+            "annotations": {
+                "readOnlyHint": false,
+                "destructiveHint": false,
+                "idempotentHint": true,
+            },
+        },
+        {
+            "name": "search",
+            "description": "Find active tasks whose description, notes or tags contain the query, \
+                            ignoring case.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "query": { "type": "string" } },
+                "required": ["query"],
+            },
+            "annotations": { "readOnlyHint": true },
+        },
+    ])
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListArguments {
+    state: Option<TaskState>,
+    project: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompleteArguments {
+    id: Option<Uuid>,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchArguments {
+    query: String,
+}
+
+/// Why a tool could not do what was asked, told to the assistant rather
+/// than treated as a protocol error.
+struct ToolError(String);
+
+impl From<ToNotDoError> for ToolError {
+    fn from(e: ToNotDoError) -> Self {
+        Self(e.to_string())
+    }
+}
+
+fn call_tool(
+    db_manager: &mut DatabaseManager,
+    name: &str,
+    arguments: Value,
+) -> Result<Value, Error> {
+    let arguments = if arguments.is_null() {
+        json!({})
+    } else {
+        arguments
+    };
+
+    let result = match name {
+        "list_tasks" => {
+            let ListArguments { state, project } = params_as(arguments)?;
+            list_tasks(db_manager, state, project)
+        }
+        "add_task" => add_task(db_manager, params_as(arguments)?),
+        "complete_task" => complete_task(db_manager, params_as(arguments)?),
+        "search" => {
+            let SearchArguments { query } = params_as(arguments)?;
+            search(db_manager, &query)
+        }
+        _ => return Err(Error::new(INVALID_PARAMS, format!("Unknown tool {}", name))),
+    };
+
+    Ok(match result {
+        Ok(text) => json!({ "content": [{ "type": "text", "text": text }] }),
+        Err(ToolError(text)) => {
+            json!({ "content": [{ "type": "text", "text": text }], "isError": true })
+        }
+    })
+}
+
+/// One line per task, with everything an assistant needs to refer to it.
+fn describe(tasks: &[Task]) -> String {
+    if tasks.is_empty() {
+        return "No tasks".to_string();
+    }
+
+    tasks
+        .iter()
+        .map(|task| {
+            let mut line = format!(
+                "- {} [{:?}] {}",
+                task.id(),
+                task.state(),
+                task.description()
+            );
+            if let Some(project) = task.project() {
+                line.push_str(&format!(" (project: {})", project));
+            }
+            if let Some(due) = task.due() {
+                line.push_str(&format!(" (due: {})", due));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn list_tasks(
+    db_manager: &mut DatabaseManager,
+    state: Option<TaskState>,
+    project: Option<String>,
+) -> Result<String, ToolError> {
+    let filter = TaskFilter {
+        state,
+        project,
+        archived: false,
+    };
+    Ok(describe(&db_manager.query(&filter)?))
+}
+
+fn add_task(db_manager: &mut DatabaseManager, new_task: NewTask) -> Result<String, ToolError> {
+    let task = new_task.into_task();
+    db_manager.add(&task)?;
+    Ok(format!(
+        "Added:\n{}",
+        describe(&[stored_task(db_manager, task.id())?])
+    ))
+}
+
+fn complete_task(
+    db_manager: &mut DatabaseManager,
+    arguments: CompleteArguments,
+) -> Result<String, ToolError> {
+    let task = match (arguments.id, arguments.text) {
+        (Some(id), _) => stored_task(db_manager, id)?,
+        (None, Some(text)) => {
+            db_manager.database()?;
+            match db_manager.find_open_tasks(&text).as_slice() {
+                [] => return Err(ToolError(format!("No open task matches \"{}\"", text))),
+                [task] => task.clone(),
+                matches => {
+                    return Err(ToolError(format!(
+                        "Several open tasks match \"{}\"; complete one by ID:\n{}",
+                        text,
+                        describe(matches)
+                    )))
+                }
+            }
+        }
+        (None, None) => return Err(ToolError("Give either id or text".to_string())),
+    };
+
+    if task.state() != TaskState::Done {
+        db_manager.set_task_state(task.id(), TaskState::Done)?;
+    }
+    Ok(format!("Done: {}", task.description()))
+}
+
+fn search(db_manager: &mut DatabaseManager, query: &str) -> Result<String, ToolError> {
+    db_manager.database()?;
+    let tasks: Vec<Task> = db_manager
+        .search(query)
+        .into_iter()
+        .map(|found| found.task)
+        .collect();
+    Ok(describe(&tasks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn tool(db_manager: &mut DatabaseManager, name: &str, arguments: Value) -> (String, bool) {
+        let result = call(
+            db_manager,
+            "tools/call",
+            json!({ "name": name, "arguments": arguments }),
+        )
+        .unwrap();
+        (
+            result["content"][0]["text"].as_str().unwrap().to_string(),
+            result["isError"] == true,
+        )
+    }
+
+    #[test]
+    fn test_initialize_and_list_tools() {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+
+        let initialized = call(
+            &mut db_manager,
+            "initialize",
+            json!({ "protocolVersion": "2025-03-26", "capabilities": {} }),
+        )
+        .unwrap();
+        assert_eq!(initialized["protocolVersion"], "2025-03-26");
+        let initialized = call(
+            &mut db_manager,
+            "initialize",
+            json!({ "protocolVersion": "1999-01-01" }),
+        )
+        .unwrap();
+        assert_eq!(initialized["protocolVersion"], PROTOCOL_VERSIONS[0]);
+
+        let tools = call(&mut db_manager, "tools/list", Value::Null).unwrap();
+        let names: Vec<_> = tools["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["list_tasks", "add_task", "complete_task", "search"]);
+    }
+
+    #[test]
+    fn test_tools() {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+
+        let (text, is_error) = tool(
+            &mut db_manager,
+            "add_task",
+            json!({ "description": "Book dentist", "project": "health" }),
+        );
+        assert!(!is_error);
+        assert!(text.contains("Book dentist (project: health)"));
+        tool(
+            &mut db_manager,
+            "add_task",
+            json!({ "description": "Book flights" }),
+        );
+
+        let (text, is_error) = tool(&mut db_manager, "complete_task", json!({ "text": "book" }));
+        assert!(is_error);
+        assert!(text.contains("Several open tasks"));
+
+        let (text, is_error) = tool(
+            &mut db_manager,
+            "complete_task",
+            json!({ "text": "dentist" }),
+        );
+        assert!(!is_error);
+        assert_eq!(text, "Done: Book dentist");
+
+        let (text, _) = tool(&mut db_manager, "list_tasks", json!({ "state": "Done" }));
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.contains("[Done] Book dentist"));
+
+        let (text, _) = tool(&mut db_manager, "search", json!({ "query": "FLIGHTS" }));
+        assert!(text.contains("Book flights"));
+
+        let (_, is_error) = tool(
+            &mut db_manager,
+            "complete_task",
+            json!({ "id": Uuid::new_v4() }),
+        );
+        assert!(is_error);
+
+        let unknown = call(
+            &mut db_manager,
+            "tools/call",
+            json!({ "name": "delete_everything" }),
+        );
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Prometheus metrics for `serve`, at [`METRICS_ENDPOINT`] in the text
+//! exposition format, so self-hosters can graph and alert on their tasks:
+//!
+//! - `to_not_do_tasks{state}`: active tasks by state
+//! - `to_not_do_archived_tasks`: archived tasks
+//! - `to_not_do_overdue_tasks`: open tasks past their due date
+//! - `to_not_do_operations_total{operation}`: requests that created,
+//!   updated or deleted a task, or pulled or pushed sync events
+//! - `to_not_do_sync_errors_total`: sync pulls and pushes that failed
+//!
+//! The counters start from zero when the server does.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::NaiveDate;
+
+use crate::file_management::{Task, TaskState};
+
+pub const METRICS_ENDPOINT: &str = "/metrics";
+
+/// What a request did, as counted by `to_not_do_operations_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Pull,
+    Push,
+}
+
+impl Operation {
+    /// In the order of the discriminants.
+    const ALL: [Operation; 5] = [
+        Operation::Create,
+        Operation::Update,
+        Operation::Delete,
+        Operation::Pull,
+        Operation::Push,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Pull => "pull",
+            Operation::Push => "push",
+        }
+    }
+}
+
+/// The counters of a running server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    operations: [AtomicU64; 5],
+    sync_errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record(&self, operation: Operation) {
+        self.operations[operation as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_sync_error(&self) {
+        self.sync_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The metrics for `tasks` as of `today`, along with the counters.
+    pub fn render(&self, tasks: &[Task], today: NaiveDate) -> String {
+        let active = || tasks.iter().filter(|task| !task.is_archived());
+        let mut text = String::new();
+
+        family(&mut text, "tasks", "gauge", "Active tasks by state.");
+        for (state, name) in [
+            (TaskState::Todo, "todo"),
+            (TaskState::InProgress, "in-progress"),
+            (TaskState::Done, "done"),
+        ] {
+            let count = active().filter(|task| task.state() == state).count();
+            let _ = writeln!(text, "to_not_do_tasks{{state=\"{}\"}} {}", name, count);
+        }
+
+        family(&mut text, "archived_tasks", "gauge", "Archived tasks.");
+        let archived = tasks.iter().filter(|task| task.is_archived()).count();
+        let _ = writeln!(text, "to_not_do_archived_tasks {}", archived);
+
+        family(
+            &mut text,
+            "overdue_tasks",
+            "gauge",
+            "Open tasks past their due date.",
+        );
+        let overdue = active().filter(|task| task.is_overdue(today)).count();
+        let _ = writeln!(text, "to_not_do_overdue_tasks {}", overdue);
+
+        family(
+            &mut text,
+            "operations_total",
+            "counter",
+            "Requests that changed tasks or exchanged sync events.",
+        );
+        for (operation, count) in Operation::ALL.iter().zip(&self.operations) {
+            let _ = writeln!(
+                text,
+                "to_not_do_operations_total{{operation=\"{}\"}} {}",
+                operation.name(),
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        family(
+            &mut text,
+            "sync_errors_total",
+            "counter",
+            "Sync pulls and pushes that failed.",
+        );
+        let _ = writeln!(
+            text,
+            "to_not_do_sync_errors_total {}",
+            self.sync_errors.load(Ordering::Relaxed)
+        );
+        text
+    }
+}
+
+/// The `HELP` and `TYPE` lines of a metric family.
+fn family(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP to_not_do_{} {}", name, help);
+    let _ = writeln!(text, "# TYPE to_not_do_{} {}", name, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let yesterday = today.pred_opt().unwrap();
+        let tasks = [
+            Task::new("Late").with_due(yesterday),
+            Task::new("Started").with_state(TaskState::InProgress),
+            Task::new("Done late")
+                .with_due(yesterday)
+                .with_completed_at(today),
+            Task::new("Shelved").with_due(yesterday).with_archived(),
+        ];
+
+        let metrics = Metrics::default();
+        metrics.record(Operation::Create);
+        metrics.record(Operation::Create);
+        metrics.record(Operation::Push);
+        metrics.record_sync_error();
+
+        let text = metrics.render(&tasks, today);
+        for line in [
+            "# TYPE to_not_do_tasks gauge",
+            "to_not_do_tasks{state=\"todo\"} 1",
+            "to_not_do_tasks{state=\"in-progress\"} 1",
+            "to_not_do_tasks{state=\"done\"} 1",
+            "to_not_do_archived_tasks 1",
+            "to_not_do_overdue_tasks 1",
+            "# TYPE to_not_do_operations_total counter",
+            "to_not_do_operations_total{operation=\"create\"} 2",
+            "to_not_do_operations_total{operation=\"update\"} 0",
+            "to_not_do_operations_total{operation=\"push\"} 1",
+            "to_not_do_sync_errors_total 1",
+        ] {
+            assert!(text.lines().any(|other| other == line), "{}", line);
+        }
+    }
+}
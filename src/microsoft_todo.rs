@@ -0,0 +1,468 @@
+//! Tasks from Microsoft To Do, copied in by `import --from microsoft-todo`
+//! through the Microsoft Graph API, for moving over from Outlook and To Do.
+//!
+//! Every list is imported, each becoming a project, except the default
+//! "Tasks" list, whose tasks get none. Importance maps to priority, with
+//! normal importance meaning no priority, categories become tags and the
+//! body becomes the notes. Imported tasks keep the ID they had in To Do
+//! under the [`TASK_KEY`] metadata, so importing again updates them instead
+//! of adding them twice; the title, status, due date and importance are
+//! copied over on every import.
+//!
+//! The Graph API needs an access token with the `Tasks.Read` permission.
+//! For a one-off move the quickest is to sign in to Graph Explorer
+//! (<https://developer.microsoft.com/graph/graph-explorer>) and copy the
+//! token it shows; it lasts about an hour.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Priority, Task, TaskState},
+    gcal::encode_segment,
+};
+
+/// Read for the access token when the `[microsoft_todo]` section gives
+/// none.
+pub const MICROSOFT_TOKEN_ENV: &str = "TO_NOT_DO_MICROSOFT_TOKEN";
+
+/// Metadata holding the ID of the To Do task a task was imported from.
+pub const TASK_KEY: &str = "microsoft_todo_task";
+
+const DEFAULT_API_URL: &str = "https://graph.microsoft.com/v1.0";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings from the `[microsoft_todo]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MicrosoftTodoConfig {
+    /// Graph access token. Falls back to the `TO_NOT_DO_MICROSOFT_TOKEN`
+    /// environment variable.
+    pub token: Option<String>,
+    /// Where the Graph API is, when not at Microsoft itself.
+    pub api_url: Option<String>,
+}
+
+fn microsoft_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::MicrosoftTodoError(reason.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TodoList {
+    pub id: String,
+    pub display_name: String,
+    /// `defaultList` for the "Tasks" list, `flaggedEmails` or `none`.
+    #[serde(default)]
+    pub wellknown_list_name: Option<String>,
+}
+
+impl TodoList {
+    /// The project the tasks of the list go to.
+    fn project(&self) -> Option<&str> {
+        match self.wellknown_list_name.as_deref() {
+            Some("defaultList") => None,
+            _ => Some(&self.display_name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemBody {
+    pub content: String,
+    /// `text` or `html`.
+    pub content_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateTimeTimeZone {
+    /// Such as `2025-03-14T00:00:00.0000000`.
+    pub date_time: String,
+    pub time_zone: String,
+}
+
+impl DateTimeTimeZone {
+    fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(self.date_time.get(..10)?, "%Y-%m-%d").ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TodoTask {
+    pub id: String,
+    pub title: String,
+    /// `notStarted`, `inProgress`, `completed`, `waitingOnOthers` or
+    /// `deferred`.
+    pub status: String,
+    /// `low`, `normal` or `high`.
+    pub importance: String,
+    #[serde(default)]
+    pub body: Option<ItemBody>,
+    #[serde(default)]
+    pub due_date_time: Option<DateTimeTimeZone>,
+    #[serde(default)]
+    pub completed_date_time: Option<DateTimeTimeZone>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+}
+
+impl TodoTask {
+    fn state(&self) -> TaskState {
+        match self.status.as_str() {
+            "completed" => TaskState::Done,
+            "inProgress" => TaskState::InProgress,
+            _ => TaskState::Todo,
+        }
+    }
+
+    fn priority(&self) -> Option<Priority> {
+        match self.importance.as_str() {
+            "high" => Some(Priority::High),
+            "low" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    fn due(&self) -> Option<NaiveDate> {
+        self.due_date_time.as_ref()?.date()
+    }
+
+    /// The body as plain text, when there is any. To Do keeps notes as
+    /// text, but Outlook tasks may have HTML bodies, which are left out.
+    fn notes(&self) -> Option<&str> {
+        let body = self.body.as_ref()?;
+        let content = body.content.trim();
+        (body.content_type.eq_ignore_ascii_case("text") && !content.is_empty()).then_some(content)
+    }
+}
+
+/// What the import needs of the Graph API.
+pub trait MicrosoftTodoApi {
+    fn lists(&self) -> Result<Vec<TodoList>, ToNotDoError>;
+
+    /// Every task in the list with ID `list`.
+    fn tasks(&self, list: &str) -> Result<Vec<TodoTask>, ToNotDoError>;
+}
+
+/// One page of a Graph collection.
+#[derive(Deserialize)]
+struct Page<T> {
+    value: Vec<T>,
+    #[serde(default, rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+/// The Microsoft Graph API, authenticated with an access token.
+pub struct MicrosoftTodoClient {
+    agent: ureq::Agent,
+    api_url: String,
+    authorization: String,
+}
+
+impl MicrosoftTodoClient {
+    pub fn new(config: &MicrosoftTodoConfig) -> Result<Self, ToNotDoError> {
+        let token = config
+            .token
+            .clone()
+            .or_else(|| std::env::var(MICROSOFT_TOKEN_ENV).ok())
+            .ok_or_else(|| {
+                microsoft_error(format!(
+                    "No access token; set token in the [microsoft_todo] config section or {}",
+                    MICROSOFT_TOKEN_ENV
+                ))
+            })?;
+
+        Ok(Self {
+            // Statuses like 401 are answers here, not failures.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            api_url: config
+                .api_url
+                .as_deref()
+                .unwrap_or(DEFAULT_API_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            authorization: format!("Bearer {}", token),
+        })
+    }
+
+    /// Every item of the collection at `path`, following the pages.
+    fn collect<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, ToNotDoError> {
+        let mut items = Vec::new();
+        let mut url = format!("{}{}", self.api_url, path);
+        loop {
+            let mut response = self
+                .agent
+                .get(&url)
+                .header("Authorization", &self.authorization)
+                .header("Accept", "application/json")
+                .call()
+                .map_err(microsoft_error)?;
+            let page: Page<T> = match response.status().as_u16() {
+                200..=299 => response.body_mut().read_json().map_err(microsoft_error)?,
+                401 | 403 => {
+                    return Err(microsoft_error(
+                        "access denied; the token may have expired or lack Tasks.Read",
+                    ))
+                }
+                status => {
+                    return Err(microsoft_error(format!(
+                        "{} answered {}: {}",
+                        path,
+                        status,
+                        response.body_mut().read_to_string().unwrap_or_default()
+                    )))
+                }
+            };
+            items.extend(page.value);
+            match page.next_link {
+                Some(next) => url = next,
+                None => return Ok(items),
+            }
+        }
+    }
+}
+
+impl MicrosoftTodoApi for MicrosoftTodoClient {
+    fn lists(&self) -> Result<Vec<TodoList>, ToNotDoError> {
+        self.collect("/me/todo/lists")
+    }
+
+    fn tasks(&self, list: &str) -> Result<Vec<TodoTask>, ToNotDoError> {
+        self.collect(&format!("/me/todo/lists/{}/tasks", encode_segment(list)))
+    }
+}
+
+/// What [`import`] changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MicrosoftTodoReport {
+    pub lists: usize,
+    pub added: usize,
+    pub updated: usize,
+}
+
+/// Adds a task for every task in every To Do list, or updates the one
+/// imported from it before.
+pub fn import(
+    db_manager: &mut DatabaseManager,
+    api: &dyn MicrosoftTodoApi,
+) -> Result<MicrosoftTodoReport, ToNotDoError> {
+    let lists = api.lists()?;
+    let imported: BTreeMap<String, Task> = db_manager
+        .get_tasks()?
+        .iter()
+        .filter_map(|task| Some((task.metadata().get(TASK_KEY)?.clone(), task.clone())))
+        .collect();
+
+    let mut report = MicrosoftTodoReport {
+        lists: lists.len(),
+        ..MicrosoftTodoReport::default()
+    };
+    db_manager.begin();
+    for list in &lists {
+        for todo in api.tasks(&list.id)? {
+            let state = todo.state();
+            let Some(task) = imported.get(&todo.id) else {
+                let mut task = Task::new(&todo.title)
+                    .with_state(state)
+                    .with_tags(&todo.categories)
+                    .with_metadata(TASK_KEY, &todo.id);
+                if state == TaskState::Done {
+                    let completed = todo.completed_date_time.as_ref().and_then(|c| c.date());
+                    task = task.with_completed_at(
+                        completed.unwrap_or_else(|| chrono::Utc::now().date_naive()),
+                    );
+                }
+                if let Some(project) = list.project() {
+                    task = task.with_project(project);
+                }
+                if let Some(notes) = todo.notes() {
+                    task = task.with_notes(notes);
+                }
+                if let Some(due) = todo.due() {
+                    task = task.with_due(due);
+                }
+                if let Some(priority) = todo.priority() {
+                    task = task.with_priority(priority);
+                }
+                db_manager.add_task(&task)?;
+                report.added += 1;
+                continue;
+            };
+
+            let id = task.id();
+            let mut changed = false;
+            if task.description() != todo.title {
+                db_manager.update_description(id, &todo.title)?;
+                changed = true;
+            }
+            if task.state() != state {
+                db_manager.set_task_state(id, state)?;
+                changed = true;
+            }
+            if task.due() != todo.due() {
+                db_manager.set_due(id, todo.due())?;
+                changed = true;
+            }
+            if task.priority() != todo.priority() {
+                db_manager.set_priority(id, todo.priority())?;
+                changed = true;
+            }
+            report.updated += usize::from(changed);
+        }
+    }
+    db_manager.commit()?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+
+    const LISTS_JSON: &str = r#"{
+        "@odata.context": "https://graph.microsoft.com/v1.0/$metadata#users('me')/todo/lists",
+        "value": [
+            {"id": "AAMkADefault", "displayName": "Tasks", "isOwner": true, "wellknownListName": "defaultList"},
+            {"id": "AAMkAGroceries", "displayName": "Groceries", "isOwner": true, "wellknownListName": "none"}
+        ]
+    }"#;
+
+    const TASKS_JSON: &str = r#"{
+        "value": [{
+            "id": "AAMkATaxes",
+            "title": "File the taxes",
+            "status": "inProgress",
+            "importance": "high",
+            "body": {"content": "Receipts are in the blue folder", "contentType": "text"},
+            "dueDateTime": {"dateTime": "2025-04-15T00:00:00.0000000", "timeZone": "UTC"},
+            "categories": ["Red category"],
+            "createdDateTime": "2025-03-01T09:00:00Z"
+        }, {
+            "id": "AAMkACall",
+            "title": "Call the bank",
+            "status": "completed",
+            "importance": "normal",
+            "body": {"content": "", "contentType": "text"},
+            "completedDateTime": {"dateTime": "2025-03-02T00:00:00.0000000", "timeZone": "UTC"}
+        }],
+        "@odata.nextLink": "https://graph.microsoft.com/v1.0/me/todo/lists/AAMkADefault/tasks?$skip=2"
+    }"#;
+
+    /// To Do kept in memory.
+    struct MemoryTodo {
+        lists: Vec<TodoList>,
+        tasks: RefCell<BTreeMap<String, Vec<TodoTask>>>,
+    }
+
+    impl MicrosoftTodoApi for MemoryTodo {
+        fn lists(&self) -> Result<Vec<TodoList>, ToNotDoError> {
+            Ok(self.lists.clone())
+        }
+
+        fn tasks(&self, list: &str) -> Result<Vec<TodoTask>, ToNotDoError> {
+            Ok(self.tasks.borrow().get(list).cloned().unwrap_or_default())
+        }
+    }
+
+    fn memory_todo() -> MemoryTodo {
+        let lists: Page<TodoList> = serde_json::from_str(LISTS_JSON).unwrap();
+        let tasks: Page<TodoTask> = serde_json::from_str(TASKS_JSON).unwrap();
+        let milk = TodoTask {
+            id: "AAMkAMilk".to_string(),
+            title: "Milk".to_string(),
+            status: "notStarted".to_string(),
+            importance: "low".to_string(),
+            body: None,
+            due_date_time: None,
+            completed_date_time: None,
+            categories: Vec::new(),
+        };
+        MemoryTodo {
+            lists: lists.value,
+            tasks: RefCell::new(BTreeMap::from([
+                ("AAMkADefault".to_string(), tasks.value),
+                ("AAMkAGroceries".to_string(), vec![milk]),
+            ])),
+        }
+    }
+
+    #[test]
+    fn test_task_fields() {
+        let page: Page<TodoTask> = serde_json::from_str(TASKS_JSON).unwrap();
+        assert!(page.next_link.unwrap().ends_with("$skip=2"));
+        let taxes = &page.value[0];
+        assert_eq!(taxes.state(), TaskState::InProgress);
+        assert_eq!(taxes.priority(), Some(Priority::High));
+        assert_eq!(taxes.due(), NaiveDate::from_ymd_opt(2025, 4, 15));
+        assert_eq!(taxes.notes(), Some("Receipts are in the blue folder"));
+
+        let call = &page.value[1];
+        assert_eq!(call.state(), TaskState::Done);
+        assert_eq!(call.priority(), None);
+        assert_eq!(call.notes(), None);
+    }
+
+    #[test]
+    fn test_import() {
+        let todo = memory_todo();
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+
+        let report = import(&mut db_manager, &todo).unwrap();
+        assert_eq!(
+            report,
+            MicrosoftTodoReport {
+                lists: 2,
+                added: 3,
+                updated: 0
+            }
+        );
+        let find = |db_manager: &mut DatabaseManager, id: &str| {
+            db_manager
+                .get_tasks()
+                .unwrap()
+                .iter()
+                .find(|task| task.metadata().get(TASK_KEY).map(String::as_str) == Some(id))
+                .unwrap()
+                .clone()
+        };
+        let taxes = find(&mut db_manager, "AAMkATaxes");
+        assert_eq!(taxes.project(), None);
+        assert_eq!(taxes.tags(), ["Red category"]);
+        let call = find(&mut db_manager, "AAMkACall");
+        assert_eq!(call.completed_at(), NaiveDate::from_ymd_opt(2025, 3, 2));
+        let milk = find(&mut db_manager, "AAMkAMilk");
+        assert_eq!(milk.project(), Some("Groceries"));
+        assert_eq!(milk.priority(), Some(Priority::Low));
+
+        let report = import(&mut db_manager, &todo).unwrap();
+        assert_eq!(
+            report.added + report.updated,
+            0,
+            "imported tasks are not added twice"
+        );
+
+        todo.tasks.borrow_mut().get_mut("AAMkAGroceries").unwrap()[0].status =
+            "completed".to_string();
+        let report = import(&mut db_manager, &todo).unwrap();
+        assert_eq!((report.added, report.updated), (0, 1));
+        assert_eq!(
+            db_manager.get_task(milk.id()).unwrap().state(),
+            TaskState::Done
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use serde_json::Value;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::VERSION,
+};
+
+/// Schema version written by this build. Bump it together with a new entry in
+/// `MIGRATIONS` whenever the on-disk format changes incompatibly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a database from schema version `n` to `n + 1`.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [backfill_completed_at];
+
+/// Returns the schema version stored in `db`. Files written before versioning
+/// was introduced have none and count as version 0.
+pub fn schema_version(db: &Value) -> u32 {
+    db.get("schema_version")
+        .and_then(Value::as_u64)
+        .map_or(0, |v| v as u32)
+}
+
+/// Upgrades a raw database to [`SCHEMA_VERSION`], returning whether anything
+/// changed. Databases written by a newer build are refused.
+pub fn migrate(db: &mut Value) -> Result<bool, ToNotDoError> {
+    let found = schema_version(db);
+
+    if found > SCHEMA_VERSION {
+        return Err(ToNotDoError::DatabaseError(
+            DatabaseError::UnsupportedSchema {
+                found,
+                supported: SCHEMA_VERSION,
+            },
+        ));
+    }
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(found as usize) {
+        migration(db).map_err(|reason| {
+            ToNotDoError::DatabaseError(DatabaseError::MigrationFailed {
+                from: version as u32,
+                reason,
+            })
+        })?;
+        db["schema_version"] = Value::from(version as u32 + 1);
+    }
+
+    if found < SCHEMA_VERSION {
+        db["version"] = Value::from(VERSION);
+    }
+
+    Ok(found < SCHEMA_VERSION)
+}
+
+/// Version 0 recorded when a task was finished only through `updated_at`.
+fn backfill_completed_at(db: &mut Value) -> Result<(), String> {
+    let tasks = db
+        .get_mut("tasks")
+        .and_then(Value::as_array_mut)
+        .ok_or("missing task list")?;
+
+    for task in tasks {
+        let task = task.as_object_mut().ok_or("task is not an object")?;
+
+        if task.get("state").and_then(Value::as_str) == Some("Done")
+            && !task.contains_key("completed_at")
+        {
+            if let Some(updated_at) = task.get("updated_at").cloned() {
+                task.insert("completed_at".to_string(), updated_at);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy_database() {
+        let mut db = json!({
+            "name": "to-not-do",
+            "version": "0.0.1",
+            "tasks": [
+                {"id": "1", "state": "Done", "updated_at": "2024-01-02"},
+                {"id": "2", "state": "Todo", "updated_at": "2024-01-03"}
+            ]
+        });
+
+        assert!(migrate(&mut db).unwrap());
+        assert_eq!(schema_version(&db), SCHEMA_VERSION);
+        assert_eq!(db["version"], VERSION);
+        assert_eq!(db["tasks"][0]["completed_at"], "2024-01-02");
+        assert!(db["tasks"][1].get("completed_at").is_none());
+
+        assert!(!migrate(&mut db).unwrap());
+    }
+
+    #[test]
+    fn test_refuse_newer_database() {
+        let mut db = json!({"schema_version": SCHEMA_VERSION + 1, "tasks": []});
+
+        assert!(matches!(
+            migrate(&mut db),
+            Err(ToNotDoError::DatabaseError(
+                DatabaseError::UnsupportedSchema { .. }
+            ))
+        ));
+    }
+}
@@ -0,0 +1,602 @@
+//! Emacs org-mode outlines, written by `export --format org` and read back
+//! by `import --format org`.
+//!
+//! Every task is a headline nested under its parent, with its state as the
+//! TODO keyword, its priority as a cookie and its tags at the end. The due
+//! date is the `DEADLINE` and the completion date is `CLOSED`; `SCHEDULED`
+//! is kept in the `scheduled` metadata. The ID, project and creation date go
+//! in the property drawer along with any other metadata, and the notes are
+//! the text under the headline.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use uuid::Uuid;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Priority, Task, TaskState},
+};
+
+/// Metadata holding the `SCHEDULED` date of a task, as `YYYY-MM-DD`.
+pub const SCHEDULED_KEY: &str = "scheduled";
+
+/// Keywords for tasks in progress, besides any a `#+TODO` line declares.
+const IN_PROGRESS_KEYWORDS: [&str; 4] = ["STARTED", "DOING", "IN-PROGRESS", "INPROGRESS"];
+
+/// Properties that map onto task fields rather than metadata.
+const PROPERTIES: [&str; 3] = ["ID", "CATEGORY", "CREATED"];
+
+/// The tag org-mode marks archived headlines with.
+const ARCHIVE_TAG: &str = "ARCHIVE";
+
+fn active(date: NaiveDate) -> String {
+    date.format("<%Y-%m-%d %a>").to_string()
+}
+
+fn inactive(date: NaiveDate) -> String {
+    date.format("[%Y-%m-%d %a]").to_string()
+}
+
+/// The date of an org timestamp such as `<2025-03-10 Mon 18:00 +1w>`.
+fn parse_timestamp(timestamp: &str) -> Option<NaiveDate> {
+    let date = timestamp.strip_prefix(['<', '['])?.get(..10)?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// The scheduled date kept in metadata, also in the form Taskwarrior uses.
+fn scheduled(task: &Task) -> Option<NaiveDate> {
+    let value = task.metadata().get(SCHEDULED_KEY)?;
+    let date = value.get(..10).unwrap_or(value);
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value.get(..8).unwrap_or(value), "%Y%m%d"))
+        .ok()
+}
+
+/// Org tags only allow letters, digits, `_`, `@`, `#` and `%`.
+fn org_tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "_@#%".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn write_task(org: &mut String, task: &Task, level: usize) {
+    let keyword = match task.state() {
+        TaskState::Todo => "TODO",
+        TaskState::InProgress => "STARTED",
+        TaskState::Done => "DONE",
+    };
+    let mut headline = format!("{} {}", "*".repeat(level), keyword);
+    if let Some(priority) = task.priority() {
+        let cookie = match priority {
+            Priority::High => "A",
+            Priority::Medium => "B",
+            Priority::Low => "C",
+        };
+        headline.push_str(&format!(" [#{}]", cookie));
+    }
+    headline.push(' ');
+    headline.push_str(task.description());
+
+    let mut tags: Vec<String> = task.tags().iter().map(|tag| org_tag(tag)).collect();
+    if task.is_archived() {
+        tags.push(ARCHIVE_TAG.to_string());
+    }
+    if !tags.is_empty() {
+        headline.push_str(&format!(" :{}:", tags.join(":")));
+    }
+    org.push_str(&headline);
+    org.push('\n');
+
+    let planning: Vec<String> = [
+        task.completed_at()
+            .filter(|_| task.state() == TaskState::Done)
+            .map(|at| format!("CLOSED: {}", inactive(at))),
+        task.due().map(|due| format!("DEADLINE: {}", active(due))),
+        scheduled(task).map(|at| format!("SCHEDULED: {}", active(at))),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !planning.is_empty() {
+        org.push_str(&planning.join(" "));
+        org.push('\n');
+    }
+
+    org.push_str(":PROPERTIES:\n");
+    org.push_str(&format!(":ID: {}\n", task.id()));
+    if let Some(project) = task.project() {
+        org.push_str(&format!(":CATEGORY: {}\n", project));
+    }
+    org.push_str(&format!(":CREATED: {}\n", inactive(task.created_at())));
+    for (key, value) in task.metadata() {
+        if key != SCHEDULED_KEY {
+            org.push_str(&format!(":{}: {}\n", key, value));
+        }
+    }
+    org.push_str(":END:\n");
+
+    if let Some(notes) = task.notes() {
+        for line in notes.lines() {
+            // A line starting with a star would read as a headline.
+            if line.starts_with('*') {
+                org.push(' ');
+            }
+            org.push_str(line);
+            org.push('\n');
+        }
+    }
+}
+
+/// Writes `tasks` as an outline, subtasks under their parent.
+pub fn export(tasks: &[Task]) -> String {
+    let mut children: BTreeMap<Option<Uuid>, Vec<&Task>> = BTreeMap::new();
+    for task in tasks {
+        let parent = task
+            .parent()
+            .filter(|parent| tasks.iter().any(|task| task.id() == *parent));
+        children.entry(parent).or_default().push(task);
+    }
+
+    let mut org = "#+TODO: TODO STARTED | DONE\n".to_string();
+    let mut stack: Vec<(&Task, usize)> = children
+        .get(&None)
+        .into_iter()
+        .flatten()
+        .rev()
+        .map(|task| (*task, 1))
+        .collect();
+    while let Some((task, level)) = stack.pop() {
+        write_task(&mut org, task, level);
+        if let Some(subtasks) = children.get(&Some(task.id())) {
+            stack.extend(subtasks.iter().rev().map(|task| (*task, level + 1)));
+        }
+    }
+    org
+}
+
+/// The TODO keywords of a file, open ones and done ones.
+struct Keywords {
+    open: Vec<String>,
+    done: Vec<String>,
+}
+
+impl Keywords {
+    fn new(text: &str) -> Self {
+        let mut keywords = Self {
+            open: ["TODO", "NEXT", "WAITING", "HOLD"]
+                .into_iter()
+                .chain(IN_PROGRESS_KEYWORDS)
+                .map(str::to_string)
+                .collect(),
+            done: ["DONE", "CANCELLED", "CANCELED"]
+                .map(str::to_string)
+                .to_vec(),
+        };
+
+        for line in text.lines() {
+            let Some((name, value)) = line.trim().split_once(':') else {
+                continue;
+            };
+            if !matches!(
+                name.to_ascii_uppercase().as_str(),
+                "#+TODO" | "#+SEQ_TODO" | "#+TYP_TODO"
+            ) {
+                continue;
+            }
+            let (open, done) = value.split_once('|').unwrap_or_else(|| {
+                // Without a bar, only the last keyword means done.
+                let last = value.trim_end().rfind(' ').map_or(0, |at| at + 1);
+                value.split_at(last)
+            });
+            // Fast access keys such as `TODO(t)` are not part of the keyword.
+            let names = |words: &str| -> Vec<String> {
+                words
+                    .split_whitespace()
+                    .map(|word| word.split('(').next().unwrap_or(word).to_string())
+                    .collect()
+            };
+            keywords.open.extend(names(open));
+            keywords.done.extend(names(done));
+        }
+        keywords
+    }
+
+    fn state(&self, word: &str) -> Option<TaskState> {
+        if self.done.iter().any(|keyword| keyword == word) {
+            Some(TaskState::Done)
+        } else if IN_PROGRESS_KEYWORDS.contains(&word) {
+            Some(TaskState::InProgress)
+        } else if self.open.iter().any(|keyword| keyword == word) {
+            Some(TaskState::Todo)
+        } else {
+            None
+        }
+    }
+}
+
+/// A headline with what follows it up to the next one.
+struct Section<'a> {
+    level: usize,
+    headline: &'a str,
+    body: Vec<&'a str>,
+}
+
+fn sections(text: &str) -> Vec<Section<'_>> {
+    let mut sections: Vec<Section> = Vec::new();
+    for line in text.lines() {
+        let level = line.chars().take_while(|&c| c == '*').count();
+        if level > 0 && line[level..].starts_with(' ') {
+            sections.push(Section {
+                level,
+                headline: line[level..].trim(),
+                body: Vec::new(),
+            });
+        } else if let Some(section) = sections.last_mut() {
+            section.body.push(line);
+        }
+    }
+    sections
+}
+
+/// A task read from a headline, with the category it sets for the
+/// headlines under it.
+struct Parsed {
+    task: Option<Task>,
+    category: Option<String>,
+}
+
+fn parse_section(section: &Section, keywords: &Keywords, inherited: Option<&str>) -> Parsed {
+    let mut words = section.headline.split_whitespace().peekable();
+    let state = words.peek().and_then(|word| keywords.state(word));
+    if state.is_some() {
+        words.next();
+    }
+    let priority = words
+        .next_if(|word| word.starts_with("[#") && word.ends_with(']'))
+        .map(|cookie| match cookie {
+            "[#A]" => Priority::High,
+            "[#B]" => Priority::Medium,
+            _ => Priority::Low,
+        });
+    let mut words: Vec<&str> = words.collect();
+    let tags: Vec<String> = match words.last() {
+        Some(last) if last.len() > 1 && last.starts_with(':') && last.ends_with(':') => {
+            let tags = last
+                .trim_matches(':')
+                .split(':')
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect();
+            words.pop();
+            tags
+        }
+        _ => Vec::new(),
+    };
+
+    let mut properties: Vec<(String, String)> = Vec::new();
+    let mut planning: BTreeMap<&str, NaiveDate> = BTreeMap::new();
+    let mut notes: Vec<&str> = Vec::new();
+    let mut drawer: Option<&str> = None;
+    for (index, line) in section.body.iter().enumerate() {
+        let trimmed = line.trim();
+        if let Some(name) = drawer {
+            if trimmed.eq_ignore_ascii_case(":END:") {
+                drawer = None;
+            } else if name.eq_ignore_ascii_case("PROPERTIES") {
+                if let Some((key, value)) = trimmed
+                    .strip_prefix(':')
+                    .and_then(|property| property.split_once(':'))
+                {
+                    properties.push((key.to_string(), value.trim().to_string()));
+                }
+            }
+            continue;
+        }
+        if let Some(name) = trimmed
+            .strip_prefix(':')
+            .and_then(|name| name.strip_suffix(':'))
+            .filter(|name| !name.is_empty() && !name.contains([' ', ':']))
+        {
+            drawer = Some(name);
+            continue;
+        }
+        let is_planning = ["CLOSED:", "DEADLINE:", "SCHEDULED:"]
+            .iter()
+            .any(|keyword| trimmed.starts_with(keyword));
+        if index == 0 && is_planning {
+            let mut parts = trimmed.split_whitespace().peekable();
+            while let Some(keyword) = parts.next() {
+                let timestamp: Vec<&str> = std::iter::from_fn(|| {
+                    parts.next_if(|part| !part.ends_with(':') || part.starts_with(['<', '[']))
+                })
+                .collect();
+                if let Some(date) = timestamp.first().and_then(|first| parse_timestamp(first)) {
+                    planning.insert(keyword.trim_end_matches(':'), date);
+                }
+            }
+            continue;
+        }
+        notes.push(line);
+    }
+
+    let property = |name: &str| {
+        properties
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let category = property("CATEGORY")
+        .or(inherited)
+        .filter(|category| !category.is_empty())
+        .map(str::to_string);
+    let Some(state) = state else {
+        return Parsed {
+            task: None,
+            category,
+        };
+    };
+
+    let mut task = Task::new(&words.join(" "));
+    if let Some(id) = property("ID").and_then(|id| Uuid::parse_str(id).ok()) {
+        task = task.with_id(id);
+    }
+    if let Some(created_at) = property("CREATED").and_then(parse_timestamp) {
+        task = task.with_created_at(created_at);
+    }
+    if let Some(category) = &category {
+        task = task.with_project(category);
+    }
+    let (archived, tags): (Vec<String>, Vec<String>) =
+        tags.into_iter().partition(|tag| tag == ARCHIVE_TAG);
+    if !tags.is_empty() {
+        task = task.with_tags(&tags);
+    }
+    if !archived.is_empty() {
+        task = task.with_archived();
+    }
+    if let Some(priority) = priority {
+        task = task.with_priority(priority);
+    }
+    if let Some(&due) = planning.get("DEADLINE") {
+        task = task.with_due(due);
+    }
+    if let Some(scheduled) = planning.get("SCHEDULED") {
+        task = task.with_metadata(SCHEDULED_KEY, &scheduled.to_string());
+    }
+    for (key, value) in &properties {
+        if !PROPERTIES.iter().any(|name| key.eq_ignore_ascii_case(name)) {
+            task = task.with_metadata(key, value);
+        }
+    }
+
+    let indent = notes
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let notes: Vec<&str> = notes
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or_default().trim_end())
+        .collect();
+    let notes = notes.join("\n");
+    if !notes.trim().is_empty() {
+        task = task.with_notes(notes.trim_matches('\n'));
+    }
+
+    task = match state {
+        TaskState::Done => task.with_completed_at(
+            planning
+                .get("CLOSED")
+                .copied()
+                .unwrap_or_else(|| chrono::Utc::now().date_naive()),
+        ),
+        state => task.with_state(state),
+    };
+    Parsed {
+        task: Some(task),
+        category,
+    }
+}
+
+/// Reads the headlines with a TODO keyword as tasks, nested ones as
+/// subtasks of the nearest task above them. The others only pass their
+/// `CATEGORY` on to the tasks under them.
+pub fn parse(text: &str) -> Vec<Task> {
+    let keywords = Keywords::new(text);
+    let file_category = text.lines().find_map(|line| {
+        let (name, value) = line.trim().split_once(':')?;
+        name.eq_ignore_ascii_case("#+CATEGORY")
+            .then(|| value.trim().to_string())
+    });
+
+    let mut tasks = Vec::new();
+    // The headlines above the current one: level, task ID and category.
+    let mut outline: Vec<(usize, Option<Uuid>, Option<String>)> = Vec::new();
+    for section in sections(text) {
+        while outline
+            .last()
+            .is_some_and(|(level, _, _)| *level >= section.level)
+        {
+            outline.pop();
+        }
+        let inherited = outline
+            .last()
+            .map_or(file_category.as_deref(), |(_, _, category)| {
+                category.as_deref()
+            });
+        let parsed = parse_section(&section, &keywords, inherited);
+
+        let parent = outline.iter().rev().find_map(|(_, id, _)| *id);
+        let id = parsed.task.map(|mut task| {
+            if let Some(parent) = parent {
+                task = task.with_parent(parent);
+            }
+            let id = task.id();
+            tasks.push(task);
+            id
+        });
+        outline.push((section.level, id, parsed.category));
+    }
+    tasks
+}
+
+/// What [`import`] changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OrgImport {
+    pub added: usize,
+    pub updated: usize,
+}
+
+/// Adds the tasks of an outline, updating the ones already here under the
+/// same ID. Headlines without an `ID` property are added every time.
+pub fn import(db_manager: &mut DatabaseManager, text: &str) -> Result<OrgImport, ToNotDoError> {
+    let mut import = OrgImport::default();
+    db_manager.begin();
+    for task in parse(text) {
+        match db_manager.get_task(task.id()) {
+            None => {
+                db_manager.add_task(&task)?;
+                import.added += 1;
+            }
+            Some(stored) if !task.same_fields(stored) => {
+                db_manager.update_task(&task)?;
+                import.updated += 1;
+            }
+            Some(_) => {}
+        }
+    }
+    db_manager.commit()?;
+
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    const OUTLINE: &str = "#+TITLE: Plans
+#+TODO: TODO NEXT(n) | DONE CANCELLED
+#+CATEGORY: home
+
+Some text before the first headline.
+
+* Work
+:PROPERTIES:
+:CATEGORY: work
+:END:
+** NEXT [#A] Write report :q1:writing:
+DEADLINE: <2025-03-10 Mon> SCHEDULED: <2025-03-03 Mon 09:00>
+:PROPERTIES:
+:ID: 2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10
+:Effort: 2:00
+:END:
+:LOGBOOK:
+CLOCK: [2025-03-01 Sat 10:00]--[2025-03-01 Sat 11:00] =>  1:00
+:END:
+  Use the new template.
+  Ask Sam for the numbers.
+*** DONE Collect figures
+CLOSED: [2025-03-02 Sun 17:00]
+* STARTED Water the plants :ARCHIVE:
+* Not a task
+";
+
+    #[test]
+    fn test_parse() {
+        let tasks = parse(OUTLINE);
+        assert_eq!(tasks.len(), 3);
+
+        let report = &tasks[0];
+        assert_eq!(report.description(), "Write report");
+        assert_eq!(
+            report.id(),
+            Uuid::parse_str("2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10").unwrap()
+        );
+        assert_eq!(report.state(), TaskState::Todo);
+        assert_eq!(report.priority(), Some(Priority::High));
+        assert_eq!(report.tags(), ["q1", "writing"]);
+        assert_eq!(report.project(), Some("work"));
+        assert_eq!(report.due(), NaiveDate::from_ymd_opt(2025, 3, 10));
+        assert_eq!(report.metadata()[SCHEDULED_KEY], "2025-03-03");
+        assert_eq!(report.metadata()["Effort"], "2:00");
+        assert_eq!(
+            report.notes(),
+            Some("Use the new template.\nAsk Sam for the numbers.")
+        );
+
+        let figures = &tasks[1];
+        assert_eq!(figures.parent(), Some(report.id()));
+        assert_eq!(figures.state(), TaskState::Done);
+        assert_eq!(figures.completed_at(), NaiveDate::from_ymd_opt(2025, 3, 2));
+        assert_eq!(figures.project(), Some("work"), "categories are inherited");
+
+        let plants = &tasks[2];
+        assert_eq!(plants.state(), TaskState::InProgress);
+        assert_eq!(plants.parent(), None);
+        assert_eq!(plants.project(), Some("home"));
+        assert!(plants.is_archived());
+        assert!(plants.tags().is_empty());
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let tasks = parse(OUTLINE);
+        let org = export(&tasks);
+        assert!(org.starts_with("#+TODO: TODO STARTED | DONE\n* TODO [#A] Write report :q1:writing:\nDEADLINE: <2025-03-10 Mon> SCHEDULED: <2025-03-03 Mon>\n:PROPERTIES:\n:ID: 2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10\n:CATEGORY: work\n"));
+        assert!(org.contains("** DONE Collect figures\nCLOSED: [2025-03-02 Sun]\n"));
+        assert!(org.contains("* STARTED Water the plants :ARCHIVE:\n"));
+
+        let reparsed = parse(&org);
+        assert_eq!(reparsed.len(), tasks.len());
+        for (task, reparsed) in tasks.iter().zip(&reparsed) {
+            assert!(task.same_fields(reparsed), "{} changed", task.description());
+            assert_eq!(task.created_at(), reparsed.created_at());
+        }
+
+        let odd = Task::new("Odd")
+            .with_tags(&["to-do list".to_string()])
+            .with_notes("* not a headline");
+        let reparsed = parse(&export(&[odd]));
+        assert_eq!(reparsed[0].tags(), ["to_do_list"]);
+        assert_eq!(reparsed[0].notes(), Some("* not a headline"));
+    }
+
+    #[test]
+    fn test_import() {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        assert_eq!(
+            import(&mut db_manager, OUTLINE).unwrap(),
+            OrgImport {
+                added: 3,
+                updated: 0
+            }
+        );
+        let exported = export(db_manager.get_tasks().unwrap());
+        assert_eq!(
+            import(&mut db_manager, &exported).unwrap(),
+            OrgImport {
+                added: 0,
+                updated: 0
+            },
+            "tasks with an ID are matched"
+        );
+
+        let changed = exported.replace("TODO [#A] Write report", "DONE [#A] Write report");
+        let report_id = Uuid::parse_str("2c3ec1c2-61e5-4a4e-9d1f-4d4b1d1a6d10").unwrap();
+        let import = import(&mut db_manager, &changed).unwrap();
+        assert_eq!(import.updated, 1);
+        assert_eq!(
+            db_manager.get_task(report_id).unwrap().state(),
+            TaskState::Done
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! WASM plugins, loaded from the `plugins` directory in the data directory
+//! and run in a sandbox: they see only what the host hands them, cannot
+//! touch files or the network, and are stopped when they run too long or
+//! use too much memory.
+//!
+//! # Host API
+//!
+//! A plugin is a core WASM module (`.wasm`, or `.wat` text) that exports:
+//!
+//! - `memory`, and `alloc(len: i32) -> i32`, returning where the host may
+//!   write `len` bytes of input;
+//! - `manifest() -> i64`, describing the plugin as
+//!   `{"name", "commands": [{"name", "about"}], "filters": [...], "events": [...]}`;
+//! - `command(ptr: i32, len: i32) -> i64`, given
+//!   `{"command", "args": [...], "tasks": [...]}` and answering
+//!   `{"output", "add": [new tasks], "done": [IDs], "error"}`, every field
+//!   optional;
+//! - `filter(ptr: i32, len: i32) -> i64`, given `{"filter", "task"}` and
+//!   answering `true` or `false`;
+//! - `on_event(ptr: i32, len: i32) -> i64`, given `{"event", "task"}` for the
+//!   events it listed, where an event is `add`, `done` or `delete`, and
+//!   answering `{}` or `{"error"}`.
+//!
+//! Inputs and outputs are UTF-8 JSON; an `i64` result holds the address of
+//! the output in its upper 32 bits and its length in the lower ones. Only the
+//! exports for what the manifest lists are needed. Plugins may import
+//! `to_not_do.log(ptr: i32, len: i32)` to print a line to stderr.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wasmtime::{
+    Caller, Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+use crate::{error::ToNotDoError, file_management::Task, hooks::Lifecycle, serve::NewTask};
+
+/// Directory in the data directory holding the plugins.
+pub const PLUGINS_DIR: &str = "plugins";
+
+/// Instructions a plugin may run per call before it is stopped.
+const FUEL: u64 = 1_000_000_000;
+
+/// Memory a plugin may grow to.
+const MEMORY_LIMIT: usize = 64 << 20;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default)]
+    pub commands: Vec<PluginCommand>,
+    #[serde(default)]
+    pub filters: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginCommand {
+    pub name: String,
+    #[serde(default)]
+    pub about: String,
+}
+
+/// What a plugin command asks the host to print and do.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CommandOutput {
+    pub output: String,
+    pub add: Vec<NewTask>,
+    pub done: Vec<Uuid>,
+    pub error: Option<String>,
+}
+
+struct HostState {
+    name: String,
+    limits: StoreLimits,
+}
+
+pub struct Plugin {
+    path: PathBuf,
+    manifest: Manifest,
+    store: Store<HostState>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Plugin {
+    fn load(engine: &Engine, path: &Path) -> Result<Self, ToNotDoError> {
+        let plugin_error = |e: wasmtime::Error| plugin_error(path, e);
+
+        let module = Module::from_file(engine, path).map_err(plugin_error)?;
+        let mut store = Store::new(
+            engine,
+            HostState {
+                name: path.display().to_string(),
+                limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap(
+                "to_not_do",
+                "log",
+                |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                    let Some(memory) = caller
+                        .get_export("memory")
+                        .and_then(|export| export.into_memory())
+                    else {
+                        return;
+                    };
+                    let mut bytes = vec![0; len.max(0) as usize];
+                    if memory.read(&caller, ptr as usize, &mut bytes).is_ok() {
+                        eprintln!(
+                            "[{}] {}",
+                            caller.data().name,
+                            String::from_utf8_lossy(&bytes)
+                        );
+                    }
+                },
+            )
+            .map_err(plugin_error)?;
+
+        store.set_fuel(FUEL).map_err(plugin_error)?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .map_err(plugin_error)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| plugin_error(wasmtime::Error::msg("no exported memory")))?;
+
+        let mut plugin = Self {
+            path: path.to_path_buf(),
+            manifest: Manifest {
+                name: String::new(),
+                commands: Vec::new(),
+                filters: Vec::new(),
+                events: Vec::new(),
+            },
+            store,
+            instance,
+            memory,
+        };
+        plugin.manifest = plugin.call("manifest", None)?;
+        plugin.store.data_mut().name = plugin.manifest.name.clone();
+        Ok(plugin)
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Calls `export` with `input` written to the plugin's memory, and reads
+    /// the JSON it answers with.
+    fn call<T: serde::de::DeserializeOwned>(
+        &mut self,
+        export: &str,
+        input: Option<&Value>,
+    ) -> Result<T, ToNotDoError> {
+        let path = self.path.clone();
+        let plugin_error = |e: wasmtime::Error| plugin_error(&path, e.context(export.to_string()));
+
+        self.store.set_fuel(FUEL).map_err(plugin_error)?;
+
+        let result = match input {
+            None => self
+                .instance
+                .get_typed_func::<(), i64>(&mut self.store, export)
+                .and_then(|f| f.call(&mut self.store, ()))
+                .map_err(plugin_error)?,
+            Some(input) => {
+                let bytes = serde_json::to_vec(input)
+                    .map_err(|e| plugin_error(wasmtime::Error::msg(e.to_string())))?;
+                let len = i32::try_from(bytes.len())
+                    .map_err(|_| plugin_error(wasmtime::Error::msg("input too large")))?;
+
+                let alloc = self
+                    .instance
+                    .get_typed_func::<i32, i32>(&mut self.store, "alloc")
+                    .map_err(plugin_error)?;
+                let ptr = alloc.call(&mut self.store, len).map_err(plugin_error)?;
+                self.memory
+                    .write(&mut self.store, ptr as usize, &bytes)
+                    .map_err(|e| plugin_error(wasmtime::Error::msg(e.to_string())))?;
+
+                self.instance
+                    .get_typed_func::<(i32, i32), i64>(&mut self.store, export)
+                    .and_then(|f| f.call(&mut self.store, (ptr, len)))
+                    .map_err(plugin_error)?
+            }
+        };
+
+        let (ptr, len) = (
+            (result as u64 >> 32) as usize,
+            (result as u64 & 0xffff_ffff) as usize,
+        );
+        let mut output = vec![0; len];
+        self.memory
+            .read(&self.store, ptr, &mut output)
+            .map_err(|e| plugin_error(wasmtime::Error::msg(e.to_string())))?;
+        serde_json::from_slice(&output)
+            .map_err(|e| plugin_error(wasmtime::Error::msg(format!("invalid output: {}", e))))
+    }
+}
+
+fn plugin_error(path: &Path, e: wasmtime::Error) -> ToNotDoError {
+    ToNotDoError::PluginError(format!("{}: {:#}", path.display(), e))
+}
+
+/// Every plugin in a directory, loaded and ready to call.
+pub struct PluginHost {
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    /// Loads the plugins in `dir`, returning the ones that failed to load
+    /// alongside the others. A missing directory holds no plugins.
+    pub fn load(dir: &Path) -> (Self, Vec<ToNotDoError>) {
+        let mut host = Self {
+            plugins: Vec::new(),
+        };
+        let mut failures = Vec::new();
+
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return (host, failures);
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "wasm" || extension == "wat")
+            })
+            .collect();
+        paths.sort();
+        if paths.is_empty() {
+            return (host, failures);
+        }
+
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = match Engine::new(&config) {
+            Ok(engine) => engine,
+            Err(e) => {
+                failures.push(plugin_error(dir, e));
+                return (host, failures);
+            }
+        };
+
+        for path in paths {
+            match Plugin::load(&engine, &path) {
+                Ok(plugin) => host.plugins.push(plugin),
+                Err(e) => failures.push(e),
+            }
+        }
+        (host, failures)
+    }
+
+    pub fn plugins(&self) -> &[Plugin] {
+        &self.plugins
+    }
+
+    fn provider(&mut self, provides: impl Fn(&Manifest) -> bool) -> Option<&mut Plugin> {
+        self.plugins
+            .iter_mut()
+            .find(|plugin| provides(&plugin.manifest))
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.plugins.iter().any(|plugin| {
+            plugin
+                .manifest
+                .commands
+                .iter()
+                .any(|command| command.name == name)
+        })
+    }
+
+    /// Runs the plugin command `name` with `args`, showing it `tasks`.
+    pub fn run_command(
+        &mut self,
+        name: &str,
+        args: &[String],
+        tasks: &[Task],
+    ) -> Result<CommandOutput, ToNotDoError> {
+        let plugin = self
+            .provider(|manifest| manifest.commands.iter().any(|command| command.name == name))
+            .ok_or_else(|| ToNotDoError::PluginError(format!("No plugin command {}", name)))?;
+
+        plugin.call(
+            "command",
+            Some(&json!({ "command": name, "args": args, "tasks": tasks })),
+        )
+    }
+
+    /// Whether the plugin filter `name` accepts `task`.
+    pub fn filter(&mut self, name: &str, task: &Task) -> Result<bool, ToNotDoError> {
+        let plugin = self
+            .provider(|manifest| manifest.filters.iter().any(|filter| filter == name))
+            .ok_or_else(|| ToNotDoError::PluginError(format!("No plugin filter {}", name)))?;
+
+        plugin.call("filter", Some(&json!({ "filter": name, "task": task })))
+    }
+
+    /// Tells every plugin that listens for `event` about it, returning the
+    /// plugins that failed.
+    pub fn dispatch(&mut self, event: Lifecycle, task: &Task) -> Vec<ToNotDoError> {
+        let name = match event {
+            Lifecycle::Add => "add",
+            Lifecycle::Done => "done",
+            Lifecycle::Delete => "delete",
+        };
+
+        let mut failures = Vec::new();
+        for plugin in &mut self.plugins {
+            if !plugin.manifest.events.iter().any(|event| event == name) {
+                continue;
+            }
+
+            let answer: Result<EventAnswer, _> =
+                plugin.call("on_event", Some(&json!({ "event": name, "task": task })));
+            match answer {
+                Ok(EventAnswer { error: Some(error) }) => failures.push(ToNotDoError::PluginError(
+                    format!("{}: {}", plugin.manifest.name, error),
+                )),
+                Ok(_) => {}
+                Err(e) => failures.push(e),
+            }
+        }
+        failures
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct EventAnswer {
+    error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// A plugin in WAT whose exports answer with fixed JSON.
+    fn fixed_plugin(manifest: &str, command: &str, filter: &str, on_event: &str) -> String {
+        let mut data = String::new();
+        let mut exports = String::new();
+        let mut offset = 0;
+        for (name, output) in [
+            ("manifest", manifest),
+            ("command", command),
+            ("filter", filter),
+            ("on_event", on_event),
+        ] {
+            data.push_str(&format!(
+                "(data (i32.const {}) \"{}\")\n",
+                offset,
+                output.replace('\\', "\\\\").replace('"', "\\\"")
+            ));
+            let packed = ((offset as i64) << 32) | output.len() as i64;
+            let params = if name == "manifest" {
+                ""
+            } else {
+                "(param i32 i32)"
+            };
+            exports.push_str(&format!(
+                "(func (export \"{}\") {} (result i64) i64.const {})\n",
+                name, params, packed
+            ));
+            offset += output.len();
+        }
+
+        format!(
+            "(module
+               (import \"to_not_do\" \"log\" (func $log (param i32 i32)))
+               (memory (export \"memory\") 1)
+               (func (export \"alloc\") (param i32) (result i32) i32.const 32768)
+               {}
+               {})",
+            data, exports
+        )
+    }
+
+    #[test]
+    fn test_plugin_host() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("greeter.wat"),
+            fixed_plugin(
+                r#"{"name":"greeter","commands":[{"name":"greet","about":"Say hi"}],"filters":["all"],"events":["add"]}"#,
+                r#"{"output":"hello from wasm","add":[{"description":"From plugin"}]}"#,
+                "true",
+                r#"{"error":"not today"}"#,
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("broken.wasm"), b"not wasm").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"ignored").unwrap();
+
+        let (mut host, failures) = PluginHost::load(dir.path());
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].to_string().contains("broken.wasm"));
+        assert_eq!(host.plugins().len(), 1);
+        assert_eq!(host.plugins()[0].manifest().name, "greeter");
+        assert!(host.has_command("greet"));
+
+        let task = Task::new("Anything");
+        let output = host
+            .run_command(
+                "greet",
+                &["--loud".to_string()],
+                std::slice::from_ref(&task),
+            )
+            .unwrap();
+        assert_eq!(output.output, "hello from wasm");
+        assert_eq!(output.add[0].description, "From plugin");
+        assert!(host.run_command("missing", &[], &[]).is_err());
+
+        assert!(host.filter("all", &task).unwrap());
+        assert!(host.filter("none", &task).is_err());
+
+        let failures = host.dispatch(Lifecycle::Add, &task);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].to_string().contains("not today"));
+        assert!(host.dispatch(Lifecycle::Done, &task).is_empty());
+    }
+
+    #[test]
+    fn test_runaway_plugin_is_stopped() {
+        let dir = tempdir().unwrap();
+        let manifest = r#"{"name":"spinner","commands":[{"name":"spin"}]}"#;
+        std::fs::write(
+            dir.path().join("spinner.wat"),
+            format!(
+                "(module
+                   (memory (export \"memory\") 1)
+                   (data (i32.const 0) \"{}\")
+                   (func (export \"alloc\") (param i32) (result i32) i32.const 1024)
+                   (func (export \"manifest\") (result i64) i64.const {})
+                   (func (export \"command\") (param i32 i32) (result i64)
+                     (loop $forever (br $forever))
+                     i64.const 0))",
+                manifest.replace('"', "\\\""),
+                manifest.len()
+            ),
+        )
+        .unwrap();
+
+        let (mut host, failures) = PluginHost::load(dir.path());
+        assert!(failures.is_empty());
+        assert!(host.run_command("spin", &[], &[]).is_err());
+    }
+}
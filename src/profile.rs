@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::ToNotDoError;
+
+pub const PROFILES_DIR_NAME: &str = "profiles";
+/// Name shown for the database kept directly in the data directory.
+pub const DEFAULT_PROFILE: &str = "default";
+
+pub fn profile_dir(root_dir: &Path, name: &str) -> PathBuf {
+    root_dir.join(PROFILES_DIR_NAME).join(name)
+}
+
+/// Profile names become directory names, so keep them to a safe alphabet.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid profile name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn profile_exists(root_dir: &Path, name: &str) -> bool {
+    name == DEFAULT_PROFILE || profile_dir(root_dir, name).is_dir()
+}
+
+/// Lists the default profile followed by every created profile, sorted.
+pub fn list_profiles(root_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(root_dir.join(PROFILES_DIR_NAME))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| validate_name(name).is_ok())
+        .collect();
+    names.sort();
+
+    std::iter::once(DEFAULT_PROFILE.to_string())
+        .chain(names)
+        .collect()
+}
+
+pub fn create_profile(root_dir: &Path, name: &str) -> Result<PathBuf, ToNotDoError> {
+    validate_name(name).map_err(ToNotDoError::ConfigError)?;
+
+    if profile_exists(root_dir, name) {
+        return Err(ToNotDoError::ConfigError(format!(
+            "Profile '{}' already exists",
+            name
+        )));
+    }
+
+    let dir = profile_dir(root_dir, name);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| ToNotDoError::ConfigError(format!("{}: {}", dir.display(), e)))?;
+
+    Ok(dir)
+}
+
+/// Records `name` as `default_profile` in the config file at `config_file`,
+/// keeping the rest of the file, comments included, as it was. Switching to
+/// the default profile removes the setting.
+pub fn set_default_profile(config_file: &Path, name: &str) -> Result<(), ToNotDoError> {
+    let config_error = |e: &dyn std::fmt::Display| {
+        ToNotDoError::ConfigError(format!("{}: {}", config_file.display(), e))
+    };
+
+    let content = match std::fs::read_to_string(config_file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(config_error(&e)),
+    };
+
+    let mut document: toml_edit::DocumentMut = content.parse().map_err(|e| config_error(&e))?;
+
+    if name == DEFAULT_PROFILE {
+        document.remove("default_profile");
+    } else {
+        document["default_profile"] = toml_edit::value(name);
+    }
+
+    std::fs::write(config_file, document.to_string()).map_err(|e| config_error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_create_and_list_profiles() {
+        let dir = tempdir().unwrap();
+
+        assert_eq!(list_profiles(dir.path()), vec![DEFAULT_PROFILE]);
+
+        create_profile(dir.path(), "work").unwrap();
+        create_profile(dir.path(), "home").unwrap();
+
+        assert_eq!(list_profiles(dir.path()), vec!["default", "home", "work"]);
+        assert!(create_profile(dir.path(), "work").is_err());
+        assert!(create_profile(dir.path(), DEFAULT_PROFILE).is_err());
+        assert!(create_profile(dir.path(), "../escape").is_err());
+    }
+
+    #[test]
+    fn test_set_default_profile_keeps_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "# my settings\n[list]\ncolumns = [\"id\"]\n").unwrap();
+
+        set_default_profile(&path, "work").unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("# my settings\n[list]"));
+        assert!(content.contains("default_profile = \"work\""));
+
+        set_default_profile(&path, DEFAULT_PROFILE).unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("default_profile"));
+    }
+}
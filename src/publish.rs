@@ -0,0 +1,202 @@
+//! A read-only board of the tasks as a single static HTML page, written by
+//! `publish` for putting on any web server or sending to people who do not
+//! use the tool themselves.
+//!
+//! The page has a column for each state and needs nothing besides itself:
+//! the styles are inline and there are no scripts. Tasks tagged
+//! [`PRIVATE_TAG`] can be left out, and archived tasks always are.
+
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{Task, TaskState},
+};
+
+/// Tag marking tasks left out with `--exclude-private`.
+pub const PRIVATE_TAG: &str = "private";
+
+/// File the page is written to in the output directory.
+pub const PAGE_NAME: &str = "index.html";
+
+const STYLE: &str = "\
+body { margin: 0; font-family: system-ui, sans-serif; background: #f6f6f4; color: #222; }
+header { padding: 1rem 1.5rem 0; }
+h1 { margin: 0 0 0.25rem; }
+header p { margin: 0; color: #777; font-size: 0.85rem; }
+main { display: flex; flex-wrap: wrap; gap: 1rem; padding: 1rem 1.5rem; }
+section { flex: 1; min-width: 16rem; }
+h2 { font-size: 1rem; color: #555; }
+ul { list-style: none; padding: 0; margin: 0; }
+li { background: #fff; border: 1px solid #ddd; border-radius: 4px; padding: 0.75rem; margin-bottom: 0.5rem; }
+li p { margin: 0.5rem 0 0; color: #555; font-size: 0.9rem; white-space: pre-wrap; }
+.details { margin-top: 0.25rem; color: #777; font-size: 0.8rem; }
+.overdue { color: #b00020; }
+.done .description { text-decoration: line-through; color: #777; }
+";
+
+fn publish_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::PublishError(reason.to_string())
+}
+
+/// `text` with the characters HTML gives meaning to escaped.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The tasks to publish: those not archived, in `project` when given, and
+/// without private ones unless `private` is set.
+pub fn select<'a>(tasks: &'a [Task], project: Option<&str>, private: bool) -> Vec<&'a Task> {
+    tasks
+        .iter()
+        .filter(|task| !task.is_archived())
+        .filter(|task| project.is_none_or(|project| task.project() == Some(project)))
+        .filter(|task| private || !task.tags().iter().any(|tag| tag == PRIVATE_TAG))
+        .collect()
+}
+
+fn card(task: &Task, today: NaiveDate) -> String {
+    let mut details = Vec::new();
+    if let Some(due) = task.due() {
+        let class = if task.is_overdue(today) {
+            " class=\"overdue\""
+        } else {
+            ""
+        };
+        details.push(format!("<span{}>Due {}</span>", class, due));
+    }
+    if let Some(priority) = task.priority() {
+        details.push(format!("{:?} priority", priority));
+    }
+    if let Some(project) = task.project() {
+        details.push(escape(project));
+    }
+    if !task.tags().is_empty() {
+        details.push(escape(
+            &task
+                .tags()
+                .iter()
+                .map(|tag| format!("#{}", tag))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ));
+    }
+    if let Some(completed) = task.completed_at() {
+        details.push(format!("Done {}", completed));
+    }
+
+    let mut card = format!(
+        "<li><span class=\"description\">{}</span>",
+        escape(task.description())
+    );
+    if !details.is_empty() {
+        card.push_str(&format!(
+            "<div class=\"details\">{}</div>",
+            details.join(" · ")
+        ));
+    }
+    if let Some(notes) = task.notes() {
+        card.push_str(&format!("<p>{}</p>", escape(notes)));
+    }
+    card.push_str("</li>\n");
+    card
+}
+
+/// The page showing `tasks` under `title`, as of `today`.
+pub fn render(title: &str, tasks: &[&Task], today: NaiveDate) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n\
+         <header>\n<h1>{title}</h1>\n<p>Updated {today}</p>\n</header>\n<main>\n",
+        title = escape(title),
+    );
+
+    for (state, heading, class) in [
+        (TaskState::Todo, "To do", "todo"),
+        (TaskState::InProgress, "In progress", "in-progress"),
+        (TaskState::Done, "Done", "done"),
+    ] {
+        let column: Vec<&&Task> = tasks.iter().filter(|task| task.state() == state).collect();
+        page.push_str(&format!(
+            "<section class=\"{}\">\n<h2>{} ({})</h2>\n<ul>\n",
+            class,
+            heading,
+            column.len()
+        ));
+        for task in column {
+            page.push_str(&card(task, today));
+        }
+        page.push_str("</ul>\n</section>\n");
+    }
+
+    page.push_str("</main>\n</body>\n</html>\n");
+    page
+}
+
+/// Writes `page` to [`PAGE_NAME`] in `dir`, creating the directory if
+/// needed, and returns where it went.
+pub fn write(dir: &Path, page: &str) -> Result<PathBuf, ToNotDoError> {
+    let path = dir.join(PAGE_NAME);
+    std::fs::create_dir_all(dir)
+        .and_then(|()| crate::storage::write_atomically(&path, page.as_bytes()))
+        .map_err(|e| publish_error(format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::Priority;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let tasks = vec![
+            Task::new("Fix <script> tags & quotes")
+                .with_due(NaiveDate::from_ymd_opt(2025, 3, 1).unwrap())
+                .with_priority(Priority::High)
+                .with_project("site"),
+            Task::new("Buy a gift")
+                .with_tags(&[PRIVATE_TAG.to_string()])
+                .with_project("site"),
+            Task::new("Old launch").with_project("site").with_archived(),
+            Task::new("Plan the launch")
+                .with_project("site")
+                .with_completed_at(today),
+            Task::new("Elsewhere").with_project("home"),
+        ];
+
+        let published = select(&tasks, Some("site"), false);
+        assert_eq!(published.len(), 2);
+        assert_eq!(select(&tasks, Some("site"), true).len(), 3);
+        assert_eq!(select(&tasks, None, true).len(), 4);
+
+        let page = render("Site & launch", &published, today);
+        assert!(page.contains("<title>Site &amp; launch</title>"));
+        assert!(page.contains("Fix &lt;script&gt; tags &amp; quotes"));
+        assert!(page.contains("<span class=\"overdue\">Due 2025-03-01</span> · High priority"));
+        assert!(page.contains("<h2>To do (1)</h2>"));
+        assert!(page.contains("<h2>Done (1)</h2>"));
+        assert!(!page.contains("Buy a gift"));
+        assert!(!page.contains("<script"));
+
+        let dir = tempdir().unwrap();
+        let path = write(&dir.path().join("out"), &page).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), page);
+    }
+}
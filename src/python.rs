@@ -0,0 +1,369 @@
+//! The `to_not_do` Python module, built from this crate with the `pyo3`
+//! feature, so the task history can be analyzed and scripted from Python
+//! without parsing JSON:
+//!
+//! ```python
+//! import pandas, to_not_do
+//!
+//! tasks = to_not_do.Repository("tasks.json")
+//! tasks.add("Water the plants", project="home")
+//! done = pandas.DataFrame(task.to_dict() for task in tasks.query(state="done"))
+//! ```
+//!
+//! [`Repository`] offers the operations of [`TaskRepository`] on a database
+//! file. Tasks come out as read-only [`PyTask`] objects, with dates as
+//! `datetime.date`, and states and priorities spelled as on the command
+//! line. Failures raise `to_not_do.Error`, or `ValueError` for bad
+//! arguments.
+//!
+//! Build the module with maturin, which picks up `pyproject.toml`.
+
+use std::{path::PathBuf, sync::Mutex};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use uuid::Uuid;
+
+use crate::{
+    config::StorageConfig,
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Priority, Task, TaskState},
+    filter::TaskFilter,
+    journal::Change,
+    repository::TaskRepository,
+    storage::open_storage,
+};
+
+create_exception!(
+    to_not_do,
+    Error,
+    PyException,
+    "Raised when the task store fails."
+);
+
+fn python_error(error: ToNotDoError) -> PyErr {
+    Error::new_err(error.to_string())
+}
+
+/// The name of a value as `--help` lists it.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn parse_value<T: ValueEnum>(value: &str) -> PyResult<T> {
+    T::from_str(value, true).map_err(PyValueError::new_err)
+}
+
+fn parse_id(id: &str) -> PyResult<Uuid> {
+    Uuid::parse_str(id).map_err(|e| PyValueError::new_err(format!("{}: {}", id, e)))
+}
+
+/// A task as Python sees it.
+#[pyclass(name = "Task", module = "to_not_do", frozen)]
+pub struct PyTask(Task);
+
+#[pymethods]
+impl PyTask {
+    #[getter]
+    fn id(&self) -> String {
+        self.0.id().to_string()
+    }
+
+    #[getter]
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+
+    #[getter]
+    fn notes(&self) -> Option<&str> {
+        self.0.notes()
+    }
+
+    #[getter]
+    fn state(&self) -> String {
+        value_name(self.0.state())
+    }
+
+    #[getter]
+    fn due(&self) -> Option<NaiveDate> {
+        self.0.due()
+    }
+
+    #[getter]
+    fn priority(&self) -> Option<String> {
+        self.0.priority().map(value_name)
+    }
+
+    #[getter]
+    fn tags(&self) -> Vec<String> {
+        self.0.tags().to_vec()
+    }
+
+    #[getter]
+    fn parent(&self) -> Option<String> {
+        self.0.parent().map(|parent| parent.to_string())
+    }
+
+    #[getter]
+    fn project(&self) -> Option<&str> {
+        self.0.project()
+    }
+
+    #[getter]
+    fn created_at(&self) -> NaiveDate {
+        self.0.created_at()
+    }
+
+    #[getter]
+    fn updated_at(&self) -> NaiveDate {
+        self.0.updated_at()
+    }
+
+    #[getter]
+    fn completed_at(&self) -> Option<NaiveDate> {
+        self.0.completed_at()
+    }
+
+    #[getter]
+    fn archived(&self) -> bool {
+        self.0.is_archived()
+    }
+
+    #[getter]
+    fn metadata(&self) -> std::collections::BTreeMap<String, String> {
+        self.0.metadata().clone()
+    }
+
+    /// What happened to the task, oldest first, as `(datetime, event)`
+    /// pairs.
+    #[getter]
+    fn history(&self) -> Vec<(DateTime<Utc>, String)> {
+        self.0
+            .history()
+            .iter()
+            .map(|entry| (entry.at, entry.event.clone()))
+            .collect()
+    }
+
+    /// The fields of the task, except its history, as a `dict`; a row for a
+    /// data frame.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("id", self.id())?;
+        dict.set_item("description", self.description())?;
+        dict.set_item("notes", self.notes())?;
+        dict.set_item("state", self.state())?;
+        dict.set_item("due", self.due())?;
+        dict.set_item("priority", self.priority())?;
+        dict.set_item("tags", self.tags())?;
+        dict.set_item("parent", self.parent())?;
+        dict.set_item("project", self.project())?;
+        dict.set_item("created_at", self.created_at())?;
+        dict.set_item("updated_at", self.updated_at())?;
+        dict.set_item("completed_at", self.completed_at())?;
+        dict.set_item("archived", self.archived())?;
+        dict.set_item("metadata", self.metadata())?;
+        Ok(dict)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Task {} {:?}>", self.0.short_id(), self.0.description())
+    }
+}
+
+/// The tasks in a database file.
+#[pyclass(name = "Repository", module = "to_not_do")]
+pub struct Repository {
+    manager: Mutex<DatabaseManager>,
+}
+
+impl Repository {
+    fn call<T>(
+        &self,
+        call: impl FnOnce(&mut DatabaseManager) -> Result<T, ToNotDoError>,
+    ) -> PyResult<T> {
+        let mut manager = self
+            .manager
+            .lock()
+            .map_err(|_| Error::new_err("The repository is poisoned"))?;
+        call(&mut manager).map_err(python_error)
+    }
+}
+
+#[pymethods]
+impl Repository {
+    /// Opens the database file at `path`, creating it when there is none
+    /// yet.
+    #[new]
+    fn new(path: PathBuf) -> PyResult<Self> {
+        let manager = DatabaseManager::with_storage(open_storage(&path, &StorageConfig::default()))
+            .map_err(python_error)?;
+        Ok(Self {
+            manager: Mutex::new(manager),
+        })
+    }
+
+    /// Adds a task and returns it.
+    #[pyo3(signature = (description, *, project=None, due=None, priority=None, tags=None, parent=None))]
+    fn add(
+        &self,
+        description: &str,
+        project: Option<&str>,
+        due: Option<NaiveDate>,
+        priority: Option<&str>,
+        tags: Option<Vec<String>>,
+        parent: Option<&str>,
+    ) -> PyResult<PyTask> {
+        if description.trim().is_empty() {
+            return Err(PyValueError::new_err("The description is empty"));
+        }
+        let mut task = Task::new(description).with_tags(&tags.unwrap_or_default());
+        if let Some(project) = project {
+            task = task.with_project(project);
+        }
+        if let Some(due) = due {
+            task = task.with_due(due);
+        }
+        if let Some(priority) = priority {
+            task = task.with_priority(parse_value::<Priority>(priority)?);
+        }
+        if let Some(parent) = parent {
+            task = task.with_parent(parse_id(parent)?);
+        }
+        self.call(|manager| manager.add(&task))?;
+        Ok(PyTask(task))
+    }
+
+    /// The task with ID `id`, or `None`.
+    fn get(&self, id: &str) -> PyResult<Option<PyTask>> {
+        let id = parse_id(id)?;
+        Ok(self.call(|manager| manager.get(id))?.map(PyTask))
+    }
+
+    /// The active tasks, or the archived ones, in the order they were
+    /// added; only those in `state` and `project` if given.
+    #[pyo3(signature = (*, state=None, project=None, archived=false))]
+    fn query(
+        &self,
+        state: Option<&str>,
+        project: Option<String>,
+        archived: bool,
+    ) -> PyResult<Vec<PyTask>> {
+        let filter = TaskFilter {
+            state: state.map(parse_value::<TaskState>).transpose()?,
+            project,
+            archived,
+        };
+        let tasks = self.call(|manager| manager.query(&filter))?;
+        Ok(tasks.into_iter().map(PyTask).collect())
+    }
+
+    /// Moves the task with ID `id` to `state`.
+    fn set_state(&self, id: &str, state: &str) -> PyResult<()> {
+        let id = parse_id(id)?;
+        let state = parse_value::<TaskState>(state)?;
+        self.call(|manager| manager.set_task_state(id, state))
+    }
+
+    fn complete(&self, id: &str) -> PyResult<()> {
+        self.set_state(id, "done")
+    }
+
+    fn delete(&self, id: &str) -> PyResult<()> {
+        let id = parse_id(id)?;
+        self.call(|manager| manager.delete(id))
+    }
+
+    /// How the tasks changed since the previous call, also by other
+    /// processes, as `(task, None)` for added or changed tasks and
+    /// `(None, id)` for removed ones. The first call reports every task.
+    fn watch(&self) -> PyResult<Vec<(Option<PyTask>, Option<String>)>> {
+        let changes = self.call(|manager| manager.watch())?;
+        Ok(changes
+            .into_iter()
+            .filter_map(|change| match change {
+                Change::PutTask { task } => Some((Some(PyTask(*task)), None)),
+                Change::RemoveTask { id } => Some((None, Some(id.to_string()))),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[pymodule]
+fn to_not_do(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Repository>()?;
+    module.add_class::<PyTask>()?;
+    module.add("Error", module.py().get_type::<Error>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyModule;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_module() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "to_not_do").unwrap();
+            to_not_do(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("to_not_do", module).unwrap();
+            globals.set_item("path", &path).unwrap();
+            py.run(
+                cr#"
+import datetime
+
+tasks = to_not_do.Repository(path)
+plants = tasks.add("Water the plants", project="home", due=datetime.date(2025, 3, 10))
+call = tasks.add("Call Sam", priority="high", tags=["phone"])
+tasks.complete(plants.id)
+
+done = tasks.query(state="done")
+assert [task.description for task in done] == ["Water the plants"]
+assert done[0].due == datetime.date(2025, 3, 10)
+assert done[0].completed_at is not None
+assert done[0].history == []
+row = tasks.get(call.id).to_dict()
+assert (row["priority"], row["tags"], row["project"]) == ("high", ["phone"], None)
+assert len(tasks.query(project="home")) == 1
+
+for bad in [lambda: tasks.add(" "), lambda: tasks.get("nope"), lambda: tasks.query(state="later")]:
+    try:
+        bad()
+        raise AssertionError("no error")
+    except ValueError:
+        pass
+
+tasks.delete(call.id)
+try:
+    tasks.delete(call.id)
+    raise AssertionError("no error")
+except to_not_do.Error:
+    pass
+
+changes = to_not_do.Repository(path).watch()
+assert [task.id for task, _ in changes if task] == [plants.id]
+"#,
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
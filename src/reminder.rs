@@ -0,0 +1,228 @@
+//! Reminders for tasks coming due, shown as desktop notifications by
+//! `notify-daemon`.
+
+use std::{collections::BTreeMap, path::Path};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Database, Task, TaskState},
+};
+
+/// Settings from the `[notify]` config section.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Time of day, on its due date, when a task is reminded of.
+    pub time: NaiveTime,
+    /// How long the snooze action puts a reminder off.
+    pub snooze_minutes: i64,
+    /// How often the daemon looks for reminders.
+    pub interval_seconds: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            time: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            snooze_minutes: 60,
+            interval_seconds: 60,
+        }
+    }
+}
+
+impl NotifyConfig {
+    pub fn snooze(&self) -> Duration {
+        Duration::minutes(self.snooze_minutes)
+    }
+}
+
+/// What the user asked for from a reminder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReminderAction {
+    Done,
+    Snooze,
+}
+
+/// Reminders already shown, kept in the state directory so a restarted
+/// daemon does not show them again.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReminderState {
+    reminded: BTreeMap<Uuid, Reminded>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Reminded {
+    /// The due date reminded of; a task moved to another date is reminded
+    /// of again.
+    due: NaiveDate,
+    snoozed_until: Option<NaiveDateTime>,
+}
+
+impl ReminderState {
+    pub fn read(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+
+    /// The tasks of `db` to remind of at `now`, which are then remembered
+    /// as reminded. Tasks done or gone are forgotten.
+    pub fn take_due(&mut self, db: &Database, now: NaiveDateTime, time: NaiveTime) -> Vec<Task> {
+        let pending: Vec<&Task> = db
+            .tasks()
+            .iter()
+            .filter(|task| task.state() != TaskState::Done && !task.is_archived())
+            .collect();
+        self.reminded
+            .retain(|id, _| pending.iter().any(|task| task.id() == *id));
+
+        let mut due = Vec::new();
+        for task in pending {
+            let Some(due_date) = task.due() else {
+                continue;
+            };
+            if due_date.and_time(time) > now {
+                continue;
+            }
+
+            let remind = match self.reminded.get(&task.id()) {
+                None => true,
+                Some(reminded) if reminded.due != due_date => true,
+                Some(reminded) => reminded.snoozed_until.is_some_and(|until| until <= now),
+            };
+            if remind {
+                self.reminded.insert(
+                    task.id(),
+                    Reminded {
+                        due: due_date,
+                        snoozed_until: None,
+                    },
+                );
+                due.push(task.clone());
+            }
+        }
+        due
+    }
+
+    /// Shows the reminder for task `id` again at `until`.
+    pub fn snooze(&mut self, id: Uuid, until: NaiveDateTime) {
+        if let Some(reminded) = self.reminded.get_mut(&id) {
+            reminded.snoozed_until = Some(until);
+        }
+    }
+}
+
+/// Shows a desktop notification for `task`. Where the desktop supports
+/// actions, it offers to mark the task done or snooze it, and sends the one
+/// picked on `actions`.
+#[cfg(feature = "notifications")]
+pub fn show(
+    task: &Task,
+    actions: std::sync::mpsc::Sender<(Uuid, ReminderAction)>,
+) -> Result<(), ToNotDoError> {
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname(crate::file_management::APP_NAME)
+        .summary(task.description());
+    if let Some(due) = task.due() {
+        notification.body(&format!("Due {}", due));
+    }
+
+    // Elsewhere, waiting for an action needs an event loop we do not run.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    notification
+        .action("done", "Mark done")
+        .action("snooze", "Snooze");
+
+    let handle = notification
+        .show()
+        .map_err(|e| ToNotDoError::NotificationError(e.to_string()))?;
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let id = task.id();
+        std::thread::spawn(move || {
+            handle.wait_for_action(|action| {
+                let action = match action {
+                    "done" => ReminderAction::Done,
+                    "snooze" => ReminderAction::Snooze,
+                    _ => return,
+                };
+                let _ = actions.send((id, action));
+            })
+        });
+    }
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let _ = (handle, actions);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(tasks: &[Task]) -> Database {
+        let mut db = Database::default();
+        for task in tasks {
+            db.put_task(task.clone());
+        }
+        db
+    }
+
+    fn at(date: NaiveDate, hour: u32) -> NaiveDateTime {
+        date.and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_take_due() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let tomorrow = today.succ_opt().unwrap();
+        let nine = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+        let due_today = Task::new("Due today").with_due(today);
+        let due_tomorrow = Task::new("Due tomorrow").with_due(tomorrow);
+        let done = Task::new("Already done")
+            .with_due(today)
+            .with_state(TaskState::Done);
+        let db = database(&[
+            due_today.clone(),
+            due_tomorrow.clone(),
+            done,
+            Task::new("No due date"),
+        ]);
+
+        let mut state = ReminderState::default();
+        assert!(state.take_due(&db, at(today, 8), nine).is_empty());
+
+        let due = state.take_due(&db, at(today, 9), nine);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id(), due_today.id());
+        assert!(state.take_due(&db, at(today, 10), nine).is_empty());
+
+        state.snooze(due_today.id(), at(today, 12));
+        assert!(state.take_due(&db, at(today, 11), nine).is_empty());
+        assert_eq!(state.take_due(&db, at(today, 12), nine).len(), 1);
+
+        let due = state.take_due(&db, at(tomorrow, 9), nine);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id(), due_tomorrow.id());
+
+        let moved = database(&[due_today.clone().with_due(tomorrow)]);
+        assert_eq!(state.take_due(&moved, at(tomorrow, 10), nine).len(), 1);
+        assert_eq!(state.reminded.len(), 1, "tasks gone are forgotten");
+    }
+}
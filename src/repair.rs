@@ -0,0 +1,277 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+use crate::{
+    compression::Compression,
+    encryption::KeyringKeySource,
+    error::{DatabaseError, ToNotDoError},
+    file_management::{AppPaths, Backup, Database, Task},
+    journal::{folding_path, Change, Event},
+    storage::Storage,
+};
+
+/// What [`repair`] managed to recover.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Tasks recovered from the damaged files.
+    pub salvaged: usize,
+    /// The backup the database was rebuilt from and how many tasks it held.
+    pub backup: Option<(PathBuf, usize)>,
+    /// Where the damaged files were moved.
+    pub quarantined: Vec<PathBuf>,
+    /// Tasks in the repaired database.
+    pub total: usize,
+}
+
+/// Finds every complete task object in `data`, even if the file around them
+/// is truncated or otherwise broken. Later copies of a task win.
+pub fn salvage_tasks(data: &[u8]) -> Vec<Task> {
+    let data = Compression::decompress(data).unwrap_or_else(|_| data.to_vec());
+    let mut tasks: Vec<Task> = Vec::new();
+    let mut start = 0;
+
+    while let Some(offset) = data[start..].iter().position(|&b| b == b'{') {
+        let from = start + offset;
+        let mut stream = serde_json::Deserializer::from_slice(&data[from..]).into_iter::<Task>();
+
+        match stream.next() {
+            Some(Ok(task)) => {
+                tasks.retain(|t| t.id() != task.id());
+                tasks.push(task);
+                start = from + stream.byte_offset();
+            }
+            _ => start = from + 1,
+        }
+    }
+
+    tasks
+}
+
+/// Returns the most recent backup in `dir`, going by the timestamped names
+/// [`Backup::file_name`] gives them.
+pub fn latest_backup(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .max_by_key(|path| path.file_name().map(|name| name.to_os_string()))
+}
+
+/// Moves a damaged file aside so it is kept for inspection but no longer
+/// read.
+pub fn quarantine(path: &Path) -> std::io::Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".corrupt-{}", Utc::now().format("%Y%m%d-%H%M%S")));
+
+    let target = path.with_file_name(name);
+    std::fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Whether `error` means the database files are damaged, rather than that
+/// they cannot be read here, such as for want of the key or because a newer
+/// version wrote them.
+fn is_damage(error: &ToNotDoError) -> bool {
+    matches!(
+        error,
+        ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(e))
+            if e.kind() == std::io::ErrorKind::InvalidData
+    )
+}
+
+/// Rebuilds a damaged database: tasks are salvaged from the database and
+/// the journal is replayed as far as it can be read, the damaged files are
+/// quarantined, and the result is laid over the latest backup. Returns
+/// `None` when `storage` loads fine, and the error when it cannot be loaded
+/// for another reason than damage.
+pub fn repair(
+    paths: &AppPaths,
+    storage: &mut dyn Storage,
+) -> Result<Option<RepairReport>, ToNotDoError> {
+    if !storage.exists() {
+        return Ok(None);
+    }
+    match storage.load() {
+        Ok(_) => return Ok(None),
+        Err(e) if !is_damage(&e) => return Err(e),
+        Err(_) => {}
+    }
+
+    let io_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+    let read = |path: &Path| std::fs::read(path).map_err(|e| ToNotDoError::DatabaseError(e.into()));
+    let mut report = RepairReport::default();
+
+    let mut db = match latest_backup(&paths.backup_dir()) {
+        Some(path) => {
+            let mut keys = KeyringKeySource::new(&paths.db_file);
+            let backup = Backup::read(&path, Some(&mut keys))?;
+            report.backup = Some((path, backup.database.tasks().len()));
+            backup.database
+        }
+        None => Database::default(),
+    };
+
+    if paths.db_file.is_file() {
+        for task in salvage_tasks(&read(&paths.db_file)?) {
+            db.put_task(task);
+            report.salvaged += 1;
+        }
+        report
+            .quarantined
+            .push(quarantine(&paths.db_file).map_err(io_error)?);
+    }
+
+    // Events are replayed in order so removals stay removed; tasks are
+    // salvaged from the lines that no longer parse.
+    let journal = paths.journal_file();
+    for path in [folding_path(&journal), journal] {
+        if !path.is_file() {
+            continue;
+        }
+
+        for line in read(&path)?.split(|&b| b == b'\n') {
+            match serde_json::from_slice::<Event>(line) {
+                Ok(event) => {
+                    if let Change::PutTask { .. } = event.change {
+                        report.salvaged += 1;
+                    }
+                    db.apply(event.change);
+                }
+                Err(_) => {
+                    for task in salvage_tasks(line) {
+                        db.put_task(task);
+                        report.salvaged += 1;
+                    }
+                }
+            }
+        }
+        report
+            .quarantined
+            .push(quarantine(&path).map_err(io_error)?);
+    }
+
+    report.total = db.tasks().len();
+    storage.save(&db)?;
+
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encryption::Passphrase,
+        file_management::{create_data_directory, DatabaseManager},
+        storage::{open_app_storage, FileStorage},
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_salvage_truncated_database() {
+        let first = Task::new("First").with_notes("Has {braces} in it");
+        let second = Task::new("Second");
+
+        let mut db = Database::default();
+        db.insert_task(first.clone()).unwrap();
+        db.insert_task(second).unwrap();
+        let json = serde_json::to_vec_pretty(&db).unwrap();
+
+        // Cut the file off in the middle of the second task.
+        let cut = json.len() - 60;
+        assert_eq!(salvage_tasks(&json[..cut]), vec![first]);
+        assert_eq!(salvage_tasks(&json).len(), 2);
+        assert!(salvage_tasks(b"not json at all").is_empty());
+    }
+
+    #[test]
+    fn test_repair_restores_backup_and_salvaged_tasks() {
+        let dir = tempdir().unwrap();
+        let paths = AppPaths::new(&create_data_directory(dir.path()));
+
+        let backed_up = Task::new("Backed up");
+        let mut db_manager = DatabaseManager::open(&paths.db_file).unwrap();
+        db_manager.add_task(&backed_up).unwrap();
+        db_manager
+            .backup(&paths.config_file, &paths.backup_dir(), None)
+            .unwrap();
+
+        let recent = Task::new("Added after the backup");
+        db_manager.add_task(&recent).unwrap();
+
+        let json = std::fs::read(&paths.db_file).unwrap();
+        std::fs::write(&paths.db_file, &json[..json.len() - 4]).unwrap();
+
+        let mut storage = FileStorage::new(&paths.db_file);
+        let report = repair(&paths, &mut storage).unwrap().unwrap();
+
+        assert_eq!(report.salvaged, 2);
+        assert_eq!(report.backup.map(|(_, count)| count), Some(1));
+        assert_eq!(report.total, 2);
+        assert!(report.quarantined[0].is_file());
+        assert_eq!(storage.load().unwrap().tasks(), &[backed_up, recent]);
+
+        assert!(repair(&paths, &mut storage).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_repair_replays_journal_removals() {
+        let dir = tempdir().unwrap();
+        let paths = AppPaths::new(&create_data_directory(dir.path()));
+        let config = crate::config::StorageConfig {
+            journal: true,
+            ..Default::default()
+        };
+
+        let kept = Task::new("Kept");
+        let removed = Task::new("Removed");
+        let mut db_manager =
+            DatabaseManager::with_storage(open_app_storage(&paths, &config)).unwrap();
+        db_manager.add_task(&kept).unwrap();
+        db_manager.add_task(&removed).unwrap();
+        db_manager.delete_task(removed.id()).unwrap();
+
+        let journal = paths.journal_file();
+        let mut data = std::fs::read(&journal).unwrap();
+        data.extend_from_slice(b"{\"at\": \"2024-01-01T00:00:00Z\", \"type\": oops}\n");
+        std::fs::write(&journal, data).unwrap();
+
+        let mut storage = open_app_storage(&paths, &config);
+        let report = repair(&paths, storage.as_mut()).unwrap().unwrap();
+        assert_eq!(report.total, 1);
+        assert_eq!(storage.load().unwrap().tasks(), &[kept]);
+    }
+
+    #[test]
+    fn test_repair_refuses_unreadable_but_healthy_database() {
+        let dir = tempdir().unwrap();
+        let paths = AppPaths::new(&create_data_directory(dir.path()));
+
+        // Encrypted with a key that is not at hand.
+        let mut storage =
+            FileStorage::new(&paths.db_file).with_encryption(true, Box::new(Passphrase("secret")));
+        storage.open().unwrap();
+        let encrypted = std::fs::read(&paths.db_file).unwrap();
+        let mut wrong =
+            FileStorage::new(&paths.db_file).with_encryption(true, Box::new(Passphrase("guess")));
+        assert!(matches!(
+            repair(&paths, &mut wrong),
+            Err(ToNotDoError::EncryptionError(_))
+        ));
+        assert_eq!(std::fs::read(&paths.db_file).unwrap(), encrypted);
+
+        // Written by a newer version.
+        let newer = format!(
+            r#"{{"name": "to-not-do", "version": "9.0.0", "schema_version": {}, "tasks": []}}"#,
+            crate::migration::SCHEMA_VERSION + 1
+        );
+        std::fs::write(&paths.db_file, &newer).unwrap();
+        assert!(matches!(
+            repair(&paths, &mut FileStorage::new(&paths.db_file)),
+            Err(ToNotDoError::DatabaseError(
+                DatabaseError::UnsupportedSchema { .. }
+            ))
+        ));
+        assert_eq!(std::fs::read_to_string(&paths.db_file).unwrap(), newer);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Duration, NaiveDate};
+
+use crate::file_management::{Task, TaskState};
+
+/// Label used for tasks that do not belong to any project.
+pub const NO_PROJECT: &str = "(no project)";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectProgress {
+    pub project: String,
+    pub done: usize,
+    pub total: usize,
+}
+
+impl ProjectProgress {
+    pub fn percent(&self) -> usize {
+        (self.done * 100)
+            .checked_div(self.total)
+            .unwrap_or_default()
+    }
+
+    /// Renders a fixed-width bar such as `[#####-----]`.
+    pub fn bar(&self, width: usize) -> String {
+        let filled = (self.done * width)
+            .checked_div(self.total)
+            .unwrap_or_default();
+
+        format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled))
+    }
+}
+
+/// Computes completion per project in a single pass over the tasks, sorted by
+/// project name. Tasks without a project are only counted when `include_none`
+/// is set.
+pub fn project_progress(tasks: &[Task], include_none: bool) -> Vec<ProjectProgress> {
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+
+    for task in tasks {
+        let project = match task.project() {
+            Some(project) => project,
+            None if include_none => NO_PROJECT,
+            None => continue,
+        };
+
+        let (done, total) = counts.entry(project).or_default();
+        *total += 1;
+        if task.state() == TaskState::Done {
+            *done += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|(project, (done, total))| ProjectProgress {
+            project: project.to_string(),
+            done,
+            total,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionSummary {
+    pub completed_today: usize,
+    /// Consecutive days, ending today or yesterday, with at least one
+    /// completed task.
+    pub streak: usize,
+    pub remaining: usize,
+}
+
+pub fn completion_summary(tasks: &[Task], today: NaiveDate) -> CompletionSummary {
+    let completions: Vec<NaiveDate> = tasks.iter().filter_map(|t| t.completed_at()).collect();
+    let remaining = tasks
+        .iter()
+        .filter(|t| t.state() != TaskState::Done && !t.is_archived())
+        .count();
+
+    summarize_completions(&completions, remaining, today)
+}
+
+fn summarize_completions(
+    completions: &[NaiveDate],
+    remaining: usize,
+    today: NaiveDate,
+) -> CompletionSummary {
+    let days: BTreeSet<NaiveDate> = completions.iter().copied().collect();
+
+    let mut day = if days.contains(&today) {
+        today
+    } else {
+        today - Duration::days(1)
+    };
+
+    let mut streak = 0;
+    while days.contains(&day) {
+        streak += 1;
+        day -= Duration::days(1);
+    }
+
+    CompletionSummary {
+        completed_today: completions.iter().filter(|&&d| d == today).count(),
+        streak,
+        remaining,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_progress() {
+        let tasks = vec![
+            Task::new("Write spec").with_project("work"),
+            Task::new("Ship it").with_project("work"),
+            Task::new("Water plants").with_project("home"),
+            Task::new("Loose end"),
+        ];
+
+        let progress = project_progress(&tasks, false);
+
+        assert_eq!(progress.len(), 2);
+        assert_eq!(progress[0].project, "home");
+        assert_eq!(progress[1].project, "work");
+        assert_eq!(progress[1].total, 2);
+        assert_eq!(progress[1].done, 0);
+
+        let progress = project_progress(&tasks, true);
+
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[0].project, NO_PROJECT);
+    }
+
+    #[test]
+    fn test_summarize_completions() {
+        let today = NaiveDate::from_ymd_opt(2030, 3, 10).unwrap();
+        let days_ago = |n| today - Duration::days(n);
+
+        let completions = [today, today, days_ago(1), days_ago(2), days_ago(4)];
+        let summary = summarize_completions(&completions, 7, today);
+
+        assert_eq!(
+            summary,
+            CompletionSummary {
+                completed_today: 2,
+                streak: 3,
+                remaining: 7,
+            }
+        );
+
+        let summary = summarize_completions(&[days_ago(1), days_ago(2)], 0, today);
+        assert_eq!(summary.completed_today, 0);
+        assert_eq!(summary.streak, 2);
+
+        assert_eq!(summarize_completions(&[days_ago(2)], 0, today).streak, 0);
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        let progress = ProjectProgress {
+            project: "work".to_string(),
+            done: 1,
+            total: 4,
+        };
+
+        assert_eq!(progress.percent(), 25);
+        assert_eq!(progress.bar(8), "[##------]");
+
+        let empty = ProjectProgress {
+            project: "empty".to_string(),
+            done: 0,
+            total: 0,
+        };
+
+        assert_eq!(empty.percent(), 0);
+        assert_eq!(empty.bar(4), "[----]");
+    }
+}
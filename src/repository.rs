@@ -0,0 +1,115 @@
+use uuid::Uuid;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task},
+    filter::TaskFilter,
+    journal::Change,
+};
+
+/// The stable integration point for programs embedding the task store.
+///
+/// [`DatabaseManager`] implements it on top of any [`Storage`], so the
+/// usual way to bring your own persistence is to implement
+/// [`Storage`] and keep the merging, history and retention logic of the
+/// manager. Implementing this trait directly replaces that logic too.
+///
+/// [`Storage`]: crate::storage::Storage
+pub trait TaskRepository {
+    /// Stores a new task. Fails if a task with the same ID exists.
+    fn add(&mut self, task: &Task) -> Result<(), ToNotDoError>;
+
+    fn get(&mut self, id: Uuid) -> Result<Option<Task>, ToNotDoError>;
+
+    /// The tasks matching `filter`, in the order they were added.
+    fn query(&mut self, filter: &TaskFilter) -> Result<Vec<Task>, ToNotDoError>;
+
+    /// Replaces the stored task with the same ID by `task`.
+    fn update(&mut self, task: &Task) -> Result<(), ToNotDoError>;
+
+    fn delete(&mut self, id: Uuid) -> Result<(), ToNotDoError>;
+
+    /// Returns how the tasks changed since the previous call, including
+    /// changes made by other processes. The first call reports every task.
+    /// Only [`Change::PutTask`] and [`Change::RemoveTask`] are returned.
+    fn watch(&mut self) -> Result<Vec<Change>, ToNotDoError>;
+}
+
+impl TaskRepository for DatabaseManager {
+    fn add(&mut self, task: &Task) -> Result<(), ToNotDoError> {
+        self.add_task(task)
+    }
+
+    fn get(&mut self, id: Uuid) -> Result<Option<Task>, ToNotDoError> {
+        Ok(self.database()?.task(id).cloned())
+    }
+
+    fn query(&mut self, filter: &TaskFilter) -> Result<Vec<Task>, ToNotDoError> {
+        Ok(filter.apply(self.database()?.tasks()))
+    }
+
+    fn update(&mut self, task: &Task) -> Result<(), ToNotDoError> {
+        self.update_task(task)
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), ToNotDoError> {
+        self.delete_task(id)
+    }
+
+    fn watch(&mut self) -> Result<Vec<Change>, ToNotDoError> {
+        DatabaseManager::watch(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::TaskState;
+    use tempfile::tempdir;
+
+    /// Uses nothing but the trait, as an embedding program would.
+    fn plan_the_week(repository: &mut impl TaskRepository) -> Uuid {
+        let task = Task::new("Plan the week").with_project("home");
+        repository.add(&task).unwrap();
+
+        let mut done = repository.get(task.id()).unwrap().unwrap();
+        done = done.with_completed_at(chrono::Utc::now().date_naive());
+        repository.update(&done).unwrap();
+
+        task.id()
+    }
+
+    #[test]
+    fn test_database_manager_as_repository() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let mut app = DatabaseManager::open(&db_file).unwrap();
+        let mut other_process = DatabaseManager::open(&db_file).unwrap();
+
+        assert!(app.watch().unwrap().is_empty());
+
+        let id = plan_the_week(&mut other_process);
+        let stored = app.get(id).unwrap().unwrap();
+        assert_eq!(stored.state(), TaskState::Done);
+        assert!(stored.completed_at().is_some());
+
+        let done = TaskFilter {
+            state: Some(TaskState::Done),
+            ..TaskFilter::default()
+        };
+        assert_eq!(app.query(&done).unwrap(), std::slice::from_ref(&stored));
+        assert!(matches!(
+            app.watch().unwrap().as_slice(),
+            [Change::PutTask { task }] if task.id() == id
+        ));
+        assert!(app.watch().unwrap().is_empty());
+
+        other_process.delete(id).unwrap();
+        assert!(matches!(
+            app.watch().unwrap().as_slice(),
+            [Change::RemoveTask { id: removed }] if *removed == id
+        ));
+        assert_eq!(app.get(id).unwrap(), None);
+        assert!(app.update(&stored).is_err());
+    }
+}
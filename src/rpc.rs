@@ -0,0 +1,276 @@
+//! JSON-RPC 2.0 over stdin and stdout, for editors and other long-lived
+//! processes that keep `to-not-do rpc` running as a child process.
+//!
+//! Every request and response is one line of JSON. The methods mirror the
+//! REST API of `serve`:
+//!
+//! - `add`: the fields of a new task, as for `POST /api/tasks`; returns the task
+//! - `get`: `{"id"}`; returns the task
+//! - `query`: a [`TaskFilter`]; returns the matching tasks
+//! - `update`: `{"id", ...}` with the fields to change; returns the task
+//! - `delete`: `{"id"}`; returns null
+//! - `watch`: returns how the tasks changed since the previous `watch`
+
+use std::io::{BufRead, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::DatabaseManager,
+    filter::TaskFilter,
+    repository::TaskRepository,
+    serve::{stored_task, NewTask, TaskChanges},
+};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Application errors, in the range JSON-RPC leaves to servers.
+pub const TASK_NOT_FOUND: i64 = -32001;
+pub const DATABASE_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Absent for notifications, which get no response.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// The error a method answers with.
+#[derive(Debug, Serialize)]
+pub struct Error {
+    pub code: i64,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ToNotDoError> for Error {
+    fn from(e: ToNotDoError) -> Self {
+        match e {
+            ToNotDoError::DatabaseError(DatabaseError::TaskNotFound(_)) => {
+                Error::new(TASK_NOT_FOUND, e)
+            }
+            _ => Error::new(DATABASE_ERROR, e),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskId {
+    id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    id: Uuid,
+    #[serde(flatten)]
+    changes: TaskChanges,
+}
+
+/// Answers requests read from `input` on `output` until `input` ends.
+pub fn run(
+    db_manager: &mut DatabaseManager,
+    input: impl BufRead,
+    output: impl Write,
+) -> std::io::Result<()> {
+    serve(input, output, |method, params| {
+        call(db_manager, method, params)
+    })
+}
+
+/// Reads requests from `input` until it ends, answering each on `output`
+/// with what `handle` returns for its method and parameters.
+pub fn serve(
+    input: impl BufRead,
+    mut output: impl Write,
+    mut handle: impl FnMut(&str, Value) -> Result<Value, Error>,
+) -> std::io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(response) = respond(&mut handle, &line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// The response to one line of input, or `None` for a notification.
+fn respond(
+    handle: &mut impl FnMut(&str, Value) -> Result<Value, Error>,
+    line: &str,
+) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Some(response(Value::Null, Err(Error::new(PARSE_ERROR, e)))),
+    };
+    let request: Request = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(e) => return Some(response(Value::Null, Err(Error::new(INVALID_REQUEST, e)))),
+    };
+
+    let result = if request.jsonrpc == "2.0" {
+        handle(&request.method, request.params)
+    } else {
+        Err(Error::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))
+    };
+    request.id.map(|id| response(id, result))
+}
+
+fn response(id: Value, result: Result<Value, Error>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+pub(crate) fn call(
+    db_manager: &mut DatabaseManager,
+    method: &str,
+    params: Value,
+) -> Result<Value, Error> {
+    match method {
+        "add" => {
+            let task = params_as::<NewTask>(params)?.into_task();
+            db_manager.add(&task)?;
+            to_value(stored_task(db_manager, task.id())?)
+        }
+        "get" => {
+            let TaskId { id } = params_as(params)?;
+            to_value(stored_task(db_manager, id)?)
+        }
+        "query" => {
+            let filter: TaskFilter = if params.is_null() {
+                TaskFilter::default()
+            } else {
+                params_as(params)?
+            };
+            to_value(db_manager.query(&filter)?)
+        }
+        "update" => {
+            let Update { id, changes } = params_as(params)?;
+            to_value(changes.apply(db_manager, id)?)
+        }
+        "delete" => {
+            let TaskId { id } = params_as(params)?;
+            db_manager.delete(id)?;
+            Ok(Value::Null)
+        }
+        "watch" => to_value(TaskRepository::watch(db_manager)?),
+        _ => Err(Error::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}", method),
+        )),
+    }
+}
+
+pub fn params_as<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    serde_json::from_value(params).map_err(|e| Error::new(INVALID_PARAMS, e))
+}
+
+pub fn to_value(value: impl Serialize) -> Result<Value, Error> {
+    serde_json::to_value(value).map_err(|e| Error::new(DATABASE_ERROR, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let input: String = requests
+            .iter()
+            .map(|request| format!("{}\n", request))
+            .collect();
+        let mut output = Vec::new();
+        run(&mut db_manager, input.as_bytes(), &mut output).unwrap();
+
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rpc_session() {
+        let id = Uuid::new_v4();
+        let responses = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "watch" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "add", "params": { "description": "Call the plumber" } }),
+            json!({ "jsonrpc": "2.0", "method": "query" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "query", "params": { "state": "Done" } }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "get", "params": { "id": id } }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "teleport" }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "get", "params": { "name": "x" } }),
+        ]);
+
+        assert_eq!(responses.len(), 6, "notifications get no response");
+        assert_eq!(responses[0]["result"], json!([]));
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["result"]["description"], "Call the plumber");
+        assert_eq!(responses[2]["result"], json!([]));
+        assert_eq!(responses[3]["error"]["code"], TASK_NOT_FOUND);
+        assert_eq!(responses[4]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[5]["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_rpc_update_and_delete() {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let mut handle = |method: &str, params| call(&mut db_manager, method, params);
+        let mut call = |request: Value| respond(&mut handle, &request.to_string()).unwrap();
+
+        let added = call(
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "add", "params": { "description": "Pay rent" } }),
+        );
+        let id = added["result"]["id"].clone();
+
+        let updated = call(
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "update", "params": { "id": id, "state": "Done", "project": "home" } }),
+        );
+        assert_eq!(updated["result"]["state"], "Done");
+        assert_eq!(updated["result"]["project"], "home");
+
+        let watched = call(json!({ "jsonrpc": "2.0", "id": 3, "method": "watch" }));
+        assert_eq!(watched["result"][0]["task"]["id"], id);
+
+        let deleted =
+            call(json!({ "jsonrpc": "2.0", "id": 4, "method": "delete", "params": { "id": id } }));
+        assert_eq!(deleted["result"], Value::Null);
+        let watched = call(json!({ "jsonrpc": "2.0", "id": 5, "method": "watch" }));
+        assert_eq!(
+            watched["result"],
+            json!([{ "type": "remove_task", "id": id }])
+        );
+
+        assert_eq!(
+            call(json!("not a request"))["error"]["code"],
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            respond(&mut handle, "{").unwrap()["error"]["code"],
+            PARSE_ERROR
+        );
+    }
+}
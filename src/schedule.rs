@@ -0,0 +1,122 @@
+//! Upkeep that depends on the time rather than on a command: `tick` runs it
+//! once, for cron and the like, and `daemon` runs it on an interval.
+
+use std::path::Path;
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::{
+    compact::PurgeReport, config::Config, error::ToNotDoError, file_management::DatabaseManager,
+    webhook,
+};
+
+/// What one tick did.
+#[derive(Debug, Default)]
+pub struct TickReport {
+    /// What the retention policies removed.
+    pub purged: PurgeReport,
+    /// Tasks reported to the webhooks as newly overdue.
+    pub overdue: usize,
+    /// Daily summaries sent to the webhooks.
+    pub summaries: usize,
+    /// Webhook deliveries that failed.
+    pub failures: Vec<ToNotDoError>,
+}
+
+/// Applies the retention policies from the `[compact]` section, retries the
+/// webhook deliveries that failed before, reports the tasks that fell
+/// overdue by `now` to the webhooks and sends the daily summaries that are
+/// due. What was sent and what failed is remembered in `webhook_state`, so
+/// each task is reported once and each summary sent once a day.
+pub fn tick(
+    db_manager: &mut DatabaseManager,
+    config: &Config,
+    now: NaiveDateTime,
+    webhook_state: &Path,
+) -> Result<TickReport, ToNotDoError> {
+    let purged = db_manager.purge_expired(&config.compact)?;
+
+    let mut report = TickReport {
+        purged,
+        ..TickReport::default()
+    };
+    if !config.webhooks.is_empty() {
+        report.failures = webhook::retry_pending(&config.webhooks, Utc::now(), webhook_state)?;
+
+        let db = db_manager.database()?;
+        let payloads = webhook::newly_overdue(db, now.date(), webhook_state)?;
+        report.overdue = payloads.len();
+        report
+            .failures
+            .extend(webhook::notify(&config.webhooks, &payloads, webhook_state));
+
+        let summaries = webhook::due_summaries(&config.webhooks, db, now, webhook_state)?;
+        report.summaries = summaries.len();
+        report
+            .failures
+            .extend(webhook::send(&summaries, webhook_state));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::CompactConfig,
+        file_management::{Task, TaskState},
+        storage::MemoryStorage,
+        webhook::{WebhookConfig, WebhookEvent},
+    };
+    use chrono::{Duration, NaiveDate};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tick() {
+        let dir = tempdir().unwrap();
+        let state = dir.path().join("webhooks.json");
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let now = today.and_hms_opt(12, 0, 0).unwrap();
+
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let old = Task::new("Long gone");
+        db_manager.add_task(&old).unwrap();
+        db_manager.delete_task(old.id()).unwrap();
+        db_manager
+            .add_task(&Task::new("Late").with_due(today - Duration::days(1)))
+            .unwrap();
+        db_manager
+            .add_task(
+                &Task::new("Late but done")
+                    .with_due(today - Duration::days(1))
+                    .with_state(TaskState::Done),
+            )
+            .unwrap();
+
+        let mut config = Config {
+            compact: CompactConfig {
+                deleted_days: 0,
+                ..CompactConfig::default()
+            },
+            ..Config::default()
+        };
+        let report = tick(&mut db_manager, &config, now, &state).unwrap();
+        assert_eq!(report.purged.deleted, 1);
+        assert_eq!(report.overdue, 0, "no webhooks, nothing to report");
+
+        // Nothing listens, so deliveries fail and are not queued.
+        config.webhooks = vec![WebhookConfig {
+            events: vec![WebhookEvent::Overdue, WebhookEvent::Summary],
+            retries: 0,
+            ..WebhookConfig::new("http://127.0.0.1:9/")
+        }];
+        let report = tick(&mut db_manager, &config, now, &state).unwrap();
+        assert_eq!(report.overdue, 1);
+        assert_eq!(report.summaries, 1);
+        assert_eq!(report.failures.len(), 2);
+
+        let report = tick(&mut db_manager, &config, now, &state).unwrap();
+        assert_eq!(report.overdue, 0, "each task is reported once");
+        assert_eq!(report.summaries, 0, "one summary a day");
+    }
+}
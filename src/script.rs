@@ -0,0 +1,241 @@
+//! Lua scripts, for automation too small to be worth a plugin: `script run
+//! <file>`, or the commands defined in `[scripts.<name>]` config sections.
+//!
+//! Scripts get Lua's `string`, `table`, `math` and `utf8` libraries but no
+//! access to files, processes or the network, and are stopped when they run
+//! or allocate too much. Their command line arguments are in `arg`, and the
+//! tasks are reached through the `tasks` table:
+//!
+//! - `tasks.list([filter])`: the tasks matching a filter such as
+//!   `{state = "Todo", project = "home"}`, all unarchived ones without it
+//! - `tasks.get(id)`: the task, or nil
+//! - `tasks.add(fields)`: adds a task, e.g. `{description = "Call Sam"}`, and
+//!   returns it
+//! - `tasks.update(id, changes)`: changes the given fields and returns the task
+//! - `tasks.done(id)`: marks a task done and returns it
+//! - `tasks.delete(id)`
+//!
+//! Tasks are tables with the fields of the JSON export.
+
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use mlua::{HookTriggers, Lua, LuaOptions, LuaSerdeExt, SerializeOptions, StdLib, Value};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, TaskState},
+    filter::TaskFilter,
+    repository::TaskRepository,
+    serve::{stored_task, NewTask, TaskChanges},
+};
+
+/// Memory a script may allocate.
+const MEMORY_LIMIT: usize = 64 << 20;
+
+/// Instructions a script may run before it is stopped.
+const INSTRUCTION_LIMIT: u64 = 100_000_000;
+
+/// Instructions run between checks against [`INSTRUCTION_LIMIT`].
+const INSTRUCTION_CHECK: u32 = 10_000;
+
+/// Runs the Lua `source` named `name` with `args` as its `arg` table.
+pub fn run(
+    db_manager: &mut DatabaseManager,
+    name: &str,
+    source: &str,
+    args: &[String],
+) -> Result<(), ToNotDoError> {
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8,
+        LuaOptions::default(),
+    )
+    .map_err(script_error)?;
+    lua.set_memory_limit(MEMORY_LIMIT).map_err(script_error)?;
+    let executed = AtomicU64::new(0);
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(INSTRUCTION_CHECK),
+        move |_, _| {
+            let total = executed.fetch_add(INSTRUCTION_CHECK.into(), Ordering::Relaxed);
+            if total >= INSTRUCTION_LIMIT {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "stopped after {} instructions",
+                    INSTRUCTION_LIMIT
+                )));
+            }
+            Ok(())
+        },
+    );
+
+    let db_manager = RefCell::new(db_manager);
+    lua.scope(|scope| {
+        let globals = lua.globals();
+        // The base library can still read files.
+        globals.set("dofile", Value::Nil)?;
+        globals.set("loadfile", Value::Nil)?;
+        globals.set("arg", args)?;
+
+        let tasks = lua.create_table()?;
+        tasks.set(
+            "list",
+            scope.create_function(|lua, filter: Option<Value>| {
+                let filter: TaskFilter = match filter {
+                    Some(filter) => lua.from_value(filter)?,
+                    None => TaskFilter::default(),
+                };
+                let tasks = db_manager
+                    .borrow_mut()
+                    .query(&filter)
+                    .map_err(mlua::Error::runtime)?;
+                to_lua(lua, &tasks)
+            })?,
+        )?;
+        tasks.set(
+            "get",
+            scope.create_function(|lua, id: String| {
+                let task = db_manager
+                    .borrow_mut()
+                    .get(task_id(&id)?)
+                    .map_err(mlua::Error::runtime)?;
+                to_lua(lua, &task)
+            })?,
+        )?;
+        tasks.set(
+            "add",
+            scope.create_function(|lua, fields: Value| {
+                let task = lua.from_value::<NewTask>(fields)?.into_task();
+                let mut db_manager = db_manager.borrow_mut();
+                let task = db_manager
+                    .add(&task)
+                    .and_then(|()| stored_task(&mut db_manager, task.id()))
+                    .map_err(mlua::Error::runtime)?;
+                to_lua(lua, &task)
+            })?,
+        )?;
+        tasks.set(
+            "update",
+            scope.create_function(|lua, (id, changes): (String, Value)| {
+                let changes: TaskChanges = lua.from_value(changes)?;
+                let task = changes
+                    .apply(&mut db_manager.borrow_mut(), task_id(&id)?)
+                    .map_err(mlua::Error::runtime)?;
+                to_lua(lua, &task)
+            })?,
+        )?;
+        tasks.set(
+            "done",
+            scope.create_function(|lua, id: String| {
+                let changes = TaskChanges {
+                    state: Some(TaskState::Done),
+                    ..TaskChanges::default()
+                };
+                let task = changes
+                    .apply(&mut db_manager.borrow_mut(), task_id(&id)?)
+                    .map_err(mlua::Error::runtime)?;
+                to_lua(lua, &task)
+            })?,
+        )?;
+        tasks.set(
+            "delete",
+            scope.create_function(|_, id: String| {
+                db_manager
+                    .borrow_mut()
+                    .delete(task_id(&id)?)
+                    .map_err(mlua::Error::runtime)
+            })?,
+        )?;
+        globals.set("tasks", tasks)?;
+
+        lua.load(source).set_name(name).exec()
+    })
+    .map_err(script_error)
+}
+
+fn task_id(id: &str) -> mlua::Result<Uuid> {
+    Uuid::parse_str(id).map_err(|_| mlua::Error::runtime(format!("Invalid task ID {}", id)))
+}
+
+/// Converts `value` to Lua, leaving out the fields that are unset rather than
+/// setting them to a null that Lua would treat as true.
+fn to_lua<'lua>(lua: &'lua Lua, value: &impl Serialize) -> mlua::Result<Value<'lua>> {
+    lua.to_value_with(
+        value,
+        SerializeOptions::new()
+            .serialize_none_to_null(false)
+            .serialize_unit_to_null(false),
+    )
+}
+
+fn script_error(e: mlua::Error) -> ToNotDoError {
+    ToNotDoError::ScriptError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{file_management::Task, storage::MemoryStorage};
+
+    fn manager(tasks: &[Task]) -> DatabaseManager {
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        for task in tasks {
+            db_manager.add_task(task).unwrap();
+        }
+        db_manager
+    }
+
+    #[test]
+    fn test_script_api() {
+        let rent = Task::new("Pay rent").with_project("home");
+        let mut db_manager = manager(&[rent.clone(), Task::new("Write report")]);
+
+        let source = r#"
+            local home = tasks.list({project = "home"})
+            assert(#home == 1 and home[1].description == "Pay rent")
+            assert(home[1].due == nil, "unset fields are nil")
+
+            tasks.done(home[1].id)
+            local added = tasks.add({description = "Call " .. arg[1], tags = {"phone"}})
+            tasks.update(added.id, {project = "work"})
+
+            for _, task in ipairs(tasks.list()) do
+                if task.description == "Write report" then
+                    tasks.delete(task.id)
+                end
+            end
+            assert(tasks.get("9bd4dfa0-7c7b-4b5e-a1b5-6c1c1f5bdf10") == nil)
+        "#;
+        run(&mut db_manager, "test", source, &["Sam".to_string()]).unwrap();
+
+        let tasks = db_manager.get_tasks().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].id(), rent.id());
+        assert_eq!(tasks[0].state(), TaskState::Done);
+        assert_eq!(tasks[1].description(), "Call Sam");
+        assert_eq!(tasks[1].tags(), ["phone"]);
+        assert_eq!(tasks[1].project(), Some("work"));
+    }
+
+    #[test]
+    fn test_script_sandbox() {
+        let mut db_manager = manager(&[]);
+
+        for source in [
+            "io.open('/etc/passwd')",
+            "os.execute('true')",
+            "dofile('/etc/passwd')",
+            "require('os')",
+        ] {
+            assert!(run(&mut db_manager, "test", source, &[]).is_err());
+        }
+
+        let e = run(&mut db_manager, "forever.lua", "while true do end", &[]).unwrap_err();
+        assert!(e.to_string().contains("stopped after"));
+
+        let e = run(&mut db_manager, "broken.lua", "tasks.done('nope')", &[]).unwrap_err();
+        assert!(e.to_string().contains("Invalid task ID nope"));
+    }
+}
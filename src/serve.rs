@@ -0,0 +1,897 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    config::StorageConfig,
+    error::{DatabaseError, ToNotDoError},
+    feed::{self, ATOM_ENDPOINT, FEED_ENDPOINT},
+    file_management::{DatabaseManager, Priority, Task, TaskState},
+    filter::TaskFilter,
+    journal::{journal_path, Event},
+    metrics::{Metrics, Operation, METRICS_ENDPOINT},
+    repository::TaskRepository,
+    storage::{open_storage_with_journal, MemoryStorage, Storage},
+    sync::{self, PullQuery, PullResponse, PushRequest, PushResponse, OPS_ENDPOINT},
+    web,
+};
+
+/// Where the REST API lists and creates tasks; each task is at
+/// `TASKS_ENDPOINT/<id>`.
+pub const TASKS_ENDPOINT: &str = "/api/tasks";
+
+/// Whether `given` is `expected`, taking as long wherever they first
+/// differ, so timing the answer does not give the token away byte by byte.
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+/// What the server needs to answer requests against one database.
+pub struct ServerState {
+    pub db_file: PathBuf,
+    /// Journal of the database; next to it unless set with
+    /// [`ServerState::with_journal`].
+    pub journal_file: PathBuf,
+    pub storage: StorageConfig,
+    /// Bearer token clients must present; `None` lets anyone in.
+    pub token: Option<String>,
+    /// Secret that opens the feeds and the name they give; `None` leaves
+    /// them off.
+    pub feed: Option<(String, String)>,
+    /// Counters for [`METRICS_ENDPOINT`].
+    pub metrics: Metrics,
+    /// Serializes requests that touch the database files.
+    lock: Mutex<()>,
+}
+
+impl ServerState {
+    pub fn new(db_file: PathBuf, storage: StorageConfig, token: Option<String>) -> Self {
+        Self {
+            journal_file: journal_path(&db_file),
+            db_file,
+            storage,
+            token,
+            feed: None,
+            metrics: Metrics::default(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Keeps the journal of the database at `path`.
+    pub fn with_journal(mut self, path: PathBuf) -> Self {
+        self.journal_file = path;
+        self
+    }
+
+    /// Offers the feeds to clients presenting `token`, under the name
+    /// `name`.
+    pub fn with_feed(mut self, token: String, name: String) -> Self {
+        self.feed = Some((token, name));
+        self
+    }
+
+    /// Whether a client presenting `authorization`, the value of its
+    /// `Authorization` header, may use the server.
+    pub fn accepts(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| tokens_match(token, given))
+    }
+
+    /// Whether a request for `host`, the value of its `Host` header, may be
+    /// answered. Without a token, a web page could have the browser send
+    /// requests under a host name it rebinds to this server, so only IP
+    /// addresses and `localhost` are answered then.
+    pub fn accepts_host(&self, host: Option<&str>) -> bool {
+        let Some(host) = host.filter(|_| self.token.is_none()) else {
+            return true;
+        };
+
+        let name = match host.strip_prefix('[') {
+            Some(address) => address.split(']').next().unwrap_or_default(),
+            None => host.split(':').next().unwrap_or_default(),
+        };
+        name.eq_ignore_ascii_case("localhost") || name.parse::<std::net::IpAddr>().is_ok()
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+        let host = headers
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok());
+        if !self.accepts_host(host) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Unexpected Host; use the server's address or set a token".to_string(),
+            ));
+        }
+
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+
+        if self.accepts(authorization) {
+            Ok(())
+        } else {
+            Err((
+                StatusCode::UNAUTHORIZED,
+                "Invalid or missing token".to_string(),
+            ))
+        }
+    }
+
+    /// The storage of the database, as configured.
+    fn open_storage(&self) -> Box<dyn Storage> {
+        open_storage_with_journal(&self.db_file, &self.journal_file, &self.storage)
+    }
+
+    /// Runs `read` on the database as it is now.
+    pub fn read_tasks<T>(
+        &self,
+        read: impl FnOnce(&mut DatabaseManager) -> Result<T, ToNotDoError>,
+    ) -> Result<T, ToNotDoError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut manager = DatabaseManager::with_storage(self.open_storage())?;
+        read(&mut manager)
+    }
+
+    /// Runs `edit` on a copy of the database and saves what it changed as
+    /// sync events, so replicas syncing with this server also get the edits
+    /// made through its APIs.
+    pub fn edit_tasks<T>(
+        &self,
+        edit: impl FnOnce(&mut DatabaseManager) -> Result<T, ToNotDoError>,
+    ) -> Result<T, ToNotDoError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut storage = self.open_storage();
+
+        let before = storage.open()?;
+        let mut copy =
+            DatabaseManager::with_storage(Box::new(MemoryStorage::with_database(before.clone())))?;
+        let result = edit(&mut copy)?;
+
+        let at = Utc::now();
+        let events = before
+            .changes_to(copy.database()?)
+            .into_iter()
+            .map(|change| Event { at, change })
+            .collect();
+        sync::push(storage.as_mut(), &sync::ops_log_path(&self.db_file), events)?;
+        Ok(result)
+    }
+}
+
+pub fn router(state: Arc<ServerState>) -> Router {
+    let router = Router::new()
+        .route(OPS_ENDPOINT, get(pull_ops).post(push_ops))
+        .route(METRICS_ENDPOINT, get(metrics))
+        .route(FEED_ENDPOINT, get(feed))
+        .route(ATOM_ENDPOINT, get(atom_feed))
+        .route(TASKS_ENDPOINT, get(list_tasks).post(create_task))
+        .route(
+            &format!("{}/{{id}}", TASKS_ENDPOINT),
+            get(get_task).patch(update_task).delete(delete_task),
+        )
+        .fallback(get(web::asset));
+
+    #[cfg(feature = "grpc")]
+    let router = crate::grpc::add_service(router, Arc::clone(&state));
+
+    router.with_state(state)
+}
+
+/// Serves requests on `listener` until the process is stopped.
+pub fn serve(listener: std::net::TcpListener, state: ServerState) -> Result<(), ToNotDoError> {
+    let server_error = |e: std::io::Error| ToNotDoError::SyncError(e.to_string());
+
+    listener.set_nonblocking(true).map_err(server_error)?;
+
+    tokio::runtime::Runtime::new()
+        .map_err(server_error)?
+        .block_on(async {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            axum::serve(listener, router(Arc::new(state))).await
+        })
+        .map_err(server_error)
+}
+
+fn internal_error(e: ToNotDoError) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// A request that is well-formed but cannot be carried out.
+fn invalid_request(reason: String) -> (StatusCode, String) {
+    (StatusCode::UNPROCESSABLE_ENTITY, reason)
+}
+
+fn task_error(e: ToNotDoError) -> (StatusCode, String) {
+    let status = match &e {
+        ToNotDoError::DatabaseError(DatabaseError::TaskNotFound(_)) => StatusCode::NOT_FOUND,
+        ToNotDoError::DatabaseError(DatabaseError::UuidAlreadyExists(_)) => StatusCode::CONFLICT,
+        ToNotDoError::DatabaseError(DatabaseError::ReadOnly(_)) => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string())
+}
+
+/// Body of a request creating a task; only the description is required.
+#[derive(Debug, Deserialize)]
+pub struct NewTask {
+    pub description: String,
+    pub notes: Option<String>,
+    pub due: Option<NaiveDate>,
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub parent: Option<Uuid>,
+    pub project: Option<String>,
+}
+
+impl NewTask {
+    /// Why the task cannot be created, if it cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.description.trim().is_empty() {
+            return Err("The description must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn into_task(self) -> Task {
+        let mut task = Task::new(&self.description).with_tags(&self.tags);
+        if let Some(notes) = &self.notes {
+            task = task.with_notes(notes);
+        }
+        if let Some(due) = self.due {
+            task = task.with_due(due);
+        }
+        if let Some(priority) = self.priority {
+            task = task.with_priority(priority);
+        }
+        if let Some(parent) = self.parent {
+            task = task.with_parent(parent);
+        }
+        if let Some(project) = &self.project {
+            task = task.with_project(project);
+        }
+        task
+    }
+}
+
+/// Body of a request changing a task; fields left out keep their value.
+#[derive(Debug, Default, Deserialize)]
+pub struct TaskChanges {
+    pub description: Option<String>,
+    pub notes: Option<String>,
+    pub state: Option<TaskState>,
+    pub due: Option<NaiveDate>,
+    pub priority: Option<Priority>,
+    pub tags: Option<Vec<String>>,
+    pub project: Option<String>,
+}
+
+impl TaskChanges {
+    /// Why the changes cannot be made, if they cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .description
+            .as_deref()
+            .is_some_and(|description| description.trim().is_empty())
+        {
+            return Err("The description must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Makes the changes to the task with ID `id`, returning it as stored.
+    pub fn apply(&self, manager: &mut DatabaseManager, id: Uuid) -> Result<Task, ToNotDoError> {
+        let task = self.change_fields(stored_task(manager, id)?);
+        manager.update(&task)?;
+        if let Some(state) = self.state {
+            manager.set_task_state(id, state)?;
+        }
+        stored_task(manager, id)
+    }
+
+    /// `task` with every field but the state changed; the state goes through
+    /// [`DatabaseManager::set_task_state`] to keep the completion date right.
+    fn change_fields(&self, mut task: Task) -> Task {
+        if let Some(description) = &self.description {
+            task = task.with_description(description);
+        }
+        if let Some(notes) = &self.notes {
+            task = task.with_notes(notes);
+        }
+        if let Some(due) = self.due {
+            task = task.with_due(due);
+        }
+        if let Some(priority) = self.priority {
+            task = task.with_priority(priority);
+        }
+        if let Some(tags) = &self.tags {
+            task = task.with_tags(tags);
+        }
+        if let Some(project) = &self.project {
+            task = task.with_project(project);
+        }
+        task
+    }
+}
+
+async fn list_tasks(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Query(filter): Query<TaskFilter>,
+) -> Result<Json<Vec<Task>>, (StatusCode, String)> {
+    state.authorize(&headers)?;
+    state
+        .read_tasks(|manager| manager.query(&filter))
+        .map(Json)
+        .map_err(task_error)
+}
+
+/// The task with ID `id`, or an error if there is none.
+pub fn stored_task(manager: &mut DatabaseManager, id: Uuid) -> Result<Task, ToNotDoError> {
+    manager
+        .get(id)?
+        .ok_or(ToNotDoError::DatabaseError(DatabaseError::TaskNotFound(id)))
+}
+
+async fn get_task(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Task>, (StatusCode, String)> {
+    state.authorize(&headers)?;
+    state
+        .read_tasks(|manager| stored_task(manager, id))
+        .map(Json)
+        .map_err(task_error)
+}
+
+async fn create_task(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Json(new_task): Json<NewTask>,
+) -> Result<(StatusCode, Json<Task>), (StatusCode, String)> {
+    state.authorize(&headers)?;
+    new_task.validate().map_err(invalid_request)?;
+    if let Some(parent) = new_task.parent {
+        let exists = state
+            .read_tasks(|manager| manager.get(parent))
+            .map_err(task_error)?
+            .is_some();
+        if !exists {
+            return Err(invalid_request(format!("Parent task {} not found", parent)));
+        }
+    }
+
+    let task = new_task.into_task();
+    state
+        .edit_tasks(|manager| {
+            manager.add(&task)?;
+            stored_task(manager, task.id())
+        })
+        .inspect(|_| state.metrics.record(Operation::Create))
+        .map(|task| (StatusCode::CREATED, Json(task)))
+        .map_err(task_error)
+}
+
+async fn update_task(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(changes): Json<TaskChanges>,
+) -> Result<Json<Task>, (StatusCode, String)> {
+    state.authorize(&headers)?;
+    changes.validate().map_err(invalid_request)?;
+    state
+        .edit_tasks(|manager| changes.apply(manager, id))
+        .inspect(|_| state.metrics.record(Operation::Update))
+        .map(Json)
+        .map_err(task_error)
+}
+
+async fn delete_task(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state.authorize(&headers)?;
+    state
+        .edit_tasks(|manager| manager.delete(id))
+        .inspect(|()| state.metrics.record(Operation::Delete))
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(task_error)
+}
+
+async fn pull_ops(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Query(query): Query<PullQuery>,
+) -> Result<Json<PullResponse>, (StatusCode, String)> {
+    state.authorize(&headers)?;
+
+    let _guard = state.lock.lock().unwrap_or_else(|e| e.into_inner());
+    sync::pull(&sync::ops_log_path(&state.db_file), query.since)
+        .inspect(|_| state.metrics.record(Operation::Pull))
+        .inspect_err(|_| state.metrics.record_sync_error())
+        .map(Json)
+        .map_err(internal_error)
+}
+
+async fn push_ops(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Json(request): Json<PushRequest>,
+) -> Result<Json<PushResponse>, (StatusCode, String)> {
+    state.authorize(&headers)?;
+
+    let _guard = state.lock.lock().unwrap_or_else(|e| e.into_inner());
+    let mut storage = state.open_storage();
+    sync::push(
+        storage.as_mut(),
+        &sync::ops_log_path(&state.db_file),
+        request.events,
+    )
+    .inspect(|_| state.metrics.record(Operation::Push))
+    .inspect_err(|_| state.metrics.record_sync_error())
+    .map(Json)
+    .map_err(internal_error)
+}
+
+/// The metrics in the Prometheus text format.
+async fn metrics(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+) -> Result<([(header::HeaderName, &'static str); 1], String), (StatusCode, String)> {
+    state.authorize(&headers)?;
+    let today = chrono::Local::now().date_naive();
+    state
+        .read_tasks(|manager| Ok(state.metrics.render(manager.database()?.tasks(), today)))
+        .map(|text| ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+        .map_err(internal_error)
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedQuery {
+    token: Option<String>,
+}
+
+impl ServerState {
+    /// The name of the feeds, for clients presenting their token in the
+    /// URL. Without it the feeds look like they are not there.
+    fn feed_name(&self, query: FeedQuery) -> Result<&str, (StatusCode, String)> {
+        match (&self.feed, query.token) {
+            (Some((token, name)), Some(given)) if tokens_match(token, &given) => Ok(name),
+            _ => Err((StatusCode::NOT_FOUND, "Not found".to_string())),
+        }
+    }
+}
+
+/// The calendar feed of due tasks.
+async fn feed(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<FeedQuery>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), (StatusCode, String)> {
+    let name = state.feed_name(query)?;
+    state
+        .read_tasks(|manager| Ok(feed::render(manager.database()?.tasks(), name, Utc::now())))
+        .map(|feed| {
+            (
+                [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+                feed,
+            )
+        })
+        .map_err(internal_error)
+}
+
+/// The Atom feed of completed tasks.
+async fn atom_feed(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<FeedQuery>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), (StatusCode, String)> {
+    let name = state.feed_name(query)?;
+    state
+        .read_tasks(|manager| Ok(feed::render_atom(manager.database()?.tasks(), name)))
+        .map(|feed| {
+            (
+                [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+                feed,
+            )
+        })
+        .map_err(internal_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_management::{DatabaseManager, Task, TaskField},
+        storage::{FileStorage, Storage},
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tokens_match() {
+        let state = ServerState::new(
+            PathBuf::new(),
+            StorageConfig::default(),
+            Some("s3cret".into()),
+        );
+        assert!(state.accepts(Some("Bearer s3cret")));
+        assert!(!state.accepts(Some("Bearer s3creT")));
+        assert!(!state.accepts(Some("Bearer s3cre")));
+        assert!(!state.accepts(Some("s3cret")));
+        assert!(!state.accepts(None));
+    }
+
+    #[test]
+    fn test_accepts_host() {
+        let open = ServerState::new(PathBuf::new(), StorageConfig::default(), None);
+        for host in [
+            "localhost:8080",
+            "127.0.0.1:8080",
+            "[::1]:8080",
+            "192.168.1.2",
+        ] {
+            assert!(open.accepts_host(Some(host)), "{}", host);
+        }
+        assert!(open.accepts_host(None));
+        assert!(!open.accepts_host(Some("attacker.example:8080")));
+        assert!(!open.accepts_host(Some("127.0.0.1.nip.io")));
+
+        let guarded = ServerState::new(
+            PathBuf::new(),
+            StorageConfig::default(),
+            Some("s3cret".into()),
+        );
+        assert!(guarded.accepts_host(Some("tasks.example.org")));
+    }
+
+    fn start_server(db_file: PathBuf, token: &str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = ServerState::new(db_file, StorageConfig::default(), Some(token.to_string()));
+
+        std::thread::spawn(move || serve(listener, state));
+        url
+    }
+
+    #[test]
+    fn test_sync_between_replicas() {
+        let dir = tempdir().unwrap();
+        let remote = start_server(dir.path().join("server.json"), "secret");
+
+        let laptop_file = dir.path().join("laptop.json");
+        let phone_file = dir.path().join("phone.json");
+        let from_laptop = Task::new("From laptop");
+        let from_phone = Task::new("From phone");
+        DatabaseManager::open(&laptop_file)
+            .unwrap()
+            .add_task(&from_laptop)
+            .unwrap();
+        DatabaseManager::open(&phone_file)
+            .unwrap()
+            .add_task(&from_phone)
+            .unwrap();
+
+        let mut laptop = FileStorage::new(&laptop_file);
+        let mut phone = FileStorage::new(&phone_file);
+
+        assert!(sync::sync(
+            &mut laptop,
+            &laptop_file.with_extension("sync"),
+            Some(&remote),
+            Some("wrong")
+        )
+        .is_err());
+
+        let report = sync::sync(
+            &mut laptop,
+            &laptop_file.with_extension("sync"),
+            Some(&remote),
+            Some("secret"),
+        )
+        .unwrap();
+        assert_eq!((report.pushed, report.pulled), (1, 0));
+
+        let report = sync::sync(
+            &mut phone,
+            &phone_file.with_extension("sync"),
+            Some(&remote),
+            Some("secret"),
+        )
+        .unwrap();
+        assert_eq!((report.pushed, report.pulled), (1, 1));
+
+        // The remote is remembered, and the laptop only pulls what is new.
+        let report = sync::sync(
+            &mut laptop,
+            &laptop_file.with_extension("sync"),
+            None,
+            Some("secret"),
+        )
+        .unwrap();
+        assert_eq!((report.pushed, report.pulled), (0, 1));
+
+        let ids = |storage: &mut FileStorage| {
+            let mut ids: Vec<_> = storage
+                .load()
+                .unwrap()
+                .tasks()
+                .iter()
+                .map(Task::id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&mut laptop), ids(&mut phone));
+        assert_eq!(ids(&mut laptop).len(), 2);
+
+        // Both edit the same field before syncing again.
+        DatabaseManager::open(&laptop_file)
+            .unwrap()
+            .update_description(from_phone.id(), "Edited on the laptop")
+            .unwrap();
+        DatabaseManager::open(&phone_file)
+            .unwrap()
+            .update_description(from_phone.id(), "Edited on the phone")
+            .unwrap();
+        let laptop_state = laptop_file.with_extension("sync");
+        let status = sync::status(&mut laptop, &laptop_state).unwrap();
+        assert_eq!(status.remote.as_deref(), Some(remote.as_str()));
+        assert!(status.synced_at.is_some());
+        assert_eq!(status.pending, 1);
+        assert!(status.conflicts.is_empty());
+
+        sync::sync(
+            &mut phone,
+            &phone_file.with_extension("sync"),
+            None,
+            Some("secret"),
+        )
+        .unwrap();
+        let report = sync::sync(&mut laptop, &laptop_state, None, Some("secret")).unwrap();
+        assert_eq!(report.conflicts, 1);
+
+        let status = sync::status(&mut laptop, &laptop_state).unwrap();
+        assert_eq!(status.pending, 0);
+        let [conflict] = status.conflicts.as_slice() else {
+            panic!("Expected one conflict");
+        };
+        assert_eq!(conflict.fields, [TaskField::Description]);
+        assert_eq!(conflict.ours.description(), "Edited on the laptop");
+        assert_eq!(conflict.theirs.description(), "Edited on the phone");
+
+        sync::dismiss_conflict(&laptop_state, from_phone.id()).unwrap();
+        assert!(sync::status(&mut laptop, &laptop_state)
+            .unwrap()
+            .conflicts
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rest_api() {
+        let dir = tempdir().unwrap();
+        let server_file = dir.path().join("server.json");
+        let remote = start_server(server_file.clone(), "secret");
+        let tasks = format!("{}{}", remote, TASKS_ENDPOINT);
+        let agent = ureq::Agent::new_with_defaults();
+        let authorization = "Bearer secret";
+
+        assert!(agent.get(&tasks).call().is_err());
+
+        let created: Task = agent
+            .post(&tasks)
+            .header("Authorization", authorization)
+            .send_json(serde_json::json!({ "description": "Buy milk", "project": "home" }))
+            .unwrap()
+            .body_mut()
+            .read_json()
+            .unwrap();
+        assert_eq!(created.description(), "Buy milk");
+        let task = format!("{}/{}", tasks, created.id());
+
+        let updated: Task = agent
+            .patch(&task)
+            .header("Authorization", authorization)
+            .send_json(serde_json::json!({ "description": "Buy oat milk", "state": "Done" }))
+            .unwrap()
+            .body_mut()
+            .read_json()
+            .unwrap();
+        assert_eq!(updated.description(), "Buy oat milk");
+        assert!(updated.completed_at().is_some());
+
+        let done: Vec<Task> = agent
+            .get(&tasks)
+            .header("Authorization", authorization)
+            .query("state", "Done")
+            .query("project", "home")
+            .call()
+            .unwrap()
+            .body_mut()
+            .read_json()
+            .unwrap();
+        assert_eq!(done, [updated]);
+
+        // Replicas syncing with the server get the edits made over REST.
+        let laptop_file = dir.path().join("laptop.json");
+        let mut laptop = FileStorage::new(&laptop_file);
+        let report = sync::sync(
+            &mut laptop,
+            &laptop_file.with_extension("sync"),
+            Some(&remote),
+            Some("secret"),
+        )
+        .unwrap();
+        assert_eq!(report.pulled, 2);
+        assert_eq!(
+            laptop.load().unwrap().tasks()[0].description(),
+            "Buy oat milk"
+        );
+
+        let status = |response: Result<_, ureq::Error>| match response {
+            Ok(response) => ureq::http::Response::status(&response).as_u16(),
+            Err(ureq::Error::StatusCode(status)) => status,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(
+            status(
+                agent
+                    .post(&tasks)
+                    .header("Authorization", authorization)
+                    .send_json(serde_json::json!({ "description": " " }))
+            ),
+            422
+        );
+        assert_eq!(
+            status(
+                agent
+                    .post(&tasks)
+                    .header("Authorization", authorization)
+                    .send_json(serde_json::json!({
+                        "description": "Orphan",
+                        "parent": uuid::Uuid::new_v4(),
+                    }))
+            ),
+            422
+        );
+        assert_eq!(
+            status(
+                agent
+                    .post(&tasks)
+                    .header("Authorization", authorization)
+                    .send_json(serde_json::json!({ "notes": "No description" }))
+            ),
+            422
+        );
+        assert_eq!(
+            status(
+                agent
+                    .delete(&task)
+                    .header("Authorization", authorization)
+                    .call()
+            ),
+            204
+        );
+        assert_eq!(
+            status(
+                agent
+                    .get(&task)
+                    .header("Authorization", authorization)
+                    .call()
+            ),
+            404
+        );
+        assert!(DatabaseManager::open(&server_file)
+            .unwrap()
+            .get_tasks()
+            .unwrap()
+            .is_empty());
+
+        let metrics = format!("{}{}", remote, METRICS_ENDPOINT);
+        assert_eq!(status(agent.get(&metrics).call()), 401);
+        let text = agent
+            .get(&metrics)
+            .header("Authorization", authorization)
+            .call()
+            .unwrap()
+            .body_mut()
+            .read_to_string()
+            .unwrap();
+        assert!(text.contains("to_not_do_tasks{state=\"todo\"} 0\n"));
+        assert!(text.contains("to_not_do_operations_total{operation=\"create\"} 1\n"));
+        assert!(text.contains("to_not_do_operations_total{operation=\"delete\"} 1\n"));
+        assert!(text.contains("to_not_do_operations_total{operation=\"pull\"} 1\n"));
+    }
+
+    #[test]
+    fn test_web_page_needs_no_token() {
+        let dir = tempdir().unwrap();
+        let remote = start_server(dir.path().join("server.json"), "secret");
+        let agent = ureq::Agent::new_with_defaults();
+
+        let mut page = agent.get(&remote).call().unwrap();
+        assert!(page.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        assert!(page.body_mut().read_to_string().unwrap().contains("app.js"));
+
+        let script = agent.get(format!("{}/app.js", remote)).call().unwrap();
+        assert!(script.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/javascript"));
+
+        assert!(agent.get(format!("{}/missing.js", remote)).call().is_err());
+    }
+
+    #[test]
+    fn test_feed() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("server.json");
+        let due = Utc::now().date_naive();
+        DatabaseManager::open(&db_file)
+            .unwrap()
+            .add_task(&Task::new("Pay rent").with_due(due))
+            .unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = format!("http://{}", listener.local_addr().unwrap());
+        let state = ServerState::new(db_file, StorageConfig::default(), Some("secret".into()))
+            .with_feed("feedsecret".into(), "to-not-do".into());
+        std::thread::spawn(move || serve(listener, state));
+        let agent = ureq::Agent::new_with_defaults();
+
+        let mut feed = agent
+            .get(format!("{}{}?token=feedsecret", remote, FEED_ENDPOINT))
+            .call()
+            .unwrap();
+        assert!(feed.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/calendar"));
+        assert!(feed
+            .body_mut()
+            .read_to_string()
+            .unwrap()
+            .contains("SUMMARY:Pay rent"));
+        assert!(agent
+            .get(format!("{}{}?token=feedsecret", remote, ATOM_ENDPOINT))
+            .call()
+            .unwrap()
+            .body_mut()
+            .read_to_string()
+            .unwrap()
+            .starts_with("<?xml"));
+
+        // Neither a wrong token nor the API token opens it.
+        for query in ["", "?token=wrong", "?token=secret"] {
+            assert!(agent
+                .get(format!("{}{}{}", remote, FEED_ENDPOINT, query))
+                .call()
+                .is_err());
+        }
+    }
+}
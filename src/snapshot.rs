@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    encryption::{Cipher, KeySource},
+    error::ToNotDoError,
+    file_management::{Backup, Database, APP_NAME, VERSION},
+};
+
+pub const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+
+/// A named copy of the database, listed by [`list_snapshots`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub tasks: usize,
+}
+
+/// Snapshot names become file names, so keep them to a safe alphabet.
+pub fn validate_name(name: &str) -> Result<(), ToNotDoError> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(ToNotDoError::ConfigError(format!(
+            "Invalid snapshot name '{}': use letters, digits, '-' and '_'",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn snapshot_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+/// Saves `db` in `dir` as the snapshot `name`, or one named after the
+/// current time, encrypted with `cipher` if given. Existing snapshots are
+/// never overwritten.
+pub fn create_snapshot(
+    dir: &Path,
+    name: Option<&str>,
+    db: &Database,
+    cipher: Option<&Cipher>,
+) -> Result<String, ToNotDoError> {
+    let created_at = Utc::now();
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => created_at.format("%Y%m%d-%H%M%S").to_string(),
+    };
+    validate_name(&name)?;
+
+    let path = snapshot_path(dir, &name);
+    if path.exists() {
+        return Err(ToNotDoError::ConfigError(format!(
+            "Snapshot '{}' already exists",
+            name
+        )));
+    }
+
+    Backup {
+        app: APP_NAME.to_string(),
+        version: VERSION.to_string(),
+        created_at,
+        database: db.clone(),
+        config: None,
+    }
+    .write(&path, cipher)?;
+
+    Ok(name)
+}
+
+/// Lists the snapshots in `dir`, oldest first, decrypting encrypted ones
+/// with keys from `keys`. Files that cannot be read as snapshots are
+/// skipped.
+pub fn list_snapshots(dir: &Path, mut keys: Option<&mut dyn KeySource>) -> Vec<SnapshotInfo> {
+    let mut snapshots: Vec<SnapshotInfo> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_string();
+            let keys = keys.as_mut().map(|keys| &mut **keys as &mut dyn KeySource);
+            let snapshot = Backup::read(&path, keys).ok()?;
+            Some(SnapshotInfo {
+                name,
+                created_at: snapshot.created_at,
+                tasks: snapshot.database.tasks().len(),
+            })
+        })
+        .collect();
+
+    snapshots.sort_by(|a, b| (a.created_at, &a.name).cmp(&(b.created_at, &b.name)));
+    snapshots
+}
+
+/// Reads the snapshot `name` from `dir`, decrypting it with a key from
+/// `keys` when it is encrypted.
+pub fn read_snapshot(
+    dir: &Path,
+    name: &str,
+    keys: Option<&mut dyn KeySource>,
+) -> Result<Backup, ToNotDoError> {
+    validate_name(name)?;
+
+    let path = snapshot_path(dir, name);
+    if !path.is_file() {
+        return Err(ToNotDoError::ConfigError(format!(
+            "Snapshot '{}' does not exist",
+            name
+        )));
+    }
+
+    Backup::read(&path, keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{is_encrypted, Passphrase};
+    use crate::file_management::Task;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_create_list_and_read_snapshots() {
+        let dir = tempdir().unwrap();
+        let mut db = Database::default();
+        db.insert_task(Task::new("Before bulk edit")).unwrap();
+
+        assert_eq!(
+            create_snapshot(dir.path(), Some("before-edit"), &db, None).unwrap(),
+            "before-edit"
+        );
+        assert!(create_snapshot(dir.path(), Some("before-edit"), &db, None).is_err());
+        assert!(create_snapshot(dir.path(), Some("../escape"), &db, None).is_err());
+
+        db.insert_task(Task::new("Later")).unwrap();
+        let unnamed = create_snapshot(dir.path(), None, &db, None).unwrap();
+
+        let snapshots = list_snapshots(dir.path(), None);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].name, "before-edit");
+        assert_eq!(snapshots[0].tasks, 1);
+        assert_eq!(snapshots[1].name, unnamed);
+
+        let restored = read_snapshot(dir.path(), "before-edit", None).unwrap();
+        assert_eq!(restored.database.tasks().len(), 1);
+        assert!(read_snapshot(dir.path(), "missing", None).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_snapshots() {
+        let dir = tempdir().unwrap();
+        let mut db = Database::default();
+        db.insert_task(Task::new("Secret")).unwrap();
+
+        let cipher = Passphrase("correct").cipher(None).unwrap();
+        create_snapshot(dir.path(), Some("secret"), &db, Some(&cipher)).unwrap();
+        let data = std::fs::read(snapshot_path(dir.path(), "secret")).unwrap();
+        assert!(is_encrypted(&data));
+
+        assert!(read_snapshot(dir.path(), "secret", None).is_err());
+        assert!(read_snapshot(dir.path(), "secret", Some(&mut Passphrase("wrong"))).is_err());
+        let restored =
+            read_snapshot(dir.path(), "secret", Some(&mut Passphrase("correct"))).unwrap();
+        assert_eq!(restored.database.tasks().len(), 1);
+
+        let snapshots = list_snapshots(dir.path(), Some(&mut Passphrase("correct")));
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].tasks, 1);
+    }
+}
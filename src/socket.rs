@@ -0,0 +1,113 @@
+//! The control socket of `daemon`: a Unix domain socket answering the
+//! JSON-RPC requests of [`rpc`](crate::rpc), one per line, so editor
+//! plugins and status bars can query and change tasks without starting a
+//! process each time.
+//!
+//! Every connection is served on a thread of its own, which hands each
+//! request to the thread owning the database as a [`Job`] and writes back
+//! the answer.
+
+use std::{
+    io::BufReader,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::mpsc,
+};
+
+use crate::{error::ToNotDoError, file_management::DatabaseManager, rpc};
+
+/// A request for the thread that owns the database.
+pub type Job = Box<dyn FnOnce(&mut DatabaseManager) + Send>;
+
+fn socket_error(path: &Path, reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::SocketError(format!("{}: {}", path.display(), reason))
+}
+
+/// Listens on `path`, replacing a socket left there by an earlier run, and
+/// returns the requests as they come in. Only the current user may connect.
+pub fn listen(path: &Path) -> Result<mpsc::Receiver<Job>, ToNotDoError> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(socket_error(path, "not a socket"));
+        }
+        std::fs::remove_file(path).map_err(|e| socket_error(path, e))?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| socket_error(path, e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| socket_error(path, e))?;
+
+    let (jobs, queued) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let jobs = jobs.clone();
+            std::thread::spawn(move || answer(stream, jobs));
+        }
+    });
+    Ok(queued)
+}
+
+/// Answers the requests on one connection until the client closes it.
+fn answer(stream: UnixStream, jobs: mpsc::Sender<Job>) {
+    let Ok(input) = stream.try_clone() else {
+        return;
+    };
+    let _ = rpc::serve(BufReader::new(input), stream, |method, params| {
+        let method = method.to_string();
+        let (reply, answer) = mpsc::channel();
+        let job: Job = Box::new(move |db_manager| {
+            let _ = reply.send(rpc::call(db_manager, &method, params));
+        });
+        let stopping = || rpc::Error::new(rpc::DATABASE_ERROR, "The daemon is stopping");
+        jobs.send(job).map_err(|_| stopping())?;
+        answer.recv().unwrap_or_else(|_| Err(stopping()))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use serde_json::{json, Value};
+    use std::io::{BufRead, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_socket() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.sock");
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        assert!(listen(&dir.path().join("file")).is_err());
+
+        drop(listen(&path).unwrap());
+        // A socket left behind is replaced.
+        let queued = listen(&path).unwrap();
+        std::thread::spawn(move || {
+            let mut db_manager =
+                DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+            for job in queued {
+                job(&mut db_manager);
+            }
+        });
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        let mut responses = BufReader::new(client.try_clone().unwrap()).lines();
+        let mut call = |request: Value| {
+            writeln!(client, "{}", request).unwrap();
+            serde_json::from_str::<Value>(&responses.next().unwrap().unwrap()).unwrap()
+        };
+
+        let added = call(json!({
+            "jsonrpc": "2.0", "id": 1, "method": "add",
+            "params": {"description": "Water the plants"}
+        }));
+        assert_eq!(added["result"]["description"], "Water the plants");
+        let listed = call(json!({"jsonrpc": "2.0", "id": 2, "method": "query"}));
+        assert_eq!(listed["result"].as_array().unwrap().len(), 1);
+        let unknown = call(json!({"jsonrpc": "2.0", "id": 3, "method": "nope"}));
+        assert_eq!(unknown["error"]["code"], rpc::METHOD_NOT_FOUND);
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    compression::Compression,
+    config::StorageConfig,
+    encryption,
+    error::ToNotDoError,
+    file_management::{AppPaths, TaskState},
+    format::Format,
+    journal::read_events,
+    migration::schema_version,
+    repair::latest_backup,
+    storage::{file_storage, open_app_storage},
+};
+
+/// Where the database stands on disk, for `db stats`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DbStats {
+    pub file_size: u64,
+    pub todo: usize,
+    pub in_progress: usize,
+    pub done: usize,
+    pub archived: usize,
+    pub history_entries: usize,
+    pub journal_events: usize,
+    pub journal_size: u64,
+    pub last_backup: Option<DateTime<Utc>>,
+    /// Schema version of the file as stored, before any migration.
+    pub schema_version: u32,
+    pub backend: String,
+}
+
+impl DbStats {
+    pub fn tasks(&self) -> usize {
+        self.todo + self.in_progress + self.done
+    }
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+/// Describes how the database is stored, going by the file itself where it
+/// tells and by `config` otherwise.
+fn describe_backend(db_file: &Path, config: &StorageConfig) -> String {
+    let data = std::fs::read(db_file).unwrap_or_default();
+    let encrypted = encryption::is_encrypted(&data) || (data.is_empty() && config.encrypt);
+    let compression = if encrypted || data.is_empty() {
+        config.compression
+    } else {
+        Compression::detect(&data)
+    };
+    let format = config
+        .format
+        .or_else(|| Format::from_path(db_file))
+        .unwrap_or_default();
+
+    let mut backend = format!("file ({:?}", format).to_lowercase();
+    if compression != Compression::None {
+        backend.push_str(&format!(", {:?}", compression).to_lowercase());
+    }
+    if encrypted {
+        backend.push_str(", encrypted");
+    }
+    backend.push(')');
+    if config.partition {
+        backend.push_str(" + per-project files");
+    }
+    if config.journal {
+        backend.push_str(" + journal");
+    } else if config.wal {
+        backend.push_str(" + write-ahead log");
+    }
+
+    backend
+}
+
+/// Gathers [`DbStats`] for the database in `paths`.
+pub fn db_stats(paths: &AppPaths, config: &StorageConfig) -> Result<DbStats, ToNotDoError> {
+    let db_file = &paths.db_file;
+    let raw = file_storage(db_file, config).load_value()?;
+    let db = open_app_storage(paths, config).load()?;
+
+    let journal = paths.journal_file();
+    let (events, _) = read_events(&journal)?;
+
+    let last_backup = latest_backup(&paths.backup_dir())
+        .and_then(|path| std::fs::metadata(path).ok()?.modified().ok())
+        .map(DateTime::<Utc>::from);
+
+    let mut stats = DbStats {
+        file_size: file_size(db_file),
+        journal_events: events.len(),
+        journal_size: file_size(&journal),
+        last_backup,
+        schema_version: schema_version(&raw),
+        backend: describe_backend(db_file, config),
+        ..DbStats::default()
+    };
+
+    for task in db.tasks() {
+        match task.state() {
+            TaskState::Todo => stats.todo += 1,
+            TaskState::InProgress => stats.in_progress += 1,
+            TaskState::Done => stats.done += 1,
+        }
+        if task.is_archived() {
+            stats.archived += 1;
+        }
+        stats.history_entries += task.history().len();
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_management::{DatabaseManager, Task},
+        migration::SCHEMA_VERSION,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_db_stats() {
+        let dir = tempdir().unwrap();
+        let paths = AppPaths::new(dir.path());
+        let config = StorageConfig {
+            journal: true,
+            compression: Compression::Gzip,
+            ..StorageConfig::default()
+        };
+
+        let mut db_manager =
+            DatabaseManager::with_storage(open_app_storage(&paths, &config)).unwrap();
+        let todo = Task::new("Todo");
+        let done = Task::new("Done");
+        db_manager.add_task(&todo).unwrap();
+        db_manager.add_task(&done).unwrap();
+        db_manager
+            .set_task_state(done.id(), TaskState::Done)
+            .unwrap();
+        db_manager
+            .postpone_task(todo.id(), chrono::Duration::days(1))
+            .unwrap();
+
+        let stats = db_stats(&paths, &config).unwrap();
+
+        assert_eq!((stats.todo, stats.in_progress, stats.done), (1, 0, 1));
+        assert_eq!(stats.tasks(), 2);
+        assert_eq!(stats.history_entries, 1);
+        assert_eq!(stats.journal_events, 4);
+        assert!(stats.journal_size > 0);
+        assert_eq!(stats.last_backup, None);
+        assert_eq!(stats.schema_version, SCHEMA_VERSION);
+        assert_eq!(stats.backend, "file (json, gzip) + journal");
+    }
+}
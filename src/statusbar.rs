@@ -0,0 +1,337 @@
+//! Compact summaries of the tasks for status bars, printed by `status
+//! --format <bar>`; see [`Bar`] for the bars. With tmux, `--tmux` is
+//! short for `--format tmux`:
+//!
+//! ```text
+//! set -g status-right '#(to-not-do status --tmux)'
+//! ```
+//!
+//! Waybar wants JSON from a custom module, and can style the module by the
+//! `overdue`, `due` and `clear` classes in its CSS:
+//!
+//! ```text
+//! "custom/tasks": {
+//!     "exec": "to-not-do status --format waybar",
+//!     "return-type": "json",
+//!     "interval": 30
+//! }
+//! ```
+//!
+//! Bars run the command every few seconds, so what it printed is kept in
+//! the state directory and printed again without opening the database
+//! until the database changes on disk or the day does. A database without
+//! local files, such as a remote one, is only looked at again after
+//! [`CACHE_TTL`].
+
+use std::{path::Path, time::Duration};
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{AppPaths, Task, TaskState},
+    publish, storage,
+};
+
+/// How long a summary of a database without local files is printed again.
+pub const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// How much of the focused task's description a segment shows.
+const FOCUS_WIDTH: usize = 24;
+
+/// `text` cut to `width` characters, marking the cut.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// Status bars `status` prints a summary for. Each shows the focused task
+/// and the number of overdue tasks, or of open ones when none are overdue.
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// A segment for tmux's `status-right`, colored with `#[fg=...]`.
+    Tmux,
+    /// The JSON of a Waybar custom module, with today's agenda as the
+    /// tooltip and a class telling whether anything is overdue.
+    Waybar,
+    /// A line for a Polybar `custom/script` module, colored with `%{F...}`.
+    Polybar,
+}
+
+impl Bar {
+    /// Name of the file in the state directory caching the summary.
+    pub fn name(self) -> &'static str {
+        match self {
+            Bar::Tmux => "tmux",
+            Bar::Waybar => "waybar",
+            Bar::Polybar => "polybar",
+        }
+    }
+
+    /// The summary of the active `tasks` as of `today`.
+    pub fn render(self, focused: Option<&Task>, tasks: &[Task], today: NaiveDate) -> String {
+        match self {
+            Bar::Tmux => tmux(focused, tasks, today),
+            Bar::Waybar => waybar(focused, tasks, today),
+            Bar::Polybar => polybar(focused, tasks, today),
+        }
+    }
+}
+
+/// The open `tasks` due by `today`, the longest overdue first.
+pub fn agenda(tasks: &[Task], today: NaiveDate) -> Vec<&Task> {
+    let mut agenda: Vec<&Task> = tasks
+        .iter()
+        .filter(|task| task.state() != TaskState::Done)
+        .filter(|task| task.due().is_some_and(|due| due <= today))
+        .collect();
+    agenda.sort_by_key(|task| (task.due(), std::cmp::Reverse(task.priority())));
+    agenda
+}
+
+/// The focused task cut to fit, and the count to show with its label.
+fn counts(focused: Option<&Task>, tasks: &[Task], today: NaiveDate) -> (Option<String>, String) {
+    let focus = focused.map(|task| truncate(task.description(), FOCUS_WIDTH));
+    let overdue = tasks.iter().filter(|task| task.is_overdue(today)).count();
+    if overdue > 0 {
+        return (focus, format!("{} overdue", overdue));
+    }
+    let open = tasks
+        .iter()
+        .filter(|task| task.state() != TaskState::Done)
+        .count();
+    (focus, format!("{} open", open))
+}
+
+fn tmux(focused: Option<&Task>, tasks: &[Task], today: NaiveDate) -> String {
+    let (focus, count) = counts(focused, tasks, today);
+    let mut parts = Vec::new();
+    if let Some(focus) = focus {
+        // `#` starts a format in the status line.
+        parts.push(format!(
+            "#[fg=cyan]▶ {}#[default]",
+            focus.replace('#', "##")
+        ));
+    }
+    let color = if count.ends_with("overdue") {
+        "red,bold"
+    } else {
+        "green"
+    };
+    parts.push(format!("#[fg={}]{}#[default]", color, count));
+    parts.join(" ")
+}
+
+fn polybar(focused: Option<&Task>, tasks: &[Task], today: NaiveDate) -> String {
+    let (focus, count) = counts(focused, tasks, today);
+    let mut parts = Vec::new();
+    if let Some(focus) = focus {
+        // `%{` starts a format tag.
+        parts.push(format!(
+            "%{{F#5fafd7}}▶ {}%{{F-}}",
+            focus.replace("%{", "% {")
+        ));
+    }
+    let color = if count.ends_with("overdue") {
+        "#e06c75"
+    } else {
+        "#98c379"
+    };
+    parts.push(format!("%{{F{}}}{}%{{F-}}", color, count));
+    parts.join(" ")
+}
+
+fn waybar(focused: Option<&Task>, tasks: &[Task], today: NaiveDate) -> String {
+    let (focus, count) = counts(focused, tasks, today);
+    // Waybar reads both as Pango markup.
+    let text = match focus {
+        Some(focus) => format!("▶ {} · {}", publish::escape(&focus), count),
+        None => count,
+    };
+
+    let agenda = agenda(tasks, today);
+    let mut tooltip = Vec::new();
+    if let Some(task) = focused {
+        tooltip.push(format!("Focus: {}", publish::escape(task.description())));
+    }
+    if agenda.is_empty() {
+        tooltip.push("Nothing due today".to_string());
+    } else {
+        tooltip.push("Due today:".to_string());
+    }
+    for task in &agenda {
+        let mut line = format!("• {}", publish::escape(task.description()));
+        if let Some(due) = task.due().filter(|&due| due < today) {
+            line.push_str(&format!(" (overdue since {})", due));
+        }
+        tooltip.push(line);
+    }
+
+    let class = if agenda.iter().any(|task| task.is_overdue(today)) {
+        "overdue"
+    } else if !agenda.is_empty() {
+        "due"
+    } else {
+        "clear"
+    };
+    serde_json::json!({
+        "text": text,
+        "tooltip": tooltip.join("\n"),
+        "class": class,
+        "alt": class,
+    })
+    .to_string()
+}
+
+/// A summary printed earlier, with what it depended on.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cached {
+    /// When the database last changed on disk as of the summary.
+    modified: Option<DateTime<Utc>>,
+    written: DateTime<Utc>,
+    day: NaiveDate,
+    output: String,
+}
+
+fn last_modified(paths: &AppPaths) -> Option<DateTime<Utc>> {
+    storage::last_modified(&paths.db_file, &paths.journal_file()).map(DateTime::from)
+}
+
+/// The summary kept at `path` for the database of `paths`, if it still
+/// holds at `now`.
+pub fn cached(path: &Path, paths: &AppPaths, now: DateTime<Local>) -> Option<String> {
+    let cached: Cached = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    let modified = last_modified(paths);
+
+    let fresh = match modified {
+        Some(_) => cached.modified == modified,
+        None => (now.with_timezone(&Utc) - cached.written)
+            .to_std()
+            .is_ok_and(|age| age < CACHE_TTL),
+    };
+    (fresh && cached.day == now.date_naive()).then_some(cached.output)
+}
+
+/// Keeps `output`, the summary of the database of `paths` made at `now`,
+/// at `path`.
+pub fn store(
+    path: &Path,
+    paths: &AppPaths,
+    output: &str,
+    now: DateTime<Local>,
+) -> Result<(), ToNotDoError> {
+    let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+    let cached = Cached {
+        modified: last_modified(paths),
+        written: now.with_timezone(&Utc),
+        day: now.date_naive(),
+        output: output.to_string(),
+    };
+    let json = serde_json::to_vec(&cached)
+        .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    storage::write_atomically(path, &json).map_err(write_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tmux() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let yesterday = today.pred_opt().unwrap();
+        let focused = Task::new("Write the #1 quarterly report for the board");
+        let mut tasks = vec![focused.clone(), Task::new("Later")];
+        assert_eq!(
+            tmux(Some(&focused), &tasks, today),
+            "#[fg=cyan]▶ Write the ##1 quarterly …#[default] #[fg=green]2 open#[default]"
+        );
+
+        tasks.push(Task::new("Late").with_due(yesterday));
+        tasks.push(
+            Task::new("Done late")
+                .with_due(yesterday)
+                .with_completed_at(today),
+        );
+        assert_eq!(
+            tmux(None, &tasks, today),
+            "#[fg=red,bold]1 overdue#[default]"
+        );
+        assert_eq!(
+            Bar::Polybar.render(None, &tasks, today),
+            "%{F#e06c75}1 overdue%{F-}"
+        );
+    }
+
+    #[test]
+    fn test_waybar() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let focused = Task::new("Plan <launch>");
+        let mut tasks = vec![
+            focused.clone(),
+            Task::new("Call Sam").with_due(today),
+            Task::new("Next week").with_due(today + chrono::Duration::days(7)),
+        ];
+
+        let output: serde_json::Value =
+            serde_json::from_str(&Bar::Waybar.render(Some(&focused), &tasks, today)).unwrap();
+        assert_eq!(output["text"], "▶ Plan &lt;launch&gt; · 3 open");
+        assert_eq!(
+            output["tooltip"],
+            "Focus: Plan &lt;launch&gt;\nDue today:\n• Call Sam"
+        );
+        assert_eq!(output["class"], "due");
+
+        tasks.push(Task::new("Pay rent").with_due(today - chrono::Duration::days(2)));
+        let output: serde_json::Value =
+            serde_json::from_str(&Bar::Waybar.render(None, &tasks, today)).unwrap();
+        assert_eq!(output["text"], "1 overdue");
+        assert_eq!(
+            output["tooltip"],
+            "Due today:\n• Pay rent (overdue since 2025-03-08)\n• Call Sam"
+        );
+        assert_eq!(output["class"], "overdue");
+
+        let output: serde_json::Value =
+            serde_json::from_str(&Bar::Waybar.render(None, &[], today)).unwrap();
+        assert_eq!(output["tooltip"], "Nothing due today");
+        assert_eq!(output["class"], "clear");
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempdir().unwrap();
+        let paths = AppPaths::new(dir.path());
+        let db_file = &paths.db_file;
+        let path = dir.path().join("tasks.tmux");
+        let now = Local.with_ymd_and_hms(2025, 3, 10, 12, 0, 0).unwrap();
+        assert_eq!(cached(&path, &paths, now), None);
+
+        // Without files, only for a while.
+        store(&path, &paths, "3 open", now).unwrap();
+        assert_eq!(cached(&path, &paths, now).as_deref(), Some("3 open"));
+        let later = now + chrono::Duration::seconds(CACHE_TTL.as_secs() as i64 + 1);
+        assert_eq!(cached(&path, &paths, later), None);
+
+        // With files, until they change or the day does.
+        std::fs::write(db_file, "{}").unwrap();
+        store(&path, &paths, "4 open", now).unwrap();
+        assert_eq!(cached(&path, &paths, later).as_deref(), Some("4 open"));
+        assert_eq!(cached(&path, &paths, now + chrono::Duration::days(1)), None);
+        let file = std::fs::File::options().write(true).open(db_file).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(cached(&path, &paths, now), None);
+    }
+}
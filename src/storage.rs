@@ -0,0 +1,1079 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::{
+    checksum::ChecksumStorage,
+    compression::Compression,
+    config::StorageConfig,
+    encryption::{self, Cipher, KeySource, KeyringKeySource},
+    error::{DatabaseError, ToNotDoError},
+    file_management::{AppPaths, Database},
+    format::Format,
+    journal::{journal_path, JournalStorage},
+    migration,
+    wal::{wal_path, WalStorage},
+};
+
+/// Persistence backend behind [`DatabaseManager`](crate::file_management::DatabaseManager).
+///
+/// Implementations only move whole databases in and out; all task logic stays
+/// in the manager, so new backends never need to touch the CLI layer.
+pub trait Storage: Send {
+    /// Loads the database, creating an empty one first if none exists yet.
+    fn open(&mut self) -> Result<Database, ToNotDoError> {
+        if !self.exists() {
+            let db = Database::default();
+            self.save(&db)?;
+            return Ok(db);
+        }
+
+        self.load()
+    }
+
+    fn exists(&self) -> bool;
+
+    fn load(&mut self) -> Result<Database, ToNotDoError>;
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError>;
+
+    /// Saves `db` like [`Storage::save`] and tells the revision of what it
+    /// wrote, which writes by other processes right after do not change.
+    /// `None` when the storage cannot tell.
+    fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+        self.save(db)?;
+        Ok(None)
+    }
+
+    /// Tells the stored database as it is now apart from how it was after
+    /// any later write, whether made here or by another process, so loading
+    /// it again can be skipped while it stays the same. `None` when the
+    /// storage cannot tell without loading.
+    fn revision(&self) -> Option<Revision> {
+        None
+    }
+
+    /// Records that `db` has changes that are not saved yet, so they survive
+    /// a crash before the next save. Most storages have nothing to do.
+    fn stage(&mut self, _db: &Database) -> Result<(), ToNotDoError> {
+        Ok(())
+    }
+
+    /// Keeps loading from writing anything, as it otherwise may to store a
+    /// migrated database or to tidy up after a crash. Saving is left alone;
+    /// [`ReadOnlyStorage`] refuses it.
+    fn set_read_only(&mut self) {}
+
+    /// Loads the latest database, applies `change` and saves the result. The
+    /// database is left untouched when `change` fails.
+    fn update(
+        &mut self,
+        change: &mut dyn FnMut(&mut Database) -> Result<(), ToNotDoError>,
+    ) -> Result<Database, ToNotDoError> {
+        let mut db = self.load()?;
+        change(&mut db)?;
+        self.save(&db)?;
+        Ok(db)
+    }
+}
+
+/// Builds the storage for the database file at `db_file` as configured,
+/// with its journal next to it.
+pub fn open_storage(db_file: &Path, config: &StorageConfig) -> Box<dyn Storage> {
+    open_storage_with_journal(db_file, &journal_path(db_file), config)
+}
+
+/// Builds the storage for the database of `paths` as configured, with its
+/// journal in the state directory.
+pub fn open_app_storage(paths: &AppPaths, config: &StorageConfig) -> Box<dyn Storage> {
+    open_storage_with_journal(&paths.db_file, &paths.journal_file(), config)
+}
+
+/// Builds the storage for the database file at `db_file` as configured,
+/// journaling to `journal` when the journal is on.
+pub fn open_storage_with_journal(
+    db_file: &Path,
+    journal: &Path,
+    config: &StorageConfig,
+) -> Box<dyn Storage> {
+    // Partitions left behind by an earlier `partition = true` are still read,
+    // and folded back into the database file on the next save.
+    let storage: Box<dyn Storage> = if config.partition || partitions_dir(db_file).is_dir() {
+        Box::new(PartitionedStorage::new(db_file, config))
+    } else {
+        Box::new(file_storage(db_file, config))
+    };
+    let storage = Box::new(ChecksumStorage::new(storage, db_file));
+
+    // The journal is append-only already, so it needs no write-ahead log.
+    if config.journal {
+        Box::new(JournalStorage::new(storage, journal))
+    } else if config.wal {
+        Box::new(WalStorage::new(storage, &wal_path(db_file)))
+    } else {
+        storage
+    }
+}
+
+/// Whether the database at `db_file` exists but cannot be written, because
+/// of its permissions or a read-only filesystem.
+pub fn is_read_only(db_file: &Path) -> bool {
+    match OpenOptions::new().write(true).open(db_file) {
+        Ok(_) => false,
+        Err(e) => matches!(
+            e.kind(),
+            std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem
+        ),
+    }
+}
+
+/// Wraps another storage so that everything can be read but nothing is ever
+/// written; saving fails with [`DatabaseError::ReadOnly`].
+pub struct ReadOnlyStorage {
+    inner: Box<dyn Storage>,
+    path: PathBuf,
+}
+
+impl ReadOnlyStorage {
+    pub fn new(mut inner: Box<dyn Storage>, path: &Path) -> Self {
+        inner.set_read_only();
+        Self {
+            inner,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Storage for ReadOnlyStorage {
+    /// A missing database reads as an empty one instead of being created.
+    fn open(&mut self) -> Result<Database, ToNotDoError> {
+        if !self.exists() {
+            return Ok(Database::default());
+        }
+
+        self.load()
+    }
+
+    fn exists(&self) -> bool {
+        self.inner.exists()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        self.inner.load()
+    }
+
+    fn revision(&self) -> Option<Revision> {
+        self.inner.revision()
+    }
+
+    fn save(&mut self, _db: &Database) -> Result<(), ToNotDoError> {
+        Err(ToNotDoError::DatabaseError(DatabaseError::ReadOnly(
+            self.path.clone(),
+        )))
+    }
+}
+
+/// Builds the storage for the database file itself, leaving out the
+/// journal.
+pub fn file_storage(db_file: &Path, config: &StorageConfig) -> FileStorage {
+    partition_storage(db_file, db_file, config)
+}
+
+/// Like [`file_storage`] for `path`, a file belonging to the database at
+/// `db_file` and sharing its encryption key.
+fn partition_storage(path: &Path, db_file: &Path, config: &StorageConfig) -> FileStorage {
+    let mut storage = FileStorage::new(path);
+    if let Some(format) = config.format {
+        storage = storage.with_format(format);
+    }
+
+    storage
+        .with_compression(config.compression)
+        .with_encryption(config.encrypt, Box::new(KeyringKeySource::new(db_file)))
+}
+
+/// Directory holding the per-project files of a partitioned database.
+pub fn partitions_dir(db_file: &Path) -> PathBuf {
+    db_file.with_extension("projects")
+}
+
+/// When the database at `db_file` last changed on disk, in any of the
+/// files the storages keep it in: the file itself, its `journal`, write-ahead
+/// log and partitions. `None` when it has no files, such as a remote one.
+pub fn last_modified(db_file: &Path, journal: &Path) -> Option<std::time::SystemTime> {
+    let partitions = std::fs::read_dir(partitions_dir(db_file))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()));
+    [
+        db_file.to_path_buf(),
+        journal.to_path_buf(),
+        wal_path(db_file),
+    ]
+    .into_iter()
+    .chain(partitions)
+    .filter_map(|path| std::fs::metadata(path).ok()?.modified().ok())
+    .max()
+}
+
+/// Length, modification time and, on Unix, inode of a file, to tell
+/// whether it changed. Saves replace the file with a new one, so the inode
+/// still tells two saves apart when the clock did not tick in between.
+type Fingerprint = (u64, std::time::SystemTime, u64);
+
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = std::fs::metadata(path).ok()?;
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+    Some((metadata.len(), metadata.modified().ok()?, inode))
+}
+
+/// The files of a stored database as they were at one point; see
+/// [`Storage::revision`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision(Vec<Option<Fingerprint>>);
+
+impl Revision {
+    /// The revision of the files at `paths`, any of which may be missing.
+    pub fn of<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Self {
+        Self(
+            paths
+                .into_iter()
+                .map(|path| fingerprint(path.as_ref()))
+                .collect(),
+        )
+    }
+
+    /// This revision together with `other`, for storages built on others.
+    pub fn and(mut self, other: Revision) -> Self {
+        self.0.extend(other.0);
+        self
+    }
+
+    /// The revision of a file that does not exist.
+    pub fn missing() -> Self {
+        Self(vec![None])
+    }
+}
+
+/// File name for the partition of `project`: the name itself with anything
+/// but lowercase ASCII letters, digits, `-` and `_` percent-encoded. Capitals
+/// are encoded too, so projects differing only in case get files that differ
+/// on case-insensitive file systems as well.
+fn partition_file_name(project: &str, extension: &str) -> String {
+    let mut name = String::new();
+    for byte in project.bytes() {
+        if byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    // A lone `%` cannot come out of the encoding above.
+    if name.is_empty() {
+        name.push('%');
+    }
+
+    format!("{}.{}", name, extension)
+}
+
+struct Partition {
+    fingerprint: Option<Fingerprint>,
+    db: Database,
+}
+
+/// Stores the tasks of each project in a file of their own under
+/// [`partitions_dir`], and the remaining tasks with everything else in the
+/// database file. Files are only read again when they changed on disk, and
+/// only written when their part of the database changed.
+pub struct PartitionedStorage {
+    db_file: PathBuf,
+    config: StorageConfig,
+    partitions: BTreeMap<PathBuf, Partition>,
+    read_only: bool,
+}
+
+impl PartitionedStorage {
+    pub fn new(db_file: &Path, config: &StorageConfig) -> Self {
+        Self {
+            db_file: db_file.to_path_buf(),
+            config: config.clone(),
+            partitions: BTreeMap::new(),
+            read_only: false,
+        }
+    }
+
+    fn storage(&self, path: &Path) -> FileStorage {
+        let mut storage = partition_storage(path, &self.db_file, &self.config);
+        storage.read_only = self.read_only;
+        storage
+    }
+
+    fn partition_path(&self, project: &str) -> PathBuf {
+        let extension = self
+            .db_file
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        partitions_dir(&self.db_file).join(partition_file_name(project, &extension))
+    }
+
+    fn partition_paths(&self) -> Result<Vec<PathBuf>, ToNotDoError> {
+        let mut paths = Vec::new();
+
+        match std::fs::read_dir(partitions_dir(&self.db_file)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let path = entry
+                        .map_err(|e| ToNotDoError::DatabaseError(e.into()))?
+                        .path();
+                    if path.is_file() {
+                        paths.push(path);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(ToNotDoError::DatabaseError(e.into())),
+        }
+
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Reads the file at `path`, unless it is unchanged since it was last
+    /// read or written.
+    fn read(&mut self, path: &Path) -> Result<&Database, ToNotDoError> {
+        let current = fingerprint(path);
+        let stale = self.partitions.get(path).is_none_or(|partition| {
+            partition.fingerprint.is_none() || partition.fingerprint != current
+        });
+
+        if stale {
+            let db = self.storage(path).load()?;
+            self.partitions.insert(
+                path.to_path_buf(),
+                Partition {
+                    fingerprint: fingerprint(path),
+                    db,
+                },
+            );
+        }
+
+        Ok(&self.partitions[path].db)
+    }
+
+    /// Writes `db` to the file at `path`, unless it holds that already,
+    /// returning the fingerprint of the file as written.
+    fn write(&mut self, path: &Path, db: Database) -> Result<Option<Fingerprint>, ToNotDoError> {
+        let current = fingerprint(path);
+        let unchanged = self.partitions.get(path).is_some_and(|partition| {
+            partition.fingerprint.is_some()
+                && partition.fingerprint == current
+                && partition.db == db
+        });
+        if unchanged {
+            return Ok(current);
+        }
+
+        let written = self.storage(path).write(&db)?;
+        self.partitions.insert(
+            path.to_path_buf(),
+            Partition {
+                fingerprint: written,
+                db,
+            },
+        );
+        Ok(written)
+    }
+}
+
+impl Storage for PartitionedStorage {
+    fn exists(&self) -> bool {
+        self.db_file.is_file()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        let db_file = self.db_file.clone();
+        let mut db = self.read(&db_file)?.clone();
+
+        for path in self.partition_paths()? {
+            for task in self.read(&path)?.tasks() {
+                db.put_task(task.clone());
+            }
+        }
+
+        Ok(db)
+    }
+
+    fn revision(&self) -> Option<Revision> {
+        let partitions = self.partition_paths().ok()?;
+        Some(Revision::of(
+            std::iter::once(self.db_file.clone()).chain(partitions),
+        ))
+    }
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.save_revised(db).map(|_| ())
+    }
+
+    fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let mut main = db.clone();
+        let projects = if self.config.partition {
+            main.split_by_project()
+        } else {
+            BTreeMap::new()
+        };
+
+        // Projects first, so a crash part way leaves tasks in two files
+        // rather than in none; loading prefers the project file.
+        let mut written = BTreeMap::new();
+        if !projects.is_empty() {
+            std::fs::create_dir_all(partitions_dir(&self.db_file)).map_err(write_error)?;
+        }
+        for (project, part) in projects {
+            let path = self.partition_path(&project);
+            let fingerprint = self.write(&path, part)?;
+            written.insert(path, fingerprint);
+        }
+
+        let db_file = self.db_file.clone();
+        let main = self.write(&db_file, main)?;
+
+        for path in self.partition_paths()? {
+            if !written.contains_key(&path) {
+                std::fs::remove_file(&path).map_err(write_error)?;
+                self.partitions.remove(&path);
+            }
+        }
+        if !self.config.partition {
+            let _ = std::fs::remove_dir(partitions_dir(&self.db_file));
+        }
+
+        // The same order as `revision`, the partitions sorted by path.
+        Ok(Some(Revision(
+            std::iter::once(main).chain(written.into_values()).collect(),
+        )))
+    }
+}
+
+/// Stores the database in a single file, optionally compressed and
+/// encrypted.
+pub struct FileStorage {
+    path: PathBuf,
+    format: Format,
+    compression: Compression,
+    encrypt: bool,
+    keys: Option<Box<dyn KeySource>>,
+    cipher: Option<Cipher>,
+    minified: bool,
+    /// Leaves a file that needed migrating as it is; see
+    /// [`Storage::set_read_only`].
+    read_only: bool,
+}
+
+impl FileStorage {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            format: Format::from_path(path).unwrap_or_default(),
+            compression: Compression::None,
+            encrypt: false,
+            keys: None,
+            cipher: None,
+            minified: false,
+            read_only: false,
+        }
+    }
+
+    /// Saves the file as `format`. Files are read in whatever format they
+    /// were written in when it can be recognized.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Compresses the file with `compression` when saving. Files are always
+    /// read with whatever compression they were written with.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Saves JSON without indentation or line breaks. Files read without line
+    /// breaks stay that way too.
+    pub fn with_minified(mut self, minified: bool) -> Self {
+        self.minified = minified;
+        self
+    }
+
+    /// Reads encrypted files with keys from `keys`. With `encrypt` set, a
+    /// plain file is encrypted the next time it is saved; an encrypted file
+    /// always stays encrypted.
+    pub fn with_encryption(mut self, encrypt: bool, keys: Box<dyn KeySource>) -> Self {
+        self.encrypt = encrypt;
+        self.keys = Some(keys);
+        self
+    }
+
+    fn cipher(&mut self, salt: Option<[u8; encryption::SALT_LEN]>) -> Result<Cipher, ToNotDoError> {
+        if let Some(cipher) = self
+            .cipher
+            .as_ref()
+            .filter(|cipher| salt.is_none_or(|salt| cipher.salt() == salt))
+        {
+            return Ok(cipher.clone());
+        }
+
+        let cipher = match self.keys.as_mut() {
+            Some(keys) => keys.cipher(salt)?,
+            None => {
+                return Err(ToNotDoError::EncryptionError(
+                    "Database is encrypted but no key is available".into(),
+                ))
+            }
+        };
+
+        self.cipher = Some(cipher.clone());
+        Ok(cipher)
+    }
+
+    /// Reads the file into a generic value, without migrating it or checking
+    /// that it is a valid database.
+    pub fn load_value(&mut self) -> Result<Value, ToNotDoError> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(ToNotDoError::DatabaseError(
+                    DatabaseError::FailedToReadFile(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "Database file not found",
+                    )),
+                ))
+            }
+        };
+
+        let data = match encryption::salt_of(&data) {
+            Some(salt) => self.cipher(Some(salt))?.decrypt(&data)?,
+            None => data,
+        };
+
+        let data = Compression::decompress(&data).map_err(|_| invalid_data())?;
+
+        let format = Format::sniff(&data).unwrap_or(self.format);
+        if format == Format::Json && !data.contains(&b'\n') {
+            self.minified = true;
+        }
+
+        format.to_value(&data).map_err(|_| invalid_data())
+    }
+}
+
+fn invalid_data() -> ToNotDoError {
+    ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Failed to read database file",
+    )))
+}
+
+impl Storage for FileStorage {
+    fn exists(&self) -> bool {
+        self.path.exists() && self.path.is_file()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        let mut db = self.load_value()?;
+        let migrated = migration::migrate(&mut db)?;
+        let db = serde_json::from_value(db).map_err(|_| invalid_data())?;
+
+        if migrated && !self.read_only {
+            self.save(&db)?;
+        }
+
+        Ok(db)
+    }
+
+    fn revision(&self) -> Option<Revision> {
+        Some(Revision::of([&self.path]))
+    }
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.write(db).map(|_| ())
+    }
+
+    fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+        Ok(Some(Revision(vec![self.write(db)?])))
+    }
+
+    fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+}
+
+impl FileStorage {
+    /// Saves `db`, returning the fingerprint of the file as written.
+    fn write(&mut self, db: &Database) -> Result<Option<Fingerprint>, ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let data = match self.format {
+            Format::Json if self.minified => serde_json::to_vec(db).map_err(|e| e.to_string()),
+            format => format.to_bytes(db),
+        }
+        .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+
+        let data = self.compression.compress(&data).map_err(write_error)?;
+
+        let data = if self.cipher.is_some() || self.encrypt {
+            self.cipher(None)?.encrypt(&data)?
+        } else {
+            data
+        };
+
+        replace_file(&self.path, &data).map_err(write_error)
+    }
+}
+
+/// Value of `--db` that keeps the database in memory instead of a file.
+pub const MEMORY_DB: &str = ":memory:";
+
+/// Keeps the database in memory only, for tests, demos and one-off pipelines
+/// where nothing should be persisted.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    db: Option<Database>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A storage that starts out holding `db`.
+    pub fn with_database(db: Database) -> Self {
+        Self { db: Some(db) }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn exists(&self) -> bool {
+        self.db.is_some()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        self.db.clone().ok_or_else(|| {
+            ToNotDoError::DatabaseError(DatabaseError::FailedToReadFile(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Database not found",
+            )))
+        })
+    }
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.db = Some(db.clone());
+        Ok(())
+    }
+}
+
+/// Replaces the file at `path` with `contents` without ever leaving it half
+/// written: the data goes to a temporary file in the same directory, is
+/// flushed to disk and then renamed over the original. The new file keeps
+/// the permissions of the original; a file that did not exist yet is only
+/// readable by its owner on Unix.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    replace_file(path, contents).map(|_| ())
+}
+
+/// [`write_atomically`], returning the fingerprint of the new file as it
+/// was before it replaced the original, so another write right after does
+/// not change it.
+fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<Option<Fingerprint>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid path"))?;
+
+    let tmp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut tmp_file = options.open(&tmp_path)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            tmp_file.set_permissions(metadata.permissions())?;
+        }
+        tmp_file.write_all(contents)?;
+        tmp_file.sync_all()?;
+        // Renaming keeps the length, modification time and inode.
+        let written = fingerprint(&tmp_path);
+        std::fs::rename(&tmp_path, path)?;
+        Ok(written)
+    })();
+
+    let written = match result {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    };
+
+    // Persist the rename itself. Directories cannot be opened for syncing on
+    // every platform, so this is best effort.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encryption::Passphrase, file_management::Task};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_creates_database() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        let mut storage = FileStorage::new(&path);
+        assert!(!storage.exists());
+
+        let db = storage.open().unwrap();
+
+        assert!(storage.exists());
+        assert!(db.tasks().is_empty());
+        assert!(storage.load().unwrap().tasks().is_empty());
+    }
+
+    #[test]
+    fn test_read_only_storage_refuses_to_save() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        let mut storage = ReadOnlyStorage::new(Box::new(FileStorage::new(&path)), &path);
+        assert!(storage.open().unwrap().tasks().is_empty());
+        assert!(!path.exists());
+
+        FileStorage::new(&path).open().unwrap();
+        let mut db = storage.open().unwrap();
+        db.insert_task(Task::new("Unsaved")).unwrap();
+
+        assert!(matches!(
+            storage.save(&db),
+            Err(ToNotDoError::DatabaseError(DatabaseError::ReadOnly(_)))
+        ));
+        assert!(storage.load().unwrap().tasks().is_empty());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new();
+        assert!(!storage.exists());
+        assert!(storage.load().is_err());
+
+        let mut db = storage.open().unwrap();
+        db.insert_task(Task::new("Kept in memory")).unwrap();
+        storage.save(&db).unwrap();
+
+        assert_eq!(storage.load().unwrap().tasks(), db.tasks());
+    }
+
+    #[test]
+    fn test_partitioned_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+        let config = StorageConfig {
+            partition: true,
+            ..StorageConfig::default()
+        };
+
+        let mut db = Database::default();
+        db.insert_task(Task::new("Loose")).unwrap();
+        db.insert_task(Task::new("Garden").with_project("home"))
+            .unwrap();
+        db.insert_task(Task::new("Report").with_project("work/q3"))
+            .unwrap();
+
+        let mut storage = open_storage(&path, &config);
+        storage.save(&db).unwrap();
+
+        let home = partitions_dir(&path).join("home.json");
+        let work = partitions_dir(&path).join("work%2Fq3.json");
+        assert!(home.is_file() && work.is_file());
+        assert_eq!(FileStorage::new(&path).load().unwrap().tasks().len(), 1);
+
+        let mut loaded = open_storage(&path, &config).load().unwrap();
+        assert_eq!(loaded.tasks().len(), 3);
+
+        // Only the project that changed is written again.
+        let home_written = std::fs::metadata(&home).unwrap().modified().unwrap();
+        loaded.put_task(Task::new("Slides").with_project("work/q3"));
+        storage.save(&loaded).unwrap();
+        assert_eq!(
+            std::fs::metadata(&home).unwrap().modified().unwrap(),
+            home_written
+        );
+        assert_eq!(storage.load().unwrap().tasks().len(), 4);
+
+        // Turning partitioning off folds the projects back into one file.
+        let mut storage = open_storage(&path, &StorageConfig::default());
+        let db = storage.load().unwrap();
+        storage.save(&db).unwrap();
+        assert!(!partitions_dir(&path).exists());
+        assert_eq!(FileStorage::new(&path).load().unwrap().tasks().len(), 4);
+    }
+
+    #[test]
+    fn test_partition_names_differ_in_more_than_case() {
+        let names: Vec<String> = ["Work", "work", "WORK", "%57ork"]
+            .iter()
+            .map(|project| partition_file_name(project, "json").to_lowercase())
+            .collect();
+        assert_eq!(names[1], "work.json");
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[i + 1..].contains(name), "{} collides", name);
+        }
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+        let config = StorageConfig {
+            partition: true,
+            ..StorageConfig::default()
+        };
+        let mut db = Database::default();
+        db.insert_task(Task::new("Upper").with_project("Work"))
+            .unwrap();
+        db.insert_task(Task::new("Lower").with_project("work"))
+            .unwrap();
+        let mut storage = open_storage(&path, &config);
+        storage.save(&db).unwrap();
+        assert_eq!(std::fs::read_dir(partitions_dir(&path)).unwrap().count(), 2);
+        assert_eq!(
+            open_storage(&path, &config).load().unwrap().tasks().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_write_atomically_replaces_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        write_atomically(&path, b"a much longer first version").unwrap();
+        write_atomically(&path, b"short").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"short");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomically_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let mut storage = open_storage(&path, &StorageConfig::default());
+        storage.open().unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&crate::checksum::checksum_path(&path)), 0o600);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let mut db = storage.load().unwrap();
+        db.insert_task(Task::new("Private")).unwrap();
+        storage.save(&db).unwrap();
+        assert_eq!(mode(&path), 0o640);
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+        std::fs::write(&path, b"original").unwrap();
+
+        // Block the temporary file so the write fails before the rename.
+        let tmp_path = dir
+            .path()
+            .join(format!(".tasks.json.{}.tmp", std::process::id()));
+        std::fs::create_dir(&tmp_path).unwrap();
+
+        assert!(write_atomically(&path, b"new").is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+    }
+
+    #[test]
+    fn test_update_saves_changes() {
+        let dir = tempdir().unwrap();
+        let mut storage = FileStorage::new(&dir.path().join("tasks.json"));
+        storage.open().unwrap();
+
+        let task = Task::new("Stored task");
+        storage
+            .update(&mut |db| db.insert_task(task.clone()))
+            .unwrap();
+
+        assert_eq!(storage.load().unwrap().tasks(), &[task]);
+    }
+
+    #[test]
+    fn test_load_upgrades_legacy_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        std::fs::write(
+            &path,
+            r#"{"name": "to-not-do", "version": "0.0.1", "tasks": []}"#,
+        )
+        .unwrap();
+
+        FileStorage::new(&path).load().unwrap();
+
+        let on_disk: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            migration::schema_version(&on_disk),
+            migration::SCHEMA_VERSION
+        );
+    }
+
+    #[test]
+    fn test_encrypted_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        let mut plain = FileStorage::new(&path);
+        plain.open().unwrap();
+
+        let mut storage =
+            FileStorage::new(&path).with_encryption(true, Box::new(Passphrase("secret")));
+        let task = Task::new("Hidden task");
+        storage
+            .update(&mut |db| db.insert_task(task.clone()))
+            .unwrap();
+
+        assert!(encryption::is_encrypted(&std::fs::read(&path).unwrap()));
+        assert!(plain.load().is_err());
+
+        // Once encrypted, the file stays encrypted even if the config changes.
+        let mut storage =
+            FileStorage::new(&path).with_encryption(false, Box::new(Passphrase("secret")));
+        assert_eq!(storage.load().unwrap().tasks(), std::slice::from_ref(&task));
+        let db = storage.load().unwrap();
+        storage.save(&db).unwrap();
+        assert!(encryption::is_encrypted(&std::fs::read(&path).unwrap()));
+
+        let mut wrong =
+            FileStorage::new(&path).with_encryption(true, Box::new(Passphrase("guess")));
+        assert!(wrong.load().is_err());
+    }
+
+    #[test]
+    fn test_compressed_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.json");
+
+        let mut storage = FileStorage::new(&path).with_compression(Compression::Zstd);
+        let task = Task::new("Squeezed task");
+        storage.open().unwrap();
+        storage
+            .update(&mut |db| db.insert_task(task.clone()))
+            .unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(Compression::detect(&data), Compression::Zstd);
+
+        // Detected on load regardless of the configured compression.
+        let mut storage = FileStorage::new(&path).with_compression(Compression::Gzip);
+        assert_eq!(storage.load().unwrap().tasks(), std::slice::from_ref(&task));
+
+        storage.save(&Database::default()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(Compression::detect(&data), Compression::Gzip);
+    }
+
+    #[test]
+    fn test_message_pack_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tasks.msgpack");
+
+        let mut storage = FileStorage::new(&path);
+        let task = Task::new("Packed task");
+        storage.open().unwrap();
+        storage
+            .update(&mut |db| db.insert_task(task.clone()))
+            .unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(Format::sniff(&data), Some(Format::MessagePack));
+
+        // The format is recognized on load whatever the storage is set to.
+        let mut storage = FileStorage::new(&path).with_format(Format::Json);
+        assert_eq!(storage.load().unwrap().tasks(), std::slice::from_ref(&task));
+    }
+
+    #[test]
+    fn test_text_format_storage() {
+        let dir = tempdir().unwrap();
+        let task = Task::new("Hand edited task")
+            .with_tags(&["dotfiles".to_string()])
+            .with_notes("Keep in git");
+
+        for name in ["tasks.toml", "tasks.yaml"] {
+            let path = dir.path().join(name);
+            let mut storage = FileStorage::new(&path);
+            storage.open().unwrap();
+            storage
+                .update(&mut |db| db.insert_task(task.clone()))
+                .unwrap();
+
+            let text = std::fs::read_to_string(&path).unwrap();
+            assert!(text.contains("Hand edited task"));
+            assert!(!text.trim_start().starts_with('{'));
+
+            assert_eq!(
+                FileStorage::new(&path).load().unwrap().tasks(),
+                std::slice::from_ref(&task)
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_update_is_not_saved() {
+        let dir = tempdir().unwrap();
+        let mut storage = FileStorage::new(&dir.path().join("tasks.json"));
+        storage.open().unwrap();
+
+        let result = storage.update(&mut |db| {
+            db.insert_task(Task::new("Never saved"))?;
+            Err(ToNotDoError::ConfigError("rejected".to_string()))
+        });
+
+        assert!(result.is_err());
+        assert!(storage.load().unwrap().tasks().is_empty());
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Database, Task, TaskField},
+    journal::{append_events, read_events, truncate_partial_event, Change, Event},
+    storage::Storage,
+};
+
+/// Token used to authenticate against a sync server when none is passed on
+/// the command line.
+pub const SYNC_TOKEN_ENV: &str = "TO_NOT_DO_SYNC_TOKEN";
+/// Endpoint that takes pushed changes (`POST`) and hands out pulled ones
+/// (`GET ?since=<cursor>`).
+pub const OPS_ENDPOINT: &str = "/sync/ops";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushRequest {
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushResponse {
+    /// Cursor of the first pushed event in the server's log.
+    pub start: usize,
+    /// Cursor just past the pushed events.
+    pub cursor: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullQuery {
+    #[serde(default)]
+    pub since: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullResponse {
+    pub events: Vec<Event>,
+    /// Cursor to pull from next time.
+    pub cursor: usize,
+}
+
+/// What a [`sync`] exchanged with the server.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub pushed: usize,
+    pub pulled: usize,
+    /// Tasks pulled with fields also edited here, left for `sync resolve`.
+    pub conflicts: usize,
+}
+
+/// A task edited both here and on another replica since the last sync.
+/// The sync keeps whichever edit of each field came last; the copies are
+/// kept until someone has looked at them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub ours: Task,
+    pub theirs: Task,
+    /// The fields the two copies disagree on.
+    pub fields: Vec<TaskField>,
+}
+
+/// How a replica stands with its sync server, for `sync status`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SyncStatus {
+    pub remote: Option<String>,
+    pub synced_at: Option<DateTime<Utc>>,
+    /// Changes made here that the next sync pushes.
+    pub pending: usize,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+/// Log of every change the server accepted, kept next to its database. A
+/// cursor is the number of events a replica has already seen.
+pub fn ops_log_path(db_file: &Path) -> PathBuf {
+    db_file.with_extension("ops")
+}
+
+/// Server side of a pull: the events after `since`.
+pub fn pull(log: &Path, since: usize) -> Result<PullResponse, ToNotDoError> {
+    let (events, _) = read_events(log)?;
+    let cursor = events.len();
+
+    Ok(PullResponse {
+        events: events.into_iter().skip(since).collect(),
+        cursor,
+    })
+}
+
+/// Server side of a push: merges `events` into the database in `storage` and
+/// appends them to the log for other replicas to pull.
+pub fn push(
+    storage: &mut dyn Storage,
+    log: &Path,
+    events: Vec<Event>,
+) -> Result<PushResponse, ToNotDoError> {
+    let events: Vec<Event> = events
+        .into_iter()
+        .filter(|event| is_shared(&event.change))
+        .collect();
+
+    let (existing, complete) = read_events(log)?;
+    let start = existing.len();
+
+    if !events.is_empty() {
+        storage.open()?;
+        storage.update(&mut |db| {
+            for event in &events {
+                db.merge_change(&event.change);
+            }
+            Ok(())
+        })?;
+
+        truncate_partial_event(log, complete)?;
+        append_events(log, &events)?;
+    }
+
+    Ok(PushResponse {
+        start,
+        cursor: start + events.len(),
+    })
+}
+
+/// Only tasks are shared between replicas; the focus and context belong to
+/// each machine.
+fn is_shared(change: &Change) -> bool {
+    matches!(change, Change::PutTask { .. } | Change::RemoveTask { .. })
+}
+
+/// What a replica remembers between syncs, kept in its state directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    remote: Option<String>,
+    cursor: usize,
+    /// The database as of the last sync, to tell which changes are new.
+    base: Database,
+    #[serde(default)]
+    synced_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    conflicts: Vec<SyncConflict>,
+}
+
+impl SyncState {
+    fn read(path: &Path) -> Result<Self, ToNotDoError> {
+        match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| ToNotDoError::SyncError(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(ToNotDoError::DatabaseError(e.into())),
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+struct Client {
+    agent: ureq::Agent,
+    url: String,
+    authorization: Option<String>,
+}
+
+impl Client {
+    fn new(remote: &str, token: Option<&str>) -> Self {
+        Self {
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            url: format!("{}{}", remote, OPS_ENDPOINT),
+            authorization: token.map(|token| format!("Bearer {}", token)),
+        }
+    }
+
+    fn push(&self, events: Vec<Event>) -> Result<PushResponse, ToNotDoError> {
+        let mut request = self.agent.post(&self.url);
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization);
+        }
+
+        request
+            .send_json(PushRequest { events })
+            .and_then(|mut response| response.body_mut().read_json())
+            .map_err(|e| ToNotDoError::SyncError(format!("{}: {}", self.url, e)))
+    }
+
+    fn pull(&self, since: usize) -> Result<PullResponse, ToNotDoError> {
+        let mut request = self.agent.get(&self.url).query("since", since.to_string());
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization);
+        }
+
+        request
+            .call()
+            .and_then(|mut response| response.body_mut().read_json())
+            .map_err(|e| ToNotDoError::SyncError(format!("{}: {}", self.url, e)))
+    }
+}
+
+/// Exchanges changes with the sync server at `remote`, or the one used last
+/// time: pulls what other replicas pushed since the last sync, pushes what
+/// changed here, and merges the two. What was synced is remembered in
+/// `state_path`.
+pub fn sync(
+    storage: &mut dyn Storage,
+    state_path: &Path,
+    remote: Option<&str>,
+    token: Option<&str>,
+) -> Result<SyncReport, ToNotDoError> {
+    let mut state = SyncState::read(state_path)?;
+
+    let remote = match remote {
+        Some(remote) => remote.trim_end_matches('/').to_string(),
+        None => state
+            .remote
+            .clone()
+            .ok_or_else(|| ToNotDoError::SyncError("no remote given".to_string()))?,
+    };
+
+    // A different server has its own log, so start over with it.
+    if state.remote.as_deref() != Some(remote.as_str()) {
+        state = SyncState {
+            remote: Some(remote.clone()),
+            ..SyncState::default()
+        };
+    }
+
+    let client = Client::new(&remote, token);
+    let local = storage.open()?;
+    let pulled = client.pull(state.cursor)?;
+
+    let at = Utc::now();
+    let events: Vec<Event> = state
+        .base
+        .changes_to(&local)
+        .into_iter()
+        .filter(is_shared)
+        .map(|change| Event { at, change })
+        .collect();
+    let pushed_count = events.len();
+    let pushed = client.push(events)?;
+
+    let mut conflicts = 0;
+    for event in &pulled.events {
+        let Change::PutTask { task: theirs } = &event.change else {
+            continue;
+        };
+        let (Some(ours), Some(base)) = (local.task(theirs.id()), state.base.task(theirs.id()))
+        else {
+            continue;
+        };
+        let fields = ours.conflicting_fields(base, theirs);
+        if !fields.is_empty() {
+            state.conflicts.retain(|c| c.ours.id() != ours.id());
+            state.conflicts.push(SyncConflict {
+                ours: ours.clone(),
+                theirs: (**theirs).clone(),
+                fields,
+            });
+            conflicts += 1;
+        }
+    }
+
+    storage.update(&mut |db| {
+        for event in &pulled.events {
+            db.merge_change(&event.change);
+        }
+        Ok(())
+    })?;
+
+    let mut base = local;
+    for event in &pulled.events {
+        base.merge_change(&event.change);
+    }
+
+    // Skip over our own events unless another replica pushed in between,
+    // in which case they are pulled again next time and merge as no-ops.
+    state.cursor = if pushed.start == pulled.cursor {
+        pushed.cursor
+    } else {
+        pulled.cursor
+    };
+    state.base = base;
+    state.synced_at = Some(at);
+    state.write(state_path)?;
+
+    Ok(SyncReport {
+        pushed: pushed_count,
+        pulled: pulled.events.len(),
+        conflicts,
+    })
+}
+
+/// When the replica last synced, what it would push now and the conflicts
+/// left from earlier syncs.
+pub fn status(storage: &mut dyn Storage, state_path: &Path) -> Result<SyncStatus, ToNotDoError> {
+    let state = SyncState::read(state_path)?;
+    let local = storage.open()?;
+    let pending = state
+        .base
+        .changes_to(&local)
+        .iter()
+        .filter(|change| is_shared(change))
+        .count();
+
+    Ok(SyncStatus {
+        remote: state.remote,
+        synced_at: state.synced_at,
+        pending,
+        conflicts: state.conflicts,
+    })
+}
+
+/// The conflicts left from earlier syncs.
+pub fn conflicts(state_path: &Path) -> Result<Vec<SyncConflict>, ToNotDoError> {
+    Ok(SyncState::read(state_path)?.conflicts)
+}
+
+/// Forgets the conflict over the task `id` once it has been looked at.
+pub fn dismiss_conflict(state_path: &Path, id: Uuid) -> Result<(), ToNotDoError> {
+    let mut state = SyncState::read(state_path)?;
+    state.conflicts.retain(|conflict| conflict.ours.id() != id);
+    state.write(state_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{file_management::Task, storage::FileStorage};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_push_and_pull() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("server.json");
+        let log = ops_log_path(&db_file);
+        let mut storage = FileStorage::new(&db_file);
+
+        let task = Task::new("Pushed");
+        let events = vec![
+            Event {
+                at: Utc::now(),
+                change: Change::PutTask {
+                    task: Box::new(task.clone()),
+                },
+            },
+            Event {
+                at: Utc::now(),
+                change: Change::SetContext {
+                    context: Some("work".to_string()),
+                },
+            },
+        ];
+
+        let pushed = push(&mut storage, &log, events).unwrap();
+        assert_eq!((pushed.start, pushed.cursor), (0, 1));
+        assert_eq!(storage.load().unwrap().tasks(), std::slice::from_ref(&task));
+
+        assert_eq!(pull(&log, 0).unwrap().events.len(), 1);
+        let pulled = pull(&log, 1).unwrap();
+        assert!(pulled.events.is_empty());
+        assert_eq!(pulled.cursor, 1);
+    }
+}
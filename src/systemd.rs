@@ -0,0 +1,166 @@
+//! systemd user units running `notify-daemon` and `tick`, written by
+//! `generate systemd`.
+
+use std::path::Path;
+
+use crate::file_management::APP_NAME;
+
+/// A unit file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub name: String,
+    pub contents: String,
+}
+
+/// What the units run, and when.
+#[derive(Debug, Clone)]
+pub struct UnitOptions<'a> {
+    /// The `to-not-do` executable.
+    pub exe: &'a Path,
+    /// Arguments given before the command, such as `--profile`.
+    pub global_args: &'a [String],
+    /// Added to the unit names, so each profile gets units of its own.
+    pub suffix: Option<&'a str>,
+    /// When `tick` runs, as a systemd calendar event such as `hourly`.
+    pub tick_calendar: &'a str,
+    /// Include a unit for `notify-daemon`.
+    pub notify: bool,
+}
+
+/// The units for `options`: a service for `notify-daemon`, and a service
+/// for `tick` with the timer that starts it.
+pub fn units(options: &UnitOptions) -> Vec<Unit> {
+    let name = |unit: &str, kind: &str| match options.suffix {
+        Some(suffix) => format!("{}-{}-{}.{}", APP_NAME, unit, suffix, kind),
+        None => format!("{}-{}.{}", APP_NAME, unit, kind),
+    };
+    let description = |what: &str| match options.suffix {
+        Some(suffix) => format!("{} {} ({})", APP_NAME, what, suffix),
+        None => format!("{} {}", APP_NAME, what),
+    };
+    let exec = |command: &str| {
+        std::iter::once(options.exe.to_string_lossy().into_owned())
+            .chain(options.global_args.iter().cloned())
+            .chain(std::iter::once(command.to_string()))
+            .map(|arg| quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut units = Vec::new();
+    if options.notify {
+        units.push(Unit {
+            name: name("notify", "service"),
+            contents: format!(
+                "[Unit]\n\
+                 Description={}\n\
+                 After=graphical-session.target\n\
+                 PartOf=graphical-session.target\n\
+                 \n\
+                 [Service]\n\
+                 ExecStart={}\n\
+                 Restart=on-failure\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=graphical-session.target\n",
+                description("reminders"),
+                exec("notify-daemon")
+            ),
+        });
+    }
+
+    let tick_service = name("tick", "service");
+    units.push(Unit {
+        name: tick_service.clone(),
+        contents: format!(
+            "[Unit]\n\
+             Description={}\n\
+             \n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart={}\n",
+            description("upkeep"),
+            exec("tick")
+        ),
+    });
+    units.push(Unit {
+        name: name("tick", "timer"),
+        contents: format!(
+            "[Unit]\n\
+             Description={}\n\
+             \n\
+             [Timer]\n\
+             OnCalendar={}\n\
+             Persistent=true\n\
+             Unit={}\n\
+             \n\
+             [Install]\n\
+             WantedBy=timers.target\n",
+            description("upkeep timer"),
+            options.tick_calendar,
+            tick_service
+        ),
+    });
+    units
+}
+
+/// Quotes `arg` for an `ExecStart=` line.
+fn quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\;$".contains(c)) {
+        return arg;
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        let global_args = ["--profile".to_string(), "work".to_string()];
+        let options = UnitOptions {
+            exe: Path::new("/opt/my apps/to-not-do"),
+            global_args: &global_args,
+            suffix: Some("work"),
+            tick_calendar: "*:0/15",
+            notify: true,
+        };
+        let units = units(&options);
+
+        let names: Vec<&str> = units.iter().map(|unit| unit.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "to-not-do-notify-work.service",
+                "to-not-do-tick-work.service",
+                "to-not-do-tick-work.timer"
+            ]
+        );
+        assert!(units[0]
+            .contents
+            .contains("ExecStart=\"/opt/my apps/to-not-do\" --profile work notify-daemon\n"));
+        assert!(units[1].contents.contains("Type=oneshot\n"));
+        assert!(units[2].contents.contains("OnCalendar=*:0/15\n"));
+        assert!(units[2]
+            .contents
+            .contains("Unit=to-not-do-tick-work.service\n"));
+
+        let units = super::units(&UnitOptions {
+            suffix: None,
+            notify: false,
+            ..options
+        });
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].name, "to-not-do-tick.service");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("tick"), "tick");
+        assert_eq!(quote("50%"), "50%%");
+        assert_eq!(quote("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
@@ -0,0 +1,580 @@
+//! A Telegram bot for `bot telegram`, to capture and tick off tasks from a
+//! phone.
+//!
+//! Any text sent to the bot is added as a task: the first line is the
+//! description and the lines after it the notes. `/today` lists the open
+//! tasks due today or overdue, each with a button that marks it done.
+//!
+//! The bot answers only the chats in `allowed_chats` of the `[telegram]`
+//! config section. Any other chat is told its ID, so it can be added there.
+
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    error::ToNotDoError,
+    file_management::{DatabaseManager, Task, TaskState},
+};
+
+/// Read for the bot token when the `[telegram]` section gives none.
+pub const TELEGRAM_TOKEN_ENV: &str = "TO_NOT_DO_TELEGRAM_TOKEN";
+
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+/// How long a poll for updates waits for one to come.
+const POLL_SECONDS: u64 = 30;
+
+/// How long a single request may take, polls included.
+const TIMEOUT: Duration = Duration::from_secs(POLL_SECONDS + 30);
+
+/// Most tasks `/today` lists, to stay within the size of a message.
+const AGENDA_LIMIT: usize = 50;
+
+/// Longest button label, in characters.
+const LABEL_LENGTH: usize = 40;
+
+/// Callback data of the button marking a task done under the reply to an
+/// added task; the task ID follows.
+const DONE_BUTTON: &str = "done:";
+
+/// Callback data of the button marking a task done in the agenda.
+const AGENDA_BUTTON: &str = "today:";
+
+const HELP: &str = "Send me a task and I will add it; lines after the first become its notes.\n\
+                    /today lists what is due today or overdue.";
+
+/// Settings from the `[telegram]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    /// Bot token from @BotFather. Falls back to the
+    /// `TO_NOT_DO_TELEGRAM_TOKEN` environment variable.
+    pub token: Option<String>,
+    /// IDs of the chats the bot answers.
+    pub allowed_chats: Vec<i64>,
+    /// Where the Bot API is, when not at Telegram itself.
+    pub api_url: Option<String>,
+}
+
+fn telegram_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::TelegramError(reason.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Chat {
+    pub id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Message {
+    pub message_id: i64,
+    pub chat: Chat,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+/// A press on a button under a message.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CallbackQuery {
+    pub id: String,
+    /// The message with the button.
+    #[serde(default)]
+    pub message: Option<Message>,
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+/// Something that happened in a chat with the bot; only messages and
+/// button presses are of interest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Update {
+    pub update_id: i64,
+    #[serde(default)]
+    pub message: Option<Message>,
+    #[serde(default)]
+    pub callback_query: Option<CallbackQuery>,
+}
+
+/// A message from the bot, with a row of buttons per `(label, data)` pair.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reply {
+    pub text: String,
+    pub buttons: Vec<(String, String)>,
+}
+
+impl Reply {
+    fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            buttons: Vec::new(),
+        }
+    }
+
+    fn keyboard(&self) -> Value {
+        let rows: Vec<Value> = self
+            .buttons
+            .iter()
+            .map(|(label, data)| json!([{ "text": label, "callback_data": data }]))
+            .collect();
+        json!({ "inline_keyboard": rows })
+    }
+}
+
+/// What the bot needs of the Bot API.
+pub trait TelegramApi {
+    /// The updates from `offset` on, waiting a while for one if there are
+    /// none yet.
+    fn updates(&self, offset: i64) -> Result<Vec<Update>, ToNotDoError>;
+
+    fn send(&self, chat: i64, reply: &Reply) -> Result<(), ToNotDoError>;
+
+    /// Replaces a message sent before, buttons included.
+    fn edit(&self, chat: i64, message_id: i64, reply: &Reply) -> Result<(), ToNotDoError>;
+
+    /// Acknowledges a button press, showing `text` briefly.
+    fn answer(&self, callback_id: &str, text: &str) -> Result<(), ToNotDoError>;
+}
+
+/// What every Bot API method answers.
+#[derive(Deserialize)]
+struct Answer<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+/// The Bot API, with the token from the `[telegram]` section.
+pub struct TelegramClient {
+    agent: ureq::Agent,
+    api_url: String,
+    token: String,
+}
+
+impl TelegramClient {
+    pub fn new(config: &TelegramConfig) -> Result<Self, ToNotDoError> {
+        let token = config
+            .token
+            .clone()
+            .or_else(|| std::env::var(TELEGRAM_TOKEN_ENV).ok())
+            .ok_or_else(|| {
+                telegram_error(format!(
+                    "No bot token; set token in the [telegram] config section or {}",
+                    TELEGRAM_TOKEN_ENV
+                ))
+            })?;
+        let api_url = config
+            .api_url
+            .as_deref()
+            .unwrap_or(DEFAULT_API_URL)
+            .trim_end_matches('/');
+
+        Ok(Self {
+            // Failures are reported in the answer, with a description.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            api_url: api_url.to_string(),
+            token,
+        })
+    }
+
+    /// The failure of `method`, without the token, which is in the URL.
+    fn failed(&self, method: &str, reason: impl std::fmt::Display) -> ToNotDoError {
+        let reason = reason.to_string().replace(&self.token, "<token>");
+        telegram_error(format!("{} failed: {}", method, reason))
+    }
+
+    /// Calls `method` with the parameters in `body`.
+    fn call<T: DeserializeOwned>(&self, method: &str, body: Value) -> Result<T, ToNotDoError> {
+        let answer: Answer<T> = self
+            .agent
+            .post(format!("{}/bot{}/{}", self.api_url, self.token, method))
+            .send_json(body)
+            .map_err(|e| self.failed(method, e))?
+            .body_mut()
+            .read_json()
+            .map_err(|e| self.failed(method, e))?;
+        match answer {
+            Answer {
+                ok: true,
+                result: Some(result),
+                ..
+            } => Ok(result),
+            Answer { description, .. } => Err(self.failed(
+                method,
+                description.unwrap_or_else(|| "no reason given".to_string()),
+            )),
+        }
+    }
+
+    /// The username of the bot, checking the token on the way.
+    pub fn username(&self) -> Result<String, ToNotDoError> {
+        #[derive(Deserialize)]
+        struct User {
+            username: String,
+        }
+
+        let user: User = self.call("getMe", json!({}))?;
+        Ok(user.username)
+    }
+}
+
+impl TelegramApi for TelegramClient {
+    fn updates(&self, offset: i64) -> Result<Vec<Update>, ToNotDoError> {
+        self.call(
+            "getUpdates",
+            json!({
+                "offset": offset,
+                "timeout": POLL_SECONDS,
+                "allowed_updates": ["message", "callback_query"],
+            }),
+        )
+    }
+
+    fn send(&self, chat: i64, reply: &Reply) -> Result<(), ToNotDoError> {
+        self.call::<Value>(
+            "sendMessage",
+            json!({ "chat_id": chat, "text": reply.text, "reply_markup": reply.keyboard() }),
+        )
+        .map(|_| ())
+    }
+
+    fn edit(&self, chat: i64, message_id: i64, reply: &Reply) -> Result<(), ToNotDoError> {
+        self.call::<Value>(
+            "editMessageText",
+            json!({
+                "chat_id": chat,
+                "message_id": message_id,
+                "text": reply.text,
+                "reply_markup": reply.keyboard(),
+            }),
+        )
+        .map(|_| ())
+    }
+
+    fn answer(&self, callback_id: &str, text: &str) -> Result<(), ToNotDoError> {
+        self.call::<Value>(
+            "answerCallbackQuery",
+            json!({ "callback_query_id": callback_id, "text": text }),
+        )
+        .map(|_| ())
+    }
+}
+
+/// `text` cut to fit on a button.
+fn label(text: &str) -> String {
+    if text.chars().count() <= LABEL_LENGTH {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(LABEL_LENGTH - 1).collect();
+    format!("{}…", cut.trim_end())
+}
+
+/// The open tasks due by `today`, oldest due date first, each with a button
+/// marking it done.
+pub fn agenda(tasks: &[Task], today: NaiveDate) -> Reply {
+    let mut due: Vec<&Task> = tasks
+        .iter()
+        .filter(|task| !task.is_archived() && task.state() != TaskState::Done)
+        .filter(|task| task.due().is_some_and(|due| due <= today))
+        .collect();
+    if due.is_empty() {
+        return Reply::text("Nothing is due today.");
+    }
+    due.sort_by_key(|task| task.due());
+
+    let mut text = format!("Due by {}:", today.format("%a %-d %b"));
+    for task in due.iter().take(AGENDA_LIMIT) {
+        text.push_str("\n• ");
+        text.push_str(task.description());
+        if let Some(due) = task.due().filter(|&due| due < today) {
+            text.push_str(&format!(" (due {})", due));
+        }
+    }
+    if due.len() > AGENDA_LIMIT {
+        text.push_str(&format!("\nand {} more", due.len() - AGENDA_LIMIT));
+    }
+
+    Reply {
+        text,
+        buttons: due
+            .iter()
+            .take(AGENDA_LIMIT)
+            .map(|task| {
+                (
+                    format!("✓ {}", label(task.description())),
+                    format!("{}{}", AGENDA_BUTTON, task.id()),
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Acts on one update, as of `today`. Failures to reach Telegram are
+/// returned; failures of the task store are told to the chat.
+pub fn handle(
+    db_manager: &mut DatabaseManager,
+    api: &dyn TelegramApi,
+    config: &TelegramConfig,
+    update: &Update,
+    today: NaiveDate,
+) -> Result<(), ToNotDoError> {
+    if let Some(message) = &update.message {
+        let chat = message.chat.id;
+        if !config.allowed_chats.contains(&chat) {
+            return api.send(
+                chat,
+                &Reply::text(format!(
+                    "This chat ({}) may not use this bot; add it to allowed_chats in the \
+                     [telegram] config section.",
+                    chat
+                )),
+            );
+        }
+        let Some(text) = message.text.as_deref().map(str::trim) else {
+            return Ok(());
+        };
+        let reply = respond(db_manager, text, today).unwrap_or_else(|e| Reply::text(e.to_string()));
+        return api.send(chat, &reply);
+    }
+
+    if let Some(query) = &update.callback_query {
+        let Some(message) = query
+            .message
+            .as_ref()
+            .filter(|message| config.allowed_chats.contains(&message.chat.id))
+        else {
+            return api.answer(&query.id, "Not allowed");
+        };
+        let data = query.data.as_deref().unwrap_or_default();
+        let (in_agenda, id) = match (
+            data.strip_prefix(AGENDA_BUTTON),
+            data.strip_prefix(DONE_BUTTON),
+        ) {
+            (Some(id), _) => (true, id),
+            (None, Some(id)) => (false, id),
+            (None, None) => return api.answer(&query.id, "Unknown button"),
+        };
+
+        let done = Uuid::parse_str(id)
+            .map_err(|e| telegram_error(format!("Invalid task ID {}: {}", id, e)))
+            .and_then(|id| complete(db_manager, id));
+        let task = match done {
+            Ok(task) => task,
+            Err(e) => return api.answer(&query.id, &e.to_string()),
+        };
+        api.answer(&query.id, &format!("Done: {}", task.description()))?;
+
+        let reply = if in_agenda {
+            agenda(db_manager.get_tasks()?, today)
+        } else {
+            Reply::text(format!("✓ {}", task.description()))
+        };
+        return api.edit(message.chat.id, message.message_id, &reply);
+    }
+
+    Ok(())
+}
+
+/// The reply to `text`, a command or a task to add.
+fn respond(
+    db_manager: &mut DatabaseManager,
+    text: &str,
+    today: NaiveDate,
+) -> Result<Reply, ToNotDoError> {
+    if let Some(command) = text.strip_prefix('/') {
+        // In groups, commands come as /today@name_of_bot.
+        let command = command.split_whitespace().next().unwrap_or_default();
+        let command = command.split('@').next().unwrap_or_default();
+        return Ok(match command {
+            "today" => agenda(db_manager.get_tasks()?, today),
+            _ => Reply::text(HELP),
+        });
+    }
+
+    let (description, notes) = text.split_once('\n').unwrap_or((text, ""));
+    let description = description.trim();
+    if description.is_empty() {
+        return Ok(Reply::text(HELP));
+    }
+    let mut task = Task::new(description);
+    if !notes.trim().is_empty() {
+        task = task.with_notes(notes.trim());
+    }
+    db_manager.add_task(&task)?;
+
+    Ok(Reply {
+        text: format!("Added: {}", task.description()),
+        buttons: vec![(
+            "✓ Done".to_string(),
+            format!("{}{}", DONE_BUTTON, task.id()),
+        )],
+    })
+}
+
+/// Marks the task with ID `id` done, returning it.
+fn complete(db_manager: &mut DatabaseManager, id: Uuid) -> Result<Task, ToNotDoError> {
+    db_manager.set_task_state(id, TaskState::Done)?;
+    db_manager
+        .get_task(id)
+        .cloned()
+        .ok_or_else(|| telegram_error(format!("No task {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+
+    const CHAT: i64 = 42;
+
+    /// Records what the bot sends.
+    #[derive(Default)]
+    struct MemoryChat {
+        sent: RefCell<Vec<(i64, Reply)>>,
+        edited: RefCell<Vec<(i64, i64, Reply)>>,
+        answered: RefCell<Vec<String>>,
+    }
+
+    impl TelegramApi for MemoryChat {
+        fn updates(&self, _offset: i64) -> Result<Vec<Update>, ToNotDoError> {
+            Ok(Vec::new())
+        }
+
+        fn send(&self, chat: i64, reply: &Reply) -> Result<(), ToNotDoError> {
+            self.sent.borrow_mut().push((chat, reply.clone()));
+            Ok(())
+        }
+
+        fn edit(&self, chat: i64, message_id: i64, reply: &Reply) -> Result<(), ToNotDoError> {
+            self.edited
+                .borrow_mut()
+                .push((chat, message_id, reply.clone()));
+            Ok(())
+        }
+
+        fn answer(&self, _callback_id: &str, text: &str) -> Result<(), ToNotDoError> {
+            self.answered.borrow_mut().push(text.to_string());
+            Ok(())
+        }
+    }
+
+    fn message(chat: i64, text: &str) -> Update {
+        serde_json::from_value(json!({
+            "update_id": 1,
+            "message": {
+                "message_id": 7,
+                "from": {"id": chat, "is_bot": false, "first_name": "Sam"},
+                "chat": {"id": chat, "type": "private", "first_name": "Sam"},
+                "date": 1741600000,
+                "text": text,
+            }
+        }))
+        .unwrap()
+    }
+
+    fn press(data: &str) -> Update {
+        serde_json::from_value(json!({
+            "update_id": 2,
+            "callback_query": {
+                "id": "4382bfdwdsb323b2d9",
+                "from": {"id": CHAT, "is_bot": false, "first_name": "Sam"},
+                "message": {"message_id": 8, "chat": {"id": CHAT, "type": "private"}, "date": 1741600000},
+                "chat_instance": "-5093",
+                "data": data,
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_agenda() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let yesterday = today.pred_opt().unwrap();
+        let tasks = [
+            Task::new("Later").with_due(today.succ_opt().unwrap()),
+            Task::new("Water the plants").with_due(today),
+            Task::new("Pay the rent").with_due(yesterday),
+            Task::new("Done").with_due(today).with_completed_at(today),
+            Task::new("Someday"),
+        ];
+
+        let reply = agenda(&tasks, today);
+        assert_eq!(
+            reply.text,
+            "Due by Mon 10 Mar:\n• Pay the rent (due 2025-03-09)\n• Water the plants"
+        );
+        assert_eq!(reply.buttons[0].0, "✓ Pay the rent");
+        assert_eq!(reply.buttons[1].1, format!("today:{}", tasks[1].id()));
+        assert_eq!(
+            reply.keyboard()["inline_keyboard"][0][0]["callback_data"],
+            format!("today:{}", tasks[2].id())
+        );
+        assert_eq!(agenda(&[], today).text, "Nothing is due today.");
+
+        assert_eq!(label(&"a".repeat(40)), "a".repeat(40));
+        assert_eq!(label(&"a".repeat(41)), format!("{}…", "a".repeat(39)));
+    }
+
+    #[test]
+    fn test_handle() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let config = TelegramConfig {
+            allowed_chats: vec![CHAT],
+            ..Default::default()
+        };
+        let chat = MemoryChat::default();
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let rent = Task::new("Pay the rent").with_due(today);
+        db_manager.add_task(&rent).unwrap();
+        let mut handle = |update: Update| {
+            handle(&mut db_manager, &chat, &config, &update, today).unwrap();
+        };
+
+        // Strangers are told the ID to allow.
+        handle(message(7, "Buy milk"));
+        assert!(chat.sent.borrow()[0].1.text.contains("(7)"));
+
+        handle(message(CHAT, "Buy milk\noat, not soy\n"));
+        let added = chat.sent.borrow()[1].1.clone();
+        assert_eq!(added.text, "Added: Buy milk");
+        let done = added.buttons[0].1.clone();
+
+        handle(message(CHAT, "/today@tonotdo_bot"));
+        assert_eq!(chat.sent.borrow()[2].1.buttons.len(), 1);
+        handle(message(CHAT, "/start"));
+        assert_eq!(chat.sent.borrow()[3].1.text, HELP);
+
+        handle(press(&done));
+        handle(press(&format!("today:{}", rent.id())));
+        handle(press(&format!("today:{}", Uuid::nil())));
+        assert_eq!(
+            *chat.answered.borrow(),
+            [
+                "Done: Buy milk".to_string(),
+                "Done: Pay the rent".to_string(),
+                format!("Task not found: {}", Uuid::nil()),
+            ]
+        );
+        let edited = chat.edited.borrow();
+        assert_eq!(edited[0], (CHAT, 8, Reply::text("✓ Buy milk")));
+        assert_eq!(edited[1].2.text, "Nothing is due today.");
+        drop(edited);
+
+        let tasks = db_manager.get_tasks().unwrap();
+        let milk = tasks
+            .iter()
+            .find(|t| t.description() == "Buy milk")
+            .unwrap();
+        assert_eq!(milk.notes(), Some("oat, not soy"));
+        assert!(tasks.iter().all(|t| t.state() == TaskState::Done));
+    }
+}
@@ -0,0 +1,1125 @@
+//! Tasks in Todoist, through its REST API: `import --from todoist` copies
+//! the open ones, and `sync todoist` keeps both sides in step.
+//!
+//! The content, description, project, labels, priority, due date and
+//! completion of a task are synced. Todoist's inbox is the lack of a project
+//! here, and its priorities p1 to p3 are high, medium and low. Due times are
+//! dropped, keeping the date. Todoist has no tasks in progress, so those are
+//! open tasks there.
+//!
+//! Each sync compares both sides with how the previous one left them, which
+//! is kept in the state directory, and takes every field from the side that
+//! changed it. When both changed a field, the [`MergePreference`] decides.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::{merge_field, DatabaseManager, MergePreference, Priority, Task, TaskState},
+};
+
+/// Read for the API token when the `[todoist]` section gives none.
+pub const TODOIST_TOKEN_ENV: &str = "TO_NOT_DO_TODOIST_TOKEN";
+
+const DEFAULT_API_URL: &str = "https://api.todoist.com/api/v1";
+
+/// How long a single request may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings from the `[todoist]` config section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TodoistConfig {
+    /// API token from Todoist's integration settings. Falls back to the
+    /// `TO_NOT_DO_TODOIST_TOKEN` environment variable.
+    pub token: Option<String>,
+    /// Where the API is, when not at Todoist itself.
+    pub api_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    #[serde(default, alias = "is_inbox_project")]
+    pub inbox_project: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Due {
+    /// `2025-03-10`, or `2025-03-10T18:00:00` with a time.
+    pub date: String,
+}
+
+/// A task as the API returns it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TodoistTask {
+    pub id: String,
+    pub content: String,
+    #[serde(default)]
+    pub description: String,
+    pub project_id: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// From 1, the default, to 4, the most urgent.
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default)]
+    pub due: Option<Due>,
+    #[serde(default, alias = "is_completed")]
+    pub checked: bool,
+    #[serde(default)]
+    pub is_deleted: bool,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+impl TodoistTask {
+    fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+            .as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| at.with_timezone(&Utc))
+    }
+}
+
+/// What a Todoist task and a local one have in common, compared field by
+/// field to tell which side changed what.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoistFields {
+    pub content: String,
+    pub description: Option<String>,
+    pub project: Option<String>,
+    pub labels: Vec<String>,
+    pub priority: Option<Priority>,
+    pub due: Option<NaiveDate>,
+    pub done: bool,
+}
+
+impl TodoistFields {
+    pub fn of(task: &Task) -> Self {
+        Self {
+            content: task.description().to_string(),
+            description: task.notes().map(str::to_string),
+            project: task.project().map(str::to_string),
+            labels: task.tags().to_vec(),
+            priority: task.priority(),
+            due: task.due(),
+            done: task.state() == TaskState::Done,
+        }
+    }
+
+    fn from_remote(task: &TodoistTask, projects: &Projects) -> Self {
+        Self {
+            content: task.content.clone(),
+            description: Some(task.description.clone()).filter(|text| !text.is_empty()),
+            project: projects.name(&task.project_id),
+            labels: task.labels.clone(),
+            priority: match task.priority {
+                4 => Some(Priority::High),
+                3 => Some(Priority::Medium),
+                2 => Some(Priority::Low),
+                _ => None,
+            },
+            due: task
+                .due
+                .as_ref()
+                .and_then(|due| due.date.get(..10))
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()),
+            done: task.checked,
+        }
+    }
+
+    /// The priority as Todoist numbers it.
+    fn todoist_priority(&self) -> u8 {
+        match self.priority {
+            Some(Priority::High) => 4,
+            Some(Priority::Medium) => 3,
+            Some(Priority::Low) => 2,
+            None => 1,
+        }
+    }
+
+    /// Merges the edits made on either side since `base`; `prefer` settles
+    /// fields changed on both, with `local_newer` for
+    /// [`MergePreference::Newest`].
+    fn merge(
+        base: &Self,
+        local: &Self,
+        remote: &Self,
+        prefer: MergePreference,
+        local_newer: bool,
+    ) -> Self {
+        let local_wins = prefer.local_wins(local_newer);
+
+        Self {
+            content: merge_field(&base.content, &local.content, &remote.content, local_wins),
+            description: merge_field(
+                &base.description,
+                &local.description,
+                &remote.description,
+                local_wins,
+            ),
+            project: merge_field(&base.project, &local.project, &remote.project, local_wins),
+            labels: merge_field(&base.labels, &local.labels, &remote.labels, local_wins),
+            priority: merge_field(
+                &base.priority,
+                &local.priority,
+                &remote.priority,
+                local_wins,
+            ),
+            due: merge_field(&base.due, &local.due, &remote.due, local_wins),
+            done: merge_field(&base.done, &local.done, &remote.done, local_wins),
+        }
+    }
+}
+
+/// What the sync needs of the Todoist API.
+pub trait TodoistApi {
+    fn projects(&self) -> Result<Vec<Project>, ToNotDoError>;
+
+    fn add_project(&self, name: &str) -> Result<Project, ToNotDoError>;
+
+    /// The open tasks.
+    fn tasks(&self) -> Result<Vec<TodoistTask>, ToNotDoError>;
+
+    /// The task `id`, open or completed, or `None` once it is deleted.
+    fn task(&self, id: &str) -> Result<Option<TodoistTask>, ToNotDoError>;
+
+    /// Adds an open task with `fields` to project `project_id`, returning
+    /// its ID.
+    fn add_task(&self, fields: &TodoistFields, project_id: &str) -> Result<String, ToNotDoError>;
+
+    /// Changes every field of task `id` but its project and completion.
+    fn update_task(&self, id: &str, fields: &TodoistFields) -> Result<(), ToNotDoError>;
+
+    fn move_task(&self, id: &str, project_id: &str) -> Result<(), ToNotDoError>;
+
+    /// Completes or reopens task `id`.
+    fn set_done(&self, id: &str, done: bool) -> Result<(), ToNotDoError>;
+
+    fn delete_task(&self, id: &str) -> Result<(), ToNotDoError>;
+}
+
+fn todoist_error(reason: impl std::fmt::Display) -> ToNotDoError {
+    ToNotDoError::TodoistError(reason.to_string())
+}
+
+/// One page of a list the API hands out in pages.
+#[derive(Deserialize)]
+struct Page<T> {
+    results: Vec<T>,
+    next_cursor: Option<String>,
+}
+
+/// The Todoist API, authenticated with a token.
+pub struct TodoistClient {
+    agent: ureq::Agent,
+    api_url: String,
+    authorization: String,
+}
+
+impl TodoistClient {
+    pub fn new(config: &TodoistConfig) -> Result<Self, ToNotDoError> {
+        let token = config
+            .token
+            .clone()
+            .or_else(|| std::env::var(TODOIST_TOKEN_ENV).ok())
+            .ok_or_else(|| {
+                todoist_error(format!(
+                    "No API token; set token in the [todoist] config section or {}",
+                    TODOIST_TOKEN_ENV
+                ))
+            })?;
+
+        Ok(Self {
+            // Statuses like 404 are answers here, not failures.
+            agent: ureq::Agent::new_with_config(
+                ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_global(Some(TIMEOUT))
+                    .build(),
+            ),
+            api_url: config
+                .api_url
+                .as_deref()
+                .unwrap_or(DEFAULT_API_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            authorization: format!("Bearer {}", token),
+        })
+    }
+
+    /// Passes a success through; any other status becomes an error naming
+    /// `path`, or the token when access was denied.
+    fn check(
+        &self,
+        path: &str,
+        response: ureq::http::Response<ureq::Body>,
+    ) -> Result<ureq::http::Response<ureq::Body>, ToNotDoError> {
+        match response.status().as_u16() {
+            200..=299 => Ok(response),
+            401 | 403 => Err(todoist_error(
+                "access denied; check the token in the [todoist] config section",
+            )),
+            status => Err(todoist_error(format!("{} answered {}", path, status))),
+        }
+    }
+
+    fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        cursor: Option<&str>,
+    ) -> Result<Option<T>, ToNotDoError> {
+        let mut request = self
+            .agent
+            .get(format!("{}{}", self.api_url, path))
+            .header("Authorization", &self.authorization);
+        if let Some(cursor) = cursor {
+            request = request.query("cursor", cursor);
+        }
+
+        let response = request.call().map_err(todoist_error)?;
+        if response.status() == 404 {
+            return Ok(None);
+        }
+        self.check(path, response)?
+            .body_mut()
+            .read_json()
+            .map(Some)
+            .map_err(todoist_error)
+    }
+
+    fn list<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, ToNotDoError> {
+        let mut items = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page: Page<T> = self
+                .get(path, cursor.as_deref())?
+                .ok_or_else(|| todoist_error(format!("{} not found", path)))?;
+            items.extend(page.results);
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn post(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+    ) -> Result<ureq::http::Response<ureq::Body>, ToNotDoError> {
+        let response = self
+            .agent
+            .post(format!("{}{}", self.api_url, path))
+            .header("Authorization", &self.authorization)
+            .send_json(body)
+            .map_err(todoist_error)?;
+        self.check(path, response)
+    }
+}
+
+impl TodoistApi for TodoistClient {
+    fn projects(&self) -> Result<Vec<Project>, ToNotDoError> {
+        self.list("/projects")
+    }
+
+    fn add_project(&self, name: &str) -> Result<Project, ToNotDoError> {
+        self.post("/projects", serde_json::json!({ "name": name }))?
+            .body_mut()
+            .read_json()
+            .map_err(todoist_error)
+    }
+
+    fn tasks(&self) -> Result<Vec<TodoistTask>, ToNotDoError> {
+        self.list("/tasks")
+    }
+
+    fn task(&self, id: &str) -> Result<Option<TodoistTask>, ToNotDoError> {
+        self.get(&format!("/tasks/{}", id), None)
+    }
+
+    fn add_task(&self, fields: &TodoistFields, project_id: &str) -> Result<String, ToNotDoError> {
+        let mut body = task_body(fields);
+        body["project_id"] = project_id.into();
+        let task: TodoistTask = self
+            .post("/tasks", body)?
+            .body_mut()
+            .read_json()
+            .map_err(todoist_error)?;
+        Ok(task.id)
+    }
+
+    fn update_task(&self, id: &str, fields: &TodoistFields) -> Result<(), ToNotDoError> {
+        self.post(&format!("/tasks/{}", id), task_body(fields))
+            .map(|_| ())
+    }
+
+    fn move_task(&self, id: &str, project_id: &str) -> Result<(), ToNotDoError> {
+        self.post(
+            &format!("/tasks/{}/move", id),
+            serde_json::json!({ "project_id": project_id }),
+        )
+        .map(|_| ())
+    }
+
+    fn set_done(&self, id: &str, done: bool) -> Result<(), ToNotDoError> {
+        let action = if done { "close" } else { "reopen" };
+        self.post(&format!("/tasks/{}/{}", id, action), serde_json::json!({}))
+            .map(|_| ())
+    }
+
+    fn delete_task(&self, id: &str) -> Result<(), ToNotDoError> {
+        let path = format!("/tasks/{}", id);
+        let response = self
+            .agent
+            .delete(format!("{}{}", self.api_url, path))
+            .header("Authorization", &self.authorization)
+            .call()
+            .map_err(todoist_error)?;
+        if response.status() == 404 {
+            return Ok(());
+        }
+        self.check(&path, response).map(|_| ())
+    }
+}
+
+/// The JSON for the fields of a task, bar its project and completion.
+fn task_body(fields: &TodoistFields) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "content": fields.content,
+        "description": fields.description.as_deref().unwrap_or_default(),
+        "labels": fields.labels,
+        "priority": fields.todoist_priority(),
+    });
+    match fields.due {
+        Some(due) => body["due_date"] = due.format("%Y-%m-%d").to_string().into(),
+        None => body["due_string"] = "no date".into(),
+    }
+    body
+}
+
+/// The Todoist projects, by ID and by name.
+struct Projects {
+    projects: Vec<Project>,
+}
+
+impl Projects {
+    /// The project with ID `id` as a project here; none for the inbox.
+    fn name(&self, id: &str) -> Option<String> {
+        self.projects
+            .iter()
+            .find(|project| project.id == id && !project.inbox_project)
+            .map(|project| project.name.clone())
+    }
+
+    /// The ID of the project named `name`, adding it when there is none,
+    /// or of the inbox when `name` is `None`.
+    fn id(&mut self, api: &dyn TodoistApi, name: Option<&str>) -> Result<String, ToNotDoError> {
+        let found = self.projects.iter().find(|project| match name {
+            Some(name) => project.name == name && !project.inbox_project,
+            None => project.inbox_project,
+        });
+        if let Some(project) = found {
+            return Ok(project.id.clone());
+        }
+
+        let name = name.ok_or_else(|| todoist_error("no inbox project"))?;
+        let project = api.add_project(name)?;
+        let id = project.id.clone();
+        self.projects.push(project);
+        Ok(id)
+    }
+}
+
+/// What an [`import`] or [`sync`] changed on either side.
+#[derive(Debug, Default)]
+pub struct TodoistReport {
+    /// Tasks added, changed or deleted here.
+    pub pulled: usize,
+    /// Tasks added, changed or deleted in Todoist.
+    pub pushed: usize,
+    /// Tasks that failed to sync; they are tried again next time.
+    pub failures: Vec<ToNotDoError>,
+}
+
+/// A task paired with a Todoist task, with the fields both had after the
+/// last sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Synced {
+    todoist_id: String,
+    fields: TodoistFields,
+}
+
+/// The pairings of local tasks with Todoist ones, each with the fields
+/// they agreed on at the last sync.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TodoistState {
+    tasks: BTreeMap<Uuid, Synced>,
+}
+
+impl TodoistState {
+    fn read(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+/// A Todoist task as it is now.
+struct Remote {
+    fields: TodoistFields,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl Remote {
+    fn of(task: &TodoistTask, projects: &Projects) -> Self {
+        Self {
+            fields: TodoistFields::from_remote(task, projects),
+            updated_at: task.updated_at(),
+        }
+    }
+}
+
+/// Adds the open Todoist tasks not imported or synced before, remembering
+/// in `state_path` which they are so a later [`sync`] carries on from there.
+pub fn import(
+    db_manager: &mut DatabaseManager,
+    api: &dyn TodoistApi,
+    state_path: &Path,
+) -> Result<TodoistReport, ToNotDoError> {
+    let mut state = TodoistState::read(state_path);
+    let projects = Projects {
+        projects: api.projects()?,
+    };
+    let known: BTreeSet<String> = state
+        .tasks
+        .values()
+        .map(|synced| synced.todoist_id.clone())
+        .collect();
+
+    let mut report = TodoistReport::default();
+    db_manager.begin();
+    for task in api.tasks()? {
+        if known.contains(&task.id) {
+            continue;
+        }
+        let id = Uuid::new_v4();
+        let fields = TodoistFields::from_remote(&task, &projects);
+        match store(db_manager, id, None, &fields) {
+            Ok(()) => {
+                report.pulled += 1;
+                state.tasks.insert(
+                    id,
+                    Synced {
+                        todoist_id: task.id,
+                        fields,
+                    },
+                );
+            }
+            Err(e) => report.failures.push(e),
+        }
+    }
+    db_manager.commit()?;
+
+    state.write(state_path)?;
+    Ok(report)
+}
+
+/// Syncs the tasks of `db_manager` with Todoist both ways, remembering in
+/// `state_path` how both sides were left. Tasks done or archived here are
+/// only sent to Todoist if they were synced before.
+pub fn sync(
+    db_manager: &mut DatabaseManager,
+    api: &dyn TodoistApi,
+    state_path: &Path,
+    prefer: MergePreference,
+) -> Result<TodoistReport, ToNotDoError> {
+    let mut state = TodoistState::read(state_path);
+    let mut projects = Projects {
+        projects: api.projects()?,
+    };
+    let mut open: BTreeMap<String, TodoistTask> = api
+        .tasks()?
+        .into_iter()
+        .map(|task| (task.id.clone(), task))
+        .collect();
+    let snapshot = db_manager.database()?.clone();
+    let local = |id: Uuid| snapshot.task(id);
+
+    let mut report = TodoistReport::default();
+    let mut synced = BTreeMap::new();
+    db_manager.begin();
+
+    for (&id, entry) in &state.tasks {
+        let remote = match open.remove(&entry.todoist_id) {
+            Some(task) => Ok(Some(Remote::of(&task, &projects))),
+            // Completed tasks are left out of the open ones, so one done at
+            // the last sync is taken to be as it was left.
+            None if entry.fields.done => Ok(Some(Remote {
+                fields: entry.fields.clone(),
+                updated_at: None,
+            })),
+            None => api.task(&entry.todoist_id).map(|task| {
+                task.filter(|task| !task.is_deleted)
+                    .map(|task| Remote::of(&task, &projects))
+            }),
+        };
+
+        let result = remote.and_then(|remote| {
+            sync_known(
+                db_manager,
+                api,
+                &mut projects,
+                entry,
+                id,
+                local(id),
+                remote,
+                prefer,
+            )
+        });
+        match result {
+            Ok((outcome, kept)) => {
+                report.pulled += usize::from(outcome.pulled);
+                report.pushed += usize::from(outcome.pushed);
+                synced.extend(kept);
+            }
+            Err(e) => {
+                report.failures.push(e);
+                synced.insert(id, entry.clone());
+            }
+        }
+    }
+
+    for task in open.into_values() {
+        let id = Uuid::new_v4();
+        let fields = TodoistFields::from_remote(&task, &projects);
+        match store(db_manager, id, None, &fields) {
+            Ok(()) => {
+                report.pulled += 1;
+                synced.insert(
+                    id,
+                    Synced {
+                        todoist_id: task.id,
+                        fields,
+                    },
+                );
+            }
+            Err(e) => report.failures.push(e),
+        }
+    }
+
+    for task in snapshot.tasks() {
+        let known = synced.contains_key(&task.id()) || state.tasks.contains_key(&task.id());
+        if known || task.is_archived() || task.state() == TaskState::Done {
+            continue;
+        }
+        let fields = TodoistFields::of(task);
+        let added = projects
+            .id(api, fields.project.as_deref())
+            .and_then(|project_id| api.add_task(&fields, &project_id));
+        match added {
+            Ok(todoist_id) => {
+                report.pushed += 1;
+                synced.insert(task.id(), Synced { todoist_id, fields });
+            }
+            Err(e) => report.failures.push(e),
+        }
+    }
+    db_manager.commit()?;
+
+    state.tasks = synced;
+    state.write(state_path)?;
+    Ok(report)
+}
+
+/// What syncing one task did.
+#[derive(Default)]
+struct Outcome {
+    pulled: bool,
+    pushed: bool,
+}
+
+/// Syncs a task that was paired with a Todoist task by an earlier sync.
+/// Either may have been deleted since; the other is then deleted too, unless
+/// it was changed, in which case it is copied back. Returns the pairing to
+/// keep, if any.
+#[allow(clippy::too_many_arguments)]
+fn sync_known(
+    db_manager: &mut DatabaseManager,
+    api: &dyn TodoistApi,
+    projects: &mut Projects,
+    entry: &Synced,
+    id: Uuid,
+    task: Option<&Task>,
+    remote: Option<Remote>,
+    prefer: MergePreference,
+) -> Result<(Outcome, Option<(Uuid, Synced)>), ToNotDoError> {
+    let mut outcome = Outcome::default();
+    let kept = match (task, remote) {
+        (None, None) => None,
+        (None, Some(remote)) if remote.fields == entry.fields => {
+            api.delete_task(&entry.todoist_id)?;
+            outcome.pushed = true;
+            None
+        }
+        (None, Some(remote)) => {
+            // Deleted here but changed there; the old ID may not be reused.
+            let id = Uuid::new_v4();
+            store(db_manager, id, None, &remote.fields)?;
+            outcome.pulled = true;
+            Some((
+                id,
+                Synced {
+                    todoist_id: entry.todoist_id.clone(),
+                    fields: remote.fields,
+                },
+            ))
+        }
+        (Some(task), None) if TodoistFields::of(task) == entry.fields => {
+            db_manager.delete_task(id)?;
+            outcome.pulled = true;
+            None
+        }
+        (Some(task), None) => {
+            let fields = TodoistFields::of(task);
+            let project_id = projects.id(api, fields.project.as_deref())?;
+            let todoist_id = api.add_task(&fields, &project_id)?;
+            if fields.done {
+                api.set_done(&todoist_id, true)?;
+            }
+            outcome.pushed = true;
+            Some((id, Synced { todoist_id, fields }))
+        }
+        (Some(task), Some(remote)) => {
+            let local = TodoistFields::of(task);
+            let local_modified = task
+                .history()
+                .last()
+                .map(|entry| entry.at)
+                .into_iter()
+                .chain(
+                    task.updated_at()
+                        .and_hms_opt(0, 0, 0)
+                        .map(|at| at.and_utc()),
+                )
+                .max();
+            let local_newer = match (local_modified, remote.updated_at) {
+                (Some(local), Some(remote)) => local > remote,
+                (_, None) => true,
+                (None, Some(_)) => false,
+            };
+            let fields =
+                TodoistFields::merge(&entry.fields, &local, &remote.fields, prefer, local_newer);
+
+            if fields != local {
+                store(db_manager, id, Some(task), &fields)?;
+                outcome.pulled = true;
+            }
+            if fields != remote.fields {
+                push(api, projects, &entry.todoist_id, &remote.fields, &fields)?;
+                outcome.pushed = true;
+            }
+            Some((
+                id,
+                Synced {
+                    todoist_id: entry.todoist_id.clone(),
+                    fields,
+                },
+            ))
+        }
+    };
+    Ok((outcome, kept))
+}
+
+/// Changes the Todoist task `todoist_id` from `before` to `after`.
+fn push(
+    api: &dyn TodoistApi,
+    projects: &mut Projects,
+    todoist_id: &str,
+    before: &TodoistFields,
+    after: &TodoistFields,
+) -> Result<(), ToNotDoError> {
+    let same_project_and_state = TodoistFields {
+        project: before.project.clone(),
+        done: before.done,
+        ..after.clone()
+    };
+    if &same_project_and_state != before {
+        api.update_task(todoist_id, after)?;
+    }
+    if after.project != before.project {
+        let project_id = projects.id(api, after.project.as_deref())?;
+        api.move_task(todoist_id, &project_id)?;
+    }
+    if after.done != before.done {
+        api.set_done(todoist_id, after.done)?;
+    }
+    Ok(())
+}
+
+/// Gives the task `id` the synced `fields`, adding it when there is no
+/// `task` yet.
+fn store(
+    db_manager: &mut DatabaseManager,
+    id: Uuid,
+    task: Option<&Task>,
+    fields: &TodoistFields,
+) -> Result<(), ToNotDoError> {
+    let task = match task {
+        Some(task) => task.clone(),
+        None => {
+            let task = Task::new(&fields.content).with_id(id);
+            db_manager.add_task(&task)?;
+            task
+        }
+    };
+
+    if task.description() != fields.content {
+        db_manager.update_description(id, &fields.content)?;
+    }
+    if task.notes() != fields.description.as_deref() {
+        db_manager.set_notes(id, fields.description.as_deref())?;
+    }
+    if task.project() != fields.project.as_deref() {
+        db_manager.set_project(id, fields.project.as_deref())?;
+    }
+    if task.tags() != fields.labels {
+        db_manager.set_tags(id, &fields.labels)?;
+    }
+    if task.priority() != fields.priority {
+        db_manager.set_priority(id, fields.priority)?;
+    }
+    if task.due() != fields.due {
+        db_manager.set_due(id, fields.due)?;
+    }
+    if (task.state() == TaskState::Done) != fields.done {
+        let state = if fields.done {
+            TaskState::Done
+        } else {
+            TaskState::Todo
+        };
+        db_manager.set_task_state(id, state)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::cell::RefCell;
+    use tempfile::tempdir;
+
+    const TASK_JSON: &str = r#"{
+        "id": "6X7rM8997g3RQmvh",
+        "user_id": "2671355",
+        "project_id": "6Jf8VQXxpwv56VQ7",
+        "section_id": null,
+        "parent_id": null,
+        "labels": ["errands"],
+        "checked": false,
+        "is_deleted": false,
+        "added_at": "2025-03-01T08:00:00.000000Z",
+        "updated_at": "2025-03-02T09:30:00.123456Z",
+        "due": {"date": "2025-03-10T18:00:00", "string": "Mar 10 6pm", "lang": "en", "is_recurring": false},
+        "priority": 4,
+        "child_order": 1,
+        "content": "Buy milk",
+        "description": "",
+        "note_count": 0,
+        "day_order": -1,
+        "is_collapsed": false
+    }"#;
+
+    /// A Todoist account kept in memory, with an inbox and a "Home" project.
+    struct MemoryTodoist {
+        projects: RefCell<Vec<Project>>,
+        tasks: RefCell<BTreeMap<String, TodoistTask>>,
+        next_id: RefCell<usize>,
+    }
+
+    impl MemoryTodoist {
+        fn new() -> Self {
+            let project = |id: &str, name: &str, inbox_project| Project {
+                id: id.to_string(),
+                name: name.to_string(),
+                inbox_project,
+            };
+            Self {
+                projects: RefCell::new(vec![
+                    project("inbox", "Inbox", true),
+                    project("home", "Home", false),
+                ]),
+                tasks: RefCell::default(),
+                next_id: RefCell::default(),
+            }
+        }
+
+        fn id(&self) -> String {
+            *self.next_id.borrow_mut() += 1;
+            format!("t{}", self.next_id.borrow())
+        }
+
+        fn get(&self, id: &str) -> TodoistTask {
+            self.tasks.borrow()[id].clone()
+        }
+
+        fn edit(&self, id: &str, edit: impl FnOnce(&mut TodoistTask)) {
+            edit(self.tasks.borrow_mut().get_mut(id).unwrap());
+        }
+    }
+
+    impl TodoistApi for MemoryTodoist {
+        fn projects(&self) -> Result<Vec<Project>, ToNotDoError> {
+            Ok(self.projects.borrow().clone())
+        }
+
+        fn add_project(&self, name: &str) -> Result<Project, ToNotDoError> {
+            let project = Project {
+                id: self.id(),
+                name: name.to_string(),
+                inbox_project: false,
+            };
+            self.projects.borrow_mut().push(project.clone());
+            Ok(project)
+        }
+
+        fn tasks(&self) -> Result<Vec<TodoistTask>, ToNotDoError> {
+            Ok(self
+                .tasks
+                .borrow()
+                .values()
+                .filter(|task| !task.checked)
+                .cloned()
+                .collect())
+        }
+
+        fn task(&self, id: &str) -> Result<Option<TodoistTask>, ToNotDoError> {
+            Ok(self.tasks.borrow().get(id).cloned())
+        }
+
+        fn add_task(
+            &self,
+            fields: &TodoistFields,
+            project_id: &str,
+        ) -> Result<String, ToNotDoError> {
+            let id = self.id();
+            let task = TodoistTask {
+                id: id.clone(),
+                content: String::new(),
+                description: String::new(),
+                project_id: project_id.to_string(),
+                labels: Vec::new(),
+                priority: 1,
+                due: None,
+                checked: false,
+                is_deleted: false,
+                updated_at: None,
+            };
+            self.tasks.borrow_mut().insert(id.clone(), task);
+            self.update_task(&id, fields)?;
+            Ok(id)
+        }
+
+        fn update_task(&self, id: &str, fields: &TodoistFields) -> Result<(), ToNotDoError> {
+            self.edit(id, |task| {
+                task.content = fields.content.clone();
+                task.description = fields.description.clone().unwrap_or_default();
+                task.labels = fields.labels.clone();
+                task.priority = fields.todoist_priority();
+                task.due = fields.due.map(|due| Due {
+                    date: due.to_string(),
+                });
+            });
+            Ok(())
+        }
+
+        fn move_task(&self, id: &str, project_id: &str) -> Result<(), ToNotDoError> {
+            self.edit(id, |task| task.project_id = project_id.to_string());
+            Ok(())
+        }
+
+        fn set_done(&self, id: &str, done: bool) -> Result<(), ToNotDoError> {
+            self.edit(id, |task| task.checked = done);
+            Ok(())
+        }
+
+        fn delete_task(&self, id: &str) -> Result<(), ToNotDoError> {
+            self.tasks.borrow_mut().remove(id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_task_fields() {
+        let task: TodoistTask = serde_json::from_str(TASK_JSON).unwrap();
+        let projects = Projects {
+            projects: vec![Project {
+                id: "6Jf8VQXxpwv56VQ7".to_string(),
+                name: "Home".to_string(),
+                inbox_project: false,
+            }],
+        };
+        let fields = TodoistFields::from_remote(&task, &projects);
+        assert_eq!(
+            fields,
+            TodoistFields {
+                content: "Buy milk".to_string(),
+                description: None,
+                project: Some("Home".to_string()),
+                labels: vec!["errands".to_string()],
+                priority: Some(Priority::High),
+                due: NaiveDate::from_ymd_opt(2025, 3, 10),
+                done: false,
+            }
+        );
+        assert_eq!(
+            task.updated_at().unwrap().to_rfc3339(),
+            "2025-03-02T09:30:00.123456+00:00"
+        );
+
+        let body = task_body(&fields);
+        assert_eq!(body["priority"], 4);
+        assert_eq!(body["due_date"], "2025-03-10");
+        let body = task_body(&TodoistFields {
+            due: None,
+            ..fields
+        });
+        assert_eq!(body["due_string"], "no date");
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = TodoistFields::of(&Task::new("Buy milk"));
+        let local = TodoistFields {
+            content: "Buy oat milk".to_string(),
+            due: NaiveDate::from_ymd_opt(2025, 3, 10),
+            ..base.clone()
+        };
+        let remote = TodoistFields {
+            content: "Buy soy milk".to_string(),
+            done: true,
+            ..base.clone()
+        };
+
+        let merged = TodoistFields::merge(&base, &local, &remote, MergePreference::Ours, false);
+        assert_eq!(merged.content, "Buy oat milk");
+        assert_eq!(merged.due, local.due);
+        assert!(merged.done);
+
+        let merged = TodoistFields::merge(&base, &local, &remote, MergePreference::Theirs, true);
+        assert_eq!(merged.content, "Buy soy milk");
+        assert_eq!(merged.due, local.due);
+
+        let merged = TodoistFields::merge(&base, &local, &remote, MergePreference::Newest, false);
+        assert_eq!(merged.content, "Buy soy milk");
+    }
+
+    #[test]
+    fn test_import_and_sync() {
+        let dir = tempdir().unwrap();
+        let state = dir.path().join("tasks.todoist");
+        let todoist = MemoryTodoist::new();
+        let milk_fields = TodoistFields {
+            content: "Buy milk".to_string(),
+            description: None,
+            project: Some("Home".to_string()),
+            labels: vec!["errands".to_string()],
+            priority: Some(Priority::Medium),
+            due: NaiveDate::from_ymd_opt(2025, 3, 10),
+            done: false,
+        };
+        let milk_id = todoist.add_task(&milk_fields, "home").unwrap();
+
+        let mut db_manager = DatabaseManager::with_storage(Box::new(MemoryStorage::new())).unwrap();
+        let report_task = Task::new("Write report").with_project("Work");
+        db_manager.add_task(&report_task).unwrap();
+
+        let report = import(&mut db_manager, &todoist, &state).unwrap();
+        assert_eq!((report.pulled, report.pushed), (1, 0));
+        let report = import(&mut db_manager, &todoist, &state).unwrap();
+        assert_eq!(report.pulled, 0, "imported tasks are not imported again");
+
+        let milk = db_manager
+            .get_tasks()
+            .unwrap()
+            .iter()
+            .find(|task| task.description() == "Buy milk")
+            .unwrap()
+            .clone();
+        assert_eq!(TodoistFields::of(&milk), milk_fields);
+
+        let report = sync(&mut db_manager, &todoist, &state, MergePreference::Ours).unwrap();
+        assert_eq!((report.pulled, report.pushed), (0, 1));
+        assert!(report.failures.is_empty());
+        let report_id = todoist
+            .tasks()
+            .unwrap()
+            .into_iter()
+            .find(|task| task.content == "Write report")
+            .unwrap()
+            .id;
+        let work = todoist.get(&report_id).project_id;
+        assert!(todoist
+            .projects()
+            .unwrap()
+            .iter()
+            .any(|project| project.id == work && project.name == "Work"));
+
+        let report = sync(&mut db_manager, &todoist, &state, MergePreference::Ours).unwrap();
+        assert_eq!((report.pulled, report.pushed), (0, 0), "nothing changed");
+
+        // Both sides rename the milk task, and the report is done in Todoist.
+        db_manager
+            .update_description(milk.id(), "Buy oat milk")
+            .unwrap();
+        db_manager.set_project(milk.id(), None).unwrap();
+        todoist.edit(&milk_id, |task| {
+            task.content = "Buy soy milk".to_string();
+            task.priority = 4;
+        });
+        todoist.set_done(&report_id, true).unwrap();
+
+        let report = sync(&mut db_manager, &todoist, &state, MergePreference::Theirs).unwrap();
+        assert_eq!((report.pulled, report.pushed), (2, 1));
+        let milk = db_manager.get_task(milk.id()).unwrap().clone();
+        assert_eq!(milk.description(), "Buy soy milk", "Todoist wins");
+        assert_eq!(milk.priority(), Some(Priority::High));
+        assert_eq!(milk.project(), None);
+        assert_eq!(todoist.get(&milk_id).project_id, "inbox");
+        assert_eq!(
+            db_manager.get_task(report_task.id()).unwrap().state(),
+            TaskState::Done
+        );
+
+        // Deleted on one side and unchanged on the other, so gone from both.
+        todoist.delete_task(&milk_id).unwrap();
+        let report = sync(&mut db_manager, &todoist, &state, MergePreference::Ours).unwrap();
+        assert_eq!(report.pulled, 1);
+        assert!(db_manager.get_task(milk.id()).is_none());
+
+        db_manager.delete_task(report_task.id()).unwrap();
+        let report = sync(&mut db_manager, &todoist, &state, MergePreference::Ours).unwrap();
+        assert_eq!(report.pushed, 1);
+        assert!(todoist.tasks.borrow().is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+use uuid::Uuid;
+
+pub const URI_SCHEME: &str = "to-not-do";
+
+/// Builds the deep link for a task, e.g. `to-not-do://task/<uuid>`.
+pub fn task_uri(task_id: Uuid) -> String {
+    format!("{}://task/{}", URI_SCHEME, task_id)
+}
+
+/// Extracts the task ID from a `to-not-do://task/<uuid>` deep link.
+pub fn parse_task_uri(uri: &str) -> Result<Uuid, String> {
+    let rest = uri
+        .trim()
+        .strip_prefix(URI_SCHEME)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .ok_or_else(|| format!("Not a {} URI: {}", URI_SCHEME, uri))?;
+
+    let id = rest
+        .strip_prefix("task/")
+        .ok_or_else(|| format!("Unsupported URI target: {}", uri))?;
+
+    Uuid::parse_str(id.trim_end_matches('/'))
+        .map_err(|_| format!("Invalid task ID in URI: {}", uri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_uri_round_trip() {
+        let task_id = Uuid::new_v4();
+
+        let uri = task_uri(task_id);
+
+        assert_eq!(uri, format!("to-not-do://task/{}", task_id));
+        assert_eq!(parse_task_uri(&uri), Ok(task_id));
+        assert_eq!(parse_task_uri(&format!("{}/", uri)), Ok(task_id));
+    }
+
+    #[test]
+    fn test_parse_invalid_task_uri() {
+        let task_id = Uuid::new_v4();
+
+        assert!(parse_task_uri(&format!("https://task/{}", task_id)).is_err());
+        assert!(parse_task_uri(&format!("to-not-do://project/{}", task_id)).is_err());
+        assert!(parse_task_uri("to-not-do://task/not-a-uuid").is_err());
+    }
+}
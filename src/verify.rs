@@ -0,0 +1,362 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::file_management::{Priority, TaskState};
+
+/// A broken invariant found by [`verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The task concerned, by ID or position, or `None` for the database as a
+    /// whole.
+    pub task: Option<String>,
+    pub problem: String,
+    /// Whether `--fix` repairs it.
+    pub fixable: bool,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.task {
+            Some(task) => write!(f, "task {}: {}", task, self.problem),
+            None => write!(f, "{}", self.problem),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    violations: Vec<Violation>,
+    fix: bool,
+}
+
+impl Checker {
+    /// Records a violation and whether it can be fixed, returning whether to
+    /// apply the fix.
+    fn report(&mut self, task: Option<&str>, problem: String, fixable: bool) -> bool {
+        self.violations.push(Violation {
+            task: task.map(str::to_string),
+            problem,
+            fixable,
+        });
+        self.fix && fixable
+    }
+
+    /// Checks that the optional `field` of `task` holds a `T`; when fixing, an
+    /// invalid value is removed.
+    fn check_optional<T: DeserializeOwned>(
+        &mut self,
+        id: &str,
+        task: &mut Map<String, Value>,
+        field: &str,
+        what: &str,
+    ) {
+        let Some(value) = task.get(field).filter(|v| !v.is_null()) else {
+            return;
+        };
+
+        if !parses::<T>(value) {
+            let problem = format!("invalid {} {}", what, value);
+            if self.report(Some(id), problem, true) {
+                task.remove(field);
+            }
+        }
+    }
+
+    /// Like [`Checker::check_optional`] for a field every task must have;
+    /// when fixing, it is replaced with `default`.
+    fn check_required<T: DeserializeOwned>(
+        &mut self,
+        id: &str,
+        task: &mut Map<String, Value>,
+        field: &str,
+        what: &str,
+        default: Value,
+    ) {
+        let problem = match task.get(field) {
+            Some(value) if parses::<T>(value) => return,
+            Some(value) => format!("invalid {} {}", what, value),
+            None => format!("missing {}", what),
+        };
+
+        if self.report(Some(id), problem, true) {
+            task.insert(field.to_string(), default);
+        }
+    }
+}
+
+fn parses<T: DeserializeOwned>(value: &Value) -> bool {
+    serde_json::from_value::<T>(value.clone()).is_ok()
+}
+
+/// Checks a raw database for broken invariants: tasks with missing,
+/// malformed or duplicate IDs, invalid states, priorities or dates, and
+/// parents or a focus that point at tasks that do not exist or form a
+/// cycle. With `fix` set, every fixable violation is repaired in `db`.
+pub fn verify(db: &mut Value, fix: bool) -> Vec<Violation> {
+    let mut checker = Checker {
+        fix,
+        ..Checker::default()
+    };
+
+    let Some(tasks) = db.get_mut("tasks").and_then(Value::as_array_mut) else {
+        checker.report(None, "missing task list".to_string(), false);
+        return checker.violations;
+    };
+
+    let today = Value::from(Utc::now().date_naive().to_string());
+    let mut seen: HashMap<Uuid, Value> = HashMap::new();
+    let mut index = 0;
+
+    tasks.retain_mut(|task| {
+        index += 1;
+        let position = format!("#{}", index);
+
+        let Some(task) = task.as_object_mut() else {
+            return !checker.report(Some(&position), "not an object".to_string(), true);
+        };
+
+        let id = match task.get("id").and_then(Value::as_str).map(Uuid::parse_str) {
+            Some(Ok(id)) => id,
+            _ => {
+                let fixable = checker.report(Some(&position), "missing or invalid ID".into(), true);
+                if !fixable {
+                    return true;
+                }
+                let id = Uuid::new_v4();
+                task.insert("id".to_string(), Value::from(id.to_string()));
+                id
+            }
+        };
+        let label = id.simple().to_string()[..8].to_string();
+
+        if let Some(first) = seen.get(&id) {
+            if *first == Value::Object(task.clone()) {
+                return !checker.report(Some(&label), "duplicate of another task".into(), true);
+            }
+
+            if checker.report(Some(&label), "ID shared with another task".into(), true) {
+                let id = Uuid::new_v4();
+                task.insert("id".to_string(), Value::from(id.to_string()));
+                seen.insert(id, Value::Object(task.clone()));
+            }
+        } else {
+            seen.insert(id, Value::Object(task.clone()));
+        }
+
+        if !task.get("description").is_some_and(Value::is_string) {
+            checker.report(Some(&label), "missing description".to_string(), false);
+        }
+
+        let state = Value::from("Todo");
+        checker.check_required::<TaskState>(&label, task, "state", "state", state);
+        checker.check_required::<NaiveDate>(&label, task, "created_at", "date", today.clone());
+        checker.check_required::<NaiveDate>(&label, task, "updated_at", "date", today.clone());
+        checker.check_optional::<NaiveDate>(&label, task, "due", "due date");
+        checker.check_optional::<NaiveDate>(&label, task, "completed_at", "completion date");
+        checker.check_optional::<Priority>(&label, task, "priority", "priority");
+        checker.check_optional::<Uuid>(&label, task, "parent", "parent");
+
+        if task.get("state").and_then(Value::as_str) != Some("Done")
+            && task.get("completed_at").is_some_and(|v| !v.is_null())
+        {
+            let problem = "completion date on an unfinished task".to_string();
+            if checker.report(Some(&label), problem, true) {
+                task.remove("completed_at");
+            }
+        }
+
+        if let Some(history) = task.get_mut("history").and_then(Value::as_array_mut) {
+            let before = history.len();
+            let valid = |entry: &Value| {
+                entry.get("at").is_some_and(parses::<DateTime<Utc>>)
+                    && entry.get("event").is_some_and(Value::is_string)
+            };
+            let invalid = history.iter().filter(|entry| !valid(entry)).count();
+
+            if invalid > 0 {
+                let problem = format!("{} of {} history entries are invalid", invalid, before);
+                if checker.report(Some(&label), problem, true) {
+                    history.retain(valid);
+                }
+            }
+        }
+
+        true
+    });
+
+    check_parents(&mut checker, tasks);
+
+    let ids: HashSet<String> = tasks
+        .iter()
+        .filter_map(|task| task.get("id").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect();
+    if let Some(focus) = db.get("focus").and_then(Value::as_str) {
+        if !ids.contains(focus) {
+            let problem = format!("focus points at missing task {}", focus);
+            if checker.report(None, problem, true) {
+                if let Some(db) = db.as_object_mut() {
+                    db.remove("focus");
+                }
+            }
+        }
+    }
+
+    checker.violations
+}
+
+/// Reports parents that do not exist or that lead back to the task itself;
+/// fixing detaches the task from its parent.
+fn check_parents(checker: &mut Checker, tasks: &mut [Value]) {
+    let id_of = |task: &Value| task.get("id").and_then(Value::as_str).map(str::to_string);
+
+    let ids: HashSet<String> = tasks.iter().filter_map(id_of).collect();
+    let mut parents: HashMap<String, String> = tasks
+        .iter()
+        .filter_map(|task| {
+            let parent = task.get("parent")?.as_str()?;
+            Some((id_of(task)?, parent.to_string()))
+        })
+        .collect();
+
+    for task in tasks.iter_mut() {
+        let Some(id) = id_of(task) else {
+            continue;
+        };
+        let Some(parent) = parents.get(&id) else {
+            continue;
+        };
+
+        let problem = if !ids.contains(parent) {
+            format!("parent {} does not exist", parent)
+        } else if leads_back(&parents, &id) {
+            "parent chain loops back to the task".to_string()
+        } else {
+            continue;
+        };
+
+        // The ID may be anything in a damaged file.
+        let label: String = id.chars().take(8).collect();
+        if checker.report(Some(&label), problem, true) {
+            parents.remove(&id);
+            if let Some(task) = task.as_object_mut() {
+                task.remove("parent");
+            }
+        }
+    }
+}
+
+fn leads_back(parents: &HashMap<String, String>, id: &str) -> bool {
+    let mut current = parents.get(id);
+
+    for _ in 0..parents.len() {
+        match current {
+            Some(parent) if parent == id => return true,
+            Some(parent) => current = parents.get(parent),
+            None => return false,
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_verify_and_fix() {
+        let a = Uuid::new_v4().to_string();
+        let b = Uuid::new_v4().to_string();
+        let task = |id: &str| {
+            json!({
+                "id": id,
+                "description": "Task",
+                "state": "Todo",
+                "created_at": "2024-01-01",
+                "updated_at": "2024-01-01",
+            })
+        };
+
+        let mut broken = task(&b);
+        broken["state"] = json!("Doing");
+        broken["due"] = json!("tomorrow");
+        broken["parent"] = json!(Uuid::new_v4().to_string());
+        broken["completed_at"] = json!("2024-01-02");
+
+        let mut shared_id = task(&a);
+        shared_id["description"] = json!("Other task");
+
+        let mut db = json!({
+            "tasks": [task(&a), task(&a), shared_id, broken, "junk"],
+            "focus": Uuid::new_v4().to_string(),
+        });
+        let clean = db.clone();
+
+        let violations = verify(&mut db, false);
+        assert_eq!(violations.len(), 8, "{:?}", violations);
+        assert!(violations.iter().all(|v| v.fixable));
+        assert_eq!(db, clean);
+
+        assert_eq!(verify(&mut db, true).len(), 8);
+        assert!(verify(&mut db, false).is_empty());
+
+        let tasks = db["tasks"].as_array().unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert_ne!(tasks[1]["id"], json!(a));
+        assert_eq!(tasks[2]["state"], json!("Todo"));
+        assert!(db.get("focus").is_none());
+        assert!(
+            serde_json::from_value::<crate::file_management::Database>(json!({
+                "name": "to-not-do",
+                "version": "0.1.0",
+                "tasks": tasks,
+            }))
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_verify_parent_cycle() {
+        let a = Uuid::new_v4().to_string();
+        let b = Uuid::new_v4().to_string();
+        let task = |id: &str, parent: &str| {
+            json!({
+                "id": id,
+                "description": "Task",
+                "state": "Todo",
+                "created_at": "2024-01-01",
+                "updated_at": "2024-01-01",
+                "parent": parent,
+            })
+        };
+
+        let mut db = json!({ "tasks": [task(&a, &b), task(&b, &a)] });
+
+        assert_eq!(verify(&mut db, true).len(), 1);
+        assert!(verify(&mut db, false).is_empty());
+    }
+
+    #[test]
+    fn test_verify_short_id_with_parent() {
+        let mut db = json!({ "tasks": [{
+            "id": "abc",
+            "description": "Task",
+            "state": "Todo",
+            "created_at": "2024-01-01",
+            "updated_at": "2024-01-01",
+            "parent": Uuid::new_v4().to_string(),
+        }] });
+
+        let violations = verify(&mut db, false);
+        assert!(violations
+            .iter()
+            .any(|v| v.task.as_deref() == Some("abc") && v.problem.starts_with("parent")));
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+use crate::{
+    error::{DatabaseError, ToNotDoError},
+    file_management::Database,
+    journal::{append_events, read_events, truncate_partial_event, Event},
+    storage::{Revision, Storage},
+};
+
+/// Write-ahead log kept next to the database file at `db_file`.
+pub fn wal_path(db_file: &Path) -> PathBuf {
+    db_file.with_extension("wal")
+}
+
+/// Guards another storage against crashes: every change is appended to a
+/// write-ahead log before the database is saved, and the log is only cleared
+/// once the save succeeded. Changes still in the log when the database is
+/// next loaded are replayed and saved.
+pub struct WalStorage {
+    inner: Box<dyn Storage>,
+    path: PathBuf,
+    last: Option<Database>,
+    read_only: bool,
+}
+
+impl WalStorage {
+    pub fn new(inner: Box<dyn Storage>, wal: &Path) -> Self {
+        Self {
+            inner,
+            path: wal.to_path_buf(),
+            last: None,
+            read_only: false,
+        }
+    }
+
+    /// Appends the changes from the last logged state to `db`.
+    fn log(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        let previous = match self.last.take() {
+            Some(previous) => previous,
+            None if self.exists() => self.load()?,
+            None => Database::default(),
+        };
+
+        let at = Utc::now();
+        let events: Vec<Event> = previous
+            .changes_to(db)
+            .into_iter()
+            .map(|change| Event { at, change })
+            .collect();
+
+        let result = if events.is_empty() {
+            Ok(())
+        } else {
+            append_events(&self.path, &events)
+        };
+
+        self.last = Some(if result.is_ok() { db.clone() } else { previous });
+        result
+    }
+
+    fn clear(&self) -> Result<(), ToNotDoError> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(ToNotDoError::DatabaseError(
+                DatabaseError::FailedToWriteFile(e),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Storage for WalStorage {
+    fn exists(&self) -> bool {
+        self.inner.exists()
+    }
+
+    fn load(&mut self) -> Result<Database, ToNotDoError> {
+        let mut db = if self.inner.exists() {
+            self.inner.load()?
+        } else {
+            Database::default()
+        };
+
+        let (events, complete) = read_events(&self.path)?;
+        if events.is_empty() {
+            if !self.read_only {
+                truncate_partial_event(&self.path, complete)?;
+            }
+        } else {
+            for event in events {
+                db.apply(event.change);
+            }
+
+            // Replaying is idempotent, so when the database cannot be written
+            // right now, or must not be, the log is simply kept for the next
+            // load.
+            if !self.read_only && self.inner.save(&db).is_ok() {
+                self.clear()?;
+            }
+        }
+
+        self.last = Some(db.clone());
+        Ok(db)
+    }
+
+    fn revision(&self) -> Option<Revision> {
+        Some(self.inner.revision()?.and(Revision::of([&self.path])))
+    }
+
+    fn save(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.log(db)?;
+        self.inner.save(db)?;
+        self.clear()
+    }
+
+    fn save_revised(&mut self, db: &Database) -> Result<Option<Revision>, ToNotDoError> {
+        self.log(db)?;
+        let revision = self.inner.save_revised(db)?;
+        self.clear()?;
+        Ok(revision.map(|revision| revision.and(Revision::missing())))
+    }
+
+    fn stage(&mut self, db: &Database) -> Result<(), ToNotDoError> {
+        self.log(db)
+    }
+
+    fn set_read_only(&mut self) {
+        self.read_only = true;
+        self.inner.set_read_only();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_management::{DatabaseManager, Task},
+        storage::FileStorage,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_wal_replays_unsaved_batch() {
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("tasks.json");
+        let storage = || {
+            Box::new(WalStorage::new(
+                Box::new(FileStorage::new(&db_file)),
+                &wal_path(&db_file),
+            ))
+        };
+
+        let mut db_manager = DatabaseManager::with_storage(storage()).unwrap();
+        let saved = Task::new("Saved");
+        db_manager.add_task(&saved).unwrap();
+        assert!(!wal_path(&db_file).exists());
+
+        // A batch that never commits, as when the process dies part way.
+        db_manager.begin();
+        let pending = Task::new("Pending");
+        db_manager.add_task(&pending).unwrap();
+        db_manager.delete_task(saved.id()).unwrap();
+        drop(db_manager);
+
+        assert_eq!(
+            FileStorage::new(&db_file).load().unwrap().tasks(),
+            std::slice::from_ref(&saved)
+        );
+
+        let db_manager = DatabaseManager::with_storage(storage()).unwrap();
+        assert!(db_manager.get_task(pending.id()).is_some());
+        assert!(db_manager.get_task(saved.id()).is_none());
+        assert!(!wal_path(&db_file).exists());
+        assert_eq!(FileStorage::new(&db_file).load().unwrap().tasks().len(), 1);
+    }
+}
@@ -0,0 +1,36 @@
+use axum::{
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+/// The web frontend, built into the binary from the `web` directory.
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct Assets;
+
+/// Serves the frontend file named by `uri`, with `/` meaning the page
+/// itself. The files hold nothing private, so they need no token; the page
+/// asks for one when the REST API does.
+pub async fn asset(uri: Uri) -> Response {
+    let path = match uri.path().trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+
+    match Assets::get(path) {
+        Some(file) => ([(header::CONTENT_TYPE, content_type(path))], file.data).into_response(),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
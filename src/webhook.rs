@@ -0,0 +1,670 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    digest::{Digest, DigestPeriod},
+    error::{DatabaseError, ToNotDoError},
+    file_management::{Database, Task, TaskState},
+    hooks::{lifecycle, Lifecycle},
+};
+
+/// Delay before `tick` first retries a failed delivery; it doubles after
+/// each retry.
+pub const FIRST_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long a single delivery may take before it counts as failed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest message Discord accepts.
+const DISCORD_MAX_CHARS: usize = 2000;
+
+#[derive(Debug, ValueEnum, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Created,
+    Completed,
+    Overdue,
+    /// The daily digest, sent by `tick` once a day.
+    Summary,
+}
+
+/// What is POSTed to a webhook.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The event as JSON, see [`Payload`].
+    #[default]
+    Json,
+    /// A message for a Slack incoming webhook.
+    Slack,
+    /// A message for a Discord webhook.
+    Discord,
+}
+
+/// A URL to notify of task events, from a `[[webhooks]]` config section.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Events sent to the URL; every task event when left out.
+    #[serde(default = "all_events")]
+    pub events: Vec<WebhookEvent>,
+    /// Times `tick` retries a failed delivery, waiting twice as long each
+    /// time.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Messages for Slack and Discord by event, replacing the built-in
+    /// ones; see [`message`] for the placeholders.
+    #[serde(default)]
+    pub templates: BTreeMap<WebhookEvent, String>,
+    /// Local time after which the daily summary is sent.
+    #[serde(default = "default_summary_time")]
+    pub summary_time: NaiveTime,
+}
+
+impl WebhookConfig {
+    /// A webhook for every task event at `url`, with the default settings.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            events: all_events(),
+            retries: default_retries(),
+            format: WebhookFormat::default(),
+            templates: BTreeMap::new(),
+            summary_time: default_summary_time(),
+        }
+    }
+}
+
+fn all_events() -> Vec<WebhookEvent> {
+    vec![
+        WebhookEvent::Created,
+        WebhookEvent::Completed,
+        WebhookEvent::Overdue,
+    ]
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_summary_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap()
+}
+
+/// The JSON body POSTed to a webhook: the task for task events, the text
+/// of the digest for summaries.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Payload {
+    pub event: WebhookEvent,
+    pub at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task: Option<Task>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl Payload {
+    pub fn new(event: WebhookEvent, task: Task) -> Self {
+        Self {
+            event,
+            at: Utc::now(),
+            task: Some(task),
+            summary: None,
+        }
+    }
+
+    pub fn summary(summary: String) -> Self {
+        Self {
+            event: WebhookEvent::Summary,
+            at: Utc::now(),
+            task: None,
+            summary: Some(summary),
+        }
+    }
+}
+
+/// The message for `payload` in the webhook's template, or the built-in
+/// one. Templates may use `{event}`, `{description}`, `{id}`, `{due}`,
+/// `{project}`, `{priority}`, `{tags}` and `{summary}`; the task ones are
+/// empty for summaries and the other way round.
+pub fn message(webhook: &WebhookConfig, payload: &Payload) -> String {
+    let template = webhook
+        .templates
+        .get(&payload.event)
+        .map(String::as_str)
+        .unwrap_or(match payload.event {
+            WebhookEvent::Created => "New task: {description}",
+            WebhookEvent::Completed => "Done: {description}",
+            WebhookEvent::Overdue => "Overdue since {due}: {description}",
+            WebhookEvent::Summary => "{summary}",
+        });
+
+    let task = payload.task.as_ref();
+    let event = serde_json::to_value(payload.event).unwrap_or_default();
+    let placeholders = [
+        ("event", event.as_str().unwrap_or_default().to_string()),
+        (
+            "description",
+            task.map(|task| task.description().to_string())
+                .unwrap_or_default(),
+        ),
+        (
+            "id",
+            task.map(|task| task.id().to_string()).unwrap_or_default(),
+        ),
+        (
+            "due",
+            task.and_then(Task::due)
+                .map(|due| due.to_string())
+                .unwrap_or_default(),
+        ),
+        (
+            "project",
+            task.and_then(Task::project).unwrap_or_default().to_string(),
+        ),
+        (
+            "priority",
+            task.and_then(Task::priority)
+                .map(|priority| format!("{:?}", priority))
+                .unwrap_or_default(),
+        ),
+        (
+            "tags",
+            task.map(|task| task.tags().join(", ")).unwrap_or_default(),
+        ),
+        ("summary", payload.summary.clone().unwrap_or_default()),
+    ];
+
+    placeholders
+        .iter()
+        .fold(template.to_string(), |message, (name, value)| {
+            message.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// The body POSTed to the webhook for `payload`.
+fn body(webhook: &WebhookConfig, payload: &Payload) -> serde_json::Value {
+    match webhook.format {
+        WebhookFormat::Json => serde_json::to_value(payload).unwrap_or_default(),
+        WebhookFormat::Slack => serde_json::json!({ "text": message(webhook, payload) }),
+        WebhookFormat::Discord => {
+            let message: String = message(webhook, payload)
+                .chars()
+                .take(DISCORD_MAX_CHARS)
+                .collect();
+            serde_json::json!({ "content": message })
+        }
+    }
+}
+
+/// A delivery that failed and waits for `tick` to retry it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct PendingDelivery {
+    url: String,
+    body: serde_json::Value,
+    /// Retries made so far.
+    retries: u32,
+    /// When it is retried next.
+    at: DateTime<Utc>,
+}
+
+/// Overdue tasks already reported, kept in the state directory so each
+/// task is reported once per time it falls overdue.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WebhookState {
+    overdue: BTreeSet<Uuid>,
+    /// The day the summary was last sent, by webhook URL.
+    #[serde(default)]
+    summaries: BTreeMap<String, NaiveDate>,
+    #[serde(default)]
+    pending: Vec<PendingDelivery>,
+}
+
+impl WebhookState {
+    fn read(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, path: &Path) -> Result<(), ToNotDoError> {
+        let write_error = |e| ToNotDoError::DatabaseError(DatabaseError::FailedToWriteFile(e));
+
+        let json = serde_json::to_vec(self)
+            .map_err(|e| write_error(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        crate::storage::write_atomically(path, &json).map_err(write_error)
+    }
+}
+
+/// Tasks created or completed between `before` and `after`.
+pub fn changes(before: &Database, after: &Database) -> Vec<Payload> {
+    lifecycle(before, after)
+        .into_iter()
+        .filter_map(|(event, task)| {
+            let event = match event {
+                Lifecycle::Add => WebhookEvent::Created,
+                Lifecycle::Done => WebhookEvent::Completed,
+                Lifecycle::Delete => return None,
+            };
+            Some(Payload::new(event, task))
+        })
+        .collect()
+}
+
+/// Tasks of `db` that fell overdue by `today` and were not reported yet,
+/// as remembered in `state_path`.
+pub fn newly_overdue(
+    db: &Database,
+    today: NaiveDate,
+    state_path: &Path,
+) -> Result<Vec<Payload>, ToNotDoError> {
+    let mut state = WebhookState::read(state_path);
+
+    let overdue: BTreeSet<Uuid> = db
+        .tasks()
+        .iter()
+        .filter(|task| task.state() != TaskState::Done && !task.is_archived())
+        .filter(|task| task.due().is_some_and(|due| due < today))
+        .map(Task::id)
+        .collect();
+
+    let payloads = db
+        .tasks()
+        .iter()
+        .filter(|task| overdue.contains(&task.id()) && !state.overdue.contains(&task.id()))
+        .map(|task| Payload::new(WebhookEvent::Overdue, task.clone()))
+        .collect();
+
+    if overdue != state.overdue {
+        state.overdue = overdue;
+        state.write(state_path)?;
+    }
+    Ok(payloads)
+}
+
+/// The webhooks whose daily summary is due at `now`, each with the summary
+/// of `db` to send it. The day each was sent is remembered in `state_path`,
+/// so each webhook gets one a day.
+pub fn due_summaries(
+    webhooks: &[WebhookConfig],
+    db: &Database,
+    now: NaiveDateTime,
+    state_path: &Path,
+) -> Result<Vec<(WebhookConfig, Payload)>, ToNotDoError> {
+    let today = now.date();
+    let due: Vec<&WebhookConfig> = webhooks
+        .iter()
+        .filter(|webhook| webhook.events.contains(&WebhookEvent::Summary))
+        .filter(|webhook| now.time() >= webhook.summary_time)
+        .collect();
+    if due.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut state = WebhookState::read(state_path);
+    let due: Vec<&WebhookConfig> = due
+        .into_iter()
+        .filter(|webhook| state.summaries.get(&webhook.url) != Some(&today))
+        .collect();
+    if due.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let digest = Digest::new(db.tasks(), today, DigestPeriod::Daily);
+    let summary = format!("{}\n\n{}", digest.subject(), digest.body());
+    for webhook in &due {
+        state.summaries.insert(webhook.url.clone(), today);
+    }
+    state.write(state_path)?;
+
+    Ok(due
+        .into_iter()
+        .map(|webhook| (webhook.clone(), Payload::summary(summary.clone())))
+        .collect())
+}
+
+/// Sends every payload to the webhooks that want its event, returning the
+/// deliveries that failed. Those worth retrying are queued in `state_path`
+/// for [`retry_pending`].
+pub fn notify(
+    webhooks: &[WebhookConfig],
+    payloads: &[Payload],
+    state_path: &Path,
+) -> Vec<ToNotDoError> {
+    let deliveries: Vec<(WebhookConfig, Payload)> = payloads
+        .iter()
+        .flat_map(|payload| {
+            webhooks
+                .iter()
+                .filter(|webhook| webhook.events.contains(&payload.event))
+                .map(|webhook| (webhook.clone(), payload.clone()))
+        })
+        .collect();
+    send(&deliveries, state_path)
+}
+
+/// Sends each payload to its webhook once, returning the deliveries that
+/// failed. Those worth retrying are queued in `state_path` for
+/// [`retry_pending`].
+pub fn send(deliveries: &[(WebhookConfig, Payload)], state_path: &Path) -> Vec<ToNotDoError> {
+    let mut failures = Vec::new();
+    let mut queued = Vec::new();
+
+    for (webhook, payload) in deliveries {
+        let body = body(webhook, payload);
+        if let Err((e, retryable)) = post(&webhook.url, &body) {
+            if retryable && webhook.retries > 0 {
+                queued.push(PendingDelivery {
+                    url: webhook.url.clone(),
+                    body,
+                    retries: 0,
+                    at: Utc::now() + FIRST_RETRY_DELAY,
+                });
+            }
+            failures.push(e);
+        }
+    }
+
+    if !queued.is_empty() {
+        let mut state = WebhookState::read(state_path);
+        state.pending.extend(queued);
+        if let Err(e) = state.write(state_path) {
+            failures.push(e);
+        }
+    }
+    failures
+}
+
+/// Retries the deliveries queued in `state_path` that are due at `now`,
+/// returning the ones given up on: those that failed `retries` times or
+/// were refused, and those whose webhook is no longer configured.
+pub fn retry_pending(
+    webhooks: &[WebhookConfig],
+    now: DateTime<Utc>,
+    state_path: &Path,
+) -> Result<Vec<ToNotDoError>, ToNotDoError> {
+    let mut state = WebhookState::read(state_path);
+    if state.pending.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut failures = Vec::new();
+    let mut pending = Vec::new();
+    for mut delivery in std::mem::take(&mut state.pending) {
+        if delivery.at > now {
+            pending.push(delivery);
+            continue;
+        }
+        let Some(webhook) = webhooks.iter().find(|webhook| webhook.url == delivery.url) else {
+            continue;
+        };
+
+        if let Err((e, retryable)) = post(&webhook.url, &delivery.body) {
+            delivery.retries += 1;
+            if retryable && delivery.retries < webhook.retries {
+                delivery.at = now + FIRST_RETRY_DELAY * 2u32.pow(delivery.retries);
+                pending.push(delivery);
+            } else {
+                failures.push(e);
+            }
+        }
+    }
+
+    state.pending = pending;
+    state.write(state_path)?;
+    Ok(failures)
+}
+
+/// POSTs `payload` to the webhook once.
+pub fn deliver(webhook: &WebhookConfig, payload: &Payload) -> Result<(), ToNotDoError> {
+    post(&webhook.url, &body(webhook, payload)).map_err(|(e, _)| e)
+}
+
+/// POSTs `body` to `url`, failing with whether it is worth retrying: when
+/// the server cannot be reached or answers with a 5xx or 429.
+fn post(url: &str, body: &serde_json::Value) -> Result<(), (ToNotDoError, bool)> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+
+    agent.post(url).send_json(body).map(|_| ()).map_err(|e| {
+        let retryable = match &e {
+            ureq::Error::StatusCode(status) => *status >= 500 || *status == 429,
+            _ => true,
+        };
+        (
+            ToNotDoError::WebhookError(format!("{}: {}", url, e)),
+            retryable,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{mpsc, Arc, Mutex},
+    };
+    use tempfile::tempdir;
+
+    /// Answers requests with `statuses` in turn, sending each body it
+    /// received back through the channel.
+    fn start_receiver(statuses: Vec<u16>) -> (String, mpsc::Receiver<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        listener.set_nonblocking(true).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let app = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move |body: String| async move {
+                sender.send(body).unwrap();
+                let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+                axum::http::StatusCode::from_u16(status).unwrap()
+            }),
+        );
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await
+            })
+        });
+        (url, receiver)
+    }
+
+    fn database(tasks: Vec<Task>) -> Database {
+        let mut db = Database::default();
+        for task in tasks {
+            db.put_task(task);
+        }
+        db
+    }
+
+    #[test]
+    fn test_changes_report_created_and_completed_tasks() {
+        let kept = Task::new("Kept");
+        let finished = Task::new("Finished");
+        let before = database(vec![kept.clone(), finished.clone()]);
+
+        let added = Task::new("Added");
+        let after = database(vec![
+            kept,
+            finished.with_state(TaskState::Done),
+            added.clone(),
+        ]);
+
+        let events: Vec<_> = changes(&before, &after)
+            .into_iter()
+            .map(|payload| {
+                let task = payload.task.unwrap();
+                (payload.event, task.description().to_string())
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (WebhookEvent::Completed, "Finished".to_string()),
+                (WebhookEvent::Created, "Added".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overdue_reported_once() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("tasks.webhooks");
+        let today = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        let late = Task::new("Late").with_due(NaiveDate::from_ymd_opt(2026, 3, 9).unwrap());
+        let db = database(vec![late.clone(), Task::new("On time").with_due(today)]);
+
+        let payloads = newly_overdue(&db, today, &state_path).unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].task.as_ref().unwrap().id(), late.id());
+        assert!(newly_overdue(&db, today, &state_path).unwrap().is_empty());
+
+        // Once done it is forgotten, so falling overdue again is reported.
+        let done = database(vec![late.clone().with_state(TaskState::Done)]);
+        assert!(newly_overdue(&done, today, &state_path).unwrap().is_empty());
+        assert_eq!(newly_overdue(&db, today, &state_path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_deliveries_are_retried_later() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("tasks.webhooks");
+        let (url, received) = start_receiver(vec![503, 503, 200]);
+        let webhooks = [WebhookConfig {
+            retries: 2,
+            ..WebhookConfig::new(&url)
+        }];
+        let payload = Payload::new(WebhookEvent::Created, Task::new("Hooked"));
+
+        assert_eq!(notify(&webhooks, &[payload], &state_path).len(), 1);
+        let first = received.recv().unwrap();
+
+        // Nothing is due yet, then one retry fails and the next succeeds.
+        let now = Utc::now();
+        assert!(retry_pending(&webhooks, now, &state_path)
+            .unwrap()
+            .is_empty());
+        assert!(received.try_recv().is_err());
+        let later = now + FIRST_RETRY_DELAY;
+        assert!(retry_pending(&webhooks, later, &state_path)
+            .unwrap()
+            .is_empty());
+        let much_later = later + FIRST_RETRY_DELAY * 2;
+        assert!(retry_pending(&webhooks, much_later, &state_path)
+            .unwrap()
+            .is_empty());
+
+        let bodies: Vec<String> = received.iter().take(2).collect();
+        assert_eq!(bodies, [first.clone(), first.clone()]);
+        let body: serde_json::Value = serde_json::from_str(&first).unwrap();
+        assert_eq!(body["event"], "created");
+        assert_eq!(body["task"]["description"], "Hooked");
+        assert!(WebhookState::read(&state_path).pending.is_empty());
+    }
+
+    #[test]
+    fn test_failed_deliveries_are_given_up() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("tasks.webhooks");
+        let (url, _received) = start_receiver(vec![500, 500, 404]);
+        let mut webhooks = [WebhookConfig {
+            retries: 1,
+            ..WebhookConfig::new(&url)
+        }];
+        let payloads = [Payload::new(WebhookEvent::Overdue, Task::new("Lost"))];
+
+        assert_eq!(notify(&webhooks, &payloads, &state_path).len(), 1);
+        let later = Utc::now() + FIRST_RETRY_DELAY;
+        assert_eq!(
+            retry_pending(&webhooks, later, &state_path).unwrap().len(),
+            1
+        );
+        assert!(WebhookState::read(&state_path).pending.is_empty());
+
+        // Client errors are not retried.
+        webhooks[0].retries = 5;
+        assert_eq!(notify(&webhooks, &payloads, &state_path).len(), 1);
+        assert!(WebhookState::read(&state_path).pending.is_empty());
+    }
+
+    #[test]
+    fn test_chat_messages() {
+        let (url, received) = start_receiver(vec![]);
+        let task = Task::new("Ship it")
+            .with_project("work")
+            .with_due(NaiveDate::from_ymd_opt(2026, 3, 9).unwrap());
+
+        let mut webhook = WebhookConfig {
+            format: WebhookFormat::Slack,
+            ..WebhookConfig::new(&url)
+        };
+        let payload = Payload::new(WebhookEvent::Overdue, task.clone());
+        deliver(&webhook, &payload).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&received.recv().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "text": "Overdue since 2026-03-09: Ship it" })
+        );
+
+        webhook.format = WebhookFormat::Discord;
+        webhook.templates.insert(
+            WebhookEvent::Completed,
+            "{event}: {description} [{project}]{summary}".to_string(),
+        );
+        let payload = Payload::new(WebhookEvent::Completed, task);
+        deliver(&webhook, &payload).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&received.recv().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "content": "completed: Ship it [work]" })
+        );
+    }
+
+    #[test]
+    fn test_summary_sent_once_a_day() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("tasks.webhooks");
+        let today = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        let db = database(vec![Task::new("Ship it").with_due(today)]);
+
+        let webhooks = [
+            WebhookConfig {
+                events: vec![WebhookEvent::Summary],
+                ..WebhookConfig::new("https://example.com/summary")
+            },
+            WebhookConfig::new("https://example.com/tasks"),
+        ];
+        let at = |hour| today.and_hms_opt(hour, 0, 0).unwrap();
+
+        assert!(due_summaries(&webhooks, &db, at(8), &state_path)
+            .unwrap()
+            .is_empty());
+        let due = due_summaries(&webhooks, &db, at(9), &state_path).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0.url, "https://example.com/summary");
+        let summary = due[0].1.summary.as_deref().unwrap();
+        assert!(summary.contains("Due today (1)\n  - Ship it"));
+        assert!(due_summaries(&webhooks, &db, at(18), &state_path)
+            .unwrap()
+            .is_empty());
+    }
+}
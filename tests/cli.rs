@@ -0,0 +1,175 @@
+//! Runs the built binary the way users do.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use tempfile::tempdir;
+
+/// The binary with its data directory at `data_dir`.
+fn to_not_do(data_dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_to-not-do"));
+    command.env("TO_NOT_DO_DATA_DIR", data_dir);
+    command
+}
+
+fn listing(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_read_only_list_leaves_files_alone() {
+    let data_dir = tempdir().unwrap();
+    let db_dir = tempdir().unwrap();
+    let db_file = db_dir.path().join("db.json");
+    let status = to_not_do(data_dir.path())
+        .arg("--db")
+        .arg(&db_file)
+        .args(["add", "Water the plants"])
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    // Schema version 0, which loading migrates.
+    let mut db: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&db_file).unwrap()).unwrap();
+    db.as_object_mut().unwrap().remove("schema_version");
+    let legacy = serde_json::to_string_pretty(&db).unwrap();
+    std::fs::remove_dir_all(db_dir.path()).unwrap();
+    std::fs::create_dir(db_dir.path()).unwrap();
+    std::fs::write(&db_file, &legacy).unwrap();
+
+    let output = to_not_do(data_dir.path())
+        .arg("--read-only")
+        .arg("--db")
+        .arg(&db_file)
+        .arg("list")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Water the plants"));
+    assert_eq!(std::fs::read_to_string(&db_file).unwrap(), legacy);
+    assert_eq!(listing(db_dir.path()), ["db.json"]);
+}
+
+#[test]
+fn test_batch_fails_when_a_line_does() {
+    let data_dir = tempdir().unwrap();
+    let mut batch = to_not_do(data_dir.path())
+        .arg("batch")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    batch
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"add Before\n--db other.json list\nrepair\nadd After\n")
+        .unwrap();
+    let output = batch.wait_with_output().unwrap();
+
+    assert!(!output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Line 2: "), "{}", stdout);
+    assert!(
+        stdout.contains("Line 3: repair cannot run in a batch"),
+        "{}",
+        stdout
+    );
+
+    let list = to_not_do(data_dir.path()).arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&list.stdout);
+    assert!(
+        stdout.contains("Before") && stdout.contains("After"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_sync_refuses_encrypted_database() {
+    let data_dir = tempdir().unwrap();
+    std::fs::write(
+        data_dir.path().join("config.toml"),
+        "[storage]\nencrypt = true\n",
+    )
+    .unwrap();
+
+    let output = to_not_do(data_dir.path())
+        .args(["sync", "--remote", "http://127.0.0.1:9"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("cannot be synced"));
+    assert!(!listing(data_dir.path())
+        .iter()
+        .any(|name| name.ends_with(".sync")));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_external_command_sees_same_tasks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = tempdir().unwrap();
+    let config_dir = home.path().join("config").join("to-not-do");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "[storage]\njournal = true\n",
+    )
+    .unwrap();
+
+    let bin = home.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let program = bin.join("to-not-do-foo");
+    std::fs::write(
+        &program,
+        "#!/bin/sh\nexec \"$TO_NOT_DO_EXE\" --db \"$TO_NOT_DO_DB\" list\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The XDG layout, with config, data and state apart.
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_to-not-do"))
+            .args(args)
+            .current_dir(home.path())
+            .env_remove("TO_NOT_DO_DATA_DIR")
+            .env_remove("TO_NOT_DO_PROFILE")
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path().join("config"))
+            .env("XDG_DATA_HOME", home.path().join("data"))
+            .env("XDG_STATE_HOME", home.path().join("state"))
+            .env("PATH", &bin)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    run(&["add", "Water the plants"]);
+    run(&["add", "Call the landlord"]);
+    run(&["profile", "create", "work"]);
+    run(&["--profile", "work", "add", "Send the report"]);
+
+    let listed = run(&["foo"]);
+    assert!(listed.contains("Water the plants"), "{}", listed);
+    assert!(listed.contains("Call the landlord"), "{}", listed);
+    assert!(!listed.contains("Send the report"), "{}", listed);
+
+    let listed = run(&["--profile", "work", "foo"]);
+    assert!(listed.contains("Send the report"), "{}", listed);
+    assert!(!listed.contains("Water the plants"), "{}", listed);
+}
@@ -0,0 +1,100 @@
+// Talks to the REST API of `to-not-do serve`. The token, when the server
+// asks for one, is kept in this browser only.
+const TASKS = "/api/tasks";
+
+async function request(method, url, body) {
+  const headers = { "Content-Type": "application/json" };
+  const token = localStorage.getItem("token");
+  if (token) {
+    headers.Authorization = `Bearer ${token}`;
+  }
+
+  const response = await fetch(url, {
+    method,
+    headers,
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+
+  if (response.status === 401) {
+    const entered = prompt("Token for this list");
+    if (entered === null) {
+      throw new Error("A token is needed to see this list");
+    }
+    localStorage.setItem("token", entered);
+    return request(method, url, body);
+  }
+  if (!response.ok) {
+    throw new Error(await response.text());
+  }
+  return response.status === 204 ? null : response.json();
+}
+
+function showError(error) {
+  const element = document.getElementById("error");
+  element.textContent = error ? error.message : "";
+  element.hidden = !error;
+}
+
+function render(tasks) {
+  const list = document.getElementById("tasks");
+  list.replaceChildren(
+    ...tasks.map((task) => {
+      const item = document.createElement("li");
+
+      const done = document.createElement("input");
+      done.type = "checkbox";
+      done.title = "Done";
+      done.addEventListener("change", () =>
+        run(() => request("PATCH", `${TASKS}/${task.id}`, { state: "Done" })),
+      );
+
+      const description = document.createElement("span");
+      description.textContent = task.description;
+
+      item.append(done, description);
+      if (task.project) {
+        const project = document.createElement("span");
+        project.className = "project";
+        project.textContent = task.project;
+        item.append(project);
+      }
+      return item;
+    }),
+  );
+  document.getElementById("empty").hidden = tasks.length > 0;
+}
+
+async function refresh() {
+  const tasks = await request("GET", TASKS);
+  render(tasks.filter((task) => task.state !== "Done"));
+}
+
+// Runs a change, then shows the list as the server now has it.
+async function run(change) {
+  try {
+    await change();
+    await refresh();
+    showError(null);
+  } catch (error) {
+    showError(error);
+  }
+}
+
+document.getElementById("add").addEventListener("submit", (event) => {
+  event.preventDefault();
+  const input = document.getElementById("description");
+  const description = input.value.trim();
+  input.value = "";
+  if (description) {
+    run(() => request("POST", TASKS, { description }));
+  }
+});
+
+// Pick up changes made elsewhere, such as from the command line.
+document.addEventListener("visibilitychange", () => {
+  if (!document.hidden) {
+    run(async () => {});
+  }
+});
+
+run(async () => {});